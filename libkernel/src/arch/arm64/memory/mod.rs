@@ -1,4 +1,5 @@
 pub mod pg_descriptors;
+pub mod pg_dump;
 pub mod pg_tables;
 pub mod pg_tear_down;
 pub mod pg_walk;
@@ -120,6 +120,7 @@ macro_rules! define_descriptor {
                             AP OFFSET(6) NUMBITS(2) [ RW_EL1 = 0b00, RW_EL0 = 0b01, RO_EL1 = 0b10, RO_EL0 = 0b11 ],
                             SH OFFSET(8) NUMBITS(2) [ NonShareable = 0b00, Unpredictable = 0b01, OuterShareable = 0b10, InnerShareable = 0b11 ],
                             AF OFFSET(10) NUMBITS(1) [ Accessed = 1 ],
+                            DBM OFFSET(51) NUMBITS(1) [ DirtyBitModifier = 1 ],
                             PXN OFFSET(53) NUMBITS(1) [ NotExecutableAtEL1 = 1, ExecutableAtEL1 = 0 ],
                             XN OFFSET(54) NUMBITS(1) [ NotExecutable = 1, Executable = 0 ],
                             // Software defined bit
@@ -149,6 +150,10 @@ macro_rules! define_descriptor {
                         _ => unreachable!(),
                     };
 
+                    // A clean page with the DBM bit is read-only to the MMU
+                    // until it's written, but writable all the same.
+                    let write = write || reg.is_set([<$name Fields>]::BlockPageFields::DBM);
+
                     let xn = reg.is_set([<$name Fields>]::BlockPageFields::XN);
                     let cow = reg.is_set([<$name Fields>]::BlockPageFields::COW);
 
@@ -189,8 +194,37 @@ macro_rules! define_descriptor {
                         reg.modify(BlockPageFields::COW::NotCowShared)
                     }
 
+                    // Let the hardware track writes to user memory, where it
+                    // manages the dirty state.
+                    reg.modify(BlockPageFields::DBM.val((perms.is_user() && perms.is_write()) as u64));
+
                     Self(reg.get())
                 }
+
+                /// Whether the block or page has been accessed since its access
+                /// flag was last cleared.
+                pub fn is_accessed(self) -> bool {
+                    let reg = InMemoryRegister::new(self.0);
+
+                    reg.is_set([<$name Fields>]::BlockPageFields::AF)
+                }
+
+                pub fn set_accessed(self, accessed: bool) -> Self {
+                    let reg = InMemoryRegister::new(self.0);
+
+                    reg.modify([<$name Fields>]::BlockPageFields::AF.val(accessed as u64));
+
+                    Self(reg.get())
+                }
+
+                /// Whether the block or page may have been written to: whether
+                /// the MMU lets it be written without a fault. A clean page
+                /// with the DBM bit only becomes writable on its first write.
+                pub fn is_dirty(self) -> bool {
+                    let reg = InMemoryRegister::new(self.0);
+
+                    matches!(reg.read([<$name Fields>]::BlockPageFields::AP), 0b00 | 0b01)
+                }
             }
 
             impl PaMapper for $name {
@@ -212,8 +246,11 @@ macro_rules! define_descriptor {
                     let reg = InMemoryRegister::new(0);
                     use [<$name Fields>]::BlockPageFields;
 
+                    // User memory starts out unaccessed, so that the first
+                    // access is recorded: by the hardware if it manages the
+                    // access flag, and otherwise through an access flag fault.
                     reg.modify(BlockPageFields::OUTPUT_ADDR.val((page_address.value() >> $tbl_shift) as u64)
-                        + BlockPageFields::AF::Accessed);
+                        + BlockPageFields::AF.val(!perms.is_user() as u64));
 
                     match memory_type {
                         MemoryType::Device => {
@@ -523,8 +560,41 @@ mod tests {
             (d.as_raw() >> PAGE_SHIFT),
             (pa.value() >> PAGE_SHIFT) as u64
         );
-        // AF bit should be set
-        assert_ne!(d.as_raw() & (1 << 10), 0);
+        // AF bit should be clear for a user page until it's accessed
+        assert_eq!(d.as_raw() & (1 << 10), 0);
+        assert!(!d.is_accessed());
+        assert!(d.set_accessed(true).is_accessed());
+
+        // ... but set for the kernel's.
+        let k = L3Descriptor::new_map_pa(pa, MemoryType::Normal, PtePermissions::rx(KERNEL_PERMS));
+        assert!(k.is_accessed());
+    }
+
+    #[test]
+    fn test_l3_dirty_state() {
+        let pa = PA::from_value(PAGE_SIZE);
+
+        let d_urw =
+            L3Descriptor::new_map_pa(pa, MemoryType::Normal, PtePermissions::rw(USER_PERMS));
+        let d_uro =
+            L3Descriptor::new_map_pa(pa, MemoryType::Normal, PtePermissions::ro(USER_PERMS));
+
+        // Writable user pages carry DBM, and start dirty.
+        assert_ne!(d_urw.as_raw() & (1 << 51), 0);
+        assert!(d_urw.is_dirty());
+        assert_eq!(d_uro.as_raw() & (1 << 51), 0);
+        assert!(!d_uro.is_dirty());
+
+        // Cleaned, by making it read-only to the MMU, it's still writable.
+        let clean = L3Descriptor::from_raw(d_urw.as_raw() | (1 << 7));
+        assert!(!clean.is_dirty());
+        assert_eq!(clean.permissions(), Some(PtePermissions::rw(USER_PERMS)));
+
+        // Dropping write permission drops DBM too, so the hardware can't make
+        // the page writable again.
+        let ro = d_urw.set_permissions(PtePermissions::ro(USER_PERMS));
+        assert_eq!(ro.as_raw() & (1 << 51), 0);
+        assert_eq!(ro.permissions(), Some(PtePermissions::ro(USER_PERMS)));
     }
 
     #[test]
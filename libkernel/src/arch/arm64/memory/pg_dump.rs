@@ -0,0 +1,405 @@
+//! Read-only inspection of page tables, for debugging.
+//!
+//! Unlike the walkers in [`super::pg_walk`], nothing here modifies a
+//! descriptor, so no TLB maintenance is needed and block mappings are reported
+//! rather than rejected.
+
+use super::{
+    pg_descriptors::{L1Descriptor, L2Descriptor, L3Descriptor, PageTableEntry},
+    pg_tables::{L0Table, L1Table, L2Table, L3Table, PageTableMapper, PgTable, PgTableArray},
+};
+use crate::{
+    error::Result,
+    memory::{
+        address::{PA, TPA, VA},
+        permissions::PtePermissions,
+    },
+};
+use core::fmt;
+
+/// Bits 47:12 of a descriptor, which hold its output address.
+const OA_MASK: u64 = ((1 << 48) - 1) & !((1 << 12) - 1);
+
+/// The number of bits of VA each entry at `level` covers.
+const fn level_shift(level: usize) -> usize {
+    39 - 9 * level
+}
+
+/// The memory attributes of a block or page.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LeafAttrs {
+    /// The `MAIR_EL1` index: 0 for normal memory, 1 for device memory.
+    pub attr_index: u8,
+    /// The `SH` field.
+    pub shareability: u8,
+    /// The access flag.
+    pub accessed: bool,
+}
+
+impl LeafAttrs {
+    fn from_raw(raw: u64) -> Self {
+        Self {
+            attr_index: ((raw >> 2) & 0b111) as u8,
+            shareability: ((raw >> 8) & 0b11) as u8,
+            accessed: raw & (1 << 10) != 0,
+        }
+    }
+}
+
+impl fmt::Display for LeafAttrs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.attr_index {
+            0 => write!(f, "normal")?,
+            1 => write!(f, "device")?,
+            n => write!(f, "attr{n}")?,
+        }
+
+        match self.shareability {
+            0b00 => write!(f, " nsh")?,
+            0b10 => write!(f, " osh")?,
+            0b11 => write!(f, " ish")?,
+            _ => write!(f, " sh?")?,
+        }
+
+        if self.accessed {
+            write!(f, " af")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// What a descriptor holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WalkEntry {
+    /// Nothing is mapped.
+    Invalid,
+    /// The next level's table.
+    Table(PA),
+    /// A block (at L1 or L2) or page (at L3) of `size` bytes at `pa`.
+    Leaf {
+        pa: PA,
+        size: usize,
+        attrs: LeafAttrs,
+        perms: PtePermissions,
+    },
+}
+
+impl WalkEntry {
+    fn decode(level: usize, raw: u64) -> Self {
+        let shift = level_shift(level);
+
+        let perms = match level {
+            1 => L1Descriptor::from_raw(raw).permissions(),
+            2 => L2Descriptor::from_raw(raw).permissions(),
+            _ => L3Descriptor::from_raw(raw).permissions(),
+        };
+
+        match (level, raw & 0b11) {
+            (0..=2, 0b11) => Self::Table(PA::from_value((raw & OA_MASK) as usize)),
+            (1 | 2, 0b01) | (3, 0b11) => Self::Leaf {
+                pa: PA::from_value((raw & OA_MASK & !((1 << shift) - 1)) as usize),
+                size: 1 << shift,
+                attrs: LeafAttrs::from_raw(raw),
+                perms: perms.unwrap_or_default(),
+            },
+            _ => Self::Invalid,
+        }
+    }
+}
+
+/// One descriptor met translating a VA.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WalkStep {
+    pub level: usize,
+    pub raw: u64,
+    pub entry: WalkEntry,
+}
+
+impl fmt::Display for WalkStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "L{} {:#018x} ", self.level, self.raw)?;
+
+        match self.entry {
+            WalkEntry::Invalid => write!(f, "invalid"),
+            WalkEntry::Table(pa) => write!(f, "table {:#x}", pa.value()),
+            WalkEntry::Leaf {
+                pa,
+                size,
+                attrs,
+                perms,
+            } => write!(
+                f,
+                "{} {:#x} ({} KiB) {attrs} {perms}",
+                if self.level == 3 { "page" } else { "block" },
+                pa.value(),
+                size >> 10
+            ),
+        }
+    }
+}
+
+/// The descriptors met translating a VA, from L0 down.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Walk {
+    steps: [Option<WalkStep>; 4],
+}
+
+impl Walk {
+    pub fn steps(&self) -> impl Iterator<Item = &WalkStep> {
+        self.steps.iter().flatten()
+    }
+
+    /// The block or page descriptor that maps the VA, if there is one.
+    pub fn leaf(&self) -> Option<&WalkStep> {
+        self.steps()
+            .last()
+            .filter(|step| matches!(step.entry, WalkEntry::Leaf { .. }))
+    }
+
+    /// The last step, which ended the walk.
+    fn last(&self) -> &WalkStep {
+        self.steps()
+            .last()
+            .expect("A walk reads at least the L0 table")
+    }
+}
+
+fn read_desc<T: PgTable, PM: PageTableMapper>(table: PA, va: VA, mapper: &mut PM) -> Result<u64> {
+    unsafe {
+        mapper.with_page_table(table.cast::<PgTableArray<T>>(), |pgtable| {
+            T::from_ptr(pgtable).get_desc(va).as_raw()
+        })
+    }
+}
+
+/// Follows `va` down the tables rooted at `l0_table`, recording every
+/// descriptor on the way.
+pub fn walk<PM: PageTableMapper>(
+    l0_table: TPA<PgTableArray<L0Table>>,
+    va: VA,
+    mapper: &mut PM,
+) -> Result<Walk> {
+    let mut walk = Walk::default();
+    let mut table = l0_table.to_untyped();
+
+    for level in 0..4 {
+        let raw = match level {
+            0 => read_desc::<L0Table, _>(table, va, mapper)?,
+            1 => read_desc::<L1Table, _>(table, va, mapper)?,
+            2 => read_desc::<L2Table, _>(table, va, mapper)?,
+            _ => read_desc::<L3Table, _>(table, va, mapper)?,
+        };
+
+        let entry = WalkEntry::decode(level, raw);
+
+        walk.steps[level] = Some(WalkStep { level, raw, entry });
+
+        match entry {
+            WalkEntry::Table(next) => table = next,
+            _ => break,
+        }
+    }
+
+    Ok(walk)
+}
+
+/// A stretch of VAs mapped to contiguous physical memory with the same
+/// attributes and permissions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MappedRun {
+    pub va: VA,
+    pub pa: PA,
+    pub size: usize,
+    pub attrs: LeafAttrs,
+    pub perms: PtePermissions,
+}
+
+impl MappedRun {
+    fn continues_with(&self, next: &MappedRun) -> bool {
+        self.va.value() + self.size == next.va.value()
+            && self.pa.value() + self.size == next.pa.value()
+            && self.attrs == next.attrs
+            && self.perms == next.perms
+    }
+}
+
+impl fmt::Display for MappedRun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:#x}-{:#x} -> {:#x} ({} KiB) {} {}",
+            self.va.value(),
+            self.va.value() + self.size - 1,
+            self.pa.value(),
+            self.size >> 10,
+            self.attrs,
+            self.perms
+        )
+    }
+}
+
+/// Calls `f` with each run of mappings between `start` and `end`, skipping
+/// whole tables' worth of unmapped space at a time.
+pub fn for_each_run<PM: PageTableMapper>(
+    l0_table: TPA<PgTableArray<L0Table>>,
+    start: VA,
+    end: VA,
+    mapper: &mut PM,
+    mut f: impl FnMut(MappedRun),
+) -> Result<()> {
+    let mut va = start.page_aligned().value();
+    let end = end.value();
+    let mut run: Option<MappedRun> = None;
+
+    while va < end {
+        let found = walk(l0_table, VA::from_value(va), mapper)?;
+        let step = found.last();
+        let span = 1usize << level_shift(step.level);
+        let offset = va & (span - 1);
+
+        if let WalkEntry::Leaf {
+            pa, attrs, perms, ..
+        } = step.entry
+        {
+            let this = MappedRun {
+                va: VA::from_value(va),
+                pa: pa.add_bytes(offset),
+                size: (span - offset).min(end - va),
+                attrs,
+                perms,
+            };
+
+            if let Some(run) = run.as_mut().filter(|run| run.continues_with(&this)) {
+                run.size += this.size;
+            } else if let Some(done) = run.replace(this) {
+                f(done);
+            }
+        }
+
+        let Some(next) = va.checked_add(span - offset) else {
+            break;
+        };
+
+        va = next;
+    }
+
+    if let Some(done) = run {
+        f(done);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::arm64::memory::pg_tables::tests::TestHarness;
+    use crate::memory::PAGE_SIZE;
+
+    const BLOCK: usize = 2 * 1024 * 1024;
+
+    #[test]
+    fn dump_known_mapping() {
+        let mut harness = TestHarness::new(16);
+
+        harness
+            .map_4k_pages(0x8_0000, 0x1_0000_0000, 2, PtePermissions::ro(false))
+            .unwrap();
+        harness
+            .map_4k_pages(0x8_2000, 0x1_0000_2000, 1, PtePermissions::rw(false))
+            .unwrap();
+        harness
+            .map_4k_pages(
+                0x40_0000,
+                0x2_0000_0000,
+                BLOCK / PAGE_SIZE,
+                PtePermissions::rx(false),
+            )
+            .unwrap();
+
+        let l0 = harness.l0_table;
+
+        let page = walk(l0, VA::from_value(0x1_0000_1234), &mut harness.mapper).unwrap();
+        let leaf = page.leaf().unwrap();
+        assert_eq!(leaf.level, 3);
+        assert!(
+            page.steps()
+                .take(3)
+                .all(|s| matches!(s.entry, WalkEntry::Table(_)))
+        );
+        assert_eq!(
+            leaf.entry,
+            WalkEntry::Leaf {
+                pa: PA::from_value(0x8_1000),
+                size: PAGE_SIZE,
+                attrs: LeafAttrs {
+                    attr_index: 0,
+                    shareability: 0b11,
+                    accessed: true
+                },
+                perms: PtePermissions::ro(false),
+            }
+        );
+        assert!(
+            leaf.to_string()
+                .ends_with("page 0x81000 (4 KiB) normal ish af r-- k")
+        );
+
+        // An aligned 2MiB region is mapped with a block.
+        let block = walk(l0, VA::from_value(0x2_0000_5000), &mut harness.mapper).unwrap();
+        let leaf = block.leaf().unwrap();
+        assert_eq!(leaf.level, 2);
+        assert!(
+            leaf.to_string()
+                .ends_with("block 0x400000 (2048 KiB) normal ish af r-x k")
+        );
+
+        let hole = walk(l0, VA::from_value(0x3_0000_0000), &mut harness.mapper).unwrap();
+        assert!(hole.leaf().is_none());
+        assert_eq!(hole.steps().last().unwrap().entry, WalkEntry::Invalid);
+
+        // Runs break where the permissions change.
+        let mut runs = Vec::new();
+        for_each_run(
+            l0,
+            VA::from_value(0x1_0000_0000),
+            VA::from_value(0x2_0010_0000),
+            &mut harness.mapper,
+            |run| runs.push(run),
+        )
+        .unwrap();
+
+        let attrs = LeafAttrs {
+            attr_index: 0,
+            shareability: 0b11,
+            accessed: true,
+        };
+        assert_eq!(
+            runs,
+            [
+                MappedRun {
+                    va: VA::from_value(0x1_0000_0000),
+                    pa: PA::from_value(0x8_0000),
+                    size: 2 * PAGE_SIZE,
+                    attrs,
+                    perms: PtePermissions::ro(false),
+                },
+                MappedRun {
+                    va: VA::from_value(0x1_0000_2000),
+                    pa: PA::from_value(0x8_2000),
+                    size: PAGE_SIZE,
+                    attrs,
+                    perms: PtePermissions::rw(false),
+                },
+                MappedRun {
+                    va: VA::from_value(0x2_0000_0000),
+                    pa: PA::from_value(0x40_0000),
+                    size: 0x10_0000,
+                    attrs,
+                    perms: PtePermissions::rx(false),
+                },
+            ]
+        );
+    }
+}
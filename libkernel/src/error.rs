@@ -23,6 +23,11 @@ pub enum ProbeError {
     // Driver probing should be tried again after other probes have succeeded.
     #[error("Driver probing deferred for other dependencies")]
     Deferred,
+
+    // The node describes a slot with nothing in it, such as an unused
+    // virtio-mmio transport.
+    #[error("No device present")]
+    NoDevice,
 }
 
 #[derive(Debug, Error, PartialEq, Eq, Clone)]
@@ -59,6 +64,9 @@ pub enum IoError {
 
     #[error("Corruption found in the filesystem metadata")]
     MetadataCorruption,
+
+    #[error("The device failed the I/O request")]
+    DeviceError,
 }
 
 #[derive(Error, Debug, PartialEq, Eq, Clone)]
@@ -104,6 +112,12 @@ pub enum FsError {
 
     #[error("Attempted to rename from cross device")]
     CrossDevice,
+
+    #[error("No space left on device")]
+    NoSpace,
+
+    #[error("File too large")]
+    FileTooLarge,
 }
 
 #[derive(Error, Debug, PartialEq, Eq, Clone)]
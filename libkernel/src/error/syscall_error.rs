@@ -38,6 +38,8 @@ pub const EDOM: isize = -33;
 pub const ERANGE: isize = -34;
 pub const EWOULDBLOCK: isize = -EAGAIN;
 pub const ENOSYS: isize = -38;
+pub const ENOTEMPTY: isize = -39;
+pub const ELOOP: isize = -40;
 pub const EOPNOTSUPP: isize = -95;
 pub const ETIMEDOUT: isize = -110;
 
@@ -52,8 +54,17 @@ pub fn kern_err_to_syscall(err: KernelError) -> isize {
         KernelError::Fs(FsError::IsADirectory) => EISDIR,
         KernelError::Fs(FsError::NotADirectory) => ENOTDIR,
         KernelError::Fs(FsError::AlreadyExists) => EEXIST,
+        KernelError::Fs(FsError::DirectoryNotEmpty) => ENOTEMPTY,
         KernelError::Fs(FsError::InvalidInput) => EINVAL, // TODO: Is this right?
+        KernelError::Fs(FsError::Loop) => ELOOP,
+        KernelError::Fs(FsError::NoDevice) => ENODEV,
+        KernelError::Fs(FsError::NoSpace) => ENOSPC,
+        KernelError::Fs(FsError::FileTooLarge) => EFBIG,
+        KernelError::Fs(FsError::CrossDevice) => EXDEV,
+        KernelError::Fs(FsError::PermissionDenied) => EACCES,
+        KernelError::Fs(FsError::TooManyFiles) => EMFILE,
         KernelError::NotATty => ENOTTY,
+        KernelError::NotPermitted => EPERM,
         KernelError::SeekPipe => ESPIPE,
         KernelError::NotSupported => ENOSYS,
         KernelError::NoMemory => ENOMEM,
@@ -62,6 +73,8 @@ pub fn kern_err_to_syscall(err: KernelError) -> isize {
         KernelError::NoChildProcess => ECHILD,
         KernelError::OpNotSupported => EOPNOTSUPP,
         KernelError::Interrupted => EINTR,
+        KernelError::Exec(_) => ENOEXEC,
+        KernelError::Io(_) => EIO,
         e => todo!("{e}"),
     }
 }
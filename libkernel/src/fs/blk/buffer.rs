@@ -110,4 +110,18 @@ impl BlockBuffer {
     pub async fn sync(&self) -> Result<()> {
         self.dev.sync().await
     }
+
+    /// Flushes cached writes to the blocks covering `len` bytes at `offset`.
+    pub async fn sync_range(&self, offset: u64, len: u64) -> Result<()> {
+        if len == 0 {
+            return Ok(());
+        }
+
+        let start_block = offset / self.block_size as u64;
+        let end_block = (offset + len - 1) / self.block_size as u64;
+
+        self.dev
+            .sync_range(start_block, end_block - start_block + 1)
+            .await
+    }
 }
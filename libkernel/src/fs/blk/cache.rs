@@ -0,0 +1,471 @@
+use core::{
+    future::poll_fn,
+    mem,
+    ops::Range,
+    pin::pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::Poll,
+    time::Duration,
+};
+
+use crate::{
+    CpuOps,
+    error::Result,
+    fs::BlockDevice,
+    sync::{
+        condvar::{CondVar, WakeupType},
+        spinlock::SpinLockIrq,
+    },
+};
+
+use alloc::{
+    boxed::Box,
+    collections::{btree_map::BTreeMap, btree_set::BTreeSet},
+};
+use async_trait::async_trait;
+use log::warn;
+
+/// Tunables for a [`CachedBlockDevice`].
+#[derive(Debug, Clone, Copy)]
+pub struct CacheConfig {
+    /// How often the writeback loop flushes dirty blocks.
+    pub writeback_interval: Duration,
+    /// Once this many blocks are dirty the writeback loop is woken early.
+    pub dirty_watermark: usize,
+    /// The most dirty blocks that may be outstanding. A write that would
+    /// exceed this flushes the oldest blocks itself before returning.
+    pub max_dirty: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            writeback_interval: Duration::from_secs(5),
+            dirty_watermark: 256,
+            max_dirty: 1024,
+        }
+    }
+}
+
+struct DirtyBlock {
+    data: Box<[u8]>,
+    seq: u64,
+}
+
+struct CacheState {
+    blocks: BTreeMap<u64, DirtyBlock>,
+    /// Dirty blocks that haven't started writeback, keyed by the sequence
+    /// number of their last write, so the oldest is always first.
+    age: BTreeMap<u64, u64>,
+    next_seq: u64,
+}
+
+/// A write-back cache in front of a [`BlockDevice`].
+///
+/// Writes are held in memory until they are flushed, either by
+/// [`run_writeback`](Self::run_writeback), by exceeding
+/// [`CacheConfig::max_dirty`], or by an explicit `sync()`. Reads see the
+/// cached data in preference to the device.
+pub struct CachedBlockDevice<CPU: CpuOps> {
+    dev: Box<dyn BlockDevice>,
+    block_size: usize,
+    config: CacheConfig,
+    state: SpinLockIrq<CacheState, CPU>,
+    /// Bumped whenever a block leaves the cache, so that a read which raced
+    /// with writeback can tell that its overlay may be stale.
+    evictions: AtomicU64,
+    kick: CondVar<bool, CPU>,
+    /// Blocks being written to the device. A newer copy of one of these isn't
+    /// written until the older one is done, or the two writes could land out
+    /// of order and leave the stale data on the device.
+    writing: CondVar<BTreeSet<u64>, CPU>,
+}
+
+impl<CPU: CpuOps> CachedBlockDevice<CPU> {
+    pub fn new(dev: Box<dyn BlockDevice>, config: CacheConfig) -> Self {
+        let block_size = dev.block_size();
+
+        Self {
+            dev,
+            block_size,
+            config,
+            state: SpinLockIrq::new(CacheState {
+                blocks: BTreeMap::new(),
+                age: BTreeMap::new(),
+                next_seq: 0,
+            }),
+            evictions: AtomicU64::new(0),
+            kick: CondVar::new(false),
+            writing: CondVar::new(BTreeSet::new()),
+        }
+    }
+
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    /// The number of blocks waiting to be written to the device.
+    pub fn dirty_blocks(&self) -> usize {
+        self.state.lock_save_irq().blocks.len()
+    }
+
+    /// Takes the oldest dirty block in `blocks` that isn't already being
+    /// written back, waiting for an earlier write to finish if those are all
+    /// that's left. Returns `None` once there's nothing left to write.
+    async fn start_writeback(&self, blocks: &Range<u64>) -> Option<(u64, u64, Box<[u8]>)> {
+        self.writing
+            .wait_until(|writing| {
+                let mut state = self.state.lock_save_irq();
+                let mut dirty = state.age.iter().filter(|(_, id)| blocks.contains(id));
+
+                let Some(oldest) = dirty.next() else {
+                    return Some(None);
+                };
+
+                let (&seq, &block_id) = core::iter::once(oldest)
+                    .chain(dirty)
+                    .find(|(_, id)| !writing.contains(id))?;
+
+                state.age.remove(&seq);
+                writing.insert(block_id);
+
+                Some(Some((seq, block_id, state.blocks[&block_id].data.clone())))
+            })
+            .await
+    }
+
+    /// Writes up to `max_blocks` of the oldest dirty blocks to the device.
+    /// Returns the number of blocks written.
+    pub async fn writeback(&self, max_blocks: usize) -> Result<usize> {
+        self.writeback_range(0..u64::MAX, max_blocks).await
+    }
+
+    /// Like [`writeback`](Self::writeback), but only writes blocks in
+    /// `blocks`.
+    async fn writeback_range(&self, blocks: Range<u64>, max_blocks: usize) -> Result<usize> {
+        let mut written = 0;
+
+        while written < max_blocks {
+            let Some((seq, block_id, data)) = self.start_writeback(&blocks).await else {
+                break;
+            };
+
+            let res = self.dev.write(block_id, &data).await;
+
+            {
+                let mut state = self.state.lock_save_irq();
+                let current = state.blocks.get(&block_id).is_some_and(|b| b.seq == seq);
+
+                if res.is_err() {
+                    // Leave the block dirty so that a later flush retries it.
+                    if current {
+                        state.age.insert(seq, block_id);
+                    }
+                } else if current {
+                    // Only drop the block if it wasn't written again in the
+                    // meantime.
+                    state.blocks.remove(&block_id);
+                    self.evictions.fetch_add(1, Ordering::Release);
+                }
+            }
+
+            self.writing.update(|writing| {
+                writing.remove(&block_id);
+                WakeupType::All
+            });
+
+            res?;
+            written += 1;
+        }
+
+        Ok(written)
+    }
+
+    /// Flushes dirty blocks every [`CacheConfig::writeback_interval`], or
+    /// sooner once [`CacheConfig::dirty_watermark`] is reached. Never returns;
+    /// intended to be the body of a writeback thread.
+    ///
+    /// `sleep` should return a future that completes after the given duration.
+    pub async fn run_writeback<F, Fut>(&self, mut sleep: F)
+    where
+        F: FnMut(Duration) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        loop {
+            {
+                let mut timeout = pin!(sleep(self.config.writeback_interval));
+                let mut kicked = pin!(self.kick.wait_until(|kick| mem::take(kick).then_some(())));
+
+                poll_fn(|cx| {
+                    if timeout.as_mut().poll(cx).is_ready() || kicked.as_mut().poll(cx).is_ready() {
+                        Poll::Ready(())
+                    } else {
+                        Poll::Pending
+                    }
+                })
+                .await;
+            }
+
+            let dirty = self.dirty_blocks();
+
+            if let Err(e) = self.writeback(dirty).await {
+                warn!("Block writeback failed: {e}");
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<CPU: CpuOps> BlockDevice for CachedBlockDevice<CPU> {
+    async fn read(&self, block_id: u64, buf: &mut [u8]) -> Result<()> {
+        debug_assert!(buf.len().is_multiple_of(self.block_size));
+
+        loop {
+            let evictions = self.evictions.load(Ordering::Acquire);
+
+            self.dev.read(block_id, buf).await?;
+
+            let state = self.state.lock_save_irq();
+
+            // A block may have been written back and dropped from the cache
+            // after the device read above fetched its old contents.
+            if self.evictions.load(Ordering::Acquire) != evictions {
+                continue;
+            }
+
+            for (i, chunk) in buf.chunks_exact_mut(self.block_size).enumerate() {
+                if let Some(block) = state.blocks.get(&(block_id + i as u64)) {
+                    chunk.copy_from_slice(&block.data);
+                }
+            }
+
+            return Ok(());
+        }
+    }
+
+    async fn write(&self, block_id: u64, buf: &[u8]) -> Result<()> {
+        debug_assert!(buf.len().is_multiple_of(self.block_size));
+
+        let dirty = {
+            let mut state = self.state.lock_save_irq();
+
+            for (i, chunk) in buf.chunks_exact(self.block_size).enumerate() {
+                let id = block_id + i as u64;
+                let seq = state.next_seq;
+
+                state.next_seq += 1;
+
+                let old = state.blocks.insert(
+                    id,
+                    DirtyBlock {
+                        data: chunk.into(),
+                        seq,
+                    },
+                );
+
+                if let Some(old) = old {
+                    state.age.remove(&old.seq);
+                }
+
+                state.age.insert(seq, id);
+            }
+
+            state.blocks.len()
+        };
+
+        if dirty >= self.config.dirty_watermark {
+            self.kick.update(|kick| {
+                *kick = true;
+                WakeupType::One
+            });
+        }
+
+        if dirty > self.config.max_dirty {
+            self.writeback(dirty - self.config.max_dirty).await?;
+        }
+
+        Ok(())
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Writes every dirty block to the device, regardless of any writeback
+    /// thread, and then syncs the device itself.
+    async fn sync(&self) -> Result<()> {
+        self.writeback(usize::MAX).await?;
+        self.dev.sync().await
+    }
+
+    /// Writes the dirty blocks in the range to the device, leaving the rest
+    /// for writeback, and then syncs the device.
+    async fn sync_range(&self, block_id: u64, count: u64) -> Result<()> {
+        self.writeback_range(block_id..block_id + count, usize::MAX)
+            .await?;
+        self.dev.sync_range(block_id, count).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::blk::test::{MemBacking, MemBlkDevice};
+    use crate::test::MockCpuOps;
+    use std::sync::{Arc, Mutex};
+    use std::vec;
+    use tokio::sync::Notify;
+
+    const BLOCK_SIZE: usize = 16;
+
+    fn setup(config: CacheConfig) -> (CachedBlockDevice<MockCpuOps>, Arc<Mutex<MemBacking>>) {
+        let backing = MemBacking::new(vec![0; BLOCK_SIZE * 8]);
+        let dev = MemBlkDevice::new(backing.clone(), BLOCK_SIZE);
+
+        (CachedBlockDevice::new(Box::new(dev), config), backing)
+    }
+
+    #[tokio::test]
+    async fn writes_are_cached_until_sync() {
+        let (cache, backing) = setup(CacheConfig::default());
+
+        cache.write(1, &[0xaa; BLOCK_SIZE * 2]).await.unwrap();
+
+        assert_eq!(cache.dirty_blocks(), 2);
+        assert_eq!(backing.lock().unwrap().writes, 0);
+
+        // Reads see the cached data before it reaches the device.
+        let mut buf = [0; BLOCK_SIZE * 3];
+        cache.read(0, &mut buf).await.unwrap();
+        assert_eq!(&buf[..BLOCK_SIZE], &[0; BLOCK_SIZE]);
+        assert_eq!(&buf[BLOCK_SIZE..], &[0xaa; BLOCK_SIZE * 2]);
+
+        cache.sync().await.unwrap();
+
+        assert_eq!(cache.dirty_blocks(), 0);
+        assert_eq!(backing.lock().unwrap().writes, 2);
+        assert_eq!(
+            &backing.lock().unwrap().data[BLOCK_SIZE..BLOCK_SIZE * 3],
+            &[0xaa; BLOCK_SIZE * 2]
+        );
+    }
+
+    #[tokio::test]
+    async fn sync_range_leaves_other_blocks_dirty() {
+        let (cache, backing) = setup(CacheConfig::default());
+
+        cache.write(0, &[0xaa; BLOCK_SIZE * 4]).await.unwrap();
+        cache.sync_range(1, 2).await.unwrap();
+
+        assert_eq!(cache.dirty_blocks(), 2);
+        assert_eq!(backing.lock().unwrap().writes, 2);
+
+        let data = &backing.lock().unwrap().data;
+        assert_eq!(&data[..BLOCK_SIZE], &[0; BLOCK_SIZE]);
+        assert_eq!(&data[BLOCK_SIZE..BLOCK_SIZE * 3], &[0xaa; BLOCK_SIZE * 2]);
+        assert_eq!(&data[BLOCK_SIZE * 3..BLOCK_SIZE * 4], &[0; BLOCK_SIZE]);
+    }
+
+    #[tokio::test]
+    async fn max_dirty_bounds_outstanding_blocks() {
+        let (cache, backing) = setup(CacheConfig {
+            max_dirty: 2,
+            ..CacheConfig::default()
+        });
+
+        for i in 0..4 {
+            cache.write(i, &[i as u8 + 1; BLOCK_SIZE]).await.unwrap();
+        }
+
+        assert_eq!(cache.dirty_blocks(), 2);
+
+        // The oldest blocks were the ones flushed.
+        let data = &backing.lock().unwrap().data;
+        assert_eq!(&data[..BLOCK_SIZE], &[1; BLOCK_SIZE]);
+        assert_eq!(&data[BLOCK_SIZE..BLOCK_SIZE * 2], &[2; BLOCK_SIZE]);
+        assert_eq!(&data[BLOCK_SIZE * 2..BLOCK_SIZE * 3], &[0; BLOCK_SIZE]);
+    }
+
+    #[tokio::test]
+    async fn rewritten_block_waits_for_earlier_writeback() {
+        let (cache, backing) = setup(CacheConfig::default());
+        let cache = Arc::new(cache);
+        let stall = Arc::new(Notify::new());
+
+        backing.lock().unwrap().stall = Some(stall.clone());
+        cache.write(0, &[1; BLOCK_SIZE]).await.unwrap();
+
+        // The device holds on to the first copy of the block.
+        let c = cache.clone();
+        let first = tokio::spawn(async move { c.writeback(1).await });
+        tokio::task::yield_now().await;
+
+        cache.write(0, &[2; BLOCK_SIZE]).await.unwrap();
+        backing.lock().unwrap().stall = None;
+
+        // The second copy isn't written until the first has landed.
+        let c = cache.clone();
+        let sync = tokio::spawn(async move { c.sync().await });
+        tokio::task::yield_now().await;
+        assert_eq!(backing.lock().unwrap().writes, 0);
+
+        stall.notify_one();
+        first.await.unwrap().unwrap();
+        sync.await.unwrap().unwrap();
+
+        assert_eq!(backing.lock().unwrap().writes, 2);
+        assert_eq!(
+            &backing.lock().unwrap().data[..BLOCK_SIZE],
+            &[2; BLOCK_SIZE]
+        );
+        assert_eq!(cache.dirty_blocks(), 0);
+    }
+
+    #[tokio::test]
+    async fn writeback_flushes_after_interval() {
+        let (cache, backing) = setup(CacheConfig {
+            writeback_interval: Duration::from_millis(10),
+            ..CacheConfig::default()
+        });
+        let cache = Arc::new(cache);
+
+        cache.write(3, &[0x55; BLOCK_SIZE]).await.unwrap();
+
+        let c = cache.clone();
+        let wb = tokio::spawn(async move { c.run_writeback(tokio::time::sleep).await });
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(cache.dirty_blocks(), 0);
+        assert_eq!(
+            &backing.lock().unwrap().data[BLOCK_SIZE * 3..BLOCK_SIZE * 4],
+            &[0x55; BLOCK_SIZE]
+        );
+
+        wb.abort();
+    }
+
+    #[tokio::test]
+    async fn watermark_wakes_writeback_early() {
+        let (cache, backing) = setup(CacheConfig {
+            writeback_interval: Duration::from_secs(3600),
+            dirty_watermark: 2,
+            ..CacheConfig::default()
+        });
+        let cache = Arc::new(cache);
+
+        let c = cache.clone();
+        let wb = tokio::spawn(async move { c.run_writeback(tokio::time::sleep).await });
+
+        cache.write(0, &[1; BLOCK_SIZE]).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(backing.lock().unwrap().writes, 0);
+
+        cache.write(1, &[2; BLOCK_SIZE]).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(backing.lock().unwrap().writes, 2);
+        assert_eq!(cache.dirty_blocks(), 0);
+
+        wb.abort();
+    }
+}
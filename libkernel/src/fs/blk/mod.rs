@@ -1,2 +1,6 @@
 pub mod buffer;
+pub mod cache;
 pub mod ramdisk;
+
+#[cfg(test)]
+pub mod test;
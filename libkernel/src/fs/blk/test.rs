@@ -0,0 +1,74 @@
+//! An in-memory block device shared by the block layer and filesystem tests.
+
+use crate::error::Result;
+use crate::fs::BlockDevice;
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+use std::vec::Vec;
+use tokio::sync::Notify;
+
+/// The contents of a [`MemBlkDevice`], kept outside the device so a test can
+/// inspect it or mount it again.
+pub struct MemBacking {
+    pub data: Vec<u8>,
+    /// The number of blocks written to the device.
+    pub writes: usize,
+    /// While set, each write waits to be let through before landing.
+    pub stall: Option<Arc<Notify>>,
+}
+
+impl MemBacking {
+    pub fn new(data: Vec<u8>) -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self {
+            data,
+            writes: 0,
+            stall: None,
+        }))
+    }
+}
+
+pub struct MemBlkDevice {
+    backing: Arc<Mutex<MemBacking>>,
+    block_size: usize,
+}
+
+impl MemBlkDevice {
+    pub fn new(backing: Arc<Mutex<MemBacking>>, block_size: usize) -> Self {
+        Self {
+            backing,
+            block_size,
+        }
+    }
+}
+
+#[async_trait]
+impl BlockDevice for MemBlkDevice {
+    async fn read(&self, block_id: u64, buf: &mut [u8]) -> Result<()> {
+        let backing = self.backing.lock().unwrap();
+        let off = block_id as usize * self.block_size;
+        buf.copy_from_slice(&backing.data[off..off + buf.len()]);
+        Ok(())
+    }
+
+    async fn write(&self, block_id: u64, buf: &[u8]) -> Result<()> {
+        let stall = self.backing.lock().unwrap().stall.clone();
+
+        if let Some(stall) = stall {
+            stall.notified().await;
+        }
+
+        let mut backing = self.backing.lock().unwrap();
+        let off = block_id as usize * self.block_size;
+        backing.data[off..off + buf.len()].copy_from_slice(buf);
+        backing.writes += buf.len() / self.block_size;
+        Ok(())
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    async fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+}
@@ -154,6 +154,10 @@ where
         InodeId::from_fsid_and_inodeid(fs.id(), self.id.get() as u64)
     }
 
+    fn cacheable(&self) -> bool {
+        true
+    }
+
     async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
         let inner = self.inner.lock().await;
         // Must be a regular file.
@@ -26,7 +26,7 @@ pub struct BiosParameterBlock {
     _sectors_per_track: u16,
     _head_count: u16,
     _hidden_sector_count: u32,
-    _total_sectors_32: u32,
+    pub total_sectors_32: u32,
 
     /* FAT32 Extended BPB */
     // The size of ONE FAT in sectors.
@@ -41,8 +41,19 @@ pub struct BiosParameterBlock {
 
 unsafe impl Pod for BiosParameterBlock {}
 
+/// Offset of the boot sector signature, which must read `0x55 0xAA`.
+const BOOT_SIG_OFFSET: u64 = 510;
+const BOOT_SIG: u16 = 0xAA55;
+
 impl BiosParameterBlock {
     pub async fn new(dev: &BlockBuffer) -> Result<Self> {
+        let sig: u16 = dev.read_obj(BOOT_SIG_OFFSET).await?;
+
+        if sig != BOOT_SIG {
+            warn!("Boot sector signature 0x{sig:04X} is invalid");
+            return Err(FsError::InvalidFs.into());
+        }
+
         let bpb: Self = dev.read_obj(0).await?;
 
         if bpb._fat_size_16 != 0 || bpb._root_entry_count != 0 {
@@ -124,6 +135,12 @@ impl BiosParameterBlock {
         self.fat_region_start() + self.fat_len() * self.num_fats as usize
     }
 
+    /// The number of clusters that fit in the data region.
+    pub fn cluster_count(&self) -> usize {
+        (self.total_sectors_32 as usize).saturating_sub(self.data_region_start().0 as _)
+            / self.sectors_per_cluster as usize
+    }
+
     pub fn sector_size(&self) -> usize {
         self.bytes_per_sector as _
     }
@@ -162,7 +179,7 @@ pub mod test {
             _sectors_per_track: 0,
             _head_count: 0,
             _hidden_sector_count: 0,
-            _total_sectors_32: 0,
+            total_sectors_32: 0,
             fat_size_32: 1000, // Size of ONE FAT in sectors
             _ext_flags: 0,
             _fs_version: 0,
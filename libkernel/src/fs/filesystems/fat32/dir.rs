@@ -1,7 +1,14 @@
 use core::ptr;
 use core::time::Duration;
-
-use super::{Cluster, Fat32Operations, file::Fat32FileNode, reader::Fat32Reader};
+use core::{cmp::min, mem::offset_of};
+
+use super::{
+    Cluster, Fat32Operations,
+    file::{Fat32FileNode, FileState},
+    journal::{JOURNAL_NAME, Transaction},
+    reader::Fat32Reader,
+    writer::{chain_tail, grow_chain, write_chain},
+};
 use crate::{
     error::{FsError, KernelError, Result},
     fs::{
@@ -9,13 +16,13 @@ use crate::{
         attr::{FileAttr, FilePermissions},
     },
 };
-use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
+use alloc::{boxed::Box, collections::BTreeSet, format, string::String, sync::Arc, vec, vec::Vec};
 use async_trait::async_trait;
 use log::warn;
 
 bitflags::bitflags! {
-    #[derive(Clone, Copy, Debug)]
-    struct Fat32Attributes: u8 {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub(super) struct Fat32Attributes: u8 {
         const READ_ONLY    = 0x01;
         const HIDDEN       = 0x02;
         const SYSTEM       = 0x04;
@@ -26,13 +33,46 @@ bitflags::bitflags! {
     }
 }
 
+/// Write permission for anyone.
+const WRITE_BITS: FilePermissions = FilePermissions::S_IWUSR
+    .union(FilePermissions::S_IWGRP)
+    .union(FilePermissions::S_IWOTH);
+
+impl Fat32Attributes {
+    /// The permission bits of an entry's `st_mode`. FAT has no permissions
+    /// beyond the read-only attribute, which removes the write bits from a
+    /// file. As on Linux, directories ignore it.
+    pub(super) fn mode(self) -> FilePermissions {
+        if self.contains(Self::DIRECTORY) {
+            FilePermissions::from_bits_retain(0o755)
+        } else if self.contains(Self::READ_ONLY) {
+            FilePermissions::from_bits_retain(0o444)
+        } else {
+            FilePermissions::from_bits_retain(0o644)
+        }
+    }
+
+    /// The attributes after a `chmod` to `mode`: a file becomes read-only
+    /// when no write bits are left.
+    pub(super) fn with_mode(self, mode: FilePermissions) -> Self {
+        if self.contains(Self::DIRECTORY) {
+            self
+        } else if mode.intersects(WRITE_BITS) {
+            self - Self::READ_ONLY
+        } else {
+            self | Self::READ_ONLY
+        }
+    }
+}
+
 impl TryFrom<Fat32Attributes> for FileType {
     type Error = KernelError;
 
     fn try_from(value: Fat32Attributes) -> Result<Self> {
         if value.contains(Fat32Attributes::DIRECTORY) {
             Ok(FileType::Directory)
-        } else if value.contains(Fat32Attributes::ARCHIVE) {
+        } else if !value.contains(Fat32Attributes::DEVICE) {
+            // `ARCHIVE` only records whether the file has been backed up.
             Ok(FileType::File)
         } else {
             warn!("Entry is neither a regular file nor a directory. Ignoring.");
@@ -91,6 +131,29 @@ struct DirEntry {
 }
 
 impl DirEntry {
+    fn new(short_name: [u8; 11], attributes: Fat32Attributes, cluster: Cluster) -> Self {
+        Self {
+            dos_file_name: short_name[..8].try_into().unwrap(),
+            dos_extension: short_name[8..].try_into().unwrap(),
+            attributes,
+            _reserved: 0,
+            ctime_ms: 0,
+            ctime: 0,
+            cdate: 0,
+            adate: 0,
+            clust_high: (cluster.0 >> 16) as u16,
+            mtime: 0,
+            mdate: 0,
+            clust_low: cluster.0 as u16,
+            size: 0,
+        }
+    }
+
+    fn to_bytes(self) -> [u8; DIR_ENTRY_SIZE] {
+        // SAFETY: `DirEntry` is a packed, 32-byte POD type.
+        unsafe { core::mem::transmute(self) }
+    }
+
     pub fn parse_filename(&self) -> String {
         let name_part = self
             .dos_file_name
@@ -116,10 +179,275 @@ impl DirEntry {
     }
 }
 
+pub(super) const DIR_ENTRY_SIZE: usize = 32;
+const ENTRY_END: u8 = 0x00;
+const ENTRY_DELETED: u8 = 0xE5;
+const LFN_ATTR: u8 = 0x0F;
+const LFN_LAST: u8 = 0x40;
+const LFN_CHARS: usize = 13;
+const MAX_NAME_LEN: usize = 255;
+
+/// How many ".." entries [`Fat32DirNode::is_within`] follows before deciding
+/// the volume is corrupt. Matches the deepest tree a `PATH_MAX` path can name.
+const MAX_DIR_DEPTH: usize = 2048;
+
+/// Where a file's 8.3 entry lives: the first cluster of its directory and
+/// the index of the entry within it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DirEntryLoc {
+    pub dir: Cluster,
+    pub index: u64,
+}
+
+impl DirEntryLoc {
+    /// An inode number for a file that has no cluster to take one from.
+    /// Cluster numbers only use 28 bits, so with the top bit set these can't
+    /// collide with them.
+    pub fn inode_id(self) -> u64 {
+        1 << 63 | (self.dir.value() as u64) << 32 | self.index
+    }
+}
+
+/// Rewrites the first cluster and size fields of the 8.3 entry at `loc`.
+pub async fn update_entry<T: Fat32Operations>(
+    fs: &T,
+    loc: DirEntryLoc,
+    cluster: Cluster,
+    size: u32,
+) -> Result<()> {
+    let base = loc.index * DIR_ENTRY_SIZE as u64;
+
+    write_chain(
+        fs,
+        loc.dir,
+        base + offset_of!(DirEntry, clust_high) as u64,
+        &((cluster.0 >> 16) as u16).to_le_bytes(),
+    )
+    .await?;
+
+    // `clust_low` is immediately followed by `size`.
+    let mut tail = [0; 6];
+    tail[..2].copy_from_slice(&(cluster.0 as u16).to_le_bytes());
+    tail[2..].copy_from_slice(&size.to_le_bytes());
+
+    write_chain(
+        fs,
+        loc.dir,
+        base + offset_of!(DirEntry, clust_low) as u64,
+        &tail,
+    )
+    .await
+}
+
+/// Applies `mode` to the attributes of the 8.3 entry at `loc`, returning the
+/// permission bits FAT can actually record.
+pub async fn update_entry_mode<T: Fat32Operations>(
+    fs: &Arc<T>,
+    loc: DirEntryLoc,
+    mode: FilePermissions,
+) -> Result<FilePermissions> {
+    let offset = loc.index * DIR_ENTRY_SIZE as u64 + offset_of!(DirEntry, attributes) as u64;
+    let mut attrs = [0];
+
+    Fat32Reader::new(fs.clone(), loc.dir, u64::MAX)
+        .read_at(offset, &mut attrs)
+        .await?;
+
+    let old = Fat32Attributes::from_bits_retain(attrs[0]);
+    let new = old.with_mode(mode);
+
+    if new != old {
+        write_chain(&**fs, loc.dir, offset, &[new.bits()]).await?;
+    }
+
+    Ok(new.mode())
+}
+
+/// Writes `atime` and `mtime` to the 8.3 entry at `loc`. FAT keeps only the
+/// date of the last access and stores the modification time to 2 seconds, so
+/// the times actually recorded are returned.
+pub async fn update_entry_times<T: Fat32Operations>(
+    fs: &T,
+    loc: DirEntryLoc,
+    atime: Duration,
+    mtime: Duration,
+) -> Result<(Duration, Duration)> {
+    let base = loc.index * DIR_ENTRY_SIZE as u64;
+    let (adate, _) = duration_to_fat_datetime(atime);
+    let (mdate, mtime) = duration_to_fat_datetime(mtime);
+
+    write_chain(
+        fs,
+        loc.dir,
+        base + offset_of!(DirEntry, adate) as u64,
+        &adate.to_le_bytes(),
+    )
+    .await?;
+
+    // `mtime` is immediately followed by `mdate`.
+    let mut buf = [0; 4];
+    buf[..2].copy_from_slice(&mtime.to_le_bytes());
+    buf[2..].copy_from_slice(&mdate.to_le_bytes());
+
+    write_chain(fs, loc.dir, base + offset_of!(DirEntry, mtime) as u64, &buf).await?;
+
+    Ok((
+        fat_date_to_duration(adate),
+        fat_datetime_to_duration(mdate, mtime, 0),
+    ))
+}
+
+/// Returns true if the raw 8.3 entry `entry` reserves the journal's clusters.
+fn is_journal(entry: &[u8]) -> bool {
+    entry[..11] == JOURNAL_NAME[..]
+        && Fat32Attributes::from_bits_retain(entry[11])
+            .contains(Fat32Attributes::HIDDEN | Fat32Attributes::SYSTEM)
+}
+
+/// Computes the checksum of an 8.3 name that each of its LFN entries carry.
+fn short_name_checksum(short_name: &[u8; 11]) -> u8 {
+    short_name
+        .iter()
+        .fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c))
+}
+
+fn is_valid_long_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name.encode_utf16().count() <= MAX_NAME_LEN
+        && !name
+            .chars()
+            .any(|c| c.is_control() || "\"*/:<>?\\|".contains(c))
+}
+
+fn is_valid_short_char(c: u8) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || b"!#$%&'()-@^_`{}~".contains(&c)
+}
+
+/// Returns the 8.3 form of `name` if the name can be stored without any LFN
+/// entries. Short names are reported in lower case, so only names without
+/// upper-case letters round-trip exactly.
+fn exact_short_name(name: &str) -> Option<[u8; 11]> {
+    if name.bytes().any(|c| c.is_ascii_uppercase()) || name.ends_with('.') {
+        return None;
+    }
+
+    let (base, ext) = name.rsplit_once('.').unwrap_or((name, ""));
+
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 {
+        return None;
+    }
+
+    let mut short_name = [b' '; 11];
+    let (name_part, ext_part) = short_name.split_at_mut(8);
+
+    for (dst, c) in name_part
+        .iter_mut()
+        .zip(base.bytes())
+        .chain(ext_part.iter_mut().zip(ext.bytes()))
+    {
+        *dst = c.to_ascii_uppercase();
+
+        if !is_valid_short_char(*dst) {
+            return None;
+        }
+    }
+
+    Some(short_name)
+}
+
+/// Generates a `BASIS~N` style 8.3 alias for a long name that doesn't clash
+/// with any of the short names in `taken`.
+fn generate_short_name(name: &str, taken: &BTreeSet<[u8; 11]>) -> Result<[u8; 11]> {
+    let to_short = |s: &str, max: usize| -> Vec<u8> {
+        s.bytes()
+            .filter(|&c| c != b' ' && c != b'.')
+            .map(|c| {
+                let c = c.to_ascii_uppercase();
+                if is_valid_short_char(c) { c } else { b'_' }
+            })
+            .take(max)
+            .collect()
+    };
+
+    let trimmed = name.trim_start_matches('.');
+    let (base, ext) = trimmed.rsplit_once('.').unwrap_or((trimmed, ""));
+
+    let mut base = to_short(base, 8);
+    let ext = to_short(ext, 3);
+
+    if base.is_empty() {
+        base.push(b'_');
+    }
+
+    for n in 1..1_000_000 {
+        let tail = format!("~{n}");
+        let keep = min(base.len(), 8 - tail.len());
+
+        let mut short_name = [b' '; 11];
+        short_name[..keep].copy_from_slice(&base[..keep]);
+        short_name[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+        short_name[8..8 + ext.len()].copy_from_slice(&ext);
+
+        if !taken.contains(&short_name) {
+            return Ok(short_name);
+        }
+    }
+
+    Err(FsError::AlreadyExists.into())
+}
+
+/// Builds the LFN entries for `name`, in on-disk order.
+fn lfn_entries(name: &str, checksum: u8) -> Vec<[u8; DIR_ENTRY_SIZE]> {
+    let mut chars: Vec<u16> = name.encode_utf16().collect();
+
+    if !chars.len().is_multiple_of(LFN_CHARS) {
+        chars.push(0x0000);
+    }
+
+    while !chars.len().is_multiple_of(LFN_CHARS) {
+        chars.push(0xFFFF);
+    }
+
+    let count = chars.len() / LFN_CHARS;
+
+    // The entry holding the end of the name comes first on disk.
+    (0..count)
+        .rev()
+        .map(|i| {
+            let part = &chars[i * LFN_CHARS..][..LFN_CHARS];
+            let mut sequence_number = i as u8 + 1;
+
+            if i == count - 1 {
+                sequence_number |= LFN_LAST;
+            }
+
+            let entry = LfnEntry {
+                sequence_number,
+                name1: part[..5].try_into().unwrap(),
+                attributes: LFN_ATTR,
+                entry_type: 0,
+                checksum,
+                name2: part[5..11].try_into().unwrap(),
+                first_cluster: 0,
+                name3: part[11..].try_into().unwrap(),
+            };
+
+            // SAFETY: `LfnEntry` is a packed, 32-byte POD type.
+            unsafe { core::mem::transmute(entry) }
+        })
+        .collect()
+}
+
 struct Fat32DirEntry {
     attr: FileAttr,
     cluster: Cluster,
     name: String,
+    // Index of the first LFN entry, or of the 8.3 entry if there are none.
+    first_index: u64,
+    // Index of the 8.3 entry within the directory.
+    index: u64,
     offset: u64,
 }
 
@@ -127,7 +455,9 @@ struct Fat32DirStream<T: Fat32Operations> {
     reader: Fat32Reader<T>,
     offset: u64,
     lfn_buffer: Vec<u16>,
+    lfn_start: u64,
     fs_id: u64,
+    bytes_per_cluster: usize,
 }
 
 impl<T: Fat32Operations> Clone for Fat32DirStream<T> {
@@ -136,7 +466,9 @@ impl<T: Fat32Operations> Clone for Fat32DirStream<T> {
             reader: self.reader.clone(),
             offset: self.offset,
             lfn_buffer: self.lfn_buffer.clone(),
+            lfn_start: self.lfn_start,
             fs_id: self.fs_id,
+            bytes_per_cluster: self.bytes_per_cluster,
         }
     }
 }
@@ -145,6 +477,7 @@ impl<T: Fat32Operations> Fat32DirStream<T> {
     pub fn new(fs: Arc<T>, root: Cluster) -> Self {
         let max_sz = fs.iter_clusters(root).count() as u64 * fs.bytes_per_cluster() as u64;
         let fs_id = fs.id();
+        let bytes_per_cluster = fs.bytes_per_cluster();
 
         // For directory nodes, the size is 0. In our case, fake the size to be
         // the number of clusters in the chain such that we never read past the
@@ -153,7 +486,9 @@ impl<T: Fat32Operations> Fat32DirStream<T> {
             reader: Fat32Reader::new(fs, root, max_sz),
             offset: 0,
             lfn_buffer: Vec::new(),
+            lfn_start: 0,
             fs_id,
+            bytes_per_cluster,
         }
     }
 
@@ -186,6 +521,10 @@ impl<T: Fat32Operations> Fat32DirStream<T> {
                 let lfn_entry: LfnEntry =
                     unsafe { ptr::read_unaligned(entry_bytes.as_ptr() as *const _) };
 
+                if self.lfn_buffer.is_empty() {
+                    self.lfn_start = self.offset;
+                }
+
                 // LFN entries are stored backwards, so we prepend.
                 let new_chars = lfn_entry.extract_chars();
                 self.lfn_buffer.splice(0..0, new_chars);
@@ -197,7 +536,10 @@ impl<T: Fat32Operations> Fat32DirStream<T> {
             let dir_entry: DirEntry =
                 unsafe { ptr::read_unaligned(entry_bytes.as_ptr() as *const _) };
 
-            if dir_entry.attributes.contains(Fat32Attributes::VOLUME_LABEL) {
+            // The journal is the filesystem's own, so it's kept out of sight.
+            if dir_entry.attributes.contains(Fat32Attributes::VOLUME_LABEL)
+                || is_journal(&entry_bytes)
+            {
                 self.lfn_buffer.clear();
                 self.offset += 1;
                 continue;
@@ -218,10 +560,16 @@ impl<T: Fat32Operations> Fat32DirStream<T> {
             // Process the metadata from the 8.3 entry
             let file_type = FileType::try_from(dir_entry.attributes)?;
             let cluster = Cluster::from_high_low(dir_entry.clust_high, dir_entry.clust_low);
+            let bpc = self.bytes_per_cluster as u64;
             let attr = FileAttr {
+                id: InodeId::from_fsid_and_inodeid(self.fs_id, cluster.value() as _),
                 size: dir_entry.size as u64,
+                block_size: bpc as _,
+                // `st_blocks` is always in 512-byte units, regardless of the
+                // cluster size.
+                blocks: (dir_entry.size as u64).div_ceil(bpc) * bpc / 512,
                 file_type,
-                mode: FilePermissions::from_bits_retain(0o755),
+                mode: dir_entry.attributes.mode(),
                 atime: fat_date_to_duration(dir_entry.adate),
                 mtime: fat_datetime_to_duration(dir_entry.mdate, dir_entry.mtime, 0),
                 ctime: fat_datetime_to_duration(
@@ -232,6 +580,13 @@ impl<T: Fat32Operations> Fat32DirStream<T> {
                 ..Default::default()
             };
 
+            let index = self.offset;
+            let first_index = if self.lfn_buffer.is_empty() {
+                index
+            } else {
+                self.lfn_start
+            };
+
             self.lfn_buffer.clear();
             self.offset += 1;
 
@@ -239,6 +594,8 @@ impl<T: Fat32Operations> Fat32DirStream<T> {
                 attr,
                 cluster,
                 name,
+                first_index,
+                index,
                 // Note that the offset should be to the *next* entry, so using
                 // the advanced entry is correct.
                 offset: self.offset,
@@ -247,6 +604,12 @@ impl<T: Fat32Operations> Fat32DirStream<T> {
     }
 }
 
+/// Days between 1970-01-01 and 1980-01-01 = 3652 (incl. 1972 and 1976 leap years)
+const DAYS_OFFSET: u32 = 3652;
+
+/// The last year a FAT date can hold.
+const FAT_MAX_YEAR: u32 = 1980 + 127;
+
 /// Determines if a given year is a leap year
 #[inline(always)]
 fn is_leap_year(year: u32) -> bool {
@@ -298,8 +661,6 @@ fn fat_date_to_duration(date: u16) -> Duration {
         return Duration::ZERO;
     }
 
-    // Days between 1970-01-01 and 1980-01-01 = 3652 (incl. 1972 and 1976 leap years)
-    const DAYS_OFFSET: u32 = 3652;
     let days_total = DAYS_OFFSET + days_since_1980(year, month, day);
 
     Duration::from_secs(days_total as u64 * 86_400)
@@ -324,6 +685,48 @@ fn fat_datetime_to_duration(date: u16, time: u16, csecs: u8) -> Duration {
     base + Duration::from_secs(hours * 3600 + minutes * 60 + secs) + Duration::from_millis(millis)
 }
 
+/// Converts a `Duration` since the Unix epoch into FAT (date, time) fields,
+/// truncating to FAT's 2-second resolution. Times outside 1980-2107 are
+/// clamped to that range.
+fn duration_to_fat_datetime(time: Duration) -> (u16, u16) {
+    let Some(secs) = time.as_secs().checked_sub(DAYS_OFFSET as u64 * 86_400) else {
+        // 1980-01-01 00:00:00
+        return (1 << 5 | 1, 0);
+    };
+
+    let mut days = secs / 86_400;
+    let secs = (secs % 86_400) as u16;
+    let mut year = 1980;
+
+    loop {
+        let year_len = 365 + is_leap_year(year) as u64;
+
+        if days < year_len {
+            break;
+        }
+
+        if year == FAT_MAX_YEAR {
+            // 2107-12-31 23:59:58
+            return ((127 << 9) | (12 << 5) | 31, (23 << 11) | (59 << 5) | 29);
+        }
+
+        days -= year_len;
+        year += 1;
+    }
+
+    let mut month = 1;
+
+    while days >= days_in_month(year, month) as u64 {
+        days -= days_in_month(year, month) as u64;
+        month += 1;
+    }
+
+    let date = ((year - 1980) << 9 | month << 5 | (days as u32 + 1)) as u16;
+    let time = (secs / 3600) << 11 | (secs / 60 % 60) << 5 | (secs % 60) / 2;
+
+    (date, time)
+}
+
 #[async_trait]
 impl<T: Fat32Operations> DirStream for Fat32DirStream<T> {
     async fn next_entry(&mut self) -> Result<Option<Dirent>> {
@@ -342,19 +745,315 @@ pub struct Fat32DirNode<T: Fat32Operations> {
     attr: FileAttr,
     root: Cluster,
     fs: Arc<T>,
-    streamer: Fat32DirStream<T>,
 }
 
 impl<T: Fat32Operations> Fat32DirNode<T> {
     pub fn new(fs: Arc<T>, root: Cluster, attr: FileAttr) -> Self {
-        let streamer = Fat32DirStream::new(fs.clone(), root);
+        Self { attr, root, fs }
+    }
 
-        Self {
-            attr,
-            root,
-            fs,
-            streamer,
+    /// Creates a stream over the directory. The directory can grow, so the
+    /// stream is built afresh for each walk.
+    fn streamer(&self) -> Fat32DirStream<T> {
+        Fat32DirStream::new(self.fs.clone(), self.root)
+    }
+
+    async fn find(&self, name: &str) -> Result<Fat32DirEntry> {
+        let mut dir_iter = self.streamer();
+
+        while let Some(entry) = dir_iter.next_fat32_entry().await? {
+            if entry.name.eq_ignore_ascii_case(name) {
+                return Ok(entry);
+            }
+        }
+
+        Err(FsError::NotFound.into())
+    }
+
+    /// Returns true if the directory holds nothing but "." and "..".
+    async fn is_empty(&self) -> Result<bool> {
+        let mut dir_iter = self.streamer();
+
+        while let Some(entry) = dir_iter.next_fat32_entry().await? {
+            if entry.name != "." && entry.name != ".." {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Allocates the first cluster of a new subdirectory of this directory
+    /// and writes its "." and ".." entries.
+    async fn new_subdir_cluster(&self) -> Result<Cluster> {
+        let cluster = grow_chain(&*self.fs, None, 1)
+            .await?
+            .ok_or(FsError::NoSpace)?;
+
+        let parent = if self.root == self.fs.root_cluster() {
+            Cluster(0)
+        } else {
+            self.root
+        };
+
+        let dot = DirEntry::new(*b".          ", Fat32Attributes::DIRECTORY, cluster);
+        let dotdot = DirEntry::new(*b"..         ", Fat32Attributes::DIRECTORY, parent);
+
+        write_chain(&*self.fs, cluster, 0, &dot.to_bytes()).await?;
+        write_chain(
+            &*self.fs,
+            cluster,
+            DIR_ENTRY_SIZE as u64,
+            &dotdot.to_bytes(),
+        )
+        .await?;
+
+        Ok(cluster)
+    }
+
+    /// Reads every raw 32-byte entry slot in the directory's cluster chain.
+    async fn read_raw(&self) -> Result<Vec<u8>> {
+        let (_, len) = chain_tail(&*self.fs, self.root)?;
+        let max_sz = len as u64 * self.fs.bytes_per_cluster() as u64;
+        let mut buf = vec![0; max_sz as usize];
+
+        let reader = Fat32Reader::new(self.fs.clone(), self.root, max_sz);
+        let mut read = 0;
+
+        while read < buf.len() {
+            match reader.read_at(read as u64, &mut buf[read..]).await? {
+                0 => return Err(FsError::InvalidFs.into()),
+                n => read += n,
+            }
         }
+
+        Ok(buf)
+    }
+
+    /// Finds `count` consecutive free entry slots, growing the directory if
+    /// there is no such run. Returns the index of the first slot.
+    async fn find_free_slots(&self, raw: &[u8], count: usize) -> Result<u64> {
+        let total = raw.len() / DIR_ENTRY_SIZE;
+        let mut run_start = total;
+        let mut run_len = 0;
+
+        for (i, entry) in raw.chunks_exact(DIR_ENTRY_SIZE).enumerate() {
+            match entry[0] {
+                ENTRY_END => {
+                    // Everything from the end marker onwards is free.
+                    if run_len == 0 {
+                        run_start = i;
+                    }
+                    break;
+                }
+                ENTRY_DELETED => {
+                    if run_len == 0 {
+                        run_start = i;
+                    }
+
+                    run_len += 1;
+
+                    if run_len == count {
+                        return Ok(run_start as u64);
+                    }
+                }
+                _ => {
+                    run_start = total;
+                    run_len = 0;
+                }
+            }
+        }
+
+        // Only a run reaching the end of the directory can be extended.
+        let available = total - run_start;
+
+        if available < count {
+            let bpc = self.fs.bytes_per_cluster();
+            let new_clusters = ((count - available) * DIR_ENTRY_SIZE).div_ceil(bpc);
+            let (last, _) = chain_tail(&*self.fs, self.root)?;
+
+            grow_chain(&*self.fs, Some(last), new_clusters).await?;
+        }
+
+        Ok(run_start as u64)
+    }
+
+    /// Reads the raw entry slot at `index`.
+    async fn read_slot(&self, index: u64) -> Result<[u8; DIR_ENTRY_SIZE]> {
+        let mut buf = [0; DIR_ENTRY_SIZE];
+        let reader = Fat32Reader::new(self.fs.clone(), self.root, u64::MAX);
+
+        if reader
+            .read_at(index * DIR_ENTRY_SIZE as u64, &mut buf)
+            .await?
+            != DIR_ENTRY_SIZE
+        {
+            return Err(FsError::InvalidFs.into());
+        }
+
+        Ok(buf)
+    }
+
+    /// Reads the raw 8.3 entry at `index`.
+    async fn read_entry(&self, index: u64) -> Result<DirEntry> {
+        let buf = self.read_slot(index).await?;
+
+        // SAFETY: `DirEntry` is a packed, 32-byte POD type.
+        Ok(unsafe { ptr::read_unaligned(buf.as_ptr() as *const _) })
+    }
+
+    /// Returns the first cluster of the journal, if this directory holds the
+    /// entry reserving it.
+    pub async fn find_journal(&self) -> Result<Option<Cluster>> {
+        let raw = self.read_raw().await?;
+
+        Ok(raw
+            .chunks_exact(DIR_ENTRY_SIZE)
+            .take_while(|e| e[0] != ENTRY_END)
+            .find(|e| e[0] != ENTRY_DELETED && is_journal(e))
+            .map(|e| {
+                Cluster::from_high_low(
+                    u16::from_le_bytes([e[20], e[21]]),
+                    u16::from_le_bytes([e[26], e[27]]),
+                )
+            })
+            .filter(|cluster| cluster.is_valid()))
+    }
+
+    /// Adds an entry named `name` to the directory as part of `tx`,
+    /// generating an 8.3 alias and LFN entries when the name needs them. The
+    /// name fields of `entry` are overwritten; everything else is stored as
+    /// given. Returns the location of the new 8.3 entry.
+    async fn add_entry(
+        &self,
+        name: &str,
+        mut entry: DirEntry,
+        tx: &mut Transaction,
+    ) -> Result<DirEntryLoc> {
+        if !is_valid_long_name(name) {
+            return Err(FsError::InvalidInput.into());
+        }
+
+        let raw = self.read_raw().await?;
+
+        let mut entries = Vec::new();
+
+        // The journal's 8.3 name stays unique, as it can't be looked up.
+        let short_name = match exact_short_name(name).filter(|n| n != JOURNAL_NAME) {
+            Some(short_name) => short_name,
+            None => {
+                let taken = raw
+                    .chunks_exact(DIR_ENTRY_SIZE)
+                    .take_while(|e| e[0] != ENTRY_END)
+                    .filter(|e| e[0] != ENTRY_DELETED && e[11] != LFN_ATTR)
+                    .map(|e| e[..11].try_into().unwrap())
+                    .collect();
+
+                let short_name = generate_short_name(name, &taken)?;
+                entries.extend(lfn_entries(name, short_name_checksum(&short_name)));
+                short_name
+            }
+        };
+
+        entry.dos_file_name = short_name[..8].try_into().unwrap();
+        entry.dos_extension = short_name[8..].try_into().unwrap();
+        entries.push(entry.to_bytes());
+
+        let count = entries.len();
+        let start = self.find_free_slots(&raw, count).await?;
+
+        for (i, entry) in entries.into_iter().enumerate() {
+            tx.write(
+                DirEntryLoc {
+                    dir: self.root,
+                    index: start + i as u64,
+                },
+                entry,
+            );
+        }
+
+        Ok(DirEntryLoc {
+            dir: self.root,
+            index: start + count as u64 - 1,
+        })
+    }
+
+    /// Marks the 8.3 entry of `entry` and any LFN entries before it as
+    /// deleted as part of `tx`. The clusters the entry points at are left
+    /// alone.
+    async fn remove_entry(&self, entry: &Fat32DirEntry, tx: &mut Transaction) -> Result<()> {
+        for index in entry.first_index..=entry.index {
+            let mut slot = self.read_slot(index).await?;
+            slot[0] = ENTRY_DELETED;
+
+            tx.write(
+                DirEntryLoc {
+                    dir: self.root,
+                    index,
+                },
+                slot,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Removes `entry` from the directory as part of `tx`, refusing to
+    /// remove a directory that isn't empty. The entry's clusters are freed
+    /// by [`Self::release`] once `tx` has been committed.
+    async fn unlink_entry(&self, entry: &Fat32DirEntry, tx: &mut Transaction) -> Result<()> {
+        if entry.attr.file_type == FileType::Directory
+            && !Self::new(self.fs.clone(), entry.cluster, entry.attr.clone())
+                .is_empty()
+                .await?
+        {
+            return Err(FsError::DirectoryNotEmpty.into());
+        }
+
+        self.remove_entry(entry, tx).await
+    }
+
+    /// Frees the clusters of an entry that has been unlinked.
+    async fn release(&self, entry: &Fat32DirEntry) -> Result<()> {
+        let loc = DirEntryLoc {
+            dir: self.root,
+            index: entry.index,
+        };
+
+        // A file that is still open keeps its clusters until the last
+        // reference is dropped.
+        if !FileState::unlink(&*self.fs, loc) && entry.cluster.is_valid() {
+            self.fs.free_chain(entry.cluster).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if `cluster` is this directory or one of its ancestors.
+    /// A chain of ".." entries deeper than [`MAX_DIR_DEPTH`], as a loop on a
+    /// corrupt volume would be, is reported as [`FsError::InvalidFs`].
+    async fn is_within(&self, cluster: Cluster) -> Result<bool> {
+        let root = self.fs.root_cluster();
+        let mut dir = self.root;
+
+        for _ in 0..MAX_DIR_DEPTH {
+            if dir == root {
+                return Ok(cluster == root);
+            }
+
+            if dir == cluster {
+                return Ok(true);
+            }
+
+            let parent = Self::new(self.fs.clone(), dir, self.attr.clone())
+                .find("..")
+                .await?
+                .cluster;
+
+            dir = if parent.is_valid() { parent } else { root };
+        }
+
+        Err(FsError::InvalidFs.into())
     }
 }
 
@@ -365,31 +1064,222 @@ impl<T: Fat32Operations> Inode for Fat32DirNode<T> {
     }
 
     async fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
-        let mut dir_iter = self.streamer.clone();
-
-        while let Some(entry) = dir_iter.next_fat32_entry().await? {
-            if entry.name.eq_ignore_ascii_case(name) {
-                return match entry.attr.file_type {
-                    FileType::File => Ok(Arc::new(Fat32FileNode::new(
-                        self.fs.clone(),
-                        entry.cluster,
-                        entry.attr.clone(),
-                    )?)),
-                    FileType::Directory => Ok(Arc::new(Self::new(
-                        self.fs.clone(),
-                        entry.cluster,
-                        entry.attr.clone(),
-                    ))),
-                    _ => Err(KernelError::NotSupported),
+        let entry = self.find(name).await?;
+
+        match entry.attr.file_type {
+            FileType::File => Ok(Arc::new(Fat32FileNode::new(
+                self.fs.clone(),
+                entry.cluster,
+                entry.attr.clone(),
+                DirEntryLoc {
+                    dir: self.root,
+                    index: entry.index,
+                },
+            )?)),
+            FileType::Directory => {
+                // A ".." entry refers to the root directory as cluster 0.
+                let cluster = if entry.cluster.is_valid() {
+                    entry.cluster
+                } else {
+                    self.fs.root_cluster()
                 };
+
+                Ok(Arc::new(Self::new(
+                    self.fs.clone(),
+                    cluster,
+                    FileAttr {
+                        id: InodeId::from_fsid_and_inodeid(self.fs.id(), cluster.value() as _),
+                        ..entry.attr
+                    },
+                )))
             }
+            _ => Err(KernelError::NotSupported),
         }
+    }
 
-        Err(FsError::NotFound.into())
+    async fn create(
+        &self,
+        name: &str,
+        file_type: FileType,
+        _permissions: FilePermissions,
+    ) -> Result<Arc<dyn Inode>> {
+        if !matches!(file_type, FileType::File | FileType::Directory) {
+            return Err(KernelError::NotSupported);
+        }
+
+        match self.lookup(name).await {
+            Ok(_) => return Err(FsError::AlreadyExists.into()),
+            Err(KernelError::Fs(FsError::NotFound)) => {}
+            Err(e) => return Err(e),
+        }
+
+        let attributes = if file_type == FileType::Directory {
+            Fat32Attributes::DIRECTORY
+        } else {
+            Fat32Attributes::ARCHIVE
+        };
+
+        let mut attr = FileAttr {
+            block_size: self.fs.bytes_per_cluster() as _,
+            file_type,
+            mode: attributes.mode(),
+            ..Default::default()
+        };
+
+        if file_type == FileType::Directory {
+            let cluster = self.new_subdir_cluster().await?;
+
+            let entry = DirEntry::new([b' '; 11], attributes, cluster);
+            let mut tx = Transaction::new();
+
+            let added = match self.add_entry(name, entry, &mut tx).await {
+                Ok(_) => tx.commit(&*self.fs).await,
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = added {
+                self.fs.free_chain(cluster).await?;
+                return Err(e);
+            }
+
+            attr.id = InodeId::from_fsid_and_inodeid(self.fs.id(), cluster.value() as _);
+
+            return Ok(Arc::new(Self::new(self.fs.clone(), cluster, attr)));
+        }
+
+        // New files start out empty, with no clusters allocated.
+        let entry = DirEntry::new([b' '; 11], attributes, Cluster(0));
+        let mut tx = Transaction::new();
+        let loc = self.add_entry(name, entry, &mut tx).await?;
+        tx.commit(&*self.fs).await?;
+
+        Ok(Arc::new(Fat32FileNode::new(
+            self.fs.clone(),
+            Cluster(0),
+            attr,
+            loc,
+        )?))
+    }
+
+    async fn unlink(&self, name: &str) -> Result<()> {
+        if name == "." || name == ".." {
+            return Err(FsError::InvalidInput.into());
+        }
+
+        let entry = self.find(name).await?;
+        let mut tx = Transaction::new();
+
+        // Remove the entry before freeing its clusters, so that a crash in
+        // between leaks clusters rather than leaving an entry that points at
+        // free ones.
+        self.unlink_entry(&entry, &mut tx).await?;
+        tx.commit(&*self.fs).await?;
+
+        self.release(&entry).await
+    }
+
+    async fn rename_from(
+        &self,
+        old_parent: Arc<dyn Inode>,
+        old_name: &str,
+        new_name: &str,
+        no_replace: bool,
+    ) -> Result<()> {
+        let old_parent = Arc::downcast::<Self>(old_parent).map_err(|_| FsError::CrossDevice)?;
+
+        if old_parent.fs.id() != self.fs.id() {
+            return Err(FsError::CrossDevice.into());
+        }
+
+        if old_name == "." || old_name == ".." {
+            return Err(FsError::InvalidInput.into());
+        }
+
+        if old_name == new_name && old_parent.root == self.root {
+            return Ok(());
+        }
+
+        let entry = old_parent.find(old_name).await?;
+        let is_dir = entry.attr.file_type == FileType::Directory;
+        let moved = old_parent.root != self.root;
+
+        // A directory can't be moved underneath itself.
+        if is_dir && moved && self.is_within(entry.cluster).await? {
+            return Err(KernelError::InvalidValue);
+        }
+
+        // Every entry update below lands together through the journal, if
+        // the volume has one.
+        let mut tx = Transaction::new();
+
+        let replaced = match self.find(new_name).await {
+            // Renaming an entry onto itself, e.g. to change the case of its
+            // name, replaces nothing.
+            Ok(target) if !moved && target.index == entry.index => None,
+            Ok(_) if no_replace => return Err(FsError::AlreadyExists.into()),
+            Ok(target) => {
+                match (is_dir, target.attr.file_type == FileType::Directory) {
+                    (true, false) => return Err(FsError::NotADirectory.into()),
+                    (false, true) => return Err(FsError::IsADirectory.into()),
+                    _ => {}
+                }
+
+                self.unlink_entry(&target, &mut tx).await?;
+                Some(target)
+            }
+            Err(KernelError::Fs(FsError::NotFound)) => None,
+            Err(e) => return Err(e),
+        };
+
+        // Without a journal, the new entry is written before the old one is
+        // removed, so a crash part way through leaves the file reachable
+        // under both names rather than under neither. The entry is copied as
+        // is, so the file's data clusters, size and timestamps are untouched.
+        let raw_entry = old_parent.read_entry(entry.index).await?;
+        let new_loc = self.add_entry(new_name, raw_entry, &mut tx).await?;
+
+        if is_dir && moved {
+            let parent = if self.root == self.fs.root_cluster() {
+                Cluster(0)
+            } else {
+                self.root
+            };
+
+            let moved_dir = Self::new(self.fs.clone(), entry.cluster, entry.attr.clone());
+            let mut dotdot = moved_dir.read_entry(1).await?;
+            dotdot.clust_high = (parent.0 >> 16) as u16;
+            dotdot.clust_low = parent.0 as u16;
+
+            tx.write(
+                DirEntryLoc {
+                    dir: entry.cluster,
+                    index: 1,
+                },
+                dotdot.to_bytes(),
+            );
+        }
+
+        old_parent.remove_entry(&entry, &mut tx).await?;
+        tx.commit(&*self.fs).await?;
+
+        if let Some(target) = replaced {
+            self.release(&target).await?;
+        }
+
+        FileState::relocate(
+            &*self.fs,
+            DirEntryLoc {
+                dir: old_parent.root,
+                index: entry.index,
+            },
+            new_loc,
+        );
+
+        Ok(())
     }
 
     async fn readdir(&self, start_offset: u64) -> Result<Box<dyn DirStream>> {
-        let mut iter = self.streamer.clone();
+        let mut iter = self.streamer();
 
         iter.advance(start_offset);
 
@@ -408,6 +1298,66 @@ mod test {
 
     mod raw_test;
 
+    #[test]
+    fn attributes_map_to_mode_and_back() {
+        let st_mode = |attrs: Fat32Attributes| {
+            u32::from(FileType::try_from(attrs).unwrap()) | attrs.mode().bits() as u32
+        };
+
+        // Regular files, whether or not they've been archived.
+        assert_eq!(st_mode(Fat32Attributes::ARCHIVE), 0o100644);
+        assert_eq!(st_mode(Fat32Attributes::empty()), 0o100644);
+
+        let read_only = Fat32Attributes::READ_ONLY | Fat32Attributes::ARCHIVE;
+        assert_eq!(st_mode(read_only), 0o100444);
+        assert_eq!(read_only.with_mode(read_only.mode()), read_only);
+        assert_eq!(
+            read_only.with_mode(FilePermissions::from_bits_retain(0o644)),
+            Fat32Attributes::ARCHIVE
+        );
+        assert_eq!(
+            Fat32Attributes::ARCHIVE.with_mode(FilePermissions::from_bits_retain(0o555)),
+            read_only
+        );
+
+        // Directories ignore READ_ONLY both ways.
+        let dir = Fat32Attributes::DIRECTORY;
+        assert_eq!(st_mode(dir), 0o040755);
+        assert_eq!(st_mode(dir | Fat32Attributes::READ_ONLY), 0o040755);
+        assert_eq!(dir.with_mode(FilePermissions::from_bits_retain(0o555)), dir);
+        assert_eq!(dir.with_mode(dir.mode()), dir);
+
+        // Devices aren't supported.
+        assert!(FileType::try_from(Fat32Attributes::DEVICE).is_err());
+    }
+
+    #[test]
+    fn fat_datetime_round_trips() {
+        let secs = |d: Duration| d.as_secs();
+        let round_trip = |t: u64| {
+            let (date, time) = duration_to_fat_datetime(Duration::from_secs(t));
+            secs(fat_datetime_to_duration(date, time, 0))
+        };
+
+        // 2024-02-29 13:37:43 (a leap day), truncated to the even second.
+        assert_eq!(round_trip(1_709_213_863), 1_709_213_862);
+        assert_eq!(
+            duration_to_fat_datetime(Duration::from_secs(1_709_213_863)),
+            ((44 << 9) | (2 << 5) | 29, (13 << 11) | (37 << 5) | 21)
+        );
+
+        // 1999-12-31 23:59:58 and the first second of 2000.
+        assert_eq!(round_trip(946_684_798), 946_684_798);
+        assert_eq!(round_trip(946_684_800), 946_684_800);
+
+        // Anything before the FAT epoch becomes 1980-01-01.
+        assert_eq!(round_trip(0), 315_532_800);
+        assert_eq!(round_trip(315_532_801), 315_532_800);
+
+        // Anything after 2107 becomes its last representable second.
+        assert_eq!(round_trip(u64::MAX), 4_354_819_198);
+    }
+
     fn checksum_83(name: &[u8; 8], ext: &[u8; 3]) -> u8 {
         let mut sum: u8 = 0;
         for &byte in name.iter().chain(ext.iter()) {
@@ -578,6 +1528,32 @@ mod test {
         assert_eq!(dir.cluster, Cluster(11));
     }
 
+    #[tokio::test]
+    async fn lookup_attr_reports_size_and_stable_id() {
+        let mut data = Vec::new();
+        data.extend_from_slice(
+            &DirEntryBuilder::new("FILE", "TXT")
+                .attributes(Fat32Attributes::ARCHIVE)
+                .cluster(10)
+                .size(1024)
+                .build(),
+        );
+
+        let fs = setup_dir_test(data).await;
+        let dir = Fat32DirNode::new(fs, Cluster(2), FileAttr::default());
+
+        let first = dir.lookup("file.txt").await.unwrap();
+        let second = dir.lookup("FILE.TXT").await.unwrap();
+
+        let attr = first.getattr().await.unwrap();
+        assert_eq!(attr.size, 1024);
+        // Two 512-byte clusters, reported in 512-byte units.
+        assert_eq!(attr.blocks, 2);
+        assert_eq!(attr.id, first.id());
+        assert_eq!(attr.id, second.getattr().await.unwrap().id);
+        assert_eq!(attr.id.inode_id(), 10);
+    }
+
     #[tokio::test]
     async fn test_single_lfn_entry() {
         let sfn = DirEntryBuilder::new("TESTFI~1", "TXT")
@@ -5,6 +5,7 @@ use crate::{
 
 use alloc::vec;
 use alloc::vec::Vec;
+use core::cmp::min;
 
 use super::{Cluster, bpb::BiosParameterBlock};
 
@@ -30,23 +31,59 @@ impl From<u32> for FatEntry {
     }
 }
 
+impl From<&FatEntry> for u32 {
+    fn from(value: &FatEntry) -> Self {
+        match value {
+            FatEntry::Free => 0,
+            FatEntry::Reserved => 1,
+            FatEntry::NextCluster(cluster) => cluster.0,
+            FatEntry::Bad => 0xFFFFFF7,
+            FatEntry::Eoc => 0xFFFFFFF,
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Debug)]
 pub struct Fat {
     data: Vec<FatEntry>,
+    // One past the highest cluster number that is backed by the data region.
+    // The FAT itself is usually rounded up to whole sectors, so entries past
+    // this point don't describe real clusters.
+    max_cluster: usize,
+    free_clusters: usize,
+    next_free: Cluster,
 }
 
-pub struct ClusterChainIterator<'a> {
-    fat: &'a Fat,
+/// The position of a walk along a cluster chain.
+///
+/// This is kept separate from the FAT itself so that a walk can be resumed
+/// across calls which each take the FAT lock only for a single step.
+pub struct ClusterChain {
     current_or_next: Option<Cluster>,
+    // A valid chain visits each FAT entry at most once, so walking more
+    // clusters than the FAT holds means the chain loops back on itself.
+    steps_left: usize,
 }
 
-impl<'a> Iterator for ClusterChainIterator<'a> {
-    type Item = Result<Cluster>;
+impl ClusterChain {
+    pub fn new(fat: &Fat, root: Cluster) -> Self {
+        Self {
+            current_or_next: Some(root),
+            steps_left: fat.data.len(),
+        }
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
+    pub fn next(&mut self, fat: &Fat) -> Option<Result<Cluster>> {
         let cluster_to_return = self.current_or_next?;
 
-        let entry = match self.fat.data.get(cluster_to_return.value()) {
+        if self.steps_left == 0 {
+            self.current_or_next = None;
+            return Some(Err(IoError::MetadataCorruption.into()));
+        }
+
+        self.steps_left -= 1;
+
+        let entry = match fat.data.get(cluster_to_return.value()) {
             Some(entry) => entry,
             None => {
                 self.current_or_next = None;
@@ -71,6 +108,19 @@ impl<'a> Iterator for ClusterChainIterator<'a> {
     }
 }
 
+pub struct ClusterChainIterator<'a> {
+    fat: &'a Fat,
+    chain: ClusterChain,
+}
+
+impl<'a> Iterator for ClusterChainIterator<'a> {
+    type Item = Result<Cluster>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.chain.next(self.fat)
+    }
+}
+
 impl Fat {
     pub async fn read_fat(
         dev: &BlockBuffer,
@@ -95,66 +145,144 @@ impl Fat {
             );
         }
 
-        Ok(Self { data: fat })
+        let max_cluster = min(fat.len(), bpb.cluster_count() + 2);
+
+        let free_clusters = fat
+            .iter()
+            .take(max_cluster)
+            .skip(2)
+            .filter(|e| **e == FatEntry::Free)
+            .count();
+
+        Ok(Self {
+            data: fat,
+            max_cluster,
+            free_clusters,
+            next_free: Cluster(2),
+        })
     }
 
     pub fn get_cluster_chain(&self, root: Cluster) -> impl Iterator<Item = Result<Cluster>> {
         ClusterChainIterator {
             fat: self,
-            current_or_next: Some(root),
+            chain: ClusterChain::new(self, root),
         }
     }
-}
 
-#[cfg(test)]
-mod test {
-    use crate::error::{IoError, KernelError, Result};
-    use crate::fs::filesystems::fat32::Cluster;
-    use crate::fs::filesystems::fat32::bpb::test::create_test_bpb;
-    use crate::fs::filesystems::fat32::fat::{Fat, FatEntry};
-    use crate::fs::{BlockDevice, blk::buffer::BlockBuffer};
-    use async_trait::async_trait;
+    /// Returns the number of data clusters on the volume.
+    pub fn cluster_count(&self) -> usize {
+        self.max_cluster.saturating_sub(2)
+    }
 
-    const EOC: u32 = 0xFFFFFFFF;
-    const BAD: u32 = 0xFFFFFFF7;
-    const FREE: u32 = 0;
-    const RESERVED: u32 = 1;
+    pub fn free_clusters(&self) -> usize {
+        self.free_clusters
+    }
 
-    struct MemBlkDevice {
-        data: Vec<u8>,
+    pub fn next_free_hint(&self) -> Cluster {
+        self.next_free
     }
 
-    #[async_trait]
-    impl BlockDevice for MemBlkDevice {
-        /// Read one or more blocks starting at `block_id`.
-        /// The `buf` length must be a multiple of `block_size`.
-        async fn read(&self, block_id: u64, buf: &mut [u8]) -> Result<()> {
-            buf.copy_from_slice(&self.data[block_id as usize..block_id as usize + buf.len()]);
-            Ok(())
+    /// Sets where the search for a free cluster begins, typically from the
+    /// FSInfo sector. Out-of-range hints are ignored.
+    pub fn set_next_free_hint(&mut self, hint: Cluster) {
+        if hint.is_valid() && hint.value() < self.max_cluster {
+            self.next_free = hint;
         }
+    }
 
-        /// Write one or more blocks starting at `block_id`.
-        /// The `buf` length must be a multiple of `block_size`.
-        async fn write(&self, _block_id: u64, _buf: &[u8]) -> Result<()> {
-            unimplemented!()
+    /// Returns the raw on-disk value of the FAT entry for `cluster`.
+    pub fn raw_entry(&self, cluster: Cluster) -> Result<u32> {
+        self.data
+            .get(cluster.value())
+            .map(u32::from)
+            .ok_or(IoError::OutOfBounds.into())
+    }
+
+    /// Allocates a free cluster and marks it as the end of a chain. If `prev`
+    /// is given, the new cluster is linked onto the end of that chain.
+    pub fn alloc_cluster(&mut self, prev: Option<Cluster>) -> Result<Cluster> {
+        if prev.is_some_and(|p| self.data.get(p.value()) != Some(&FatEntry::Eoc)) {
+            return Err(IoError::MetadataCorruption.into());
         }
 
-        /// The size of a single block in bytes.
-        fn block_size(&self) -> usize {
-            1
+        let start = self.next_free.value();
+
+        let new = (start..self.max_cluster)
+            .chain(2..start)
+            .find(|&c| self.data[c] == FatEntry::Free)
+            .map(|c| Cluster(c as _))
+            .ok_or(FsError::NoSpace)?;
+
+        self.data[new.value()] = FatEntry::Eoc;
+
+        if let Some(prev) = prev {
+            self.data[prev.value()] = FatEntry::NextCluster(new);
         }
 
-        /// Flushes any caches to the underlying device.
-        async fn sync(&self) -> Result<()> {
-            unimplemented!()
+        self.free_clusters -= 1;
+        self.next_free = if new.value() + 1 < self.max_cluster {
+            Cluster(new.0 + 1)
+        } else {
+            Cluster(2)
+        };
+
+        Ok(new)
+    }
+
+    /// Releases every cluster in the chain starting at `root`, returning the
+    /// clusters that were freed.
+    pub fn free_chain(&mut self, root: Cluster) -> Result<Vec<Cluster>> {
+        let chain = self.get_cluster_chain(root).collect::<Result<Vec<_>>>()?;
+
+        for cluster in chain.iter() {
+            self.data[cluster.value()] = FatEntry::Free;
         }
+
+        self.free_clusters += chain.len();
+
+        Ok(chain)
+    }
+
+    /// Makes `last` the final cluster of its chain, freeing every cluster
+    /// that followed it. Returns all clusters whose entries changed.
+    pub fn truncate_chain(&mut self, last: Cluster) -> Result<Vec<Cluster>> {
+        let next = match self.data.get(last.value()) {
+            Some(FatEntry::Eoc) => return Ok(Vec::new()),
+            Some(FatEntry::NextCluster(next)) => *next,
+            Some(_) => return Err(IoError::MetadataCorruption.into()),
+            None => return Err(IoError::OutOfBounds.into()),
+        };
+
+        let mut changed = self.free_chain(next)?;
+
+        self.data[last.value()] = FatEntry::Eoc;
+        changed.push(last);
+
+        Ok(changed)
     }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::error::{IoError, KernelError};
+    use crate::fs::blk::{
+        buffer::BlockBuffer,
+        test::{MemBacking, MemBlkDevice},
+    };
+    use crate::fs::filesystems::fat32::Cluster;
+    use crate::fs::filesystems::fat32::bpb::test::create_test_bpb;
+    use crate::fs::filesystems::fat32::fat::{Fat, FatEntry};
+
+    const EOC: u32 = 0xFFFFFFFF;
+    const BAD: u32 = 0xFFFFFFF7;
+    const FREE: u32 = 0;
+    const RESERVED: u32 = 1;
 
     fn setup_fat_test(fat_data: &[u32]) -> BlockBuffer {
         let mut data = Vec::new();
         data.extend(fat_data.iter().flat_map(|x| x.to_le_bytes()));
 
-        BlockBuffer::new(Box::new(MemBlkDevice { data }))
+        BlockBuffer::new(Box::new(MemBlkDevice::new(MemBacking::new(data), 1)))
     }
 
     #[tokio::test]
@@ -249,8 +377,13 @@ mod test {
             /* 16 */ 99, // Chain pointing out of bounds
         ];
 
-        let data = fat_data.iter().map(|&v| FatEntry::from(v)).collect();
-        Fat { data }
+        let data: Vec<_> = fat_data.iter().map(|&v| FatEntry::from(v)).collect();
+        Fat {
+            max_cluster: data.len(),
+            free_clusters: 1,
+            next_free: Cluster(2),
+            data,
+        }
     }
 
     #[test]
@@ -304,6 +437,23 @@ mod test {
         ));
     }
 
+    #[test]
+    fn test_chain_with_cycle() {
+        let fat = setup_chain_test_fat();
+        let chain: Vec<_> = fat.get_cluster_chain(Cluster(13)).collect();
+
+        // The walk must terminate, having reported the loop as corruption.
+        assert!(chain.len() <= fat.data.len() + 1);
+        assert!(matches!(
+            chain.last(),
+            Some(Err(KernelError::Io(IoError::MetadataCorruption)))
+        ));
+        assert_eq!(
+            chain[..3],
+            [Ok(Cluster(13)), Ok(Cluster(14)), Ok(Cluster(15))]
+        );
+    }
+
     #[test]
     fn test_chain_points_out_of_bounds() {
         let fat = setup_chain_test_fat();
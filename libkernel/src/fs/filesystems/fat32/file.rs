@@ -1,29 +1,217 @@
 use crate::{
-    error::Result,
-    fs::{Inode, InodeId, attr::FileAttr},
+    error::{FsError, KernelError, Result},
+    fs::{
+        Inode, InodeId,
+        attr::{FileAttr, FilePermissions},
+    },
+    sync::{mutex::Mutex, spinlock::SpinLockIrq},
 };
 use alloc::boxed::Box;
 use alloc::sync::Arc;
+use alloc::vec;
 use async_trait::async_trait;
+use core::{cmp::min, time::Duration};
 
-use super::{Cluster, Fat32Operations, reader::Fat32Reader};
+use super::{
+    Cluster, Fat32Operations,
+    dir::{DirEntryLoc, Fat32Attributes, update_entry, update_entry_mode, update_entry_times},
+    reader::Fat32Reader,
+    writer::{chain_tail, grow_chain, write_chain},
+};
+
+/// FAT32 stores file sizes in a 32-bit field.
+const MAX_FILE_SIZE: u64 = u32::MAX as u64;
+
+struct FileInner {
+    loc: DirEntryLoc,
+    // The first cluster of the file, or zero while it has no data.
+    root: Cluster,
+    size: u64,
+    /// The permission bits, which follow the entry's read-only attribute.
+    mode: FilePermissions,
+    atime: Duration,
+    mtime: Duration,
+    unlinked: bool,
+}
+
+/// State shared by every live inode for the same file.
+///
+/// A file's first cluster and size live in its directory entry, so inodes
+/// looked up separately must agree on them. This is also what lets an
+/// unlinked file keep its data until the last reference is dropped.
+pub struct FileState<T: Fat32Operations> {
+    fs: Arc<T>,
+    inner: SpinLockIrq<FileInner, T::Cpu>,
+    /// Held while the cluster chain or the size changes, so writers on
+    /// separate descriptors don't each grow the chain, and truncation doesn't
+    /// free clusters a write is still filling.
+    io: Mutex<(), T::Cpu>,
+}
+
+impl<T: Fat32Operations> FileState<T> {
+    /// Returns the state of the file whose entry is at `loc`, creating it
+    /// from the entry's `root` and `attr` if nothing holds it yet.
+    pub fn get(fs: Arc<T>, loc: DirEntryLoc, root: Cluster, attr: &FileAttr) -> Arc<Self> {
+        let mut files = fs.open_files().lock_save_irq();
+
+        if let Some(state) = files.get(&loc).and_then(|f| f.upgrade()) {
+            return state;
+        }
+
+        files.retain(|_, f| f.strong_count() > 0);
+
+        let state = Arc::new(Self {
+            fs: fs.clone(),
+            inner: SpinLockIrq::new(FileInner {
+                loc,
+                root,
+                size: attr.size,
+                mode: attr.mode,
+                atime: attr.atime,
+                mtime: attr.mtime,
+                unlinked: false,
+            }),
+            io: Mutex::new(()),
+        });
+
+        files.insert(loc, Arc::downgrade(&state));
+
+        state
+    }
+
+    /// Marks the file at `loc` as unlinked. Returns `false` if nothing holds
+    /// the file, in which case the caller must free its clusters.
+    pub fn unlink(fs: &T, loc: DirEntryLoc) -> bool {
+        let state = fs
+            .open_files()
+            .lock_save_irq()
+            .remove(&loc)
+            .and_then(|f| f.upgrade());
+
+        match state {
+            Some(state) => {
+                state.inner.lock_save_irq().unlinked = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves the file whose entry was at `old` to `new`, after a rename.
+    pub fn relocate(fs: &T, old: DirEntryLoc, new: DirEntryLoc) {
+        let mut files = fs.open_files().lock_save_irq();
+
+        if let Some(file) = files.remove(&old) {
+            if let Some(state) = file.upgrade() {
+                state.inner.lock_save_irq().loc = new;
+            }
+
+            files.insert(new, file);
+        }
+    }
+}
+
+impl<T: Fat32Operations> Drop for FileState<T> {
+    fn drop(&mut self) {
+        let inner = self.inner.lock_save_irq();
+
+        if inner.unlinked && inner.root.is_valid() {
+            self.fs.free_orphan(inner.root);
+        }
+    }
+}
 
 pub struct Fat32FileNode<T: Fat32Operations> {
-    reader: Fat32Reader<T>,
+    state: Arc<FileState<T>>,
     attr: FileAttr,
     id: InodeId,
 }
 
 impl<T: Fat32Operations> Fat32FileNode<T> {
-    pub fn new(fs: Arc<T>, root: Cluster, attr: FileAttr) -> Result<Self> {
-        let id = InodeId::from_fsid_and_inodeid(fs.id() as _, root.value() as _);
+    pub fn new(fs: Arc<T>, root: Cluster, attr: FileAttr, loc: DirEntryLoc) -> Result<Self> {
+        // An empty file has no cluster, so it's known by its entry instead.
+        let ino = if root.is_valid() {
+            root.value() as _
+        } else {
+            loc.inode_id()
+        };
+        let id = InodeId::from_fsid_and_inodeid(fs.id() as _, ino);
 
         Ok(Self {
-            reader: Fat32Reader::new(fs, root, attr.size),
-            attr,
+            state: FileState::get(fs, loc, root, &attr),
+            attr: FileAttr { id, ..attr },
             id,
         })
     }
+
+    fn fs(&self) -> &T {
+        &self.state.fs
+    }
+
+    fn root(&self) -> Cluster {
+        self.state.inner.lock_save_irq().root
+    }
+
+    fn size(&self) -> u64 {
+        self.state.inner.lock_save_irq().size
+    }
+
+    /// Grows the cluster chain until it can hold `len` bytes.
+    async fn ensure_capacity(&self, len: u64) -> Result<()> {
+        let needed = len.div_ceil(self.fs().bytes_per_cluster() as u64) as usize;
+        let root = self.root();
+
+        let (last, have) = if root.is_valid() {
+            let (last, have) = chain_tail(self.fs(), root)?;
+            (Some(last), have)
+        } else {
+            (None, 0)
+        };
+
+        if needed > have {
+            let first = grow_chain(self.fs(), last, needed - have).await?;
+
+            if let (None, Some(first)) = (last, first) {
+                self.state.inner.lock_save_irq().root = first;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Zeroes the bytes in `from..to`, growing the file as needed. Newly
+    /// allocated clusters are already zeroed, but the tail of the old last
+    /// cluster may hold stale data.
+    async fn zero_fill(&self, from: u64, to: u64) -> Result<()> {
+        self.ensure_capacity(to).await?;
+
+        let zeroes = vec![0; self.fs().bytes_per_cluster()];
+        let mut pos = from;
+
+        while pos < to {
+            let n = min(zeroes.len() as u64, to - pos) as usize;
+            write_chain(self.fs(), self.root(), pos, &zeroes[..n]).await?;
+            pos += n as u64;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the current first cluster and size back to the directory entry.
+    async fn sync_entry(&self) -> Result<()> {
+        let (loc, root, size) = {
+            let inner = self.state.inner.lock_save_irq();
+
+            // The entry slot of an unlinked file may already have been reused.
+            if inner.unlinked {
+                return Ok(());
+            }
+
+            (inner.loc, inner.root, inner.size)
+        };
+
+        update_entry(self.fs(), loc, root, size as u32).await
+    }
 }
 
 #[async_trait]
@@ -32,18 +220,168 @@ impl<T: Fat32Operations> Inode for Fat32FileNode<T> {
         self.id
     }
 
+    /// The ID is the first cluster the file had when it was looked up, so it's
+    /// only unique while the file still starts there and hasn't been unlinked,
+    /// after which the cluster could be given to another file.
+    fn cacheable(&self) -> bool {
+        let inner = self.state.inner.lock_save_irq();
+        let id = InodeId::from_fsid_and_inodeid(self.fs().id() as _, inner.root.value() as _);
+
+        !inner.unlinked && inner.root.is_valid() && self.id == id
+    }
+
     async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
-        self.reader.read_at(offset, buf).await
+        Fat32Reader::new(self.state.fs.clone(), self.root(), self.size())
+            .read_at(offset, buf)
+            .await
+    }
+
+    async fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let end = offset
+            .checked_add(buf.len() as u64)
+            .filter(|&end| end <= MAX_FILE_SIZE)
+            .ok_or(FsError::FileTooLarge)?;
+
+        let _io = self.state.io.lock().await;
+        let size = self.size();
+
+        if offset > size {
+            self.zero_fill(size, offset).await?;
+        }
+
+        self.ensure_capacity(end).await?;
+        write_chain(self.fs(), self.root(), offset, buf).await?;
+
+        {
+            let mut inner = self.state.inner.lock_save_irq();
+            inner.size = inner.size.max(end);
+        }
+
+        self.sync_entry().await?;
+
+        Ok(buf.len())
+    }
+
+    async fn truncate(&self, new_size: u64) -> Result<()> {
+        if new_size > MAX_FILE_SIZE {
+            return Err(FsError::FileTooLarge.into());
+        }
+
+        let _io = self.state.io.lock().await;
+        let size = self.size();
+        let root = self.root();
+
+        if new_size > size {
+            self.zero_fill(size, new_size).await?;
+        } else if new_size < size && root.is_valid() {
+            let keep = new_size.div_ceil(self.fs().bytes_per_cluster() as u64) as usize;
+
+            if keep == 0 {
+                self.fs().free_chain(root).await?;
+                self.state.inner.lock_save_irq().root = Cluster(0);
+            } else {
+                let last = self
+                    .fs()
+                    .iter_clusters(root)
+                    .nth(keep - 1)
+                    .ok_or(FsError::InvalidFs)??;
+
+                self.fs().truncate_chain(last).await?;
+            }
+        }
+
+        self.state.inner.lock_save_irq().size = new_size;
+        self.sync_entry().await
     }
 
     async fn getattr(&self) -> Result<FileAttr> {
-        Ok(self.attr.clone())
+        let bpc = self.fs().bytes_per_cluster() as u64;
+        let (size, mode, atime, mtime, unlinked) = {
+            let inner = self.state.inner.lock_save_irq();
+            (
+                inner.size,
+                inner.mode,
+                inner.atime,
+                inner.mtime,
+                inner.unlinked,
+            )
+        };
+
+        Ok(FileAttr {
+            size,
+            mode,
+            atime,
+            mtime,
+            nlinks: if unlinked { 0 } else { 1 },
+            // `st_blocks` is always in 512-byte units, regardless of the
+            // cluster size.
+            blocks: size.div_ceil(bpc) * bpc / 512,
+            ..self.attr.clone()
+        })
+    }
+
+    /// Only the mode and the access and modification times can be changed,
+    /// and only as far as the entry can record them. FAT has no owners, so
+    /// they can't change.
+    async fn setattr(&self, attr: FileAttr) -> Result<()> {
+        if attr.uid != self.attr.uid || attr.gid != self.attr.gid {
+            return Err(KernelError::NotPermitted);
+        }
+
+        let (loc, mode, atime, mtime) = {
+            let inner = self.state.inner.lock_save_irq();
+            let loc = if inner.unlinked {
+                None
+            } else {
+                Some(inner.loc)
+            };
+
+            (loc, inner.mode, inner.atime, inner.mtime)
+        };
+
+        let (mode, (atime, mtime)) = match loc {
+            Some(loc) => {
+                let mode = if attr.mode.bits() != mode.bits() {
+                    update_entry_mode(&self.state.fs, loc, attr.mode).await?
+                } else {
+                    mode
+                };
+
+                let times = if attr.atime != atime || attr.mtime != mtime {
+                    update_entry_times(self.fs(), loc, attr.atime, attr.mtime).await?
+                } else {
+                    (atime, mtime)
+                };
+
+                (mode, times)
+            }
+            // There's no entry left to record them in.
+            None => (
+                Fat32Attributes::ARCHIVE.with_mode(attr.mode).mode(),
+                (attr.atime, attr.mtime),
+            ),
+        };
+
+        let mut inner = self.state.inner.lock_save_irq();
+        inner.mode = mode;
+        inner.atime = atime;
+        inner.mtime = mtime;
+
+        Ok(())
     }
 }
 
 #[cfg(test)]
 pub mod test {
-    use crate::{error::FsError, fs::filesystems::fat32::Sector};
+    use crate::{
+        error::FsError,
+        fs::filesystems::fat32::{OpenFiles, Sector, journal::Journal},
+        test::MockCpuOps,
+    };
 
     use super::*;
     use alloc::{collections::BTreeMap, sync::Arc, vec};
@@ -52,6 +390,7 @@ pub mod test {
         file_data: BTreeMap<u32, Vec<u8>>, // Map Sector(u32) -> data
         sector_size: usize,
         sectors_per_cluster: usize,
+        open_files: OpenFiles<Self>,
     }
 
     impl MockFs {
@@ -70,11 +409,14 @@ pub mod test {
                 file_data,
                 sector_size,
                 sectors_per_cluster,
+                open_files: SpinLockIrq::new(BTreeMap::new()),
             }
         }
     }
 
     impl Fat32Operations for MockFs {
+        type Cpu = MockCpuOps;
+
         async fn read_sector(
             &self,
             sector: Sector,
@@ -91,6 +433,9 @@ pub mod test {
         fn id(&self) -> u64 {
             0
         }
+        fn root_cluster(&self) -> Cluster {
+            Cluster(2)
+        }
         fn sector_size(&self) -> usize {
             self.sector_size
         }
@@ -115,6 +460,45 @@ pub mod test {
                 (self.file_data.len() + self.sectors_per_cluster - 1) / self.sectors_per_cluster;
             (0..num_clusters).map(move |i| Ok(Cluster((root.value() + i) as u32)))
         }
+
+        // The mock only backs read paths; writes are tested against a real
+        // volume image.
+        async fn write_sector(
+            &self,
+            _sector: Sector,
+            _offset: usize,
+            _buf: &[u8],
+        ) -> Result<usize> {
+            unimplemented!()
+        }
+
+        async fn alloc_cluster(&self, _prev: Option<Cluster>) -> Result<Cluster> {
+            unimplemented!()
+        }
+
+        async fn free_chain(&self, _root: Cluster) -> Result<()> {
+            unimplemented!()
+        }
+
+        async fn truncate_chain(&self, _last: Cluster) -> Result<()> {
+            unimplemented!()
+        }
+
+        fn free_orphan(&self, _root: Cluster) {
+            unimplemented!()
+        }
+
+        async fn sync_dev(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn open_files(&self) -> &OpenFiles<Self> {
+            &self.open_files
+        }
+
+        fn journal(&self) -> Option<&Journal<MockCpuOps>> {
+            None
+        }
     }
 
     async fn setup_file_test(content: &[u8]) -> Fat32FileNode<MockFs> {
@@ -126,6 +510,10 @@ pub mod test {
                 size: content.len() as _,
                 ..FileAttr::default()
             },
+            DirEntryLoc {
+                dir: Cluster(2),
+                index: 0,
+            },
         )
         .unwrap()
     }
@@ -0,0 +1,75 @@
+use crate::{error::Result, fs::blk::buffer::BlockBuffer, pod::Pod};
+use log::warn;
+
+use super::{Sector, bpb::BiosParameterBlock};
+
+const LEAD_SIG: u32 = 0x41615252;
+const STRUCT_SIG: u32 = 0x61417272;
+const TRAIL_SIG: u32 = 0xAA550000;
+
+/// Offset of `free_count` within the sector; `next_free` follows it.
+const COUNTS_OFFSET: u64 = 488;
+
+/// Value used by both counters to mean "not known".
+pub const UNKNOWN: u32 = 0xFFFFFFFF;
+
+/// The FAT32 FSInfo sector, which caches the free cluster count and a hint
+/// for where to start looking for free clusters.
+#[repr(C, packed)]
+pub struct FsInfo {
+    lead_sig: u32,
+    _reserved1: [u8; 480],
+    struct_sig: u32,
+    pub free_count: u32,
+    pub next_free: u32,
+    _reserved2: [u8; 12],
+    trail_sig: u32,
+}
+
+unsafe impl Pod for FsInfo {}
+
+impl FsInfo {
+    fn sector(bpb: &BiosParameterBlock) -> Option<Sector> {
+        match bpb.fsinfo_sector {
+            0 | 0xFFFF => None,
+            n => Some(Sector(n as _)),
+        }
+    }
+
+    /// Reads the FSInfo sector, returning `None` if the volume doesn't have
+    /// one or its signatures are wrong.
+    pub async fn read(dev: &BlockBuffer, bpb: &BiosParameterBlock) -> Result<Option<Self>> {
+        let Some(sector) = Self::sector(bpb) else {
+            return Ok(None);
+        };
+
+        let info: Self = dev.read_obj(bpb.sector_offset(sector)).await?;
+
+        if info.lead_sig != LEAD_SIG || info.struct_sig != STRUCT_SIG || info.trail_sig != TRAIL_SIG
+        {
+            warn!("FSInfo sector has invalid signatures, ignoring");
+            return Ok(None);
+        }
+
+        Ok(Some(info))
+    }
+
+    /// Writes back the free cluster count and next-free hint.
+    pub async fn write_counts(
+        dev: &BlockBuffer,
+        bpb: &BiosParameterBlock,
+        free_count: u32,
+        next_free: u32,
+    ) -> Result<()> {
+        let Some(sector) = Self::sector(bpb) else {
+            return Ok(());
+        };
+
+        let mut buf = [0; 8];
+        buf[..4].copy_from_slice(&free_count.to_le_bytes());
+        buf[4..].copy_from_slice(&next_free.to_le_bytes());
+
+        dev.write_at(bpb.sector_offset(sector) + COUNTS_OFFSET, &buf)
+            .await
+    }
+}
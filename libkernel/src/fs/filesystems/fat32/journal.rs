@@ -0,0 +1,267 @@
+//! An intent log for directory entry updates.
+//!
+//! FAT has no journal of its own, so a crash part way through an operation
+//! that rewrites several directory entries, such as a rename, can leave only
+//! some of them written. A volume can opt into journaling these updates by
+//! reserving clusters for it: a hidden system file named `MOSSJRNL` in the root
+//! directory.
+//!
+//! A [`Transaction`] collects the new contents of every entry slot an operation
+//! touches. On commit they're written to the journal as one checksummed record
+//! before any slot is updated in place, and the record is cleared once every
+//! slot has been. At mount, a whole record is replayed to finish its operation,
+//! while a torn one is thrown away, leaving the directories as they were before
+//! the operation started.
+//!
+//! Only directory entries are covered. Clusters are allocated and freed
+//! outside transactions, so a crash can still leak clusters, but it can't
+//! leave entries that disagree with each other.
+
+use crate::{
+    CpuOps,
+    error::{FsError, Result},
+    sync::mutex::Mutex,
+};
+use alloc::{sync::Arc, vec, vec::Vec};
+use log::warn;
+
+use super::{
+    Cluster, Fat32Operations,
+    dir::{DIR_ENTRY_SIZE, DirEntryLoc},
+    reader::Fat32Reader,
+    writer::write_chain,
+};
+
+/// The 8.3 name of the file reserving the journal's clusters.
+pub const JOURNAL_NAME: &[u8; 11] = b"MOSSJRNL   ";
+
+const MAGIC: u32 = u32::from_le_bytes(*b"MJNL");
+
+/// The magic, a CRC32 of the rest of the record, and the number of slots.
+const HEADER_SIZE: usize = 12;
+
+/// The cluster and index of a slot, followed by its new contents.
+const SLOT_SIZE: usize = 8 + DIR_ENTRY_SIZE;
+
+/// The region reserved for the journal: the cluster chain of the journal file.
+pub struct Journal<CPU: CpuOps> {
+    root: Cluster,
+    len: usize,
+    /// There's only room for one record, so this is held from writing a
+    /// record until it has been cleared.
+    lock: Mutex<(), CPU>,
+}
+
+impl<CPU: CpuOps> Journal<CPU> {
+    pub fn new(root: Cluster, len: usize) -> Self {
+        Self {
+            root,
+            len,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Finishes the transaction recorded in the journal, if there's a whole
+    /// one, and clears the journal. Returns true if a transaction was
+    /// replayed.
+    pub async fn replay<T: Fat32Operations<Cpu = CPU>>(&self, fs: &Arc<T>) -> Result<bool> {
+        let reader = Fat32Reader::new(fs.clone(), self.root, self.len as u64);
+        let mut header = [0; HEADER_SIZE];
+
+        read_exact(&reader, 0, &mut header).await?;
+
+        if u32::from_le_bytes(header[..4].try_into().unwrap()) != MAGIC {
+            return Ok(false);
+        }
+
+        let crc = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        let count = u32::from_le_bytes(header[8..].try_into().unwrap()) as usize;
+
+        let tx = match count
+            .checked_mul(SLOT_SIZE)
+            .filter(|&n| HEADER_SIZE + n <= self.len)
+        {
+            Some(n) => {
+                let mut record = vec![0; 4 + n];
+                record[..4].copy_from_slice(&header[8..]);
+                read_exact(&reader, HEADER_SIZE as u64, &mut record[4..]).await?;
+
+                (crc32(&record) == crc).then(|| Transaction::decode(&record[4..]))
+            }
+            None => None,
+        };
+
+        let replayed = match tx {
+            Some(tx) => {
+                tx.apply(&**fs).await?;
+                fs.sync_dev().await?;
+                true
+            }
+            None => {
+                warn!("Discarding a torn record from the FAT32 journal");
+                false
+            }
+        };
+
+        self.clear(&**fs).await?;
+
+        Ok(replayed)
+    }
+
+    async fn clear<T: Fat32Operations>(&self, fs: &T) -> Result<()> {
+        write_chain(fs, self.root, 0, &[0; 4]).await?;
+        fs.sync_dev().await
+    }
+}
+
+/// Updates to directory entry slots that land all together or not at all.
+#[derive(Default)]
+pub struct Transaction {
+    writes: Vec<(DirEntryLoc, [u8; DIR_ENTRY_SIZE])>,
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues `entry` to be written to the slot at `loc`. Slots are written in
+    /// the order they're queued, which is all the protection a volume without
+    /// a journal gets.
+    pub fn write(&mut self, loc: DirEntryLoc, entry: [u8; DIR_ENTRY_SIZE]) {
+        self.writes.push((loc, entry));
+    }
+
+    /// Writes every queued slot, through the journal if `fs` has one.
+    pub async fn commit<T: Fat32Operations>(self, fs: &T) -> Result<()> {
+        if self.writes.is_empty() {
+            return Ok(());
+        }
+
+        let Some(journal) = fs.journal() else {
+            return self.apply(fs).await;
+        };
+
+        let record = self.encode();
+
+        if record.len() > journal.len {
+            warn!(
+                "{} directory entry updates don't fit in the FAT32 journal, writing them unjournaled",
+                self.writes.len()
+            );
+            return self.apply(fs).await;
+        }
+
+        let _guard = journal.lock.lock().await;
+
+        write_chain(fs, journal.root, 0, &record).await?;
+        fs.sync_dev().await?;
+
+        self.apply(fs).await?;
+        fs.sync_dev().await?;
+
+        journal.clear(fs).await
+    }
+
+    /// Builds the journal record for the transaction.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut record = Vec::with_capacity(HEADER_SIZE + self.writes.len() * SLOT_SIZE);
+
+        record.extend_from_slice(&MAGIC.to_le_bytes());
+        record.extend_from_slice(&[0; 4]);
+        record.extend_from_slice(&(self.writes.len() as u32).to_le_bytes());
+
+        for (loc, entry) in &self.writes {
+            record.extend_from_slice(&loc.dir.0.to_le_bytes());
+            record.extend_from_slice(&(loc.index as u32).to_le_bytes());
+            record.extend_from_slice(entry);
+        }
+
+        let crc = crc32(&record[8..]);
+        record[4..8].copy_from_slice(&crc.to_le_bytes());
+
+        record
+    }
+
+    fn decode(slots: &[u8]) -> Self {
+        let writes = slots
+            .chunks_exact(SLOT_SIZE)
+            .map(|slot| {
+                let loc = DirEntryLoc {
+                    dir: Cluster(u32::from_le_bytes(slot[..4].try_into().unwrap())),
+                    index: u32::from_le_bytes(slot[4..8].try_into().unwrap()) as u64,
+                };
+
+                (loc, slot[8..].try_into().unwrap())
+            })
+            .collect();
+
+        Self { writes }
+    }
+
+    async fn apply<T: Fat32Operations>(&self, fs: &T) -> Result<()> {
+        for (loc, entry) in &self.writes {
+            write_chain(fs, loc.dir, loc.index * DIR_ENTRY_SIZE as u64, entry).await?;
+        }
+
+        Ok(())
+    }
+}
+
+async fn read_exact<T: Fat32Operations>(
+    reader: &Fat32Reader<T>,
+    offset: u64,
+    buf: &mut [u8],
+) -> Result<()> {
+    if reader.read_at(offset, buf).await? != buf.len() {
+        return Err(FsError::InvalidFs.into());
+    }
+
+    Ok(())
+}
+
+/// The CRC-32 used by zlib and Ethernet.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for &byte in data {
+        crc ^= byte as u32;
+
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn record_round_trips() {
+        let mut tx = Transaction::new();
+        let loc = |dir, index| DirEntryLoc {
+            dir: Cluster(dir),
+            index,
+        };
+
+        tx.write(loc(2, 3), [0xAA; DIR_ENTRY_SIZE]);
+        tx.write(loc(9, 0), [0x55; DIR_ENTRY_SIZE]);
+
+        let record = tx.encode();
+        assert_eq!(record.len(), HEADER_SIZE + 2 * SLOT_SIZE);
+        assert_eq!(record[..4], *b"MJNL");
+        assert_eq!(crc32(&record[8..]).to_le_bytes(), record[4..8]);
+
+        let decoded = Transaction::decode(&record[HEADER_SIZE..]);
+        assert_eq!(decoded.writes, tx.writes);
+    }
+}
@@ -1,10 +1,14 @@
 use crate::{
-    error::{FsError, Result},
-    fs::{FileType, Filesystem, Inode, InodeId, attr::FileAttr, blk::buffer::BlockBuffer},
+    CpuOps,
+    error::{FsError, KernelError, Result},
+    fs::{FileType, Filesystem, FsStats, Inode, InodeId, attr::FileAttr, blk::buffer::BlockBuffer},
+    sync::{once_lock::OnceLock, spinlock::SpinLockIrq},
 };
 use alloc::{
     boxed::Box,
+    collections::BTreeMap,
     sync::{Arc, Weak},
+    vec::Vec,
 };
 use async_trait::async_trait;
 use bpb::BiosParameterBlock;
@@ -13,15 +17,22 @@ use core::{
     fmt::Display,
     ops::{Add, Mul},
 };
-use dir::Fat32DirNode;
-use fat::Fat;
-use log::warn;
+use dir::{DirEntryLoc, Fat32Attributes, Fat32DirNode};
+use fat::{ClusterChain, Fat};
+use file::FileState;
+use fsinfo::FsInfo;
+use journal::Journal;
+use log::{info, warn};
+use writer::chain_tail;
 
 mod bpb;
 mod dir;
 mod fat;
 mod file;
+mod fsinfo;
+mod journal;
 mod reader;
+mod writer;
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct Sector(u32);
@@ -71,18 +82,56 @@ impl Display for Cluster {
     }
 }
 
-pub struct Fat32Filesystem {
+/// When changes to the FAT reach the disk. Either way, syncing the
+/// filesystem, as happens before shutdown, writes out every change.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FatWritePolicy {
+    /// Each allocation or free is flushed to the disk before it returns.
+    /// Slow, but a crash can't lose a change to the FAT.
+    WriteThrough,
+    /// Changes are left dirty in the block cache, to be written out by its
+    /// writeback or when the filesystem is synced.
+    #[default]
+    WriteBack,
+}
+
+impl FatWritePolicy {
+    /// Picks the policy from comma-separated mount options, where
+    /// `fat=writethrough` or `fat=writeback` selects one. Other options are
+    /// left for the caller.
+    pub fn from_mount_options(options: &str) -> Result<Self> {
+        let mut policy = Self::default();
+
+        for option in options.split(',') {
+            policy = match option.split_once('=') {
+                Some(("fat", "writethrough")) => Self::WriteThrough,
+                Some(("fat", "writeback")) => Self::WriteBack,
+                Some(("fat", _)) => return Err(KernelError::InvalidValue),
+                _ => policy,
+            };
+        }
+
+        Ok(policy)
+    }
+}
+
+pub struct Fat32Filesystem<CPU: CpuOps> {
     dev: BlockBuffer,
     bpb: BiosParameterBlock,
-    fat: Fat,
+    fat: SpinLockIrq<Fat, CPU>,
+    // FAT entries changed in memory that still need writing to disk.
+    dirty_fat: SpinLockIrq<Vec<Cluster>, CPU>,
+    fat_policy: SpinLockIrq<FatWritePolicy, CPU>,
+    open_files: OpenFiles<Self>,
+    journal: OnceLock<Journal<CPU>, CPU>,
     id: u64,
     this: Weak<Self>,
 }
 
-impl Fat32Filesystem {
+impl<CPU: CpuOps> Fat32Filesystem<CPU> {
     pub async fn new(dev: BlockBuffer, id: u64) -> Result<Arc<Self>> {
         let bpb = BiosParameterBlock::new(&dev).await?;
-        let fat = Fat::read_fat(&dev, &bpb, 0).await?;
+        let mut fat = Fat::read_fat(&dev, &bpb, 0).await?;
 
         for fat_num in 1..bpb.num_fats {
             let other_fat = Fat::read_fat(&dev, &bpb, fat_num as _).await?;
@@ -93,17 +142,118 @@ impl Fat32Filesystem {
             }
         }
 
-        Ok(Arc::new_cyclic(|weak| Self {
+        // The free count in FSInfo is only advisory, so we always take the
+        // count from the FAT itself. The next-free hint is still worth
+        // honouring as it saves rescanning the start of the FAT.
+        if let Some(info) = FsInfo::read(&dev, &bpb).await? {
+            let next_free = info.next_free;
+
+            if next_free != fsinfo::UNKNOWN {
+                fat.set_next_free_hint(Cluster(next_free));
+            }
+        }
+
+        let fs = Arc::new_cyclic(|weak| Self {
             bpb,
             dev,
-            fat,
+            fat: SpinLockIrq::new(fat),
+            dirty_fat: SpinLockIrq::new(Vec::new()),
+            fat_policy: SpinLockIrq::new(FatWritePolicy::default()),
+            open_files: SpinLockIrq::new(BTreeMap::new()),
+            journal: OnceLock::new(),
             this: weak.clone(),
             id,
-        }))
+        });
+
+        fs.open_journal().await?;
+
+        Ok(fs)
+    }
+
+    /// Picks up the journal, if the volume reserves one, and replays any
+    /// transaction a crash left in it.
+    async fn open_journal(self: &Arc<Self>) -> Result<()> {
+        let root = Fat32DirNode::new(self.clone(), self.bpb.root_cluster, FileAttr::default());
+
+        let Some(cluster) = root.find_journal().await? else {
+            return Ok(());
+        };
+
+        let (_, len) = chain_tail(&**self, cluster)?;
+        let journal = Journal::new(cluster, len * self.bytes_per_cluster());
+
+        if journal.replay(self).await? {
+            info!("Replayed an unfinished directory update from the FAT32 journal");
+        }
+
+        let _ = self.journal.set(journal);
+
+        Ok(())
+    }
+
+    /// Selects when changes to the FAT are written to the disk.
+    pub fn set_fat_write_policy(&self, policy: FatWritePolicy) {
+        *self.fat_policy.lock_save_irq() = policy;
+    }
+
+    /// Returns the number of unallocated clusters on the volume.
+    pub fn free_clusters(&self) -> usize {
+        self.fat.lock_save_irq().free_clusters()
+    }
+
+    /// Writes out FAT entries that were changed without waiting on I/O.
+    async fn flush_fat(&self) -> Result<()> {
+        let dirty = core::mem::take(&mut *self.dirty_fat.lock_save_irq());
+
+        for cluster in dirty {
+            self.write_fat_entry(cluster).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Flushes the FAT entries just written if the policy is write-through.
+    /// Only the FATs are flushed; other dirty blocks are left to writeback.
+    async fn commit_fat(&self) -> Result<()> {
+        let policy = *self.fat_policy.lock_save_irq();
+
+        match policy {
+            FatWritePolicy::WriteThrough => {
+                let start = self.bpb.sector_offset(self.bpb.fat_region_start());
+                let end = self.bpb.sector_offset(self.bpb.data_region_start());
+
+                self.dev.sync_range(start, end - start).await
+            }
+            FatWritePolicy::WriteBack => Ok(()),
+        }
+    }
+
+    /// Copies the in-memory FAT entry for `cluster` to each on-disk FAT,
+    /// preserving the reserved top nibble of the existing entry.
+    async fn write_fat_entry(&self, cluster: Cluster) -> Result<()> {
+        let value = self.fat.lock_save_irq().raw_entry(cluster)?;
+
+        for fat_num in 0..self.bpb.num_fats as usize {
+            let (start, _) = self.bpb.fat_region(fat_num).ok_or(FsError::InvalidFs)?;
+            let offset = self.bpb.sector_offset(start) + cluster.value() as u64 * 4;
+
+            let old: u32 = self.dev.read_obj(offset).await?;
+            let new = (old & 0xF000_0000) | value;
+
+            self.dev.write_at(offset, &new.to_le_bytes()).await?;
+        }
+
+        Ok(())
     }
 }
 
-trait Fat32Operations: Send + Sync + 'static {
+/// Files with live inodes, keyed by the location of their 8.3 entry.
+type OpenFiles<T> =
+    SpinLockIrq<BTreeMap<DirEntryLoc, Weak<FileState<T>>>, <T as Fat32Operations>::Cpu>;
+
+trait Fat32Operations: Send + Sync + Sized + 'static {
+    type Cpu: CpuOps;
+
     fn read_sector(
         &self,
         sector: Sector,
@@ -112,6 +262,7 @@ trait Fat32Operations: Send + Sync + 'static {
     ) -> impl Future<Output = Result<usize>> + Send;
 
     fn id(&self) -> u64;
+    fn root_cluster(&self) -> Cluster;
     fn sector_size(&self) -> usize;
     fn sectors_per_cluster(&self) -> usize;
 
@@ -121,9 +272,41 @@ trait Fat32Operations: Send + Sync + 'static {
 
     fn cluster_to_sectors(&self, cluster: Cluster) -> Result<impl Iterator<Item = Sector> + Send>;
     fn iter_clusters(&self, root: Cluster) -> impl Iterator<Item = Result<Cluster>> + Send;
+
+    fn write_sector(
+        &self,
+        sector: Sector,
+        offset: usize,
+        buf: &[u8],
+    ) -> impl Future<Output = Result<usize>> + Send;
+
+    /// Allocates a free cluster, linking it onto the end of the chain whose
+    /// last cluster is `prev`, if given.
+    fn alloc_cluster(&self, prev: Option<Cluster>) -> impl Future<Output = Result<Cluster>> + Send;
+
+    /// Frees every cluster in the chain starting at `root`.
+    fn free_chain(&self, root: Cluster) -> impl Future<Output = Result<()>> + Send;
+
+    /// Makes `last` the end of its chain, freeing the clusters after it.
+    fn truncate_chain(&self, last: Cluster) -> impl Future<Output = Result<()>> + Send;
+
+    /// Frees the chain of a file that was unlinked while still open. This
+    /// runs when the last reference to the file goes away, so it can't wait
+    /// on I/O.
+    fn free_orphan(&self, root: Cluster);
+
+    /// Waits until every write issued so far has reached the disk.
+    fn sync_dev(&self) -> impl Future<Output = Result<()>> + Send;
+
+    fn open_files(&self) -> &OpenFiles<Self>;
+
+    /// The journal for directory entry updates, if the volume has one.
+    fn journal(&self) -> Option<&Journal<Self::Cpu>>;
 }
 
-impl Fat32Operations for Fat32Filesystem {
+impl<CPU: CpuOps> Fat32Operations for Fat32Filesystem<CPU> {
+    type Cpu = CPU;
+
     async fn read_sector(&self, sector: Sector, offset: usize, buf: &mut [u8]) -> Result<usize> {
         debug_assert!(offset < self.bpb.sector_size());
 
@@ -145,6 +328,10 @@ impl Fat32Operations for Fat32Filesystem {
         self.id
     }
 
+    fn root_cluster(&self) -> Cluster {
+        self.bpb.root_cluster
+    }
+
     fn sector_size(&self) -> usize {
         self.bpb.sector_size()
     }
@@ -158,12 +345,90 @@ impl Fat32Operations for Fat32Filesystem {
     }
 
     fn iter_clusters(&self, root: Cluster) -> impl Iterator<Item = Result<Cluster>> {
-        self.fat.get_cluster_chain(root)
+        let mut chain = ClusterChain::new(&self.fat.lock_save_irq(), root);
+
+        core::iter::from_fn(move || chain.next(&self.fat.lock_save_irq()))
+    }
+
+    async fn write_sector(&self, sector: Sector, offset: usize, buf: &[u8]) -> Result<usize> {
+        debug_assert!(offset < self.bpb.sector_size());
+
+        let write_sz = min(buf.len(), self.bpb.sector_size() - offset);
+
+        self.dev
+            .write_at(
+                self.bpb.sector_offset(sector) + offset as u64,
+                &buf[..write_sz],
+            )
+            .await?;
+
+        Ok(write_sz)
+    }
+
+    async fn alloc_cluster(&self, prev: Option<Cluster>) -> Result<Cluster> {
+        self.flush_fat().await?;
+
+        let new = self.fat.lock_save_irq().alloc_cluster(prev)?;
+
+        self.write_fat_entry(new).await?;
+
+        if let Some(prev) = prev {
+            self.write_fat_entry(prev).await?;
+        }
+
+        self.commit_fat().await?;
+
+        Ok(new)
+    }
+
+    async fn free_chain(&self, root: Cluster) -> Result<()> {
+        self.flush_fat().await?;
+
+        let freed = self.fat.lock_save_irq().free_chain(root)?;
+
+        for cluster in freed {
+            self.write_fat_entry(cluster).await?;
+        }
+
+        self.commit_fat().await
+    }
+
+    async fn truncate_chain(&self, last: Cluster) -> Result<()> {
+        self.flush_fat().await?;
+
+        let changed = self.fat.lock_save_irq().truncate_chain(last)?;
+
+        for cluster in changed {
+            self.write_fat_entry(cluster).await?;
+        }
+
+        self.commit_fat().await
+    }
+
+    fn free_orphan(&self, root: Cluster) {
+        let freed = self.fat.lock_save_irq().free_chain(root);
+
+        match freed {
+            Ok(freed) => self.dirty_fat.lock_save_irq().extend(freed),
+            Err(e) => warn!("Failed to free clusters of unlinked file: {e}"),
+        }
+    }
+
+    async fn sync_dev(&self) -> Result<()> {
+        self.dev.sync().await
+    }
+
+    fn open_files(&self) -> &OpenFiles<Self> {
+        &self.open_files
+    }
+
+    fn journal(&self) -> Option<&Journal<CPU>> {
+        self.journal.get()
     }
 }
 
 #[async_trait]
-impl Filesystem for Fat32Filesystem {
+impl<CPU: CpuOps> Filesystem for Fat32Filesystem<CPU> {
     fn id(&self) -> u64 {
         self.id
     }
@@ -180,8 +445,1049 @@ impl Filesystem for Fat32Filesystem {
             FileAttr {
                 id: InodeId::from_fsid_and_inodeid(self.id, self.bpb.root_cluster.0 as _),
                 file_type: FileType::Directory,
+                mode: Fat32Attributes::DIRECTORY.mode(),
                 ..FileAttr::default()
             },
         )))
     }
+
+    async fn stats(&self) -> Result<FsStats> {
+        let fat = self.fat.lock_save_irq();
+
+        Ok(FsStats {
+            block_size: self.bytes_per_cluster() as _,
+            total_blocks: fat.cluster_count() as _,
+            free_blocks: fat.free_clusters() as _,
+            max_name_len: 255,
+        })
+    }
+
+    async fn sync(&self) -> Result<()> {
+        self.flush_fat().await?;
+
+        let (free, next_free) = {
+            let fat = self.fat.lock_save_irq();
+            (fat.free_clusters() as u32, fat.next_free_hint().0)
+        };
+
+        FsInfo::write_counts(&self.dev, &self.bpb, free, next_free).await?;
+
+        self.dev.sync().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        error::KernelError,
+        fs::{
+            attr::{AccessMode, FilePermissions},
+            blk::{
+                cache::{CacheConfig, CachedBlockDevice},
+                test::{MemBacking, MemBlkDevice},
+            },
+        },
+        proc::{
+            caps::Capabilities,
+            ids::{Gid, Uid},
+        },
+        test::MockCpuOps,
+    };
+    use alloc::{string::String, vec::Vec};
+    use core::time::Duration;
+    use journal::{JOURNAL_NAME, Transaction};
+    use std::sync::Mutex;
+    use tokio::sync::Notify;
+
+    const SECTOR_SIZE: usize = 512;
+    const RESERVED_SECTORS: usize = 32;
+    const FAT_SECTORS: usize = 1;
+    const NUM_FATS: usize = 2;
+    const DATA_START: usize = RESERVED_SECTORS + FAT_SECTORS * NUM_FATS;
+    const TOTAL_SECTORS: usize = DATA_START + 8;
+    const EOC: u32 = 0x0FFFFFFF;
+
+    fn put_u16(img: &mut [u8], off: usize, val: u16) {
+        img[off..off + 2].copy_from_slice(&val.to_le_bytes());
+    }
+
+    fn put_u32(img: &mut [u8], off: usize, val: u32) {
+        img[off..off + 4].copy_from_slice(&val.to_le_bytes());
+    }
+
+    fn cluster_offset(cluster: u32) -> usize {
+        (DATA_START + cluster as usize - 2) * SECTOR_SIZE
+    }
+
+    fn put_dirent(img: &mut [u8], idx: usize, name: &[u8; 11], attr: u8, cluster: u32, size: u32) {
+        let off = cluster_offset(2) + idx * 32;
+        img[off..off + 11].copy_from_slice(name);
+        img[off + 11] = attr;
+        put_u16(img, off + 20, (cluster >> 16) as u16);
+        put_u16(img, off + 26, cluster as u16);
+        put_u32(img, off + 28, size);
+    }
+
+    /// Builds a tiny FAT32 image with one sector per cluster. The root
+    /// directory lives in cluster 2 and holds `hello.txt` (cluster 3) and
+    /// `big.bin`, whose chain (4 -> 6) skips over a free cluster. Clusters 5,
+    /// 7, 8 and 9 are free, and FSInfo points allocation at cluster 7.
+    fn build_image() -> Vec<u8> {
+        let mut img = vec![0u8; TOTAL_SECTORS * SECTOR_SIZE];
+
+        // Boot sector.
+        img[0..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+        img[3..11].copy_from_slice(b"MOSSTEST");
+        put_u16(&mut img, 11, SECTOR_SIZE as u16);
+        img[13] = 1;
+        put_u16(&mut img, 14, RESERVED_SECTORS as u16);
+        img[16] = NUM_FATS as u8;
+        img[21] = 0xF8;
+        put_u32(&mut img, 32, TOTAL_SECTORS as u32);
+        put_u32(&mut img, 36, FAT_SECTORS as u32);
+        put_u32(&mut img, 44, 2);
+        put_u16(&mut img, 48, 1);
+        img[510] = 0x55;
+        img[511] = 0xAA;
+
+        // FSInfo, with an unknown free count.
+        let info = SECTOR_SIZE;
+        put_u32(&mut img, info, 0x41615252);
+        put_u32(&mut img, info + 484, 0x61417272);
+        put_u32(&mut img, info + 488, 0xFFFFFFFF);
+        put_u32(&mut img, info + 492, 7);
+        put_u32(&mut img, info + 508, 0xAA550000);
+
+        // FATs.
+        let fat = [0x0FFFFFF8, EOC, EOC, EOC, 6, 0, EOC];
+        for fat_num in 0..NUM_FATS {
+            let base = (RESERVED_SECTORS + fat_num * FAT_SECTORS) * SECTOR_SIZE;
+            for (i, entry) in fat.iter().enumerate() {
+                put_u32(&mut img, base + i * 4, *entry);
+            }
+        }
+
+        // Root directory.
+        put_dirent(&mut img, 0, b"HELLO   TXT", 0x20, 3, 13);
+        put_dirent(&mut img, 1, b"BIG     BIN", 0x20, 4, 600);
+
+        // File data.
+        let hello = cluster_offset(3);
+        img[hello..hello + 13].copy_from_slice(b"Hello, FAT32!");
+
+        let first = cluster_offset(4);
+        img[first..first + SECTOR_SIZE].fill(b'a');
+        let second = cluster_offset(6);
+        img[second..second + 88].fill(b'b');
+
+        img
+    }
+
+    async fn mount_shared(img: Arc<Mutex<MemBacking>>) -> Result<Arc<Fat32Filesystem<MockCpuOps>>> {
+        Fat32Filesystem::new(BlockBuffer::new(Box::new(MemBlkDevice::new(img, 1))), 1).await
+    }
+
+    async fn mount(data: Vec<u8>) -> Result<Arc<Fat32Filesystem<MockCpuOps>>> {
+        mount_shared(MemBacking::new(data)).await
+    }
+
+    #[tokio::test]
+    async fn mount_and_read_root_dir() {
+        let fs = mount(build_image()).await.expect("mount should succeed");
+        let root = fs.root_inode().await.unwrap();
+
+        let mut names = Vec::new();
+        let mut stream = root.readdir(0).await.unwrap();
+        while let Some(dirent) = stream.next_entry().await.unwrap() {
+            names.push(dirent.name);
+        }
+        assert_eq!(names, [String::from("hello.txt"), String::from("big.bin")]);
+
+        let hello = root.lookup("hello.txt").await.unwrap();
+        let mut buf = [0; 32];
+        let n = hello.read_at(0, &mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"Hello, FAT32!");
+    }
+
+    #[tokio::test]
+    async fn read_follows_fat_chain() {
+        let fs = mount(build_image()).await.unwrap();
+        let big = fs
+            .root_inode()
+            .await
+            .unwrap()
+            .lookup("big.bin")
+            .await
+            .unwrap();
+
+        let mut buf = vec![0; 600];
+        let mut read = 0;
+        while read < buf.len() {
+            let n = big.read_at(read as u64, &mut buf[read..]).await.unwrap();
+            assert_ne!(n, 0);
+            read += n;
+        }
+
+        assert!(buf[..SECTOR_SIZE].iter().all(|&b| b == b'a'));
+        assert!(buf[SECTOR_SIZE..].iter().all(|&b| b == b'b'));
+    }
+
+    #[tokio::test]
+    async fn mount_rejects_bad_signature() {
+        let mut img = build_image();
+        img[511] = 0;
+
+        assert!(matches!(
+            mount(img).await,
+            Err(KernelError::Fs(FsError::InvalidFs))
+        ));
+    }
+
+    #[tokio::test]
+    async fn mount_rejects_mismatched_fats() {
+        let mut img = build_image();
+        put_u32(
+            &mut img,
+            (RESERVED_SECTORS + FAT_SECTORS) * SECTOR_SIZE + 3 * 4,
+            0,
+        );
+
+        assert!(matches!(
+            mount(img).await,
+            Err(KernelError::Fs(FsError::InvalidFs))
+        ));
+    }
+
+    #[tokio::test]
+    async fn free_count_tracks_allocations() {
+        let img = MemBacking::new(build_image());
+        let fs = mount_shared(img.clone()).await.unwrap();
+
+        let stats = fs.stats().await.unwrap();
+        assert_eq!(stats.block_size, SECTOR_SIZE as u64);
+        assert_eq!(stats.total_blocks, 8);
+        assert_eq!(stats.free_blocks, 4);
+
+        // Grow a chain by three clusters, starting from the FSInfo hint.
+        let first = fs.alloc_cluster(None).await.unwrap();
+        let second = fs.alloc_cluster(Some(first)).await.unwrap();
+        let third = fs.alloc_cluster(Some(second)).await.unwrap();
+
+        assert_eq!(first, Cluster(7));
+        assert_eq!(fs.free_clusters(), 1);
+        assert_eq!(fs.stats().await.unwrap().free_blocks, 1);
+
+        let chain: Vec<_> = fs.iter_clusters(first).collect::<Result<_>>().unwrap();
+        assert_eq!(chain, [first, second, third]);
+
+        // The allocations must have reached every FAT on disk.
+        fs.sync().await.unwrap();
+        let info_free = u32::from_le_bytes(
+            img.lock().unwrap().data[SECTOR_SIZE + 488..][..4]
+                .try_into()
+                .unwrap(),
+        );
+        assert_eq!(info_free, 1);
+
+        let remounted = mount_shared(img.clone()).await.unwrap();
+        assert_eq!(remounted.free_clusters(), 1);
+
+        fs.free_chain(first).await.unwrap();
+        assert_eq!(fs.free_clusters(), 4);
+        assert_eq!(mount_shared(img).await.unwrap().free_clusters(), 4);
+    }
+
+    #[tokio::test]
+    async fn alloc_fails_when_full() {
+        let fs = mount(build_image()).await.unwrap();
+
+        for _ in 0..4 {
+            fs.alloc_cluster(None).await.unwrap();
+        }
+
+        assert_eq!(fs.free_clusters(), 0);
+        assert!(matches!(
+            fs.alloc_cluster(None).await,
+            Err(KernelError::Fs(FsError::NoSpace))
+        ));
+    }
+
+    async fn read_all(inode: &Arc<dyn Inode>) -> Vec<u8> {
+        let mut buf = vec![0; inode.getattr().await.unwrap().size as usize];
+        let mut read = 0;
+        while read < buf.len() {
+            read += inode.read_at(read as u64, &mut buf[read..]).await.unwrap();
+        }
+        buf
+    }
+
+    async fn list(dir: &Arc<dyn Inode>) -> Vec<String> {
+        let mut names = Vec::new();
+        let mut stream = dir.readdir(0).await.unwrap();
+        while let Some(dirent) = stream.next_entry().await.unwrap() {
+            names.push(dirent.name);
+        }
+        names
+    }
+
+    #[tokio::test]
+    async fn read_only_attribute_denies_write_access() {
+        let mut img = build_image();
+        put_dirent(&mut img, 2, b"RO      TXT", 0x21, 0, 0);
+
+        let fs = mount(img).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+
+        // Check as the file's owner, who the permission bits would otherwise
+        // let write.
+        let owner_access = |attr: &FileAttr, mode| {
+            attr.check_access(attr.uid, attr.gid, Capabilities::new_empty(), mode)
+        };
+
+        let attr = root
+            .lookup("ro.txt")
+            .await
+            .unwrap()
+            .getattr()
+            .await
+            .unwrap();
+        assert_eq!(attr.mode.bits(), 0o444);
+        assert!(owner_access(&attr, AccessMode::R_OK).is_ok());
+        assert!(matches!(
+            owner_access(&attr, AccessMode::W_OK),
+            Err(KernelError::NotPermitted)
+        ));
+
+        // Without the attribute, the owner may write.
+        let attr = root
+            .lookup("hello.txt")
+            .await
+            .unwrap()
+            .getattr()
+            .await
+            .unwrap();
+        assert_eq!(attr.mode.bits(), 0o644);
+        assert!(owner_access(&attr, AccessMode::W_OK).is_ok());
+    }
+
+    #[tokio::test]
+    async fn chmod_toggles_read_only_attribute() {
+        let img = MemBacking::new(build_image());
+        let fs = mount_shared(img.clone()).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+        let attr_byte = cluster_offset(2) + 11;
+
+        let hello = root.lookup("hello.txt").await.unwrap();
+        let mut attr = hello.getattr().await.unwrap();
+
+        // Clearing every write bit sets READ_ONLY, keeping ARCHIVE.
+        attr.mode = FilePermissions::from_bits_retain(0o555);
+        hello.setattr(attr.clone()).await.unwrap();
+        assert_eq!(img.lock().unwrap().data[attr_byte], 0x21);
+
+        // The mode is what FAT can record, seen by every inode for the file.
+        let again = root.lookup("hello.txt").await.unwrap();
+        assert_eq!(again.getattr().await.unwrap().mode.bits(), 0o444);
+
+        // Any write bit clears it again.
+        attr.mode = FilePermissions::from_bits_retain(0o200);
+        hello.setattr(attr.clone()).await.unwrap();
+        assert_eq!(img.lock().unwrap().data[attr_byte], 0x20);
+        assert_eq!(again.getattr().await.unwrap().mode.bits(), 0o644);
+
+        // FAT has no owners to change.
+        attr.uid = Uid::new(1000);
+        assert!(matches!(
+            hello.setattr(attr).await,
+            Err(KernelError::NotPermitted)
+        ));
+
+        let fs = mount_shared(img).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+        assert_eq!(root.getattr().await.unwrap().mode.bits(), 0o755);
+        let hello = root.lookup("hello.txt").await.unwrap();
+        assert_eq!(hello.getattr().await.unwrap().mode.bits(), 0o644);
+    }
+
+    #[tokio::test]
+    async fn utimes_round_to_fat_resolution() {
+        let img = MemBacking::new(build_image());
+        let fs = mount_shared(img.clone()).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+        let entry = cluster_offset(2);
+
+        // 2024-02-29 13:37:43 and 2023-07-04 08:15:30.
+        let hello = root.lookup("hello.txt").await.unwrap();
+        let mut attr = hello.getattr().await.unwrap();
+        attr.atime = Duration::from_secs(1_688_458_530);
+        attr.mtime = Duration::from_secs(1_709_213_863);
+        hello.setattr(attr).await.unwrap();
+
+        let le16 = |off| {
+            let img = img.lock().unwrap();
+            u16::from_le_bytes([img.data[entry + off], img.data[entry + off + 1]])
+        };
+        assert_eq!(le16(18), (43 << 9) | (7 << 5) | 4);
+        assert_eq!(le16(22), (13 << 11) | (37 << 5) | 21);
+        assert_eq!(le16(24), (44 << 9) | (2 << 5) | 29);
+
+        // The modification time keeps 2 seconds, the access time only the
+        // date.
+        let check = |attr: FileAttr| {
+            assert_eq!(attr.mtime, Duration::from_secs(1_709_213_862));
+            assert_eq!(attr.atime, Duration::from_secs(1_688_428_800));
+        };
+        check(hello.getattr().await.unwrap());
+
+        let fs = mount_shared(img).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+        let hello = root.lookup("hello.txt").await.unwrap();
+        check(hello.getattr().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn create_then_reopen() {
+        let img = MemBacking::new(build_image());
+        let fs = mount_shared(img.clone()).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+
+        let file = root
+            .create("notes.txt", FileType::File, FilePermissions::empty())
+            .await
+            .unwrap();
+        assert_eq!(file.getattr().await.unwrap().size, 0);
+        assert_eq!(fs.free_clusters(), 4);
+
+        assert_eq!(file.write_at(0, b"hello world").await.unwrap(), 11);
+        assert_eq!(fs.free_clusters(), 3);
+
+        assert!(matches!(
+            root.create("NOTES.TXT", FileType::File, FilePermissions::empty())
+                .await,
+            Err(KernelError::Fs(FsError::AlreadyExists))
+        ));
+
+        // A fresh mount must see the entry, its size and its data.
+        let fs = mount_shared(img).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+        assert_eq!(list(&root).await, ["hello.txt", "big.bin", "notes.txt"]);
+
+        let file = root.lookup("notes.txt").await.unwrap();
+        assert_eq!(read_all(&file).await, b"hello world");
+    }
+
+    #[tokio::test]
+    async fn concurrent_appends_share_one_chain() {
+        let img = MemBacking::new(build_image());
+        let fs = mount_shared(img.clone()).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+        let bpc = fs.bytes_per_cluster();
+
+        let file = root
+            .create("log", FileType::File, FilePermissions::empty())
+            .await
+            .unwrap();
+        file.write_at(0, &vec![b'x'; bpc]).await.unwrap();
+
+        // Hold every device write, so the two appends interleave.
+        let stall = Arc::new(Notify::new());
+        img.lock().unwrap().stall = Some(stall.clone());
+
+        let appends = [(1, b'a'), (2, b'b')].map(|(n, byte)| {
+            let file = file.clone();
+            tokio::spawn(async move { file.write_at((n * bpc) as u64, &vec![byte; bpc]).await })
+        });
+
+        while !appends.iter().all(|append| append.is_finished()) {
+            stall.notify_one();
+            tokio::task::yield_now().await;
+        }
+
+        img.lock().unwrap().stall = None;
+
+        for append in appends {
+            assert_eq!(append.await.unwrap().unwrap(), bpc);
+        }
+
+        // Both appends extended the same chain, and nothing leaked.
+        assert_eq!(fs.free_clusters(), 1);
+        assert_eq!(
+            read_all(&file).await,
+            [vec![b'x'; bpc], vec![b'a'; bpc], vec![b'b'; bpc]].concat()
+        );
+    }
+
+    #[tokio::test]
+    async fn empty_files_have_distinct_ids() {
+        let fs = mount(build_image()).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+
+        let a = root
+            .create("a", FileType::File, FilePermissions::empty())
+            .await
+            .unwrap();
+        let b = root
+            .create("b", FileType::File, FilePermissions::empty())
+            .await
+            .unwrap();
+
+        assert_ne!(a.id(), b.id());
+        assert_eq!(a.getattr().await.unwrap().id, a.id());
+        assert_eq!(root.lookup("a").await.unwrap().id(), a.id());
+        assert_eq!(root.lookup("b").await.unwrap().id(), b.id());
+    }
+
+    #[tokio::test]
+    async fn create_long_names() {
+        let img = MemBacking::new(build_image());
+        let fs = mount_shared(img.clone()).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+
+        // Both names share the same 8.3 basis, so need distinct numeric tails.
+        for name in ["Quarterly Report.markdown", "Quarterly Review.markdown"] {
+            root.create(name, FileType::File, FilePermissions::empty())
+                .await
+                .unwrap();
+        }
+
+        let fs = mount_shared(img).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+        assert_eq!(
+            list(&root).await,
+            [
+                "hello.txt",
+                "big.bin",
+                "Quarterly Report.markdown",
+                "Quarterly Review.markdown"
+            ]
+        );
+        assert!(root.lookup("quarterly review.markdown").await.is_ok());
+
+        assert!(matches!(
+            root.create("bad:name", FileType::File, FilePermissions::empty())
+                .await,
+            Err(KernelError::Fs(FsError::InvalidInput))
+        ));
+    }
+
+    #[tokio::test]
+    async fn create_grows_directory() {
+        let fs = mount(build_image()).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+
+        // The root directory's single cluster holds 16 entries.
+        let names: Vec<_> = (0..20).map(|i| alloc::format!("f{i}")).collect();
+        for name in names.iter() {
+            root.create(name, FileType::File, FilePermissions::empty())
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(fs.free_clusters(), 3);
+        assert_eq!(list(&root).await[2..], names[..]);
+    }
+
+    #[tokio::test]
+    async fn create_skips_short_gap_in_full_directory() {
+        let mut img = build_image();
+
+        // Fill the root directory's cluster with no end marker, leaving a
+        // single deleted slot between live entries.
+        for idx in 2..16 {
+            let mut name = *b"FILE    TXT";
+            name[4] = b'A' + idx as u8;
+            put_dirent(&mut img, idx, &name, 0x20, 0, 0);
+        }
+        img[cluster_offset(2) + 2 * 32] = 0xE5;
+
+        let fs = mount(img).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+        let mut expected = list(&root).await;
+        assert_eq!(expected.len(), 15);
+
+        // The name needs more slots than the gap holds, so the directory grows
+        // rather than overwriting the entries after the gap.
+        let name = "A rather long file name.txt";
+        root.create(name, FileType::File, FilePermissions::empty())
+            .await
+            .unwrap();
+
+        expected.push(name.into());
+        assert_eq!(fs.free_clusters(), 3);
+        assert_eq!(list(&root).await, expected);
+    }
+
+    #[tokio::test]
+    async fn write_and_truncate() {
+        let fs = mount(build_image()).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+        let file = root
+            .create("data", FileType::File, FilePermissions::empty())
+            .await
+            .unwrap();
+
+        // Writing past the end leaves a zeroed hole.
+        file.write_at(600, b"tail").await.unwrap();
+        assert_eq!(fs.free_clusters(), 2);
+
+        let data = read_all(&file).await;
+        assert_eq!(data.len(), 604);
+        assert!(data[..600].iter().all(|&b| b == 0));
+        assert_eq!(&data[600..], b"tail");
+
+        file.truncate(10).await.unwrap();
+        assert_eq!(fs.free_clusters(), 3);
+        assert_eq!(file.getattr().await.unwrap().size, 10);
+
+        file.truncate(0).await.unwrap();
+        assert_eq!(fs.free_clusters(), 4);
+
+        let file = root.lookup("data").await.unwrap();
+        assert_eq!(file.getattr().await.unwrap().size, 0);
+    }
+
+    /// Adds a `sub` directory in cluster 5 holding an empty `child.txt`.
+    fn add_subdir(img: &mut [u8]) {
+        for fat_num in 0..NUM_FATS {
+            let base = (RESERVED_SECTORS + fat_num * FAT_SECTORS) * SECTOR_SIZE;
+            put_u32(img, base + 5 * 4, EOC);
+        }
+
+        put_dirent(img, 2, b"SUB        ", 0x10, 5, 0);
+
+        let dir = cluster_offset(5);
+        img[dir..dir + 11].copy_from_slice(b".          ");
+        img[dir + 11] = 0x10;
+        put_u16(img, dir + 26, 5);
+        img[dir + 32..dir + 43].copy_from_slice(b"..         ");
+        img[dir + 43] = 0x10;
+        img[dir + 64..dir + 75].copy_from_slice(b"CHILD   TXT");
+        img[dir + 75] = 0x20;
+    }
+
+    #[tokio::test]
+    async fn unlink_frees_clusters() {
+        let img = MemBacking::new(build_image());
+        let fs = mount_shared(img.clone()).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+
+        root.unlink("big.bin").await.unwrap();
+        assert_eq!(fs.free_clusters(), 6);
+        assert!(matches!(
+            root.lookup("big.bin").await,
+            Err(KernelError::Fs(FsError::NotFound))
+        ));
+
+        let fs = mount_shared(img).await.unwrap();
+        assert_eq!(fs.free_clusters(), 6);
+        assert_eq!(list(&fs.root_inode().await.unwrap()).await, ["hello.txt"]);
+    }
+
+    #[tokio::test]
+    async fn unlink_long_name_reuses_slots() {
+        let fs = mount(build_image()).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+
+        let name = "A rather long file name.txt";
+        root.create(name, FileType::File, FilePermissions::empty())
+            .await
+            .unwrap();
+        root.unlink(name).await.unwrap();
+        assert_eq!(list(&root).await, ["hello.txt", "big.bin"]);
+
+        // The freed LFN and 8.3 slots are picked up again.
+        root.create(name, FileType::File, FilePermissions::empty())
+            .await
+            .unwrap();
+        assert_eq!(list(&root).await, ["hello.txt", "big.bin", name]);
+    }
+
+    #[tokio::test]
+    async fn unlink_open_file_frees_on_last_close() {
+        let img = MemBacking::new(build_image());
+        let fs = mount_shared(img.clone()).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+
+        let open = root.lookup("hello.txt").await.unwrap();
+        root.unlink("hello.txt").await.unwrap();
+
+        // The data stays readable through the open inode.
+        assert_eq!(fs.free_clusters(), 4);
+        assert_eq!(open.getattr().await.unwrap().nlinks, 0);
+        assert_eq!(read_all(&open).await, b"Hello, FAT32!");
+
+        // A new file can take over the old entry without being confused
+        // with the orphan.
+        let new = root
+            .create("other", FileType::File, FilePermissions::empty())
+            .await
+            .unwrap();
+        assert_eq!(new.getattr().await.unwrap().size, 0);
+
+        drop(open);
+        assert_eq!(fs.free_clusters(), 5);
+
+        fs.sync().await.unwrap();
+        assert_eq!(mount_shared(img).await.unwrap().free_clusters(), 5);
+    }
+
+    #[tokio::test]
+    async fn rmdir_requires_empty_dir() {
+        let mut img = build_image();
+        add_subdir(&mut img);
+        let fs = mount(img).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+        assert_eq!(fs.free_clusters(), 3);
+
+        assert!(matches!(
+            root.unlink("sub").await,
+            Err(KernelError::Fs(FsError::DirectoryNotEmpty))
+        ));
+
+        let sub = root.lookup("sub").await.unwrap();
+        sub.unlink("child.txt").await.unwrap();
+        root.unlink("sub").await.unwrap();
+
+        assert_eq!(fs.free_clusters(), 4);
+        assert_eq!(list(&root).await, ["hello.txt", "big.bin"]);
+    }
+
+    #[tokio::test]
+    async fn mkdir_writes_dot_entries() {
+        let img = MemBacking::new(build_image());
+        let fs = mount_shared(img.clone()).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+
+        let docs = root
+            .create("docs", FileType::Directory, FilePermissions::empty())
+            .await
+            .unwrap();
+        let inner = docs
+            .create("inner", FileType::Directory, FilePermissions::empty())
+            .await
+            .unwrap();
+        assert_eq!(fs.free_clusters(), 2);
+
+        assert!(matches!(
+            root.create("docs", FileType::Directory, FilePermissions::empty())
+                .await,
+            Err(KernelError::Fs(FsError::AlreadyExists))
+        ));
+
+        let fs = mount_shared(img).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+        let docs = root.lookup("docs").await.unwrap();
+        assert_eq!(docs.getattr().await.unwrap().file_type, FileType::Directory);
+        assert_eq!(list(&docs).await, [".", "..", "inner"]);
+
+        // Each ".." names the parent; the root is referred to as cluster 0.
+        assert_eq!(docs.lookup(".").await.unwrap().id(), docs.id());
+        assert_eq!(docs.lookup("..").await.unwrap().id(), root.id());
+
+        let inner_again = docs.lookup("inner").await.unwrap();
+        assert_eq!(inner_again.id(), inner.id());
+        assert_eq!(list(&inner_again).await, [".", ".."]);
+        assert_eq!(inner_again.lookup("..").await.unwrap().id(), docs.id());
+    }
+
+    #[tokio::test]
+    async fn rename_across_directories() {
+        let img = MemBacking::new(build_image());
+        let fs = mount_shared(img.clone()).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+
+        let a = root
+            .create("a", FileType::Directory, FilePermissions::empty())
+            .await
+            .unwrap();
+        let b = root
+            .create("b", FileType::Directory, FilePermissions::empty())
+            .await
+            .unwrap();
+        assert_eq!(fs.free_clusters(), 2);
+
+        let open = root.lookup("hello.txt").await.unwrap();
+        let id = open.id();
+
+        a.rename_from(root.clone(), "hello.txt", "Greeting File.txt", false)
+            .await
+            .unwrap();
+        b.rename_from(a.clone(), "greeting file.txt", "moved.txt", false)
+            .await
+            .unwrap();
+
+        // No data clusters are touched by a rename.
+        assert_eq!(fs.free_clusters(), 2);
+
+        // Writes through an inode opened before the rename land in the
+        // renamed entry.
+        open.write_at(13, b"!!").await.unwrap();
+
+        let fs = mount_shared(img).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+        assert!(matches!(
+            root.lookup("hello.txt").await,
+            Err(KernelError::Fs(FsError::NotFound))
+        ));
+        assert_eq!(list(&root.lookup("a").await.unwrap()).await, [".", ".."]);
+
+        let moved = root
+            .lookup("b")
+            .await
+            .unwrap()
+            .lookup("moved.txt")
+            .await
+            .unwrap();
+        assert_eq!(moved.id(), id);
+        assert_eq!(read_all(&moved).await, b"Hello, FAT32!!!");
+    }
+
+    #[tokio::test]
+    async fn rename_replaces_unless_noreplace() {
+        let fs = mount(build_image()).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+
+        assert!(matches!(
+            root.rename_from(root.clone(), "hello.txt", "big.bin", true)
+                .await,
+            Err(KernelError::Fs(FsError::AlreadyExists))
+        ));
+
+        root.rename_from(root.clone(), "hello.txt", "big.bin", false)
+            .await
+            .unwrap();
+
+        // The replaced file's clusters are freed.
+        assert_eq!(fs.free_clusters(), 6);
+        assert_eq!(list(&root).await, ["big.bin"]);
+        assert_eq!(
+            read_all(&root.lookup("big.bin").await.unwrap()).await,
+            b"Hello, FAT32!"
+        );
+    }
+
+    #[tokio::test]
+    async fn rename_dir_updates_dotdot() {
+        let mut img = build_image();
+        add_subdir(&mut img);
+        let img = MemBacking::new(img);
+        let fs = mount_shared(img.clone()).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+
+        let dest = root
+            .create("dest", FileType::Directory, FilePermissions::empty())
+            .await
+            .unwrap();
+
+        dest.rename_from(root.clone(), "sub", "sub", false)
+            .await
+            .unwrap();
+
+        // A directory can't be moved into its own subtree.
+        let sub = dest.lookup("sub").await.unwrap();
+        assert!(matches!(
+            sub.rename_from(root.clone(), "dest", "dest", false).await,
+            Err(KernelError::InvalidValue)
+        ));
+
+        let fs = mount_shared(img).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+        let dest = root.lookup("dest").await.unwrap();
+        let sub = dest.lookup("sub").await.unwrap();
+
+        assert_eq!(sub.lookup("..").await.unwrap().id(), dest.id());
+        assert_eq!(list(&sub).await, [".", "..", "child.txt"]);
+    }
+
+    #[tokio::test]
+    async fn rename_into_dotdot_loop_fails() {
+        let mut img = build_image();
+        add_subdir(&mut img);
+
+        // Point `sub`'s ".." back at itself.
+        put_u16(&mut img, cluster_offset(5) + 32 + 26, 5);
+
+        let fs = mount(img).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+        root.create("dest", FileType::Directory, FilePermissions::empty())
+            .await
+            .unwrap();
+        let sub = root.lookup("sub").await.unwrap();
+
+        assert!(matches!(
+            sub.rename_from(root.clone(), "dest", "dest", false).await,
+            Err(KernelError::Fs(FsError::InvalidFs))
+        ));
+    }
+
+    /// Reserves cluster 5 for a journal, with the entry reserving it placed
+    /// straight after `big.bin`.
+    fn add_journal(img: &mut [u8]) {
+        put_dirent(img, 2, JOURNAL_NAME, 0x06, 5, 0);
+
+        for fat_num in 0..NUM_FATS {
+            let base = (RESERVED_SECTORS + fat_num * FAT_SECTORS) * SECTOR_SIZE;
+            put_u32(img, base + 5 * 4, EOC);
+        }
+    }
+
+    /// The updates a rename of `hello.txt` to `greet.txt` makes: the new
+    /// entry goes in the first free slot, then the old one is deleted.
+    fn half_done_rename(img: &mut [u8]) -> Transaction {
+        let root = cluster_offset(2);
+        let old: [u8; 32] = img[root..root + 32].try_into().unwrap();
+
+        let mut renamed = old;
+        renamed[..11].copy_from_slice(b"GREET   TXT");
+        let mut deleted = old;
+        deleted[0] = 0xE5;
+
+        let mut tx = Transaction::new();
+        let slot = |index| DirEntryLoc {
+            dir: Cluster(2),
+            index,
+        };
+        tx.write(slot(3), renamed);
+        tx.write(slot(0), deleted);
+
+        // The crash comes after the record and the new entry reached the
+        // disk, but before the old entry was removed.
+        let record = tx.encode();
+        let journal = cluster_offset(5);
+        img[journal..journal + record.len()].copy_from_slice(&record);
+        img[root + 3 * 32..root + 4 * 32].copy_from_slice(&renamed);
+
+        tx
+    }
+
+    #[tokio::test]
+    async fn journal_replays_half_done_rename() {
+        let mut img = build_image();
+        add_journal(&mut img);
+        half_done_rename(&mut img);
+
+        let img = MemBacking::new(img);
+        let fs = mount_shared(img.clone()).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+
+        // The journal itself stays out of sight.
+        assert_eq!(list(&root).await, ["big.bin", "greet.txt"]);
+        assert_eq!(
+            read_all(&root.lookup("greet.txt").await.unwrap()).await,
+            b"Hello, FAT32!"
+        );
+        assert!(matches!(
+            root.lookup("mossjrnl").await,
+            Err(KernelError::Fs(FsError::NotFound))
+        ));
+
+        // The record has been cleared, so a second mount replays nothing.
+        let journal = cluster_offset(5);
+        assert_eq!(img.lock().unwrap().data[journal..journal + 4], [0; 4]);
+        let fs = mount_shared(img).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+        assert_eq!(list(&root).await, ["big.bin", "greet.txt"]);
+    }
+
+    #[tokio::test]
+    async fn journal_discards_torn_record() {
+        let mut img = build_image();
+        add_journal(&mut img);
+        half_done_rename(&mut img);
+
+        // The last slot of the record never made it to disk, and neither did
+        // the in-place update.
+        let root = cluster_offset(2);
+        img[root + 3 * 32..root + 4 * 32].fill(0);
+        let journal = cluster_offset(5);
+        img[journal + 12 + 40..journal + 12 + 80].fill(0);
+
+        let img = MemBacking::new(img);
+        let fs = mount_shared(img.clone()).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+
+        assert_eq!(list(&root).await, ["hello.txt", "big.bin"]);
+        assert_eq!(img.lock().unwrap().data[journal..journal + 4], [0; 4]);
+    }
+
+    #[tokio::test]
+    async fn rename_goes_through_journal() {
+        let mut img = build_image();
+        add_journal(&mut img);
+
+        let img = MemBacking::new(img);
+        let fs = mount_shared(img.clone()).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+        assert_eq!(fs.free_clusters(), 3);
+
+        root.rename_from(root.clone(), "hello.txt", "Greeting File.txt", false)
+            .await
+            .unwrap();
+
+        // Two LFN entries and the 8.3 entry were added and one 8.3 entry was
+        // deleted, all recorded before the record was cleared.
+        let journal = cluster_offset(5);
+        {
+            let img = img.lock().unwrap();
+            assert_eq!(img.data[journal..journal + 4], [0; 4]);
+            assert_eq!(img.data[journal + 8..journal + 12], 4u32.to_le_bytes());
+        }
+
+        let fs = mount_shared(img).await.unwrap();
+        let root = fs.root_inode().await.unwrap();
+        assert_eq!(list(&root).await, ["big.bin", "Greeting File.txt"]);
+        assert_eq!(fs.free_clusters(), 3);
+    }
+
+    /// Dirties a data block and then allocates three clusters under `policy`,
+    /// returning the number of blocks that reached the device before and
+    /// after a sync.
+    async fn fat_writes(policy: FatWritePolicy) -> (usize, usize) {
+        let img = MemBacking::new(build_image());
+        let dev = MemBlkDevice::new(img.clone(), SECTOR_SIZE);
+        let cache = CachedBlockDevice::<MockCpuOps>::new(Box::new(dev), CacheConfig::default());
+        let fs = Fat32Filesystem::<MockCpuOps>::new(BlockBuffer::new(Box::new(cache)), 1)
+            .await
+            .unwrap();
+
+        fs.set_fat_write_policy(policy);
+        fs.dev
+            .write_at(cluster_offset(3) as u64, b"x")
+            .await
+            .unwrap();
+
+        for _ in 0..3 {
+            fs.alloc_cluster(None).await.unwrap();
+        }
+
+        let before_sync = img.lock().unwrap().writes;
+        fs.sync().await.unwrap();
+
+        (before_sync, img.lock().unwrap().writes)
+    }
+
+    #[tokio::test]
+    async fn fat_write_policy_controls_flushes() {
+        // Each allocation flushes its sector of both FATs, but not the data
+        // block; the sync adds that and FSInfo.
+        assert_eq!(fat_writes(FatWritePolicy::WriteThrough).await, (6, 8));
+
+        // The FAT sectors stay dirty in the cache until the sync, which
+        // writes each of them once.
+        assert_eq!(fat_writes(FatWritePolicy::WriteBack).await, (0, 4));
+    }
+
+    #[test]
+    fn fat_write_policy_from_mount_options() {
+        assert_eq!(
+            FatWritePolicy::from_mount_options("").unwrap(),
+            FatWritePolicy::WriteBack
+        );
+        assert_eq!(
+            FatWritePolicy::from_mount_options("ro,fat=writethrough").unwrap(),
+            FatWritePolicy::WriteThrough
+        );
+        assert!(FatWritePolicy::from_mount_options("fat=sometimes").is_err());
+    }
 }
@@ -0,0 +1,79 @@
+use crate::error::{FsError, Result};
+use alloc::vec;
+
+use super::{Cluster, Fat32Operations};
+
+/// Returns the last cluster of the chain starting at `root` along with the
+/// number of clusters in the chain.
+pub fn chain_tail<T: Fat32Operations>(fs: &T, root: Cluster) -> Result<(Cluster, usize)> {
+    let mut last = root;
+    let mut len = 0;
+
+    for cluster in fs.iter_clusters(root) {
+        last = cluster?;
+        len += 1;
+    }
+
+    Ok((last, len))
+}
+
+/// Appends `count` zero-filled clusters to the chain ending at `last`, or
+/// starts a new chain when `last` is `None`. Returns the first cluster that
+/// was added.
+pub async fn grow_chain<T: Fat32Operations>(
+    fs: &T,
+    mut last: Option<Cluster>,
+    count: usize,
+) -> Result<Option<Cluster>> {
+    let mut first = None;
+    let zeroes = vec![0; fs.sector_size()];
+
+    for _ in 0..count {
+        let new = fs.alloc_cluster(last).await?;
+
+        for sector in fs.cluster_to_sectors(new)? {
+            fs.write_sector(sector, 0, &zeroes).await?;
+        }
+
+        first.get_or_insert(new);
+        last = Some(new);
+    }
+
+    Ok(first)
+}
+
+/// Writes `buf` into the chain starting at `root`, `offset` bytes in. The
+/// chain must already be long enough to hold the data.
+pub async fn write_chain<T: Fat32Operations>(
+    fs: &T,
+    root: Cluster,
+    offset: u64,
+    buf: &[u8],
+) -> Result<()> {
+    let bpc = fs.bytes_per_cluster() as u64;
+    let sector_size = fs.sector_size() as u64;
+
+    let mut pos = offset;
+    let mut written = 0;
+    let mut clusters = fs.iter_clusters(root).skip((offset / bpc) as usize);
+
+    while written < buf.len() {
+        let cluster = clusters.next().ok_or(FsError::OutOfBounds)??;
+        let skip = (pos % bpc) / sector_size;
+
+        for sector in fs.cluster_to_sectors(cluster)?.skip(skip as _) {
+            if written == buf.len() {
+                break;
+            }
+
+            let n = fs
+                .write_sector(sector, (pos % sector_size) as _, &buf[written..])
+                .await?;
+
+            written += n;
+            pos += n as u64;
+        }
+    }
+
+    Ok(())
+}
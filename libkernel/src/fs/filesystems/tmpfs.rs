@@ -11,7 +11,7 @@ use crate::{
         PAGE_SIZE,
         address::{AddressTranslator, VA},
         allocators::phys::PageAllocGetter,
-        page::ClaimedPage,
+        page::{ClaimedPage, PageFrame},
     },
     sync::spinlock::SpinLockIrq,
 };
@@ -83,6 +83,24 @@ where
 
         Ok(self.block_ptr_mut(block_idx))
     }
+
+    /// Zeroes everything held in the allocated blocks from `offset` onwards.
+    /// Blocks past the end of the file may have been written through a shared
+    /// mapping, and that data mustn't show up once the file grows over them.
+    fn zero_from(&mut self, mut offset: usize) {
+        while offset < self.allocated_blocks * BLOCK_SZ {
+            let (blk_idx, blk_offset) = (offset / BLOCK_SZ, offset % BLOCK_SZ);
+            let len = BLOCK_SZ - blk_offset;
+
+            unsafe {
+                self.block_ptr_mut(blk_idx)
+                    .add(blk_offset)
+                    .write_bytes(0, len);
+            }
+
+            offset += len;
+        }
+    }
 }
 
 impl<C, G, T> Drop for TmpFsRegInner<C, G, T>
@@ -174,18 +192,19 @@ where
         while bytes_to_read > 0 {
             let (blk_idx, blk_offset) = Self::offset_to_block_locus(offset as _);
 
-            // Check if this block is actually allocated (sparse file protection)
-            // though try_alloc_block enforces continuity, so this check is sanity.
-            if blk_idx >= inner.allocated_blocks {
-                break;
-            }
-
             let bytes_in_block = BLOCK_SZ - blk_offset;
             let chunk_len = min(bytes_to_read, bytes_in_block);
 
             unsafe {
-                let src = inner.block_ptr_mut(blk_idx).add(blk_offset);
-                src.copy_to_nonoverlapping(buf_ptr, chunk_len);
+                // Blocks past the last one written exist only because the file
+                // was extended by truncate, and read as zeros.
+                if blk_idx >= inner.allocated_blocks {
+                    buf_ptr.write_bytes(0, chunk_len);
+                } else {
+                    let src = inner.block_ptr_mut(blk_idx).add(blk_offset);
+                    src.copy_to_nonoverlapping(buf_ptr, chunk_len);
+                }
+
                 buf_ptr = buf_ptr.add(chunk_len);
             };
 
@@ -212,6 +231,11 @@ where
             return Ok(0);
         }
 
+        if offset as usize > inner.size {
+            let old_size = inner.size;
+            inner.zero_from(old_size);
+        }
+
         let mut buf_ptr = buf.as_ptr();
         let mut total_written = 0;
 
@@ -257,6 +281,8 @@ where
             // We just update the size. The holes are implicitly zeroed by
             // read_at logic, and write_at will fill them with zeroed pages when
             // touched.
+            let old_size = inner.size;
+            inner.zero_from(old_size);
             inner.size = new_size;
             self.attr.lock_save_irq().size = size;
             return Ok(());
@@ -315,6 +341,28 @@ where
         Ok(())
     }
 
+    async fn shared_page(&self, offset: u64) -> Result<PageFrame> {
+        if offset as usize >= MAX_SZ {
+            return Err(FsError::OutOfBounds.into());
+        }
+
+        let (blk_idx, _) = Self::offset_to_block_locus(offset as _);
+        let block_ptr = self.inner.lock_save_irq().try_alloc_block(blk_idx)?;
+
+        // SAFETY: The block belongs to this file, which keeps its own
+        // reference. The caller's is taken by the clone.
+        let page = unsafe {
+            ClaimedPage::<C, G, T>::from_pfn(
+                VA::from_ptr_mut(block_ptr.cast()).to_pa::<T>().to_pfn(),
+            )
+        };
+        let pfn = page.clone().leak();
+
+        page.leak();
+
+        Ok(pfn)
+    }
+
     async fn getattr(&self) -> Result<FileAttr> {
         Ok(self.attr.lock_save_irq().clone())
     }
@@ -805,6 +853,17 @@ where
     pub fn alloc_inode_id(&self) -> u64 {
         self.next_inode_id.fetch_add(1, Ordering::Relaxed)
     }
+
+    /// Creates a regular file that isn't linked into any directory. It lives
+    /// only as long as references to the returned inode.
+    pub fn create_unlinked(&self, mode: FilePermissions) -> Result<Arc<dyn Inode>> {
+        let id = InodeId::from_fsid_and_inodeid(self.id, self.alloc_inode_id());
+        let inode = TmpFsReg::<C, G, T>::new(id, mode)?;
+
+        inode.attr.lock_save_irq().nlinks = 0;
+
+        Ok(Arc::new(inode))
+    }
 }
 
 #[async_trait]
@@ -977,6 +1036,69 @@ mod tests {
         assert_eq!(&buf[5..], &[0, 0, 0, 0, 0]);
     }
 
+    #[tokio::test]
+    async fn test_truncate_extends_past_allocated_blocks() {
+        let fs = setup_fs();
+        let reg = fs.create_unlinked(FilePermissions::all()).unwrap();
+
+        reg.write_at(0, b"data").await.unwrap();
+        reg.truncate(3 * PAGE_SIZE as u64).await.unwrap();
+
+        // The extension spans blocks that were never written.
+        let mut buf = vec![0xffu8; 3 * PAGE_SIZE];
+        let read = reg.read_at(0, &mut buf).await.unwrap();
+        assert_eq!(read, 3 * PAGE_SIZE);
+        assert_eq!(&buf[..4], b"data");
+        assert!(buf[4..].iter().all(|b| *b == 0));
+
+        let attr = reg.getattr().await.unwrap();
+        assert_eq!(attr.nlinks, 0);
+    }
+
+    #[tokio::test]
+    async fn test_shared_page() {
+        let fs = setup_fs();
+        let reg = fs.create_unlinked(FilePermissions::all()).unwrap();
+        reg.truncate(PAGE_SIZE as u64).await.unwrap();
+
+        let pfn = reg.shared_page(0).await.unwrap();
+        let page =
+            unsafe { core::slice::from_raw_parts_mut(pfn.pa().value() as *mut u8, PAGE_SIZE) };
+
+        // Writes through the page and through the file see each other.
+        page[..5].copy_from_slice(b"hello");
+        let mut buf = [0u8; 5];
+        reg.read_at(0, &mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        reg.write_at(0, b"world").await.unwrap();
+        assert_eq!(&page[..5], b"world");
+
+        // Shrinking the file leaves the page to the caller's reference.
+        reg.truncate(0).await.unwrap();
+        let pg_alloc = PG_ALLOC.get().unwrap();
+        assert!(pg_alloc.is_allocated_exclusive(pfn));
+        drop(unsafe {
+            ClaimedPage::<MockCpuOps, TmpFsPgAllocGetter, IdentityTranslator>::from_pfn(pfn)
+        });
+
+        // Data written past the end of the file doesn't show up when the file
+        // grows over it.
+        let pfn = reg.shared_page(0).await.unwrap();
+        let page =
+            unsafe { core::slice::from_raw_parts_mut(pfn.pa().value() as *mut u8, PAGE_SIZE) };
+        page.fill(0xff);
+
+        reg.truncate(PAGE_SIZE as u64).await.unwrap();
+        let mut buf = vec![0xffu8; PAGE_SIZE];
+        reg.read_at(0, &mut buf).await.unwrap();
+        assert!(buf.iter().all(|b| *b == 0));
+
+        drop(unsafe {
+            ClaimedPage::<MockCpuOps, TmpFsPgAllocGetter, IdentityTranslator>::from_pfn(pfn)
+        });
+    }
+
     #[tokio::test]
     async fn test_dir_create_and_lookup() {
         let fs = setup_fs();
@@ -25,6 +25,7 @@ use crate::{
     driver::CharDevDescriptor,
     error::{FsError, KernelError, Result},
     fs::{path::Path, pathbuf::PathBuf},
+    memory::page::PageFrame,
 };
 use alloc::vec::Vec;
 use alloc::{boxed::Box, string::String, sync::Arc};
@@ -41,7 +42,8 @@ bitflags::bitflags! {
         const O_CREAT     = 0o100;
         const O_EXCL      = 0o200;
         const O_TRUNC     = 0o1000;
-        const O_DIRECTORY = 0o200000;
+        const O_DIRECTORY = 0o40000;
+        const O_NOFOLLOW  = 0o100000;
         const O_APPEND    = 0o2000;
         const O_NONBLOCK  = 0o4000;
         const O_CLOEXEC   = 0o2000000;
@@ -75,6 +77,23 @@ pub trait Filesystem: Send + Sync {
     async fn sync(&self) -> Result<()> {
         Ok(())
     }
+
+    /// Reports the size and free space of the filesystem.
+    ///
+    /// Filesystems without a fixed capacity can rely on the default, which
+    /// reports everything as zero.
+    async fn stats(&self) -> Result<FsStats> {
+        Ok(FsStats::default())
+    }
+}
+
+/// Capacity information for a filesystem, as reported by `statfs`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FsStats {
+    pub block_size: u64,
+    pub total_blocks: u64,
+    pub free_blocks: u64,
+    pub max_name_len: u64,
 }
 
 // A unique identifier for an inode across the entire VFS. A tuple of
@@ -178,6 +197,39 @@ pub trait BlockDevice: Send + Sync {
 
     /// Flushes any caches to the underlying device.
     async fn sync(&self) -> Result<()>;
+
+    /// Flushes cached writes to the `count` blocks starting at `block_id`.
+    /// Devices that can't flush part of their cache sync all of it.
+    async fn sync_range(&self, block_id: u64, count: u64) -> Result<()> {
+        let _ = (block_id, count);
+
+        self.sync().await
+    }
+}
+
+/// Allows a block device to be shared, e.g. between a filesystem and a
+/// writeback thread.
+#[async_trait]
+impl<T: BlockDevice + ?Sized> BlockDevice for Arc<T> {
+    async fn read(&self, block_id: u64, buf: &mut [u8]) -> Result<()> {
+        (**self).read(block_id, buf).await
+    }
+
+    async fn write(&self, block_id: u64, buf: &[u8]) -> Result<()> {
+        (**self).write(block_id, buf).await
+    }
+
+    fn block_size(&self) -> usize {
+        (**self).block_size()
+    }
+
+    async fn sync(&self) -> Result<()> {
+        (**self).sync().await
+    }
+
+    async fn sync_range(&self, block_id: u64, count: u64) -> Result<()> {
+        (**self).sync_range(block_id, count).await
+    }
 }
 
 /// A stateless representation of a filesystem object.
@@ -311,6 +363,34 @@ pub trait Inode: Send + Sync + Any {
         Err(KernelError::NotSupported)
     }
 
+    /// For files whose contents are generated on demand, returns the full
+    /// contents as of now. Opening such a file takes a snapshot so that every
+    /// read through that open file sees the same contents.
+    ///
+    /// Returns `None` for ordinary files, which are read directly.
+    async fn snapshot(&self) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    /// Whether reads of the file may be served from the kernel's page cache.
+    ///
+    /// The cache keys pages by [`Inode::id`], so this must only be true while
+    /// no other file can have the same ID, and all writes to the file go
+    /// through the cache.
+    fn cacheable(&self) -> bool {
+        false
+    }
+
+    /// Returns the page holding the file's data at `offset`, which must be
+    /// page aligned, so that it can be mapped into shared mappings of the
+    /// file. A reference to the page is taken on behalf of the caller.
+    ///
+    /// Only filesystems that keep file contents in memory can support this.
+    /// Shared mappings of other files go through the kernel's own cache.
+    async fn shared_page(&self, _offset: u64) -> Result<PageFrame> {
+        Err(KernelError::NotSupported)
+    }
+
     /// Flushes all modified data, including metadata, to the disk device containing the inode.
     ///
     /// The default implementation is a no-op so that read-only filesystems do
@@ -375,6 +455,10 @@ where
     async fn readlink(&self) -> Result<PathBuf> {
         self.readlink().await
     }
+
+    async fn snapshot(&self) -> Result<Option<Vec<u8>>> {
+        Ok(Some(self.read().await?))
+    }
 }
 
 pub struct SimpleDirStream {
@@ -209,6 +209,45 @@ impl Path {
     pub fn file_name(&self) -> Option<&str> {
         self.components().last()
     }
+
+    /// Lexically normalises the path, removing `.` components and resolving
+    /// `..` against the component before it. `..` at the root stays at the
+    /// root, while leading `..`s are kept in a relative path.
+    ///
+    /// This doesn't consult the filesystem, so a `..` that follows a symlink
+    /// removes the link rather than going to its target's parent.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libkernel::fs::path::Path;
+    /// use libkernel::fs::pathbuf::PathBuf;
+    ///
+    /// assert_eq!(Path::new("/usr/./lib/../bin").normalize(), PathBuf::from("/usr/bin"));
+    /// assert_eq!(Path::new("/..").normalize(), PathBuf::from("/"));
+    /// assert_eq!(Path::new("../a/..").normalize(), PathBuf::from(".."));
+    /// ```
+    pub fn normalize(&self) -> PathBuf {
+        let mut components = Vec::new();
+
+        for component in self.components() {
+            if component != ".." {
+                components.push(component);
+            } else if components.last().is_some_and(|&c| c != "..") {
+                components.pop();
+            } else if self.is_relative() {
+                components.push(component);
+            }
+        }
+
+        let mut ret = PathBuf::from(if self.is_absolute() { "/" } else { "" });
+
+        for component in components {
+            ret.push(component);
+        }
+
+        ret
+    }
 }
 
 impl AsRef<Path> for str {
@@ -334,6 +373,17 @@ mod tests {
         assert_eq!(Path::new("a").parent(), None);
     }
 
+    #[test]
+    fn test_normalize() {
+        assert_eq!(Path::new("/a/b/../c").normalize(), "/a/c".into());
+        assert_eq!(Path::new("/a/./b/").normalize(), "/a/b".into());
+        assert_eq!(Path::new("/a/b/../..").normalize(), "/".into());
+        assert_eq!(Path::new("/../../a").normalize(), "/a".into());
+        assert_eq!(Path::new("/").normalize(), "/".into());
+        assert_eq!(Path::new("a/../../b").normalize(), "../b".into());
+        assert_eq!(Path::new("a/..").normalize(), "".into());
+    }
+
     #[test]
     fn test_file_name() {
         assert_eq!(Path::new("/a/b/c.txt").file_name(), Some("c.txt"));
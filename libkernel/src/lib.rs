@@ -43,6 +43,12 @@ pub trait CpuOps: 'static {
 pub struct PageInfo {
     pub pfn: PageFrame,
     pub perms: PtePermissions,
+    /// Whether the page has been accessed since it was mapped or the flag was
+    /// last cleared.
+    pub accessed: bool,
+    /// Whether the page may have been written to since it was mapped or last
+    /// cleaned.
+    pub dirty: bool,
 }
 
 /// Represents a process's memory context, abstracting the hardware-specific
@@ -87,6 +93,18 @@ pub trait UserAddressSpace: Send + Sync {
     /// state. Used on process termination code-paths.
     fn deactivate(&self);
 
+    /// Tears down the address space, deactivating it first if it's active on
+    /// the current CPU.
+    ///
+    /// This address space's reference to every mapped frame is dropped, so a
+    /// frame shared with another address space (e.g. CoW after a `fork()`) is
+    /// only freed along with its last mapping. The page tables themselves are
+    /// freed too. Dropping an address space has the same effect, but this
+    /// makes the point at which it happens explicit, e.g. on process exit.
+    fn destroy(self)
+    where
+        Self: Sized;
+
     /// Maps a single physical page frame to a virtual address.
     ///
     /// This function creates a page table entry (PTE) that maps the given
@@ -166,6 +184,24 @@ pub trait UserAddressSpace: Send + Sync {
     /// mapping exists for `va`.
     fn translate(&self, va: VA) -> Option<PageInfo>;
 
+    /// Records that the page at `va` has been accessed.
+    ///
+    /// User pages are mapped unaccessed. Where the hardware doesn't track
+    /// accesses itself, the first access to a page faults, and the fault
+    /// handler calls this before retrying it.
+    ///
+    /// Returns an error if no page is mapped at `va`.
+    fn mark_accessed(&mut self, va: VA) -> Result<()>;
+
+    /// The number of pages mapped: the resident set size of the process using
+    /// this address space.
+    ///
+    /// A page mapped by several address spaces, e.g. shared copy-on-write after
+    /// a `fork()` or through a shared file mapping, counts once in each.
+    /// Replacing a page, as breaking copy-on-write does, leaves the count as it
+    /// was.
+    fn resident_pages(&self) -> usize;
+
     /// Atomically protects a region in the source address space and clones the
     /// mappings into a destination address space.
     ///
@@ -210,6 +246,11 @@ pub trait KernAddressSpace: Send {
     /// Map the given region as MMIO memory.
     fn map_mmio(&mut self, region: PhysMemoryRegion) -> Result<VA>;
 
+    /// Unmap an MMIO region previously mapped with `map_mmio`. `region`
+    /// starts at the address `map_mmio` returned and has the size of the
+    /// physical region that was mapped.
+    fn unmap_mmio(&mut self, region: VirtMemoryRegion) -> Result<()>;
+
     /// Map the given region as normal memory.
     fn map_normal(
         &mut self,
@@ -241,15 +282,24 @@ pub trait VirtualMemory: CpuOps + Sized {
 #[cfg(test)]
 pub mod test {
     use core::hint::spin_loop;
+    use core::sync::atomic::{AtomicUsize, Ordering};
 
     use crate::CpuOps;
 
-    // A CPU mock object that can be used in unit-tests.
+    static NEXT_CPU_ID: AtomicUsize = AtomicUsize::new(0);
+
+    std::thread_local! {
+        static CPU_ID: usize = NEXT_CPU_ID.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // A CPU mock object that can be used in unit-tests. Each test thread acts
+    // as a CPU of its own, so that threads contending for a lock aren't taken
+    // for a CPU re-acquiring a lock it already holds.
     pub struct MockCpuOps {}
 
     impl CpuOps for MockCpuOps {
         fn id() -> usize {
-            0
+            CPU_ID.with(|id| *id)
         }
 
         fn halt() -> ! {
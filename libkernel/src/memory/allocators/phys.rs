@@ -16,6 +16,8 @@ use crate::{
 use core::{
     cmp::min,
     mem::{MaybeUninit, size_of, transmute},
+    ptr,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
 };
 use intrusive_collections::{LinkedList, UnsafeRef};
 use log::info;
@@ -153,6 +155,11 @@ impl FrameAllocatorInner {
 
 pub struct FrameAllocator<CPU: CpuOps> {
     pub(super) inner: SpinLockIrq<FrameAllocatorInner, CPU>,
+    /// Allocations leaving fewer pages free than this raise `pressure`.
+    low_watermark: AtomicUsize,
+    /// Set when free memory falls below the low watermark or an allocation
+    /// fails, until taken by [`FrameAllocator::take_pressure`].
+    pressure: AtomicBool,
 }
 
 pub struct PageAllocation<'a, CPU: CpuOps> {
@@ -171,6 +178,38 @@ impl<CPU: CpuOps> PageAllocation<'_, CPU> {
         &self.region
     }
 
+    /// Drops this reference to the allocation. If it was the last one, the
+    /// block's contents are zeroed before it is returned to the allocator, so
+    /// that they can't be observed by whoever allocates it next.
+    pub fn free_zeroed<T: AddressTranslator<()>>(self) {
+        let head_pfn = self.region.start_address().to_pfn();
+
+        {
+            let mut inner = self.inner.lock_save_irq();
+
+            if let FrameState::AllocatedHead(ref mut info) = inner.get_frame_mut(head_pfn).state
+                && info.ref_count > 1
+            {
+                info.ref_count -= 1;
+                drop(inner);
+                core::mem::forget(self);
+                return;
+            }
+        }
+
+        // SAFETY: We hold the only reference to the block, so nothing else
+        // can be accessing it.
+        unsafe {
+            ptr::write_bytes(
+                self.region.start_address().to_va::<T>().as_ptr_mut() as *mut u8,
+                0,
+                self.region.size(),
+            );
+        }
+
+        drop(self);
+    }
+
     /// Leak the allocation as a slab allocation, for it to be picked back up
     /// again once the slab has been free'd.
     ///
@@ -225,6 +264,10 @@ impl<CPU: CpuOps> FrameAllocator<CPU> {
     /// # Arguments
     /// * `order`: The order of the allocation, where the number of pages is `2^order`.
     ///   `order = 0` requests a single page.
+    ///
+    /// An allocation that leaves free memory below the low watermark, or that
+    /// fails for lack of it, raises memory pressure (see
+    /// [`FrameAllocator::take_pressure`]).
     pub fn alloc_frames(&self, order: u8) -> Result<PageAllocation<'_, CPU>> {
         let mut inner = self.inner.lock_save_irq();
         let requested_order = order as usize;
@@ -240,6 +283,7 @@ impl<CPU: CpuOps> FrameAllocator<CPU> {
                 Some((pg_block, order))
             })
         else {
+            self.pressure.store(true, Ordering::Relaxed);
             return Err(KernelError::NoMemory);
         };
 
@@ -273,6 +317,10 @@ impl<CPU: CpuOps> FrameAllocator<CPU> {
 
         inner.free_pages -= num_pages_in_block;
 
+        if inner.free_pages < self.low_watermark.load(Ordering::Relaxed) {
+            self.pressure.store(true, Ordering::Relaxed);
+        }
+
         Ok(PageAllocation {
             region: PhysMemoryRegion::new(block_pfn.pa(), num_pages_in_block << PAGE_SHIFT),
             inner: &self.inner,
@@ -305,16 +353,20 @@ impl<CPU: CpuOps> FrameAllocator<CPU> {
 
     /// Returns `true` if the page is part of an allocated block and has a ref
     /// count of 1, `false` otherwise.
-    pub fn is_allocated_exclusive(&self, mut pfn: PageFrame) -> bool {
+    pub fn is_allocated_exclusive(&self, pfn: PageFrame) -> bool {
+        self.ref_count(pfn) == 1
+    }
+
+    /// Returns the ref count of the allocated block the page is part of, or 0
+    /// if the page isn't allocated.
+    pub fn ref_count(&self, mut pfn: PageFrame) -> u32 {
         let inner = self.inner.lock_save_irq();
 
         loop {
             match inner.get_frame(pfn).state {
                 FrameState::AllocatedTail(TailInfo { head }) => pfn = head,
-                FrameState::AllocatedHead(AllocatedInfo { ref_count: 1, .. }) => {
-                    return true;
-                }
-                _ => return false,
+                FrameState::AllocatedHead(AllocatedInfo { ref_count, .. }) => return ref_count,
+                _ => return 0,
             }
         }
     }
@@ -331,6 +383,22 @@ impl<CPU: CpuOps> FrameAllocator<CPU> {
         self.inner.lock_save_irq().free_pages
     }
 
+    /// Sets the number of free pages below which allocations raise memory
+    /// pressure. Zero, the default, turns the check off.
+    pub fn set_low_watermark(&self, pages: usize) {
+        self.low_watermark.store(pages, Ordering::Relaxed);
+    }
+
+    /// Returns whether memory pressure has been raised since the last call,
+    /// clearing it.
+    ///
+    /// Nothing is reclaimed by the allocator itself: it's called by the heap
+    /// with its own state borrowed, so reclaiming there, which frees heap
+    /// memory, could corrupt it. Whoever reclaims polls this instead.
+    pub fn take_pressure(&self) -> bool {
+        self.pressure.swap(false, Ordering::Relaxed)
+    }
+
     /// Initializes the frame allocator. This is the main bootstrap function.
     ///
     /// # Safety
@@ -389,7 +457,9 @@ impl<CPU: CpuOps> FrameAllocator<CPU> {
         };
 
         for region in smalloc.res.iter() {
-            for pfn in region.iter_pfns() {
+            // Reservations needn't be page aligned; any page they touch, even
+            // partially, belongs to the kernel.
+            for pfn in region.align_to_page_boundary().iter_pfns() {
                 if pfn >= base_page && pfn.value() < base_page.value() + frame_list.total_pages() {
                     allocator.get_frame_mut(pfn).state = FrameState::Kernel;
                 }
@@ -424,6 +494,8 @@ impl<CPU: CpuOps> FrameAllocator<CPU> {
         (
             FrameAllocator {
                 inner: SpinLockIrq::new(allocator),
+                low_watermark: AtomicUsize::new(0),
+                pressure: AtomicBool::new(false),
             },
             frame_list,
         )
@@ -432,6 +504,12 @@ impl<CPU: CpuOps> FrameAllocator<CPU> {
 
 pub trait PageAllocGetter<C: CpuOps>: Send + Sync + 'static {
     fn global_page_alloc() -> &'static FrameAllocator<C>;
+
+    /// Frees memory held by caches, returning whether any was freed. Called
+    /// when a single page allocation fails, before it's retried.
+    fn reclaim() -> bool {
+        false
+    }
 }
 
 #[cfg(test)]
@@ -570,6 +648,29 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn low_watermark_raises_pressure() {
+        let fixture = TestFixture::new(&[(0, (1 << (MAX_ORDER + PAGE_SHIFT)) * 2)], &[]);
+        let free = fixture.free_pages();
+
+        // Off by default.
+        let _a = fixture.allocator.alloc_frames(0).unwrap();
+        assert!(!fixture.allocator.take_pressure());
+
+        fixture.allocator.set_low_watermark(free - 2);
+        let _b = fixture.allocator.alloc_frames(0).unwrap();
+        assert!(!fixture.allocator.take_pressure());
+
+        let _c = fixture.allocator.alloc_frames(0).unwrap();
+        assert!(fixture.allocator.take_pressure());
+        assert!(!fixture.allocator.take_pressure());
+
+        // Failing for lack of memory raises it too.
+        fixture.allocator.set_low_watermark(0);
+        while fixture.allocator.alloc_frames(0).map(|a| a.leak()).is_ok() {}
+        assert!(fixture.allocator.take_pressure());
+    }
+
     /// Tests basic allocator initialization with a single large, contiguous memory region.
     #[test]
     fn init_simple() {
@@ -636,6 +737,57 @@ pub mod tests {
         ));
     }
 
+    #[test]
+    fn reserved_partial_pages_never_allocated() {
+        let pages_in_max_block = 1 << MAX_ORDER;
+        let block_size = pages_in_max_block * PAGE_SIZE;
+
+        // Reservations that start and end part way through a page. The first
+        // straddles the boundary between the first two max-order blocks.
+        let res_regions = &[
+            (block_size - 0x100, 0x200),
+            (block_size * 3 + 0x80, PAGE_SIZE),
+        ];
+        let fixture = TestFixture::new(&[(0, 4 * block_size)], res_regions);
+
+        let base = fixture.frame_list.base_page().value();
+        let reserved = [
+            base + pages_in_max_block - 1,
+            base + pages_in_max_block,
+            base + pages_in_max_block * 3,
+            base + pages_in_max_block * 3 + 1,
+        ];
+
+        for pfn in reserved {
+            assert!(matches!(
+                fixture.frame_state(PageFrame::from_pfn(pfn)),
+                FrameState::Kernel
+            ));
+        }
+
+        let initial_free = fixture.free_pages();
+        let mut allocs = Vec::new();
+
+        while let Ok(alloc) = fixture.allocator.alloc_frames(0) {
+            let pfn = alloc.region.start_address().to_pfn().value();
+            assert!(!reserved.contains(&pfn));
+            allocs.push(alloc);
+        }
+
+        // Only the block clear of every reservation was free.
+        assert_eq!(initial_free, pages_in_max_block);
+        assert_eq!(allocs.len(), initial_free);
+        assert_eq!(fixture.free_pages(), 0);
+
+        // Every page comes back, merged into the block it was split from.
+        drop(allocs);
+        assert_eq!(fixture.free_pages(), initial_free);
+
+        let mut expected_counts = [0; MAX_ORDER + 1];
+        expected_counts[MAX_ORDER] = 1;
+        fixture.assert_free_list_counts(&expected_counts);
+    }
+
     /// Tests a simple allocation and deallocation cycle.
     #[test]
     fn simple_alloc_and_free() {
@@ -784,6 +936,7 @@ pub mod tests {
         assert_eq!(fixture.free_pages(), initial_free - pages_in_block);
 
         let pfn = alloc1.region().start_address().to_pfn();
+        assert_eq!(fixture.allocator.ref_count(pfn.add_pages(1)), 3);
 
         // First free should just decrement the count
         drop(alloc1);
@@ -806,8 +959,55 @@ pub mod tests {
         }
 
         // Third free should actually release the memory
+        assert!(fixture.allocator.is_allocated_exclusive(pfn));
         drop(alloc3);
         assert_eq!(fixture.free_pages(), initial_free);
+        assert_eq!(fixture.allocator.ref_count(pfn), 0);
         assert!(matches!(fixture.frame_state(pfn), FrameState::Free { .. }));
     }
+
+    /// Tests that `free_zeroed` only scrubs the block once its last reference
+    /// goes, and that a reallocation then sees zeroes.
+    #[test]
+    fn free_zeroed_scrubs_last_reference() {
+        let fixture = TestFixture::new(&[(0, (1 << (MAX_ORDER + PAGE_SHIFT)) * 2)], &[]);
+        let initial_free = fixture.free_pages();
+
+        let alloc1 = fixture.allocator.alloc_frames(0).unwrap();
+        let alloc2 = alloc1.clone();
+        let region = *alloc1.region();
+        let contents = || unsafe {
+            std::slice::from_raw_parts(
+                region
+                    .start_address()
+                    .to_va::<IdentityTranslator>()
+                    .as_ptr() as *const u8,
+                PAGE_SIZE,
+            )
+        };
+
+        unsafe {
+            ptr::write_bytes(
+                region
+                    .start_address()
+                    .to_va::<IdentityTranslator>()
+                    .as_ptr_mut() as *mut u8,
+                0xaa,
+                PAGE_SIZE,
+            );
+        }
+
+        // Another reference remains, so the contents must be left alone.
+        alloc1.free_zeroed::<IdentityTranslator>();
+        assert!(contents().iter().all(|&b| b == 0xaa));
+        assert_eq!(fixture.free_pages(), initial_free - 1);
+
+        alloc2.free_zeroed::<IdentityTranslator>();
+        assert_eq!(fixture.free_pages(), initial_free);
+
+        // The most recently freed page is handed out first.
+        let realloc = fixture.allocator.alloc_frames(0).unwrap();
+        assert_eq!(*realloc.region(), region);
+        assert!(contents().iter().all(|&b| b == 0));
+    }
 }
@@ -1,6 +1,6 @@
 /// A slab allocator for Moss.
 use super::{
-    SLAB_FRAME_ALLOC_ORDER, SLAB_MAX_OBJ_SHIFT, alloc_order,
+    SLAB_FRAME_ALLOC_ORDER, SLAB_MAX_OBJ_SHIFT, SLAB_SIZE_BYTES, alloc_order,
     slab::{Slab, SlabState},
 };
 use crate::{
@@ -60,6 +60,8 @@ pub struct SlabManager<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslato
     pub(super) free: LinkedList<FrameAdapter>,
     pub(super) partial: LinkedList<FrameAdapter>,
     pub(super) free_list_sz: usize,
+    /// The number of slabs (free, partial and full) owned by this manager.
+    nr_slabs: usize,
     obj_shift: usize,
     frame_list: FrameList,
     phantom1: PhantomData<A>,
@@ -73,6 +75,7 @@ impl<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> SlabManager
             free: LinkedList::new(FrameAdapter::new()),
             partial: LinkedList::new(FrameAdapter::new()),
             free_list_sz: 0,
+            nr_slabs: 0,
             obj_shift,
             frame_list,
             phantom1: PhantomData,
@@ -138,21 +141,29 @@ impl<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> SlabManager
     /// slabs first; if none are avilable allocate a new slab from the frame
     /// allocator.
     pub fn alloc(&mut self) -> *mut u8 {
+        self.alloc_checked()
+            .expect("OOM - cannot allocate physical frame")
+    }
+
+    /// As [`Self::alloc`], but returns `None` if there's no physical memory
+    /// for a new slab.
+    pub fn alloc_checked(&mut self) -> Option<*mut u8> {
         // Fast path, first.
         if let Some(ptr) = self.try_alloc() {
-            return ptr;
+            return Some(ptr);
         }
 
         // Slow path, allocate a new frame.
         let new_alloc = A::global_page_alloc()
             .alloc_frames(SLAB_FRAME_ALLOC_ORDER as _)
-            .expect("OOM - cannot allocate physical frame");
+            .ok()?;
 
         let mut slab = Slab::new::<T, CPU>(&new_alloc, self.obj_shift);
 
         let obj = slab.alloc_object().expect("Slab should be empty");
         let state = slab.state();
         let frame = new_alloc.into_slab(slab);
+        self.nr_slabs += 1;
 
         // We now have ownership of the frame.
         if state == SlabState::Partial {
@@ -162,7 +173,7 @@ impl<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> SlabManager
                 .push_front(unsafe { UnsafeRef::from_raw(frame) });
         }
 
-        obj
+        Some(obj)
     }
 
     /// Free the given allocation.
@@ -222,6 +233,7 @@ impl<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> SlabManager
                     }
 
                     self.free_list_sz -= num_freed;
+                    self.nr_slabs -= num_freed;
                 }
 
                 if frame.link.is_linked() {
@@ -276,6 +288,15 @@ impl<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> SlabAllocat
     ) -> Option<&SpinLockIrq<SlabManager<CPU, A, T>, CPU>> {
         Some(&self.managers[alloc_order(layout)?])
     }
+
+    /// Returns the number of bytes of physical memory currently held in slabs,
+    /// across all size classes.
+    pub fn slab_bytes(&self) -> usize {
+        self.managers
+            .iter()
+            .map(|man| man.lock_save_irq().nr_slabs * SLAB_SIZE_BYTES)
+            .sum()
+    }
 }
 
 #[cfg(test)]
@@ -356,9 +377,11 @@ mod tests {
             assert!(inner.partial.is_empty());
             assert!(inner.free.is_empty());
         }
+        assert_eq!(allocator.slab_bytes(), 0);
 
         // Alloc one object
         let ptr = alloc.lock_save_irq().alloc();
+        assert_eq!(allocator.slab_bytes(), SLAB_SIZE_BYTES);
 
         {
             let inner = alloc.lock_save_irq();
@@ -386,6 +409,8 @@ mod tests {
             assert!(inner.partial.is_empty());
             assert_eq!(inner.free.iter().count(), 1);
         }
+        // Free slabs are kept around, so still count as slab memory.
+        assert_eq!(allocator.slab_bytes(), SLAB_SIZE_BYTES);
     }
 
     #[test]
@@ -1,4 +1,9 @@
-use super::{allocator::SlabAllocator, cache::SlabCache};
+use super::{
+    alloc_order,
+    allocator::SlabAllocator,
+    cache::SlabCache,
+    stats::{HeapCounters, HeapStats},
+};
 use crate::{
     CpuOps,
     memory::{
@@ -9,7 +14,14 @@ use crate::{
         region::PhysMemoryRegion,
     },
 };
-use core::{alloc::GlobalAlloc, marker::PhantomData, ops::DerefMut};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    marker::PhantomData,
+    ops::DerefMut,
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use log::error;
 
 pub trait SlabGetter<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> {
     fn global_slab_alloc() -> &'static SlabAllocator<CPU, A, T>;
@@ -33,6 +45,7 @@ where
     phantom3: PhantomData<CPU>,
     phantom4: PhantomData<T>,
     phantom5: PhantomData<SG>,
+    stats: HeapCounters,
 }
 
 impl<CPU, S, PG, T, SG> Default for KHeap<CPU, S, PG, T, SG>
@@ -63,11 +76,17 @@ where
             phantom3: PhantomData,
             phantom4: PhantomData,
             phantom5: PhantomData,
+            stats: HeapCounters::new(),
         }
     }
 
+    /// Returns the heap's current usage.
+    pub fn stats(&self) -> HeapStats {
+        self.stats.fold()
+    }
+
     /// Calculates the Frame Allocator order required for a large allocation.
-    fn calculate_huge_order(layout: core::alloc::Layout) -> usize {
+    fn calculate_huge_order(layout: Layout) -> usize {
         // Ensure we cover the size, rounding UP to the nearest page.
         let size = core::cmp::max(layout.size(), layout.align());
         let pages_needed = size.div_ceil(PAGE_SIZE);
@@ -86,34 +105,41 @@ where
         // Store the slab_cache pointer in the storage.
         S::store(slab_cache);
     }
-}
 
-unsafe impl<CPU, S, PG, T, SG> GlobalAlloc for KHeap<CPU, S, PG, T, SG>
-where
-    CPU: CpuOps,
-    S: SlabCacheStorage,
-    PG: PageAllocGetter<CPU>,
-    T: AddressTranslator<()>,
-    SG: SlabGetter<CPU, PG, T>,
-{
-    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
-        let mut cache = S::get();
+    /// Takes an allocation straight from the frame allocator.
+    fn alloc_huge(&self, layout: Layout) -> *mut u8 {
+        let order = Self::calculate_huge_order(layout);
 
-        let Some(cache_line) = cache.get_cache(layout) else {
+        let Ok(alloc) = PG::global_page_alloc().alloc_frames(order as _) else {
+            return ptr::null_mut();
+        };
+
+        self.stats.huge::<CPU>((PAGE_SIZE << order) as isize);
+        self.stats.sample_peak();
+
+        alloc
+            .leak()
+            .start_address()
+            .to_va::<T>()
+            .cast::<u8>()
+            .as_ptr_mut()
+    }
+
+    fn alloc_slab(&self, layout: Layout) -> *mut u8 {
+        let Some(class) = alloc_order(layout) else {
             // Allocation is too big for SLAB. Defer to using the frame
             // allocator directly.
-            return PG::global_page_alloc()
-                .alloc_frames(Self::calculate_huge_order(layout) as _)
-                .unwrap()
-                .leak()
-                .start_address()
-                .to_va::<T>()
-                .cast::<u8>()
-                .as_ptr_mut();
+            return self.alloc_huge(layout);
         };
 
+        let mut cache = S::get();
+        let cache_line = cache
+            .get_cache(layout)
+            .expect("Every slab size class has a cache line");
+
         if let Some(ptr) = cache_line.alloc() {
             // Fast path, cache-hit.
+            self.stats.slab::<CPU>(class, 1);
             return ptr;
         }
 
@@ -123,16 +149,60 @@ where
             .unwrap()
             .lock_save_irq();
 
-        let ptr = slab.alloc();
+        let Some(ptr) = slab.alloc_checked() else {
+            return ptr::null_mut();
+        };
 
         // Fill up our cache with objects from the (maybe freshly allocated)
         // slab.
         cache_line.fill_from(&mut slab);
 
+        self.stats.slab::<CPU>(class, 1);
+        self.stats.sample_peak();
+
+        ptr
+    }
+
+    /// Logs why an allocation of `layout` failed. Logging can allocate too,
+    /// so a failure while reporting isn't reported again.
+    #[cold]
+    fn report_oom(&self, layout: Layout) {
+        static REPORTING: AtomicBool = AtomicBool::new(false);
+
+        if REPORTING.swap(true, Ordering::Acquire) {
+            return;
+        }
+
+        error!(
+            "Kernel heap: out of memory allocating {} bytes (align {})",
+            layout.size(),
+            layout.align()
+        );
+        self.stats().log_report();
+
+        REPORTING.store(false, Ordering::Release);
+    }
+}
+
+unsafe impl<CPU, S, PG, T, SG> GlobalAlloc for KHeap<CPU, S, PG, T, SG>
+where
+    CPU: CpuOps,
+    S: SlabCacheStorage,
+    PG: PageAllocGetter<CPU>,
+    T: AddressTranslator<()>,
+    SG: SlabGetter<CPU, PG, T>,
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.alloc_slab(layout);
+
+        if ptr.is_null() {
+            self.report_oom(layout);
+        }
+
         ptr
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let mut cache = S::get();
 
         let Some(cache_line) = cache.get_cache(layout) else {
@@ -143,6 +213,8 @@ where
                 PAGE_SIZE << Self::calculate_huge_order(layout),
             );
 
+            self.stats.huge::<CPU>(-(allocated_region.size() as isize));
+
             unsafe {
                 PG::global_page_alloc().alloc_from_region(allocated_region);
             }
@@ -150,6 +222,10 @@ where
             return;
         };
 
+        if let Some(class) = alloc_order(layout) {
+            self.stats.slab::<CPU>(class, -1);
+        }
+
         if cache_line.free(ptr).is_ok() {
             return;
         }
@@ -183,11 +259,13 @@ mod tests {
     use std::{
         cell::RefCell,
         ops::{Deref, DerefMut},
-        sync::{Arc, Barrier, OnceLock},
+        sync::{Arc, Barrier, Mutex, OnceLock},
         thread,
     };
 
     static FIXTURE: OnceLock<TestFixture> = OnceLock::new();
+    /// Held by each test, since they check the fixture's free pages.
+    static FIXTURE_USERS: Mutex<()> = Mutex::new(());
     static SLAB_ALLOCATOR: OnceLock<
         SlabAllocator<MockCpuOps, TestAllocGetter, IdentityTranslator>,
     > = OnceLock::new();
@@ -261,8 +339,89 @@ mod tests {
         TestSlabGetter,
     >;
 
+    /// Gives back this thread's slab cache and the page it lives in.
+    fn release_thread_cache() {
+        let slab = SLAB_ALLOCATOR.get().unwrap();
+        ThreadLocalCacheStorage::get().purge_into(&slab);
+
+        let addr = ThreadLocalCacheStorage::get().deref() as *const SlabCache;
+
+        unsafe {
+            FIXTURE
+                .get()
+                .unwrap()
+                .allocator
+                .alloc_from_region(PhysMemoryRegion::new(
+                    PA::from_value(addr as usize),
+                    PAGE_SIZE,
+                ));
+        }
+    }
+
+    #[test]
+    fn heap_stats_track_allocations() {
+        let _guard = FIXTURE_USERS.lock().unwrap();
+        let _ = get_fixture();
+        let _ = TestSlabGetter::global_slab_alloc();
+
+        thread::spawn(|| {
+            TestHeap::init_for_this_cpu();
+
+            let heap = TestHeap::new();
+            assert_eq!(heap.stats(), HeapStats::default());
+
+            // Size, alignment, and the bytes each is accounted at: the size of
+            // its slab class, or a power of two pages for the last.
+            let allocs = [
+                (24, 8, 32),
+                (64, 64, 64),
+                (10, 128, 128),
+                (1000, 8, 1024),
+                (24, 4, 32),
+                (20_000, 8, 8 * PAGE_SIZE),
+            ];
+
+            let mut live = Vec::new();
+            let mut expected = 0;
+
+            for (size, align, bytes) in allocs {
+                let layout = Layout::from_size_align(size, align).unwrap();
+                let ptr = unsafe { heap.alloc(layout) };
+                assert!(!ptr.is_null());
+
+                expected += bytes;
+                assert_eq!(heap.stats().allocated_bytes(), expected);
+
+                live.push((ptr, layout, bytes));
+            }
+
+            let stats = heap.stats();
+            assert_eq!(stats.huge_bytes, 8 * PAGE_SIZE);
+            assert_eq!(stats.outstanding[5], 2);
+            assert_eq!(stats.outstanding[10], 1);
+            assert_eq!(stats.peak_bytes, expected);
+
+            for (ptr, layout, bytes) in live.into_iter().rev() {
+                unsafe { heap.dealloc(ptr, layout) };
+
+                expected -= bytes;
+                assert_eq!(heap.stats().allocated_bytes(), expected);
+            }
+
+            let stats = heap.stats();
+            assert_eq!(stats.allocated_bytes(), 0);
+            assert!(stats.outstanding.iter().all(|&n| n == 0));
+            assert_eq!(stats.peak_bytes, 1280 + 8 * PAGE_SIZE);
+
+            release_thread_cache();
+        })
+        .join()
+        .unwrap();
+    }
+
     #[test]
     fn heap_stress_test() {
+        let _guard = FIXTURE_USERS.lock().unwrap();
         let _ = get_fixture();
         let _ = TestSlabGetter::global_slab_alloc();
 
@@ -352,23 +511,8 @@ mod tests {
                     }
                 }
 
-                // Purge the per-cpu caches.
-                let slab = SLAB_ALLOCATOR.get().unwrap();
-                ThreadLocalCacheStorage::get().purge_into(&slab);
-
-                let addr = ThreadLocalCacheStorage::get().deref() as *const SlabCache;
-
-                // Return the slab cache page.
-                unsafe {
-                    FIXTURE
-                        .get()
-                        .unwrap()
-                        .allocator
-                        .alloc_from_region(PhysMemoryRegion::new(
-                            PA::from_value(addr as usize),
-                            PAGE_SIZE,
-                        ));
-                }
+                // Purge the per-cpu caches and return the slab cache page.
+                release_thread_cache();
             }))
         }
 
@@ -10,6 +10,7 @@ pub mod cache;
 pub mod heap;
 #[allow(clippy::module_inception)]
 pub(super) mod slab;
+pub mod stats;
 
 /// Returns the index into the slab/cache list for a given layout.
 fn alloc_order(layout: core::alloc::Layout) -> Option<usize> {
@@ -0,0 +1,157 @@
+//! Kernel heap usage accounting.
+//!
+//! Each CPU counts the allocations it makes and frees in a slot of its own, so
+//! the fast path only touches a cache line no other CPU writes to. An object
+//! freed on a different CPU from the one that allocated it leaves one slot's
+//! count negative and another's positive; only their sum means anything, so
+//! the slots are folded together when the stats are read.
+
+use super::SLAB_MAX_OBJ_SHIFT;
+use crate::CpuOps;
+use core::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
+use log::error;
+
+/// The number of slab size classes. Class `i` holds objects of `1 << i` bytes;
+/// class 0 is never used.
+pub const NUM_SIZE_CLASSES: usize = SLAB_MAX_OBJ_SHIFT as usize + 1;
+
+/// The number of per-CPU slots. CPUs beyond this share slots, which costs some
+/// contention but no accuracy.
+const STAT_SLOTS: usize = 64;
+
+/// A snapshot of the kernel heap's usage.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Bytes held by live slab allocations, each counted at the size of its
+    /// class.
+    pub slab_bytes: usize,
+    /// Bytes held by live allocations too big for a slab, which come straight
+    /// from the frame allocator in power-of-two runs of pages.
+    pub huge_bytes: usize,
+    /// The most bytes seen allocated at once. This is sampled whenever a CPU
+    /// goes to the shared allocators, so it can miss a short-lived peak served
+    /// entirely from per-CPU caches.
+    pub peak_bytes: usize,
+    /// Live allocations in each size class.
+    pub outstanding: [usize; NUM_SIZE_CLASSES],
+}
+
+impl HeapStats {
+    /// Bytes held by all live allocations.
+    pub fn allocated_bytes(&self) -> usize {
+        self.slab_bytes + self.huge_bytes
+    }
+
+    /// Writes the stats to the log as errors, for reporting a failure.
+    pub fn log_report(&self) {
+        error!(
+            "Heap: {} bytes allocated ({} in slabs, {} huge), peak {} bytes",
+            self.allocated_bytes(),
+            self.slab_bytes,
+            self.huge_bytes,
+            self.peak_bytes
+        );
+
+        for (class, &count) in self.outstanding.iter().enumerate() {
+            if count != 0 {
+                error!(
+                    "  {:>5} byte objects: {count} live, {} bytes",
+                    1usize << class,
+                    count << class
+                );
+            }
+        }
+    }
+}
+
+/// One CPU's slot, aligned to a cache line so neighbouring slots don't share
+/// one.
+#[repr(align(64))]
+struct CpuCounters {
+    outstanding: [AtomicIsize; NUM_SIZE_CLASSES],
+    huge_bytes: AtomicIsize,
+}
+
+impl CpuCounters {
+    const fn new() -> Self {
+        Self {
+            outstanding: [const { AtomicIsize::new(0) }; NUM_SIZE_CLASSES],
+            huge_bytes: AtomicIsize::new(0),
+        }
+    }
+}
+
+/// The live counters behind [`HeapStats`].
+pub(super) struct HeapCounters {
+    cpus: [CpuCounters; STAT_SLOTS],
+    peak: AtomicUsize,
+}
+
+impl HeapCounters {
+    pub const fn new() -> Self {
+        Self {
+            cpus: [const { CpuCounters::new() }; STAT_SLOTS],
+            peak: AtomicUsize::new(0),
+        }
+    }
+
+    fn this_cpu<CPU: CpuOps>(&self) -> &CpuCounters {
+        &self.cpus[CPU::id() % STAT_SLOTS]
+    }
+
+    /// Counts `delta` objects allocated (or freed, if negative) from size
+    /// class `class`.
+    pub fn slab<CPU: CpuOps>(&self, class: usize, delta: isize) {
+        self.this_cpu::<CPU>().outstanding[class].fetch_add(delta, Ordering::Relaxed);
+    }
+
+    /// Counts `delta` bytes allocated (or freed, if negative) from the frame
+    /// allocator.
+    pub fn huge<CPU: CpuOps>(&self, delta: isize) {
+        self.this_cpu::<CPU>()
+            .huge_bytes
+            .fetch_add(delta, Ordering::Relaxed);
+    }
+
+    /// Records the current usage as the peak if it's the highest yet. Folds
+    /// every slot, so this is only for slow paths.
+    pub fn sample_peak(&self) {
+        let now = self.fold().allocated_bytes();
+
+        self.peak.fetch_max(now, Ordering::Relaxed);
+    }
+
+    pub fn fold(&self) -> HeapStats {
+        let mut outstanding = [0isize; NUM_SIZE_CLASSES];
+        let mut huge_bytes = 0isize;
+
+        for cpu in &self.cpus {
+            for (sum, count) in outstanding.iter_mut().zip(&cpu.outstanding) {
+                *sum += count.load(Ordering::Relaxed);
+            }
+
+            huge_bytes += cpu.huge_bytes.load(Ordering::Relaxed);
+        }
+
+        // A free racing with the read can be seen before its allocation.
+        let outstanding = outstanding.map(|count| count.max(0) as usize);
+
+        let slab_bytes: usize = outstanding
+            .iter()
+            .enumerate()
+            .map(|(class, count)| count << class)
+            .sum();
+
+        let huge_bytes = huge_bytes.max(0) as usize;
+
+        HeapStats {
+            slab_bytes,
+            huge_bytes,
+            peak_bytes: self
+                .peak
+                .load(Ordering::Relaxed)
+                .max(slab_bytes + huge_bytes),
+            outstanding,
+        }
+    }
+}
@@ -1247,4 +1247,37 @@ mod tests {
 
         assert_eq!(smalloc.iter_free().count(), 0);
     }
+
+    #[test]
+    fn iter_free_two_banks_with_reservations() {
+        let mut smalloc = get_smalloc();
+
+        // Two banks, listed out of order, with the second given as two
+        // overlapping ranges.
+        smalloc
+            .add_memory(PhysMemoryRegion::new(PA::from_value(0x8000), 0x2000)) // 0x8000-0xA000
+            .unwrap();
+        smalloc
+            .add_memory(PhysMemoryRegion::new(PA::from_value(0x1000), 0x1000)) // 0x1000-0x2000
+            .unwrap();
+        smalloc
+            .add_memory(PhysMemoryRegion::new(PA::from_value(0x9000), 0x2000)) // 0x9000-0xB000
+            .unwrap();
+
+        // A memory reservation block entry and a /reserved-memory region.
+        smalloc
+            .add_reservation(PhysMemoryRegion::new(PA::from_value(0x1000), 0x400)) // 0x1000-0x1400
+            .unwrap();
+        smalloc
+            .add_reservation(PhysMemoryRegion::new(PA::from_value(0x9000), 0x1000)) // 0x9000-0xA000
+            .unwrap();
+
+        assert_eq!(smalloc.memory.count, 2);
+
+        let free_regions: Vec<_> = smalloc.iter_free().collect();
+        assert_eq!(free_regions.len(), 3);
+        check_region(&free_regions[0], 0x1400, 0xC00);
+        check_region(&free_regions[1], 0x8000, 0x1000);
+        check_region(&free_regions[2], 0xA000, 0x1000);
+    }
 }
@@ -6,7 +6,7 @@ use super::{
 };
 use crate::{
     CpuOps,
-    error::Result,
+    error::{KernelError, Result},
     memory::{
         PAGE_SIZE,
         address::{PA, VA},
@@ -69,11 +69,22 @@ impl<A: CpuOps, G: PageAllocGetter<A>, T: AddressTranslator<()>> Display for Cla
     }
 }
 
+/// Cloning a `ClaimedPage` takes another reference to the same physical page,
+/// rather than copying it.
+impl<A: CpuOps, G: PageAllocGetter<A>, T: AddressTranslator<()>> Clone for ClaimedPage<A, G, T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone(), PhantomData, PhantomData)
+    }
+}
+
 impl<A: CpuOps, G: PageAllocGetter<A>, T: AddressTranslator<()>> ClaimedPage<A, G, T> {
     /// Allocates a single physical page. The contents of the page are
     /// undefined.
     fn alloc() -> Result<Self> {
-        let frame = G::global_page_alloc().alloc_frames(0)?;
+        let frame = match G::global_page_alloc().alloc_frames(0) {
+            Err(KernelError::NoMemory) if G::reclaim() => G::global_page_alloc().alloc_frames(0)?,
+            res => res?,
+        };
         Ok(Self(frame, PhantomData, PhantomData))
     }
 
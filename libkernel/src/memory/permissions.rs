@@ -114,6 +114,12 @@ impl PtePermissions {
         self.execute
     }
 
+    /// Returns `true` if the mapping is both writable and executable, which
+    /// W^X forbids.
+    pub const fn is_write_exec(&self) -> bool {
+        self.write && self.execute
+    }
+
     /// Returns `true` if the mapping is accessible from user space.
     pub const fn is_user(&self) -> bool {
         self.user
@@ -237,6 +243,16 @@ mod tests {
         assert!(!p.is_cow());
     }
 
+    #[test]
+    fn test_write_exec() {
+        assert!(PtePermissions::rwx(false).is_write_exec());
+        assert!(!PtePermissions::rw(true).is_write_exec());
+        assert!(!PtePermissions::rx(true).is_write_exec());
+
+        // A CoW mapping isn't writable until it's been copied.
+        assert!(!PtePermissions::rwx(true).into_cow().is_write_exec());
+    }
+
     #[test]
     fn test_cow_transition() {
         let p_rw = PtePermissions::rw(true);
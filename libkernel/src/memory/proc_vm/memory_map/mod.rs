@@ -168,6 +168,9 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
         self.unmap_region(range.align_to_page_boundary(), None)
     }
 
+    /// Changes the permissions of `protect_region`, splitting and merging VMAs
+    /// as needed. The region may span several VMAs, but every page of it must
+    /// be mapped.
     pub fn mprotect(
         &mut self,
         protect_region: VirtMemoryRegion,
@@ -181,52 +184,76 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
             return Err(KernelError::InvalidValue);
         }
 
-        let affected_vma_addr = self
-            .find_vma(protect_region.start_address())
-            .map(|x| x.region.start_address())
-            .ok_or(KernelError::NoMemory)?;
+        // Reject W^X violations up front, as the address space would refuse
+        // them only after the VMAs had been changed.
+        if new_perms.write && new_perms.execute {
+            return Err(KernelError::InvalidValue);
+        }
 
-        let affected_vma = self
-            .vmas
-            .remove(&affected_vma_addr)
-            .expect("Should have the same key as the start address");
+        // Find the VMAs covering the region, making sure there are no holes and
+        // nothing sealed before anything is changed.
+        let mut covering = Vec::new();
+        let mut cursor = protect_region.start_address();
 
-        // Easy case, the entire VMA is changing.
-        if affected_vma.region == protect_region {
-            let old_vma = affected_vma.clone();
-            let mut new_vma = old_vma.clone();
-            new_vma.permissions = new_perms;
+        while cursor < protect_region.end_address() {
+            let vma = self.find_vma(cursor).ok_or(KernelError::NoMemory)?;
 
-            self.insert_and_merge(new_vma.clone());
-            self.address_space
-                .protect_range(protect_region, new_perms.into())?;
+            if vma.is_sealed() {
+                return Err(KernelError::NotPermitted);
+            }
 
-            return Ok(());
+            covering.push(vma.region.start_address());
+            cursor = vma.region.end_address();
         }
 
-        // Next case, a sub-region of a VMA is changing, requring a split.
-        if affected_vma.region.contains(protect_region) {
-            let (left, right) = affected_vma.region.punch_hole(protect_region);
-            let mut new_vma = affected_vma.clone().shrink_to(protect_region);
-            new_vma.permissions = new_perms;
+        // Take them all out first, so that re-inserting one can't merge it into
+        // another that's still to be processed.
+        let covering: Vec<_> = covering
+            .into_iter()
+            .map(|addr| {
+                self.vmas
+                    .remove(&addr)
+                    .expect("Should have the same key as the start address")
+            })
+            .collect();
+
+        for vma in covering {
+            let region = vma
+                .region
+                .intersection(protect_region)
+                .expect("Covering VMA should overlap the region");
+
+            self.protect_vma(vma, region, new_perms)?;
+        }
 
-            if let Some(left) = left {
-                self.insert_and_merge(affected_vma.shrink_to(left));
-            }
+        Ok(())
+    }
 
-            self.address_space
-                .protect_range(protect_region, new_perms.into())?;
-            self.insert_and_merge(new_vma);
+    /// Applies `new_perms` to `region` of a VMA that has been removed from the
+    /// map, splitting off and re-inserting the parts outside `region`.
+    fn protect_vma(
+        &mut self,
+        vma: VMArea,
+        region: VirtMemoryRegion,
+        new_perms: VMAPermissions,
+    ) -> Result<()> {
+        let (left, right) = vma.region.punch_hole(region);
+        let mut new_vma = vma.shrink_to(region);
+        new_vma.permissions = new_perms;
 
-            if let Some(right) = right {
-                self.insert_and_merge(affected_vma.shrink_to(right));
-            }
+        if let Some(left) = left {
+            self.insert_and_merge(vma.shrink_to(left));
+        }
 
-            return Ok(());
+        self.address_space
+            .protect_range(region, new_vma.pte_permissions())?;
+        self.insert_and_merge(new_vma);
+
+        if let Some(right) = right {
+            self.insert_and_merge(vma.shrink_to(right));
         }
 
-        // TODO: protecting over contiguous VMAreas.
-        Err(KernelError::NoMemory)
+        Ok(())
     }
 
     /// Checks if a given virtual memory region is completely free.
@@ -471,17 +498,18 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
     }
 
     /// Attempts to clone this memory map, sharing any already-mapped writable
-    /// pages as CoW pages. If the VMA isn't writable, the ref count is
-    /// incremented.
+    /// pages as CoW pages. If the VMA isn't writable, or is a shared mapping,
+    /// the ref count is incremented.
     pub fn clone_as_cow(&mut self) -> Result<Self> {
         let mut new_as = AS::new()?;
         let new_vmas = self.vmas.clone();
 
         for vma in new_vmas.values() {
-            let mut pte_perms = PtePermissions::from(vma.permissions);
+            let mut pte_perms = vma.pte_permissions();
 
-            // Mark all writable pages as CoW.
-            if pte_perms.is_write() {
+            // Mark all writable pages as CoW, except those of shared mappings
+            // which both maps must keep writing to.
+            if pte_perms.is_write() && !vma.is_shared() {
                 pte_perms = pte_perms.into_cow();
             }
 
@@ -498,10 +526,25 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
         })
     }
 
+    /// Tears down the map's address space. See [`UserAddressSpace::destroy`].
+    pub fn destroy(self) {
+        self.address_space.destroy();
+    }
+
     pub fn address_space_mut(&mut self) -> &mut AS {
         &mut self.address_space
     }
 
+    /// The total size of every VMA, in bytes.
+    pub fn mapped_bytes(&self) -> usize {
+        self.vmas.values().map(|vma| vma.region.size()).sum()
+    }
+
+    /// See [`UserAddressSpace::resident_pages`].
+    pub fn resident_pages(&self) -> usize {
+        self.address_space.resident_pages()
+    }
+
     pub fn vma_count(&self) -> usize {
         self.vmas.len()
     }
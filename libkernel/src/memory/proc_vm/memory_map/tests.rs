@@ -1,7 +1,7 @@
 use super::MemoryMap;
 use crate::{
     PageInfo, UserAddressSpace,
-    error::Result,
+    error::{KernelError, Result},
     fs::Inode,
     memory::{
         PAGE_SIZE,
@@ -48,6 +48,8 @@ impl UserAddressSpace for MockAddressSpace {
         unimplemented!()
     }
 
+    fn destroy(self) {}
+
     fn map_page(&mut self, _page: PageFrame, _va: VA, _perms: PtePermissions) -> Result<()> {
         panic!("Should be called by the demand-pager");
     }
@@ -85,6 +87,14 @@ impl UserAddressSpace for MockAddressSpace {
         None
     }
 
+    fn mark_accessed(&mut self, _va: VA) -> Result<()> {
+        unreachable!("Not called")
+    }
+
+    fn resident_pages(&self) -> usize {
+        0
+    }
+
     fn protect_and_clone_region(
         &mut self,
         _region: VirtMemoryRegion,
@@ -126,6 +136,7 @@ fn create_file_vma(
             file: inode,
             offset,
             len: size as u64,
+            shared: false,
         }),
         perms,
     )
@@ -875,6 +886,26 @@ fn test_mprotect_file_backed_split() {
     assert_ops_log_protect(&pvm, region, VMAPermissions::ro());
 }
 
+#[test]
+fn test_mprotect_shared_file_stays_read_only() {
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
+    let start = 0x48000;
+    let region = VirtMemoryRegion::new(VA::from_value(start), PAGE_SIZE);
+
+    pvm.insert_and_merge(VMArea::new(
+        region,
+        VMAreaKind::new_shared_file(new_inode(), 0, PAGE_SIZE as u64),
+        VMAPermissions::ro(),
+    ));
+
+    pvm.mprotect(region, VMAPermissions::rw()).unwrap();
+
+    // The VMA becomes writable, but its pages don't until they're written, so
+    // that the write is noticed.
+    assert_vma_perms(&pvm, start, VMAPermissions::rw());
+    assert_ops_log_protect(&pvm, region, VMAPermissions::ro());
+}
+
 #[test]
 fn test_mprotect_merge_restoration() {
     // Ensures that if we split permissions, then restore them, the VMAs
@@ -902,3 +933,104 @@ fn test_mprotect_merge_restoration() {
     assert_vma_exists(&pvm, start, size);
     assert_vma_perms(&pvm, start, VMAPermissions::rw());
 }
+
+#[test]
+fn test_mprotect_sealed_vma() {
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
+    let start = 0x60000;
+
+    let mut sealed = create_anon_vma(start, PAGE_SIZE, VMAPermissions::ro());
+    sealed.seal();
+    pvm.insert_and_merge(sealed);
+
+    // A neighbouring VMA with the same permissions must not absorb it.
+    pvm.insert_and_merge(create_anon_vma(
+        start + PAGE_SIZE,
+        PAGE_SIZE,
+        VMAPermissions::ro(),
+    ));
+    assert_eq!(pvm.vmas.len(), 2);
+
+    let region = VirtMemoryRegion::new(VA::from_value(start), PAGE_SIZE);
+    assert!(matches!(
+        pvm.mprotect(region, VMAPermissions::rw()),
+        Err(KernelError::NotPermitted)
+    ));
+
+    assert_vma_perms(&pvm, start, VMAPermissions::ro());
+    assert!(pvm.address_space.ops_log.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_mprotect_rejects_write_exec() {
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
+    let start = 0x70000;
+
+    pvm.insert_and_merge(create_anon_vma(start, PAGE_SIZE, VMAPermissions::rw()));
+
+    let region = VirtMemoryRegion::new(VA::from_value(start), PAGE_SIZE);
+    let wx = VMAPermissions {
+        read: true,
+        write: true,
+        execute: true,
+    };
+
+    assert!(matches!(
+        pvm.mprotect(region, wx),
+        Err(KernelError::InvalidValue)
+    ));
+
+    assert_vma_perms(&pvm, start, VMAPermissions::rw());
+    assert!(pvm.address_space.ops_log.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_mprotect_across_vmas() {
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
+    let start = 0x80000;
+
+    pvm.insert_and_merge(create_anon_vma(start, 2 * PAGE_SIZE, VMAPermissions::rw()));
+    pvm.insert_and_merge(create_anon_vma(
+        start + 2 * PAGE_SIZE,
+        2 * PAGE_SIZE,
+        VMAPermissions::rx(),
+    ));
+    assert_eq!(pvm.vmas.len(), 2);
+
+    // Cover the tail of the first VMA and the head of the second.
+    let region = VirtMemoryRegion::new(VA::from_value(start + PAGE_SIZE), 2 * PAGE_SIZE);
+    pvm.mprotect(region, VMAPermissions::ro()).unwrap();
+
+    assert_eq!(pvm.vmas.len(), 3);
+    assert_vma_exists(&pvm, start, PAGE_SIZE);
+    assert_vma_perms(&pvm, start, VMAPermissions::rw());
+    assert_vma_exists(&pvm, start + PAGE_SIZE, 2 * PAGE_SIZE);
+    assert_vma_perms(&pvm, start + PAGE_SIZE, VMAPermissions::ro());
+    assert_vma_exists(&pvm, start + 3 * PAGE_SIZE, PAGE_SIZE);
+    assert_vma_perms(&pvm, start + 3 * PAGE_SIZE, VMAPermissions::rx());
+}
+
+#[test]
+fn test_mprotect_over_hole() {
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
+    let start = 0x90000;
+
+    pvm.insert_and_merge(create_anon_vma(start, PAGE_SIZE, VMAPermissions::rw()));
+    pvm.insert_and_merge(create_anon_vma(
+        start + 2 * PAGE_SIZE,
+        PAGE_SIZE,
+        VMAPermissions::rw(),
+    ));
+
+    let region = VirtMemoryRegion::new(VA::from_value(start), 3 * PAGE_SIZE);
+    assert!(matches!(
+        pvm.mprotect(region, VMAPermissions::ro()),
+        Err(KernelError::NoMemory)
+    ));
+
+    // Nothing is changed.
+    assert_eq!(pvm.vmas.len(), 2);
+    assert_vma_perms(&pvm, start, VMAPermissions::rw());
+    assert_vma_perms(&pvm, start + 2 * PAGE_SIZE, VMAPermissions::rw());
+    assert!(pvm.address_space.ops_log.lock().unwrap().is_empty());
+}
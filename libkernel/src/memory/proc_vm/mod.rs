@@ -4,11 +4,11 @@ use crate::{
     UserAddressSpace,
     error::{KernelError, Result},
 };
-use alloc::string::ToString;
+use alloc::{string::ToString, vec::Vec};
 use memory_map::{AddressRequest, MemoryMap};
 use vmarea::{AccessKind, FaultValidation, VMAPermissions, VMArea, VMAreaKind};
 
-use super::{PAGE_SIZE, address::VA, region::VirtMemoryRegion};
+use super::{PAGE_SIZE, address::VA, page::PageFrame, region::VirtMemoryRegion};
 
 pub mod memory_map;
 pub mod vmarea;
@@ -49,21 +49,13 @@ impl<AS: UserAddressSpace> ProcessVM<AS> {
         Ok(Self { mm, brk })
     }
 
-    pub fn from_map(map: MemoryMap<AS>) -> Self {
-        // Last entry will be the VMA with the highest address.
-        let brk = map
-            .vmas
-            .last_key_value()
-            .expect("No VMAs in map")
-            .1
-            .region
-            .end_address()
-            // VMAs should already be page-aligned, but just in case.
-            .align_up(PAGE_SIZE);
-
+    /// Constructs a new Process VM structure from an existing memory map. The
+    /// heap starts, empty, at `brk_start`; typically the end of the program's
+    /// highest loaded segment.
+    pub fn from_map(map: MemoryMap<AS>, brk_start: VA) -> Self {
         Self {
             mm: map,
-            brk: VirtMemoryRegion::new(brk, 0),
+            brk: VirtMemoryRegion::new(brk_start.align_up(PAGE_SIZE), 0),
         }
     }
 
@@ -84,10 +76,27 @@ impl<AS: UserAddressSpace> ProcessVM<AS> {
         }
     }
 
+    /// Tears down the process's address space. See
+    /// [`UserAddressSpace::destroy`].
+    pub fn destroy(self) {
+        self.mm.destroy();
+    }
+
     pub fn mm_mut(&mut self) -> &mut MemoryMap<AS> {
         &mut self.mm
     }
 
+    /// The size of the process's address space: the bytes covered by its VMAs,
+    /// whether or not they're backed yet.
+    pub fn mapped_bytes(&self) -> usize {
+        self.mm.mapped_bytes()
+    }
+
+    /// The number of the process's pages that are mapped.
+    pub fn resident_pages(&self) -> usize {
+        self.mm.resident_pages()
+    }
+
     pub fn current_brk(&self) -> VA {
         self.brk.end_address()
     }
@@ -101,11 +110,13 @@ impl<AS: UserAddressSpace> ProcessVM<AS> {
     /// * `new_end_addr`: The desired new end address for the program break.
     ///
     /// # Returns
-    /// * `Ok(())` on success.
+    /// * `Ok((brk, pages))` on success, where `pages` are the frames that were
+    ///   unmapped by shrinking the break. The caller is responsible for freeing
+    ///   them.
     /// * `Err(KernelError)` on failure. This can happen if the requested memory
     ///   region conflicts with an existing mapping, or if the request is invalid
     ///   (e.g., shrinking the break below its initial start address).
-    pub fn resize_brk(&mut self, new_end_addr: VA) -> Result<VA> {
+    pub fn resize_brk(&mut self, new_end_addr: VA) -> Result<(VA, Vec<PageFrame>)> {
         let brk_start = self.brk.start_address();
         let current_end = self.brk.end_address();
 
@@ -123,7 +134,7 @@ impl<AS: UserAddressSpace> ProcessVM<AS> {
         if new_end_addr_aligned == current_end {
             // The requested break is the same as the current one, or it is
             // within the same page as the existing allocation. This is a no-op.
-            return Ok(new_end_addr);
+            return Ok((new_end_addr, Vec::new()));
         }
 
         // Grow the break
@@ -143,18 +154,18 @@ impl<AS: UserAddressSpace> ProcessVM<AS> {
 
             self.brk = new_brk_region;
 
-            return Ok(new_end_addr);
+            return Ok((new_end_addr, Vec::new()));
         }
 
         // Shrink the break
         // At this point, we know `new_end_aligned < current_end`.
         let unmap_region =
             VirtMemoryRegion::from_start_end_address(new_end_addr_aligned, current_end);
-        self.mm.munmap(unmap_region)?;
+        let pages = self.mm.munmap(unmap_region)?;
 
         self.brk = new_brk_region;
 
-        Ok(new_end_addr)
+        Ok((new_end_addr, pages))
     }
 
     pub fn clone_as_cow(&mut self) -> Result<Self> {
@@ -177,6 +188,7 @@ mod tests {
             kind: VMAreaKind::Anon, // Simplification for test
             permissions: VMAPermissions::rx(),
             name: String::new(),
+            sealed: false,
         };
 
         ProcessVM::from_vma(text_vma).unwrap()
@@ -203,7 +215,7 @@ mod tests {
         let initial_brk_start = vm.brk.start_address();
         let brk_addr = initial_brk_start.add_bytes(1);
 
-        let new_brk = vm.resize_brk(brk_addr).unwrap();
+        let new_brk = vm.resize_brk(brk_addr).unwrap().0;
 
         // The new break should be page-aligned
         let expected_brk_end = brk_addr.align_up(PAGE_SIZE);
@@ -230,7 +242,7 @@ mod tests {
         assert_eq!(vm.brk.size(), PAGE_SIZE);
 
         // When: we grow the break again
-        let new_brk = vm.resize_brk(vm.current_brk().add_pages(1)).unwrap();
+        let new_brk = vm.resize_brk(vm.current_brk().add_pages(1)).unwrap().0;
 
         // Then: the break should be extended
         let expected_brk_end = initial_brk_start.add_pages(2);
@@ -254,7 +266,7 @@ mod tests {
 
         // When: we shrink the break by one page
         let new_brk_addr = initial_brk_start.add_pages(2);
-        let new_brk = vm.resize_brk(new_brk_addr).unwrap();
+        let new_brk = vm.resize_brk(new_brk_addr).unwrap().0;
 
         // Then: the break should be updated
         assert_eq!(new_brk, new_brk_addr);
@@ -275,7 +287,7 @@ mod tests {
         vm.resize_brk(initial_brk_start.add_pages(2)).unwrap();
 
         // When: we shrink the break all the way back to its start
-        let new_brk = vm.resize_brk(initial_brk_start).unwrap();
+        let new_brk = vm.resize_brk(initial_brk_start).unwrap().0;
 
         // Then: the break should be zero-sized again
         assert_eq!(new_brk, initial_brk_start);
@@ -292,10 +304,10 @@ mod tests {
         // Given: a VM with a 2-page heap
         let mut vm = setup_vm();
         let initial_brk_start = vm.brk.start_address();
-        let current_brk_end = vm.resize_brk(initial_brk_start.add_pages(2)).unwrap();
+        let current_brk_end = vm.resize_brk(initial_brk_start.add_pages(2)).unwrap().0;
 
         // When: we resize the break to its current end
-        let new_brk = vm.resize_brk(current_brk_end).unwrap();
+        let new_brk = vm.resize_brk(current_brk_end).unwrap().0;
 
         // Then: nothing should change
         assert_eq!(new_brk, current_brk_end);
@@ -333,6 +345,7 @@ mod tests {
             kind: VMAreaKind::Anon,
             permissions: VMAPermissions::ro(),
             name: String::new(),
+            sealed: false,
         };
         vm.mm.insert_and_merge(obstacle_vma);
         assert_eq!(vm.mm.vma_count(), 2);
@@ -13,7 +13,9 @@ use core::cmp;
 
 use crate::{
     fs::{Inode, InodeId},
-    memory::{PAGE_MASK, PAGE_SIZE, address::VA, region::VirtMemoryRegion},
+    memory::{
+        PAGE_MASK, PAGE_SIZE, address::VA, permissions::PtePermissions, region::VirtMemoryRegion,
+    },
 };
 use alloc::string::{String, ToString};
 use alloc::sync::Arc;
@@ -111,11 +113,15 @@ pub struct VMFileMapping {
     pub(super) file: Arc<dyn Inode>,
     pub(super) offset: u64,
     pub(super) len: u64,
+    pub(super) shared: bool,
 }
 
 impl PartialEq for VMFileMapping {
     fn eq(&self, other: &Self) -> bool {
-        Arc::ptr_eq(&self.file, &other.file) && self.offset == other.offset && self.len == other.len
+        Arc::ptr_eq(&self.file, &other.file)
+            && self.offset == other.offset
+            && self.len == other.len
+            && self.shared == other.shared
     }
 }
 
@@ -134,6 +140,12 @@ impl VMFileMapping {
     pub fn file_len(&self) -> u64 {
         self.len
     }
+
+    /// Returns true if the mapping shares the file's pages (`MAP_SHARED`),
+    /// rather than taking a private copy of them.
+    pub fn is_shared(&self) -> bool {
+        self.shared
+    }
 }
 
 /// Defines the backing source for a `VMArea`.
@@ -161,7 +173,23 @@ impl VMAreaKind {
     }
 
     pub fn new_file(file: Arc<dyn Inode>, offset: u64, len: u64) -> Self {
-        Self::File(VMFileMapping { file, offset, len })
+        Self::File(VMFileMapping {
+            file,
+            offset,
+            len,
+            shared: false,
+        })
+    }
+
+    /// Creates a mapping of a file whose pages are shared with every other
+    /// shared mapping of the file, so writes to them are seen by all.
+    pub fn new_shared_file(file: Arc<dyn Inode>, offset: u64, len: u64) -> Self {
+        Self::File(VMFileMapping {
+            file,
+            offset,
+            len,
+            shared: true,
+        })
     }
 }
 
@@ -177,6 +205,7 @@ pub struct VMArea {
     pub(super) name: String,
     pub(super) kind: VMAreaKind,
     pub(super) permissions: VMAPermissions,
+    pub(super) sealed: bool,
 }
 
 impl VMArea {
@@ -192,6 +221,7 @@ impl VMArea {
             kind,
             permissions,
             name: String::new(),
+            sealed: false,
         }
     }
 
@@ -251,9 +281,11 @@ impl VMArea {
                 file: f,
                 offset: hdr.p_offset(endian) - mappable_region.offset() as u64,
                 len: hdr.p_filesz(endian) + mappable_region.offset() as u64,
+                shared: false,
             }),
             permissions,
             name: String::new(),
+            sealed: false,
         }
     }
 
@@ -375,6 +407,22 @@ impl VMArea {
         self.permissions
     }
 
+    /// Returns the permissions to give pages that are already mapped when the
+    /// VMA's permissions change, or it is cloned.
+    ///
+    /// Pages of shared mappings are never made writable here, so that the
+    /// first write to each faults and can be tracked to write the page back
+    /// to the file.
+    pub fn pte_permissions(&self) -> PtePermissions {
+        let mut permissions = self.permissions;
+
+        if self.is_shared() {
+            permissions.write = false;
+        }
+
+        permissions.into()
+    }
+
     pub fn contains_address(&self, addr: VA) -> bool {
         self.region.contains_address(addr)
     }
@@ -385,7 +433,7 @@ impl VMArea {
     /// Merging is possible if permissions are identical and the backing storage
     /// is of a compatible and contiguous nature.
     pub(super) fn can_merge_with(&self, other: &VMArea) -> bool {
-        if self.permissions != other.permissions {
+        if self.permissions != other.permissions || self.sealed || other.sealed {
             return false;
         }
 
@@ -401,7 +449,7 @@ impl VMArea {
                 let contiguous_offset =
                     other_map.offset == self_map.offset + self.region.size() as u64;
 
-                same_file && contiguous_offset
+                same_file && contiguous_offset && self_map.shared == other_map.shared
             }
 
             _ => false,
@@ -417,12 +465,28 @@ impl VMArea {
         clone
     }
 
+    /// Seals the VMA, such that its permissions can no longer be changed with
+    /// `mprotect`. Used for kernel-provided pages which userspace must never
+    /// be able to write to.
+    pub fn seal(&mut self) {
+        self.sealed = true;
+    }
+
+    pub fn is_sealed(&self) -> bool {
+        self.sealed
+    }
+
     /// Returns true if the VMA is backed by a file. False if it's an anonymous
     /// mapping.
     pub fn is_file_backed(&self) -> bool {
         matches!(self.kind, VMAreaKind::File(_))
     }
 
+    /// Returns true if the VMA is a shared mapping of a file.
+    pub fn is_shared(&self) -> bool {
+        matches!(self.kind, VMAreaKind::File(ref mapping) if mapping.shared)
+    }
+
     /// Shrink this VMA's region to `new_region`, recalculating file offsets,
     /// for file mappings.
     #[must_use]
@@ -450,6 +514,7 @@ impl VMArea {
                         file: vmfile_mapping.file.clone(),
                         offset: vmfile_mapping.offset + start_offset as u64,
                         len: new_sz,
+                        shared: vmfile_mapping.shared,
                     });
                 }
 
@@ -508,6 +573,7 @@ pub mod tests {
                 file: dummy_inode,
                 offset: file_offset,
                 len: filesz,
+                shared: false,
             }),
             VMAPermissions::rw(),
         )
@@ -36,6 +36,7 @@ pub struct CondVar<S, C: CpuOps> {
 
 impl<S, C: CpuOps> CondVar<S, C> {
     /// Creates a new, empty wait queue, initialized with state `initial_state`.
+    #[cfg_attr(all(feature = "lockdep", debug_assertions), track_caller)]
     pub fn new(initial_state: S) -> Self {
         Self {
             inner: Arc::new(SpinLockIrq::new(CondVarInner::new(initial_state))),
@@ -0,0 +1,580 @@
+//! Lock-ordering validation.
+//!
+//! Every lock belongs to a class, keyed by the source location the lock was
+//! created at. Whenever a lock is acquired, an edge is recorded from the class
+//! of each lock already held to the class of the new one. An edge that would
+//! close a cycle in this graph means two code paths take the same locks in
+//! opposite orders, and could deadlock against each other, so the validator
+//! panics as soon as it sees one, whether or not the deadlock actually
+//! happened.
+//!
+//! Spinlocks are held by a CPU, which can't be switched away from while it
+//! holds one. Sleeping locks (mutexes and rwlocks) are held by a task, which
+//! may be suspended with one held and resumed on another CPU, so they're
+//! tracked per task. The scheduler says which task each CPU is running with
+//! [`switch_task`].
+//!
+//! Tracking is only compiled in with the `lockdep` feature in debug builds.
+//! Otherwise [`LockClassKey`] is zero-sized and all its hooks are empty.
+
+use crate::CpuOps;
+use core::panic::Location;
+use core::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
+#[cfg(all(feature = "lockdep", debug_assertions))]
+use core::sync::atomic::{AtomicU16, AtomicUsize};
+
+/// The most lock classes the validator can tell apart. Locks of any further
+/// classes go untracked.
+const MAX_CLASSES: usize = 512;
+
+const WORDS: usize = MAX_CLASSES / 64;
+
+/// A directed graph of the orders in which lock classes have been acquired.
+pub struct LockGraph {
+    classes: [AtomicPtr<Location<'static>>; MAX_CLASSES],
+    /// `edges[a]` has bit `b` set once a lock of class `b` has been taken
+    /// while one of class `a` was held.
+    edges: [[AtomicU64; WORDS]; MAX_CLASSES],
+}
+
+impl LockGraph {
+    pub const fn new() -> Self {
+        Self {
+            classes: [const { AtomicPtr::new(core::ptr::null_mut()) }; MAX_CLASSES],
+            edges: [const { [const { AtomicU64::new(0) }; WORDS] }; MAX_CLASSES],
+        }
+    }
+
+    /// Returns the index of the class created at `location`, registering it
+    /// if it hasn't been seen before. Returns `None` if the graph is full.
+    pub fn class(&self, location: &'static Location<'static>) -> Option<usize> {
+        // The same call site may be given more than one `Location` by the
+        // compiler, so classes are compared by value.
+        let hash = (location.line() as usize)
+            .wrapping_mul(31)
+            .wrapping_add(location.column() as usize);
+
+        for probe in 0..MAX_CLASSES {
+            let slot = &self.classes[(hash + probe) % MAX_CLASSES];
+            let new = location as *const _ as *mut _;
+
+            let existing = match slot.compare_exchange(
+                core::ptr::null_mut(),
+                new,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return Some((hash + probe) % MAX_CLASSES),
+                Err(existing) => existing,
+            };
+
+            // SAFETY: Slots only ever hold `&'static Location`s.
+            if unsafe { *existing == *location } {
+                return Some((hash + probe) % MAX_CLASSES);
+            }
+        }
+
+        None
+    }
+
+    /// Returns where the locks of class `class` are created.
+    pub fn location(&self, class: usize) -> Option<&'static Location<'static>> {
+        // SAFETY: Slots only ever hold null or `&'static Location`s.
+        unsafe { self.classes[class].load(Ordering::Acquire).as_ref() }
+    }
+
+    fn has_edge(&self, from: usize, to: usize) -> bool {
+        self.edges[from][to / 64].load(Ordering::Relaxed) & (1 << (to % 64)) != 0
+    }
+
+    /// Returns whether `to` can be reached from `from` by following edges.
+    fn reaches(&self, from: usize, to: usize) -> bool {
+        let mut visited = [0u64; WORDS];
+        let mut stack = [0u16; MAX_CLASSES];
+        let mut depth = 1;
+
+        stack[0] = from as u16;
+        visited[from / 64] |= 1 << (from % 64);
+
+        while depth > 0 {
+            depth -= 1;
+            let class = stack[depth] as usize;
+
+            if class == to {
+                return true;
+            }
+
+            for (word, edges) in self.edges[class].iter().enumerate() {
+                let mut next = edges.load(Ordering::Relaxed) & !visited[word];
+                visited[word] |= next;
+
+                while next != 0 {
+                    stack[depth] = (word * 64 + next.trailing_zeros() as usize) as u16;
+                    depth += 1;
+                    next &= next - 1;
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Records that a lock of class `to` was taken while one of class `from`
+    /// was held. Returns `false`, without recording anything, if `from` is
+    /// already known to be taken while `to` is held.
+    ///
+    /// Nesting locks of the same class isn't tracked.
+    pub fn add_edge(&self, from: usize, to: usize) -> bool {
+        if from == to || self.has_edge(from, to) {
+            return true;
+        }
+
+        if self.reaches(to, from) {
+            return false;
+        }
+
+        self.edges[from][to / 64].fetch_or(1 << (to % 64), Ordering::Relaxed);
+        true
+    }
+}
+
+#[cfg(all(feature = "lockdep", debug_assertions))]
+static GRAPH: LockGraph = LockGraph::new();
+
+/// The most CPUs whose held locks are tracked.
+#[cfg(all(feature = "lockdep", debug_assertions))]
+const MAX_CPUS: usize = 64;
+
+/// How deeply locks may be nested on one CPU before further ones go
+/// untracked.
+#[cfg(all(feature = "lockdep", debug_assertions))]
+const MAX_HELD: usize = 32;
+
+/// The most tasks whose held sleeping locks are tracked at once.
+#[cfg(all(feature = "lockdep", debug_assertions))]
+const MAX_TASKS: usize = 256;
+
+/// The classes of the locks a CPU or task holds, in the order they were taken.
+#[cfg(all(feature = "lockdep", debug_assertions))]
+struct HeldLocks {
+    depth: AtomicUsize,
+    classes: [AtomicU16; MAX_HELD],
+}
+
+#[cfg(all(feature = "lockdep", debug_assertions))]
+impl HeldLocks {
+    const fn new() -> Self {
+        Self {
+            depth: AtomicUsize::new(0),
+            classes: [const { AtomicU16::new(0) }; MAX_HELD],
+        }
+    }
+
+    /// Records an edge from each held class to `class`, returning the first
+    /// held class that `class` has previously been taken before.
+    fn conflict(&self, class: usize) -> Option<usize> {
+        let depth = self.depth.load(Ordering::Relaxed).min(MAX_HELD);
+
+        self.classes[..depth]
+            .iter()
+            .map(|held| held.load(Ordering::Relaxed) as usize)
+            .find(|&held| !GRAPH.add_edge(held, class))
+    }
+
+    fn push(&self, class: usize) {
+        let depth = self.depth.load(Ordering::Relaxed);
+
+        if let Some(slot) = self.classes.get(depth) {
+            slot.store(class as u16, Ordering::Relaxed);
+        }
+
+        self.depth.store(depth + 1, Ordering::Relaxed);
+    }
+
+    fn remove(&self, class: usize) {
+        let depth = self.depth.load(Ordering::Relaxed);
+
+        // Locks nested beyond what we track are assumed to be released first.
+        if depth > MAX_HELD {
+            self.depth.store(depth - 1, Ordering::Relaxed);
+            return;
+        }
+
+        // A lock released by a different holder from the one that took it
+        // won't be found.
+        let Some(pos) = self.classes[..depth]
+            .iter()
+            .rposition(|held| held.load(Ordering::Relaxed) as usize == class)
+        else {
+            return;
+        };
+
+        for i in pos..depth - 1 {
+            let next = self.classes[i + 1].load(Ordering::Relaxed);
+            self.classes[i].store(next, Ordering::Relaxed);
+        }
+
+        self.depth.store(depth - 1, Ordering::Relaxed);
+    }
+}
+
+/// The sleeping locks held by one task. `task` is zero while the slot is free.
+#[cfg(all(feature = "lockdep", debug_assertions))]
+struct TaskHeldLocks {
+    task: AtomicUsize,
+    locks: HeldLocks,
+}
+
+#[cfg(all(feature = "lockdep", debug_assertions))]
+static HELD: [HeldLocks; MAX_CPUS] = [const { HeldLocks::new() }; MAX_CPUS];
+
+#[cfg(all(feature = "lockdep", debug_assertions))]
+static TASK_HELD: [TaskHeldLocks; MAX_TASKS] = [const {
+    TaskHeldLocks {
+        task: AtomicUsize::new(0),
+        locks: HeldLocks::new(),
+    }
+}; MAX_TASKS];
+
+/// The task each CPU is running, as given to [`switch_task`].
+#[cfg(all(feature = "lockdep", debug_assertions))]
+static CURRENT_TASK: [AtomicUsize; MAX_CPUS] = [const { AtomicUsize::new(0) }; MAX_CPUS];
+
+/// Runs `f` on the calling CPU's held locks with interrupts masked, so that
+/// an interrupt handler taking a lock can't interleave with it.
+#[cfg(all(feature = "lockdep", debug_assertions))]
+fn with_held<CPU: CpuOps, R>(f: impl FnOnce(&HeldLocks) -> R) -> Option<R> {
+    let held = HELD.get(CPU::id())?;
+    let flags = CPU::disable_interrupts();
+    let ret = f(held);
+    CPU::restore_interrupt_state(flags);
+    Some(ret)
+}
+
+/// Runs `f` on the sleeping locks held by the task the calling CPU is running,
+/// with interrupts masked. A task holding none has no slot; one is claimed for
+/// it if `claim` is set, and freed again once it holds nothing.
+///
+/// A CPU that hasn't been told of a task tracks its sleeping locks alongside
+/// its spinlocks.
+#[cfg(all(feature = "lockdep", debug_assertions))]
+fn with_task_held<CPU: CpuOps, R>(claim: bool, f: impl FnOnce(&HeldLocks) -> R) -> Option<R> {
+    let task = CURRENT_TASK
+        .get(CPU::id())
+        .map_or(0, |task| task.load(Ordering::Relaxed));
+
+    if task == 0 {
+        return with_held::<CPU, _>(f);
+    }
+
+    let flags = CPU::disable_interrupts();
+
+    let slot = TASK_HELD
+        .iter()
+        .find(|slot| slot.task.load(Ordering::Relaxed) == task)
+        .or_else(|| {
+            claim
+                .then(|| {
+                    TASK_HELD.iter().find(|slot| {
+                        slot.task
+                            .compare_exchange(0, task, Ordering::Relaxed, Ordering::Relaxed)
+                            .is_ok()
+                    })
+                })
+                .flatten()
+        });
+
+    let ret = slot.map(|slot| {
+        let ret = f(&slot.locks);
+
+        if slot.locks.depth.load(Ordering::Relaxed) == 0 {
+            slot.task.store(0, Ordering::Relaxed);
+        }
+
+        ret
+    });
+
+    CPU::restore_interrupt_state(flags);
+    ret
+}
+
+/// Tells the validator that the calling CPU is now running `task`: any value
+/// unique to the task for as long as it lives, or zero for none. The sleeping
+/// locks taken from now on are charged to it.
+#[inline]
+pub fn switch_task<CPU: CpuOps>(_task: usize) {
+    #[cfg(all(feature = "lockdep", debug_assertions))]
+    if let Some(current) = CURRENT_TASK.get(CPU::id()) {
+        current.store(_task, Ordering::Relaxed);
+    }
+}
+
+/// Identifies the class a lock belongs to.
+#[derive(Clone, Copy)]
+pub struct LockClassKey {
+    #[cfg(all(feature = "lockdep", debug_assertions))]
+    location: Option<&'static Location<'static>>,
+}
+
+impl LockClassKey {
+    /// The class of locks created at the caller's location.
+    #[cfg_attr(all(feature = "lockdep", debug_assertions), track_caller)]
+    pub const fn here() -> Self {
+        Self {
+            #[cfg(all(feature = "lockdep", debug_assertions))]
+            location: Some(Location::caller()),
+        }
+    }
+
+    /// A key for locks that shouldn't be validated.
+    pub const fn untracked() -> Self {
+        Self {
+            #[cfg(all(feature = "lockdep", debug_assertions))]
+            location: None,
+        }
+    }
+
+    /// Checks that taking a lock of this class now, on this CPU and by the task
+    /// it's running, is consistent with every ordering seen so far. Call this
+    /// before waiting for the lock, so that a report is made before the
+    /// deadlock it predicts.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the CPU or task holds a lock that has previously been taken
+    /// while holding one of this class.
+    #[inline]
+    pub fn validate<CPU: CpuOps>(self) {
+        #[cfg(all(feature = "lockdep", debug_assertions))]
+        self.validate_tracked::<CPU>();
+    }
+
+    /// Records that the calling CPU now holds a lock of this class.
+    #[inline]
+    pub fn acquired<CPU: CpuOps>(self) {
+        #[cfg(all(feature = "lockdep", debug_assertions))]
+        self.acquired_tracked::<CPU>();
+    }
+
+    /// Records that the calling CPU has released a lock of this class. Locks
+    /// needn't be released in the order they were taken.
+    #[inline]
+    pub fn released<CPU: CpuOps>(self) {
+        #[cfg(all(feature = "lockdep", debug_assertions))]
+        self.released_tracked::<CPU>();
+    }
+
+    /// Records that the task running on the calling CPU now holds a sleeping
+    /// lock of this class.
+    #[inline]
+    pub fn acquired_sleeping<CPU: CpuOps>(self) {
+        #[cfg(all(feature = "lockdep", debug_assertions))]
+        if let Some(class) = self.class() {
+            with_task_held::<CPU, _>(true, |held| held.push(class));
+        }
+    }
+
+    /// Records that the task running on the calling CPU has released a
+    /// sleeping lock of this class.
+    #[inline]
+    pub fn released_sleeping<CPU: CpuOps>(self) {
+        #[cfg(all(feature = "lockdep", debug_assertions))]
+        if let Some(class) = self.class() {
+            with_task_held::<CPU, _>(false, |held| held.remove(class));
+        }
+    }
+}
+
+#[cfg(all(feature = "lockdep", debug_assertions))]
+impl LockClassKey {
+    fn class(self) -> Option<usize> {
+        GRAPH.class(self.location?)
+    }
+
+    fn validate_tracked<CPU: CpuOps>(self) {
+        let Some(class) = self.class() else {
+            return;
+        };
+
+        let conflict = with_held::<CPU, _>(|held| held.conflict(class))
+            .flatten()
+            .or_else(|| with_task_held::<CPU, _>(false, |held| held.conflict(class)).flatten());
+
+        if let Some(held) = conflict {
+            panic!(
+                "Lock ordering violation: lock created at {} taken while holding lock created at {}, which has previously been taken while holding it",
+                self.location.unwrap(),
+                GRAPH.location(held).unwrap(),
+            );
+        }
+    }
+
+    fn acquired_tracked<CPU: CpuOps>(self) {
+        if let Some(class) = self.class() {
+            with_held::<CPU, _>(|held| held.push(class));
+        }
+    }
+
+    fn released_tracked<CPU: CpuOps>(self) {
+        if let Some(class) = self.class() {
+            with_held::<CPU, _>(|held| held.remove(class));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location() -> &'static Location<'static> {
+        Location::caller()
+    }
+
+    #[test]
+    fn classes_are_keyed_by_location() {
+        let graph = LockGraph::new();
+        let a = graph.class(Location::caller()).unwrap();
+        let b = graph.class(location()).unwrap();
+
+        assert_ne!(a, b);
+        assert_eq!(graph.class(location()), Some(b));
+        assert_eq!(graph.location(b), Some(location()));
+    }
+
+    #[test]
+    fn reversed_order_is_reported() {
+        let graph = LockGraph::new();
+        let (a, b, c) = (1, 2, 3);
+
+        // A -> B, then B -> A.
+        assert!(graph.add_edge(a, b));
+        assert!(graph.add_edge(a, b));
+        assert!(!graph.add_edge(b, a));
+
+        // A -> B -> C, then C -> A.
+        assert!(graph.add_edge(b, c));
+        assert!(!graph.add_edge(c, a));
+
+        // Orders consistent with the graph are fine, as is nesting a class.
+        assert!(graph.add_edge(a, c));
+        assert!(graph.add_edge(c, c));
+    }
+
+    #[cfg(all(feature = "lockdep", debug_assertions))]
+    mod locks {
+        use super::super::{MAX_CPUS, switch_task};
+        use crate::CpuOps;
+        use crate::sync::mutex::{AsyncMutexGuard, Mutex};
+        use crate::sync::spinlock::SpinLockIrq;
+        use core::pin::pin;
+        use core::task::{Context, Poll, Waker};
+        use std::panic::{AssertUnwindSafe, catch_unwind};
+
+        /// A CPU of its own, so that locks held by other tests' threads
+        /// don't show up as held here.
+        struct LastCpu;
+
+        impl CpuOps for LastCpu {
+            fn id() -> usize {
+                MAX_CPUS - 1
+            }
+
+            fn halt() -> ! {
+                unimplemented!()
+            }
+
+            fn disable_interrupts() -> usize {
+                0
+            }
+
+            fn restore_interrupt_state(_flags: usize) {}
+
+            fn enable_interrupts() {}
+        }
+
+        /// Another CPU of its own, for tests that switch tasks on it.
+        struct SecondLastCpu;
+
+        impl CpuOps for SecondLastCpu {
+            fn id() -> usize {
+                MAX_CPUS - 2
+            }
+
+            fn halt() -> ! {
+                unimplemented!()
+            }
+
+            fn disable_interrupts() -> usize {
+                0
+            }
+
+            fn restore_interrupt_state(_flags: usize) {}
+
+            fn enable_interrupts() {}
+        }
+
+        fn lock(mutex: &Mutex<(), SecondLastCpu>) -> AsyncMutexGuard<'_, (), SecondLastCpu> {
+            let mut cx = Context::from_waker(Waker::noop());
+
+            let Poll::Ready(guard) = pin!(mutex.lock()).poll(&mut cx) else {
+                panic!("uncontended lock should succeed");
+            };
+
+            guard
+        }
+
+        #[test]
+        fn spinlock_order_inversion_is_reported() {
+            let a = SpinLockIrq::<_, LastCpu>::new(0);
+            let b = SpinLockIrq::<_, LastCpu>::new(0);
+
+            {
+                let _a = a.lock_save_irq();
+                let _b = b.lock_save_irq();
+            }
+
+            // Taking them in the same order again is fine.
+            {
+                let _a = a.lock_save_irq();
+                let _b = b.lock_save_irq();
+            }
+
+            let result = catch_unwind(AssertUnwindSafe(|| {
+                let _b = b.lock_save_irq();
+                let _a = a.lock_save_irq();
+            }));
+
+            assert!(result.is_err());
+        }
+
+        #[test]
+        fn sleeping_locks_are_held_by_tasks() {
+            let a = Mutex::<_, SecondLastCpu>::new(());
+            let b = Mutex::<_, SecondLastCpu>::new(());
+
+            // One task takes B while another, switched away from on the same
+            // CPU, holds A. That doesn't order A before B.
+            switch_task::<SecondLastCpu>(1);
+            let guard_a = lock(&a);
+            switch_task::<SecondLastCpu>(2);
+            drop(lock(&b));
+            switch_task::<SecondLastCpu>(1);
+            drop(guard_a);
+
+            switch_task::<SecondLastCpu>(3);
+            {
+                let _b = lock(&b);
+                let _a = lock(&a);
+            }
+
+            let result = catch_unwind(AssertUnwindSafe(|| {
+                let _a = lock(&a);
+                let _b = lock(&b);
+            }));
+
+            switch_task::<SecondLastCpu>(0);
+
+            assert!(result.is_err());
+        }
+    }
+}
@@ -1,4 +1,5 @@
 pub mod condvar;
+pub mod lockdep;
 pub mod mpsc;
 pub mod mutex;
 pub mod once_lock;
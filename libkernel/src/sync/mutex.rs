@@ -1,17 +1,118 @@
 use alloc::collections::VecDeque;
 use core::cell::UnsafeCell;
-use core::future::Future;
+use core::future::{Future, poll_fn};
 use core::ops::{Deref, DerefMut};
-use core::pin::Pin;
+use core::pin::{Pin, pin};
 use core::task::{Context, Poll, Waker};
 
 use crate::CpuOps;
 
+use super::lockdep::LockClassKey;
 use super::spinlock::SpinLockIrq;
 
+struct Waiter {
+    ticket: u64,
+    waker: Waker,
+}
+
 struct MutexState {
     is_locked: bool,
-    waiters: VecDeque<Waker>,
+    /// Whether the lock is handed straight to the longest waiting task.
+    fair: bool,
+    /// The waiter the lock has been handed to, which has yet to notice.
+    handoff: Option<u64>,
+    next_ticket: u64,
+    waiters: VecDeque<Waiter>,
+}
+
+impl MutexState {
+    const fn new(fair: bool) -> Self {
+        Self {
+            is_locked: false,
+            fair,
+            handoff: None,
+            next_ticket: 0,
+            waiters: VecDeque::new(),
+        }
+    }
+
+    /// Releases the lock, or in fair mode passes it on to the head of the
+    /// queue, and wakes the next waiter.
+    fn unlock(&mut self) {
+        let Some(next) = self.waiters.pop_front() else {
+            self.is_locked = false;
+            return;
+        };
+
+        if self.fair {
+            self.handoff = Some(next.ticket);
+        } else {
+            self.is_locked = false;
+        }
+
+        next.waker.wake();
+    }
+
+    /// Tries to take the lock for the waiter holding `ticket`, queueing it if
+    /// the lock is busy.
+    fn poll_lock(&mut self, ticket: &mut Option<u64>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.handoff.is_some() && self.handoff == *ticket {
+            self.handoff = None;
+            *ticket = None;
+            return Poll::Ready(());
+        }
+
+        // In fair mode, a task may only take a free lock if nobody is queued.
+        if !self.is_locked && (!self.fair || self.waiters.is_empty()) {
+            if let Some(ticket) = ticket.take() {
+                self.waiters.retain(|waiter| waiter.ticket != ticket);
+            }
+            self.is_locked = true;
+            return Poll::Ready(());
+        }
+
+        match *ticket {
+            Some(ticket) => match self.waiters.iter_mut().find(|w| w.ticket == ticket) {
+                Some(waiter) => waiter.waker.clone_from(cx.waker()),
+                // We were woken by an unfair unlock but lost the race for the
+                // lock, so go to the back of the queue again.
+                None => self.waiters.push_back(Waiter {
+                    ticket,
+                    waker: cx.waker().clone(),
+                }),
+            },
+            None => {
+                let new = self.next_ticket;
+                self.next_ticket += 1;
+                *ticket = Some(new);
+                self.waiters.push_back(Waiter {
+                    ticket: new,
+                    waker: cx.waker().clone(),
+                });
+            }
+        }
+
+        Poll::Pending
+    }
+
+    /// Forgets the waiter holding `ticket`, whose future was dropped before it
+    /// got the lock. Anything handed to it is passed on, as is a wakeup it
+    /// never acted on.
+    fn cancel(&mut self, ticket: u64) {
+        let queued = self.waiters.len();
+        self.waiters.retain(|waiter| waiter.ticket != ticket);
+        let woken = self.waiters.len() == queued;
+
+        if self.handoff == Some(ticket) {
+            self.handoff = None;
+            self.unlock();
+        } else if woken && !self.fair && !self.is_locked {
+            // An unfair unlock woke this waiter, which will now never come
+            // back for the lock. Wake the next in its place, or it may wait
+            // forever.
+            self.unlock();
+        }
+    }
 }
 
 /// An asynchronous, mutex primitive.
@@ -19,8 +120,13 @@ struct MutexState {
 /// This mutex can be used to protect shared data across asynchronous tasks.
 /// `lock()` returns a future that resolves to a guard. When the guard is
 /// dropped, the lock is released.
+///
+/// By default, a released lock goes to whichever task gets to it first, even if
+/// others have been waiting longer. A mutex made with [`Mutex::new_fair`]
+/// instead hands the lock to its waiters in the order they arrived.
 pub struct Mutex<T: ?Sized, CPU: CpuOps> {
     state: SpinLockIrq<MutexState, CPU>,
+    class: LockClassKey,
     data: UnsafeCell<T>,
 }
 
@@ -34,18 +140,42 @@ pub struct AsyncMutexGuard<'a, T: ?Sized, CPU: CpuOps> {
 }
 
 /// A future that resolves to an `AsyncMutexGuard` when the lock is acquired.
+///
+/// Dropping the future gives up its place in the queue.
 pub struct MutexGuardFuture<'a, T: ?Sized, CPU: CpuOps> {
     mutex: &'a Mutex<T, CPU>,
+    ticket: Option<u64>,
 }
 
 impl<T, CPU: CpuOps> Mutex<T, CPU> {
-    /// Creates a new asynchronous mutex in an unlocked state.
+    /// Creates a new asynchronous mutex in an unlocked state. Its lock class is
+    /// keyed by the caller's location.
+    #[cfg_attr(all(feature = "lockdep", debug_assertions), track_caller)]
     pub const fn new(data: T) -> Self {
         Self {
-            state: SpinLockIrq::new(MutexState {
-                is_locked: false,
-                waiters: VecDeque::new(),
-            }),
+            state: SpinLockIrq::untracked(MutexState::new(false)),
+            class: LockClassKey::here(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Creates a new asynchronous mutex that lock-ordering validation ignores,
+    /// for the internals of locks that are validated in their own right.
+    pub(crate) const fn untracked(data: T) -> Self {
+        Self {
+            state: SpinLockIrq::untracked(MutexState::new(false)),
+            class: LockClassKey::untracked(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Creates a new asynchronous mutex in an unlocked state, which hands the
+    /// lock to waiting tasks in the order they started waiting.
+    #[cfg_attr(all(feature = "lockdep", debug_assertions), track_caller)]
+    pub const fn new_fair(data: T) -> Self {
+        Self {
+            state: SpinLockIrq::untracked(MutexState::new(true)),
+            class: LockClassKey::here(),
             data: UnsafeCell::new(data),
         }
     }
@@ -66,7 +196,67 @@ impl<T: ?Sized, CPU: CpuOps> Mutex<T, CPU> {
     /// be `.await`ed to acquire the lock. The lock is released when the
     /// returned `AsyncMutexGuard` is dropped.
     pub fn lock(&self) -> MutexGuardFuture<'_, T, CPU> {
-        MutexGuardFuture { mutex: self }
+        MutexGuardFuture {
+            mutex: self,
+            ticket: None,
+        }
+    }
+
+    /// Attempts to acquire the lock without waiting. Returns `None` if the lock
+    /// is held or, for a fair mutex, if other tasks are already waiting for it.
+    pub fn try_lock(&self) -> Option<AsyncMutexGuard<'_, T, CPU>> {
+        let mut state = self.state.lock_save_irq();
+
+        if state.is_locked || (state.fair && !state.waiters.is_empty()) {
+            return None;
+        }
+
+        state.is_locked = true;
+        self.class.acquired_sleeping::<CPU>();
+
+        Some(AsyncMutexGuard { mutex: self })
+    }
+
+    /// Acquires the lock, unless `timeout` completes first, in which case the
+    /// attempt is abandoned and `None` is returned.
+    pub async fn lock_or(
+        &self,
+        timeout: impl Future<Output = ()>,
+    ) -> Option<AsyncMutexGuard<'_, T, CPU>> {
+        let mut lock = pin!(self.lock());
+        let mut timeout = pin!(timeout);
+
+        poll_fn(|cx| {
+            if let Poll::Ready(guard) = lock.as_mut().poll(cx) {
+                return Poll::Ready(Some(guard));
+            }
+
+            timeout.as_mut().poll(cx).map(|()| None)
+        })
+        .await
+    }
+
+    fn poll_lock(&self, ticket: &mut Option<u64>, cx: &mut Context<'_>) -> Poll<()> {
+        self.class.validate::<CPU>();
+
+        let ret = self.state.lock_save_irq().poll_lock(ticket, cx);
+
+        if ret.is_ready() {
+            self.class.acquired_sleeping::<CPU>();
+        }
+
+        ret
+    }
+
+    fn cancel(&self, ticket: Option<u64>) {
+        if let Some(ticket) = ticket {
+            self.state.lock_save_irq().cancel(ticket);
+        }
+    }
+
+    fn unlock(&self) {
+        self.class.released_sleeping::<CPU>();
+        self.state.lock_save_irq().unlock();
     }
 
     /// Returns a mutable reference to the underlying data.
@@ -87,29 +277,23 @@ impl<'a, T: ?Sized, CPU: CpuOps> Future for MutexGuardFuture<'a, T, CPU> {
     type Output = AsyncMutexGuard<'a, T, CPU>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut state = self.mutex.state.lock_save_irq();
+        let this = self.get_mut();
 
-        if !state.is_locked {
-            state.is_locked = true;
-            Poll::Ready(AsyncMutexGuard { mutex: self.mutex })
-        } else {
-            if state.waiters.iter().all(|w| !w.will_wake(cx.waker())) {
-                state.waiters.push_back(cx.waker().clone());
-            }
-            Poll::Pending
-        }
+        this.mutex
+            .poll_lock(&mut this.ticket, cx)
+            .map(|()| AsyncMutexGuard { mutex: this.mutex })
     }
 }
 
-impl<T: ?Sized, CPU: CpuOps> Drop for AsyncMutexGuard<'_, T, CPU> {
+impl<T: ?Sized, CPU: CpuOps> Drop for MutexGuardFuture<'_, T, CPU> {
     fn drop(&mut self) {
-        let mut state = self.mutex.state.lock_save_irq();
-
-        if let Some(next_waker) = state.waiters.pop_front() {
-            next_waker.wake();
-        }
+        self.mutex.cancel(self.ticket);
+    }
+}
 
-        state.is_locked = false;
+impl<T: ?Sized, CPU: CpuOps> Drop for AsyncMutexGuard<'_, T, CPU> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
     }
 }
 
@@ -135,7 +319,10 @@ unsafe impl<T: ?Sized + Send, CPU: CpuOps> Sync for Mutex<T, CPU> {}
 impl<CPU: CpuOps> Mutex<(), CPU> {
     /// Acquires the mutex lock without caring about the data.
     pub(crate) fn acquire(&self) -> MutexAcquireFuture<'_, CPU> {
-        MutexAcquireFuture { mutex: self }
+        MutexAcquireFuture {
+            mutex: self,
+            ticket: None,
+        }
     }
 
     /// Releases the mutex lock without caring about the data.
@@ -143,35 +330,202 @@ impl<CPU: CpuOps> Mutex<(), CPU> {
     /// # Safety
     /// The caller must ensure that they have previously called [`Self::acquire()`].
     pub(crate) unsafe fn release(&self) {
-        let mut state = self.state.lock_save_irq();
-
-        if let Some(next_waker) = state.waiters.pop_front() {
-            next_waker.wake();
-        }
-
-        state.is_locked = false;
+        self.unlock();
     }
 }
 
 /// A future that resolves to a locked mutex
 pub struct MutexAcquireFuture<'a, CPU: CpuOps> {
     mutex: &'a Mutex<(), CPU>,
+    ticket: Option<u64>,
 }
 
 impl<CPU: CpuOps> Future for MutexAcquireFuture<'_, CPU> {
     type Output = ();
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut state = self.mutex.state.lock_save_irq();
+        let this = self.get_mut();
+        this.mutex.poll_lock(&mut this.ticket, cx)
+    }
+}
 
-        if !state.is_locked {
-            state.is_locked = true;
-            Poll::Ready(())
-        } else {
-            if state.waiters.iter().all(|w| !w.will_wake(cx.waker())) {
-                state.waiters.push_back(cx.waker().clone());
-            }
-            Poll::Pending
+impl<CPU: CpuOps> Drop for MutexAcquireFuture<'_, CPU> {
+    fn drop(&mut self) {
+        self.mutex.cancel(self.ticket);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::MockCpuOps;
+    use core::future::{pending, ready};
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::Wake;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn fair_mutex_hands_over_in_arrival_order() {
+        let mutex = Arc::new(Mutex::<Vec<usize>, MockCpuOps>::new_fair(Vec::new()));
+        let guard = mutex.lock().await;
+
+        let mut handles = Vec::new();
+        for i in 0..5 {
+            let mutex = mutex.clone();
+            handles.push(tokio::spawn(async move { mutex.lock().await.push(i) }));
+
+            // Let the task start waiting before the next one arrives.
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        drop(guard);
+
+        // A newcomer doesn't get in ahead of the tasks already waiting.
+        assert_eq!(*mutex.lock().await, [0, 1, 2, 3, 4]);
+
+        for handle in handles {
+            handle.await.unwrap();
+        }
+    }
+
+    #[test]
+    fn fair_mutex_survives_cancelled_waiters() {
+        let mutex = Mutex::<(), MockCpuOps>::new_fair(());
+        let mut cx = Context::from_waker(Waker::noop());
+
+        let guard = pin!(mutex.lock()).poll(&mut cx);
+        let Poll::Ready(guard) = guard else {
+            panic!("uncontended lock should succeed");
+        };
+
+        let mut first = Box::pin(mutex.lock());
+        let mut second = Box::pin(mutex.lock());
+        let mut third = Box::pin(mutex.lock());
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+        assert!(third.as_mut().poll(&mut cx).is_pending());
+
+        // Cancel a waiter in the middle of the queue...
+        drop(second);
+
+        // ...and one that the lock has already been handed to.
+        drop(guard);
+        drop(first);
+
+        // A newcomer must still wait its turn behind `third`.
+        let mut late = Box::pin(mutex.lock());
+        assert!(late.as_mut().poll(&mut cx).is_pending());
+
+        let Poll::Ready(guard) = third.as_mut().poll(&mut cx) else {
+            panic!("lock should have been handed to the third waiter");
+        };
+        drop(guard);
+
+        assert!(late.as_mut().poll(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn unfair_mutex_forgets_cancelled_waiters() {
+        let mutex = Mutex::<(), MockCpuOps>::new(());
+        let mut cx = Context::from_waker(Waker::noop());
+
+        let Poll::Ready(guard) = pin!(mutex.lock()).poll(&mut cx) else {
+            panic!("uncontended lock should succeed");
+        };
+
+        let mut waiter = Box::pin(mutex.lock());
+        assert!(waiter.as_mut().poll(&mut cx).is_pending());
+        drop(waiter);
+        drop(guard);
+
+        assert!(mutex.state.lock_save_irq().waiters.is_empty());
+        assert!(pin!(mutex.lock()).poll(&mut cx).is_ready());
+    }
+
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
         }
     }
+
+    #[test]
+    fn unfair_mutex_passes_on_a_dropped_wakeup() {
+        let mutex = Mutex::<(), MockCpuOps>::new(());
+        let mut cx = Context::from_waker(Waker::noop());
+        let wakes = Arc::new(CountingWaker::default());
+        let waker = Waker::from(wakes.clone());
+
+        let guard = mutex.try_lock().unwrap();
+        let mut first = Box::pin(mutex.lock());
+        let mut second = Box::pin(mutex.lock());
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+        assert!(
+            second
+                .as_mut()
+                .poll(&mut Context::from_waker(&waker))
+                .is_pending()
+        );
+
+        // The unlock wakes `first`, which is dropped without coming back for
+        // the lock...
+        drop(guard);
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 0);
+        drop(first);
+
+        // ...so `second` is woken in its place.
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 1);
+        assert!(second.as_mut().poll(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn try_lock_fails_while_contended() {
+        let mutex = Mutex::<u32, MockCpuOps>::new(0);
+
+        let mut guard = mutex.try_lock().unwrap();
+        *guard += 1;
+        assert!(mutex.try_lock().is_none());
+
+        drop(guard);
+        assert_eq!(*mutex.try_lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn fair_try_lock_respects_the_queue() {
+        let mutex = Mutex::<(), MockCpuOps>::new_fair(());
+        let mut cx = Context::from_waker(Waker::noop());
+
+        let guard = mutex.try_lock().unwrap();
+        let mut waiter = Box::pin(mutex.lock());
+        assert!(waiter.as_mut().poll(&mut cx).is_pending());
+        drop(guard);
+
+        // The lock now belongs to `waiter`.
+        assert!(mutex.try_lock().is_none());
+        assert!(waiter.as_mut().poll(&mut cx).is_ready());
+    }
+
+    #[tokio::test]
+    async fn lock_or_gives_up_on_timeout() {
+        let mutex = Mutex::<(), MockCpuOps>::new(());
+
+        assert!(mutex.lock_or(pending()).await.is_some());
+
+        let guard = mutex.lock().await;
+        assert!(mutex.lock_or(ready(())).await.is_none());
+        assert!(
+            mutex
+                .lock_or(tokio::time::sleep(Duration::from_millis(10)))
+                .await
+                .is_none()
+        );
+
+        // The abandoned attempts left nothing behind in the queue.
+        drop(guard);
+        assert!(mutex.state.lock_save_irq().waiters.is_empty());
+        assert!(mutex.lock_or(pending()).await.is_some());
+    }
 }
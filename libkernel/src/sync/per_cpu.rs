@@ -230,6 +230,34 @@ impl<T: Send + Sync, CPU: CpuOps> PerCpu<T, CPU> {
     }
 }
 
+/// Returns the current CPU's instance of `var`.
+///
+/// This doesn't touch the interrupt mask, so the value must tolerate being
+/// accessed from an interrupt handler on the same CPU (e.g. an atomic or a
+/// `Cell` that is only touched with interrupts masked).
+///
+/// # Panics
+/// Panics if `var` has not been initialized.
+pub fn this_cpu<T: Send, CPU: CpuOps>(var: &PerCpu<T, CPU>) -> &T {
+    var.get()
+}
+
+/// Mutably borrows the current CPU's instance of `var`.
+///
+/// Interrupts are masked until the returned guard is dropped, so neither an
+/// interrupt handler nor a migration to another CPU can observe the value
+/// half-updated.
+///
+/// # Panics
+/// Panics if `var` has not been initialized or is already borrowed on this
+/// CPU.
+#[track_caller]
+pub fn this_cpu_mut<T: Send, CPU: CpuOps>(
+    var: &PerCpu<RefCell<T>, CPU>,
+) -> IrqGuard<RefMut<'_, T>, CPU> {
+    var.borrow_mut()
+}
+
 // Implement the type-erased initializer trait.
 impl<T: Send, CPU: CpuOps> PerCpuInitializer for PerCpu<T, CPU> {
     fn init(&self, num_cpus: usize) {
@@ -392,6 +420,26 @@ mod tests {
         *data.borrow_mut() = 5;
     }
 
+    #[test]
+    fn test_this_cpu_counter_is_per_cpu() {
+        let counter: PerCpu<_, MockArch> = PerCpu::new(|| RefCell::new(0u64));
+        counter.init(2);
+
+        MOCK_CPU_ID.with(|id| id.set(0));
+        *this_cpu_mut(&counter) += 1;
+
+        MOCK_CPU_ID.with(|id| id.set(1));
+        for _ in 0..3 {
+            *this_cpu_mut(&counter) += 1;
+        }
+        assert_eq!(*this_cpu(&counter).borrow(), 3);
+
+        MOCK_CPU_ID.with(|id| id.set(0));
+        *this_cpu_mut(&counter) += 1;
+        assert_eq!(*this_cpu(&counter).borrow(), 2);
+        assert_eq!(*unsafe { counter.get_for_cpu(1) }.borrow(), 3);
+    }
+
     #[test]
     fn test_multithreaded_access_is_isolated() {
         // This is the stress test. It gives high confidence that the `unsafe impl Sync`
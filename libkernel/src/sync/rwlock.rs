@@ -1,3 +1,4 @@
+use super::lockdep::LockClassKey;
 use super::spinlock::SpinLockIrq;
 use crate::CpuOps;
 use crate::sync::mutex::Mutex;
@@ -16,6 +17,7 @@ struct RwlockState<CPU: CpuOps> {
 /// dropped, the lock is released.
 pub struct Rwlock<T: ?Sized, CPU: CpuOps> {
     state: RwlockState<CPU>,
+    class: LockClassKey,
     data: UnsafeCell<T>,
 }
 
@@ -38,13 +40,16 @@ pub struct AsyncRwlockWriteGuard<'a, T: ?Sized, CPU: CpuOps> {
 }
 
 impl<T, CPU: CpuOps> Rwlock<T, CPU> {
-    /// Creates a new asynchronous rwlock in an unlocked state.
+    /// Creates a new asynchronous rwlock in an unlocked state. Its lock class
+    /// is keyed by the caller's location.
+    #[cfg_attr(all(feature = "lockdep", debug_assertions), track_caller)]
     pub fn new(data: T) -> Self {
         Self {
             state: RwlockState {
-                num_readers: SpinLockIrq::new(0),
-                writer_lock: Mutex::new(()),
+                num_readers: SpinLockIrq::untracked(0),
+                writer_lock: Mutex::untracked(()),
             },
+            class: LockClassKey::here(),
             data: UnsafeCell::new(data),
         }
     }
@@ -64,11 +69,14 @@ impl<T: ?Sized, CPU: CpuOps> Rwlock<T, CPU> {
     /// Returns a guard asynchronously. The guard is released when the
     /// returned [`AsyncRwlockReadGuard`] is dropped.
     pub async fn read(&self) -> AsyncRwlockReadGuard<'_, T, CPU> {
+        self.class.validate::<CPU>();
+
         let mut num_readers = self.state.num_readers.lock_save_irq();
         *num_readers += 1;
         if *num_readers == 1 {
             self.state.writer_lock.acquire().await;
         }
+        self.class.acquired_sleeping::<CPU>();
         AsyncRwlockReadGuard { rwlock: self }
     }
 
@@ -77,13 +85,17 @@ impl<T: ?Sized, CPU: CpuOps> Rwlock<T, CPU> {
     /// Returns a guard asynchronously. The guard is released when the
     /// returned [`AsyncRwlockWriteGuard`] is dropped.
     pub async fn write(&self) -> AsyncRwlockWriteGuard<'_, T, CPU> {
+        self.class.validate::<CPU>();
         self.state.writer_lock.acquire().await;
+        self.class.acquired_sleeping::<CPU>();
         AsyncRwlockWriteGuard { rwlock: self }
     }
 }
 
 impl<T: ?Sized, CPU: CpuOps> Drop for AsyncRwlockReadGuard<'_, T, CPU> {
     fn drop(&mut self) {
+        self.rwlock.class.released_sleeping::<CPU>();
+
         let mut num_readers = self.rwlock.state.num_readers.lock_save_irq();
         *num_readers -= 1;
         if *num_readers == 0 {
@@ -103,6 +115,7 @@ impl<T: ?Sized, CPU: CpuOps> Deref for AsyncRwlockReadGuard<'_, T, CPU> {
 
 impl<T: ?Sized, CPU: CpuOps> Drop for AsyncRwlockWriteGuard<'_, T, CPU> {
     fn drop(&mut self) {
+        self.rwlock.class.released_sleeping::<CPU>();
         unsafe { self.rwlock.state.writer_lock.release() };
     }
 }
@@ -2,16 +2,35 @@ use core::cell::UnsafeCell;
 use core::hint::spin_loop;
 use core::marker::PhantomData;
 use core::ops::{Deref, DerefMut};
+#[cfg(debug_assertions)]
+use core::sync::atomic::AtomicUsize;
 use core::sync::atomic::{AtomicBool, Ordering};
 
+use super::lockdep::LockClassKey;
 use crate::CpuOps;
 
+/// The value of `owner` while no CPU holds the lock.
+#[cfg(debug_assertions)]
+const NO_OWNER: usize = usize::MAX;
+
+/// How many times a CPU may spin waiting for a lock before we assume it never
+/// will be released.
+#[cfg(debug_assertions)]
+const SPIN_LIMIT: usize = 100_000_000;
+
 /// A spinlock that also disables interrupts on the local core while held.
 ///
 /// This prevents deadlocks with interrupt handlers on the same core and
 /// provides SMP-safety against other cores.
+///
+/// In debug builds, the lock remembers which CPU holds it. A CPU trying to take
+/// a lock it already holds panics rather than spinning forever, as does one
+/// that has waited implausibly long for the lock.
 pub struct SpinLockIrq<T: ?Sized, CPU: CpuOps> {
     lock: AtomicBool,
+    #[cfg(debug_assertions)]
+    owner: AtomicUsize,
+    class: LockClassKey,
     _phantom: PhantomData<CPU>,
     data: UnsafeCell<T>,
 }
@@ -20,10 +39,28 @@ unsafe impl<T: ?Sized + Send, CPU: CpuOps> Send for SpinLockIrq<T, CPU> {}
 unsafe impl<T: ?Sized + Send, CPU: CpuOps> Sync for SpinLockIrq<T, CPU> {}
 
 impl<T, CPU: CpuOps> SpinLockIrq<T, CPU> {
-    /// Creates a new IRQ-safe spinlock.
+    /// Creates a new IRQ-safe spinlock. Its lock class is keyed by the
+    /// caller's location.
+    #[cfg_attr(all(feature = "lockdep", debug_assertions), track_caller)]
     pub const fn new(data: T) -> Self {
         Self {
             lock: AtomicBool::new(false),
+            #[cfg(debug_assertions)]
+            owner: AtomicUsize::new(NO_OWNER),
+            class: LockClassKey::here(),
+            _phantom: PhantomData,
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Creates a new IRQ-safe spinlock that lock-ordering validation ignores,
+    /// for the internals of locks that are validated in their own right.
+    pub(crate) const fn untracked(data: T) -> Self {
+        Self {
+            lock: AtomicBool::new(false),
+            #[cfg(debug_assertions)]
+            owner: AtomicUsize::new(NO_OWNER),
+            class: LockClassKey::untracked(),
             _phantom: PhantomData,
             data: UnsafeCell::new(data),
         }
@@ -36,25 +73,86 @@ impl<T: ?Sized, CPU: CpuOps> SpinLockIrq<T, CPU> {
     pub fn lock_save_irq(&self) -> SpinLockIrqGuard<'_, T, CPU> {
         let saved_irq_flags = CPU::disable_interrupts();
 
+        self.class.validate::<CPU>();
+
+        #[cfg(debug_assertions)]
+        let mut spins = 0;
+
         while self
             .lock
             .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
             .is_err()
         {
+            #[cfg(debug_assertions)]
+            self.check_recursion();
+
             // Spin while waiting for the lock to become available.
             // The `Relaxed` load is sufficient here because the `Acquire`
             // exchange in the loop will synchronize memory.
             while self.lock.load(Ordering::Relaxed) {
+                #[cfg(debug_assertions)]
+                {
+                    spins += 1;
+                    if spins == SPIN_LIMIT {
+                        panic!(
+                            "Possible deadlock: CPU {} spun {SPIN_LIMIT} times on spinlock {:p} held by CPU {}",
+                            CPU::id(),
+                            self,
+                            self.owner.load(Ordering::Relaxed)
+                        );
+                    }
+                }
+
                 spin_loop();
             }
         }
 
+        #[cfg(debug_assertions)]
+        self.owner.store(CPU::id(), Ordering::Relaxed);
+        self.class.acquired::<CPU>();
+
         SpinLockIrqGuard {
             lock: self,
             irq_flags: saved_irq_flags,
             _marker: PhantomData,
         }
     }
+
+    /// Attempts to acquire the lock without spinning. Returns `None`, with the
+    /// interrupt state restored, if the lock is already held.
+    pub fn try_lock_save_irq(&self) -> Option<SpinLockIrqGuard<'_, T, CPU>> {
+        let saved_irq_flags = CPU::disable_interrupts();
+
+        if self
+            .lock
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            CPU::restore_interrupt_state(saved_irq_flags);
+            return None;
+        }
+
+        #[cfg(debug_assertions)]
+        self.owner.store(CPU::id(), Ordering::Relaxed);
+        self.class.acquired::<CPU>();
+
+        Some(SpinLockIrqGuard {
+            lock: self,
+            irq_flags: saved_irq_flags,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Panics if the lock is held by the calling CPU. Interrupts are masked
+    /// while a lock is held, so nothing else on this CPU can release it.
+    #[cfg(debug_assertions)]
+    fn check_recursion(&self) {
+        let cpu = CPU::id();
+
+        if self.owner.load(Ordering::Relaxed) == cpu {
+            panic!("Recursive acquire of spinlock {self:p} on CPU {cpu}");
+        }
+    }
 }
 
 /// An RAII guard for an IRQ-safe spinlock.
@@ -91,8 +189,126 @@ impl<'a, T: ?Sized, CPU: CpuOps> DerefMut for SpinLockIrqGuard<'a, T, CPU> {
 impl<'a, T: ?Sized, CPU: CpuOps> Drop for SpinLockIrqGuard<'a, T, CPU> {
     /// Releases the lock and restores the previous interrupt state.
     fn drop(&mut self) {
+        self.lock.class.released::<CPU>();
+        #[cfg(debug_assertions)]
+        self.lock.owner.store(NO_OWNER, Ordering::Relaxed);
+
         self.lock.lock.store(false, Ordering::Release);
 
         CPU::restore_interrupt_state(self.irq_flags);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::MockCpuOps;
+    use std::cell::Cell;
+    #[cfg(debug_assertions)]
+    use std::panic::{AssertUnwindSafe, catch_unwind};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn recursive_acquire_panics() {
+        let lock = SpinLockIrq::<_, MockCpuOps>::new(0);
+        let _guard = lock.lock_save_irq();
+
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            let _again = lock.lock_save_irq();
+        }));
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn owner_is_cleared_on_release() {
+        let lock = SpinLockIrq::<_, MockCpuOps>::new(0);
+
+        *lock.lock_save_irq() += 1;
+        *lock.lock_save_irq() += 1;
+        assert_eq!(lock.owner.load(Ordering::Relaxed), NO_OWNER);
+
+        // A lock this CPU already holds can still be tried without panicking.
+        let _guard = lock.try_lock_save_irq().unwrap();
+        assert!(lock.try_lock_save_irq().is_none());
+    }
+
+    std::thread_local! {
+        static IRQS_ENABLED: Cell<bool> = const { Cell::new(true) };
+    }
+
+    /// A CPU that keeps track of whether its interrupts are masked.
+    struct IrqCpu;
+
+    impl CpuOps for IrqCpu {
+        fn id() -> usize {
+            MockCpuOps::id()
+        }
+
+        fn halt() -> ! {
+            unimplemented!()
+        }
+
+        fn disable_interrupts() -> usize {
+            IRQS_ENABLED.with(|enabled| enabled.replace(false)) as usize
+        }
+
+        fn restore_interrupt_state(flags: usize) {
+            IRQS_ENABLED.with(|enabled| enabled.set(flags != 0));
+        }
+
+        fn enable_interrupts() {
+            IRQS_ENABLED.with(|enabled| enabled.set(true));
+        }
+    }
+
+    fn irqs_enabled() -> bool {
+        IRQS_ENABLED.with(Cell::get)
+    }
+
+    #[test]
+    fn try_lock_keeps_irqs_masked_only_on_success() {
+        let lock = Arc::new(SpinLockIrq::<_, IrqCpu>::new(0));
+
+        let guard = lock.try_lock_save_irq().unwrap();
+        assert!(!irqs_enabled());
+
+        // Another CPU fails to take the lock, and is left with interrupts as
+        // they were.
+        let other = lock.clone();
+        thread::spawn(move || {
+            assert!(other.try_lock_save_irq().is_none());
+            assert!(irqs_enabled());
+        })
+        .join()
+        .unwrap();
+
+        drop(guard);
+        assert!(irqs_enabled());
+    }
+
+    #[test]
+    fn contention_is_not_recursion() {
+        let lock = Arc::new(SpinLockIrq::<_, MockCpuOps>::new(0));
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let lock = lock.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        *lock.lock_save_irq() += 1;
+                    }
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(*lock.lock_save_irq(), 4000);
+    }
+}
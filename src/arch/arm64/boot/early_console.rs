@@ -0,0 +1,55 @@
+//! Brings up the early console on the UART that will become the kernel's
+//! console, mapped through the fixmap since `map_mmio` isn't available yet.
+
+use crate::{
+    arch::arm64::memory::fixmap::{self, MmioSlot},
+    console::early::{self, EarlyConsole},
+    drivers::{fdt_prober::stdout_node, uart::pl011::Pl011EarlyRegs},
+    early_print,
+};
+use fdt_parser::Fdt;
+use libkernel::memory::{address::PA, permissions::PtePermissions};
+
+/// The physical address of the PL011 to use when `stdout-path` doesn't name
+/// one, set at build time, e.g. `MOSS_EARLYCON_PL011=0x9000000`.
+const DEFAULT_PL011: Option<&str> = option_env!("MOSS_EARLYCON_PL011");
+
+fn parse_addr(s: &str) -> Option<usize> {
+    match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Finds the registers of the PL011 the early console should drive.
+fn find_pl011(fdt: &Fdt<'static>) -> Option<PA> {
+    stdout_node(fdt)
+        .filter(|node| {
+            node.compatible()
+                .is_some_and(|mut compats| compats.any(|c| c.ok() == Some("arm,pl011")))
+        })
+        .and_then(|node| node.reg()?.next())
+        .map(|region| PA::from_value(region.address as usize))
+        .or_else(|| DEFAULT_PL011.and_then(parse_addr).map(PA::from_value))
+}
+
+/// Starts the early console, if there's a UART it can drive.
+pub(super) fn setup_early_console(fdt: &Fdt<'static>) {
+    let Some(pa) = find_pl011(fdt) else {
+        return;
+    };
+
+    let va = fixmap::map(MmioSlot::EarlyConsole, pa, PtePermissions::rw(false));
+
+    // SAFETY: The registers were just mapped into the early console's slot,
+    // which nothing else uses.
+    let regs = unsafe { &mut *(va.value() as *mut Pl011EarlyRegs) };
+
+    early::register(EarlyConsole {
+        uart: regs,
+        base: pa,
+        release: || fixmap::unmap(MmioSlot::EarlyConsole),
+    });
+
+    early_print!("moss: early console on PL011 at {pa:?}\n");
+}
@@ -1,6 +1,7 @@
 use crate::memory::{INITAL_ALLOCATOR, PageOffsetTranslator};
 
 use super::super::memory::{
+    check_layout,
     fixmap::{FIXMAPS, Fixmap},
     mmu::smalloc_page_allocator::SmallocPageAlloc,
     tlb::AllEl1TlbInvalidator,
@@ -64,5 +65,7 @@ pub fn setup_logical_map(pgtbl_base: TPA<PgTableArray<L0Table>>) -> Result<()> {
         map_range(pgtbl_base, map_attrs, &mut ctx)?;
     }
 
+    check_layout(mem_list.iter());
+
     Ok(())
 }
@@ -70,6 +70,30 @@ pub fn setup_allocator(dtb_ptr: TPA<u8>, image_start: PA, image_end: PA) -> Resu
             Ok(())
         })?;
 
+    // Reserve the regions firmware set aside under /reserved-memory. Nodes
+    // without a `reg` ask for a dynamically placed region, which isn't
+    // supported, so they are skipped.
+    for node in dt.reserved_memory() {
+        let Some(regs) = node.reg() else {
+            continue;
+        };
+
+        for reg in regs {
+            let Some(size) = reg.size else {
+                continue;
+            };
+
+            let start_addr = PA::from_value(reg.address as usize);
+
+            info!(
+                "Reserving {} from FDT {start_addr} (0x{size:x} bytes)",
+                node.name
+            );
+
+            alloc.add_reservation(PhysMemoryRegion::new(start_addr, size))?;
+        }
+    }
+
     // Reserve the kernel address.
     info!("Reserving kernel text {image_start} - {image_end}");
     alloc.add_reservation(PhysMemoryRegion::from_start_end_address(
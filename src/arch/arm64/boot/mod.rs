@@ -1,9 +1,11 @@
 use super::{
+    cpu_area::init_cpu_area,
     exceptions::{ExceptionState, secondary_exceptions_init},
     memory::{
         fixmap::FIXMAPS,
+        hafdbs::log_hw_flags,
         heap::{KernelHeap, SLAB_ALLOC},
-        mmu::setup_kern_addr_space,
+        mmu::{remap_readonly, setup_kern_addr_space},
     },
     proc::vdso::vdso_init,
 };
@@ -12,7 +14,7 @@ use crate::{
     arch::{ArchImpl, arm64::exceptions::exceptions_init},
     console::setup_console_logger,
     drivers::{
-        fdt_prober::{probe_for_fdt_devices, set_fdt_va},
+        fdt_prober::{get_fdt, probe_for_fdt_devices, set_fdt_va},
         init::run_initcalls,
     },
     interrupts::{cpu_messenger::cpu_messenger_init, get_interrupt_root},
@@ -24,7 +26,8 @@ use aarch64_cpu::{
     asm::{self, barrier},
     registers::{ReadWriteable, SCTLR_EL1, TCR_EL1, TTBR0_EL1},
 };
-use core::arch::global_asm;
+use core::arch::{asm, global_asm};
+use early_console::setup_early_console;
 use libkernel::{
     CpuOps,
     arch::arm64::memory::pg_tables::{L0Table, PgTableArray},
@@ -39,11 +42,13 @@ use logical_map::setup_logical_map;
 use memory::{setup_allocator, setup_stack_and_heap};
 use secondary::{boot_secondaries, cpu_count, save_idmap, secondary_booted};
 
+mod early_console;
 mod exception_level;
 mod logical_map;
 pub(super) mod memory;
 mod paging_bootstrap;
 pub(super) mod secondary;
+pub(super) mod topology;
 
 global_asm!(include_str!("start.s"));
 
@@ -58,7 +63,7 @@ global_asm!(include_str!("start.s"));
 /// 0xffff_0000_0000_0000 - 0xffff_8000_0000_0000 | Logical Memory Map
 /// 0xffff_8000_0000_0000 - 0xffff_8000_1fff_ffff | Kernel image
 /// 0xffff_8100_0000_0000 - 0xffff_8100_0000_1000 | VDSO (userspace)
-/// 0xffff_9000_0000_0000 - 0xffff_9000_0020_1fff | Fixed mappings
+/// 0xffff_9000_0000_0000 - 0xffff_9000_0020_3fff | Fixed mappings
 /// 0xffff_b800_0000_0000 - 0xffff_b800_0000_8000 | Kernel Stack (per CPU)
 /// 0xffff_d000_0000_0000 - 0xffff_d000_ffff_ffff | MMIO remap
 /// 0xffff_e000_0000_0000 - 0xffff_e000_0000_0800 | Exception Vector Table
@@ -73,6 +78,7 @@ fn arch_init_stage1(
 ) -> VA {
     (|| -> Result<VA> {
         setup_console_logger();
+        log_hw_flags();
 
         setup_allocator(dtb_ptr, image_start, image_end)?;
 
@@ -84,6 +90,7 @@ fn arch_init_stage1(
         };
 
         set_fdt_va(dtb_addr.cast());
+        setup_early_console(&get_fdt());
         setup_logical_map(highmem_pgtable_base)?;
         let stack_addr = setup_stack_and_heap(highmem_pgtable_base)?;
         setup_kern_addr_space(highmem_pgtable_base)?;
@@ -118,14 +125,23 @@ fn arch_init_stage2(frame: *mut ExceptionState) -> *mut ExceptionState {
         panic!("Cannot setup slab allocator");
     }
 
+    init_cpu_area();
     KernelHeap::init_for_this_cpu();
 
     // Don't trap wfi/wfe in el0.
     SCTLR_EL1.modify(SCTLR_EL1::NTWE::DontTrap + SCTLR_EL1::NTWI::DontTrap);
 
+    enable_el0_counter();
+
     exceptions_init().expect("Failed to initialize exceptions");
     ArchImpl::enable_interrupts();
 
+    // Drivers may look at boot parameters, and the log level should apply to
+    // their probing too.
+    let cmdline = super::fdt::get_cmdline().unwrap_or_default();
+    crate::kernel::cmdline::init(&cmdline);
+    crate::console::apply_cmdline_log_level();
+
     unsafe { run_initcalls() };
     probe_for_fdt_devices();
 
@@ -137,9 +153,9 @@ fn arch_init_stage2(frame: *mut ExceptionState) -> *mut ExceptionState {
         panic!("VDSO setup failed: {e}");
     }
 
-    let cmdline = super::fdt::get_cmdline();
+    remap_readonly().expect("Failed to write-protect the kernel image");
 
-    kmain(cmdline.unwrap_or_default(), frame);
+    kmain(cmdline, frame);
 
     boot_secondaries();
 
@@ -155,7 +171,10 @@ fn arch_init_secondary(ctx_frame: *mut ExceptionState) -> *mut ExceptionState {
     // Don't trap secondaries wfi/wfe in el0.
     SCTLR_EL1.modify(SCTLR_EL1::NTWE::DontTrap + SCTLR_EL1::NTWI::DontTrap);
 
+    enable_el0_counter();
+
     // Setup heap per-cpu data.
+    init_cpu_area();
     KernelHeap::init_for_this_cpu();
 
     // Enable interrupts and exceptions.
@@ -179,6 +198,20 @@ fn arch_init_secondary(ctx_frame: *mut ExceptionState) -> *mut ExceptionState {
     ctx_frame
 }
 
+/// Lets EL0 read `CNTPCT_EL0`, which the vvar timekeeping page is relative to.
+fn enable_el0_counter() {
+    unsafe {
+        asm!(
+            "mrs {tmp}, cntkctl_el1",
+            "orr {tmp}, {tmp}, #1", // EL0PCTEN
+            "msr cntkctl_el1, {tmp}",
+            "isb",
+            tmp = out(reg) _,
+            options(nostack),
+        )
+    };
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn park_cpu() -> ! {
     loop {
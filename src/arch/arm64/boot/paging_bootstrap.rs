@@ -9,13 +9,13 @@ use libkernel::arch::arm64::memory::pg_tables::{
 };
 use libkernel::arch::arm64::memory::tlb::NullTlbInvalidator;
 use libkernel::error::{KernelError, Result};
-use libkernel::memory::address::{AddressTranslator, IdentityTranslator, PA, TPA, TVA};
+use libkernel::memory::address::{IdentityTranslator, PA, TPA, TVA};
 use libkernel::memory::permissions::PtePermissions;
-use libkernel::memory::region::PhysMemoryRegion;
+use libkernel::memory::region::{PhysMemoryRegion, VirtMemoryRegion};
 use libkernel::memory::{PAGE_MASK, PAGE_SIZE};
-use tock_registers::interfaces::{ReadWriteable, Writeable};
+use tock_registers::interfaces::{ReadWriteable, Readable, Writeable};
 
-use crate::arch::arm64::memory::IMAGE_BASE;
+use crate::arch::arm64::memory::{IMAGE_BASE, hafdbs::hw_flags};
 
 use super::park_cpu;
 
@@ -24,9 +24,28 @@ const MAX_FDT_SIZE: usize = 2 * 1024 * 1024;
 
 unsafe extern "C" {
     static __image_start: u8;
+    static __rodata_end: u8;
+    static __vectors_start: u8;
+    static __vdso_start: u8;
     static __image_end: u8;
 }
 
+/// The permissions the image page at `offset` is mapped with during boot.
+/// Text and rodata stay RWX until `remap_readonly` tightens them, and so do
+/// the exception vectors. Everything else is data, which is never executed.
+fn image_page_perms(offset: usize) -> PtePermissions {
+    let offset_of = |sym: *const u8| sym.addr() - (&raw const __image_start).addr();
+
+    let data = offset_of(&raw const __rodata_end)..offset_of(&raw const __vectors_start);
+    let late_data = offset_of(&raw const __vdso_start)..;
+
+    if data.contains(&offset) || late_data.contains(&offset) {
+        PtePermissions::rw(false)
+    } else {
+        PtePermissions::rwx(false)
+    }
+}
+
 struct StaticPageAllocator {
     base: PA,
     allocated: usize,
@@ -67,18 +86,6 @@ impl PageAllocator for StaticPageAllocator {
     }
 }
 
-struct KernelImageTranslator {}
-
-impl<T> AddressTranslator<T> for KernelImageTranslator {
-    fn virt_to_phys(_va: libkernel::memory::address::TVA<T>) -> TPA<T> {
-        unreachable!("Should only be used to translate PA -> VA")
-    }
-
-    fn phys_to_virt(_pa: TPA<T>) -> libkernel::memory::address::TVA<T> {
-        IMAGE_BASE.cast()
-    }
-}
-
 struct IdmapTranslator {}
 
 impl PageTableMapper for IdmapTranslator {
@@ -139,18 +146,21 @@ fn do_paging_bootstrap(static_pages: PA, image_addr: PA, fdt_addr: PA) -> Result
         &mut bootstrap_ctx,
     )?;
 
-    // TODO: Split out the permissions of the kernel image, such that the
-    // appropriate permissions are set for each segment of the kernel image.
-    map_range(
-        highmem_l0,
-        MapAttributes {
-            phys: kernel_range,
-            virt: kernel_range.map_via::<KernelImageTranslator>(),
-            mem_type: MemoryType::Normal,
-            perms: PtePermissions::rwx(false),
-        },
-        &mut bootstrap_ctx,
-    )?;
+    // Map the image a page at a time, rather than with block mappings, so that
+    // `remap_readonly` can tighten the permissions of each section once boot
+    // is complete.
+    for offset in (0..image_size).step_by(PAGE_SIZE) {
+        map_range(
+            highmem_l0,
+            MapAttributes {
+                phys: PhysMemoryRegion::new(image_addr.add_bytes(offset), PAGE_SIZE),
+                virt: VirtMemoryRegion::new(IMAGE_BASE.add_bytes(offset), PAGE_SIZE),
+                mem_type: MemoryType::Normal,
+                perms: image_page_perms(offset),
+            },
+            &mut bootstrap_ctx,
+        )?;
+    }
 
     enable_mmu(idmap_l0.to_untyped(), highmem_l0.to_untyped());
 
@@ -188,6 +198,9 @@ pub extern "C" fn enable_mmu(idmap_l0: PA, highmem_l0: PA) {
             TCR_EL1::T0SZ.val(16), // 48-bit VA
     );
 
+    // Let the MMU track page accesses and writes, where it can.
+    TCR_EL1.set(TCR_EL1.get() | hw_flags().tcr_bits());
+
     barrier::dsb(barrier::ISHST);
     barrier::isb(barrier::SY);
 
@@ -5,9 +5,15 @@ use crate::{
             boot::{
                 arch_init_secondary,
                 memory::{KERNEL_STACK_PG_ORDER, allocate_kstack_region},
+                topology::{
+                    CpuDesc, EnableMethod, init_logical_map, logical_id, parse_cpus, parse_psci,
+                    this_mpidr,
+                },
             },
+            cpu_area::{online_cpu_areas, this_cpu_area},
             memory::flush_to_ram,
-            psci::{PSCIEntry, PSCIMethod, boot_secondary_psci},
+            psci::boot_secondary_psci,
+            spin_table::boot_secondary_spin_table,
         },
     },
     drivers::{fdt_prober::get_fdt, timer::now},
@@ -30,7 +36,7 @@ use libkernel::{
         permissions::PtePermissions,
     },
 };
-use log::{info, warn};
+use log::{debug, info, warn};
 
 unsafe extern "C" {
     static __boot_stack: u8;
@@ -54,8 +60,9 @@ extern "C" fn do_secondary_start(boot_info: *const SecondaryBootInfo) {
     naked_asm!(
         "ldr x1, [x0]", // Setup boot stack.
         "mov sp, x1",
-        "mov x19, x0", // Save boot_info .
-        "mov x0, sp",  // Arg0: EL1 stack pointer.
+        "mov x19, x0",        // Save boot_info .
+        "msr tpidr_el1, xzr", // No per-CPU area until `arch_init_secondary`.
+        "mov x0, sp",         // Arg0: EL1 stack pointer.
         "bl transition_to_el1",
         "ldr x0, [x19, #0x18]", // Arg0: idmap_ttbr.
         "ldr x1, [x19, #0x10]", // Arg1: kmem_ttbr.
@@ -72,39 +79,24 @@ extern "C" fn do_secondary_start(boot_info: *const SecondaryBootInfo) {
     )
 }
 
-enum EntryMethod {
-    Psci(PSCIEntry),
+/// Entry point for cores released from a spin-table.
+///
+/// Unlike PSCI, a spin-table passes no context argument, so fetch the boot
+/// context PC-relatively; with the MMU off that yields its physical address.
+#[unsafe(naked)]
+extern "C" fn spin_table_secondary_start() {
+    naked_asm!(
+        "adrp x0, {ctx}",
+        "add  x0, x0, :lo12:{ctx}",
+        "b    {start}",
+        ctx = sym SECONDARY_BOOT_CTX,
+        start = sym do_secondary_start,
+    )
 }
 
-fn find_enable_method(node: &fdt_parser::Node<'static>) -> Result<EntryMethod> {
-    let method = node
-        .find_property("enable-method")
-        .ok_or(KernelError::Other("enable-method property missing"))?
-        .str();
-
-    if method == "psci" {
-        let fdt = get_fdt();
-        let psci_node = fdt
-            .get_node_by_name("psci")
-            .ok_or(KernelError::Other("psci node missing"))?;
-
-        let method = match psci_node.find_property("method").map(|x| x.str()) {
-            Some("hvc") => PSCIMethod::Hvc,
-            Some("smc") => PSCIMethod::Smc,
-            _ => return Err(KernelError::Other("Unknown method in psci node")),
-        };
-
-        let cpu_on_id = psci_node.find_property("cpu_on").map(|x| x.u32());
-
-        Ok(EntryMethod::Psci(PSCIEntry { method, cpu_on_id }))
-    } else {
-        Err(KernelError::Other("Unknown enable method"))
-    }
-}
+static mut SECONDARY_BOOT_CTX: MaybeUninit<SecondaryBootInfo> = MaybeUninit::uninit();
 
 fn prepare_for_secondary_entry() -> Result<(PA, PA)> {
-    static mut SECONDARY_BOOT_CTX: MaybeUninit<SecondaryBootInfo> = MaybeUninit::uninit();
-
     let entry_fn = kfunc_pa!(do_secondary_start as *const () as usize);
     let boot_stack = ksym_pa!(__boot_stack);
     let ctx = ksym_pa!(SECONDARY_BOOT_CTX);
@@ -145,25 +137,35 @@ fn prepare_for_secondary_entry() -> Result<(PA, PA)> {
     Ok((entry_fn, ctx))
 }
 
-fn do_boot_secondary(cpu_node: fdt_parser::Node<'static>) -> Result<()> {
-    let id = cpu_node
-        .reg()
-        .and_then(|mut x| x.next().map(|x| x.address))
-        .ok_or(KernelError::Other("reg property missing on CPU node"))?;
-
+fn do_boot_secondary(cpu: &CpuDesc) -> Result<()> {
     // Skip boot core.
-    if id == 0 {
+    if cpu.mpidr == this_mpidr() {
         return Ok(());
     }
 
-    let mode = find_enable_method(&cpu_node)?;
+    if logical_id(cpu.mpidr).is_none() {
+        return Err(KernelError::Other("more cores than supported CPUs"));
+    }
+
+    let method = cpu
+        .enable_method
+        .ok_or(KernelError::Other("enable-method property missing"))?;
 
     let (entry_fn, ctx) = prepare_for_secondary_entry()?;
 
     SECONDARY_BOOT_FLAG.store(false, Ordering::Relaxed);
 
-    match mode {
-        EntryMethod::Psci(pscientry) => boot_secondary_psci(pscientry, id as _, entry_fn, ctx),
+    match method {
+        EnableMethod::Psci => {
+            boot_secondary_psci(parse_psci(&get_fdt())?, cpu.mpidr as _, entry_fn, ctx)
+        }
+        // Cores sharing a release address all leave the spin loop together,
+        // and would then race for the single boot context; we assume each
+        // core has its own, as is the norm.
+        EnableMethod::SpinTable { release_addr } => boot_secondary_spin_table(
+            release_addr,
+            kfunc_pa!(spin_table_secondary_start as *const () as usize),
+        ),
     }
 
     let timeout = now().map(|x| x + Duration::from_millis(100));
@@ -181,26 +183,20 @@ fn do_boot_secondary(cpu_node: fdt_parser::Node<'static>) -> Result<()> {
     Ok(())
 }
 
-fn cpu_node_iter() -> impl Iterator<Item = fdt_parser::Node<'static>> {
-    let fdt = get_fdt();
+pub fn boot_secondaries() {
+    let cpus = parse_cpus(&get_fdt());
 
-    fdt.all_nodes().filter(|node| {
-        node.find_property("device_type")
-            .map(|prop| prop.str() == "cpu")
-            .unwrap_or(false)
-    })
-}
+    init_logical_map(&cpus);
 
-pub fn boot_secondaries() {
-    for cpu_node in cpu_node_iter() {
-        if let Err(e) = do_boot_secondary(cpu_node) {
-            log::warn!("Failed to boot secondary: {e}");
+    for cpu in cpus {
+        if let Err(e) = do_boot_secondary(&cpu) {
+            log::warn!("Failed to boot secondary 0x{:x}: {e}", cpu.mpidr);
         }
     }
 }
 
 pub fn cpu_count() -> usize {
-    cpu_node_iter().count()
+    parse_cpus(&get_fdt()).len()
 }
 
 pub fn save_idmap(addr: PA) {
@@ -211,8 +207,22 @@ pub fn save_idmap(addr: PA) {
 
 pub fn secondary_booted() {
     let id = ArchImpl::id();
+    let cache = this_cpu_area()
+        .expect("Secondary booted without a per-CPU area")
+        .slab_cache();
+
+    // Each core must have its own slab cache page; sharing one would let two
+    // cores hand out the same objects.
+    assert!(
+        online_cpu_areas()
+            .filter(|area| area.slab_cache() == cache)
+            .count()
+            == 1,
+        "CPU {id} shares its slab cache with another core"
+    );
 
     info!("CPU {id} online.");
+    debug!("CPU {id} slab cache at {cache:p}");
 
     SECONDARY_BOOT_FLAG.store(true, Ordering::Release);
 }
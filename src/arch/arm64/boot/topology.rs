@@ -0,0 +1,231 @@
+//! The CPU topology, as described by the FDT `/cpus` node.
+//!
+//! Cores are told apart in hardware by the affinity fields of `MPIDR_EL1`,
+//! which need not be dense: the first core of a second cluster is typically
+//! `0x100`. Following Linux, the kernel instead numbers the cores from 0, with
+//! the boot core always CPU 0, and keeps a logical map back to the affinity.
+
+use crate::arch::arm64::{
+    cpu_area::MAX_CPUS,
+    psci::{PSCIEntry, PSCIMethod},
+};
+use aarch64_cpu::registers::{MPIDR_EL1, Readable};
+use alloc::vec::Vec;
+use core::{
+    iter,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use fdt_parser::{Fdt, Node};
+use libkernel::{
+    error::{KernelError, Result},
+    memory::address::PA,
+};
+use log::warn;
+
+/// How firmware expects a secondary core to be started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnableMethod {
+    Psci,
+    /// The core spins until an entry point is written to its
+    /// `cpu-release-addr`.
+    SpinTable {
+        release_addr: PA,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuDesc {
+    /// The affinity fields of the core's `MPIDR_EL1`, from its `reg`.
+    pub mpidr: u64,
+    /// `None` if the node has no `enable-method`, as is usual for the boot
+    /// core.
+    pub enable_method: Option<EnableMethod>,
+}
+
+fn parse_cpu(node: &Node<'static>) -> Result<CpuDesc> {
+    let mpidr = node
+        .reg()
+        .and_then(|mut reg| reg.next().map(|reg| reg.address))
+        .ok_or(KernelError::Other("reg property missing on CPU node"))?;
+
+    let enable_method = match node.find_property("enable-method").map(|prop| prop.str()) {
+        None => None,
+        Some("psci") => Some(EnableMethod::Psci),
+        Some("spin-table") => {
+            let release_addr = node
+                .find_property("cpu-release-addr")
+                .ok_or(KernelError::Other(
+                    "cpu-release-addr missing on spin-table CPU",
+                ))?
+                .u64();
+
+            Some(EnableMethod::SpinTable {
+                release_addr: PA::from_value(release_addr as _),
+            })
+        }
+        Some(_) => return Err(KernelError::Other("Unknown enable method")),
+    };
+
+    Ok(CpuDesc {
+        mpidr,
+        enable_method,
+    })
+}
+
+/// Lists the cores described under `/cpus`, skipping (with a warning) any
+/// that can't be parsed.
+pub fn parse_cpus(fdt: &Fdt<'static>) -> Vec<CpuDesc> {
+    fdt.all_nodes()
+        .filter(|node| {
+            node.find_property("device_type")
+                .map(|prop| prop.str() == "cpu")
+                .unwrap_or(false)
+        })
+        .filter_map(|node| {
+            parse_cpu(&node)
+                .inspect_err(|e| warn!("Ignoring CPU node {}: {e}", node.name))
+                .ok()
+        })
+        .collect()
+}
+
+/// The affinity fields of `MPIDR_EL1`, Aff0 to Aff3, which is what a CPU
+/// node's `reg` holds.
+const MPIDR_AFFINITY_MASK: u64 = 0xff_00ff_ffff;
+
+/// Marks a logical CPU with no core behind it.
+const NO_CPU: u64 = u64::MAX;
+
+/// The MPIDR affinity of each logical CPU, indexed by its id.
+static LOGICAL_MAP: [AtomicU64; MAX_CPUS] = [const { AtomicU64::new(NO_CPU) }; MAX_CPUS];
+
+/// Returns the MPIDR affinity of the running core.
+pub fn this_mpidr() -> u64 {
+    MPIDR_EL1.get() & MPIDR_AFFINITY_MASK
+}
+
+/// The affinities of `cpus` in logical CPU order: the boot core first, then
+/// the rest in the order they're listed.
+fn logical_order(boot: u64, cpus: &[CpuDesc]) -> impl Iterator<Item = u64> {
+    let others = cpus
+        .iter()
+        .map(|cpu| cpu.mpidr)
+        .filter(move |&mpidr| mpidr != boot);
+
+    iter::once(boot).chain(others)
+}
+
+/// Numbers the cores in `cpus`. Must be called on the boot core before any
+/// secondary is started; cores beyond [`MAX_CPUS`] are left without an id.
+pub fn init_logical_map(cpus: &[CpuDesc]) {
+    for (slot, mpidr) in LOGICAL_MAP.iter().zip(logical_order(this_mpidr(), cpus)) {
+        slot.store(mpidr, Ordering::Release);
+    }
+}
+
+/// Returns the logical id of the core with MPIDR affinity `mpidr`, or `None`
+/// if it hasn't been given one.
+pub fn logical_id(mpidr: u64) -> Option<usize> {
+    LOGICAL_MAP
+        .iter()
+        .position(|slot| slot.load(Ordering::Acquire) == mpidr)
+}
+
+/// Reads how to make PSCI calls from the `/psci` node.
+pub fn parse_psci(fdt: &Fdt<'static>) -> Result<PSCIEntry> {
+    let psci_node = fdt
+        .get_node_by_name("psci")
+        .ok_or(KernelError::Other("psci node missing"))?;
+
+    let method = match psci_node.find_property("method").map(|x| x.str()) {
+        Some("hvc") => PSCIMethod::Hvc,
+        Some("smc") => PSCIMethod::Smc,
+        _ => return Err(KernelError::Other("Unknown method in psci node")),
+    };
+
+    let cpu_on_id = psci_node.find_property("cpu_on").map(|x| x.u32());
+
+    Ok(PSCIEntry { method, cpu_on_id })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{drivers::fdt_prober::test_fdt::FdtBuilder, ktest};
+
+    fn cpu(fdt: &mut FdtBuilder, name: &str, reg: u32, method: Option<&str>) {
+        fdt.begin_node(name)
+            .prop_str("device_type", "cpu")
+            .prop_str("compatible", "arm,cortex-a72")
+            .prop_cells("reg", &[reg]);
+        if let Some(method) = method {
+            fdt.prop_str("enable-method", method);
+        }
+    }
+
+    ktest! {
+        fn topology_parses_four_cores() {
+            let mut fdt = FdtBuilder::new();
+            fdt.prop_cells("#address-cells", &[2])
+                .prop_cells("#size-cells", &[2]);
+
+            fdt.begin_node("cpus")
+                .prop_cells("#address-cells", &[1])
+                .prop_cells("#size-cells", &[0]);
+            cpu(&mut fdt, "cpu@0", 0, None);
+            fdt.end_node();
+            cpu(&mut fdt, "cpu@1", 1, Some("psci"));
+            fdt.end_node();
+            cpu(&mut fdt, "cpu@100", 0x100, Some("spin-table"));
+            fdt.prop_cells("cpu-release-addr", &[0, 0x8000_fff8]).end_node();
+            cpu(&mut fdt, "cpu@101", 0x101, Some("psci"));
+            fdt.end_node();
+            fdt.end_node();
+
+            fdt.begin_node("psci")
+                .prop_str("compatible", "arm,psci")
+                .prop_str("method", "smc")
+                .prop_cells("cpu_on", &[0x8400_0003])
+                .end_node();
+
+            let fdt = fdt.finish();
+
+            assert_eq!(
+                parse_cpus(&fdt),
+                [
+                    CpuDesc { mpidr: 0, enable_method: None },
+                    CpuDesc { mpidr: 1, enable_method: Some(EnableMethod::Psci) },
+                    CpuDesc {
+                        mpidr: 0x100,
+                        enable_method: Some(EnableMethod::SpinTable {
+                            release_addr: PA::from_value(0x8000_fff8),
+                        }),
+                    },
+                    CpuDesc { mpidr: 0x101, enable_method: Some(EnableMethod::Psci) },
+                ]
+            );
+
+            let psci = parse_psci(&fdt).unwrap();
+            assert!(matches!(psci.method, PSCIMethod::Smc));
+            assert_eq!(psci.cpu_on_id, Some(0x8400_0003));
+        }
+    }
+
+    ktest! {
+        fn logical_ids_start_at_the_boot_core() {
+            let cpus: Vec<_> = [0, 1, 0x100, 0x101]
+                .into_iter()
+                .map(|mpidr| CpuDesc {
+                    mpidr,
+                    enable_method: Some(EnableMethod::Psci),
+                })
+                .collect();
+
+            assert_eq!(
+                logical_order(0x100, &cpus).collect::<Vec<_>>(),
+                [0x100, 0, 1, 0x101]
+            );
+            assert_eq!(logical_order(0, &cpus).collect::<Vec<_>>(), [0, 1, 0x100, 0x101]);
+        }
+    }
+}
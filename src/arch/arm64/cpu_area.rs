@@ -0,0 +1,119 @@
+//! The per-CPU area.
+//!
+//! Following Linux, `TPIDR_EL1` holds the address of the running CPU's
+//! [`CpuArea`]. Hot per-CPU state that can't go through the `per_cpu!`
+//! machinery, such as the slab cache the heap needs before `setup_percpu` has
+//! run, lives in a field of the area and is reached with a single register
+//! read.
+
+use super::ArchImpl;
+use core::{
+    arch::asm,
+    ptr,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+use libkernel::{CpuOps, memory::allocators::slab::cache::SlabCache};
+
+/// The number of CPUs with a per-CPU area.
+pub const MAX_CPUS: usize = 64;
+
+pub struct CpuArea {
+    /// The CPU this area belongs to, or `usize::MAX` before it is set up.
+    cpu_id: AtomicUsize,
+    /// This CPU's slab cache, set by `KernelHeap::init_for_this_cpu`.
+    slab_cache: AtomicPtr<SlabCache>,
+}
+
+impl CpuArea {
+    const fn new() -> Self {
+        Self {
+            cpu_id: AtomicUsize::new(usize::MAX),
+            slab_cache: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    pub fn cpu_id(&self) -> usize {
+        self.cpu_id.load(Ordering::Relaxed)
+    }
+
+    pub fn slab_cache(&self) -> *mut SlabCache {
+        self.slab_cache.load(Ordering::Relaxed)
+    }
+
+    pub fn set_slab_cache(&self, cache: *mut SlabCache) {
+        self.slab_cache.store(cache, Ordering::Relaxed);
+    }
+}
+
+static CPU_AREAS: [CpuArea; MAX_CPUS] = [const { CpuArea::new() }; MAX_CPUS];
+
+/// Points `TPIDR_EL1` at this CPU's area.
+///
+/// This must be the first thing a CPU does once its MMU is on, since the heap
+/// finds its slab cache through the area.
+pub fn init_cpu_area() {
+    let id = ArchImpl::id();
+
+    let area = CPU_AREAS
+        .get(id)
+        .unwrap_or_else(|| panic!("CPU {id} is beyond the {MAX_CPUS} supported"));
+
+    area.cpu_id.store(id, Ordering::Relaxed);
+
+    #[allow(clippy::pointers_in_nomem_asm_block)]
+    unsafe {
+        asm!("msr TPIDR_EL1, {}", in(reg) ptr::from_ref(area), options(nostack, nomem));
+    }
+}
+
+/// Iterates over the areas of the CPUs that have run [`init_cpu_area`].
+pub fn online_cpu_areas() -> impl Iterator<Item = &'static CpuArea> {
+    CPU_AREAS.iter().filter(|area| area.cpu_id() != usize::MAX)
+}
+
+/// Returns the running CPU's area, or `None` if [`init_cpu_area`] hasn't run
+/// on this CPU yet.
+pub fn this_cpu_area() -> Option<&'static CpuArea> {
+    let area: *const CpuArea;
+
+    unsafe { asm!("mrs {}, TPIDR_EL1", out(reg) area, options(nostack, nomem)) };
+
+    // SAFETY: `TPIDR_EL1` is zeroed at boot and only ever set to an entry of
+    // `CPU_AREAS` by `init_cpu_area`.
+    unsafe { area.as_ref() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ktest;
+
+    ktest! {
+        fn cpu_area_belongs_to_this_cpu() {
+            let flags = ArchImpl::disable_interrupts();
+
+            let area = this_cpu_area().unwrap();
+            assert_eq!(area.cpu_id(), ArchImpl::id());
+            assert!(ptr::eq(area, &CPU_AREAS[ArchImpl::id()]));
+            assert!(!area.slab_cache().is_null());
+
+            ArchImpl::restore_interrupt_state(flags);
+        }
+    }
+
+    ktest! {
+        fn cpu_areas_are_distinct() {
+            let online: alloc::vec::Vec<_> = online_cpu_areas().collect();
+
+            assert!(!online.is_empty());
+
+            for (i, area) in online.iter().enumerate() {
+                assert!(ptr::eq(*area, &CPU_AREAS[area.cpu_id()]));
+
+                for other in &online[i + 1..] {
+                    assert_ne!(area.slab_cache(), other.slab_cache());
+                }
+            }
+        }
+    }
+}
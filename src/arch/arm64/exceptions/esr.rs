@@ -33,6 +33,30 @@ impl Ifsc {
             _ => IfscCategory::Other,
         }
     }
+
+    /// Returns a human-readable name for the fault along with the translation
+    /// table level it was taken at, for codes that encode one.
+    pub fn describe(self) -> (&'static str, Option<i8>) {
+        let level = |base: u8| Some((self.0 - base) as i8);
+
+        match self.0 {
+            0b000000..=0b000011 => ("address size fault", level(0b000000)),
+            0b101001 => ("address size fault", Some(-1)),
+            0b101100 => ("address size fault", Some(-2)),
+            0b000100..=0b000111 => ("translation fault", level(0b000100)),
+            0b101011 => ("translation fault", Some(-1)),
+            0b101010 => ("translation fault", Some(-2)),
+            0b001000..=0b001011 => ("access flag fault", level(0b001000)),
+            0b001100..=0b001111 => ("permission fault", level(0b001100)),
+            0b010000 => ("synchronous external abort", None),
+            0b010100..=0b010111 => {
+                ("synchronous external abort on table walk", level(0b010100))
+            }
+            0b100001 => ("alignment fault", None),
+            0b110000 => ("TLB conflict abort", None),
+            _ => ("unknown fault", None),
+        }
+    }
 }
 
 impl From<u8> for Ifsc {
@@ -71,6 +95,48 @@ impl From<u64> for AbortIss {
     }
 }
 
+/// A printable summary of an instruction or data abort, used when reporting
+/// a fatal fault.
+pub struct AbortDescription {
+    user: bool,
+    instruction: bool,
+    iss: AbortIss,
+}
+
+impl fmt::Display for AbortDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (fault, level) = self.iss.ifsc.describe();
+
+        write!(
+            f,
+            "{} {} abort: {fault}",
+            if self.user { "user" } else { "kernel" },
+            if self.instruction {
+                "instruction"
+            } else {
+                "data"
+            },
+        )?;
+
+        if let Some(level) = level {
+            write!(f, " at level {level}")?;
+        }
+
+        let access = if self.instruction {
+            "execute"
+        } else if self.iss.write {
+            "write"
+        } else {
+            "read"
+        };
+
+        match self.iss.far {
+            Some(far) => write!(f, " on {access}, FAR: 0x{far:016x}"),
+            None => write!(f, " on {access}, FAR: unknown"),
+        }
+    }
+}
+
 /// Instruction Specific Syndrome for SVC/HVC/SMC exceptions.
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct SvcIss {
@@ -126,6 +192,25 @@ pub enum Exception {
     Unknown(u64),
 }
 
+impl Exception {
+    /// Describes this exception if it is an instruction or data abort.
+    pub fn describe_abort(&self) -> Option<AbortDescription> {
+        let (user, instruction, iss) = match *self {
+            Exception::InstrAbortLowerEL(iss) => (true, true, iss),
+            Exception::InstrAbortCurrentEL(iss) => (false, true, iss),
+            Exception::DataAbortLowerEL(iss) => (true, false, iss),
+            Exception::DataAbortCurrentEL(iss) => (false, false, iss),
+            _ => return None,
+        };
+
+        Some(AbortDescription {
+            user,
+            instruction,
+            iss,
+        })
+    }
+}
+
 impl From<Esr> for Exception {
     fn from(esr: Esr) -> Self {
         let value = esr.raw();
@@ -192,6 +277,11 @@ impl Esr {
         Self { value }
     }
 
+    /// Wrap a raw ESR value.
+    pub const fn from_raw(value: u64) -> Self {
+        Self { value }
+    }
+
     /// Get the raw, undecoded ESR value.
     pub fn raw(&self) -> u64 {
         self.value
@@ -211,3 +301,60 @@ impl fmt::Debug for Esr {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ktest;
+    use alloc::string::ToString;
+
+    const EC_SHIFT: u64 = 26;
+    const FNV: u64 = 1 << 10;
+    const WNR: u64 = 1 << 6;
+
+    ktest! {
+        fn describes_user_translation_fault() {
+            // FnV is set so decoding doesn't consult the live FAR_EL1.
+            let esr = Esr::from_raw((0b10_0100 << EC_SHIFT) | FNV | WNR | 0b000111);
+
+            assert_eq!(
+                esr.decode().describe_abort().unwrap().to_string(),
+                "user data abort: translation fault at level 3 on write, FAR: unknown"
+            );
+        }
+    }
+
+    ktest! {
+        fn describes_kernel_faults_with_far() {
+            let exception = Exception::DataAbortCurrentEL(AbortIss {
+                ifsc: Ifsc(0b001101),
+                write: false,
+                far: Some(0xffff_8000_0000_1000),
+            });
+
+            assert_eq!(
+                exception.describe_abort().unwrap().to_string(),
+                "kernel data abort: permission fault at level 1 on read, FAR: 0xffff800000001000"
+            );
+
+            let exception = Exception::InstrAbortCurrentEL(AbortIss {
+                ifsc: Ifsc(0b100001),
+                write: false,
+                far: Some(0x10),
+            });
+
+            assert_eq!(
+                exception.describe_abort().unwrap().to_string(),
+                "kernel instruction abort: alignment fault on execute, FAR: 0x0000000000000010"
+            );
+        }
+    }
+
+    ktest! {
+        fn non_aborts_are_not_described() {
+            let esr = Esr::from_raw(0b01_0101 << EC_SHIFT);
+
+            assert!(esr.decode().describe_abort().is_none());
+        }
+    }
+}
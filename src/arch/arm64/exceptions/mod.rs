@@ -1,10 +1,13 @@
-use super::memory::{
-    EXCEPTION_BASE,
-    fault::{handle_kernel_mem_fault, handle_mem_fault},
+use super::{
+    memory::{
+        EXCEPTION_BASE,
+        fault::{handle_kernel_mem_fault, handle_mem_fault},
+    },
+    panic::record_exception_frame,
 };
 use crate::{
     arch::{ArchImpl, arm64::boot::memory::KERNEL_STACK_PG_ORDER},
-    interrupts::get_interrupt_root,
+    interrupts::{fiq::handle_fiq, get_interrupt_root},
     ksym_pa,
     memory::PAGE_ALLOC,
     sched::{current::current_task, uspc_ret::dispatch_userspace_task},
@@ -82,7 +85,8 @@ impl Display for ExceptionState {
 global_asm!(include_str!("exceptions.s"));
 
 pub fn default_handler(state: &ExceptionState) {
-    panic!("Unhandled CPU exception.  Program state:\n{}", state);
+    record_exception_frame(state);
+    panic!("Unhandled CPU exception");
 }
 
 #[unsafe(no_mangle)]
@@ -127,18 +131,23 @@ extern "C" fn el1_sync_spx(state: *mut ExceptionState) -> *const ExceptionState
 extern "C" fn el1_irq_spx(state: *mut ExceptionState) -> *const ExceptionState {
     match get_interrupt_root() {
         Some(ref im) => im.handle_interrupt(),
-        None => panic!(
-            "IRQ handled before root interrupt controller set.\n{}",
-            unsafe { state.as_ref().unwrap() }
-        ),
+        None => {
+            record_exception_frame(unsafe { state.as_ref().unwrap() });
+            panic!("IRQ handled before root interrupt controller set")
+        }
     }
 
     state
 }
 
+/// FIQs take the fast path in [`handle_fiq`], and never reschedule.
 #[unsafe(no_mangle)]
-extern "C" fn el1_fiq_spx(state: &mut ExceptionState) {
-    default_handler(state);
+extern "C" fn el1_fiq_spx(state: *mut ExceptionState) -> *const ExceptionState {
+    if !handle_fiq() {
+        default_handler(unsafe { state.as_ref().unwrap() });
+    }
+
+    state
 }
 
 #[unsafe(no_mangle)]
@@ -182,10 +191,10 @@ extern "C" fn el0_irq(state: *mut ExceptionState) -> *mut ExceptionState {
 
     match get_interrupt_root() {
         Some(ref im) => im.handle_interrupt(),
-        None => panic!(
-            "IRQ handled before root interrupt controller set.\n{}",
-            unsafe { state.as_ref() }.unwrap()
-        ),
+        None => {
+            record_exception_frame(unsafe { state.as_ref() }.unwrap());
+            panic!("IRQ handled before root interrupt controller set")
+        }
     }
 
     dispatch_userspace_task(state);
@@ -194,8 +203,14 @@ extern "C" fn el0_irq(state: *mut ExceptionState) -> *mut ExceptionState {
 }
 
 #[unsafe(no_mangle)]
-extern "C" fn el0_fiq(state: &mut ExceptionState) {
-    default_handler(state);
+extern "C" fn el0_fiq(state: *mut ExceptionState) -> *const ExceptionState {
+    // Userspace resumes where it was interrupted, so unlike IRQs there's no
+    // need to save its context in the task.
+    if !handle_fiq() {
+        default_handler(unsafe { state.as_ref().unwrap() });
+    }
+
+    state
 }
 
 #[unsafe(no_mangle)]
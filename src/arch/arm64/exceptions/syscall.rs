@@ -5,8 +5,11 @@ use crate::{
         settime::sys_clock_settime,
         timeofday::{sys_gettimeofday, sys_settimeofday},
     },
+    console::syslog::sys_syslog,
     fs::{
         dir::sys_getdents64,
+        eventfd::sys_eventfd2,
+        memfd::sys_memfd_create,
         pipe::sys_pipe2,
         syscalls::{
             at::{
@@ -34,6 +37,7 @@ use crate::{
             ioctl::sys_ioctl,
             iov::{sys_preadv, sys_preadv2, sys_pwritev, sys_pwritev2, sys_readv, sys_writev},
             listxattr::{sys_flistxattr, sys_listxattr, sys_llistxattr},
+            mount::sys_mount,
             removexattr::{sys_fremovexattr, sys_lremovexattr, sys_removexattr},
             rw::{sys_pread64, sys_pwrite64, sys_read, sys_write},
             seek::sys_lseek,
@@ -44,15 +48,16 @@ use crate::{
             sync::{sys_fdatasync, sys_fsync, sys_sync, sys_syncfs},
             trunc::{sys_ftruncate, sys_truncate},
         },
+        timerfd::{sys_timerfd_create, sys_timerfd_settime},
     },
     kernel::{
-        hostname::sys_sethostname, power::sys_reboot, rand::sys_getrandom, sysinfo::sys_sysinfo,
+        hostname::sys_sethostname, power::sys_reboot, random::sys_getrandom, sysinfo::sys_sysinfo,
         uname::sys_uname,
     },
     memory::{
         brk::sys_brk,
         mincore::sys_mincore,
-        mmap::{sys_mmap, sys_mprotect, sys_munmap},
+        mmap::{sys_mmap, sys_mprotect, sys_msync, sys_munmap},
         process_vm::sys_process_vm_readv,
     },
     process::{
@@ -67,6 +72,7 @@ use crate::{
         exit::{sys_exit, sys_exit_group},
         fd_table::{
             dup::{sys_dup, sys_dup3},
+            epoll::{sys_epoll_create1, sys_epoll_ctl, sys_epoll_pwait},
             fcntl::sys_fcntl,
             select::{sys_ppoll, sys_pselect6},
         },
@@ -76,7 +82,7 @@ use crate::{
         thread_group::{
             Pgid,
             pid::{sys_getpgid, sys_getpid, sys_getppid, sys_setpgid},
-            rsrc_lim::sys_prlimit64,
+            rsrc_lim::{sys_getrlimit, sys_prlimit64, sys_setrlimit},
             signal::{
                 kill::{sys_kill, sys_tkill},
                 sigaction::sys_rt_sigaction,
@@ -197,6 +203,28 @@ pub async fn handle_syscall() {
         0xf => sys_lremovexattr(TUA::from_value(arg1 as _), TUA::from_value(arg2 as _)).await,
         0x10 => sys_fremovexattr(arg1.into(), TUA::from_value(arg2 as _)).await,
         0x11 => sys_getcwd(TUA::from_value(arg1 as _), arg2 as _).await,
+        0x13 => sys_eventfd2(arg1 as _, arg2 as _),
+        0x14 => sys_epoll_create1(arg1 as _),
+        0x15 => {
+            sys_epoll_ctl(
+                arg1.into(),
+                arg2 as _,
+                arg3.into(),
+                TUA::from_value(arg4 as _),
+            )
+            .await
+        }
+        0x16 => {
+            sys_epoll_pwait(
+                arg1.into(),
+                TUA::from_value(arg2 as _),
+                arg3 as _,
+                arg4 as _,
+                TUA::from_value(arg5 as _),
+                arg6 as _,
+            )
+            .await
+        }
         0x17 => sys_dup(arg1.into()),
         0x18 => sys_dup3(arg1.into(), arg2.into(), arg3 as _),
         0x19 => sys_fcntl(arg1.into(), arg2 as _, arg3 as _).await,
@@ -230,6 +258,16 @@ pub async fn handle_syscall() {
             )
             .await
         }
+        0x28 => {
+            sys_mount(
+                TUA::from_value(arg1 as _),
+                TUA::from_value(arg2 as _),
+                TUA::from_value(arg3 as _),
+                arg4 as _,
+                TUA::from_value(arg5 as _),
+            )
+            .await
+        }
         0x2b => sys_statfs(TUA::from_value(arg1 as _), TUA::from_value(arg2 as _)).await,
         0x2c => sys_fstatfs(arg1.into(), TUA::from_value(arg2 as _)).await,
         0x2d => sys_truncate(TUA::from_value(arg1 as _), arg2 as _).await,
@@ -364,6 +402,16 @@ pub async fn handle_syscall() {
         0x51 => sys_sync().await,
         0x52 => sys_fsync(arg1.into()).await,
         0x53 => sys_fdatasync(arg1.into()).await,
+        0x55 => sys_timerfd_create(arg1 as _, arg2 as _),
+        0x56 => {
+            sys_timerfd_settime(
+                arg1.into(),
+                arg2 as _,
+                TUA::from_value(arg3 as _),
+                TUA::from_value(arg4 as _),
+            )
+            .await
+        }
         0x58 => {
             sys_utimensat(
                 arg1.into(),
@@ -432,6 +480,7 @@ pub async fn handle_syscall() {
             )
             .await
         }
+        0x74 => sys_syslog(arg1 as _, TUA::from_value(arg2 as _), arg3 as _).await,
         0x75 => {
             sys_ptrace(
                 arg1 as _,
@@ -497,7 +546,8 @@ pub async fn handle_syscall() {
         0x9d => sys_setsid().await,
         0xa0 => sys_uname(TUA::from_value(arg1 as _)).await,
         0xa1 => sys_sethostname(TUA::from_value(arg1 as _), arg2 as _).await,
-        0xa3 => Err(KernelError::InvalidValue),
+        0xa3 => sys_getrlimit(arg1 as _, TUA::from_value(arg2 as _)).await,
+        0xa4 => sys_setrlimit(arg1 as _, TUA::from_value(arg2 as _)).await,
         0xa6 => sys_umask(arg1 as _).map_err(|e| match e {}),
         0xa7 => sys_prctl(arg1 as _, arg2, arg3).await,
         0xa9 => sys_gettimeofday(TUA::from_value(arg1 as _), TUA::from_value(arg2 as _)).await,
@@ -536,6 +586,7 @@ pub async fn handle_syscall() {
         0xde => sys_mmap(arg1, arg2, arg3, arg4, arg5.into(), arg6).await,
         0xdf => Ok(0), // fadvise64_64 is a no-op
         0xe2 => sys_mprotect(VA::from_value(arg1 as _), arg2 as _, arg3 as _),
+        0xe3 => sys_msync(VA::from_value(arg1 as _), arg2 as _, arg3 as _).await,
         0xe8 => sys_mincore(arg1, arg2 as _, TUA::from_value(arg3 as _)).await,
         0xe9 => Ok(0), // sys_madvise is a no-op
         0x104 => {
@@ -581,6 +632,7 @@ pub async fn handle_syscall() {
             .await
         }
         0x116 => sys_getrandom(TUA::from_value(arg1 as _), arg2 as _, arg3 as _).await,
+        0x117 => sys_memfd_create(TUA::from_value(arg1 as _), arg2 as _).await,
         0x11d => {
             sys_copy_file_range(
                 arg1.into(),
@@ -0,0 +1,123 @@
+//! The `AT_HWCAP` and `AT_HWCAP2` bits given to userspace, which libc uses to
+//! pick optimised routines. The bit numbers are Linux's.
+
+use super::rng::read_isar0;
+use core::arch::asm;
+
+const HWCAP_FP: u64 = 1 << 0;
+const HWCAP_ASIMD: u64 = 1 << 1;
+const HWCAP_AES: u64 = 1 << 3;
+const HWCAP_PMULL: u64 = 1 << 4;
+const HWCAP_SHA1: u64 = 1 << 5;
+const HWCAP_SHA2: u64 = 1 << 6;
+const HWCAP_CRC32: u64 = 1 << 7;
+const HWCAP_ATOMICS: u64 = 1 << 8;
+const HWCAP_FPHP: u64 = 1 << 9;
+const HWCAP_ASIMDHP: u64 = 1 << 10;
+const HWCAP_ASIMDRDM: u64 = 1 << 12;
+const HWCAP_SHA3: u64 = 1 << 17;
+const HWCAP_SM3: u64 = 1 << 18;
+const HWCAP_SM4: u64 = 1 << 19;
+const HWCAP_ASIMDDP: u64 = 1 << 20;
+const HWCAP_SHA512: u64 = 1 << 21;
+const HWCAP_ASIMDFHM: u64 = 1 << 23;
+const HWCAP_DIT: u64 = 1 << 24;
+const HWCAP_FLAGM: u64 = 1 << 27;
+
+const HWCAP2_FLAGM2: u64 = 1 << 7;
+const HWCAP2_RNG: u64 = 1 << 16;
+
+/// Extracts the 4-bit ID register field starting at bit `shift`.
+fn field(reg: u64, shift: u32) -> u64 {
+    (reg >> shift) & 0xf
+}
+
+/// Decodes `ID_AA64ISAR0_EL1` and `ID_AA64PFR0_EL1` into `(AT_HWCAP,
+/// AT_HWCAP2)`.
+fn hwcaps_from_id_regs(isar0: u64, pfr0: u64) -> (u64, u64) {
+    let mut hwcap = 0;
+    let mut hwcap2 = 0;
+
+    let set = |caps: &mut u64, cap, present| {
+        if present {
+            *caps |= cap;
+        }
+    };
+
+    // PFR0.FP and PFR0.AdvSIMD are 0xf when not implemented, and 1 when half
+    // precision is supported too.
+    let fp = field(pfr0, 16);
+    let asimd = field(pfr0, 20);
+    set(&mut hwcap, HWCAP_FP, fp != 0xf);
+    set(&mut hwcap, HWCAP_FPHP, fp == 1);
+    set(&mut hwcap, HWCAP_ASIMD, asimd != 0xf);
+    set(&mut hwcap, HWCAP_ASIMDHP, asimd == 1);
+    set(&mut hwcap, HWCAP_DIT, field(pfr0, 48) >= 1);
+
+    set(&mut hwcap, HWCAP_AES, field(isar0, 4) >= 1);
+    set(&mut hwcap, HWCAP_PMULL, field(isar0, 4) >= 2);
+    set(&mut hwcap, HWCAP_SHA1, field(isar0, 8) >= 1);
+    set(&mut hwcap, HWCAP_SHA2, field(isar0, 12) >= 1);
+    set(&mut hwcap, HWCAP_SHA512, field(isar0, 12) >= 2);
+    set(&mut hwcap, HWCAP_CRC32, field(isar0, 16) >= 1);
+    set(&mut hwcap, HWCAP_ATOMICS, field(isar0, 20) >= 2);
+    set(&mut hwcap, HWCAP_ASIMDRDM, field(isar0, 28) >= 1);
+    set(&mut hwcap, HWCAP_SHA3, field(isar0, 32) >= 1);
+    set(&mut hwcap, HWCAP_SM3, field(isar0, 36) >= 1);
+    set(&mut hwcap, HWCAP_SM4, field(isar0, 40) >= 1);
+    set(&mut hwcap, HWCAP_ASIMDDP, field(isar0, 44) >= 1);
+    set(&mut hwcap, HWCAP_ASIMDFHM, field(isar0, 48) >= 1);
+    set(&mut hwcap, HWCAP_FLAGM, field(isar0, 52) >= 1);
+    set(&mut hwcap2, HWCAP2_FLAGM2, field(isar0, 52) >= 2);
+    set(&mut hwcap2, HWCAP2_RNG, field(isar0, 60) >= 1);
+
+    (hwcap, hwcap2)
+}
+
+fn read_pfr0() -> u64 {
+    let pfr0: u64;
+
+    unsafe { asm!("mrs {}, id_aa64pfr0_el1", out(reg) pfr0, options(nomem, nostack)) };
+
+    pfr0
+}
+
+pub fn hwcaps() -> (u64, u64) {
+    hwcaps_from_id_regs(read_isar0(), read_pfr0())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ktest;
+
+    ktest! {
+        fn hwcaps_decode_id_regs() {
+            // No FP or AdvSIMD, nothing in ISAR0.
+            assert_eq!(hwcaps_from_id_regs(0, 0xff << 16), (0, 0));
+
+            // FP and AdvSIMD without half precision, as on a Cortex-A53 with
+            // the crypto extensions and CRC32.
+            let (hwcap, hwcap2) = hwcaps_from_id_regs(0x0001_1120, 0);
+            assert_eq!(
+                hwcap,
+                HWCAP_FP | HWCAP_ASIMD | HWCAP_AES | HWCAP_PMULL | HWCAP_SHA1 | HWCAP_SHA2 | HWCAP_CRC32
+            );
+            assert_eq!(hwcap2, 0);
+
+            // LSE atomics need ISAR0.Atomic to be 2; 1 is reserved.
+            assert_eq!(hwcaps_from_id_regs(1 << 20, 0xff << 16).0, 0);
+            assert_eq!(hwcaps_from_id_regs(2 << 20, 0xff << 16).0, HWCAP_ATOMICS);
+
+            // Half precision, DIT, SHA512, FlagM2 and RNDR.
+            let (hwcap, hwcap2) =
+                hwcaps_from_id_regs(1 << 60 | 2 << 52 | 2 << 12, 1 << 48 | 0x11 << 16);
+            assert_eq!(
+                hwcap,
+                HWCAP_FP | HWCAP_FPHP | HWCAP_ASIMD | HWCAP_ASIMDHP | HWCAP_DIT | HWCAP_SHA2
+                    | HWCAP_SHA512 | HWCAP_FLAGM
+            );
+            assert_eq!(hwcap2, HWCAP2_FLAGM2 | HWCAP2_RNG);
+        }
+    }
+}
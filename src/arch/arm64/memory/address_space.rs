@@ -1,7 +1,9 @@
-use crate::memory::PAGE_ALLOC;
+use crate::memory::{PAGE_ALLOC, page::free_user_page};
 
 use super::{
-    mmu::{page_allocator::PageTableAllocator, page_mapper::PageOffsetPgTableMapper},
+    mmu::{
+        TranslationTables, page_allocator::PageTableAllocator, page_mapper::PageOffsetPgTableMapper,
+    },
     tlb::AllEl0TlbInvalidator,
 };
 use aarch64_cpu::{
@@ -12,7 +14,7 @@ use alloc::vec::Vec;
 use libkernel::{
     PageInfo, UserAddressSpace,
     arch::arm64::memory::{
-        pg_descriptors::{L3Descriptor, MemoryType, PaMapper, PageTableEntry},
+        pg_descriptors::{L3Descriptor, L3DescriptorState, MemoryType, PaMapper, PageTableEntry},
         pg_tables::{
             L0Table, MapAttributes, MappingContext, PageAllocator, PgTableArray, map_range,
         },
@@ -32,11 +34,19 @@ use log::warn;
 
 pub struct Arm64ProcessAddressSpace {
     l0_table: TPA<PgTableArray<L0Table>>,
+    /// The number of pages mapped.
+    resident: usize,
 }
 
 unsafe impl Send for Arm64ProcessAddressSpace {}
 unsafe impl Sync for Arm64ProcessAddressSpace {}
 
+impl TranslationTables for Arm64ProcessAddressSpace {
+    fn l0_table(&self) -> TPA<PgTableArray<L0Table>> {
+        self.l0_table
+    }
+}
+
 impl UserAddressSpace for Arm64ProcessAddressSpace {
     fn new() -> Result<Self>
     where
@@ -44,7 +54,10 @@ impl UserAddressSpace for Arm64ProcessAddressSpace {
     {
         let l0_table = PageTableAllocator::new().allocate_page_table()?;
 
-        Ok(Self { l0_table })
+        Ok(Self {
+            l0_table,
+            resident: 0,
+        })
     }
 
     fn activate(&self) {
@@ -61,7 +74,21 @@ impl UserAddressSpace for Arm64ProcessAddressSpace {
         isb(SY);
     }
 
+    fn destroy(self) {
+        // Don't leave TTBR0 pointing at freed tables.
+        if TTBR0_EL1.get_baddr() == self.l0_table.value() as u64 {
+            self.deactivate();
+        }
+
+        // The tables and pages are released by `Drop`.
+        drop(self);
+    }
+
     fn map_page(&mut self, page: PageFrame, va: VA, perms: PtePermissions) -> Result<()> {
+        if perms.is_write_exec() {
+            return Err(KernelError::InvalidValue);
+        }
+
         let mut ctx = MappingContext {
             allocator: &mut PageTableAllocator::new(),
             mapper: &mut PageOffsetPgTableMapper {},
@@ -77,7 +104,11 @@ impl UserAddressSpace for Arm64ProcessAddressSpace {
                 perms,
             },
             &mut ctx,
-        )
+        )?;
+
+        self.resident += 1;
+
+        Ok(())
     }
 
     fn unmap(&mut self, _va: VA) -> Result<PageFrame> {
@@ -85,6 +116,10 @@ impl UserAddressSpace for Arm64ProcessAddressSpace {
     }
 
     fn protect_range(&mut self, va_range: VirtMemoryRegion, perms: PtePermissions) -> Result<()> {
+        if perms.is_write_exec() {
+            return Err(KernelError::InvalidValue);
+        }
+
         let mut walk_ctx = WalkContext {
             mapper: &mut PageOffsetPgTableMapper {},
             invalidator: &AllEl0TlbInvalidator::new(),
@@ -113,6 +148,8 @@ impl UserAddressSpace for Arm64ProcessAddressSpace {
             L3Descriptor::invalid()
         })?;
 
+        self.resident -= claimed_pages.len();
+
         Ok(claimed_pages)
     }
 
@@ -146,9 +183,39 @@ impl UserAddressSpace for Arm64ProcessAddressSpace {
         Some(PageInfo {
             pfn: pte.mapped_address()?.to_pfn(),
             perms: pte.permissions()?,
+            accessed: pte.is_accessed(),
+            dirty: pte.is_dirty(),
         })
     }
 
+    fn mark_accessed(&mut self, va: VA) -> Result<()> {
+        let mut walk_ctx = WalkContext {
+            mapper: &mut PageOffsetPgTableMapper {},
+            invalidator: &AllEl0TlbInvalidator::new(),
+        };
+
+        let mut mapped = false;
+
+        walk_and_modify_region(self.l0_table, va.page_region(), &mut walk_ctx, |_, pte| {
+            if let L3DescriptorState::Valid = pte.state() {
+                mapped = true;
+                pte.set_accessed(true)
+            } else {
+                pte
+            }
+        })?;
+
+        if mapped {
+            Ok(())
+        } else {
+            Err(KernelError::MappingError(MapError::NotL3Mapped))
+        }
+    }
+
+    fn resident_pages(&self) -> usize {
+        self.resident
+    }
+
     fn protect_and_clone_region(
         &mut self,
         region: VirtMemoryRegion,
@@ -193,6 +260,8 @@ impl UserAddressSpace for Arm64ProcessAddressSpace {
                 )
                 .unwrap();
 
+                other.resident += 1;
+
                 pgd.set_permissions(new_perms)
             } else {
                 pgd
@@ -208,11 +277,10 @@ impl Drop for Arm64ProcessAddressSpace {
             invalidator: &AllEl0TlbInvalidator::new(),
         };
 
+        // This scrubs the page-table frames along with the user's pages,
+        // which is cheap in comparison.
         if tear_down_address_space(self.l0_table, &mut walk_ctx, |addr| unsafe {
-            PAGE_ALLOC
-                .get()
-                .unwrap()
-                .alloc_from_region(addr.to_pfn().as_phys_range());
+            free_user_page(addr.to_pfn());
         })
         .is_err()
         {
@@ -220,3 +288,51 @@ impl Drop for Arm64ProcessAddressSpace {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ktest, memory::page::ClaimedPage};
+
+    ktest! {
+        fn destroy_frees_pages_and_tables() {
+            let page_alloc = PAGE_ALLOC.get().unwrap();
+            let baseline = page_alloc.free_pages();
+
+            let mut parent = Arm64ProcessAddressSpace::new().unwrap();
+            let mut child = Arm64ProcessAddressSpace::new().unwrap();
+
+            // Spread the pages out so that every level of table is populated
+            // more than once.
+            let vas = [0x1000, 0x4000_0000, 0x80_0000_0000].map(VA::from_value);
+            let mut pfns = Vec::new();
+
+            for va in vas {
+                let pfn = ClaimedPage::alloc_zeroed().unwrap().leak();
+                parent.map_page(pfn, va, PtePermissions::rw(true)).unwrap();
+                pfns.push(pfn);
+            }
+            assert_eq!(parent.resident_pages(), vas.len());
+
+            // Share everything with the child, as a fork would.
+            for va in vas {
+                parent
+                    .protect_and_clone_region(
+                        va.page_region(),
+                        &mut child,
+                        PtePermissions::rw(true).into_cow(),
+                    )
+                    .unwrap();
+            }
+            assert_eq!(child.resident_pages(), vas.len());
+
+            // The child still maps the pages, so they must survive.
+            parent.destroy();
+            assert!(pfns.iter().all(|&pfn| page_alloc.is_allocated(pfn)));
+
+            child.destroy();
+            assert!(pfns.iter().all(|&pfn| !page_alloc.is_allocated(pfn)));
+            assert_eq!(page_alloc.free_pages(), baseline);
+        }
+    }
+}
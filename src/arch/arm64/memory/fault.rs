@@ -1,49 +1,74 @@
-use core::mem;
+use core::{mem, slice};
 
 use crate::{
     arch::arm64::{
         boot::memory::KERNEL_STACK_AREA,
         exceptions::{
             ExceptionState,
-            esr::{AbortIss, Exception, IfscCategory},
+            esr::{AbortDescription, AbortIss, Exception, IfscCategory},
         },
-        memory::uaccess::UAccessResult,
+        memory::{
+            PAGE_OFFSET,
+            mmu::{KERN_ADDR_SPC, dump_mapping},
+            uaccess::UAccessResult,
+        },
+        panic::record_exception_frame,
+    },
+    memory::fault::{
+        FaultResolution, handle_access_flag_fault, handle_demand_fault, handle_protection_fault,
+    },
+    process::{
+        owned::OwnedTask,
+        thread_group::signal::{FaultInfo, SEGV_ACCERR, SEGV_MAPERR, SigId},
     },
-    memory::fault::{FaultResolution, handle_demand_fault, handle_protection_fault},
     sched::{current::current_task, spawn_kernel_work},
 };
 use alloc::boxed::Box;
 use libkernel::{
     UserAddressSpace,
     error::Result,
-    memory::{address::VA, proc_vm::vmarea::AccessKind, region::VirtMemoryRegion},
+    memory::{address::VA, proc_vm::vmarea::AccessKind},
 };
 
+/// An entry in the exception fixup table, emitted by the uaccess routines for
+/// each instruction that touches user memory.
 #[repr(C)]
-struct FixupTable {
-    start: VA,
-    end: VA,
+struct FixupEntry {
+    insn: VA,
     fixup: VA,
 }
 
 unsafe extern "C" {
-    static __UACCESS_FIXUP: FixupTable;
+    static __fixups_start: FixupEntry;
+    static __fixups_end: FixupEntry;
 }
 
-impl FixupTable {
-    fn is_in_fixup(&self, addr: VA) -> bool {
-        VirtMemoryRegion::from_start_end_address(self.start, self.end).contains_address(addr)
-    }
+/// Returns the address to resume at if `pc` is a user access instruction that
+/// is allowed to fault.
+fn search_fixup(pc: VA) -> Option<VA> {
+    let entries = unsafe {
+        let start = &raw const __fixups_start;
+        let end = &raw const __fixups_end;
+
+        slice::from_raw_parts(start, end.offset_from_unsigned(start))
+    };
+
+    entries
+        .iter()
+        .find(|entry| entry.insn == pc)
+        .map(|entry| entry.fixup)
 }
 
-fn run_mem_fault_handler(exception: Exception, info: AbortIss) -> Result<FaultResolution> {
+fn run_mem_fault_handler(
+    task: &OwnedTask,
+    exception: Exception,
+    info: AbortIss,
+) -> Result<FaultResolution> {
     let access_kind = determine_access_kind(exception, info);
 
     if let Some(far) = info.far {
         let fault_addr = VA::from_value(far as usize);
 
-        let task = current_task();
-
         match info.ifsc.category() {
             IfscCategory::TranslationFault => {
                 handle_demand_fault(task.vm.clone(), fault_addr, access_kind)
@@ -59,22 +84,35 @@ fn run_mem_fault_handler(exception: Exception, info: AbortIss) -> Result<FaultRe
 
                 handle_protection_fault(&mut vm, fault_addr, access_kind, pg_info)
             }
-            _ => panic!("Unhandled memory fault"),
+            IfscCategory::AccessFlagFault => {
+                handle_access_flag_fault(&mut task.vm.lock_save_irq(), fault_addr)
+            }
+            _ => panic!("Unhandled memory fault: {}", describe(exception)),
         }
     } else {
-        panic!("Instruction/Data abort with no valid Fault Address Register",);
+        panic!(
+            "Instruction/Data abort with no valid Fault Address Register: {}",
+            describe(exception)
+        );
     }
 }
 
-fn handle_uacess_abort(exception: Exception, info: AbortIss, state: &mut ExceptionState) {
-    match run_mem_fault_handler(exception, info) {
+fn handle_uacess_abort(
+    exception: Exception,
+    info: AbortIss,
+    state: &mut ExceptionState,
+    fixup: VA,
+) {
+    let resolution = run_mem_fault_handler(&current_task(), exception, info);
+
+    match resolution {
         // We mapped in a page, the uacess handler can proceed.
         Ok(FaultResolution::Resolved) => (),
         // If the fault couldn't be resolved, signal to the uacess fixup that
         // the abort failed.
         Ok(FaultResolution::Denied) => {
             state.x[0] = UAccessResult::AbortDenied as _;
-            state.elr_el1 = unsafe { __UACCESS_FIXUP.fixup.value() as u64 };
+            state.elr_el1 = fixup.value() as u64;
         }
         // If the page fault involves sleepy kernel work, we send that work
         // over to the uacess future for it to then await it.
@@ -89,58 +127,100 @@ fn handle_uacess_abort(exception: Exception, info: AbortIss, state: &mut Excepti
             state.x[0] = UAccessResult::AbortDeferred as _;
             state.x[1] = data_ptr as _;
             state.x[3] = vtable_ptr as _;
-            state.elr_el1 = unsafe { __UACCESS_FIXUP.fixup.value() as u64 };
+            state.elr_el1 = fixup.value() as u64;
         }
         Err(_) => panic!("Page fault handler error, SIGBUS on process"),
     }
 }
 
 pub fn handle_kernel_mem_fault(exception: Exception, info: AbortIss, state: &mut ExceptionState) {
-    if unsafe { __UACCESS_FIXUP.is_in_fixup(VA::from_value(state.elr_el1 as usize)) } {
-        handle_uacess_abort(exception, info, state);
+    if let Some(fixup) = search_fixup(VA::from_value(state.elr_el1 as usize)) {
+        handle_uacess_abort(exception, info, state, fixup);
         return;
     }
 
-    // If the source of the fault (ELR), wasn't in the uacess fixup section,
+    // If the source of the fault (ELR), wasn't in the uacess fixup table,
     // then any abort genereated by the kernel is a panic since we don't
     // demand-page any kernel memory.
     //
     // Try and differentiate between a stack overflow condition and other
     // faults.
+    record_exception_frame(state);
+
     if let Some(far) = info.far
         && KERNEL_STACK_AREA.contains_address(VA::from_value(far as _))
     {
-        panic!("Kernel stack overflow detected.  Context:\n{}", state);
+        panic!("Kernel stack overflow detected: {}", describe(exception));
     } else {
-        panic!("Kernel memory fault detected.  Context:\n{}", state);
+        // Don't wait on the lock: the fault may have been taken holding it.
+        if let Some(far) = info.far
+            && far as usize >= PAGE_OFFSET
+            && let Some(kspc) = KERN_ADDR_SPC.get().and_then(|k| k.try_lock_save_irq())
+        {
+            dump_mapping(&*kspc, VA::from_value(far as _));
+        }
+
+        panic!("Kernel memory fault detected: {}", describe(exception));
     }
 }
 
 pub fn handle_mem_fault(exception: Exception, info: AbortIss) {
-    match run_mem_fault_handler(exception, info) {
-        Ok(FaultResolution::Resolved) => {}
-        // TODO: Implement proc signals.
-        Ok(FaultResolution::Denied) => {
-            let task = current_task();
-            panic!(
-                "SIGSEGV on process {} {:?} PC: {:x}",
-                task.process.tgid,
-                exception,
-                task.ctx.user().elr_el1
-            )
-        }
-        // If the page fault involves sleepy kernel work, we can
-        // spawn that work on the process, since there is no other
-        // kernel work happening.
-        Ok(FaultResolution::Deferred(fut)) => spawn_kernel_work(async {
+    let deferred = handle_user_mem_fault(&mut current_task(), exception, info);
+
+    // If the page fault involves sleepy kernel work, we can spawn that work on
+    // the process, since there is no other kernel work happening.
+    if let Some(fut) = deferred {
+        spawn_kernel_work(async {
             if Box::into_pin(fut).await.is_err() {
                 panic!("Page fault defered error, SIGBUS on process");
             }
-        }),
+        });
+    }
+}
+
+/// Handles a fault taken by `task` in userspace, raising SIGSEGV if the access
+/// isn't allowed. Returns the work still needed to resolve the fault, if it
+/// has to sleep.
+fn handle_user_mem_fault(
+    task: &mut OwnedTask,
+    exception: Exception,
+    info: AbortIss,
+) -> Option<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+    match run_mem_fault_handler(task, exception, info) {
+        Ok(FaultResolution::Resolved) => None,
+        Ok(FaultResolution::Denied) => {
+            // The handler has already panicked if FAR_EL1 wasn't valid.
+            let far = VA::from_value(info.far.unwrap() as usize);
+
+            raise_segv(task, info.ifsc.category(), far);
+            None
+        }
+        Ok(FaultResolution::Deferred(fut)) => Some(fut),
         Err(_) => panic!("Page fault handler error, SIGBUS on process"),
     }
 }
 
+/// Raises SIGSEGV on `task` for a bad access to `far`. The signal is actioned
+/// on the way back to userspace, where a handler may recover from it.
+fn raise_segv(task: &mut OwnedTask, category: IfscCategory, far: VA) {
+    let code = match category {
+        IfscCategory::PermissionFault => SEGV_ACCERR,
+        _ => SEGV_MAPERR,
+    };
+
+    task.raise_fault_signal(FaultInfo {
+        signal: SigId::SIGSEGV,
+        code,
+        addr: far,
+    });
+}
+
+fn describe(exception: Exception) -> AbortDescription {
+    exception
+        .describe_abort()
+        .expect("Memory fault handlers are only entered for aborts")
+}
+
 fn determine_access_kind(exception: Exception, info: AbortIss) -> AccessKind {
     if matches!(exception, Exception::InstrAbortLowerEL(_)) {
         AccessKind::Execute
@@ -153,3 +233,64 @@ fn determine_access_kind(exception: Exception, info: AbortIss) -> AccessKind {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        arch::{Arch, ArchImpl, arm64::exceptions::esr::Ifsc},
+        ktest,
+        process::ProcVM,
+        sync::SpinLock,
+    };
+    use alloc::sync::Arc;
+    use libkernel::memory::{
+        PAGE_SIZE,
+        proc_vm::vmarea::{VMAPermissions, VMArea, VMAreaKind},
+        region::VirtMemoryRegion,
+    };
+
+    /// A read from userspace of `addr` that missed in the page tables.
+    fn user_read_fault(addr: VA) -> (Exception, AbortIss) {
+        let info = AbortIss {
+            // Translation fault, level 3.
+            ifsc: Ifsc(0b000111),
+            write: false,
+            far: Some(addr.value() as u64),
+        };
+
+        (Exception::DataAbortLowerEL(info), info)
+    }
+
+    ktest! {
+        fn bad_user_access_raises_sigsegv() {
+            let addr = VA::from_value(0x10_0000);
+            let vma = VMArea::new(
+                VirtMemoryRegion::new(addr, PAGE_SIZE),
+                VMAreaKind::Anon,
+                VMAPermissions::rw(),
+            );
+
+            // A throwaway task, so the test's own task isn't signalled.
+            let mut task = ArchImpl::create_idle_task();
+            Arc::get_mut(&mut task.t_shared).unwrap().vm =
+                Arc::new(SpinLock::new(ProcVM::from_vma(vma).unwrap()));
+
+            // An access inside the VMA is paged in.
+            let (exception, info) = user_read_fault(addr);
+            assert!(handle_user_mem_fault(&mut task, exception, info).is_none());
+            assert_eq!(task.take_signal(), None);
+
+            // One outside of any VMA can't be resolved.
+            let bad_addr = addr.add_pages(4);
+            let (exception, info) = user_read_fault(bad_addr);
+            assert!(handle_user_mem_fault(&mut task, exception, info).is_none());
+
+            assert_eq!(task.take_signal(), Some(SigId::SIGSEGV));
+
+            let fault = task.fault_info.take().unwrap();
+            assert_eq!(fault.code, SEGV_MAPERR);
+            assert_eq!(fault.addr, bad_addr);
+        }
+    }
+}
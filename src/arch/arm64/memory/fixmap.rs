@@ -1,4 +1,7 @@
-use super::{FIXMAP_BASE, tlb::AllEl1TlbInvalidator};
+use super::{
+    FIXMAP_BASE,
+    tlb::{AllEl1TlbInvalidator, El1PageTlbInvalidator},
+};
 use crate::{arch::arm64::fdt::MAX_FDT_SZ, ksym_pa, sync::SpinLock};
 use core::{
     ops::{Deref, DerefMut},
@@ -15,7 +18,7 @@ use libkernel::{
     error::{KernelError, Result},
     memory::{
         PAGE_SIZE,
-        address::{IdentityTranslator, TPA, TVA, VA},
+        address::{IdentityTranslator, PA, TPA, TVA, VA},
         permissions::PtePermissions,
         region::PhysMemoryRegion,
     },
@@ -66,6 +69,47 @@ enum FixmapSlot {
     DtbStart = 0,
     _DtbEnd = MAX_FDT_SZ / PAGE_SIZE, // 2MiB max DTB size,
     PgTableTmp,
+    EarlyConsole,
+    Scratch,
+    _End,
+}
+
+// Every slot must be covered by the fixmap's L3 tables.
+const _: () = assert!(FixmapSlot::_End as usize * PAGE_SIZE <= 2 << L2Table::SHIFT);
+
+/// A page of the fixmap that devices needed before `map_mmio` works can be
+/// mapped into. Each slot belongs to one user, so their mappings never
+/// collide.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MmioSlot {
+    /// The boot console, so it can log before the page allocator is up.
+    EarlyConsole,
+    /// Short-lived mappings, like peeking at a device's ID registers.
+    #[cfg_attr(not(test), expect(dead_code))]
+    Scratch,
+}
+
+impl From<MmioSlot> for FixmapSlot {
+    fn from(slot: MmioSlot) -> Self {
+        match slot {
+            MmioSlot::EarlyConsole => FixmapSlot::EarlyConsole,
+            MmioSlot::Scratch => FixmapSlot::Scratch,
+        }
+    }
+}
+
+/// Maps the page of MMIO holding `pa` into `slot` and returns the VA of `pa`.
+/// Whatever the slot held before is replaced, and only its page is flushed
+/// from the TLB.
+pub fn map(slot: MmioSlot, pa: PA, perms: PtePermissions) -> VA {
+    FIXMAPS
+        .lock_save_irq()
+        .map_slot(slot.into(), pa, MemoryType::Device, perms)
+}
+
+/// Removes the mapping in `slot`.
+pub fn unmap(slot: MmioSlot) {
+    FIXMAPS.lock_save_irq().unmap_slot(slot.into());
 }
 
 pub struct Fixmap {
@@ -158,17 +202,11 @@ impl Fixmap {
         &mut self,
         pa: TPA<PgTableArray<T>>,
     ) -> Result<TempFixmapGuard<PgTableArray<T>>> {
-        let va = Self::va_for_slot(FixmapSlot::PgTableTmp);
-        let invalidator = AllEl1TlbInvalidator::new();
-
-        L3Table::from_ptr(TVA::from_ptr_mut(&mut self.l3[1] as *mut _)).set_desc(
-            va,
-            L3Descriptor::new_map_pa(
-                pa.to_untyped(),
-                MemoryType::Normal,
-                PtePermissions::rw(false),
-            ),
-            &invalidator,
+        let va = self.map_slot(
+            FixmapSlot::PgTableTmp,
+            pa.to_untyped(),
+            MemoryType::Normal,
+            PtePermissions::rw(false),
         );
 
         Ok(TempFixmapGuard {
@@ -178,17 +216,108 @@ impl Fixmap {
     }
 
     fn unmap_temp_page(&mut self) {
-        let va = Self::va_for_slot(FixmapSlot::PgTableTmp);
-        let invalidator = AllEl1TlbInvalidator::new();
+        self.unmap_slot(FixmapSlot::PgTableTmp);
+    }
 
-        L3Table::from_ptr(TVA::from_ptr_mut(&mut self.l3[1] as *mut _)).set_desc(
+    /// Maps the page holding `pa` into `slot`, flushing only that page from
+    /// the TLB, and returns the VA of `pa`.
+    fn map_slot(
+        &mut self,
+        slot: FixmapSlot,
+        pa: PA,
+        memory_type: MemoryType,
+        perms: PtePermissions,
+    ) -> VA {
+        let va = Self::va_for_slot(slot);
+        let invalidator = El1PageTlbInvalidator::new(va);
+
+        self.l3_for_slot(slot).set_desc(
             va,
-            L3Descriptor::invalid(),
+            L3Descriptor::new_map_pa(pa.page_aligned(), memory_type, perms),
             &invalidator,
         );
+
+        va.add_bytes(pa.page_offset())
+    }
+
+    fn unmap_slot(&mut self, slot: FixmapSlot) {
+        let va = Self::va_for_slot(slot);
+        let invalidator = El1PageTlbInvalidator::new(va);
+
+        self.l3_for_slot(slot)
+            .set_desc(va, L3Descriptor::invalid(), &invalidator);
+    }
+
+    /// The L3 table holding the descriptor for `slot`.
+    fn l3_for_slot(&mut self, slot: FixmapSlot) -> L3Table {
+        let table = (slot as usize * PAGE_SIZE) >> L2Table::SHIFT;
+
+        L3Table::from_ptr(TVA::from_ptr_mut(&mut self.l3[table] as *mut _))
     }
 
     fn va_for_slot(slot: FixmapSlot) -> VA {
         TVA::from_value(FIXMAP_BASE.value() + (slot as usize * PAGE_SIZE))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{arch::arm64::memory::clean_dcache_range, ktest, memory::page::ClaimedPage};
+    use core::arch::asm;
+
+    /// Asks the MMU whether an EL1 read of `va` would fault.
+    fn read_faults(va: VA) -> bool {
+        let par: u64;
+
+        unsafe {
+            asm!(
+                "at s1e1r, {va}",
+                "isb",
+                "mrs {par}, par_el1",
+                va = in(reg) va.value(),
+                par = out(reg) par,
+                options(nostack),
+            )
+        };
+
+        // PAR_EL1.F
+        par & 1 != 0
+    }
+
+    /// A page filled with `byte`, written back so it can be read through the
+    /// fixmap's uncached device mapping.
+    fn filled_page(byte: u8) -> ClaimedPage {
+        let mut page = ClaimedPage::alloc_zeroed().unwrap();
+
+        page.as_slice_mut().fill(byte);
+        clean_dcache_range(page.va(), PAGE_SIZE);
+
+        page
+    }
+
+    ktest! {
+        fn fixmap_slot_maps_and_remaps() {
+            let a = filled_page(0xa5);
+            let b = filled_page(0x5a);
+            let slot_va = Fixmap::va_for_slot(FixmapSlot::Scratch);
+
+            let va = map(MmioSlot::Scratch, a.pa().add_bytes(0x10), PtePermissions::ro(false));
+            assert_eq!(va, slot_va.add_bytes(0x10));
+            assert_eq!(unsafe { (va.as_ptr() as *const u32).read_volatile() }, 0xa5a5_a5a5);
+
+            // The old translation must not survive the remap.
+            let va = map(MmioSlot::Scratch, b.pa(), PtePermissions::ro(false));
+            assert_eq!(unsafe { (va.as_ptr() as *const u32).read_volatile() }, 0x5a5a_5a5a);
+
+            // Slots are independent. The early console's slot is left as
+            // the console handoff left it, whether or not it was ever used.
+            let console = Fixmap::va_for_slot(FixmapSlot::EarlyConsole);
+            let console_mapped = !read_faults(console);
+
+            unmap(MmioSlot::Scratch);
+            assert!(read_faults(slot_va));
+            assert_eq!(!read_faults(console), console_mapped);
+        }
+    }
+}
@@ -0,0 +1,86 @@
+//! Hardware management of the access flag and dirty state (FEAT_HAFDBS).
+//!
+//! User pages are mapped with the access flag clear, so that the first access
+//! to each is recorded. With `TCR_EL1.HA` set the table walker sets the flag
+//! itself; without it the access takes an access flag fault, which
+//! [`handle_access_flag_fault`](crate::memory::fault::handle_access_flag_fault)
+//! resolves. Writable user pages carry the DBM bit, so with `TCR_EL1.HD` set a
+//! write to a cleaned page marks it dirty again without faulting.
+
+use core::arch::asm;
+use log::info;
+
+const TCR_HA: u64 = 1 << 39;
+const TCR_HD: u64 = 1 << 40;
+
+/// How much of the page state the CPU can manage itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HwFlags {
+    /// Neither: both are left to software.
+    None,
+    /// The access flag only.
+    Access,
+    /// The access flag and the dirty state.
+    AccessDirty,
+}
+
+impl HwFlags {
+    /// Decodes `ID_AA64MMFR1_EL1.HAFDBS`.
+    pub const fn from_mmfr1(mmfr1: u64) -> Self {
+        match mmfr1 & 0xf {
+            0b0000 => HwFlags::None,
+            0b0001 => HwFlags::Access,
+            // 0b0011 adds FEAT_HAFT, for table descriptors, which isn't used.
+            _ => HwFlags::AccessDirty,
+        }
+    }
+
+    /// The `TCR_EL1` bits that turn on what's supported.
+    pub const fn tcr_bits(self) -> u64 {
+        match self {
+            HwFlags::None => 0,
+            HwFlags::Access => TCR_HA,
+            HwFlags::AccessDirty => TCR_HA | TCR_HD,
+        }
+    }
+}
+
+/// What this CPU supports, from `ID_AA64MMFR1_EL1`.
+pub fn hw_flags() -> HwFlags {
+    let mmfr1: u64;
+
+    unsafe { asm!("mrs {0}, id_aa64mmfr1_el1", out(reg) mmfr1, options(nostack, nomem)) };
+
+    HwFlags::from_mmfr1(mmfr1)
+}
+
+/// Logs what the hardware will manage.
+pub fn log_hw_flags() {
+    info!(
+        "Page access/dirty tracking: {}",
+        match hw_flags() {
+            HwFlags::None => "software",
+            HwFlags::Access => "hardware access flag, software dirty state",
+            HwFlags::AccessDirty => "hardware",
+        }
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ktest;
+
+    ktest! {
+        fn hafdbs_decoding() {
+            assert_eq!(HwFlags::from_mmfr1(0x0), HwFlags::None);
+            assert_eq!(HwFlags::from_mmfr1(0x1), HwFlags::Access);
+            assert_eq!(HwFlags::from_mmfr1(0x2), HwFlags::AccessDirty);
+            assert_eq!(HwFlags::from_mmfr1(0x10_1123), HwFlags::AccessDirty);
+
+            assert_eq!(HwFlags::None.tcr_bits(), 0);
+            assert_eq!(HwFlags::Access.tcr_bits(), 1 << 39);
+            assert_eq!(HwFlags::AccessDirty.tcr_bits(), 3 << 39);
+        }
+    }
+}
@@ -1,10 +1,12 @@
 use crate::{
-    arch::ArchImpl,
+    arch::{
+        ArchImpl,
+        arm64::cpu_area::{online_cpu_areas, this_cpu_area},
+    },
     memory::{PageOffsetTranslator, page::PgAllocGetter},
     sync::OnceLock,
 };
 use core::{
-    arch::asm,
     ops::{Deref, DerefMut},
     ptr,
 };
@@ -14,6 +16,7 @@ use libkernel::{
         allocator::SlabAllocator,
         cache::SlabCache,
         heap::{KHeap, SlabCacheStorage, SlabGetter},
+        stats::HeapStats,
     },
 };
 
@@ -35,24 +38,37 @@ pub struct PerCpuCache {
 
 impl PerCpuCache {
     fn get_ptr() -> *mut SlabCache {
-        let mut cache: *mut SlabCache = ptr::null_mut();
-
-        unsafe { asm!("mrs {}, TPIDR_EL1", out(reg) cache, options(nostack, nomem)) };
+        let cache = this_cpu_area()
+            .map(|area| area.slab_cache())
+            .unwrap_or(ptr::null_mut());
 
         if cache.is_null() {
-            panic!("Attempted to use alloc/free before CPU initalisation!");
+            Self::uninitialised();
         }
 
         cache
     }
+
+    /// Reports an allocation on a CPU whose slab cache isn't set up yet,
+    /// telling apart early boot from a core whose bring-up allocates too soon.
+    #[cold]
+    fn uninitialised() -> ! {
+        if online_cpu_areas().any(|area| !area.slab_cache().is_null()) {
+            panic!(
+                "CPU {} attempted to use alloc/free before its per-CPU heap was initialised!",
+                ArchImpl::id()
+            );
+        }
+
+        panic!("Attempted to use alloc/free before the heap was initialised!");
+    }
 }
 
 impl SlabCacheStorage for PerCpuCache {
     fn store(ptr: *mut SlabCache) {
-        #[allow(clippy::pointers_in_nomem_asm_block)]
-        unsafe {
-            asm!("msr TPIDR_EL1, {}", in(reg) ptr, options(nostack, nomem));
-        };
+        this_cpu_area()
+            .expect("The per-CPU area must be set up before the heap")
+            .set_slab_cache(ptr);
     }
 
     fn get() -> impl DerefMut<Target = SlabCache> {
@@ -93,3 +109,8 @@ pub type KernelHeap =
 
 #[global_allocator]
 static K_HEAP: KernelHeap = KernelHeap::new();
+
+/// Returns the kernel heap's current usage.
+pub fn heap_stats() -> HeapStats {
+    K_HEAP.stats()
+}
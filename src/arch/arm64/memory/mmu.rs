@@ -3,17 +3,19 @@ use crate::sync::{OnceLock, SpinLock};
 use libkernel::{
     KernAddressSpace,
     arch::arm64::memory::{
-        pg_descriptors::{MemoryType, PaMapper},
+        pg_descriptors::{L3Descriptor, MemoryType, PaMapper},
+        pg_dump::{for_each_run, walk},
         pg_tables::{L0Table, MapAttributes, MappingContext, PgTableArray, map_range},
-        pg_walk::get_pte,
+        pg_walk::{WalkContext, get_pte, walk_and_modify_region},
     },
-    error::Result,
+    error::{KernelError, Result},
     memory::{
         address::{PA, TPA, VA},
         permissions::PtePermissions,
         region::{PhysMemoryRegion, VirtMemoryRegion},
     },
 };
+use log::info;
 use page_allocator::PageTableAllocator;
 use page_mapper::PageOffsetPgTableMapper;
 
@@ -30,13 +32,35 @@ pub struct Arm64KernelAddressSpace {
 
 impl Arm64KernelAddressSpace {
     fn do_map(&self, map_attrs: MapAttributes) -> Result<()> {
+        if map_attrs.perms.is_write_exec() {
+            return Err(KernelError::InvalidValue);
+        }
+
         let mut ctx = MappingContext {
             allocator: &mut PageTableAllocator::new(),
             mapper: &mut PageOffsetPgTableMapper {},
             invalidator: &AllEl1TlbInvalidator::new(),
         };
 
-        map_range(self.kernel_l0, map_attrs, &mut ctx)
+        let (virt, user) = (map_attrs.virt, map_attrs.perms.is_user());
+
+        map_range(self.kernel_l0, map_attrs, &mut ctx)?;
+
+        // User pages start out unaccessed, for the owning process to track.
+        // Nothing owns those in the kernel's tables, like the vDSO, so an
+        // access flag fault on one could never be resolved.
+        if user {
+            let mut ctx = WalkContext {
+                mapper: &mut PageOffsetPgTableMapper {},
+                invalidator: &AllEl1TlbInvalidator::new(),
+            };
+
+            walk_and_modify_region(self.kernel_l0, virt, &mut ctx, |_, desc| {
+                desc.set_accessed(true)
+            })?;
+        }
+
+        Ok(())
     }
 
     pub fn translate(&self, va: VA) -> Option<PA> {
@@ -54,10 +78,73 @@ impl Arm64KernelAddressSpace {
     pub fn table_pa(&self) -> PA {
         self.kernel_l0.to_untyped()
     }
+
+    /// Changes the permissions of an existing, page-granular mapping.
+    fn protect(&mut self, region: VirtMemoryRegion, perms: PtePermissions) -> Result<()> {
+        if perms.is_write_exec() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let mut ctx = WalkContext {
+            mapper: &mut PageOffsetPgTableMapper {},
+            invalidator: &AllEl1TlbInvalidator::new(),
+        };
+
+        walk_and_modify_region(self.kernel_l0, region, &mut ctx, |_, desc| {
+            desc.set_permissions(perms)
+        })
+    }
 }
 
 unsafe impl Send for Arm64KernelAddressSpace {}
 
+/// An address space whose translation tables can be dumped.
+pub trait TranslationTables {
+    fn l0_table(&self) -> TPA<PgTableArray<L0Table>>;
+}
+
+impl TranslationTables for Arm64KernelAddressSpace {
+    fn l0_table(&self) -> TPA<PgTableArray<L0Table>> {
+        self.kernel_l0
+    }
+}
+
+/// Logs every descriptor met translating `va` in `addr_space`.
+///
+/// The tables are only read, through the logical map, so this is safe on the
+/// live kernel address space as long as the caller holds its lock.
+pub fn dump_mapping(addr_space: &impl TranslationTables, va: VA) {
+    match walk(addr_space.l0_table(), va, &mut PageOffsetPgTableMapper {}) {
+        Ok(walk) => {
+            info!("Translation of {:#x}:", va.value());
+
+            for step in walk.steps() {
+                info!("  {step}");
+            }
+        }
+        Err(e) => info!("Cannot walk the tables for {:#x}: {e}", va.value()),
+    }
+}
+
+/// Logs the mappings between `start` and `end` in `addr_space`, merging pages
+/// that map contiguous physical memory with the same attributes.
+#[cfg_attr(not(test), expect(dead_code))]
+pub fn dump_range(addr_space: &impl TranslationTables, start: VA, end: VA) {
+    info!("Mappings in {:#x}-{:#x}:", start.value(), end.value());
+
+    let res = for_each_run(
+        addr_space.l0_table(),
+        start,
+        end,
+        &mut PageOffsetPgTableMapper {},
+        |run| info!("  {run}"),
+    );
+
+    if let Err(e) = res {
+        info!("Cannot walk the tables: {e}");
+    }
+}
+
 impl KernAddressSpace for Arm64KernelAddressSpace {
     fn map_normal(
         &mut self,
@@ -93,6 +180,49 @@ impl KernAddressSpace for Arm64KernelAddressSpace {
             base_va.value() + phys_mappable_region.offset(),
         ))
     }
+
+    fn unmap_mmio(&mut self, region: VirtMemoryRegion) -> Result<()> {
+        let mut ctx = WalkContext {
+            mapper: &mut PageOffsetPgTableMapper {},
+            invalidator: &AllEl1TlbInvalidator::new(),
+        };
+
+        // The MMIO window is handed out bump-style, so the virtual range
+        // isn't reused; only the page table entries are torn down.
+        walk_and_modify_region(
+            self.kernel_l0,
+            region.to_mappable_region().region(),
+            &mut ctx,
+            |_, _| L3Descriptor::invalid(),
+        )
+    }
+}
+
+unsafe extern "C" {
+    static __text_start: u8;
+    static __text_end: u8;
+    static __rodata_start: u8;
+    static __rodata_end: u8;
+}
+
+/// Drops write access to the kernel's text and rodata, and execute access to
+/// rodata. The image is mapped RWX during boot, so this is called once init is
+/// complete.
+pub fn remap_readonly() -> Result<()> {
+    let section = |start: *const u8, end: *const u8| {
+        VirtMemoryRegion::from_start_end_address(
+            VA::from_value(start.addr()),
+            VA::from_value(end.addr()),
+        )
+    };
+
+    let text = section(&raw const __text_start, &raw const __text_end);
+    let rodata = section(&raw const __rodata_start, &raw const __rodata_end);
+
+    let mut kspc = KERN_ADDR_SPC.get().unwrap().lock_save_irq();
+
+    kspc.protect(text, PtePermissions::rx(false))?;
+    kspc.protect(rodata, PtePermissions::ro(false))
 }
 
 pub fn setup_kern_addr_space(pa: TPA<PgTableArray<L0Table>>) -> Result<()> {
@@ -103,5 +233,147 @@ pub fn setup_kern_addr_space(pa: TPA<PgTableArray<L0Table>>) -> Result<()> {
 
     KERN_ADDR_SPC
         .set(addr_space)
-        .map_err(|_| libkernel::error::KernelError::InUse)
+        .map_err(|_| KernelError::InUse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{arch::ArchImpl, ktest, memory::page::ClaimedPage};
+    use alloc::vec::Vec;
+    use core::{arch::asm, sync::atomic::AtomicU64};
+    use libkernel::{
+        UserAddressSpace, VirtualMemory,
+        arch::arm64::memory::pg_dump::{WalkEntry, WalkStep},
+        memory::PAGE_SIZE,
+    };
+
+    /// Asks the MMU whether an EL1 write to `va` would fault, without
+    /// performing one.
+    fn write_faults(va: VA) -> bool {
+        let par: u64;
+
+        unsafe {
+            asm!(
+                "at s1e1w, {va}",
+                "isb",
+                "mrs {par}, par_el1",
+                va = in(reg) va.value(),
+                par = out(reg) par,
+                options(nostack),
+            )
+        };
+
+        // PAR_EL1.F
+        par & 1 != 0
+    }
+
+    ktest! {
+        fn write_exec_mappings_are_rejected() {
+            let page = ClaimedPage::alloc_zeroed().unwrap();
+            let region = page.pa().to_pfn().as_phys_range();
+
+            let mut kspc = KERN_ADDR_SPC.get().unwrap().lock_save_irq();
+            assert!(matches!(
+                kspc.map_normal(
+                    region,
+                    VirtMemoryRegion::new(MMIO_BASE.sub_bytes(PAGE_SIZE), PAGE_SIZE),
+                    PtePermissions::rwx(false),
+                ),
+                Err(KernelError::InvalidValue)
+            ));
+            drop(kspc);
+
+            let mut addr_space = <ArchImpl as VirtualMemory>::ProcessAddressSpace::new().unwrap();
+            assert!(matches!(
+                addr_space.map_page(
+                    page.pa().to_pfn(),
+                    VA::from_value(0x1000),
+                    PtePermissions::rwx(true),
+                ),
+                Err(KernelError::InvalidValue)
+            ));
+        }
+    }
+
+    ktest! {
+        fn dump_mapping_walks_live_tables() {
+            let page = ClaimedPage::alloc_zeroed().unwrap();
+            let kspc = KERN_ADDR_SPC.get().unwrap().lock_save_irq();
+
+            // The logical map, which may use blocks of any size.
+            let va = page.va().add_bytes(0x123);
+            let found = walk(kspc.l0_table(), va, &mut PageOffsetPgTableMapper {}).unwrap();
+            let Some(WalkStep { entry: WalkEntry::Leaf { pa, size, attrs, perms }, .. }) =
+                found.leaf().copied()
+            else {
+                panic!("The logical map should cover {va:?}");
+            };
+
+            assert_eq!(pa.add_bytes(va.value() & (size - 1)), page.pa().add_bytes(0x123));
+            assert_eq!(perms, PtePermissions::rw(false));
+            assert_eq!(attrs.attr_index, 0);
+
+            let text = VA::from_value((&raw const __text_start).addr());
+            let found = walk(kspc.l0_table(), text, &mut PageOffsetPgTableMapper {}).unwrap();
+            assert!(matches!(
+                found.leaf().unwrap().entry,
+                WalkEntry::Leaf { perms, .. } if perms == PtePermissions::rx(false)
+            ));
+
+            // The first MMIO mapping, after unmapped space that's skipped.
+            let mut runs = Vec::new();
+            for_each_run(
+                kspc.l0_table(),
+                MMIO_BASE.sub_bytes(PAGE_SIZE),
+                MMIO_BASE.add_bytes(PAGE_SIZE),
+                &mut PageOffsetPgTableMapper {},
+                |run| runs.push(run),
+            )
+            .unwrap();
+
+            assert_eq!(runs.len(), 1);
+            assert_eq!(runs[0].va, MMIO_BASE);
+            assert_eq!(runs[0].attrs.attr_index, 1);
+            assert_eq!(runs[0].perms, PtePermissions::rw(false));
+
+            dump_mapping(&*kspc, va);
+            dump_range(&*kspc, MMIO_BASE, MMIO_BASE.add_bytes(PAGE_SIZE));
+        }
+    }
+
+    ktest! {
+        fn kernel_image_is_write_protected() {
+            static WRITABLE: AtomicU64 = AtomicU64::new(0);
+
+            assert!(write_faults(VA::from_value((&raw const __text_start).addr())));
+            assert!(write_faults(VA::from_value((&raw const __rodata_start).addr())));
+            assert!(write_faults(VA::from_value(
+                (&raw const __rodata_end).addr() - 1
+            )));
+
+            // Make sure the check isn't vacuous.
+            assert!(!write_faults(VA::from_value((&raw const WRITABLE).addr())));
+        }
+    }
+
+    ktest! {
+        fn kernel_data_is_not_executable() {
+            static ZEROED: AtomicU64 = AtomicU64::new(0);
+            static INITIALISED: AtomicU64 = AtomicU64::new(1);
+
+            let kspc = KERN_ADDR_SPC.get().unwrap().lock_save_irq();
+
+            // One lives in .bss, the other in .data.
+            for va in [(&raw const ZEROED).addr(), (&raw const INITIALISED).addr()] {
+                let va = VA::from_value(va);
+                let found = walk(kspc.l0_table(), va, &mut PageOffsetPgTableMapper {}).unwrap();
+
+                assert!(matches!(
+                    found.leaf().unwrap().entry,
+                    WalkEntry::Leaf { perms, .. } if perms == PtePermissions::rw(false)
+                ));
+            }
+        }
+    }
 }
@@ -1,10 +1,15 @@
 use core::arch::asm;
 
-use libkernel::memory::address::{PA, VA};
+use crate::memory::PageOffsetTranslator;
+use libkernel::memory::{
+    address::{PA, VA},
+    region::PhysMemoryRegion,
+};
 
 pub mod address_space;
 pub mod fault;
 pub mod fixmap;
+pub mod hafdbs;
 pub mod heap;
 pub mod mmu;
 pub mod tlb;
@@ -63,29 +68,124 @@ pub fn translate_kernel_va(addr: VA) -> PA {
     PA::from_value(v + get_kimage_start().value())
 }
 
-pub fn flush_to_ram<T>(mut x: *const T) {
-    let mut stride: u64 = 0;
+/// The number of evenly spaced addresses [`check_layout`] tries in each region
+/// of RAM, besides its last byte.
+const LAYOUT_SAMPLES: usize = 8;
 
-    // Calc  cache line stride.
-    unsafe { asm!("mrs {0}, ctr_el0", out(reg) stride, options(nostack, nomem)) };
-    stride = (1 << ((stride >> 16) & 0xf)) * 4;
+/// Whether `pa` lands in the logical map and comes back unchanged.
+fn round_trips(pa: PA) -> bool {
+    let va = pa.to_va::<PageOffsetTranslator>();
 
-    let end = unsafe { x.byte_add(size_of::<T>()) };
-
-    while x < end {
-        // Clear the cache line for the given VA.
-        //
-        // NOTE: We allow the below lint since a flush to the cache is
-        // transparrent; memory hasn't changed from the point of view of the
-        // flushing core.
-        #[allow(clippy::pointers_in_nomem_asm_block)]
-        unsafe {
-            asm!("dc cvac, {0}", in(reg) x, options(nostack, nomem));
+    (PAGE_OFFSET..IMAGE_BASE.value()).contains(&va.value())
+        && va.to_pa::<PageOffsetTranslator>() == pa
+}
 
-            x = x.byte_add(stride as _);
+/// Checks that the address translations agree with the memory layout, for a
+/// sample of addresses across each region of `ram`. Panics if they don't,
+/// since every later translation would then silently corrupt memory.
+pub fn check_layout(ram: impl IntoIterator<Item = PhysMemoryRegion>) {
+    for region in ram {
+        let start = region.start_address();
+        let last = region.size().saturating_sub(1);
+
+        for i in 0..=LAYOUT_SAMPLES {
+            let offset = if i == LAYOUT_SAMPLES {
+                last
+            } else {
+                last / LAYOUT_SAMPLES * i
+            };
+            let pa = start.add_bytes(offset);
+
+            if !round_trips(pa) {
+                panic!(
+                    "Memory layout broken: {pa} maps to {:?} in the logical map",
+                    pa.to_va::<PageOffsetTranslator>()
+                );
+            }
         }
     }
 
-    // Ensure the cache maintaince op has finished.
-    unsafe { asm!("dsb ish", options(nostack, nomem)) };
+    let image = translate_kernel_va(IMAGE_BASE);
+
+    if image != get_kimage_start() {
+        panic!(
+            "Memory layout broken: the image base translates to {image}, not {}",
+            get_kimage_start()
+        );
+    }
+}
+
+pub fn flush_to_ram<T>(x: *const T) {
+    clean_dcache_range(VA::from_value(x as usize), size_of::<T>());
+}
+
+/// The size of the smallest data cache line, in bytes.
+fn dcache_line_size() -> usize {
+    let ctr: u64;
+
+    unsafe { asm!("mrs {0}, ctr_el0", out(reg) ctr, options(nostack, nomem)) };
+
+    // CTR_EL0.DminLine is the log2 of the number of words.
+    4 << ((ctr >> 16) & 0xf)
+}
+
+/// Calls `op` with the address of each data cache line covering `va..va +
+/// len`, then waits for the maintenance to complete.
+fn for_each_dcache_line(va: VA, len: usize, op: impl Fn(usize)) {
+    let line = dcache_line_size();
+    let end = va.value() + len;
+    let mut addr = va.value() & !(line - 1);
+
+    while addr < end {
+        op(addr);
+        addr += line;
+    }
+
+    // Devices, not just other cores, may be waiting on the result.
+    unsafe { asm!("dsb sy", options(nostack)) };
+}
+
+/// Writes any dirty data cache lines covering `va..va + len` back to memory.
+pub fn clean_dcache_range(va: VA, len: usize) {
+    for_each_dcache_line(va, len, |addr| unsafe {
+        asm!("dc cvac, {0}", in(reg) addr, options(nostack))
+    });
+}
+
+/// Writes back and then invalidates the data cache lines covering `va..va +
+/// len`. Cleaning first means data sharing a line with the range isn't lost.
+pub fn clean_invalidate_dcache_range(va: VA, len: usize) {
+    for_each_dcache_line(va, len, |addr| unsafe {
+        asm!("dc civac, {0}", in(reg) addr, options(nostack))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ktest;
+    use libkernel::memory::PAGE_SIZE;
+
+    ktest! {
+        fn layout_translations_round_trip() {
+            let max = IMAGE_BASE.value() - PAGE_OFFSET;
+
+            for pa in [0, 1, PAGE_SIZE - 1, PAGE_SIZE, 0x4000_0000, max - PAGE_SIZE, max - 1] {
+                assert!(round_trips(PA::from_value(pa)), "{pa:#x} should round-trip");
+            }
+
+            // One past the end would alias the kernel image.
+            assert!(!round_trips(PA::from_value(max)));
+
+            let kimage = get_kimage_start();
+            assert_eq!(translate_kernel_va(IMAGE_BASE), kimage);
+            assert_eq!(
+                translate_kernel_va(IMAGE_BASE.add_bytes(PAGE_SIZE + 8)),
+                kimage.add_bytes(PAGE_SIZE + 8)
+            );
+
+            check_layout([PhysMemoryRegion::new(kimage, 0)]);
+            check_layout([PhysMemoryRegion::new(kimage, PAGE_SIZE)]);
+        }
+    }
 }
@@ -1,6 +1,6 @@
 use core::arch::asm;
 
-use libkernel::arch::arm64::memory::tlb::TLBInvalidator;
+use libkernel::{arch::arm64::memory::tlb::TLBInvalidator, memory::address::VA};
 
 pub struct AllEl1TlbInvalidator;
 
@@ -31,6 +31,39 @@ impl Drop for AllEl1TlbInvalidator {
 
 impl TLBInvalidator for AllEl1TlbInvalidator {}
 
+/// Invalidates the EL1 TLB entries for the single page holding a VA, leaving
+/// every other translation in place.
+pub struct El1PageTlbInvalidator {
+    va: VA,
+}
+
+impl El1PageTlbInvalidator {
+    pub fn new(va: VA) -> Self {
+        Self { va }
+    }
+}
+
+impl Drop for El1PageTlbInvalidator {
+    fn drop(&mut self) {
+        // The operand is VA[55:12], for any ASID.
+        let page = (self.va.value() >> 12) & ((1 << 44) - 1);
+
+        unsafe {
+            asm!(
+                "dsb ishst",
+                // Invalidate TLB by VA, all ASIDs, for EL1, Inner Shareable.
+                "tlbi vaae1is, {}",
+                "dsb ish",
+                "isb",
+                in(reg) page,
+                options(nostack, preserves_flags)
+            );
+        }
+    }
+}
+
+impl TLBInvalidator for El1PageTlbInvalidator {}
+
 pub struct AllEl0TlbInvalidator;
 
 impl AllEl0TlbInvalidator {
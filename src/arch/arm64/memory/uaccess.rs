@@ -102,7 +102,7 @@ fn do_copy_from_user(
             lateout("x1") work_ptr,
             lateout("x3") work_vtable,
             // Clobbers
-            out("lr") _, out("x4") _
+            out("lr") _, out("x4") _, out("x5") _, out("x6") _
         );
     }
 
@@ -204,7 +204,7 @@ impl Future for Arm64CopyStrnFromUser {
                         lateout("x1") work_ptr,
                         lateout("x3") work_vtable,
                         // Clobbers
-                        out("lr") _, out("x4") _
+                        out("lr") _, out("x4") _, out("x5") _, out("x6") _
                     );
                 }
 
@@ -265,7 +265,7 @@ impl Future for Arm64CopyToUser {
                         lateout("x1") work_ptr,
                         lateout("x3") work_vtable,
                         // Clobbers
-                        out("lr") _, out("x4") _
+                        out("lr") _, out("x4") _, out("x5") _, out("x6") _
                     );
                 }
                 (
@@ -1,5 +1,32 @@
+// Record the preceding user access instruction, labelled `\insn`, in the
+// exception fixup table. A fault on that instruction resumes at `fixup` with
+// the fault handler's status in x0.
+.macro uaccess_fixup insn
+    .pushsection .exception_fixups, "a"
+    .balign 8
+    .quad   \insn
+    .quad   fixup
+    .popsection
+.endm
+
+// Fall through if a whole word remains to be copied at offset x2 and both
+// `\src + x2` and `\dst + x2` are word aligned, otherwise branch to
+// `\bytewise`. Wide accesses are only ever made when aligned, so neither
+// SCTLR_EL1.A nor device-typed user pages can cause an alignment fault; the
+// unaligned head and tail of a buffer are copied a byte at a time.
+//
+// Clobbers x5 and x6.
+.macro check_word_copy src, dst, bytewise
+    sub     x5, x3, x2
+    cmp     x5, #8
+    b.lo    \bytewise
+    add     x5, \src, x2
+    add     x6, \dst, x2
+    orr     x5, x5, x6
+    tst     x5, #7
+    b.ne    \bytewise
+.endm
 
-__uacess_begin:
 // Copy the type 'T: UserCopyable' from userspace.
 //
 // Arguments:
@@ -18,7 +45,15 @@ __uacess_begin:
 __do_copy_from_user:
     cmp     x2, x3
     beq     1f
-    ldrb    w4, [x0, x2]
+    check_word_copy x0, x1, 20f
+13: ldr     x4, [x0, x2]
+    uaccess_fixup 13b
+    str     x4, [x1, x2]
+    add     x2, x2, #8
+    b       __do_copy_from_user
+20:
+10: ldrb    w4, [x0, x2]
+    uaccess_fixup 10b
     strb    w4, [x1, x2]
     add     x2, x2, #1
     b       __do_copy_from_user
@@ -42,7 +77,8 @@ __do_copy_from_user:
 __do_copy_from_user_halt_nul:
     cmp     x2, x3
     beq     1f
-    ldrb    w4, [x0, x2]
+11: ldrb    w4, [x0, x2]
+    uaccess_fixup 11b
     strb    w4, [x1, x2]
     cmp     w4, #0
     beq     1f
@@ -68,19 +104,19 @@ __do_copy_from_user_halt_nul:
 __do_copy_to_user:
     cmp     x2, x3
     beq     1f
+    check_word_copy x0, x1, 21f
+    ldr     x4, [x0, x2]
+14: str     x4, [x1, x2]
+    uaccess_fixup 14b
+    add     x2, x2, #8
+    b       __do_copy_to_user
+21:
     ldrb    w4, [x0, x2]
-    strb    w4, [x1, x2]
+12: strb    w4, [x1, x2]
+    uaccess_fixup 12b
     add     x2, x2, #1
     b       __do_copy_to_user
 
 1:  mov     x0, #0
-__uacess_end:
 fixup:
     ret
-
-    .section .exception_fixups
-    .global __UACCESS_FIXUP
-__UACCESS_FIXUP:
-    .quad   __uacess_begin
-    .quad   __uacess_end
-    .quad   fixup
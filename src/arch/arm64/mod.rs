@@ -1,16 +1,21 @@
 use aarch64_cpu::{
     asm::wfi,
-    registers::{DAIF, MPIDR_EL1, ReadWriteable, Readable},
+    registers::{CNTFRQ_EL0, CNTPCT_EL0, DAIF, ReadWriteable, Readable},
 };
 use alloc::string::String;
 use alloc::sync::Arc;
+use boot::topology::{logical_id, this_mpidr};
+use cpu_area::this_cpu_area;
 use cpu_ops::{local_irq_restore, local_irq_save};
 use exceptions::ExceptionState;
 use libkernel::{
     CpuOps, VirtualMemory,
     arch::arm64::memory::pg_tables::{L0Table, PgTableArray},
     error::Result,
-    memory::address::{UA, VA},
+    memory::{
+        address::{UA, VA},
+        allocators::slab::stats::HeapStats,
+    },
 };
 use memory::{
     PAGE_OFFSET,
@@ -32,19 +37,30 @@ use crate::{
 use super::Arch;
 
 mod boot;
+mod cpu_area;
 mod cpu_ops;
 mod exceptions;
 mod fdt;
+mod hwcap;
 mod memory;
+mod panic;
 mod proc;
 pub mod psci;
 pub mod ptrace;
+mod rng;
+mod spin_table;
 
 pub struct Aarch64 {}
 
 impl CpuOps for Aarch64 {
     fn id() -> usize {
-        MPIDR_EL1.read(MPIDR_EL1::Aff0) as _
+        // Once a core has its per-CPU area the id is a register read away.
+        // Before that, look its affinity up in the logical map; only the boot
+        // core runs before the map is filled in, and it is always CPU 0.
+        match this_cpu_area() {
+            Some(area) => area.cpu_id(),
+            None => logical_id(this_mpidr()).unwrap_or(0),
+        }
     }
 
     fn halt() -> ! {
@@ -62,7 +78,9 @@ impl CpuOps for Aarch64 {
     }
 
     fn enable_interrupts() {
-        DAIF.modify(DAIF::I::Unmasked);
+        // FIQs are left unmasked by IRQ critical sections too, see
+        // `interrupts::fiq`.
+        DAIF.modify(DAIF::I::Unmasked + DAIF::F::Unmasked);
     }
 }
 
@@ -141,10 +159,52 @@ impl Arch for Aarch64 {
         Self::halt()
     }
 
+    fn cpu_off() -> ! {
+        psci::psci_cpu_off()
+    }
+
+    fn dump_panic_context() {
+        panic::dump_panic_context();
+    }
+
     fn get_cmdline() -> Option<String> {
         fdt::get_cmdline()
     }
 
+    fn slab_bytes() -> usize {
+        memory::heap::SLAB_ALLOC
+            .get()
+            .map_or(0, |slab| slab.slab_bytes())
+    }
+
+    fn heap_stats() -> HeapStats {
+        memory::heap::heap_stats()
+    }
+
+    fn hw_random() -> Option<u64> {
+        rng::read_rndr()
+    }
+
+    fn user_hwcaps() -> (u64, u64) {
+        hwcap::hwcaps()
+    }
+
+    fn counter_ticks() -> u64 {
+        CNTPCT_EL0.get()
+    }
+
+    fn counter_freq() -> u64 {
+        CNTFRQ_EL0.get()
+    }
+
+    fn dcache_clean_range(va: VA, len: usize) {
+        memory::clean_dcache_range(va, len)
+    }
+
+    fn dcache_invalidate_range(va: VA, len: usize) {
+        memory::clean_invalidate_dcache_range(va, len)
+    }
+
     unsafe fn copy_from_user(
         src: UA,
         dst: *mut (),
@@ -0,0 +1,155 @@
+//! Diagnostics printed when the kernel panics.
+//!
+//! Unwinding relies on the kernel being built with frame pointers, such that
+//! `x29` always points at a `{fp, lr}` frame record on the stack. Each record
+//! is validated before it is read so that a corrupt chain ends the backtrace
+//! rather than faulting again.
+
+use super::{
+    exceptions::ExceptionState,
+    memory::{IMAGE_BASE, PAGE_OFFSET},
+};
+use core::{
+    arch::asm,
+    mem::{align_of, size_of},
+    ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+use log::error;
+
+/// Give up after this many frames; a longer chain is almost certainly a loop.
+const MAX_BACKTRACE_DEPTH: usize = 32;
+
+unsafe extern "C" {
+    static __text_start: u8;
+    static __text_end: u8;
+}
+
+/// The exception frame that caused the current panic, if any.
+static PANIC_FRAME: AtomicPtr<ExceptionState> = AtomicPtr::new(ptr::null_mut());
+
+#[repr(C)]
+struct FrameRecord {
+    fp: usize,
+    lr: usize,
+}
+
+/// Records `state` so that the panic handler can print the faulting context.
+/// Should be called by exception handlers immediately before they panic.
+pub fn record_exception_frame(state: &ExceptionState) {
+    PANIC_FRAME.store(ptr::from_ref(state).cast_mut(), Ordering::Release);
+}
+
+fn text_range() -> (usize, usize) {
+    unsafe {
+        (
+            (&raw const __text_start).addr(),
+            (&raw const __text_end).addr(),
+        )
+    }
+}
+
+fn is_valid_frame_record(fp: usize) -> bool {
+    fp >= PAGE_OFFSET
+        && fp.is_multiple_of(align_of::<FrameRecord>())
+        && fp.checked_add(size_of::<FrameRecord>()).is_some()
+}
+
+/// Walks the frame record chain starting at `fp`, calling `f` with each return
+/// address found.
+fn walk_frames(mut fp: usize, mut f: impl FnMut(usize)) {
+    for _ in 0..MAX_BACKTRACE_DEPTH {
+        if !is_valid_frame_record(fp) {
+            break;
+        }
+
+        let record = unsafe { ptr::read_volatile(fp as *const FrameRecord) };
+
+        if record.lr == 0 {
+            break;
+        }
+
+        f(record.lr);
+
+        // Stacks grow downwards, so the caller's record must live above ours.
+        if record.fp <= fp {
+            break;
+        }
+
+        fp = record.fp;
+    }
+}
+
+fn print_frame(depth: usize, addr: usize) {
+    let (text_start, text_end) = text_range();
+
+    if (text_start..text_end).contains(&addr) {
+        error!(
+            "  #{depth:<2} 0x{addr:016x} (kernel+0x{:x})",
+            addr - IMAGE_BASE.value()
+        );
+    } else {
+        error!("  #{depth:<2} 0x{addr:016x} (outside kernel text)");
+    }
+}
+
+pub fn dump_panic_context() {
+    let state = PANIC_FRAME.load(Ordering::Acquire);
+
+    let fp = if let Some(state) = unsafe { state.as_ref() } {
+        error!("Exception frame:\n{state}");
+        error!("Backtrace:");
+        print_frame(0, state.elr_el1 as usize);
+        state.x[29] as usize
+    } else {
+        let fp: usize;
+        unsafe { asm!("mov {}, x29", out(reg) fp, options(nomem, nostack)) };
+        error!("Backtrace:");
+        fp
+    };
+
+    let mut depth = 1;
+
+    walk_frames(fp, |lr| {
+        print_frame(depth, lr);
+        depth += 1;
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ktest;
+    use alloc::vec::Vec;
+
+    ktest! {
+        fn walk_frames_follows_chain() {
+            let mut records = [
+                FrameRecord { fp: 0, lr: 0x30 },
+                FrameRecord { fp: 0, lr: 0x20 },
+                FrameRecord { fp: 0, lr: 0x10 },
+            ];
+            records[0].fp = ptr::from_ref(&records[1]).addr();
+            records[1].fp = ptr::from_ref(&records[2]).addr();
+
+            let mut lrs = Vec::new();
+            walk_frames(ptr::from_ref(&records[0]).addr(), |lr| lrs.push(lr));
+
+            assert_eq!(lrs, [0x30, 0x20, 0x10]);
+        }
+    }
+
+    ktest! {
+        fn walk_frames_stops_on_bogus_fp() {
+            let mut lrs = Vec::new();
+            walk_frames(0x1234, |lr| lrs.push(lr));
+            assert!(lrs.is_empty());
+
+            // A record pointing back at itself must not loop forever.
+            let mut record = FrameRecord { fp: 0, lr: 0x40 };
+            record.fp = ptr::from_ref(&record).addr();
+            walk_frames(record.fp, |lr| lrs.push(lr));
+            assert_eq!(lrs, [0x40]);
+        }
+    }
+}
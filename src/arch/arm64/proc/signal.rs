@@ -7,6 +7,7 @@ use crate::{
     },
     sched::current::current_task,
 };
+use core::mem::offset_of;
 use libkernel::{
     error::Result,
     memory::{
@@ -15,9 +16,23 @@ use libkernel::{
     },
 };
 
+/// The layout of `siginfo_t`: a common header followed by a union, of which
+/// only the `si_addr` of a fault is filled in.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RtSigInfo {
+    signo: i32,
+    errno: i32,
+    code: i32,
+    _pad: i32,
+    addr: u64,
+    _rest: [u64; 13],
+}
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 struct RtSigFrame {
+    info: RtSigInfo,
     uctx: ExceptionState,
     alt_stack_prev_addr: UA,
 }
@@ -27,12 +42,21 @@ struct RtSigFrame {
 unsafe impl UserCopyable for RtSigFrame {}
 
 pub async fn do_signal(id: SigId, sa: UserspaceSigAction) -> Result<ExceptionState> {
-    let task = current_task();
+    let mut task = current_task();
+    let fault = task.fault_info.take_if(|fault| fault.signal == id);
     let mut signal = task.process.signals.lock_save_irq();
 
     let saved_state = *task.ctx.user();
     let mut new_state = saved_state;
     let mut frame = RtSigFrame {
+        info: RtSigInfo {
+            signo: id.user_id() as _,
+            errno: 0,
+            code: fault.map_or(0, |fault| fault.code),
+            _pad: 0,
+            addr: fault.map_or(0, |fault| fault.addr.value() as _),
+            _rest: [0; 13],
+        },
         uctx: saved_state,
         alt_stack_prev_addr: UA::null(),
     };
@@ -63,6 +87,11 @@ pub async fn do_signal(id: SigId, sa: UserspaceSigAction) -> Result<ExceptionSta
     new_state.x[30] = restorer as _;
     new_state.x[0] = id.user_id();
 
+    if sa.flags.contains(SigActionFlags::SA_SIGINFO) {
+        new_state.x[1] = addr.value() as u64 + offset_of!(RtSigFrame, info) as u64;
+        new_state.x[2] = addr.value() as u64 + offset_of!(RtSigFrame, uctx) as u64;
+    }
+
     Ok(new_state)
 }
 
@@ -1,3 +1,4 @@
+use super::boot::park_cpu;
 use core::arch::naked_asm;
 use libkernel::memory::address::PA;
 
@@ -12,6 +13,7 @@ pub enum PSCIMethod {
 }
 
 const CPU_ON_ID: u32 = 0xc400_0003;
+const CPU_OFF_ID: u32 = 0x8400_0002;
 
 // Re-export the low-level PSCI helpers so other modules (e.g. `arch::arm64::mod`)
 // can invoke them without repeating the `use` dance.
@@ -39,6 +41,16 @@ pub fn boot_secondary_psci(entry: PSCIEntry, core_id: usize, entry_fn: PA, ctx:
     };
 }
 
+/// Powers down the calling core. Falls back to parking the core if firmware
+/// refuses the request.
+pub fn psci_cpu_off() -> ! {
+    unsafe {
+        do_psci_hyp_call(CPU_OFF_ID, 0, 0, 0);
+    }
+
+    park_cpu()
+}
+
 #[unsafe(naked)]
 pub unsafe extern "C" fn do_psci_hyp_call(id: u32, arg1: u64, arg2: u64, arg3: u64) -> i64 {
     naked_asm!("hvc #0", "ret")
@@ -0,0 +1,57 @@
+//! Access to the `FEAT_RNG` random number registers.
+
+use core::arch::asm;
+
+/// Returns true if `ID_AA64ISAR0_EL1` advertises the `RNDR` register.
+fn has_rndr(isar0: u64) -> bool {
+    // ID_AA64ISAR0_EL1.RNDR, bits [63:60].
+    (isar0 >> 60) & 0xf != 0
+}
+
+pub(super) fn read_isar0() -> u64 {
+    let isar0: u64;
+
+    unsafe { asm!("mrs {}, id_aa64isar0_el1", out(reg) isar0, options(nomem, nostack)) };
+
+    isar0
+}
+
+/// Reads a random value from `RNDR`, if the CPU implements it. The register
+/// can fail to produce a value, in which case `None` is also returned.
+pub fn read_rndr() -> Option<u64> {
+    if !has_rndr(read_isar0()) {
+        return None;
+    }
+
+    let val: u64;
+    let ok: u64;
+
+    // RNDR is s3_3_c2_c4_0; spelt out so no `+rng` target feature is needed.
+    // NZCV is 0b0000 on success and 0b0100 on failure.
+    unsafe {
+        asm!(
+            "mrs {val}, s3_3_c2_c4_0",
+            "cset {ok}, ne",
+            val = out(reg) val,
+            ok = out(reg) ok,
+            options(nomem, nostack),
+        )
+    };
+
+    (ok != 0).then_some(val)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ktest;
+
+    ktest! {
+        fn rndr_feature_detection() {
+            assert!(!has_rndr(0));
+            // Other ISAR0 fields must not be mistaken for RNDR.
+            assert!(!has_rndr(0x0fff_ffff_ffff_ffff));
+            assert!(has_rndr(1 << 60));
+        }
+    }
+}
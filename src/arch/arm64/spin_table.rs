@@ -0,0 +1,58 @@
+use super::memory::flush_to_ram;
+use crate::memory::PageOffsetTranslator;
+use aarch64_cpu::asm::sev;
+use libkernel::memory::address::PA;
+
+/// Publishes `entry_fn` in a spin-table release slot.
+///
+/// The spinning core polls the slot with its MMU, and therefore its caches,
+/// off, so the write has to be cleaned to the point of coherency before the
+/// core is woken.
+///
+/// # Safety
+///
+/// `release` must point to a valid, mapped release slot.
+unsafe fn write_release_addr(release: *mut u64, entry_fn: PA) {
+    unsafe { release.write_volatile(entry_fn.value() as u64) };
+
+    // This ends with a `dsb`, so the clean has completed before any `sev`
+    // that follows.
+    flush_to_ram(release);
+}
+
+/// Releases a core parked in a firmware spin-table loop, sending it to
+/// `entry_fn`.
+///
+/// The core enters with undefined registers, so `entry_fn` must find its
+/// boot context by itself.
+pub fn boot_secondary_spin_table(release_addr: PA, entry_fn: PA) {
+    let release = release_addr
+        .cast::<u64>()
+        .to_va::<PageOffsetTranslator>()
+        .as_ptr_mut();
+
+    // SAFETY: The release address lies in RAM described by the FDT, which the
+    // logical map covers.
+    unsafe { write_release_addr(release, entry_fn) };
+
+    sev();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ktest, memory::page::ClaimedPage};
+
+    ktest! {
+        fn spin_table_release_slot_is_written() {
+            let page = ClaimedPage::alloc_zeroed().unwrap();
+            let release = page.pa().cast::<u64>().to_va::<PageOffsetTranslator>();
+            let slot = unsafe { release.as_ptr_mut().add(1) };
+
+            unsafe { write_release_addr(slot, PA::from_value(0x4008_0000)) };
+
+            assert_eq!(unsafe { slot.read_volatile() }, 0x4008_0000);
+            assert_eq!(unsafe { release.as_ptr().read_volatile() }, 0);
+        }
+    }
+}
@@ -21,7 +21,10 @@ use alloc::sync::Arc;
 use libkernel::{
     CpuOps, VirtualMemory,
     error::Result,
-    memory::address::{UA, VA},
+    memory::{
+        address::{UA, VA},
+        allocators::slab::stats::HeapStats,
+    },
 };
 
 pub trait Arch: CpuOps + VirtualMemory {
@@ -55,8 +58,45 @@ pub trait Arch: CpuOps + VirtualMemory {
     /// Restarts the machine. Implementations must never return.
     fn restart() -> !;
 
+    /// Takes the calling CPU offline. Implementations must never return.
+    fn cpu_off() -> !;
+
+    /// Prints whatever register state and backtrace is available for the
+    /// current kernel panic.
+    fn dump_panic_context();
+
     fn get_cmdline() -> Option<String>;
 
+    /// Returns the number of bytes of physical memory currently held by the
+    /// kernel heap's slab allocator.
+    fn slab_bytes() -> usize;
+
+    /// Returns how much of the kernel heap is in use, and by what.
+    fn heap_stats() -> HeapStats;
+
+    /// Returns a value from the CPU's hardware random number generator, or
+    /// `None` if there isn't one or it failed to produce a value.
+    fn hw_random() -> Option<u64>;
+
+    /// Returns the `AT_HWCAP` and `AT_HWCAP2` values describing the CPU
+    /// features available to userspace.
+    fn user_hwcaps() -> (u64, u64);
+
+    /// Reads the CPU's free-running counter. Unlike the system timer, this
+    /// can be read before any timer driver has been probed.
+    fn counter_ticks() -> u64;
+
+    /// Returns the frequency of [`Arch::counter_ticks`] in Hz.
+    fn counter_freq() -> u64;
+
+    /// Writes the CPU's cached copy of `va..va + len` back to memory, so that
+    /// a device reading it sees what the CPU wrote.
+    fn dcache_clean_range(va: VA, len: usize);
+
+    /// Writes back and discards the CPU's cached copy of `va..va + len`, so
+    /// that the CPU's next reads see what a device wrote to memory.
+    fn dcache_invalidate_range(va: VA, len: usize);
+
     /// Call a user-specified signal handler in the current process.
     fn do_signal(
         sig: SigId,
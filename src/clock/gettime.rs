@@ -12,8 +12,13 @@ use crate::{drivers::timer::uptime, memory::uaccess::copy_to_user};
 
 pub async fn sys_clock_gettime(clockid: i32, time_spec: TUA<TimeSpec>) -> Result<usize> {
     let time = match ClockId::try_from(clockid).map_err(|_| KernelError::InvalidValue)? {
-        ClockId::Monotonic => uptime(),
-        ClockId::Realtime => date(),
+        // There's no NTP slewing or suspend, so the monotonic clocks all read
+        // the same counter.
+        ClockId::Monotonic
+        | ClockId::MonotonicRaw
+        | ClockId::MonotonicCoarse
+        | ClockId::BootTime => uptime(),
+        ClockId::Realtime | ClockId::RealtimeCoarse => date(),
         ClockId::ProcessCpuTimeId => {
             let task = current_task_shared();
             let total_time = task.process.stime.load(Ordering::Relaxed) as u64
@@ -3,6 +3,7 @@ pub mod realtime;
 pub mod settime;
 pub mod timeofday;
 pub mod timespec;
+pub mod vvar;
 
 pub enum ClockId {
     Monotonic = 0,
@@ -1,8 +1,10 @@
+use super::vvar;
 use crate::{
     drivers::timer::{Instant, now, uptime},
     sync::SpinLock,
 };
 use core::time::Duration;
+use log::warn;
 
 // Return a duration from the epoch.
 pub fn date() -> Duration {
@@ -22,6 +24,10 @@ pub fn set_date(duration: Duration) {
     if let Some(now) = now() {
         let mut epoch_info = EPOCH_DURATION.lock_save_irq();
         *epoch_info = Some((duration, now));
+
+        if let Err(e) = vvar::set_realtime(duration, now) {
+            warn!("Failed to publish the date to the vvar page: {e}");
+        }
     }
 }
 
@@ -0,0 +1,189 @@
+//! The timekeeping page shared read-only with userspace.
+//!
+//! Every process gets the same physical page mapped at [`VVAR_BASE`], allowing
+//! a userspace vDSO to compute the time from the architectural counter
+//! (`CNTPCT_EL0`, which EL0 is permitted to read) without making a syscall:
+//!
+//! ```text
+//! do {
+//!     seq = data.seq;                      // retry while odd
+//!     ticks = CNTPCT_EL0;
+//!     mono = (ticks - data.boot_ticks) / data.freq;
+//!     real = data.realtime + (ticks - data.realtime_ticks) / data.freq;
+//! } while (seq & 1 || seq != data.seq);
+//! ```
+//!
+//! The page is mapped without write or execute permission for EL0 and its VMA
+//! is sealed, so it can't be `mprotect`ed writable either.
+
+use crate::{
+    drivers::timer::Instant,
+    memory::{PAGE_ALLOC, page::ClaimedPage},
+    sync::{OnceLock, SpinLock},
+};
+use core::{
+    sync::atomic::{AtomicU32, AtomicU64, Ordering, fence},
+    time::Duration,
+};
+use libkernel::{
+    UserAddressSpace,
+    error::Result,
+    memory::{
+        PAGE_SIZE,
+        address::VA,
+        permissions::PtePermissions,
+        proc_vm::vmarea::{VMAPermissions, VMArea, VMAreaKind},
+        region::VirtMemoryRegion,
+    },
+};
+
+/// The address of the timekeeping page in every user address space.
+pub const VVAR_BASE: VA = VA::from_value(0x0000_7fff_0000_0000);
+
+/// The layout of the timekeeping page, as seen by userspace.
+#[repr(C)]
+pub struct VvarData {
+    /// Sequence counter; odd whilst the kernel is updating the page.
+    pub seq: AtomicU32,
+    _pad: u32,
+    /// Counter frequency in Hz.
+    pub freq: AtomicU64,
+    /// Counter value at boot, i.e. at `CLOCK_MONOTONIC` zero.
+    pub boot_ticks: AtomicU64,
+    /// Counter value at which `realtime_sec`/`realtime_nsec` were sampled.
+    pub realtime_ticks: AtomicU64,
+    pub realtime_sec: AtomicU64,
+    pub realtime_nsec: AtomicU64,
+}
+
+static VVAR_PAGE: OnceLock<ClaimedPage> = OnceLock::new();
+
+/// Serialises writers of the page.
+static VVAR_LOCK: SpinLock<()> = SpinLock::new(());
+
+fn vvar_page() -> Result<&'static ClaimedPage> {
+    if let Some(page) = VVAR_PAGE.get() {
+        return Ok(page);
+    }
+
+    // If we lose a race here, the other page is used and ours is freed.
+    let _ = VVAR_PAGE.set(ClaimedPage::alloc_zeroed()?);
+
+    Ok(VVAR_PAGE.get().unwrap())
+}
+
+fn vvar_data(page: &ClaimedPage) -> &VvarData {
+    // SAFETY: The page is zero-initialised, page-aligned and outlives the
+    // kernel. All fields are atomics, so shared access is sound.
+    unsafe { &*page.as_ptr().cast::<VvarData>() }
+}
+
+/// Runs `f` on the page, bumping the sequence counter either side of it so
+/// readers can detect a torn read.
+fn update(f: impl FnOnce(&VvarData)) -> Result<()> {
+    let data = vvar_data(vvar_page()?);
+    let _guard = VVAR_LOCK.lock_save_irq();
+
+    data.seq.fetch_add(1, Ordering::Relaxed);
+    fence(Ordering::Release);
+
+    f(data);
+
+    data.seq.fetch_add(1, Ordering::Release);
+
+    Ok(())
+}
+
+/// Publishes the counter frequency and the instant that monotonic time is
+/// measured from. Called once, when the system timer is set up.
+pub fn set_boot_time(start: Instant) -> Result<()> {
+    update(|data| {
+        data.freq.store(start.freq(), Ordering::Relaxed);
+        data.boot_ticks.store(start.ticks(), Ordering::Relaxed);
+
+        // Until the date is set, the wall clock reads as the uptime.
+        data.realtime_ticks.store(start.ticks(), Ordering::Relaxed);
+    })
+}
+
+/// Publishes that the wall-clock time was `date` at `at`.
+pub fn set_realtime(date: Duration, at: Instant) -> Result<()> {
+    update(|data| {
+        data.realtime_ticks.store(at.ticks(), Ordering::Relaxed);
+        data.realtime_sec.store(date.as_secs(), Ordering::Relaxed);
+        data.realtime_nsec
+            .store(date.subsec_nanos() as u64, Ordering::Relaxed);
+    })
+}
+
+/// Returns the VMA describing the timekeeping page, for inclusion in a new
+/// process's memory map.
+pub fn vvar_vma() -> VMArea {
+    let mut vma = VMArea::new(
+        VirtMemoryRegion::new(VVAR_BASE, PAGE_SIZE),
+        VMAreaKind::Anon,
+        VMAPermissions::ro(),
+    );
+
+    vma.set_name("[vvar]");
+    vma.seal();
+
+    vma
+}
+
+/// Maps the timekeeping page into a freshly built process address space, at
+/// the location described by [`vvar_vma`].
+pub fn map_vvar<AS: UserAddressSpace>(addr_space: &mut AS) -> Result<()> {
+    let page = vvar_page()?;
+
+    addr_space.map_page(page.pa().to_pfn(), VVAR_BASE, PtePermissions::ro(true))?;
+
+    // Take a reference on behalf of the new mapping; it's dropped by the
+    // address-space tear-down code.
+    let alloc = unsafe {
+        PAGE_ALLOC
+            .get()
+            .unwrap()
+            .alloc_from_region(page.pa().to_pfn().as_phys_range())
+    };
+
+    alloc.clone().leak();
+    alloc.leak();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{arch::ArchImpl, drivers::timer::now, ktest};
+    use libkernel::VirtualMemory;
+
+    ktest! {
+        fn vvar_is_read_only_for_el0() {
+            let mut addr_space = <ArchImpl as VirtualMemory>::ProcessAddressSpace::new().unwrap();
+
+            map_vvar(&mut addr_space).unwrap();
+
+            let info = addr_space.translate(VVAR_BASE).unwrap();
+            assert_eq!(info.pfn, vvar_page().unwrap().pa().to_pfn());
+            assert!(info.perms.is_user() && info.perms.is_read());
+            assert!(!info.perms.is_write() && !info.perms.is_execute());
+        }
+    }
+
+    ktest! {
+        fn vvar_updates_bump_sequence() {
+            let data = vvar_data(vvar_page().unwrap());
+            let seq = data.seq.load(Ordering::Acquire);
+            let at = now().unwrap();
+
+            set_realtime(Duration::new(1_000_000, 5), at).unwrap();
+
+            assert_eq!(data.seq.load(Ordering::Acquire), seq + 2);
+            assert_eq!(data.realtime_ticks.load(Ordering::Relaxed), at.ticks());
+            assert_eq!(data.realtime_sec.load(Ordering::Relaxed), 1_000_000);
+            assert_eq!(data.realtime_nsec.load(Ordering::Relaxed), 5);
+        }
+    }
+}
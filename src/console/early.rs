@@ -0,0 +1,132 @@
+//! The early boot console.
+//!
+//! Until the console's driver is probed, output is kept in a memory buffer. A
+//! hang before then would leave nothing on the serial line, so the architecture
+//! can hand over a bare UART it has mapped itself, which only needs to be able
+//! to send a byte. Everything buffered so far is written to it, and it gets a
+//! copy of all output from then on.
+//!
+//! When the real console driver takes over the early UART's device, the buffer
+//! has already been written out and isn't replayed a second time. Either way,
+//! the early UART is released once the handoff is done.
+
+use core::fmt::{self, Write};
+use libkernel::memory::address::PA;
+
+/// A UART that can be driven without its driver, by polling.
+pub trait EarlyUart: Send {
+    /// Sends `byte`, waiting for room in the transmit FIFO. Returns `false` if
+    /// there was none in time and the byte was dropped.
+    fn send_byte(&mut self, byte: u8) -> bool;
+}
+
+/// Formats into an [`EarlyUart`], turning `\n` into `\r\n` unless it already
+/// follows a `\r`.
+pub(super) struct EarlyWriter<'a> {
+    uart: &'a mut dyn EarlyUart,
+    last: u8,
+}
+
+impl<'a> EarlyWriter<'a> {
+    pub(super) fn new(uart: &'a mut dyn EarlyUart) -> Self {
+        Self { uart, last: 0 }
+    }
+}
+
+impl Write for EarlyWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &b in s.as_bytes() {
+            if b == b'\n' && self.last != b'\r' {
+                self.uart.send_byte(b'\r');
+            }
+
+            self.uart.send_byte(b);
+            self.last = b;
+        }
+
+        Ok(())
+    }
+}
+
+/// A UART handed over by the architecture to act as the early console.
+pub struct EarlyConsole {
+    pub uart: &'static mut dyn EarlyUart,
+    /// The physical address of the UART's registers, used to tell whether the
+    /// real console is the same device.
+    pub base: PA,
+    /// Tears down the architecture's mapping of the registers. Called once the
+    /// real console has taken over and `uart` is no longer used.
+    pub release: fn(),
+}
+
+/// Makes `console` the early console, writing out everything buffered so far.
+/// Does nothing once a real console is active.
+pub fn register(console: EarlyConsole) {
+    super::set_early_console(console);
+}
+
+/// Writes straight to the early console, or to the console as usual if there
+/// isn't one. Used through [`early_print!`](crate::early_print).
+pub fn print(args: fmt::Arguments) {
+    super::write_early(args);
+}
+
+/// Prints to the early console without going through the logger, for code
+/// that runs before it can be relied on.
+#[macro_export]
+macro_rules! early_print {
+    ($($arg:tt)*) => {
+        $crate::console::early::print(format_args!($($arg)*))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ktest;
+
+    /// A register bank that records every byte written to its data register.
+    struct MockUart {
+        data: [u8; 64],
+        len: usize,
+    }
+
+    impl EarlyUart for MockUart {
+        fn send_byte(&mut self, byte: u8) -> bool {
+            let Some(slot) = self.data.get_mut(self.len) else {
+                return false;
+            };
+
+            *slot = byte;
+            self.len += 1;
+
+            true
+        }
+    }
+
+    impl MockUart {
+        fn sent(&self) -> &[u8] {
+            &self.data[..self.len]
+        }
+    }
+
+    ktest! {
+        fn early_print_formats_into_registers() {
+            let mut uart = MockUart { data: [0; 64], len: 0 };
+
+            write!(EarlyWriter::new(&mut uart), "x={} {:#x}\n", 42, 255u8).unwrap();
+            assert_eq!(uart.sent(), b"x=42 0xff\r\n");
+
+            // Line endings that are already right are left alone, including
+            // across separately formatted pieces.
+            uart.len = 0;
+            write!(EarlyWriter::new(&mut uart), "a\r\nb{}\n", '\r').unwrap();
+            assert_eq!(uart.sent(), b"a\r\nb\r\n");
+
+            // A full FIFO drops bytes rather than hanging.
+            uart.len = 60;
+            write!(EarlyWriter::new(&mut uart), "hello\n").unwrap();
+            assert_eq!(&uart.sent()[60..], b"hell");
+        }
+    }
+}
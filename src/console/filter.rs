@@ -0,0 +1,220 @@
+//! Log level filtering for the console logger.
+//!
+//! The filter holds a default level and an optional set of per-module
+//! overrides. Directives are given as a comma-separated list where each entry
+//! is either a bare level (the new default) or `module=level`, e.g.
+//! `info,drivers=warn,drivers::uart=trace`. Module paths may omit the leading
+//! `moss::`. When several overrides match a record, the most specific one wins.
+
+use crate::sync::SpinLock;
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{
+    fmt::{self, Display},
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+};
+use libkernel::error::{KernelError, Result};
+use log::{LevelFilter, Metadata};
+
+pub(super) fn level_from_u8(level: u8) -> LevelFilter {
+    match level {
+        0 => LevelFilter::Off,
+        1 => LevelFilter::Error,
+        2 => LevelFilter::Warn,
+        3 => LevelFilter::Info,
+        4 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+fn strip_crate(path: &str) -> &str {
+    path.strip_prefix("moss::")
+        .or_else(|| (path == "moss").then_some(""))
+        .unwrap_or(path)
+}
+
+/// Returns whether `module` is `prefix` or one of its submodules.
+fn is_submodule(module: &str, prefix: &str) -> bool {
+    prefix.is_empty()
+        || module
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+}
+
+pub struct LogFilter {
+    default: AtomicU8,
+    /// Set whenever `modules` is non-empty, so that records can be checked
+    /// against the default level without taking the lock.
+    has_overrides: AtomicBool,
+    modules: SpinLock<Vec<(String, LevelFilter)>>,
+}
+
+impl LogFilter {
+    pub const fn new(default: LevelFilter) -> Self {
+        Self {
+            default: AtomicU8::new(default as u8),
+            has_overrides: AtomicBool::new(false),
+            modules: SpinLock::new(Vec::new()),
+        }
+    }
+
+    /// The level records are checked against when no override matches.
+    pub fn default_level(&self) -> LevelFilter {
+        level_from_u8(self.default.load(Ordering::Relaxed))
+    }
+
+    pub fn set_default_level(&self, level: LevelFilter) {
+        self.default.store(level as u8, Ordering::Relaxed);
+    }
+
+    /// Overrides the level for `module` and its submodules.
+    pub fn set_module_level(&self, module: &str, level: LevelFilter) {
+        let module = strip_crate(module);
+        let mut modules = self.modules.lock_save_irq();
+
+        match modules.iter_mut().find(|(name, _)| name == module) {
+            Some((_, old)) => *old = level,
+            None => modules.push((module.to_string(), level)),
+        }
+
+        self.has_overrides.store(true, Ordering::Relaxed);
+    }
+
+    /// Drops all per-module overrides.
+    pub fn clear_module_levels(&self) {
+        self.modules.lock_save_irq().clear();
+        self.has_overrides.store(false, Ordering::Relaxed);
+    }
+
+    /// Returns the level that applies to records from `module`.
+    pub fn level_for(&self, module: &str) -> LevelFilter {
+        if !self.has_overrides.load(Ordering::Relaxed) {
+            return self.default_level();
+        }
+
+        let module = strip_crate(module);
+
+        self.modules
+            .lock_save_irq()
+            .iter()
+            .filter(|(prefix, _)| is_submodule(module, prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, level)| *level)
+            .unwrap_or_else(|| self.default_level())
+    }
+
+    pub fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+
+    fn parse(directives: &str) -> Result<Vec<(Option<&str>, LevelFilter)>> {
+        directives
+            .split(',')
+            .map(str::trim)
+            .filter(|directive| !directive.is_empty())
+            .map(|directive| {
+                let (module, level) = match directive.split_once('=') {
+                    Some((module, level)) => (Some(module.trim()), level.trim()),
+                    None => (None, directive),
+                };
+
+                let level = level.parse().map_err(|_| KernelError::InvalidValue)?;
+                Ok((module, level))
+            })
+            .collect()
+    }
+
+    /// Applies a comma-separated list of directives on top of the current
+    /// ones. Nothing is changed unless every directive is valid.
+    pub fn apply(&self, directives: &str) -> Result<()> {
+        for (module, level) in Self::parse(directives)? {
+            match module {
+                Some(module) => self.set_module_level(module, level),
+                None => self.set_default_level(level),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Like [`LogFilter::apply`], but first drops the existing per-module
+    /// overrides.
+    pub fn replace(&self, directives: &str) -> Result<()> {
+        Self::parse(directives)?;
+        self.clear_module_levels();
+        self.apply(directives)
+    }
+}
+
+/// Formats the filter as directives that [`LogFilter::apply`] accepts.
+impl Display for LogFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.default_level().as_str().to_lowercase())?;
+
+        for (module, level) in self.modules.lock_save_irq().iter() {
+            write!(f, ",{module}={}", level.as_str().to_lowercase())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ktest;
+    use log::Level;
+
+    fn enabled(filter: &LogFilter, target: &str, level: Level) -> bool {
+        filter.enabled(&Metadata::builder().target(target).level(level).build())
+    }
+
+    ktest! {
+        fn log_filter_suppresses_lower_severity() {
+            let filter = LogFilter::new(LevelFilter::Trace);
+            assert!(enabled(&filter, "moss::drivers::uart", Level::Trace));
+
+            filter.set_default_level(LevelFilter::Warn);
+            assert!(enabled(&filter, "moss::drivers::uart", Level::Error));
+            assert!(enabled(&filter, "moss::drivers::uart", Level::Warn));
+            assert!(!enabled(&filter, "moss::drivers::uart", Level::Info));
+            assert!(!enabled(&filter, "moss::drivers::uart", Level::Trace));
+
+            filter.set_default_level(LevelFilter::Off);
+            assert!(!enabled(&filter, "moss::drivers::uart", Level::Error));
+        }
+    }
+
+    ktest! {
+        fn log_filter_module_overrides() {
+            let filter = LogFilter::new(LevelFilter::Info);
+            filter
+                .apply("warn, drivers=error,moss::drivers::uart=debug")
+                .unwrap();
+
+            assert_eq!(filter.default_level(), LevelFilter::Warn);
+            assert!(!enabled(&filter, "moss::drivers::fdt_prober", Level::Warn));
+            assert!(enabled(&filter, "moss::drivers::fdt_prober", Level::Error));
+            assert!(enabled(&filter, "moss::drivers::uart::pl011", Level::Debug));
+            assert!(!enabled(&filter, "moss::drivers::uart", Level::Trace));
+            // Only whole path segments match.
+            assert!(enabled(&filter, "moss::drivers_extra", Level::Warn));
+            assert!(!enabled(&filter, "moss::fs", Level::Info));
+            assert_eq!(
+                filter.to_string(),
+                "warn,drivers=error,drivers::uart=debug"
+            );
+
+            // A bad directive leaves the filter untouched.
+            assert!(filter.apply("info,fs=loud").is_err());
+            assert_eq!(filter.default_level(), LevelFilter::Warn);
+
+            filter.replace("fs=info").unwrap();
+            assert_eq!(filter.to_string(), "warn,fs=info");
+            assert!(!enabled(&filter, "moss::drivers::uart", Level::Info));
+            assert!(enabled(&filter, "moss::fs::pipe", Level::Info));
+        }
+    }
+}
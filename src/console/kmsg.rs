@@ -0,0 +1,237 @@
+//! The kernel log buffer.
+//!
+//! Every log record is kept in a fixed-size ring of records, whether or not
+//! the console filter lets it through, so boot messages can be read back with
+//! `dmesg` long after they scrolled off the console. Once the ring is full, each new record overwrites
+//! the oldest one.
+
+use crate::sync::SpinLock;
+use alloc::string::String;
+use core::fmt::{self, Write};
+use log::Level;
+
+/// The number of records the kernel log holds.
+const KMSG_RECORDS: usize = 1024;
+
+/// Longer messages are truncated to this many bytes.
+const KMSG_RECORD_LEN: usize = 224;
+
+pub static KMSG: SpinLock<LogRing<KMSG_RECORDS>> = SpinLock::new(LogRing::new());
+
+pub struct LogRecord {
+    seq: u64,
+    ticks: u64,
+    /// `log::Level as u8`, or 0 for an unused slot.
+    level: u8,
+    len: u16,
+    text: [u8; KMSG_RECORD_LEN],
+}
+
+impl LogRecord {
+    const EMPTY: Self = Self {
+        seq: 0,
+        ticks: 0,
+        level: 0,
+        len: 0,
+        text: [0; KMSG_RECORD_LEN],
+    };
+
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    pub fn level(&self) -> Level {
+        match self.level {
+            1 => Level::Error,
+            2 => Level::Warn,
+            3 => Level::Info,
+            4 => Level::Debug,
+            _ => Level::Trace,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        // `write_str` only ever cuts on a character boundary.
+        core::str::from_utf8(&self.text[..self.len as usize]).unwrap_or_default()
+    }
+
+    /// Formats the record the way `syslog(2)` returns it: the syslog priority,
+    /// the time since the counter started, and the text.
+    pub fn format(&self, freq: u64, out: &mut String) {
+        let prio = match self.level() {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug | Level::Trace => 7,
+        };
+        let freq = freq.max(1);
+        let secs = self.ticks / freq;
+        let micros = (self.ticks % freq) * 1_000_000 / freq;
+
+        let _ = writeln!(out, "<{prio}>[{secs:5}.{micros:06}] {}", self.text());
+    }
+}
+
+impl Write for LogRecord {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let start = self.len as usize;
+        let mut len = usize::min(KMSG_RECORD_LEN - start, s.len());
+
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        self.text[start..start + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len as u16;
+        Ok(())
+    }
+}
+
+/// A ring of the `N` most recent log records.
+///
+/// Records are numbered by a sequence number that counts every record ever
+/// pushed, which lets readers remember where they left off.
+pub struct LogRing<const N: usize> {
+    records: [LogRecord; N],
+    next_seq: u64,
+    /// How many records have been overwritten. This is also the sequence
+    /// number of the oldest record still held.
+    dropped: u64,
+}
+
+impl<const N: usize> LogRing<N> {
+    pub const fn new() -> Self {
+        Self {
+            records: [LogRecord::EMPTY; N],
+            next_seq: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Appends a record, overwriting the oldest one if the ring is full.
+    ///
+    /// The message is formatted straight into the ring, so this doesn't need
+    /// the heap and can be used from the earliest point in boot.
+    pub fn push(&mut self, ticks: u64, level: Level, args: fmt::Arguments) {
+        if self.next_seq - self.dropped == N as u64 {
+            self.dropped += 1;
+        }
+
+        let record = &mut self.records[(self.next_seq % N as u64) as usize];
+        record.seq = self.next_seq;
+        record.ticks = ticks;
+        record.level = level as u8;
+        record.len = 0;
+        let _ = record.write_fmt(args);
+
+        self.next_seq += 1;
+    }
+
+    /// The sequence number the next record will be given.
+    pub fn next_seq(&self) -> u64 {
+        self.next_seq
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Iterates, oldest first, over the records numbered `seq` onwards that
+    /// haven't been overwritten yet.
+    pub fn records_from(&self, seq: u64) -> impl Iterator<Item = &LogRecord> {
+        (seq.max(self.dropped)..self.next_seq)
+            .map(move |seq| &self.records[(seq % N as u64) as usize])
+    }
+
+    /// The capacity of the ring, in bytes of message text.
+    pub const fn text_capacity(&self) -> usize {
+        N * KMSG_RECORD_LEN
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        console::{console_level, set_console_level},
+        ktest,
+    };
+    use alloc::{boxed::Box, vec::Vec};
+    use log::LevelFilter;
+
+    ktest! {
+        fn kmsg_records_read_back_in_order() {
+            let mut ring: Box<LogRing<4>> = Box::new(LogRing::new());
+
+            ring.push(10, Level::Info, format_args!("first"));
+            ring.push(20, Level::Warn, format_args!("second {}", 2));
+            ring.push(30, Level::Error, format_args!("third"));
+
+            let texts: Vec<_> = ring.records_from(0).map(LogRecord::text).collect();
+            assert_eq!(texts, ["first", "second 2", "third"]);
+            assert_eq!(ring.dropped(), 0);
+
+            let mut out = String::new();
+            for record in ring.records_from(1) {
+                record.format(10, &mut out);
+            }
+            assert_eq!(out, "<4>[    2.000000] second 2\n<3>[    3.000000] third\n");
+        }
+    }
+
+    ktest! {
+        fn kmsg_wrap_drops_oldest() {
+            let mut ring: Box<LogRing<4>> = Box::new(LogRing::new());
+
+            for i in 0..6 {
+                ring.push(i, Level::Debug, format_args!("msg {i}"));
+            }
+
+            assert_eq!(ring.next_seq(), 6);
+            assert_eq!(ring.dropped(), 2);
+
+            let records: Vec<_> = ring
+                .records_from(0)
+                .map(|record| (record.seq(), record.text()))
+                .collect();
+            assert_eq!(
+                records,
+                [(2, "msg 2"), (3, "msg 3"), (4, "msg 4"), (5, "msg 5")]
+            );
+            assert_eq!(ring.records_from(5).count(), 1);
+            assert_eq!(ring.records_from(6).count(), 0);
+            assert_eq!(ring.records_from(0).next().unwrap().level(), Level::Debug);
+        }
+    }
+
+    ktest! {
+        fn kmsg_keeps_records_hidden_from_console() {
+            let seq = KMSG.lock_save_irq().next_seq();
+            let saved = console_level();
+
+            set_console_level(LevelFilter::Off);
+            log::debug!("kept off the console");
+            set_console_level(saved);
+
+            assert!(
+                KMSG.lock_save_irq()
+                    .records_from(seq)
+                    .any(|record| record.text().ends_with("kept off the console"))
+            );
+        }
+    }
+
+    ktest! {
+        fn kmsg_truncates_long_records() {
+            let mut ring: Box<LogRing<1>> = Box::new(LogRing::new());
+            let long = "é".repeat(KMSG_RECORD_LEN);
+
+            ring.push(0, Level::Info, format_args!("{long}"));
+
+            let text = ring.records_from(0).next().unwrap().text();
+            // A two-byte character never gets split.
+            assert_eq!(text.len(), KMSG_RECORD_LEN);
+            assert!(text.chars().all(|c| c == 'é'));
+        }
+    }
+}
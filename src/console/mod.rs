@@ -1,19 +1,36 @@
-use alloc::sync::{Arc, Weak};
+use alloc::{
+    boxed::Box,
+    sync::{Arc, Weak},
+};
 use core::{
     fmt::{self, Write},
+    pin::Pin,
     ptr::addr_of_mut,
     str,
+    sync::atomic::{AtomicU8, Ordering},
 };
-use libkernel::{driver::CharDevDescriptor, error::KernelError};
-use log::{LevelFilter, Log};
+use libkernel::{driver::CharDevDescriptor, error::KernelError, memory::address::PA};
+use log::{LevelFilter, Log, warn};
 use tty::TtyInputHandler;
 
-use crate::{drivers::timer::uptime, sync::SpinLock};
+use crate::{
+    arch::{Arch, ArchImpl},
+    drivers::timer::uptime,
+    kernel::cmdline,
+    sync::SpinLock,
+};
 
 mod buf;
 pub mod tty;
 use buf::BufConsole;
 pub mod chardev;
+pub mod early;
+use early::{EarlyConsole, EarlyWriter};
+pub mod filter;
+pub mod kmsg;
+pub mod syslog;
+use filter::LogFilter;
+use kmsg::KMSG;
 
 /// Trait for a console device.
 pub trait Console: Send + Sync {
@@ -21,28 +38,58 @@ pub trait Console: Send + Sync {
     fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result;
     fn write_buf(&self, buf: &[u8]);
 
+    /// Writes as much of `buf` as the device can take without waiting and
+    /// returns the number of bytes accepted.
+    ///
+    /// Devices that can't tell whether they are full fall back to a blocking
+    /// write of the whole buffer.
+    fn try_write_buf(&self, buf: &[u8]) -> usize {
+        self.write_buf(buf);
+        buf.len()
+    }
+
+    /// Returns a future that resolves once the device may take more output,
+    /// for writers that can sleep when `try_write_buf` comes up short rather
+    /// than spin in `write_buf`.
+    ///
+    /// Devices that can't signal this return `None`, and such writers fall
+    /// back to `write_buf`.
+    fn tx_space(&self) -> Option<Pin<Box<dyn Future<Output = ()> + Send>>> {
+        None
+    }
+
     /// Registers a handler that will receive input bytes.
     fn register_input_handler(&self, handler: Weak<dyn TtyInputHandler>);
+
+    /// The physical address of the device's registers, if it has any.
+    fn phys_base(&self) -> Option<PA> {
+        None
+    }
 }
 
 static mut EARLY_BOOT_BUFFER: BufConsole = BufConsole::new();
 
 /// Current console state.
 enum ConsoleState {
-    /// Early boot, messages are written to a temporary memory buffer.
-    Buffered,
+    /// Early boot, messages are written to a temporary memory buffer, and to
+    /// the early UART once there is one.
+    Buffered(Option<EarlyConsole>),
     /// A real console driver has been initialized.
     Device(Arc<dyn Console>, CharDevDescriptor),
 }
 
-static CONSOLE: SpinLock<ConsoleState> = SpinLock::new(ConsoleState::Buffered);
+static CONSOLE: SpinLock<ConsoleState> = SpinLock::new(ConsoleState::Buffered(None));
 
 /// Writes formatted output to the active console.
 pub fn write_fmt(args: fmt::Arguments) -> fmt::Result {
-    let console_state = CONSOLE.lock_save_irq();
+    let mut console_state = CONSOLE.lock_save_irq();
 
     match *console_state {
-        ConsoleState::Buffered => {
+        ConsoleState::Buffered(ref mut early) => {
+            if let Some(early) = early {
+                let _ = EarlyWriter::new(&mut *early.uart).write_fmt(args);
+            }
+
             // SAFETY: The lock on CONSOLE_STATE ensures that no other thread
             // can be reading or writing to the buffer at the same time.
             unsafe { (*addr_of_mut!(EARLY_BOOT_BUFFER)).write_fmt(args) }
@@ -51,6 +98,38 @@ pub fn write_fmt(args: fmt::Arguments) -> fmt::Result {
     }
 }
 
+/// Writes to the early UART alone, falling back to [`write_fmt`] without one.
+fn write_early(args: fmt::Arguments) {
+    let mut console_state = CONSOLE.lock_save_irq();
+
+    if let ConsoleState::Buffered(Some(ref mut early)) = *console_state {
+        let _ = EarlyWriter::new(&mut *early.uart).write_fmt(args);
+        return;
+    }
+
+    drop(console_state);
+
+    let _ = write_fmt(args);
+}
+
+/// Starts copying output to the early console, after writing out what's been
+/// buffered.
+fn set_early_console(console: EarlyConsole) {
+    let mut console_state = CONSOLE.lock_save_irq();
+
+    if let ConsoleState::Buffered(ref mut early) = *console_state {
+        // SAFETY: The lock on CONSOLE_STATE ensures that no other thread can
+        // be writing to the buffer at the same time.
+        let buf_contents = unsafe { (*addr_of_mut!(EARLY_BOOT_BUFFER)).data() };
+
+        if let Ok(s) = str::from_utf8(buf_contents) {
+            let _ = EarlyWriter::new(&mut *console.uart).write_str(s);
+        }
+
+        *early = Some(console);
+    }
+}
+
 /// Switches the active console from buffer to a real device and flushes output.
 pub fn set_active_console(
     console: Arc<dyn Console>,
@@ -63,8 +142,18 @@ pub fn set_active_console(
         ConsoleState::Device(console.clone(), char_dev),
     );
 
-    // If the old state was the buffer, flush its contents to the new device.
-    if let ConsoleState::Buffered = old_state {
+    let ConsoleState::Buffered(early) = old_state else {
+        return Ok(());
+    };
+
+    // Flush the buffer's contents to the new device, unless the early UART is
+    // the same device and has already written them out. Since the switch
+    // happens under the lock, nothing written since then can be missed.
+    let replayed = early
+        .as_ref()
+        .is_some_and(|early| console.phys_base() == Some(early.base));
+
+    if !replayed {
         // SAFETY: We still hold the lock, and since we just transitioned the
         // state away from `Buffered`, we have exclusive, one-time access to
         // read the buffer's contents. No new writers can appear.
@@ -75,27 +164,61 @@ pub fn set_active_console(
         }
     }
 
+    drop(console_state);
+
+    // Nothing can reach the early UART any more, so its mapping can go.
+    if let Some(early) = early {
+        (early.release)();
+    }
+
     Ok(())
 }
 
 struct ConsoleLogger;
 static CONSOLE_LOGGER: ConsoleLogger = ConsoleLogger;
 
+/// Decides which records reach the console.
+pub static LOG_FILTER: LogFilter = LogFilter::new(LevelFilter::Trace);
+
+/// The most verbose level printed on the console, set through `syslog(2)`. It
+/// applies on top of [`LOG_FILTER`].
+static CONSOLE_LEVEL: AtomicU8 = AtomicU8::new(LevelFilter::Trace as u8);
+
+pub fn console_level() -> LevelFilter {
+    filter::level_from_u8(CONSOLE_LEVEL.load(Ordering::Relaxed))
+}
+
+pub fn set_console_level(level: LevelFilter) {
+    CONSOLE_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
 impl Log for ConsoleLogger {
-    fn enabled(&self, _: &log::Metadata) -> bool {
+    /// Every record is kept in the kernel log, whether or not it's printed.
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
         true
     }
 
     fn log(&self, record: &log::Record) {
+        let module = record
+            .module_path()
+            .map(|x| x.strip_prefix("moss::").unwrap_or(x))
+            .unwrap_or("");
+
+        KMSG.lock_save_irq().push(
+            ArchImpl::counter_ticks(),
+            record.level(),
+            format_args!("{module}: {}", *record.args()),
+        );
+
+        if record.level() > console_level() || !LOG_FILTER.enabled(record.metadata()) {
+            return;
+        }
+
         let uptime = uptime();
         let _ = write_fmt(format_args!(
-            "[{:5}.{:06}] {}: {}\r\n",
+            "[{:5}.{:06}] {module}: {}\r\n",
             uptime.as_secs(),
             uptime.as_micros(),
-            record
-                .module_path()
-                .map(|x| x.strip_prefix("moss::").unwrap_or(x))
-                .unwrap_or(""),
             *record.args()
         ));
     }
@@ -107,3 +230,19 @@ pub fn setup_console_logger() {
     let _ = log::set_logger(&CONSOLE_LOGGER);
     log::set_max_level(LevelFilter::Trace);
 }
+
+/// Applies the `loglevel=` and `quiet` boot parameters to the console logger.
+///
+/// `loglevel=` takes the directives described in [`filter`], so e.g.
+/// `loglevel=info,drivers=warn` keeps driver probing quiet.
+pub fn apply_cmdline_log_level() {
+    match cmdline::get("loglevel") {
+        Some(directives) => {
+            if LOG_FILTER.apply(directives).is_err() {
+                warn!("Invalid log level {directives}, ignoring.");
+            }
+        }
+        None if cmdline::has("quiet") => LOG_FILTER.set_default_level(LevelFilter::Warn),
+        None => {}
+    }
+}
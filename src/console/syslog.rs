@@ -0,0 +1,172 @@
+use super::{console_level, kmsg::KMSG, set_console_level};
+use crate::{
+    arch::{Arch, ArchImpl},
+    memory::uaccess::copy_to_user_slice,
+    sched::current::current_task_shared,
+    sync::SpinLock,
+};
+use alloc::{string::String, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+use libkernel::{
+    error::{KernelError, Result},
+    memory::address::TUA,
+    proc::caps::CapabilitiesFlags,
+};
+use log::LevelFilter;
+
+const SYSLOG_ACTION_CLOSE: i32 = 0;
+const SYSLOG_ACTION_OPEN: i32 = 1;
+const SYSLOG_ACTION_READ: i32 = 2;
+const SYSLOG_ACTION_READ_ALL: i32 = 3;
+const SYSLOG_ACTION_READ_CLEAR: i32 = 4;
+const SYSLOG_ACTION_CLEAR: i32 = 5;
+const SYSLOG_ACTION_CONSOLE_OFF: i32 = 6;
+const SYSLOG_ACTION_CONSOLE_ON: i32 = 7;
+const SYSLOG_ACTION_CONSOLE_LEVEL: i32 = 8;
+const SYSLOG_ACTION_SIZE_UNREAD: i32 = 9;
+const SYSLOG_ACTION_SIZE_BUFFER: i32 = 10;
+
+/// The console level saved by `SYSLOG_ACTION_CONSOLE_OFF`, restored by
+/// `SYSLOG_ACTION_CONSOLE_ON`.
+static SAVED_LEVEL: SpinLock<Option<LevelFilter>> = SpinLock::new(None);
+
+/// The first record `SYSLOG_ACTION_READ` hasn't returned yet.
+static READ_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// The first record `SYSLOG_ACTION_READ_ALL` returns, moved on by clearing.
+static CLEAR_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// Maps a Linux console log level onto the closest `log` level filter.
+///
+/// Linux prints messages whose priority is numerically below the console
+/// level, so e.g. 5 lets through `KERN_WARNING` and anything more severe. The
+/// most verbose setting, 8, also enables trace records, which have no Linux
+/// counterpart.
+fn level_from_console_level(level: usize) -> Result<LevelFilter> {
+    match level {
+        1..=4 => Ok(LevelFilter::Error),
+        5 => Ok(LevelFilter::Warn),
+        6 | 7 => Ok(LevelFilter::Info),
+        8 => Ok(LevelFilter::Trace),
+        _ => Err(KernelError::InvalidValue),
+    }
+}
+
+/// Formats the kernel log from record `seq` onwards, returning each record's
+/// text with its sequence number.
+fn formatted_records(seq: u64) -> Vec<(u64, String)> {
+    let freq = ArchImpl::counter_freq();
+
+    KMSG.lock_save_irq()
+        .records_from(seq)
+        .map(|record| {
+            let mut text = String::new();
+            record.format(freq, &mut text);
+            (record.seq(), text)
+        })
+        .collect()
+}
+
+/// Copies as many whole records as fit in `len` bytes to `buf`, taking the
+/// oldest ones if `oldest` is set and the newest ones otherwise. Returns the
+/// number of bytes copied and the sequence number following the last record
+/// copied.
+async fn copy_records(
+    records: &[(u64, String)],
+    buf: TUA<u8>,
+    len: usize,
+    oldest: bool,
+) -> Result<(usize, Option<u64>)> {
+    let mut total = 0;
+    let fits = |(_, text): &&(u64, String)| {
+        total += text.len();
+        total <= len
+    };
+
+    let chosen: &[(u64, String)] = if oldest {
+        let count = records.iter().take_while(fits).count();
+        &records[..count]
+    } else {
+        let count = records.iter().rev().take_while(fits).count();
+        &records[records.len() - count..]
+    };
+
+    let mut out = Vec::new();
+    for (_, text) in chosen {
+        out.extend_from_slice(text.as_bytes());
+    }
+
+    copy_to_user_slice(&out, buf.to_untyped()).await?;
+
+    Ok((out.len(), chosen.last().map(|(seq, _)| seq + 1)))
+}
+
+pub async fn sys_syslog(action: i32, buf: TUA<u8>, len: usize) -> Result<usize> {
+    // Like Linux without `dmesg_restrict`, anyone may read the whole log.
+    if !matches!(action, SYSLOG_ACTION_READ_ALL | SYSLOG_ACTION_SIZE_BUFFER) {
+        let task = current_task_shared();
+        let creds = task.creds.lock_save_irq();
+        creds.caps().check_capable(CapabilitiesFlags::CAP_SYSLOG)?;
+    }
+
+    match action {
+        SYSLOG_ACTION_CLOSE | SYSLOG_ACTION_OPEN => Ok(0),
+        SYSLOG_ACTION_READ | SYSLOG_ACTION_READ_ALL | SYSLOG_ACTION_READ_CLEAR => {
+            if buf.is_null() {
+                return Err(KernelError::InvalidValue);
+            }
+
+            if action == SYSLOG_ACTION_READ {
+                // Unlike Linux, this doesn't wait for new records to arrive.
+                let records = formatted_records(READ_SEQ.load(Ordering::Relaxed));
+                let (copied, next) = copy_records(&records, buf, len, true).await?;
+                if let Some(next) = next {
+                    READ_SEQ.fetch_max(next, Ordering::Relaxed);
+                }
+                return Ok(copied);
+            }
+
+            let records = formatted_records(CLEAR_SEQ.load(Ordering::Relaxed));
+            let (copied, _) = copy_records(&records, buf, len, false).await?;
+
+            if action == SYSLOG_ACTION_READ_CLEAR
+                && let Some((seq, _)) = records.last()
+            {
+                CLEAR_SEQ.fetch_max(seq + 1, Ordering::Relaxed);
+            }
+
+            Ok(copied)
+        }
+        SYSLOG_ACTION_CLEAR => {
+            CLEAR_SEQ.fetch_max(KMSG.lock_save_irq().next_seq(), Ordering::Relaxed);
+            Ok(0)
+        }
+        SYSLOG_ACTION_CONSOLE_OFF => {
+            let mut saved = SAVED_LEVEL.lock_save_irq();
+            if saved.is_none() {
+                *saved = Some(console_level());
+            }
+            set_console_level(LevelFilter::Error);
+            Ok(0)
+        }
+        SYSLOG_ACTION_CONSOLE_ON => {
+            if let Some(level) = SAVED_LEVEL.lock_save_irq().take() {
+                set_console_level(level);
+            }
+            Ok(0)
+        }
+        SYSLOG_ACTION_CONSOLE_LEVEL => {
+            let level = level_from_console_level(len)?;
+            // Setting a level explicitly means there is nothing left to restore.
+            SAVED_LEVEL.lock_save_irq().take();
+            set_console_level(level);
+            Ok(0)
+        }
+        SYSLOG_ACTION_SIZE_UNREAD => Ok(formatted_records(READ_SEQ.load(Ordering::Relaxed))
+            .iter()
+            .map(|(_, text)| text.len())
+            .sum()),
+        SYSLOG_ACTION_SIZE_BUFFER => Ok(KMSG.lock_save_irq().text_capacity()),
+        _ => Err(KernelError::InvalidValue),
+    }
+}
@@ -19,7 +19,7 @@ use futures::{
 };
 use libkernel::{
     error::{KernelError, Result},
-    fs::SeekFrom,
+    fs::{OpenFlags, SeekFrom},
     memory::address::{TUA, UA},
 };
 use meta::{
@@ -82,6 +82,73 @@ impl Tty {
             }
         }
     }
+
+    /// Like `process_and_write_chunk`, but only writes what the console can
+    /// take without waiting. Returns the number of bytes of `chunk` consumed.
+    fn try_process_and_write_chunk(&mut self, chunk: &[u8]) -> usize {
+        let termios_flags = self.meta.lock_save_irq().termios.c_oflag;
+
+        if !termios_flags.contains(TermiosOutputFlags::OPOST) {
+            return self.console.try_write_buf(chunk);
+        }
+
+        let translate_newline = termios_flags.contains(TermiosOutputFlags::ONLCR);
+
+        for (i, &byte) in chunk.iter().enumerate() {
+            let accepted = if translate_newline && byte == b'\n' {
+                match self.console.try_write_buf(b"\r\n") {
+                    0 => false,
+                    1 => {
+                        // Don't leave a dangling CR; finish the pair.
+                        self.console.write_char('\n');
+                        true
+                    }
+                    _ => true,
+                }
+            } else {
+                self.console.try_write_buf(&[byte]) == 1
+            };
+
+            if !accepted {
+                return i;
+            }
+        }
+
+        chunk.len()
+    }
+
+    async fn write_nonblock(&mut self, mut ptr: UA, count: usize) -> Result<usize> {
+        const CHUNK_SZ: usize = 128;
+
+        let mut remaining = count;
+        let mut total_written = 0;
+
+        let mut user_chunk_buf = [0_u8; CHUNK_SZ];
+
+        while remaining > 0 {
+            let chunk_size = min(remaining, CHUNK_SZ);
+            let raw_slice = &mut user_chunk_buf[..chunk_size];
+
+            copy_from_user_slice(ptr, raw_slice).await?;
+
+            let written = self.try_process_and_write_chunk(raw_slice);
+
+            total_written += written;
+
+            if written < chunk_size {
+                break;
+            }
+
+            ptr = ptr.add_bytes(chunk_size);
+            remaining -= chunk_size;
+        }
+
+        if total_written == 0 && count > 0 {
+            Err(KernelError::TryAgain)
+        } else {
+            Ok(total_written)
+        }
+    }
 }
 
 #[async_trait]
@@ -137,8 +204,12 @@ impl FileOps for Tty {
         })
     }
 
-    async fn write(&mut self, _ctx: &mut FileCtx, ptr: UA, count: usize) -> Result<usize> {
-        self.writeat(ptr, count, 0).await
+    async fn write(&mut self, ctx: &mut FileCtx, ptr: UA, count: usize) -> Result<usize> {
+        if ctx.flags.contains(OpenFlags::O_NONBLOCK) {
+            self.write_nonblock(ptr, count).await
+        } else {
+            self.writeat(ptr, count, 0).await
+        }
     }
 
     async fn writeat(&mut self, mut ptr: UA, count: usize, _offset: u64) -> Result<usize> {
@@ -155,10 +226,35 @@ impl FileOps for Tty {
 
             copy_from_user_slice(ptr, raw_slice).await?;
 
-            self.process_and_write_chunk(raw_slice);
+            let mut chunk = &raw_slice[..];
+
+            loop {
+                let written = self.try_process_and_write_chunk(chunk);
+
+                total_written += written;
+                chunk = &chunk[written..];
+
+                if chunk.is_empty() {
+                    break;
+                }
+
+                // Sleep until the console has room, rather than spinning.
+                let Some(space) = self.console.tx_space() else {
+                    self.process_and_write_chunk(chunk);
+                    total_written += chunk.len();
+                    break;
+                };
+
+                if let InterruptResult::Interrupted = space.interruptable().await {
+                    return if total_written > 0 {
+                        Ok(total_written)
+                    } else {
+                        Err(KernelError::Interrupted)
+                    };
+                }
+            }
 
             ptr = ptr.add_bytes(chunk_size);
-            total_written += chunk_size;
             remaining -= chunk_size;
         }
 
@@ -166,8 +262,15 @@ impl FileOps for Tty {
     }
 
     fn poll_write_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
-        // A TTY is always ready to be written to.
-        Box::pin(async { Ok(()) })
+        let space = self.console.tx_space();
+
+        Box::pin(async move {
+            if let Some(space) = space {
+                space.await;
+            }
+
+            Ok(())
+        })
     }
 
     async fn ioctl(&mut self, _ctx: &mut FileCtx, request: usize, argp: usize) -> Result<usize> {
@@ -272,3 +375,65 @@ impl FileOps for Tty {
         Err(KernelError::SeekPipe)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ktest;
+    use alloc::vec::Vec;
+    use core::fmt;
+
+    /// A console with a transmit FIFO that only has room for `space` bytes.
+    struct FifoConsole {
+        space: SpinLock<usize>,
+        sent: SpinLock<Vec<u8>>,
+    }
+
+    impl Console for FifoConsole {
+        fn write_char(&self, c: char) {
+            self.sent.lock_save_irq().push(c as u8);
+        }
+
+        fn write_fmt(&self, _args: fmt::Arguments) -> fmt::Result {
+            Ok(())
+        }
+
+        fn write_buf(&self, buf: &[u8]) {
+            self.sent.lock_save_irq().extend_from_slice(buf);
+        }
+
+        fn try_write_buf(&self, buf: &[u8]) -> usize {
+            let mut space = self.space.lock_save_irq();
+            let n = min(*space, buf.len());
+
+            *space -= n;
+            self.sent.lock_save_irq().extend_from_slice(&buf[..n]);
+
+            n
+        }
+
+        fn register_input_handler(&self, _handler: alloc::sync::Weak<dyn TtyInputHandler>) {}
+    }
+
+    ktest! {
+        fn nonblocking_write_reports_partial_count() {
+            let console = Arc::new(FifoConsole {
+                space: SpinLock::new(3),
+                sent: SpinLock::new(Vec::new()),
+            });
+            let mut tty = Tty::new(console.clone()).unwrap();
+
+            assert_eq!(tty.try_process_and_write_chunk(b"hello"), 3);
+            assert_eq!(&*console.sent.lock_save_irq(), b"hel");
+
+            // A full FIFO accepts nothing.
+            assert_eq!(tty.try_process_and_write_chunk(b"lo"), 0);
+
+            // Newlines are still translated, without splitting the CR/LF pair.
+            *console.space.lock_save_irq() = 2;
+            console.sent.lock_save_irq().clear();
+            assert_eq!(tty.try_process_and_write_chunk(b"a\nb"), 2);
+            assert_eq!(&*console.sent.lock_save_irq(), b"a\r\n");
+        }
+    }
+}
@@ -0,0 +1,33 @@
+//! Block devices found by drivers, by name, so one can be picked as the root
+//! device from the command line.
+
+use crate::sync::SpinLock;
+use alloc::{collections::btree_map::BTreeMap, format, string::String, sync::Arc};
+use libkernel::{
+    error::{KernelError, Result},
+    fs::BlockDevice,
+};
+use log::info;
+
+static BLOCK_DEVICES: SpinLock<BTreeMap<String, Arc<dyn BlockDevice>>> =
+    SpinLock::new(BTreeMap::new());
+
+/// Registers `dev` under the first free name of `prefix` followed by a letter,
+/// like `vda`, and returns the name.
+pub fn register_block_device(prefix: &str, dev: Arc<dyn BlockDevice>) -> Result<String> {
+    let mut devices = BLOCK_DEVICES.lock_save_irq();
+
+    let name = ('a'..='z')
+        .map(|c| format!("{prefix}{c}"))
+        .find(|name| !devices.contains_key(name))
+        .ok_or(KernelError::InUse)?;
+
+    info!("Registered block device {name}");
+    devices.insert(name.clone(), dev);
+
+    Ok(name)
+}
+
+pub fn find_block_device(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    BLOCK_DEVICES.lock_save_irq().get(name).cloned()
+}
@@ -1,12 +1,13 @@
-use super::{DM, DeviceDescriptor, init::PLATFORM_BUS, probe::FdtFlags};
+use super::{
+    DM, DeviceDescriptor, DriverManager,
+    init::{PLATFORM_BUS, PlatformBus},
+    probe::{FdtFlags, FdtNodeExt},
+    uart::UartLineConfig,
+};
 use alloc::vec::Vec;
 use core::ptr::NonNull;
-use fdt_parser::Fdt;
-use libkernel::{
-    error::{KernelError, ProbeError},
-    memory::address::TVA,
-};
-use log::{error, warn};
+use fdt_parser::{Fdt, Node};
+use libkernel::memory::address::TVA;
 
 static mut FDT: TVA<u8> = TVA::from_value(usize::MAX);
 
@@ -33,21 +34,26 @@ pub fn is_intc_root(node: &fdt_parser::Node) -> bool {
 }
 
 pub fn probe_for_fdt_devices() {
-    let fdt = get_fdt();
     let mut driver_man = DM.lock_save_irq();
     let platform_bus = PLATFORM_BUS.lock_save_irq();
 
-    let mut to_probe: Vec<_> = fdt
+    probe_fdt(&get_fdt(), &mut driver_man, &platform_bus);
+}
+
+fn probe_fdt(fdt: &Fdt<'static>, driver_man: &mut DriverManager, platform_bus: &PlatformBus) {
+    let console = stdout_node_name(fdt);
+
+    let to_probe: Vec<_> = fdt
         .all_nodes()
         .filter(|node| {
             // Pre-filter nodes that are disabled, already probed, or have no
             // compatible string.
             driver_man.find_by_name(node.name).is_none()
-                && node.status().unwrap_or(fdt_parser::Status::Okay) == fdt_parser::Status::Okay
+                && node.is_enabled()
                 && node.compatible().is_some()
         })
         .map(|node| {
-            let flags = if is_active_console(node.name) {
+            let flags = if console == Some(node.name) {
                 FdtFlags::ACTIVE_CONSOLE
             } else {
                 FdtFlags::empty()
@@ -57,50 +63,303 @@ pub fn probe_for_fdt_devices() {
         })
         .collect();
 
-    let mut deferred_list = Vec::new();
-    let mut progress_made = true;
-
-    // Loop as long as we are successfully probing drivers.
-    while progress_made {
-        progress_made = false;
-        deferred_list.clear();
-
-        for desc in to_probe.drain(..) {
-            match platform_bus.probe_device(&mut driver_man, desc.clone()) {
-                Ok(Some(_)) => {
-                    progress_made = true;
-                }
-                Err(KernelError::Probe(ProbeError::Deferred)) => {
-                    deferred_list.push(desc);
-                }
-                Ok(None) => {
-                    // No driver found for this compatible string. Not an error, just ignore.
-                }
-                Err(e) => {
-                    // A fatal error occurred during probe.
-                    error!("Fatal error while probing device \"{desc}\": {e}");
-                }
+    driver_man.probe_all(to_probe, |dm, desc| {
+        platform_bus.probe_device(dm, desc.clone())
+    });
+}
+
+/// Splits a `stdout-path` value into the path (or alias) of the console node
+/// and the options following a ':', such as "115200n8".
+fn split_stdout_path(path: &str) -> (&str, Option<&str>) {
+    match path.split_once(':') {
+        Some((path, options)) => (path, Some(options)),
+        None => (path, None),
+    }
+}
+
+/// Resolves `/chosen/stdout-path` to the console's node and the options given
+/// for it. The path can name the node directly or through an entry in
+/// `/aliases`.
+fn stdout_path(fdt: &Fdt<'static>) -> Option<(Node<'static>, Option<&'static str>)> {
+    let find_node = |name: &str| fdt.all_nodes().find(|node| node.name == name);
+
+    let stdout_path = find_node("chosen")?.find_property("stdout-path")?.str();
+    let (path, options) = split_stdout_path(stdout_path);
+
+    let path = if path.starts_with('/') {
+        path
+    } else {
+        find_node("aliases")?.find_property(path)?.str()
+    };
+
+    let name = path.rsplit('/').next()?;
+
+    find_node(name).map(|node| (node, options))
+}
+
+fn stdout_node_name(fdt: &Fdt<'static>) -> Option<&'static str> {
+    stdout_path(fdt).map(|(node, _)| node.name)
+}
+
+/// Returns the node of the console named by `/chosen/stdout-path`.
+pub fn stdout_node(fdt: &Fdt<'static>) -> Option<Node<'static>> {
+    stdout_path(fdt).map(|(node, _)| node)
+}
+
+/// Returns the line settings requested for the console by the options of
+/// `stdout-path`, e.g. "serial0:115200n8". `None` means the firmware's
+/// settings should be kept.
+pub fn stdout_line_config() -> Option<UartLineConfig> {
+    stdout_path(&get_fdt())
+        .and_then(|(_, options)| options)
+        .and_then(UartLineConfig::parse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        drivers::{Driver, probe::DeviceMatchType, uart::UartParity},
+        ktest,
+        sync::SpinLock,
+    };
+    use alloc::{boxed::Box, sync::Arc};
+
+    /// Builds a device tree with the given nodes, each with string
+    /// properties, under the root node.
+    fn build_fdt(nodes: &[(&str, &[(&str, &str)])]) -> Fdt<'static> {
+        let mut fdt = test_fdt::FdtBuilder::new();
+
+        for (name, props) in nodes {
+            fdt.begin_node(name);
+            for (prop, value) in props.iter() {
+                fdt.prop_str(prop, value);
             }
+            fdt.end_node();
         }
 
-        // The next iteration of the loop will only try the devices that were deferred.
-        to_probe.append(&mut deferred_list);
+        fdt.finish()
+    }
 
-        // If we made no progress in a full pass, we are done (or have an unresolvable dependency).
-        if !progress_made && !to_probe.is_empty() {
-            for desc in &to_probe {
-                warn!("Could not probe device \"{desc}\" due to missing dependencies.");
-            }
-            break;
+    /// Registers a driver for "test,dev" nodes that records the name of each
+    /// node it probes, and whether it was flagged as the active console.
+    fn recording_bus() -> (PlatformBus, Arc<SpinLock<Vec<(&'static str, bool)>>>) {
+        let probed = Arc::new(SpinLock::new(Vec::new()));
+        let probed_by_bus = probed.clone();
+
+        let mut bus = PlatformBus::new();
+        bus.register_platform_driver(
+            DeviceMatchType::FdtCompatible("test,dev"),
+            Box::new(move |_: &mut DriverManager, desc: DeviceDescriptor| {
+                let DeviceDescriptor::Fdt(node, flags) = desc;
+                probed_by_bus
+                    .lock_save_irq()
+                    .push((node.name, flags.contains(FdtFlags::ACTIVE_CONSOLE)));
+                Ok(Arc::new(MockDevice(node.name)) as Arc<dyn Driver>)
+            }),
+        );
+
+        (bus, probed)
+    }
+
+    struct MockDevice(&'static str);
+
+    impl Driver for MockDevice {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+    }
+
+    const DEV: (&str, &str) = ("compatible", "test,dev");
+
+    ktest! {
+        fn disabled_nodes_are_not_probed() {
+            let fdt = build_fdt(&[
+                ("dev@0", &[DEV]),
+                ("dev@1", &[DEV, ("status", "disabled")]),
+                ("dev@2", &[DEV, ("status", "okay")]),
+                ("dev@3", &[DEV, ("status", "fail")]),
+            ]);
+
+            let (bus, probed) = recording_bus();
+            probe_fdt(&fdt, &mut DriverManager::new(), &bus);
+
+            let names: Vec<_> = probed.lock_save_irq().iter().map(|(name, _)| *name).collect();
+            assert_eq!(names, ["dev@0", "dev@2"]);
+        }
+    }
+
+    ktest! {
+        fn stdout_path_options_parse_to_line_config() {
+            let (_, options) = split_stdout_path("serial0:9600n8");
+
+            assert_eq!(
+                options.and_then(UartLineConfig::parse),
+                Some(UartLineConfig {
+                    baud: 9600,
+                    parity: UartParity::None,
+                    data_bits: 8,
+                    stop_bits: 1,
+                })
+            );
+
+            assert_eq!(
+                UartLineConfig::parse("115200e7"),
+                Some(UartLineConfig {
+                    baud: 115_200,
+                    parity: UartParity::Even,
+                    data_bits: 7,
+                    stop_bits: 1,
+                })
+            );
+            assert_eq!(UartLineConfig::parse("57600").unwrap().parity, UartParity::None);
+            assert_eq!(UartLineConfig::parse("fast"), None);
+            assert_eq!(UartLineConfig::parse("9600x8"), None);
+        }
+    }
+
+    ktest! {
+        fn stdout_path_flags_active_console() {
+            assert_eq!(split_stdout_path("serial1"), ("serial1", None));
+            assert_eq!(
+                split_stdout_path("/soc/serial@2000:115200n8"),
+                ("/soc/serial@2000", Some("115200n8"))
+            );
+
+            let fdt = build_fdt(&[
+                ("serial@1000", &[DEV]),
+                ("serial@2000", &[DEV]),
+                (
+                    "aliases",
+                    &[("serial0", "/serial@1000"), ("serial1", "/serial@2000")],
+                ),
+                ("chosen", &[("stdout-path", "serial1:115200n8")]),
+            ]);
+
+            let (bus, probed) = recording_bus();
+            probe_fdt(&fdt, &mut DriverManager::new(), &bus);
+
+            assert_eq!(
+                *probed.lock_save_irq(),
+                [("serial@1000", false), ("serial@2000", true)]
+            );
         }
     }
 }
 
-pub fn is_active_console(name: &'static str) -> bool {
-    let fdt = get_fdt();
+/// Builds flattened device trees for tests.
+#[cfg(test)]
+pub mod test_fdt {
+    use alloc::{boxed::Box, vec, vec::Vec};
+    use core::ptr::NonNull;
+    use fdt_parser::Fdt;
+
+    const FDT_BEGIN_NODE: u32 = 1;
+    const FDT_END_NODE: u32 = 2;
+    const FDT_PROP: u32 = 3;
+    const FDT_END: u32 = 9;
+
+    pub struct FdtBuilder {
+        strings: Vec<u8>,
+        dt_struct: Vec<u8>,
+    }
+
+    impl FdtBuilder {
+        /// Starts a tree with an empty root node.
+        pub fn new() -> Self {
+            let mut builder = Self {
+                strings: Vec::new(),
+                dt_struct: Vec::new(),
+            };
+            builder.begin_node("");
+            builder
+        }
+
+        fn push_padded(&mut self, bytes: &[u8]) {
+            self.dt_struct.extend_from_slice(bytes);
+            self.dt_struct
+                .resize(self.dt_struct.len().next_multiple_of(4), 0);
+        }
 
-    fdt.chosen()
-        .and_then(|chosen| chosen.stdout())
-        .map(|stdout| stdout.node.name == name)
-        .unwrap_or(false)
+        /// Opens a child of the current node.
+        pub fn begin_node(&mut self, name: &str) -> &mut Self {
+            self.dt_struct
+                .extend_from_slice(&FDT_BEGIN_NODE.to_be_bytes());
+            self.dt_struct.extend_from_slice(name.as_bytes());
+            self.push_padded(&[0]);
+            self
+        }
+
+        pub fn end_node(&mut self) -> &mut Self {
+            self.dt_struct
+                .extend_from_slice(&FDT_END_NODE.to_be_bytes());
+            self
+        }
+
+        /// Adds a property with a raw value to the current node.
+        pub fn prop(&mut self, name: &str, value: &[u8]) -> &mut Self {
+            self.dt_struct.extend_from_slice(&FDT_PROP.to_be_bytes());
+            self.dt_struct
+                .extend_from_slice(&(value.len() as u32).to_be_bytes());
+            self.dt_struct
+                .extend_from_slice(&(self.strings.len() as u32).to_be_bytes());
+            self.push_padded(value);
+
+            self.strings.extend_from_slice(name.as_bytes());
+            self.strings.push(0);
+            self
+        }
+
+        pub fn prop_str(&mut self, name: &str, value: &str) -> &mut Self {
+            let mut bytes = Vec::from(value.as_bytes());
+            bytes.push(0);
+            self.prop(name, &bytes)
+        }
+
+        /// Adds a property made of big-endian cells.
+        pub fn prop_cells(&mut self, name: &str, cells: &[u32]) -> &mut Self {
+            let bytes: Vec<u8> = cells.iter().flat_map(|cell| cell.to_be_bytes()).collect();
+            self.prop(name, &bytes)
+        }
+
+        /// Closes the root node and returns the parsed tree, which is leaked
+        /// so its nodes can be 'static.
+        pub fn finish(mut self) -> Fdt<'static> {
+            self.end_node();
+            self.dt_struct.extend_from_slice(&FDT_END.to_be_bytes());
+
+            // Header, then an empty memory reservation map, then the structure
+            // and strings blocks.
+            let off_struct = 40 + 16;
+            let off_strings = off_struct + self.dt_struct.len();
+            let total = off_strings + self.strings.len();
+
+            let mut blob = Vec::new();
+            for field in [
+                0xd00d_feed,
+                total as u32,
+                off_struct as u32,
+                off_strings as u32,
+                40,
+                17,
+                16,
+                0,
+                self.strings.len() as u32,
+                self.dt_struct.len() as u32,
+            ] {
+                blob.extend_from_slice(&u32::to_be_bytes(field));
+            }
+            blob.extend_from_slice(&[0; 16]);
+            blob.extend_from_slice(&self.dt_struct);
+            blob.extend_from_slice(&self.strings);
+
+            // Leak a word-aligned copy so the parsed nodes can be 'static.
+            let mut words = vec![0u64; total.div_ceil(8)];
+            let ptr = words.as_mut_ptr() as *mut u8;
+            unsafe { core::ptr::copy_nonoverlapping(blob.as_ptr(), ptr, total) };
+            Box::leak(words.into_boxed_slice());
+
+            unsafe { Fdt::from_ptr(NonNull::new_unchecked(ptr)).unwrap() }
+        }
+    }
 }
@@ -171,6 +171,7 @@ impl FilesystemDriver for CgroupFsDriver {
         &self,
         _fs_id: u64,
         device: Option<Box<dyn BlockDevice>>,
+        _options: &str,
     ) -> Result<Arc<dyn Filesystem>> {
         if device.is_some() {
             warn!("cgroupfs should not be constructed with a block device");
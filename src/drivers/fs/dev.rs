@@ -198,6 +198,7 @@ impl FilesystemDriver for DevFsDriver {
         &self,
         _fs_id: u64,
         device: Option<Box<dyn BlockDevice>>,
+        _options: &str,
     ) -> Result<Arc<dyn Filesystem>> {
         if device.is_some() {
             warn!("devfs should have no backing store");
@@ -32,6 +32,7 @@ impl FilesystemDriver for Ext4FsDriver {
         &self,
         fs_id: u64,
         device: Option<Box<dyn BlockDevice>>,
+        _options: &str,
     ) -> Result<Arc<dyn Filesystem>> {
         match device {
             Some(dev) => Ok(Ext4Filesystem::<ArchImpl>::new(BlockBuffer::new(dev), fs_id).await?),
@@ -1,9 +1,13 @@
-use crate::{drivers::Driver, fs::FilesystemDriver};
+use crate::{arch::ArchImpl, drivers::Driver, fs::FilesystemDriver};
 use alloc::{boxed::Box, sync::Arc};
 use async_trait::async_trait;
 use libkernel::{
     error::{KernelError, Result},
-    fs::{BlockDevice, Filesystem, blk::buffer::BlockBuffer, filesystems::fat32::Fat32Filesystem},
+    fs::{
+        BlockDevice, Filesystem,
+        blk::buffer::BlockBuffer,
+        filesystems::fat32::{Fat32Filesystem, FatWritePolicy},
+    },
 };
 use log::warn;
 
@@ -31,9 +35,17 @@ impl FilesystemDriver for Fat32FsDriver {
         &self,
         fs_id: u64,
         device: Option<Box<dyn BlockDevice>>,
+        options: &str,
     ) -> Result<Arc<dyn Filesystem>> {
         match device {
-            Some(dev) => Ok(Fat32Filesystem::new(BlockBuffer::new(dev), fs_id).await?),
+            Some(dev) => {
+                let policy = FatWritePolicy::from_mount_options(options)?;
+                let fs = Fat32Filesystem::<ArchImpl>::new(BlockBuffer::new(dev), fs_id).await?;
+
+                fs.set_fat_write_policy(policy);
+
+                Ok(fs)
+            }
             None => {
                 warn!("Could not mount fat32 fs with no block device");
                 Err(KernelError::InvalidValue)
@@ -1,6 +1,7 @@
 #![allow(clippy::module_name_repetitions)]
 
 mod cmdline;
+mod loglevel;
 mod meminfo;
 mod root;
 mod stat;
@@ -95,6 +96,7 @@ impl FilesystemDriver for ProcFsDriver {
         &self,
         _fs_id: u64,
         device: Option<Box<dyn BlockDevice>>,
+        _options: &str,
     ) -> Result<Arc<dyn Filesystem>> {
         if device.is_some() {
             warn!("procfs should not be constructed with a block device");
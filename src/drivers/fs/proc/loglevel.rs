@@ -0,0 +1,70 @@
+use crate::console::LOG_FILTER;
+use crate::sched::current::current_task_shared;
+use alloc::boxed::Box;
+use alloc::string::ToString;
+use async_trait::async_trait;
+use core::str;
+use libkernel::error::{KernelError, Result};
+use libkernel::fs::attr::{FileAttr, FilePermissions};
+use libkernel::fs::{FileType, Inode, InodeId};
+use libkernel::proc::caps::CapabilitiesFlags;
+
+/// `/proc/loglevel`: reads back the console log filter as directives, and
+/// replaces it with whatever directives are written.
+pub struct ProcLoglevelInode {
+    id: InodeId,
+    attr: FileAttr,
+}
+
+impl ProcLoglevelInode {
+    pub fn new(id: InodeId) -> Self {
+        Self {
+            id,
+            attr: FileAttr {
+                file_type: FileType::File,
+                mode: FilePermissions::from_bits_retain(0o644),
+                ..FileAttr::default()
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl Inode for ProcLoglevelInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let mut contents = LOG_FILTER.to_string();
+        contents.push('\n');
+
+        let bytes = contents.as_bytes();
+        let start = usize::min(offset as usize, bytes.len());
+        let len = usize::min(bytes.len() - start, buf.len());
+        buf[..len].copy_from_slice(&bytes[start..start + len]);
+        Ok(len)
+    }
+
+    async fn write_at(&self, _offset: u64, buf: &[u8]) -> Result<usize> {
+        {
+            let task = current_task_shared();
+            let creds = task.creds.lock_save_irq();
+            creds.caps().check_capable(CapabilitiesFlags::CAP_SYSLOG)?;
+        }
+
+        let directives = str::from_utf8(buf).map_err(|_| KernelError::InvalidValue)?;
+        LOG_FILTER.replace(directives)?;
+
+        Ok(buf.len())
+    }
+
+    async fn truncate(&self, _size: u64) -> Result<()> {
+        // Allow `echo ... > /proc/loglevel`, which opens with O_TRUNC.
+        Ok(())
+    }
+
+    async fn getattr(&self) -> Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+}
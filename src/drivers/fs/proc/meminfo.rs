@@ -1,3 +1,4 @@
+use crate::arch::{Arch, ArchImpl};
 use crate::memory::PAGE_ALLOC;
 use alloc::boxed::Box;
 use alloc::format;
@@ -43,10 +44,12 @@ impl SimpleFile for ProcMeminfoInode {
         let free_pages = page_alloc.free_pages();
 
         let total_ram = (total_pages * PAGE_SIZE) / 1024;
-        let free_ram = (free_pages * PAGE_SIZE) / 1204;
+        let free_ram = (free_pages * PAGE_SIZE) / 1024;
+        let slab = ArchImpl::slab_bytes() / 1024;
         let mut meminfo_content = String::new();
         meminfo_content.push_str(&format!("MemTotal: {total_ram} kB\n"));
         meminfo_content.push_str(&format!("MemFree: {free_ram} kB\n"));
+        meminfo_content.push_str(&format!("Slab: {slab} kB\n"));
         Ok(meminfo_content.into_bytes())
     }
 }
@@ -1,5 +1,6 @@
 use crate::drivers::fs::proc::cmdline::ProcCmdlineInode;
 use crate::drivers::fs::proc::get_inode_id;
+use crate::drivers::fs::proc::loglevel::ProcLoglevelInode;
 use crate::drivers::fs::proc::meminfo::ProcMeminfoInode;
 use crate::drivers::fs::proc::stat::ProcStatInode;
 use crate::drivers::fs::proc::task::ProcTaskInode;
@@ -59,6 +60,10 @@ impl Inode for ProcRootInode {
             return Ok(Arc::new(ProcCmdlineInode::new(
                 InodeId::from_fsid_and_inodeid(self.id.fs_id(), get_inode_id(&["cmdline"])),
             )));
+        } else if name == "loglevel" {
+            return Ok(Arc::new(ProcLoglevelInode::new(
+                InodeId::from_fsid_and_inodeid(self.id.fs_id(), get_inode_id(&["loglevel"])),
+            )));
         } else {
             let pid: u32 = name.parse().map_err(|_| FsError::NotFound)?;
             // Search for the task descriptor.
@@ -139,6 +144,12 @@ impl Inode for ProcRootInode {
             FileType::File,
             (entries.len() + 1) as u64,
         ));
+        entries.push(Dirent::new(
+            "loglevel".to_string(),
+            InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&["loglevel"])),
+            FileType::File,
+            (entries.len() + 1) as u64,
+        ));
 
         Ok(Box::new(SimpleDirStream::new(entries, start_offset)))
     }
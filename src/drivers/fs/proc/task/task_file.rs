@@ -9,6 +9,7 @@ use libkernel::error::{FsError, KernelError};
 use libkernel::fs::attr::{FileAttr, FilePermissions};
 use libkernel::fs::pathbuf::PathBuf;
 use libkernel::fs::{FileType, InodeId, SimpleFile};
+use libkernel::memory::PAGE_SIZE;
 
 pub enum TaskFileType {
     Status,
@@ -102,11 +103,13 @@ State:\t{state}
 Tgid:\t{tgid}
 FDSize:\t{fd_size}
 Pid:\t{pid}
+VmRSS:\t{rss} kB
 Threads:\t{tasks}\n",
                     name = name.as_str(),
                     tgid = task.process.tgid,
                     fd_size = task.fd_table.lock_save_irq().len(),
                     pid = task.tid.value(),
+                    rss = task.vm.lock_save_irq().resident_pages() * PAGE_SIZE / 1024,
                     tasks = task.process.tasks.lock_save_irq().len(),
                 ),
                 TaskFileType::Comm => format!("{name}\n", name = name.as_str()),
@@ -143,7 +146,7 @@ Threads:\t{tasks}\n",
                     output.push_str(&format!("{} ", 0)); // itrealvalue
                     output.push_str(&format!("{} ", 0)); // starttime
                     output.push_str(&format!("{} ", 0)); // vsize
-                    output.push_str(&format!("{} ", 0)); // rss
+                    output.push_str(&format!("{} ", task.vm.lock_save_irq().resident_pages())); // rss
                     output.push_str(&format!("{} ", 0)); // rsslim
                     output.push_str(&format!("{} ", 0)); // startcode
                     output.push_str(&format!("{} ", 0)); // endcode
@@ -189,7 +192,7 @@ Threads:\t{tasks}\n",
                             if vma.permissions().read { "r" } else { "-" },
                             if vma.permissions().write { "w" } else { "-" },
                             if vma.permissions().execute { "x" } else { "-" },
-                            "p", // Don't suport shared mappings... yet!
+                            if vma.is_shared() { "s" } else { "p" },
                             vma.file_offset().unwrap_or_default(),
                             vma.name()
                         ));
@@ -220,6 +220,7 @@ impl FilesystemDriver for SysFsDriver {
         &self,
         _fs_id: u64,
         device: Option<Box<dyn BlockDevice>>,
+        _options: &str,
     ) -> Result<Arc<dyn Filesystem>> {
         if device.is_some() {
             warn!("sysfs should not be constructed with a block device");
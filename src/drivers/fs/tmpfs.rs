@@ -36,6 +36,7 @@ impl FilesystemDriver for TmpFsDriver {
         &self,
         fs_id: u64,
         device: Option<Box<dyn BlockDevice>>,
+        _options: &str,
     ) -> Result<Arc<dyn Filesystem>> {
         match device {
             Some(_) => {
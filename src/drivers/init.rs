@@ -1,6 +1,6 @@
 use super::{
     Driver, DriverManager,
-    probe::{DeviceDescriptor, DeviceMatchType, ProbeFn},
+    probe::{DeviceDescriptor, DeviceMatchType, FdtFlags, ProbeFn},
 };
 use crate::{drivers::DM, sync::SpinLock};
 use alloc::collections::btree_map::BTreeMap;
@@ -27,37 +27,38 @@ impl PlatformBus {
         self.probers.insert(match_type, probe_fn);
     }
 
+    /// Finds the probe function for the first of `compats` that a driver has
+    /// registered for. Nodes list their compatible strings from the most to
+    /// the least specific, so the most specific driver wins.
+    fn find_fdt_prober<'a>(&self, compats: impl IntoIterator<Item = &'a str>) -> Option<&ProbeFn> {
+        compats.into_iter().find_map(|compat| {
+            self.probers
+                .iter()
+                .find(|(match_type, _)| match_type.matches_fdt_compatible(compat))
+                .map(|(_, probe_fn)| probe_fn)
+        })
+    }
+
     /// Called by the FDT prober to find the right driver and probe.
     pub fn probe_device(
         &self,
         dm: &mut DriverManager,
         descr: DeviceDescriptor,
     ) -> Result<Option<Arc<dyn Driver>>> {
-        let matcher = match &descr {
-            DeviceDescriptor::Fdt(node, _) => {
-                // Find the first compatible string that we have a driver for.
-                node.compatible().and_then(|compats| {
-                    for compat in compats {
-                        let compat_str = compat.ok()?;
-
-                        let match_type = DeviceMatchType::FdtCompatible(compat_str);
-
-                        if self.probers.contains_key(&match_type) {
-                            return Some(match_type);
-                        }
-                    }
-
-                    None
-                })
-            }
+        let active_console = match &descr {
+            DeviceDescriptor::Fdt(_, flags) => flags.contains(FdtFlags::ACTIVE_CONSOLE),
+        };
+
+        let probe_fn = match &descr {
+            DeviceDescriptor::Fdt(node, _) => node
+                .compatible()
+                .and_then(|compats| self.find_fdt_prober(compats.filter_map(|c| c.ok()))),
         };
 
-        if let Some(match_type) = matcher
-            && let Some(probe_fn) = self.probers.get(&match_type)
-        {
+        if let Some(probe_fn) = probe_fn {
             // We found a match, call the probe function.
             let driver = (probe_fn)(dm, descr)?;
-            dm.insert_driver(driver.clone());
+            dm.insert_device_driver(driver.clone(), active_console)?;
             return Ok(Some(driver));
         }
 
@@ -107,3 +108,46 @@ macro_rules! kernel_driver {
 }
 
 pub static PLATFORM_BUS: SpinLock<PlatformBus> = SpinLock::new(PlatformBus::new());
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ktest;
+    use alloc::boxed::Box;
+    use libkernel::error::KernelError;
+
+    fn mock_probe(_: &mut DriverManager, _: DeviceDescriptor) -> Result<Arc<dyn Driver>> {
+        Err(KernelError::NotSupported)
+    }
+
+    fn register(bus: &mut PlatformBus, match_type: DeviceMatchType) {
+        bus.register_platform_driver(match_type, Box::new(mock_probe));
+    }
+
+    fn prober_for(bus: &PlatformBus, match_type: DeviceMatchType) -> &ProbeFn {
+        bus.probers.get(&match_type).unwrap()
+    }
+
+    ktest! {
+        fn fdt_match_picks_most_specific_compatible() {
+            let single = DeviceMatchType::FdtCompatible("ns16550a");
+            let family =
+                DeviceMatchType::FdtCompatibleAny(&["vendor,uart-v1", "vendor,uart-v3"]);
+
+            let mut bus = PlatformBus::new();
+            register(&mut bus, single);
+            register(&mut bus, family);
+
+            // A node advertising two compatibles, with a driver only for the
+            // second.
+            let found = bus.find_fdt_prober(["vendor,uart-v2", "ns16550a"]).unwrap();
+            assert!(core::ptr::eq(found, prober_for(&bus, single)));
+
+            // Drivers for both: the first, more specific, compatible wins.
+            let found = bus.find_fdt_prober(["vendor,uart-v3", "ns16550a"]).unwrap();
+            assert!(core::ptr::eq(found, prober_for(&bus, family)));
+
+            assert!(bus.find_fdt_prober(["vendor,other"]).is_none());
+        }
+    }
+}
@@ -381,12 +381,7 @@ pub fn gic_v2_probe(_dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<
 
 pub fn arm_gicv2_init(bus: &mut PlatformBus, _dm: &mut DriverManager) -> Result<()> {
     bus.register_platform_driver(
-        DeviceMatchType::FdtCompatible("arm,gic-400"),
-        Box::new(gic_v2_probe),
-    );
-
-    bus.register_platform_driver(
-        DeviceMatchType::FdtCompatible("arm,cortex-a15-gic"),
+        DeviceMatchType::FdtCompatibleAny(&["arm,gic-400", "arm,cortex-a15-gic"]),
         Box::new(gic_v2_probe),
     );
 
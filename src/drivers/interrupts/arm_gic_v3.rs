@@ -7,13 +7,13 @@ use crate::{
     },
     interrupts::{
         InterruptConfig, InterruptContext, InterruptController, InterruptDescriptor,
-        InterruptManager, TriggerMode, set_interrupt_root,
+        InterruptManager, TriggerMode, fiq::FiqOps, set_interrupt_root,
     },
     kernel_driver,
     sync::SpinLock,
 };
 use aarch64_cpu::registers::MPIDR_EL1;
-use alloc::{boxed::Box, sync::Arc};
+use alloc::{boxed::Box, collections::btree_map::BTreeMap, sync::Arc};
 use core::arch::asm;
 use libkernel::{
     KernAddressSpace, VirtualMemory,
@@ -39,6 +39,23 @@ fn get_icc_iar1_el1() -> u64 {
     iar
 }
 
+#[inline(always)]
+fn get_icc_iar0_el1() -> u64 {
+    let iar: u64;
+    unsafe { asm!("mrs {}, ICC_IAR0_EL1", out(reg) iar, options(nostack, nomem)) };
+    iar
+}
+
+#[inline(always)]
+fn set_icc_eoir0_el1(id: u64) {
+    unsafe { asm!("msr ICC_EOIR0_EL1, {}", in(reg) id, options(nostack, nomem)) };
+}
+
+#[inline(always)]
+fn set_icc_igrpen0_el1(enable: u64) {
+    unsafe { asm!("msr ICC_IGRpen0_EL1, {}", in(reg) enable, options(nostack, nomem)) };
+}
+
 #[inline(always)]
 fn set_icc_pmr_el1(priority: u8) {
     unsafe { asm!("msr ICC_PMR_EL1, {}", in(reg) priority as u64, options(nostack, nomem)) };
@@ -54,6 +71,79 @@ fn set_icc_sgi1r_el1(value: u64) {
     unsafe { asm!("msr ICC_SGI1R_EL1, {}", in(reg) value, options(nostack, nomem)) };
 }
 
+/// The affinity fields of `MPIDR_EL1`, laid out as `GICD_IROUTER` expects.
+const MPIDR_AFFINITY_MASK: u64 = 0xff_00ff_ffff;
+
+/// The SGI used for IPIs, which the CPU messenger listens on.
+const IPI_SGI: u64 = 0;
+
+/// The priority of every interrupt signalled as an IRQ.
+const IRQ_PRIORITY: u8 = 0xa0;
+
+/// The priority of an interrupt routed to FIQ. It must be higher than
+/// `IRQ_PRIORITY` for the GIC to signal it while an IRQ is active.
+const FIQ_PRIORITY: u8 = 0x00;
+
+/// `GICD_CTLR.DS`: the GIC has a single security state.
+const GICD_CTLR_DS: u32 = 1 << 6;
+
+/// Returns an `IGROUPR` value with interrupt `id` moved to Group 0, which is
+/// signalled as FIQ.
+fn igroupr_with_group0(igroupr: u32, id: usize) -> u32 {
+    igroupr & !(1 << (id % 32))
+}
+
+/// Returns an `IPRIORITYR` value with the priority byte of interrupt `id` set
+/// to `priority`.
+fn ipriorityr_with_priority(ipriorityr: u32, id: usize, priority: u8) -> u32 {
+    let shift = (id % 4) * 8;
+
+    (ipriorityr & !(0xff << shift)) | (priority as u32) << shift
+}
+
+/// Maps a GIC interrupt ID to its descriptor, or `None` for the special IDs
+/// 1020-1023, e.g. spurious interrupts.
+fn descriptor_for_id(int_id: usize) -> Option<InterruptDescriptor> {
+    match int_id {
+        0..=15 => Some(InterruptDescriptor::Ipi(int_id)),
+        16..=31 => Some(InterruptDescriptor::Ppi(int_id - 16)),
+        32..=1019 => Some(InterruptDescriptor::Spi(int_id - 32)),
+        _ => None,
+    }
+}
+
+/// Acknowledges a Group 0 interrupt. Only touches system registers, so is safe
+/// in FIQ context.
+fn ack_fiq() -> Option<(usize, InterruptDescriptor)> {
+    let int_id = get_icc_iar0_el1() as usize;
+
+    descriptor_for_id(int_id).map(|desc| (int_id, desc))
+}
+
+fn eoi_fiq(int_id: usize) {
+    set_icc_eoir0_el1(int_id as u64);
+}
+
+/// Packs the affinity of `mpidr` the way `GICR_TYPER.Affinity_Value` holds
+/// it: Aff3.Aff2.Aff1.Aff0 in consecutive bytes.
+fn mpidr_to_rdist_affinity(mpidr: u64) -> u64 {
+    ((mpidr >> 32) & 0xff) << 24 | (mpidr & 0xff_ffff)
+}
+
+/// Encodes an `ICC_SGI1R_EL1` write sending `sgi` to the core with `mpidr`.
+///
+/// The target list only has 16 bits, one per Aff0 value, so cores with a
+/// larger Aff0 are reached through the range selector, which picks the block
+/// of 16 Aff0 values the list refers to.
+fn sgi1r_value(sgi: u64, mpidr: u64) -> u64 {
+    let aff0 = mpidr & 0xff;
+    let aff1 = (mpidr >> 8) & 0xff;
+    let aff2 = (mpidr >> 16) & 0xff;
+    let aff3 = (mpidr >> 32) & 0xff;
+
+    aff3 << 48 | (aff0 / 16) << 44 | aff2 << 32 | (sgi & 0xf) << 24 | aff1 << 16 | 1 << (aff0 % 16)
+}
+
 register_structs! {
     /// GICv3 Distributor registers.
     #[allow(non_snake_case)]
@@ -85,7 +175,8 @@ register_structs! {
         /// Interrupt Configuration Registers (for SPIs).
         (0x0C00 => ICFGR: [ReadWrite<u32>; 0x40]),
         (0x0d00 => _reserved_2),
-        /// Each register is 64-bits and corresponds to one interrupt ID.
+        /// Each register is 64-bits and corresponds to one SPI, starting at
+        /// interrupt ID 32.
         (0x6100 => IROUTER: [ReadWrite<u64>; 988]),
         (0x7fe0 => @END),
     }
@@ -170,6 +261,9 @@ pub struct ArmGicV3 {
     dist: &'static mut GicDistributorRegs,
     rdist_base: VA,
     rdist_stride: usize,
+    /// The MPIDR of each core that has enabled its interface, by CPU id, for
+    /// addressing IPIs.
+    core_mpidrs: BTreeMap<usize, u64>,
 }
 
 impl ArmGicV3 {
@@ -201,13 +295,18 @@ impl ArmGicV3 {
             dist.ICPENDR[i].set(0xFFFF_FFFF);
         }
 
-        // Set all priorites to 0 (highest).
-        for i in 8..((num_spis + 31) / 4) {
-            dist.IPRIORITYR[i].set(0);
+        // Set all priorities below that of FIQs.
+        for i in 8..((num_spis + 32) / 4) {
+            dist.IPRIORITYR[i].set(u32::from_ne_bytes([IRQ_PRIORITY; 4]));
         }
 
-        // EnableGrp1ns: Enable group 1Ns interrupts
-        dist.CTLR.set(1 << 1);
+        // ARE_NS: Use affinity routing, which `IROUTER` and the `ICC_*_EL1`
+        // interface rely on. It must be set before the groups are enabled.
+        dist.CTLR.set(1 << 4);
+        while dist.CTLR.get() & (1 << 31) != 0 {}
+
+        // EnableGrp1A and EnableGrp1: Enable group 1 interrupts
+        dist.CTLR.set(1 << 4 | 1 << 1 | 1 << 0);
 
         // Wait for writes to complete.
         while dist.CTLR.get() & (1 << 31) != 0 {}
@@ -216,6 +315,7 @@ impl ArmGicV3 {
             dist,
             rdist_base: rdist_mem,
             rdist_stride,
+            core_mpidrs: BTreeMap::new(),
         })
     }
 
@@ -235,15 +335,17 @@ impl ArmGicV3 {
 
         info!("GICv3: Redistributor for core {core_id} (MPIDR=0x{mpidr:x}) is awake.",);
 
-        // 2. Configure PPIs and SGIs for this core SGIs (0-15) are Group 0,
-        // PPIs (16-31) are Group 1
-        sgi_ppi.IGROUPR0.set(0xFFFF_FFFF); // PPIs are Grp1, SGIs are Grp0
+        self.core_mpidrs.insert(core_id, mpidr);
+
+        // 2. Configure PPIs and SGIs for this core. Both are Group 1, since
+        // IPIs are raised through `ICC_SGI1R_EL1`.
+        sgi_ppi.IGROUPR0.set(0xFFFF_FFFF);
         sgi_ppi.ICENABLER0.set(0xFFFF_FFFF); // Disable all
         sgi_ppi.ICPENDR0.set(0xFFFF_FFFF); // Clear all pending
 
         // Set default priorities
         for i in 0..8 {
-            sgi_ppi.IPRIORITYR[i].set(0xA0A0_A0A0);
+            sgi_ppi.IPRIORITYR[i].set(u32::from_ne_bytes([IRQ_PRIORITY; 4]));
         }
 
         // 3. Enable the CPU's system register interface to the GIC
@@ -265,20 +367,28 @@ impl ArmGicV3 {
     }
 
     /// Gets a mutable reference to the redistributor for a given CPU affinity.
+    ///
+    /// Redistributors aren't necessarily laid out in core order, so this walks
+    /// the region comparing each one's affinity until the one flagged as last.
     fn get_rdist_for_cpu(&self, mpidr: u64) -> Result<&'static mut GicRedistributorRegs> {
-        // This is a simplified search. A full implementation should parse the
-        // GICR's TYPER to find the correct redistributor for a given MPIDR. For
-        // systems where cores and redistributors are mapped linearly (like QEMU
-        // virt) this should be fine, but we do an assert just to ensure.
-        let core_id = (mpidr & 0xFF) + ((mpidr >> 8) & 0xFF) * 4; // Simple linear core ID
-        let rdist_offset = core_id as usize * self.rdist_stride;
-        let rdist_addr = self.rdist_base.value() + rdist_offset;
+        let affinity = mpidr_to_rdist_affinity(mpidr);
+        let mut rdist_addr = self.rdist_base.value();
+
+        loop {
+            let rdist = unsafe { &mut *(rdist_addr as *mut GicRedistributorRegs) };
+            let typer = rdist.TYPER.get();
 
-        let rdist = unsafe { &mut *(rdist_addr as *mut GicRedistributorRegs) };
+            if typer >> 32 == affinity {
+                return Ok(rdist);
+            }
 
-        assert_eq!(rdist.TYPER.get() >> 32, core_id);
+            // TYPER.Last
+            if typer & (1 << 4) != 0 {
+                return Err(KernelError::NoDevice);
+            }
 
-        Ok(rdist)
+            rdist_addr += self.rdist_stride;
+        }
     }
 
     /// Gets a mutable reference to the SGI/PPI block for a given redistributor.
@@ -315,8 +425,9 @@ impl InterruptController for ArmGicV3 {
                 };
                 self.dist.ICFGR[icfgr_idx].set(new_icfgr);
 
-                let target_affinity = 0;
-                self.dist.IROUTER[id].set(target_affinity);
+                // Route to the core enabling the interrupt.
+                let target_affinity = MPIDR_EL1.get() & MPIDR_AFFINITY_MASK;
+                self.dist.IROUTER[id - 32].set(target_affinity);
 
                 // Enable the interrupt
                 let enabler_idx = id / 32;
@@ -376,13 +487,7 @@ impl InterruptController for ArmGicV3 {
         // In GICv3, we read a system register to get the active interrupt.
         let int_id = get_icc_iar1_el1() as usize;
 
-        let descriptor = match int_id {
-            0..=15 => InterruptDescriptor::Ipi(int_id),
-            16..=31 => InterruptDescriptor::Ppi(int_id - 16),
-            32..=1019 => InterruptDescriptor::Spi(int_id - 32),
-            // 1020-1023 are special interrupt IDs, e.g., for spurious interrupts.
-            _ => return None,
-        };
+        let descriptor = descriptor_for_id(int_id)?;
 
         let context = ArmGicV3InterruptContext {
             raw_id: int_id as u64,
@@ -393,7 +498,12 @@ impl InterruptController for ArmGicV3 {
     }
 
     fn raise_ipi(&mut self, target_cpu_id: usize) {
-        set_icc_sgi1r_el1(1 << (target_cpu_id as u64 & 0xffff));
+        // A core that hasn't enabled its interface couldn't take the IPI.
+        let Some(&mpidr) = self.core_mpidrs.get(&target_cpu_id) else {
+            return;
+        };
+
+        set_icc_sgi1r_el1(sgi1r_value(IPI_SGI, mpidr));
     }
 
     fn enable_core(&mut self, cpu_id: usize) {
@@ -416,6 +526,47 @@ impl InterruptController for ArmGicV3 {
         });
     }
 
+    /// Moves the interrupt to Group 0 at `FIQ_PRIORITY`. SPIs are routed to,
+    /// and PPIs configured on, the calling core, which is the only one with
+    /// Group 0 enabled.
+    fn route_to_fiq(&mut self, desc: InterruptDescriptor) -> Result<FiqOps> {
+        // With two security states, Group 0 belongs to the secure world and
+        // its FIQs are taken to EL3.
+        if self.dist.CTLR.get() & GICD_CTLR_DS == 0 {
+            return Err(KernelError::NotSupported);
+        }
+
+        let GicInterruptID(id) = GicInterruptID::try_from(desc)?;
+
+        match desc {
+            InterruptDescriptor::Spi(_) => {
+                let group = &self.dist.IGROUPR[id / 32];
+                group.set(igroupr_with_group0(group.get(), id));
+
+                let prio = &self.dist.IPRIORITYR[id / 4];
+                prio.set(ipriorityr_with_priority(prio.get(), id, FIQ_PRIORITY));
+            }
+            InterruptDescriptor::Ppi(_) | InterruptDescriptor::Ipi(_) => {
+                let rdist = self.get_rdist_for_cpu(MPIDR_EL1.get())?;
+                let sgi_ppi = self.get_sgi_ppi_for_rdist(rdist);
+
+                sgi_ppi
+                    .IGROUPR0
+                    .set(igroupr_with_group0(sgi_ppi.IGROUPR0.get(), id));
+
+                let prio = &sgi_ppi.IPRIORITYR[id / 4];
+                prio.set(ipriorityr_with_priority(prio.get(), id, FIQ_PRIORITY));
+            }
+        }
+
+        set_icc_igrpen0_el1(1);
+
+        Ok(FiqOps {
+            ack: ack_fiq,
+            eoi: eoi_fiq,
+        })
+    }
+
     fn parse_fdt_interrupt_regs(
         &self,
         iter: &mut dyn Iterator<Item = u32>,
@@ -460,11 +611,12 @@ pub fn gic_v3_probe(_dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<
             let dist_region = regs.next().ok_or(NoReg)?;
             let rdist_region = regs.next().ok_or(NoReg)?;
 
+            // Without an explicit stride, each redistributor is two 64kb
+            // frames: RD and SGI.
             let rdist_stride = fdt_node
                 .find_property("redistributor-stride")
-                .map(|p| p.u32())
-                .unwrap_or(0) as usize
-                + 0x10000 * 2; // Two 64kb frames: RD and SGI
+                .map(|p| p.u32() as usize)
+                .unwrap_or(0x10000 * 2);
 
             let (dist_mem, rdist_mem) = {
                 let addr_spc = <ArchImpl as VirtualMemory>::kern_address_space();
@@ -516,3 +668,71 @@ pub fn arm_gicv3_init(bus: &mut PlatformBus, _dm: &mut DriverManager) -> Result<
 }
 
 kernel_driver!(arm_gicv3_init);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ktest;
+
+    ktest! {
+        fn gicv3_sgi1r_targets_aff0() {
+            assert_eq!(sgi1r_value(0, 0), 1);
+            assert_eq!(sgi1r_value(0, 3), 1 << 3);
+            assert_eq!(sgi1r_value(5, 1), 5 << 24 | 1 << 1);
+        }
+    }
+
+    ktest! {
+        fn gicv3_sgi1r_encodes_affinity() {
+            // Aff3 = 0x12, Aff2 = 0x34, Aff1 = 0x56, Aff0 = 0x07.
+            let mpidr = 0x12_8034_5607;
+
+            assert_eq!(
+                sgi1r_value(15, mpidr),
+                0x12 << 48 | 0x34 << 32 | 0xf << 24 | 0x56 << 16 | 1 << 7
+            );
+        }
+    }
+
+    ktest! {
+        fn gicv3_sgi1r_uses_range_selector() {
+            // Aff0 = 0x23 is bit 3 of the third block of 16.
+            assert_eq!(sgi1r_value(1, 0x123), 2 << 44 | 1 << 24 | 1 << 16 | 1 << 3);
+        }
+    }
+
+    ktest! {
+        fn gicv3_rdist_affinity_packs_mpidr() {
+            assert_eq!(mpidr_to_rdist_affinity(0x8000_0001), 1);
+            assert_eq!(mpidr_to_rdist_affinity(0x12_8034_5607), 0x1234_5607);
+        }
+    }
+
+    ktest! {
+        fn gicv3_fiq_moves_spi_to_group0() {
+            // SPI 45 is interrupt ID 77: bit 13 of IGROUPR2 and byte 1 of
+            // IPRIORITYR19.
+            let GicInterruptID(id) = GicInterruptID::try_from(InterruptDescriptor::Spi(45)).unwrap();
+            assert_eq!((id / 32, id / 4), (2, 19));
+
+            assert_eq!(igroupr_with_group0(0xffff_ffff, id), 0xffff_dfff);
+            assert_eq!(
+                ipriorityr_with_priority(0xa0a0_a0a0, id, FIQ_PRIORITY),
+                0xa0a0_00a0
+            );
+        }
+    }
+
+    ktest! {
+        fn gicv3_fiq_preempts_irqs() {
+            // Lower values are higher priorities.
+            assert!(FIQ_PRIORITY < IRQ_PRIORITY);
+
+            assert_eq!(
+                ipriorityr_with_priority(0, 3, IRQ_PRIORITY),
+                (IRQ_PRIORITY as u32) << 24
+            );
+            assert_eq!(igroupr_with_group0(1 << 31 | 1, 31), 1);
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use core::{
     any::Any,
+    fmt::Display,
     sync::atomic::{AtomicU64, Ordering},
 };
 
@@ -9,17 +10,21 @@ use alloc::{
     vec::Vec,
 };
 use libkernel::{
-    error::{KernelError, Result},
+    driver::CharDevDescriptor,
+    error::{KernelError, ProbeError, Result},
     fs::OpenFlags,
 };
+use log::{error, warn};
 use probe::DeviceDescriptor;
 
 use crate::{
+    console::Console,
     fs::{FilesystemDriver, open_file::OpenFile},
     interrupts::InterruptManager,
     sync::SpinLock,
 };
 
+pub mod blk;
 pub mod fdt_prober;
 pub mod fs;
 pub mod init;
@@ -28,6 +33,8 @@ pub mod null;
 pub mod probe;
 pub mod timer;
 pub mod uart;
+pub mod virtio;
+pub mod watchdog;
 pub mod zero;
 
 #[repr(u64)]
@@ -36,9 +43,14 @@ pub enum ReservedMajors {
     Zero = 2,
     Console = 5,
     Uart = 10,
-    End = 11,
+    Watchdog = 11,
+    End = 12,
 }
 
+/// The most passes [`DriverManager::probe_all`] makes over the devices whose
+/// probes were deferred.
+const MAX_PROBE_PASSES: usize = 8;
+
 pub trait Driver: Send + Sync + Any {
     fn name(&self) -> &'static str;
 
@@ -49,6 +61,19 @@ pub trait Driver: Send + Sync + Any {
     fn as_filesystem_driver(self: Arc<Self>) -> Option<Arc<dyn FilesystemDriver>> {
         None
     }
+
+    /// Drivers that can act as a console return themselves here so that a
+    /// `/dev/ttyS*` node is created for them when they are registered.
+    fn as_console(self: Arc<Self>) -> Option<Arc<dyn Console>> {
+        None
+    }
+
+    /// Called by [`DriverManager::remove`] before the driver is dropped.
+    /// Drivers release their claimed interrupts and unmap their MMIO regions
+    /// here.
+    fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
 }
 
 pub trait OpenableDevice: Send + Sync {
@@ -84,6 +109,83 @@ impl DriverManager {
         self.active_drivers.push(driver);
     }
 
+    /// Registers a probed device driver. If the driver is a console, it is
+    /// also exposed as a tty device node, and made the active console if
+    /// `active_console` is set.
+    ///
+    /// Returns the descriptor of the tty device node, if one was created.
+    pub fn insert_device_driver(
+        &mut self,
+        driver: Arc<dyn Driver>,
+        active_console: bool,
+    ) -> Result<Option<CharDevDescriptor>> {
+        self.insert_driver(driver.clone());
+
+        driver
+            .as_console()
+            .map(|console| uart::register_console(console, active_console))
+            .transpose()
+    }
+
+    /// Shuts `driver` down and drops the manager's reference to it. If the
+    /// shutdown hook fails, the driver stays registered.
+    pub fn remove(&mut self, driver: &Arc<dyn Driver>) -> Result<()> {
+        let idx = self
+            .active_drivers
+            .iter()
+            .position(|drv| Arc::ptr_eq(drv, driver))
+            .ok_or(KernelError::InvalidValue)?;
+
+        driver.shutdown()?;
+
+        self.active_drivers.remove(idx);
+
+        Ok(())
+    }
+
+    /// Probes each of `devices` with `probe`. A probe failing with
+    /// [`ProbeError::Deferred`] is queued and retried after the other devices
+    /// have been probed, so a device may appear before the devices it depends
+    /// on. Retries stop once a pass makes no progress, or after
+    /// [`MAX_PROBE_PASSES`] passes.
+    ///
+    /// Returns the devices that were still deferred at that point.
+    pub fn probe_all<D, F>(&mut self, devices: Vec<D>, mut probe: F) -> Vec<D>
+    where
+        D: Display,
+        F: FnMut(&mut Self, &D) -> Result<Option<Arc<dyn Driver>>>,
+    {
+        let mut queue = devices;
+
+        for _ in 0..MAX_PROBE_PASSES {
+            let mut deferred = Vec::new();
+            let mut progress_made = false;
+
+            for dev in queue.drain(..) {
+                match probe(self, &dev) {
+                    Ok(Some(_)) => progress_made = true,
+                    Err(KernelError::Probe(ProbeError::Deferred)) => deferred.push(dev),
+                    // No driver matches this device, or there's no device
+                    // behind the node. Not an error.
+                    Ok(None) | Err(KernelError::Probe(ProbeError::NoDevice)) => {}
+                    Err(e) => error!("Fatal error while probing device \"{dev}\": {e}"),
+                }
+            }
+
+            queue = deferred;
+
+            if queue.is_empty() || !progress_made {
+                break;
+            }
+        }
+
+        for dev in &queue {
+            warn!("Could not probe device \"{dev}\" due to missing dependencies.");
+        }
+
+        queue
+    }
+
     pub fn find_by_name(&self, name: &str) -> Option<Arc<dyn Driver>> {
         self.active_drivers.iter().find_map(|drv| {
             if drv.name() == name {
@@ -114,3 +216,110 @@ impl DriverManager {
 }
 
 pub static DM: SpinLock<DriverManager> = SpinLock::new(DriverManager::new());
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{arch::ArchImpl, ktest};
+    use core::sync::atomic::AtomicBool;
+    use libkernel::{
+        KernAddressSpace, VirtualMemory,
+        memory::{
+            PAGE_SIZE,
+            address::PA,
+            region::{PhysMemoryRegion, VirtMemoryRegion},
+        },
+    };
+
+    struct MockMmioDriver {
+        mmio: VirtMemoryRegion,
+        shut_down: AtomicBool,
+    }
+
+    impl Driver for MockMmioDriver {
+        fn name(&self) -> &'static str {
+            "mock-mmio"
+        }
+
+        fn shutdown(&self) -> Result<()> {
+            ArchImpl::kern_address_space()
+                .lock_save_irq()
+                .unmap_mmio(self.mmio)?;
+
+            self.shut_down.store(true, Ordering::SeqCst);
+
+            Ok(())
+        }
+    }
+
+    struct MockDriver(&'static str);
+
+    impl Driver for MockDriver {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+    }
+
+    ktest! {
+        fn probe_all_retries_deferred_probes() {
+            let mut dm = DriverManager::new();
+
+            // "a" depends on "b", which is listed after it. "c" depends on a
+            // device that never appears.
+            let deps = [("a", Some("b")), ("b", None), ("c", Some("missing"))];
+
+            let names = deps.iter().map(|(name, _)| *name).collect();
+
+            let unresolved = dm.probe_all(names, |dm, name| {
+                let (_, dep) = deps.iter().find(|(n, _)| n == name).unwrap();
+
+                if let Some(dep) = dep
+                    && dm.find_by_name(dep).is_none()
+                {
+                    return Err(ProbeError::Deferred.into());
+                }
+
+                let driver: Arc<dyn Driver> = Arc::new(MockDriver(*name));
+                dm.insert_driver(driver.clone());
+
+                Ok(Some(driver))
+            });
+
+            assert!(dm.find_by_name("a").is_some());
+            assert!(dm.find_by_name("b").is_some());
+            assert!(dm.find_by_name("c").is_none());
+            assert_eq!(unresolved, ["c"]);
+        }
+    }
+
+    ktest! {
+        fn remove_runs_shutdown_and_unmaps_mmio() {
+            let addr_spc = ArchImpl::kern_address_space();
+            let va = addr_spc
+                .lock_save_irq()
+                .map_mmio(PhysMemoryRegion::new(
+                    PA::from_value(0x0900_0000),
+                    PAGE_SIZE,
+                ))
+                .unwrap();
+            assert!(addr_spc.lock_save_irq().translate(va).is_some());
+
+            let mock = Arc::new(MockMmioDriver {
+                mmio: VirtMemoryRegion::new(va, PAGE_SIZE),
+                shut_down: AtomicBool::new(false),
+            });
+            let driver: Arc<dyn Driver> = mock.clone();
+
+            let mut dm = DM.lock_save_irq();
+            dm.insert_driver(driver.clone());
+            dm.remove(&driver).unwrap();
+
+            assert!(mock.shut_down.load(Ordering::SeqCst));
+            assert!(dm.find_by_name("mock-mmio").is_none());
+            assert_eq!(dm.remove(&driver), Err(KernelError::InvalidValue));
+            drop(dm);
+
+            assert!(addr_spc.lock_save_irq().translate(va).is_none());
+        }
+    }
+}
@@ -0,0 +1,286 @@
+//! Translation of interrupt specifiers through interrupt nexus nodes.
+//!
+//! A node with an `interrupt-map`, like a PCI host bridge, isn't an interrupt
+//! controller itself but routes its children's interrupts on to one. Each
+//! entry of the map is a child unit address and interrupt specifier, then the
+//! phandle of the parent and the unit address and specifier to use there. A
+//! child's address and specifier are ANDed with `interrupt-map-mask` and
+//! compared with each entry in turn. The parent can be a nexus itself, so the
+//! lookup is repeated until it reaches an interrupt controller.
+
+use crate::{
+    drivers::{DriverManager, fdt_prober::get_fdt},
+    interrupts::{InterruptConfig, InterruptManager},
+};
+use alloc::{sync::Arc, vec::Vec};
+use fdt_parser::{Fdt, Node};
+use libkernel::error::{ProbeError, Result};
+
+/// The most nexus nodes an interrupt is routed through, so a loop in a broken
+/// tree can't hang the probe.
+const MAX_NEXUS_DEPTH: usize = 8;
+
+/// The `interrupt-map` entry matching a child's interrupt.
+#[derive(Debug, PartialEq, Eq)]
+pub struct MapEntry<'a> {
+    /// The phandle of the interrupt parent.
+    pub parent: u32,
+    /// The unit address in the parent's domain.
+    pub address: &'a [u32],
+    /// The interrupt specifier in the parent's domain.
+    pub specifier: &'a [u32],
+}
+
+/// Finds the entry of `map` for `child`, a unit address followed by an
+/// interrupt specifier. `parent_cells` gives the `#address-cells` and
+/// `#interrupt-cells` of the node with a phandle, which determine the length
+/// of each entry.
+pub fn lookup<'a>(
+    map: &'a [u32],
+    mask: Option<&[u32]>,
+    child: &[u32],
+    parent_cells: impl Fn(u32) -> Option<(usize, usize)>,
+) -> Option<MapEntry<'a>> {
+    let matches = |key: &[u32]| {
+        key.iter().zip(child).enumerate().all(|(i, (k, c))| {
+            let m = mask
+                .and_then(|mask| mask.get(i))
+                .copied()
+                .unwrap_or(u32::MAX);
+
+            k & m == c & m
+        })
+    };
+
+    let mut rest = map;
+
+    while rest.len() > child.len() {
+        let (key, tail) = rest.split_at(child.len());
+        let (&parent, tail) = tail.split_first()?;
+        let (address_cells, interrupt_cells) = parent_cells(parent)?;
+
+        if tail.len() < address_cells + interrupt_cells {
+            return None;
+        }
+
+        let (address, tail) = tail.split_at(address_cells);
+        let (specifier, tail) = tail.split_at(interrupt_cells);
+
+        if matches(key) {
+            return Some(MapEntry {
+                parent,
+                address,
+                specifier,
+            });
+        }
+
+        rest = tail;
+    }
+
+    None
+}
+
+/// Reads a property made of big-endian cells.
+fn cells(node: &Node, name: &str) -> Option<Vec<u32>> {
+    let raw = node.find_property(name)?.raw_value();
+
+    Some(
+        raw.chunks_exact(4)
+            .map(|cell| u32::from_be_bytes(cell.try_into().unwrap()))
+            .collect(),
+    )
+}
+
+fn cell_count(node: &Node, name: &str) -> Option<usize> {
+    node.find_property(name).map(|prop| prop.u32() as usize)
+}
+
+fn find_by_phandle(fdt: &Fdt<'static>, phandle: u32) -> Option<Node<'static>> {
+    fdt.all_nodes().find(|node| {
+        node.find_property("phandle")
+            .or_else(|| node.find_property("linux,phandle"))
+            .map(|prop| prop.u32())
+            == Some(phandle)
+    })
+}
+
+/// Translates the interrupt `specifier` of the child of `nexus` at unit
+/// `address` into the interrupt controller that handles it and the specifier
+/// to hand to that controller's `parse_fdt_interrupt_regs`.
+///
+/// `address` has as many cells as the nexus's `#address-cells`, and
+/// `specifier` as many as its `#interrupt-cells`.
+pub fn map_interrupt(
+    fdt: &Fdt<'static>,
+    mut nexus: Node<'static>,
+    address: &[u32],
+    specifier: &[u32],
+) -> Result<(Node<'static>, Vec<u32>)> {
+    let mut child: Vec<u32> = address.iter().chain(specifier).copied().collect();
+
+    for _ in 0..MAX_NEXUS_DEPTH {
+        let map = cells(&nexus, "interrupt-map").ok_or(ProbeError::NoParentInterrupt)?;
+        let mask = cells(&nexus, "interrupt-map-mask");
+
+        let entry = lookup(&map, mask.as_deref(), &child, |phandle| {
+            let parent = find_by_phandle(fdt, phandle)?;
+
+            Some((
+                cell_count(&parent, "#address-cells").unwrap_or(0),
+                cell_count(&parent, "#interrupt-cells")?,
+            ))
+        })
+        .ok_or(ProbeError::NoInterrupts)?;
+
+        let parent = find_by_phandle(fdt, entry.parent).ok_or(ProbeError::NoParentInterrupt)?;
+
+        if parent.find_property("interrupt-controller").is_some() {
+            return Ok((parent, entry.specifier.to_vec()));
+        }
+
+        if parent.find_property("interrupt-map").is_none() {
+            return Err(ProbeError::NotInterruptController.into());
+        }
+
+        child = entry
+            .address
+            .iter()
+            .chain(entry.specifier)
+            .copied()
+            .collect();
+        nexus = parent;
+    }
+
+    Err(ProbeError::NoParentInterrupt.into())
+}
+
+/// Resolves the `index`th interrupt of the device at `node` to the manager of
+/// the controller that handles it, and the interrupt's config there. If the
+/// device's interrupt parent is a nexus, the interrupt is first translated
+/// through its `interrupt-map`.
+///
+/// Returns [`ProbeError::Deferred`] if the controller hasn't been probed yet.
+pub fn resolve_interrupt(
+    dm: &DriverManager,
+    node: &Node<'static>,
+    index: usize,
+) -> Result<(Arc<InterruptManager>, InterruptConfig)> {
+    let parent = node
+        .interrupt_parent()
+        .ok_or(ProbeError::NoParentInterrupt)?
+        .node;
+
+    let specifier: Vec<u32> = node
+        .interrupts()
+        .ok_or(ProbeError::NoInterrupts)?
+        .nth(index)
+        .ok_or(ProbeError::NoInterrupts)?
+        .collect();
+
+    let (intc, specifier) = if parent.find_property("interrupt-map").is_some() {
+        // The map is keyed on the start of the device's unit address, in the
+        // nexus's address cells.
+        let address_cells = cell_count(&parent, "#address-cells").unwrap_or(0);
+        let mut address = cells(node, "reg").unwrap_or_default();
+        address.resize(address_cells, 0);
+
+        map_interrupt(&get_fdt(), parent, &address, &specifier)?
+    } else {
+        (parent, specifier)
+    };
+
+    let manager = dm
+        .find_by_name(intc.name)
+        .ok_or(ProbeError::Deferred)?
+        .as_interrupt_manager()
+        .ok_or(ProbeError::NotInterruptController)?;
+
+    let config = manager.parse_fdt_interrupt_regs(&mut specifier.into_iter())?;
+
+    Ok((manager, config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{drivers::fdt_prober::test_fdt::FdtBuilder, ktest};
+
+    const GIC: u32 = 1;
+    const PCIE: u32 = 2;
+
+    /// A GIC-style controller taking three cells, with two address cells.
+    fn gic_cells(phandle: u32) -> Option<(usize, usize)> {
+        (phandle == GIC).then_some((2, 3))
+    }
+
+    /// How QEMU's `virt` machine routes PCI INTx: the slot in bits 11 to 15 of
+    /// the address and the pin are swizzled across four SPIs.
+    fn pci_map() -> Vec<u32> {
+        let mut map = Vec::new();
+
+        for slot in 0..4 {
+            for pin in 1..=4 {
+                let spi = 3 + (slot + pin - 1) % 4;
+                map.extend([slot << 11, 0, 0, pin, GIC, 0, 0, 0, spi, 4]);
+            }
+        }
+
+        map
+    }
+
+    ktest! {
+        fn interrupt_map_translates_through_nexus() {
+            let map = pci_map();
+            let mask = [0x1800, 0, 0, 7];
+
+            // Slot 1, function 2, INTB. The function bits are masked off.
+            let child = [1 << 11 | 2 << 8, 0, 0, 2];
+            assert_eq!(
+                lookup(&map, Some(&mask), &child, gic_cells),
+                Some(MapEntry { parent: GIC, address: &[0, 0], specifier: &[0, 5, 4] })
+            );
+
+            // Without a mask every bit must match.
+            assert_eq!(lookup(&map, None, &child, gic_cells), None);
+            assert_eq!(lookup(&map, Some(&mask), &[0, 0, 0, 5], gic_cells), None);
+            assert_eq!(lookup(&map, Some(&mask), &child, |_| None), None);
+
+            // The same through a tree, with a bridge behind the host bridge
+            // passing its interrupts straight up.
+            let mut fdt = FdtBuilder::new();
+            fdt.begin_node("intc@8000000")
+                .prop("interrupt-controller", &[])
+                .prop_cells("#interrupt-cells", &[3])
+                .prop_cells("#address-cells", &[2])
+                .prop_cells("phandle", &[GIC])
+                .end_node();
+            fdt.begin_node("pcie@10000000")
+                .prop_cells("#interrupt-cells", &[1])
+                .prop_cells("#address-cells", &[3])
+                .prop_cells("interrupt-map-mask", &mask)
+                .prop_cells("interrupt-map", &map)
+                .prop_cells("phandle", &[PCIE])
+                .end_node();
+            fdt.begin_node("bridge")
+                .prop_cells("#interrupt-cells", &[1])
+                .prop_cells("#address-cells", &[3])
+                .prop_cells("interrupt-map-mask", &[0, 0, 0, 7])
+                .prop_cells("interrupt-map", &[0, 0, 0, 1, PCIE, 3 << 11, 0, 0, 1])
+                .end_node();
+            let fdt = fdt.finish();
+
+            let node = |name: &str| fdt.all_nodes().find(|n| n.name == name).unwrap();
+
+            let (intc, spec) = map_interrupt(&fdt, node("pcie@10000000"), &child[..3], &[2]).unwrap();
+            assert_eq!(intc.name, "intc@8000000");
+            assert_eq!(spec, [0, 5, 4]);
+
+            let (intc, spec) = map_interrupt(&fdt, node("bridge"), &[0, 0, 0], &[1]).unwrap();
+            assert_eq!(intc.name, "intc@8000000");
+            assert_eq!(spec, [0, 6, 4]);
+
+            assert!(map_interrupt(&fdt, node("bridge"), &[0, 0, 0], &[2]).is_err());
+            assert!(map_interrupt(&fdt, node("intc@8000000"), &[0, 0], &[1]).is_err());
+        }
+    }
+}
@@ -5,6 +5,8 @@ use libkernel::error::Result;
 
 use super::{Driver, DriverManager};
 
+pub mod interrupt_map;
+
 bitflags::bitflags! {
     #[derive(Clone, Copy, PartialEq, Eq)]
     pub struct FdtFlags: u32 {
@@ -13,9 +15,37 @@ bitflags::bitflags! {
 }
 
 #[repr(C)]
-#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum DeviceMatchType {
     FdtCompatible(&'static str),
+    /// Matches a node compatible with any of a family of devices.
+    FdtCompatibleAny(&'static [&'static str]),
+}
+
+impl DeviceMatchType {
+    /// Returns true if a node listing `compat` in its `compatible` property
+    /// matches.
+    pub fn matches_fdt_compatible(&self, compat: &str) -> bool {
+        match self {
+            DeviceMatchType::FdtCompatible(s) => *s == compat,
+            DeviceMatchType::FdtCompatibleAny(list) => list.contains(&compat),
+        }
+    }
+}
+
+pub trait FdtNodeExt {
+    /// Returns true if the node's `status` property is absent or "okay".
+    /// Nodes that are "disabled", "reserved" or "fail" must not be probed.
+    fn is_enabled(&self) -> bool;
+}
+
+impl FdtNodeExt for fdt_parser::Node<'_> {
+    fn is_enabled(&self) -> bool {
+        matches!(
+            self.find_property("status").map(|status| status.str()),
+            None | Some("okay" | "ok")
+        )
+    }
 }
 
 #[derive(Clone)]
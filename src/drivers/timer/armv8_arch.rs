@@ -8,11 +8,12 @@ use libkernel::error::Result;
 use log::warn;
 
 use crate::{
+    clock::vvar,
     drivers::{
         Driver, DriverManager,
         init::PlatformBus,
         probe::{DeviceDescriptor, DeviceMatchType},
-        timer::{SYS_TIMER, SysTimer},
+        timer::{SCHED_TICK, SYS_TIMER, SysTimer},
     },
     interrupts::{ClaimedInterrupt, InterruptDescriptor::Ppi},
     kernel_driver,
@@ -84,13 +85,15 @@ fn armv8_timer_probe(dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<
                     freq,
                 });
 
-                base_driver.schedule_interrupt(Some(base_driver.now() + Duration::from_secs(5)));
+                base_driver.schedule_interrupt(Some(base_driver.now() + SCHED_TICK));
 
                 SysTimer::from_driver(base_driver)
             })?;
 
             if SYS_TIMER.set(sys_timer.clone()).is_err() {
                 warn!("Failed to set system timer");
+            } else if let Err(e) = vvar::set_boot_time(sys_timer.start_time) {
+                warn!("Failed to publish boot time to the vvar page: {e}");
             }
 
             Ok(sys_timer)
@@ -1,6 +1,7 @@
 use super::Driver;
 use crate::interrupts::{InterruptDescriptor, InterruptHandler};
 use crate::per_cpu_private;
+use crate::sched;
 use crate::sync::OnceLock;
 use alloc::{collections::binary_heap::BinaryHeap, sync::Arc};
 use core::{
@@ -12,7 +13,8 @@ use core::{
 
 pub mod armv8_arch;
 
-const USER_HZ: u64 = 100;
+/// The tick rate userspace assumes, reported as `AT_CLKTCK`.
+pub const USER_HZ: u64 = 100;
 
 /// Represents a fixed point in monotonic time.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -106,11 +108,16 @@ impl Add<Duration> for Instant {
     type Output = Self;
 
     fn add(self, rhs: Duration) -> Self::Output {
-        let secs_tick = rhs.as_secs() * self.freq;
-        let nsecs_tick = ((self.freq as u128 * rhs.subsec_nanos() as u128) / 1_000_000_000) as u64;
+        // Userspace can ask for absurdly long timeouts; saturate rather than
+        // wrapping into the past.
+        let ticks = rhs
+            .as_nanos()
+            .checked_mul(self.freq as u128)
+            .and_then(|t| u64::try_from(t / 1_000_000_000).ok())
+            .unwrap_or(u64::MAX);
 
         Self {
-            ticks: self.ticks + secs_tick + nsecs_tick,
+            ticks: self.ticks.saturating_add(ticks),
             freq: self.freq,
         }
     }
@@ -140,6 +147,10 @@ impl PartialOrd for Instant {
     }
 }
 
+/// Period of the scheduler tick. The timer always fires at least this often so
+/// that a CPU-bound task can't starve others of the CPU.
+pub const SCHED_TICK: Duration = Duration::from_millis(10);
+
 pub trait HwTimer: Send + Sync + Driver {
     /// Return an instant that represents this instant.
     fn now(&self) -> Instant;
@@ -171,8 +182,7 @@ impl InterruptHandler for SysTimer {
                 match event.what {
                     WakeupKind::Task(waker) => waker.wake(),
                     WakeupKind::Preempt => {
-                        // Do nothing, the IRQ return-to-userspace code will
-                        // call schedule() for us.
+                        // Handled by the tick below.
                     }
                 }
             } else {
@@ -181,15 +191,12 @@ impl InterruptHandler for SysTimer {
             }
         }
 
-        // Always re-arm: either next task/event, or a periodic/preemption tick.
-        let next_deadline = wake_q.peek().map(|e| e.when).or_else(|| {
-            // fallback: schedule a preemption tick in 50 ms
-            // TODO: Remove when feeling more secure about scheduling
-            let when = self.driver.now() + Duration::from_millis(50);
-            Some(when)
-        });
+        // Account the tick to the running task. The IRQ return-to-userspace
+        // code will then call schedule() to honour any reschedule request.
+        sched::timer_tick(self.driver.now());
 
-        self.driver.schedule_interrupt(next_deadline);
+        self.driver
+            .schedule_interrupt(Some(self.next_interrupt(&wake_q)));
     }
 }
 
@@ -198,6 +205,17 @@ impl SysTimer {
         self.driver.now() - self.start_time
     }
 
+    /// Returns when the timer should next fire: the earliest pending event, or
+    /// the next scheduler tick if that comes sooner.
+    fn next_interrupt(&self, wake_q: &BinaryHeap<WakeupEvent>) -> Instant {
+        let tick = self.driver.now() + SCHED_TICK;
+
+        match wake_q.peek() {
+            Some(event) if event.when < tick => event.when,
+            _ => tick,
+        }
+    }
+
     fn from_driver(driver: Arc<dyn HwTimer>) -> Self {
         Self {
             start_time: driver.now(),
@@ -221,9 +239,8 @@ impl SysTimer {
 
                 // After pushing, we must update the hardware timer in case our
                 // new event is the earliest one.
-                if let Some(next_event) = wakeup_q.peek() {
-                    self.driver.schedule_interrupt(Some(next_event.when));
-                }
+                self.driver
+                    .schedule_interrupt(Some(self.next_interrupt(&wakeup_q)));
 
                 Poll::Pending
             }
@@ -242,24 +259,19 @@ impl SysTimer {
         });
 
         // Ensure the hardware timer is armed for the earliest event.
-        if let Some(next_event) = wake_q.peek() {
-            self.driver.schedule_interrupt(Some(next_event.when));
-        }
+        self.driver
+            .schedule_interrupt(Some(self.next_interrupt(&wake_q)));
     }
 
     /// Arms the hardware timer on the current CPU so that the next scheduled
-    /// `WakeupEvent` (or the fallback preemption tick) will fire.
+    /// `WakeupEvent` (or the scheduler tick) will fire.
     /// Secondary CPUs should call this right after they have enabled their
     /// interrupt controller so that they start receiving timer interrupts.
     pub fn kick_current_cpu(&self) {
         let wake_q = WAKEUP_Q.borrow_mut();
 
-        let next_deadline = wake_q.peek().map(|e| e.when).or_else(|| {
-            // Fallback: re-use the same 15 ms periodic tick as the primary CPU.
-            Some(self.driver.now() + Duration::from_millis(15))
-        });
-
-        self.driver.schedule_interrupt(next_deadline);
+        self.driver
+            .schedule_interrupt(Some(self.next_interrupt(&wake_q)));
     }
 }
 
@@ -311,3 +323,40 @@ static SYS_TIMER: OnceLock<Arc<SysTimer>> = OnceLock::new();
 per_cpu_private! {
     static WAKEUP_Q: BinaryHeap<WakeupEvent> = BinaryHeap::new;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ktest;
+
+    const FREQ: u64 = 62_500_000;
+
+    ktest! {
+        fn instant_to_duration_does_not_overflow() {
+            // Multiplying these ticks by 1e9 overflows a u64.
+            let instant = Instant {
+                ticks: u64::MAX,
+                freq: FREQ,
+            };
+            let d = Duration::from(instant);
+
+            assert_eq!(d.as_secs(), u64::MAX / FREQ);
+            assert_eq!(
+                d.subsec_nanos() as u64,
+                (u64::MAX % FREQ) * 1_000_000_000 / FREQ
+            );
+        }
+    }
+
+    ktest! {
+        fn instant_add_saturates() {
+            let instant = Instant {
+                ticks: 100,
+                freq: FREQ,
+            };
+
+            assert_eq!((instant + Duration::from_millis(1)).ticks(), 100 + FREQ / 1000);
+            assert_eq!((instant + Duration::MAX).ticks(), u64::MAX);
+        }
+    }
+}
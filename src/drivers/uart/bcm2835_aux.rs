@@ -98,6 +98,26 @@ impl Bcm2835AuxRegBank {
         }
     }
 
+    /// Writes bytes from `buf` while the transmit FIFO can accept them and
+    /// returns how many were written.
+    pub fn try_write_buf(&mut self, buf: &[u8]) -> usize {
+        let mut written = 0;
+
+        for b in buf {
+            if !matches!(
+                self.lsr.read_as_enum(AUX_MU_LSR_REG::TransmitterEmpty),
+                Some(AUX_MU_LSR_REG::TransmitterEmpty::Value::CanAccept)
+            ) {
+                break;
+            }
+
+            self.io.write(AUX_MU_IO_REG::DATA.val(*b as u32));
+            written += 1;
+        }
+
+        written
+    }
+
     fn send_byte(&mut self, b: u8) {
         loop {
             match self.lsr.read_as_enum(AUX_MU_LSR_REG::TransmitterEmpty) {
@@ -152,6 +172,10 @@ impl Console for Bcm2835AuxUart {
         buf.iter().for_each(|b| regs.send_byte(*b));
     }
 
+    fn try_write_buf(&self, buf: &[u8]) -> usize {
+        self.regs.lock_save_irq().try_write_buf(buf)
+    }
+
     fn register_input_handler(&self, handler: Weak<dyn TtyInputHandler>) {
         *self.tty_handler.lock_save_irq() = Some(handler);
     }
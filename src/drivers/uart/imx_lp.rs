@@ -3,14 +3,13 @@ use crate::{
     drivers::{
         DeviceDescriptor, Driver, DriverManager,
         init::PlatformBus,
-        probe::{DeviceMatchType, FdtFlags},
-        uart::{UART_CHAR_DEV, Uart},
+        probe::{DeviceMatchType, interrupt_map::resolve_interrupt},
+        uart::Uart,
     },
     kernel_driver,
 };
 use aarch64_cpu::registers::{ReadWriteable, Readable, Writeable};
 use alloc::{boxed::Box, sync::Arc};
-use core::hint::spin_loop;
 use libkernel::{
     KernAddressSpace, VirtualMemory,
     error::Result,
@@ -57,6 +56,11 @@ register_bitfields![u32,
         RIE OFFSET(21) NUMBITS(1) [
             Disable = 0,
             Enable = 1
+        ],
+        /// Transmit Interrupt Enable
+        TIE OFFSET(23) NUMBITS(1) [
+            Disable = 0,
+            Enable = 1
         ]
     ],
 
@@ -106,16 +110,31 @@ impl Imx8UlpLp {
 }
 
 impl UartDriver for Imx8UlpLp {
-    fn write_buf(&mut self, buf: &[u8]) {
+    fn try_write_buf(&mut self, buf: &[u8]) -> usize {
+        let mut written = 0;
+
         for c in buf {
-            // Wait until the transmit data register is empty.
-            while !self.regs.stat.is_set(STAT::TDRE) {
-                spin_loop();
+            if !self.regs.stat.is_set(STAT::TDRE) {
+                break;
             }
 
-            // Write the byte to the data register.
             self.regs.data.write(DATA::DATA.val(*c as u32));
+            written += 1;
         }
+
+        written
+    }
+
+    fn has_tx_interrupt(&self) -> bool {
+        true
+    }
+
+    fn set_tx_interrupt(&mut self, enable: bool) {
+        self.regs.ctrl.modify(if enable {
+            CTRL::TIE::Enable
+        } else {
+            CTRL::TIE::Disable
+        });
     }
 
     fn drain_uart_rx(&mut self, buf: &mut [u8]) -> usize {
@@ -135,9 +154,11 @@ impl UartDriver for Imx8UlpLp {
 
 impl core::fmt::Write for Imx8UlpLp {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        self.write_buf(s.as_bytes());
-
-        Ok(())
+        if self.write_buf(s.as_bytes()) {
+            Ok(())
+        } else {
+            Err(core::fmt::Error)
+        }
     }
 }
 
@@ -146,44 +167,32 @@ pub fn imx8ulp_lpuart_probe(
     d: DeviceDescriptor,
 ) -> Result<Arc<dyn Driver>> {
     match d {
-        DeviceDescriptor::Fdt(fdt_node, flags) => {
+        DeviceDescriptor::Fdt(fdt_node, _) => {
             use libkernel::error::ProbeError::*;
 
             let mut regs = fdt_node.reg().ok_or(NoReg)?;
             let region = regs.next().ok_or(NoReg)?;
             let size = region.size.ok_or(NoRegSize)?;
 
-            let mut interrupts = fdt_node
-                .interrupts()
-                .ok_or(NoInterrupts)?
-                .next()
-                .ok_or(NoInterrupts)?;
-
-            let interrupt_node = fdt_node.interrupt_parent().ok_or(NoParentInterrupt)?.node;
-
-            let interrupt_manager = dm
-                .find_by_name(interrupt_node.name)
-                .ok_or(Deferred)?
-                .as_interrupt_manager()
-                .ok_or(NotInterruptController)?;
-
-            let uart_cdev = UART_CHAR_DEV.get().ok_or(Deferred)?;
-
-            let interrupt_config = interrupt_manager.parse_fdt_interrupt_regs(&mut interrupts)?;
-
-            let mem =
-                ArchImpl::kern_address_space()
-                    .lock_save_irq()
-                    .map_mmio(PhysMemoryRegion::new(
-                        PA::from_value(region.address as usize),
-                        size,
-                    ))?;
-
-            let dev = interrupt_manager.claim_interrupt(interrupt_config, |claimed_interrupt| {
-                Uart::new(Imx8UlpLp::new(mem), claimed_interrupt, fdt_node.name)
-            })?;
-
-            uart_cdev.register_console(dev.clone(), flags.contains(FdtFlags::ACTIVE_CONSOLE))?;
+            let (interrupt_manager, interrupt_config) = resolve_interrupt(dm, &fdt_node, 0)?;
+
+            let base = PA::from_value(region.address as usize);
+
+            let mem = ArchImpl::kern_address_space()
+                .lock_save_irq()
+                .map_mmio(PhysMemoryRegion::new(base, size))?;
+
+            let dev = interrupt_manager.claim_threaded_interrupt(
+                interrupt_config,
+                |claimed_interrupt| {
+                    Uart::new(
+                        Imx8UlpLp::new(mem),
+                        claimed_interrupt,
+                        fdt_node.name,
+                        Some(base),
+                    )
+                },
+            )?;
 
             Ok(dev)
         }
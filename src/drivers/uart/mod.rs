@@ -22,7 +22,12 @@
 //!     UART as a char device to obtain a `DriverDescriptor`. Also exposes the
 //!     device to userspace via `devfs`.
 
-use core::sync::atomic::{AtomicU64, Ordering};
+use core::{
+    cmp::min,
+    hint::spin_loop,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
 use super::{
     CharDriver, Driver, DriverManager, OpenableDevice, ReservedMajors, fs::dev::devfs,
@@ -36,24 +41,109 @@ use crate::{
     fs::open_file::OpenFile,
     interrupts::{ClaimedInterrupt, InterruptHandler},
     kernel_driver,
-    sync::{OnceLock, SpinLock},
+    sync::{CondVar, OnceLock, SpinLock},
 };
 use alloc::{
     boxed::Box,
-    collections::btree_map::{BTreeMap, Entry},
+    collections::{
+        VecDeque,
+        btree_map::{BTreeMap, Entry},
+    },
     format,
     sync::{Arc, Weak},
 };
 use libkernel::{
     driver::CharDevDescriptor,
-    error::{KernelError, Result},
+    error::{FsError, KernelError, Result},
     fs::{OpenFlags, attr::FilePermissions},
+    memory::address::PA,
+    sync::condvar::WakeupType,
 };
 
 //pub mod bcm2835_aux;
 pub mod imx_lp;
 pub mod pl011;
 
+/// Parity setting of a UART line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UartParity {
+    None,
+    Odd,
+    Even,
+}
+
+/// Line settings requested for a UART, such as the options of the FDT
+/// `stdout-path`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UartLineConfig {
+    pub baud: u32,
+    pub parity: UartParity,
+    pub data_bits: u8,
+    pub stop_bits: u8,
+}
+
+impl Default for UartLineConfig {
+    /// 115200 baud, 8N1.
+    fn default() -> Self {
+        Self {
+            baud: 115_200,
+            parity: UartParity::None,
+            data_bits: 8,
+            stop_bits: 1,
+        }
+    }
+}
+
+impl UartLineConfig {
+    /// Parses options of the form `<baud>[<parity>[<bits>[r]]]`, e.g.
+    /// "115200n8", as used by `stdout-path` and Linux's `console=`. Parity
+    /// and data bits default to "n8", and there is always one stop bit. The
+    /// trailing 'r' requests flow control, which is ignored.
+    pub fn parse(options: &str) -> Option<Self> {
+        let digits = options
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(options.len());
+        let (baud, rest) = options.split_at(digits);
+
+        let baud = baud.parse().ok().filter(|&b| b > 0)?;
+        let mut rest = rest.chars();
+
+        let parity = match rest.next() {
+            None | Some('n') => UartParity::None,
+            Some('o') => UartParity::Odd,
+            Some('e') => UartParity::Even,
+            Some(_) => return None,
+        };
+
+        let data_bits = match rest.next() {
+            None => 8,
+            Some(c @ '5'..='8') => c as u8 - b'0',
+            Some(_) => return None,
+        };
+
+        if !matches!(rest.next(), None | Some('r')) || rest.next().is_some() {
+            return None;
+        }
+
+        Some(Self {
+            baud,
+            parity,
+            data_bits,
+            stop_bits: 1,
+        })
+    }
+}
+
+/// How many times [`UartDriver::write_buf`] polls a full transmit FIFO before
+/// giving up on the rest of the buffer.
+///
+/// At 115200 baud a byte drains in under 100us, so a working FIFO never needs
+/// anywhere near this many polls. The cost of the bound is that each write to
+/// a wedged transmitter still stalls for a few milliseconds before its output
+/// is dropped. Without it, a wedged UART would hang the kernel, including the
+/// panic path, which logs through the console.
+pub const TX_SPIN_LIMIT: usize = 100_000;
+
 /// A trait for low-level, hardware-specific UART drivers.
 ///
 /// Implementors of this trait are responsible for the direct hardware
@@ -64,11 +154,49 @@ pub mod pl011;
 /// In addition to this trait, a driver must also implement `core::fmt::Write`
 /// to be used with the generic `Uart` wrapper.
 pub trait UartDriver: core::fmt::Write + Send + Sync + 'static {
-    /// Writes a raw byte slice to the UART's transmit buffer.
+    /// Writes a raw byte slice to the UART's transmit FIFO, waiting for room
+    /// as needed.
     ///
-    /// This method should block until all bytes in the buffer have been
-    /// successfully written to the hardware's transmit FIFO.
-    fn write_buf(&mut self, buf: &[u8]);
+    /// The wait for room is bounded by [`TX_SPIN_LIMIT`] polls. If the FIFO
+    /// stops draining, the rest of `buf` is dropped and `false` is returned,
+    /// so that a wedged UART can't hang the kernel.
+    fn write_buf(&mut self, mut buf: &[u8]) -> bool {
+        let mut spins = 0;
+
+        while !buf.is_empty() {
+            let n = self.try_write_buf(buf);
+
+            if n == 0 {
+                spins += 1;
+
+                if spins == TX_SPIN_LIMIT {
+                    return false;
+                }
+
+                spin_loop();
+            } else {
+                spins = 0;
+                buf = &buf[n..];
+            }
+        }
+
+        true
+    }
+
+    /// Writes bytes from `buf` while the transmit FIFO has room, stopping as
+    /// soon as it is full. Returns the number of bytes written.
+    fn try_write_buf(&mut self, buf: &[u8]) -> usize;
+
+    /// Whether the hardware can raise an interrupt when the transmit FIFO has
+    /// room. If so, `Uart` queues writes and feeds the FIFO from its interrupt
+    /// handler rather than spinning.
+    fn has_tx_interrupt(&self) -> bool {
+        false
+    }
+
+    /// Enables or disables the transmit interrupt. Only called if
+    /// `has_tx_interrupt` returns `true`.
+    fn set_tx_interrupt(&mut self, _enable: bool) {}
 
     /// Reads all available bytes from the UART's receive buffer into `buf`.
     ///
@@ -81,6 +209,14 @@ pub trait UartDriver: core::fmt::Write + Send + Sync + 'static {
     /// The number of bytes that were actually read from the FIFO and written
     /// into `buf`.
     fn drain_uart_rx(&mut self, buf: &mut [u8]) -> usize;
+
+    /// Programs the line settings. `clock_hz` is the frequency of the UART's
+    /// reference clock, if the device tree gives one.
+    ///
+    /// Drivers that can't reprogram the line keep the firmware's settings.
+    fn configure_line(&mut self, _config: &UartLineConfig, _clock_hz: Option<u32>) -> Result<()> {
+        Err(KernelError::NotSupported)
+    }
 }
 
 /// A generic, high-level UART device.
@@ -88,29 +224,126 @@ pub trait UartDriver: core::fmt::Write + Send + Sync + 'static {
 /// This struct acts as a wrapper around a concrete `UartDriver` implementation,
 /// providing common high-level functionality such as console integration,
 /// interrupt handling, and TTY input routing.
+///
+/// If the driver supports a transmit interrupt, writes are queued in a ring
+/// buffer which the interrupt handler drains into the hardware FIFO. Writers
+/// that can sleep wait on [`Console::tx_space`] when the ring is full, while
+/// `write_buf`, used for logging and panics, spins on the FIFO instead.
 pub struct Uart<D: UartDriver> {
     driver: SpinLock<D>,
+    /// Bytes waiting for space in the transmit FIFO. Always locked after
+    /// `driver`.
+    tx_ring: SpinLock<VecDeque<u8>>,
+    /// Set while a writer sleeps waiting for room in `tx_ring`. Always locked
+    /// after `tx_ring`.
+    tx_waiting: CondVar<bool>,
+    /// Bytes taken from the receive FIFO by the hard IRQ handler, waiting for
+    /// the interrupt thread to pass them to the TTY.
+    rx_ring: SpinLock<VecDeque<u8>>,
     name: &'static str,
-    _interrupt: ClaimedInterrupt,
+    /// The physical address of the UART's registers.
+    base: Option<PA>,
+    interrupt: ClaimedInterrupt,
     tty_handler: SpinLock<Option<Weak<dyn TtyInputHandler>>>,
 }
 
+/// The most bytes queued for interrupt-driven transmit.
+const TX_RING_SZ: usize = 4096;
+
 impl<D: UartDriver> Console for Uart<D> {
     fn write_char(&self, c: char) {
-        let _ = self.driver.lock_save_irq().write_char(c);
+        let mut driver = self.driver.lock_save_irq();
+
+        self.flush_tx(&mut driver, &mut self.tx_ring.lock_save_irq());
+
+        let _ = driver.write_char(c);
     }
 
     fn write_fmt(&self, args: core::fmt::Arguments) -> core::fmt::Result {
-        self.driver.lock_save_irq().write_fmt(args)
+        let mut driver = self.driver.lock_save_irq();
+
+        self.flush_tx(&mut driver, &mut self.tx_ring.lock_save_irq());
+
+        driver.write_fmt(args)
+    }
+
+    fn write_buf(&self, mut buf: &[u8]) {
+        let mut driver = self.driver.lock_save_irq();
+
+        if !driver.has_tx_interrupt() {
+            // A wedged UART drops what it can't send; there's no one to tell.
+            let _ = driver.write_buf(buf);
+            return;
+        }
+
+        let mut ring = self.tx_ring.lock_save_irq();
+
+        loop {
+            let n = min(buf.len(), TX_RING_SZ - ring.len());
+
+            ring.extend(&buf[..n]);
+            buf = &buf[n..];
+
+            self.pump_tx(&mut driver, &mut ring);
+
+            if buf.is_empty() {
+                return;
+            }
+
+            // The ring is full. We can't sleep here, so wait for the
+            // hardware to take what's queued.
+            self.flush_tx(&mut driver, &mut ring);
+        }
+    }
+
+    fn try_write_buf(&self, buf: &[u8]) -> usize {
+        let mut driver = self.driver.lock_save_irq();
+
+        if !driver.has_tx_interrupt() {
+            return driver.try_write_buf(buf);
+        }
+
+        let mut ring = self.tx_ring.lock_save_irq();
+        let n = min(buf.len(), TX_RING_SZ - ring.len());
+
+        ring.extend(&buf[..n]);
+
+        self.pump_tx(&mut driver, &mut ring);
+
+        n
     }
 
-    fn write_buf(&self, buf: &[u8]) {
-        self.driver.lock_save_irq().write_buf(buf);
+    fn tx_space(&self) -> Option<Pin<Box<dyn Future<Output = ()> + Send>>> {
+        if !self.driver.lock_save_irq().has_tx_interrupt() {
+            return None;
+        }
+
+        let ring = self.tx_ring.lock_save_irq();
+
+        if ring.len() < TX_RING_SZ {
+            return Some(Box::pin(async {}));
+        }
+
+        // Flag the wait while holding the ring, so the interrupt handler
+        // can't drain it without seeing the flag.
+        self.tx_waiting.update(|waiting| {
+            *waiting = true;
+            WakeupType::None
+        });
+
+        Some(Box::pin(
+            self.tx_waiting
+                .wait_until(|waiting| (!*waiting).then_some(())),
+        ))
     }
 
     fn register_input_handler(&self, handler: Weak<dyn TtyInputHandler>) {
         *self.tty_handler.lock_save_irq() = Some(handler);
     }
+
+    fn phys_base(&self) -> Option<PA> {
+        self.base
+    }
 }
 
 impl<D: UartDriver> Uart<D> {
@@ -122,28 +355,109 @@ impl<D: UartDriver> Uart<D> {
     ///   driver.
     /// * `interrupt`: The `ClaimedInterrupt` resource for this UART's IRQ.
     /// * `name`: A static string-slice to identify this device.
-    pub fn new(driver: D, interrupt: ClaimedInterrupt, name: &'static str) -> Self {
+    /// * `base`: The physical address of the UART's registers, if known.
+    pub fn new(
+        driver: D,
+        interrupt: ClaimedInterrupt,
+        name: &'static str,
+        base: Option<PA>,
+    ) -> Self {
         Self {
             driver: SpinLock::new(driver),
+            tx_ring: SpinLock::new(VecDeque::new()),
+            tx_waiting: CondVar::new(false),
+            rx_ring: SpinLock::new(VecDeque::new()),
             name,
-            _interrupt: interrupt,
+            base,
+            interrupt,
             tty_handler: SpinLock::new(None),
         }
     }
+
+    /// Moves queued bytes into the transmit FIFO until it fills or the ring
+    /// empties. The transmit interrupt is left enabled only while bytes remain.
+    fn pump_tx(&self, driver: &mut D, ring: &mut VecDeque<u8>) {
+        while !ring.is_empty() {
+            let (front, _) = ring.as_slices();
+            let len = front.len();
+            let n = driver.try_write_buf(front);
+
+            ring.drain(..n);
+
+            if n < len {
+                break;
+            }
+        }
+
+        driver.set_tx_interrupt(!ring.is_empty());
+        self.wake_tx_writers(ring);
+    }
+
+    /// Wakes writers sleeping in [`Console::tx_space`] if the ring has room.
+    fn wake_tx_writers(&self, ring: &VecDeque<u8>) {
+        if ring.len() == TX_RING_SZ {
+            return;
+        }
+
+        self.tx_waiting.update(|waiting| {
+            if core::mem::take(waiting) {
+                WakeupType::All
+            } else {
+                WakeupType::None
+            }
+        });
+    }
+
+    /// Passes received bytes on to the TTY, which runs the line discipline.
+    fn forward_rx(&self) {
+        let bytes: VecDeque<u8> = core::mem::take(&mut *self.rx_ring.lock_save_irq());
+
+        if let Some(handler) = self
+            .tty_handler
+            .lock_save_irq()
+            .as_ref()
+            .and_then(|h| h.upgrade())
+        {
+            bytes.into_iter().for_each(|b| handler.push_byte(b));
+        }
+    }
+
+    /// Writes out everything queued, blocking until the FIFO takes it. Used to
+    /// keep blocking writes from overtaking queued ones.
+    fn flush_tx(&self, driver: &mut D, ring: &mut VecDeque<u8>) {
+        if ring.is_empty() {
+            return;
+        }
+
+        let (front, back) = ring.as_slices();
+
+        // If the FIFO has wedged, the rest is dropped rather than retried.
+        let _ = driver.write_buf(front) && driver.write_buf(back);
+        ring.clear();
+
+        driver.set_tx_interrupt(false);
+        self.wake_tx_writers(ring);
+    }
 }
 
 impl<D: UartDriver> Driver for Uart<D> {
     fn name(&self) -> &'static str {
         self.name
     }
+
+    fn as_console(self: Arc<Self>) -> Option<Arc<dyn Console>> {
+        Some(self)
+    }
 }
 
 /// Handles incoming interrupts from the UART hardware.
 impl<D: UartDriver> InterruptHandler for Uart<D> {
     /// The interrupt handler function.
     ///
-    /// The handler drains the UART's receive FIFO and forwards the bytes to the
-    /// registered TTY input handler.
+    /// The handler drains the UART's receive FIFO, then refills the transmit
+    /// FIFO from the transmit ring. The received bytes are forwarded to the
+    /// registered TTY input handler from the interrupt thread if there is one,
+    /// or straight away otherwise.
     fn handle_irq(&self, _desc: crate::interrupts::InterruptDescriptor) {
         const BUF_CAPACITY: usize = 32;
         let mut byte_buf = [0u8; BUF_CAPACITY];
@@ -151,20 +465,31 @@ impl<D: UartDriver> InterruptHandler for Uart<D> {
         // Drain phase: Lock the driver and call its drain method.
         let bytes_read = self.driver.lock_save_irq().drain_uart_rx(&mut byte_buf);
 
-        // Processing phase: If bytes were read, forward them to the TTY.
-        if bytes_read > 0
-            && let Some(handler) = self
-                .tty_handler
-                .lock_save_irq()
-                .as_ref()
-                .and_then(|h| h.upgrade())
+        self.rx_ring.lock_save_irq().extend(&byte_buf[..bytes_read]);
+
         {
-            // Push each received byte to the TTY input queue.
-            byte_buf
-                .into_iter()
-                .take(bytes_read)
-                .for_each(|b| handler.push_byte(b));
+            let mut driver = self.driver.lock_save_irq();
+
+            if driver.has_tx_interrupt() {
+                self.pump_tx(&mut driver, &mut self.tx_ring.lock_save_irq());
+            }
         }
+
+        if !self.interrupt.is_threaded() {
+            self.forward_rx();
+        }
+    }
+
+    fn handle_irq_threaded(&self, _desc: crate::interrupts::InterruptDescriptor) {
+        self.forward_rx();
+    }
+
+    /// The hard handler has already emptied the receive FIFO and refilled the
+    /// transmit FIFO, so there's no need to mask the line while the thread
+    /// forwards input. Masking it would stall transmission and let the receive
+    /// FIFO overflow whenever the thread is slow to run.
+    fn oneshot(&self) -> bool {
+        false
     }
 }
 
@@ -246,4 +571,268 @@ pub fn uart_init(_bus: &mut PlatformBus, dm: &mut DriverManager) -> Result<()> {
 
 static UART_CHAR_DEV: OnceLock<Arc<UartCharDev>> = OnceLock::new();
 
+/// Exposes `console` as a new `/dev/ttyS*` device node.
+pub fn register_console(
+    console: Arc<dyn Console>,
+    active_console: bool,
+) -> Result<CharDevDescriptor> {
+    UART_CHAR_DEV
+        .get()
+        .ok_or(FsError::NoDevice)?
+        .register_console(console, active_console)
+}
+
 kernel_driver!(uart_init);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        drivers::DM,
+        fs::VFS,
+        interrupts::{
+            InterruptConfig, InterruptContext, InterruptController, InterruptDescriptor,
+            InterruptManager, TriggerMode,
+        },
+        ktest,
+        sched::current::current_task_shared,
+    };
+    use alloc::vec::Vec;
+    use core::sync::atomic::AtomicBool;
+    use libkernel::fs::path::Path;
+
+    struct MockConsole {
+        input_handler_registered: AtomicBool,
+    }
+
+    impl Console for MockConsole {
+        fn write_char(&self, _c: char) {}
+
+        fn write_fmt(&self, _args: core::fmt::Arguments) -> core::fmt::Result {
+            Ok(())
+        }
+
+        fn write_buf(&self, _buf: &[u8]) {}
+
+        fn register_input_handler(&self, _handler: Weak<dyn TtyInputHandler>) {
+            self.input_handler_registered.store(true, Ordering::SeqCst);
+        }
+    }
+
+    impl Driver for MockConsole {
+        fn name(&self) -> &'static str {
+            "mock-console"
+        }
+
+        fn as_console(self: Arc<Self>) -> Option<Arc<dyn Console>> {
+            Some(self)
+        }
+    }
+
+    ktest! {
+        async fn test_console_driver_gets_tty_node() {
+            let mock = Arc::new(MockConsole {
+                input_handler_registered: AtomicBool::new(false),
+            });
+
+            let desc = DM
+                .lock_save_irq()
+                .insert_device_driver(mock.clone(), false)
+                .unwrap()
+                .expect("console drivers should get a device node");
+            let path = format!("/dev/ttyS{}", desc.minor);
+
+            let task = current_task_shared();
+            let file = VFS
+                .open(
+                    Path::new(&path),
+                    OpenFlags::O_RDWR,
+                    VFS.root_inode(),
+                    FilePermissions::empty(),
+                    &task,
+                )
+                .await
+                .unwrap();
+
+            // Opening the node wires a tty up to the console.
+            assert!(mock.input_handler_registered.load(Ordering::SeqCst));
+            drop(file);
+        }
+    }
+
+    struct MockIntc;
+
+    impl InterruptController for MockIntc {
+        fn enable_interrupt(&mut self, _i: InterruptConfig) {}
+
+        fn disable_interrupt(&mut self, _i: InterruptDescriptor) {}
+
+        fn read_active_interrupt(&mut self) -> Option<Box<dyn InterruptContext>> {
+            None
+        }
+
+        fn raise_ipi(&mut self, _target_cpu_id: usize) {}
+
+        fn enable_core(&mut self, _cpu_id: usize) {}
+
+        fn parse_fdt_interrupt_regs(
+            &self,
+            _iter: &mut dyn Iterator<Item = u32>,
+        ) -> Result<InterruptConfig> {
+            Err(KernelError::NotSupported)
+        }
+    }
+
+    const MOCK_FIFO_SZ: usize = 4;
+
+    /// A UART whose FIFO only empties when the test says the hardware has
+    /// shifted the bytes out.
+    #[derive(Default)]
+    struct MockTxUart {
+        fifo_level: usize,
+        sent: Vec<u8>,
+        tx_irq: bool,
+    }
+
+    impl core::fmt::Write for MockTxUart {
+        fn write_str(&mut self, _s: &str) -> core::fmt::Result {
+            Ok(())
+        }
+    }
+
+    impl UartDriver for MockTxUart {
+        fn write_buf(&mut self, _buf: &[u8]) -> bool {
+            panic!("interrupt-driven transmit should not spin on the FIFO");
+        }
+
+        fn try_write_buf(&mut self, buf: &[u8]) -> usize {
+            let n = min(buf.len(), MOCK_FIFO_SZ - self.fifo_level);
+
+            self.fifo_level += n;
+            self.sent.extend_from_slice(&buf[..n]);
+
+            n
+        }
+
+        fn drain_uart_rx(&mut self, _buf: &mut [u8]) -> usize {
+            0
+        }
+
+        fn has_tx_interrupt(&self) -> bool {
+            true
+        }
+
+        fn set_tx_interrupt(&mut self, enable: bool) {
+            self.tx_irq = enable;
+        }
+    }
+
+    ktest! {
+        fn tx_interrupts_drain_the_ring() {
+            let manager = InterruptManager::new("mock-intc", Arc::new(SpinLock::new(MockIntc)));
+            let config = InterruptConfig {
+                descriptor: InterruptDescriptor::Spi(1),
+                trigger: TriggerMode::LevelHigh,
+            };
+
+            let uart = manager
+                .claim_interrupt(config, |irq| {
+                    Uart::new(MockTxUart::default(), irq, "mock-tx-uart", None)
+                })
+                .unwrap();
+
+            let msg: Vec<u8> = (0..64).collect();
+            uart.write_buf(&msg);
+
+            // Only the first FIFO-full went out immediately.
+            assert_eq!(uart.driver.lock_save_irq().sent.len(), MOCK_FIFO_SZ);
+
+            let mut irqs = 0;
+
+            while uart.driver.lock_save_irq().tx_irq {
+                // The hardware empties the FIFO and raises the interrupt.
+                uart.driver.lock_save_irq().fifo_level = 0;
+                uart.handle_irq(config.descriptor);
+
+                irqs += 1;
+                assert!(irqs <= msg.len());
+            }
+
+            assert_eq!(irqs, msg.len() / MOCK_FIFO_SZ - 1);
+            assert_eq!(uart.driver.lock_save_irq().sent, msg);
+            assert!(uart.tx_ring.lock_save_irq().is_empty());
+        }
+    }
+
+    ktest! {
+        fn full_tx_ring_wakes_writer_after_interrupt() {
+            let manager = InterruptManager::new("mock-intc", Arc::new(SpinLock::new(MockIntc)));
+            let config = InterruptConfig {
+                descriptor: InterruptDescriptor::Spi(2),
+                trigger: TriggerMode::LevelHigh,
+            };
+
+            let uart = manager
+                .claim_interrupt(config, |irq| {
+                    Uart::new(MockTxUart::default(), irq, "mock-tx-uart", None)
+                })
+                .unwrap();
+
+            let msg = [b'x'; TX_RING_SZ + MOCK_FIFO_SZ];
+            assert_eq!(uart.try_write_buf(&msg), msg.len());
+            assert_eq!(uart.try_write_buf(b"y"), 0);
+
+            let mut space = uart.tx_space().expect("TX interrupts should signal space");
+            let mut poll_space = || {
+                space
+                    .as_mut()
+                    .poll(&mut core::task::Context::from_waker(core::task::Waker::noop()))
+            };
+
+            assert!(poll_space().is_pending());
+
+            // The hardware empties the FIFO, and the interrupt makes room in
+            // the ring.
+            uart.driver.lock_save_irq().fifo_level = 0;
+            uart.handle_irq(config.descriptor);
+
+            assert!(poll_space().is_ready());
+            assert_eq!(uart.try_write_buf(b"y"), 1);
+        }
+    }
+
+    /// A UART whose transmit FIFO never drains.
+    struct WedgedUart;
+
+    impl core::fmt::Write for WedgedUart {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            if self.write_buf(s.as_bytes()) {
+                Ok(())
+            } else {
+                Err(core::fmt::Error)
+            }
+        }
+    }
+
+    impl UartDriver for WedgedUart {
+        fn try_write_buf(&mut self, _buf: &[u8]) -> usize {
+            0
+        }
+
+        fn drain_uart_rx(&mut self, _buf: &mut [u8]) -> usize {
+            0
+        }
+    }
+
+    ktest! {
+        fn wedged_tx_fifo_does_not_hang() {
+            let mut uart = WedgedUart;
+
+            assert!(!uart.write_buf(b"hello"));
+            assert!(core::fmt::Write::write_str(&mut uart, "hello").is_err());
+
+            // Nothing to send is never an error.
+            assert!(uart.write_buf(&[]));
+        }
+    }
+}
@@ -1,9 +1,10 @@
 use crate::{
     arch::ArchImpl,
+    console::early::EarlyUart,
     drivers::{
-        DeviceDescriptor, Driver, DriverManager,
+        DeviceDescriptor, Driver, DriverManager, fdt_prober,
         init::PlatformBus,
-        probe::{DeviceMatchType, FdtFlags},
+        probe::{DeviceMatchType, FdtFlags, interrupt_map::resolve_interrupt},
     },
     kernel_driver,
 };
@@ -11,17 +12,59 @@ use alloc::{boxed::Box, sync::Arc};
 use arm_pl011_uart::{
     DataBits, Interrupts, LineConfig, PL011Registers, Parity, StopBits, UniqueMmioPointer,
 };
-use core::ptr::NonNull;
+use core::{hint::spin_loop, ptr::NonNull};
 use libkernel::{
     KernAddressSpace, VirtualMemory,
-    error::{ProbeError, Result},
+    error::{KernelError, ProbeError, Result},
     memory::{
         address::{PA, VA},
         region::PhysMemoryRegion,
     },
 };
+use log::warn;
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_structs,
+    registers::{ReadOnly, WriteOnly},
+};
+
+use super::{Uart, UartDriver, UartLineConfig, UartParity};
+
+/// The reference clock assumed when the device tree doesn't give one.
+const DEFAULT_SYSCLK: u32 = 16_000_000;
+
+register_structs! {
+    /// The registers the early console uses, leaving the UART set up as the
+    /// firmware left it.
+    pub Pl011EarlyRegs {
+        (0x000 => dr: WriteOnly<u32>),
+        (0x004 => _reserved),
+        (0x018 => fr: ReadOnly<u32>),
+        (0x01c => @END),
+    }
+}
+
+/// `FR`: the transmit FIFO is full.
+const FR_TXFF: u32 = 1 << 5;
+
+/// How many times the early console polls for room in the FIFO before giving
+/// up on a byte, so a wedged UART can't hang the boot.
+const EARLY_TX_SPINS: usize = 100_000;
+
+impl EarlyUart for Pl011EarlyRegs {
+    fn send_byte(&mut self, byte: u8) -> bool {
+        for _ in 0..EARLY_TX_SPINS {
+            if self.fr.get() & FR_TXFF == 0 {
+                self.dr.set(byte as u32);
+                return true;
+            }
 
-use super::{UART_CHAR_DEV, Uart, UartDriver};
+            spin_loop();
+        }
+
+        false
+    }
+}
 
 pub struct PL011 {
     inner: arm_pl011_uart::Uart<'static>,
@@ -43,7 +86,7 @@ impl PL011 {
             stop_bits: StopBits::One,
         };
 
-        uart.enable(line_config, 115_200, 16_000_000).unwrap();
+        uart.enable(line_config, 115_200, DEFAULT_SYSCLK).unwrap();
 
         // Interrupts not enabled yet, just mask RX interrupts in hardware
         uart.set_interrupt_masks(Interrupts::RXI);
@@ -54,17 +97,42 @@ impl PL011 {
 
 impl core::fmt::Write for PL011 {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        self.write_buf(s.as_bytes());
-
-        Ok(())
+        if self.write_buf(s.as_bytes()) {
+            Ok(())
+        } else {
+            Err(core::fmt::Error)
+        }
     }
 }
 
 impl UartDriver for PL011 {
-    fn write_buf(&mut self, buf: &[u8]) {
+    fn try_write_buf(&mut self, buf: &[u8]) -> usize {
+        let mut written = 0;
+
         for c in buf {
+            if self.inner.is_tx_fifo_full() {
+                break;
+            }
+
             self.inner.write_word(*c);
+            written += 1;
+        }
+
+        written
+    }
+
+    fn has_tx_interrupt(&self) -> bool {
+        true
+    }
+
+    fn set_tx_interrupt(&mut self, enable: bool) {
+        let mut masks = Interrupts::RXI;
+
+        if enable {
+            masks |= Interrupts::TXI;
         }
+
+        self.inner.set_interrupt_masks(masks);
     }
 
     fn drain_uart_rx(&mut self, buf: &mut [u8]) -> usize {
@@ -82,6 +150,35 @@ impl UartDriver for PL011 {
 
         bytes_read
     }
+
+    fn configure_line(&mut self, config: &UartLineConfig, clock_hz: Option<u32>) -> Result<()> {
+        let line_config = LineConfig {
+            data_bits: match config.data_bits {
+                5 => DataBits::Bits5,
+                6 => DataBits::Bits6,
+                7 => DataBits::Bits7,
+                _ => DataBits::Bits8,
+            },
+            parity: match config.parity {
+                UartParity::None => Parity::None,
+                UartParity::Odd => Parity::Odd,
+                UartParity::Even => Parity::Even,
+            },
+            stop_bits: match config.stop_bits {
+                2 => StopBits::Two,
+                _ => StopBits::One,
+            },
+        };
+
+        self.inner
+            .enable(line_config, config.baud, clock_hz.unwrap_or(DEFAULT_SYSCLK))
+            .map_err(|_| KernelError::InvalidValue)?;
+
+        // Re-enabling the UART resets the interrupt masks.
+        self.inner.set_interrupt_masks(Interrupts::RXI);
+
+        Ok(())
+    }
 }
 
 pub fn pl011_probe(dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<dyn Driver>> {
@@ -95,40 +192,46 @@ pub fn pl011_probe(dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<dy
 
             let size = region.size.ok_or(ProbeError::NoRegSize)?;
 
-            let mut interrupts = fdt_node
-                .interrupts()
-                .ok_or(ProbeError::NoInterrupts)?
-                .next()
-                .ok_or(ProbeError::NoInterrupts)?;
+            let (interrupt_manager, interrupt_config) = resolve_interrupt(dm, &fdt_node, 0)?;
 
-            let interrupt_node = fdt_node
-                .interrupt_parent()
-                .ok_or(ProbeError::NoParentInterrupt)?
-                .node;
+            let base = PA::from_value(region.address as usize);
 
-            let interrupt_manager = dm
-                .find_by_name(interrupt_node.name)
-                .ok_or(ProbeError::Deferred)?
-                .as_interrupt_manager()
-                .ok_or(ProbeError::NotInterruptController)?;
+            let mem = ArchImpl::kern_address_space()
+                .lock_save_irq()
+                .map_mmio(PhysMemoryRegion::new(base, size))?;
 
-            let uart_cdev = UART_CHAR_DEV.get().ok_or(ProbeError::Deferred)?;
+            let mut pl011 = PL011::new(mem);
 
-            let mem =
-                ArchImpl::kern_address_space()
-                    .lock_save_irq()
-                    .map_mmio(PhysMemoryRegion::new(
-                        PA::from_value(region.address as usize),
-                        size,
-                    ))?;
+            if flags.contains(FdtFlags::ACTIVE_CONSOLE)
+                && let Some(line) = fdt_prober::stdout_line_config()
+            {
+                let clock_hz = fdt_node.find_property("clock-frequency").map(|p| p.u32());
 
-            let interrupt_config = interrupt_manager.parse_fdt_interrupt_regs(&mut interrupts)?;
+                // Settings the UART can't do mustn't cost us the console, so
+                // fall back to 8N1.
+                if let Err(e) = pl011.configure_line(&line, clock_hz) {
+                    warn!(
+                        "{}: can't program line {line:?} ({e}); falling back to 8N1",
+                        fdt_node.name
+                    );
 
-            let dev = interrupt_manager.claim_interrupt(interrupt_config, |claimed_interrupt| {
-                Uart::new(PL011::new(mem), claimed_interrupt, fdt_node.name)
-            })?;
+                    let fallback = UartLineConfig {
+                        parity: UartParity::None,
+                        data_bits: 8,
+                        stop_bits: 1,
+                        ..line
+                    };
+
+                    if pl011.configure_line(&fallback, clock_hz).is_err() {
+                        pl011.configure_line(&UartLineConfig::default(), clock_hz)?;
+                    }
+                }
+            }
 
-            uart_cdev.register_console(dev.clone(), flags.contains(FdtFlags::ACTIVE_CONSOLE))?;
+            let dev = interrupt_manager
+                .claim_threaded_interrupt(interrupt_config, |claimed_interrupt| {
+                    Uart::new(pl011, claimed_interrupt, fdt_node.name, Some(base))
+                })?;
 
             Ok(dev)
         }
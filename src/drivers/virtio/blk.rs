@@ -0,0 +1,323 @@
+//! The virtio block device.
+//!
+//! Requests go through a single virtqueue, one at a time. Each is a chain of
+//! a header the device reads, the data, and a status byte the device writes.
+//! Data is copied through a DMA bounce buffer, since the buffers handed to
+//! [`BlockDevice`] needn't be physically contiguous, and the caller parks on a
+//! [`CondVar`] until the device interrupts to say it's done.
+//!
+//! A caller that gives up waiting leaves its request with the device, which
+//! goes on using the header and bounce buffer. The next request waits for it
+//! to finish before reusing them.
+
+use super::{
+    INTERRUPT_USED_BUFFER, VirtioMmio,
+    queue::{Buffer, Virtqueue},
+};
+use crate::{
+    drivers::{Driver, blk::register_block_device},
+    interrupts::{
+        ClaimedInterrupt, InterruptConfig, InterruptDescriptor, InterruptHandler, InterruptManager,
+    },
+    memory::dma::{alloc_coherent, free_coherent, sync_for_cpu, sync_for_device},
+    sync::{CondVar, Mutex},
+};
+use alloc::{boxed::Box, sync::Arc};
+use async_trait::async_trait;
+use core::{mem, slice};
+use libkernel::{
+    error::{IoError, KernelError, Result},
+    fs::BlockDevice,
+    memory::{
+        PAGE_SIZE,
+        address::{PA, VA},
+    },
+    sync::condvar::WakeupType,
+};
+use log::info;
+
+pub(super) const VIRTIO_ID_BLOCK: u32 = 2;
+
+/// The device can flush its write cache.
+const VIRTIO_BLK_F_FLUSH: u64 = 1 << 9;
+
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_T_FLUSH: u32 = 4;
+
+const VIRTIO_BLK_S_OK: u8 = 0;
+
+/// Sectors are always 512 bytes, whatever the device's block size.
+const SECTOR_SIZE: usize = 512;
+
+const QUEUE_SIZE: u16 = 16;
+
+/// The most data moved by one request.
+const BOUNCE_SIZE: usize = 16 * PAGE_SIZE;
+
+#[repr(C)]
+struct ReqHeader {
+    ty: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+/// Where the device writes a request's status, after the header.
+const STATUS_OFFSET: usize = mem::size_of::<ReqHeader>();
+
+/// The state of the one request in flight.
+struct Request {
+    queue: Virtqueue,
+    /// The header and status byte.
+    header: (VA, PA),
+    bounce: (VA, PA),
+    /// The head of the chain last handed to the device, until it's used.
+    in_flight: Option<u16>,
+}
+
+impl Request {
+    fn bounce(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.bounce.0.as_ptr_mut() as *mut u8, BOUNCE_SIZE) }
+    }
+}
+
+impl Drop for Request {
+    fn drop(&mut self) {
+        unsafe {
+            free_coherent(self.header.1, PAGE_SIZE);
+            free_coherent(self.bounce.1, BOUNCE_SIZE);
+        }
+    }
+}
+
+pub struct VirtioBlk {
+    name: &'static str,
+    dev: VirtioMmio,
+    /// The size of the disk in sectors.
+    capacity: u64,
+    flush: bool,
+    req: Mutex<Request>,
+    /// Set by the interrupt handler when the device has used a request.
+    irq_seen: CondVar<bool>,
+    irq: ClaimedInterrupt,
+}
+
+impl VirtioBlk {
+    /// Waits for the device to use the chain starting at `head`. Used entries
+    /// for any other chain are stale, and dropped.
+    async fn wait_used(&self, queue: &mut Virtqueue, head: u16) {
+        loop {
+            while let Some((id, _)) = queue.pop_used() {
+                if id == head {
+                    return;
+                }
+            }
+
+            self.irq_seen
+                .wait_until(|seen| mem::take(seen).then_some(()))
+                .await;
+        }
+    }
+
+    /// Runs a request of type `ty` on the first `len` bytes of the bounce
+    /// buffer.
+    async fn submit(&self, req: &mut Request, ty: u32, sector: u64, len: usize) -> Result<()> {
+        // A cancelled request may still be using the buffers.
+        if let Some(head) = req.in_flight {
+            self.wait_used(&mut req.queue, head).await;
+            req.in_flight = None;
+        }
+
+        let (header_va, header_pa) = req.header;
+
+        unsafe {
+            (header_va.as_ptr_mut() as *mut ReqHeader).write_volatile(ReqHeader {
+                ty,
+                reserved: 0,
+                sector,
+            });
+            (header_va.as_ptr_mut() as *mut u8)
+                .add(STATUS_OFFSET)
+                .write_volatile(0xff);
+        }
+
+        sync_for_device(header_va, PAGE_SIZE);
+
+        let header = Buffer {
+            pa: header_pa,
+            len: STATUS_OFFSET as u32,
+            device_writes: false,
+        };
+        let status = Buffer {
+            pa: header_pa.add_bytes(STATUS_OFFSET),
+            len: 1,
+            device_writes: true,
+        };
+
+        let head = if len == 0 {
+            req.queue.add(&[header, status])?
+        } else {
+            sync_for_device(req.bounce.0, len);
+
+            let data = Buffer {
+                pa: req.bounce.1,
+                len: len as u32,
+                device_writes: ty == VIRTIO_BLK_T_IN,
+            };
+
+            req.queue.add(&[header, data, status])?
+        };
+
+        req.in_flight = Some(head);
+        self.dev.notify(0);
+
+        self.wait_used(&mut req.queue, head).await;
+        req.in_flight = None;
+
+        sync_for_cpu(header_va, PAGE_SIZE);
+
+        if len != 0 {
+            sync_for_cpu(req.bounce.0, len);
+        }
+
+        let status = unsafe {
+            (header_va.as_ptr_mut() as *const u8)
+                .add(STATUS_OFFSET)
+                .read_volatile()
+        };
+
+        match status {
+            VIRTIO_BLK_S_OK => Ok(()),
+            _ => Err(IoError::DeviceError.into()),
+        }
+    }
+
+    fn check_range(&self, block_id: u64, len: usize) -> Result<()> {
+        if !len.is_multiple_of(SECTOR_SIZE) {
+            return Err(KernelError::InvalidValue);
+        }
+
+        match block_id.checked_add((len / SECTOR_SIZE) as u64) {
+            Some(end) if end <= self.capacity => Ok(()),
+            _ => Err(IoError::OutOfBounds.into()),
+        }
+    }
+}
+
+#[async_trait]
+impl BlockDevice for VirtioBlk {
+    async fn read(&self, block_id: u64, buf: &mut [u8]) -> Result<()> {
+        self.check_range(block_id, buf.len())?;
+
+        let mut req = self.req.lock().await;
+
+        for (i, chunk) in buf.chunks_mut(BOUNCE_SIZE).enumerate() {
+            let sector = block_id + (i * BOUNCE_SIZE / SECTOR_SIZE) as u64;
+
+            self.submit(&mut req, VIRTIO_BLK_T_IN, sector, chunk.len())
+                .await?;
+
+            chunk.copy_from_slice(&req.bounce()[..chunk.len()]);
+        }
+
+        Ok(())
+    }
+
+    async fn write(&self, block_id: u64, buf: &[u8]) -> Result<()> {
+        self.check_range(block_id, buf.len())?;
+
+        let mut req = self.req.lock().await;
+
+        for (i, chunk) in buf.chunks(BOUNCE_SIZE).enumerate() {
+            let sector = block_id + (i * BOUNCE_SIZE / SECTOR_SIZE) as u64;
+
+            req.bounce()[..chunk.len()].copy_from_slice(chunk);
+
+            self.submit(&mut req, VIRTIO_BLK_T_OUT, sector, chunk.len())
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    async fn sync(&self) -> Result<()> {
+        // Without a flush command the device has no write cache to flush.
+        if !self.flush {
+            return Ok(());
+        }
+
+        let mut req = self.req.lock().await;
+
+        self.submit(&mut req, VIRTIO_BLK_T_FLUSH, 0, 0).await
+    }
+}
+
+impl Driver for VirtioBlk {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn shutdown(&self) -> Result<()> {
+        self.dev.reset();
+        self.irq.release();
+
+        Ok(())
+    }
+}
+
+impl InterruptHandler for VirtioBlk {
+    fn handle_irq(&self, _desc: InterruptDescriptor) {
+        if self.dev.ack_interrupt() & INTERRUPT_USED_BUFFER != 0 {
+            self.irq_seen.update(|seen| {
+                *seen = true;
+                WakeupType::All
+            });
+        }
+    }
+}
+
+/// Sets up the block device behind `dev`.
+pub(super) fn probe(
+    name: &'static str,
+    dev: VirtioMmio,
+    interrupt_manager: &Arc<InterruptManager>,
+    interrupt_config: InterruptConfig,
+) -> Result<Arc<dyn Driver>> {
+    let features = dev.negotiate(VIRTIO_BLK_F_FLUSH)?;
+    let capacity = dev.config_u64(0);
+
+    let queue = Virtqueue::new(dev.queue_max(0).min(QUEUE_SIZE))?;
+    dev.setup_queue(0, &queue)?;
+
+    let req = Request {
+        queue,
+        header: alloc_coherent(PAGE_SIZE)?,
+        bounce: alloc_coherent(BOUNCE_SIZE)?,
+        in_flight: None,
+    };
+
+    let blk = interrupt_manager.claim_interrupt(interrupt_config, |irq| VirtioBlk {
+        name,
+        dev,
+        capacity,
+        flush: features & VIRTIO_BLK_F_FLUSH != 0,
+        req: Mutex::new(req),
+        irq_seen: CondVar::new(false),
+        irq,
+    })?;
+
+    blk.dev.driver_ok();
+
+    let disk = register_block_device("vd", blk.clone())?;
+
+    info!(
+        "virtio-blk {disk}: {} MiB",
+        capacity * SECTOR_SIZE as u64 >> 20
+    );
+
+    Ok(blk)
+}
@@ -0,0 +1,349 @@
+//! The virtio console device.
+//!
+//! Only the first port is used, through its receive and transmit queues. Each
+//! queue has a DMA page split into one slot per descriptor. The receive slots
+//! are all kept with the device, which fills them as input arrives, and are
+//! handed back after their bytes are passed to the TTY. Output is copied into
+//! free transmit slots, which are reclaimed once the device has used them.
+//!
+//! Console output can come from interrupt context, so writes never sleep: a
+//! blocking write with every transmit slot in flight spins until the device
+//! catches up.
+
+use super::{
+    INTERRUPT_USED_BUFFER, VirtioMmio,
+    queue::{Buffer, Virtqueue},
+};
+use crate::{
+    console::{Console, tty::TtyInputHandler},
+    drivers::Driver,
+    interrupts::{
+        ClaimedInterrupt, InterruptConfig, InterruptDescriptor, InterruptHandler, InterruptManager,
+    },
+    memory::dma::{alloc_coherent, free_coherent, sync_for_cpu, sync_for_device},
+    sync::SpinLock,
+};
+use alloc::{
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use core::{cmp::min, fmt, hint::spin_loop, slice};
+use libkernel::{
+    error::Result,
+    memory::{
+        PAGE_SIZE,
+        address::{PA, VA},
+    },
+};
+
+pub(super) const VIRTIO_ID_CONSOLE: u32 = 3;
+
+const RECEIVEQ: u32 = 0;
+const TRANSMITQ: u32 = 1;
+
+const QUEUE_SIZE: u16 = 16;
+
+/// A virtqueue with a DMA page of equally sized buffers.
+struct Port {
+    queue: Virtqueue,
+    buf: (VA, PA),
+    slot_size: usize,
+    /// The slot each chain in flight uses, by head descriptor.
+    owner: Vec<Option<usize>>,
+    free: Vec<usize>,
+}
+
+impl Port {
+    fn new(size: u16) -> Result<Self> {
+        let queue = Virtqueue::new(size)?;
+
+        Ok(Self {
+            queue,
+            buf: alloc_coherent(PAGE_SIZE)?,
+            slot_size: PAGE_SIZE / size as usize,
+            owner: vec![None; size as usize],
+            free: (0..size as usize).rev().collect(),
+        })
+    }
+
+    fn slot(&mut self, slot: usize) -> &mut [u8] {
+        unsafe {
+            slice::from_raw_parts_mut(
+                self.buf.0.add_bytes(slot * self.slot_size).as_ptr_mut() as *mut u8,
+                self.slot_size,
+            )
+        }
+    }
+
+    /// Gives the first `len` bytes of `slot` to the device.
+    fn post(&mut self, slot: usize, len: usize, device_writes: bool) -> Result<()> {
+        let offset = slot * self.slot_size;
+
+        sync_for_device(self.buf.0.add_bytes(offset), len);
+
+        let head = self.queue.add(&[Buffer {
+            pa: self.buf.1.add_bytes(offset),
+            len: len as u32,
+            device_writes,
+        }])?;
+
+        self.owner[head as usize] = Some(slot);
+
+        Ok(())
+    }
+
+    /// Takes back the next slot the device has finished with, and the number
+    /// of bytes it wrote to it.
+    fn pop(&mut self) -> Option<(usize, usize)> {
+        let (head, len) = self.queue.pop_used()?;
+        let slot = self.owner[head as usize].take()?;
+
+        sync_for_cpu(self.buf.0.add_bytes(slot * self.slot_size), len as usize);
+
+        Some((slot, len as usize))
+    }
+
+    /// Returns transmit slots the device has sent to the free list.
+    fn reclaim(&mut self) {
+        while let Some((slot, _)) = self.pop() {
+            self.free.push(slot);
+        }
+    }
+
+    /// Queues as much of `buf` as there are free slots for, returning how many
+    /// bytes were queued.
+    fn send(&mut self, buf: &[u8]) -> usize {
+        let mut sent = 0;
+
+        while sent < buf.len()
+            && let Some(slot) = self.free.pop()
+        {
+            let n = min(buf.len() - sent, self.slot_size);
+
+            self.slot(slot)[..n].copy_from_slice(&buf[sent..sent + n]);
+
+            if self.post(slot, n, false).is_err() {
+                self.free.push(slot);
+                break;
+            }
+
+            sent += n;
+        }
+
+        sent
+    }
+}
+
+impl Drop for Port {
+    fn drop(&mut self) {
+        unsafe { free_coherent(self.buf.1, PAGE_SIZE) };
+    }
+}
+
+pub struct VirtioConsole {
+    name: &'static str,
+    dev: VirtioMmio,
+    rx: SpinLock<Port>,
+    tx: SpinLock<Port>,
+    tty_handler: SpinLock<Option<Weak<dyn TtyInputHandler>>>,
+    irq: ClaimedInterrupt,
+}
+
+impl VirtioConsole {
+    /// Passes received bytes on to the TTY and gives their slots back to the
+    /// device.
+    fn drain_rx(&self) {
+        let mut bytes = Vec::new();
+
+        {
+            let mut rx = self.rx.lock_save_irq();
+
+            while let Some((slot, len)) = rx.pop() {
+                bytes.extend_from_slice(&rx.slot(slot)[..len]);
+
+                let slot_size = rx.slot_size;
+                let _ = rx.post(slot, slot_size, true);
+            }
+        }
+
+        self.dev.notify(RECEIVEQ);
+
+        if let Some(handler) = self
+            .tty_handler
+            .lock_save_irq()
+            .as_ref()
+            .and_then(|h| h.upgrade())
+        {
+            bytes.into_iter().for_each(|b| handler.push_byte(b));
+        }
+    }
+}
+
+impl Console for VirtioConsole {
+    fn write_char(&self, c: char) {
+        self.write_buf(c.encode_utf8(&mut [0; 4]).as_bytes());
+    }
+
+    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+        struct Writer<'a>(&'a VirtioConsole);
+
+        impl fmt::Write for Writer<'_> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                self.0.write_buf(s.as_bytes());
+                Ok(())
+            }
+        }
+
+        fmt::write(&mut Writer(self), args)
+    }
+
+    fn write_buf(&self, mut buf: &[u8]) {
+        let mut tx = self.tx.lock_save_irq();
+
+        loop {
+            tx.reclaim();
+
+            let n = tx.send(buf);
+
+            if n > 0 {
+                self.dev.notify(TRANSMITQ);
+                buf = &buf[n..];
+            }
+
+            if buf.is_empty() {
+                return;
+            }
+
+            spin_loop();
+        }
+    }
+
+    fn try_write_buf(&self, buf: &[u8]) -> usize {
+        let mut tx = self.tx.lock_save_irq();
+
+        tx.reclaim();
+
+        let n = tx.send(buf);
+
+        if n > 0 {
+            self.dev.notify(TRANSMITQ);
+        }
+
+        n
+    }
+
+    fn register_input_handler(&self, handler: Weak<dyn TtyInputHandler>) {
+        *self.tty_handler.lock_save_irq() = Some(handler);
+    }
+}
+
+impl Driver for VirtioConsole {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn as_console(self: Arc<Self>) -> Option<Arc<dyn Console>> {
+        Some(self)
+    }
+
+    fn shutdown(&self) -> Result<()> {
+        self.dev.reset();
+        self.irq.release();
+
+        Ok(())
+    }
+}
+
+impl InterruptHandler for VirtioConsole {
+    fn handle_irq(&self, _desc: InterruptDescriptor) {
+        if self.dev.ack_interrupt() & INTERRUPT_USED_BUFFER != 0 {
+            self.tx.lock_save_irq().reclaim();
+            self.drain_rx();
+        }
+    }
+}
+
+/// Sets up the console behind `dev`.
+pub(super) fn probe(
+    name: &'static str,
+    dev: VirtioMmio,
+    interrupt_manager: &Arc<InterruptManager>,
+    interrupt_config: InterruptConfig,
+) -> Result<Arc<dyn Driver>> {
+    dev.negotiate(0)?;
+
+    let mut rx = Port::new(dev.queue_max(RECEIVEQ).min(QUEUE_SIZE))?;
+    let tx = Port::new(dev.queue_max(TRANSMITQ).min(QUEUE_SIZE))?;
+
+    dev.setup_queue(RECEIVEQ, &rx.queue)?;
+    dev.setup_queue(TRANSMITQ, &tx.queue)?;
+
+    // The device fills receive buffers as input arrives, so it holds all of
+    // them from the start.
+    while let Some(slot) = rx.free.pop() {
+        let slot_size = rx.slot_size;
+        rx.post(slot, slot_size, true)?;
+    }
+
+    let console = interrupt_manager.claim_interrupt(interrupt_config, |irq| VirtioConsole {
+        name,
+        dev,
+        rx: SpinLock::new(rx),
+        tx: SpinLock::new(tx),
+        tty_handler: SpinLock::new(None),
+        irq,
+    })?;
+
+    console.dev.driver_ok();
+    console.dev.notify(RECEIVEQ);
+
+    Ok(console)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ktest;
+
+    ktest! {
+        fn virtio_console_tx_submission() {
+            let mut tx = Port::new(4).unwrap();
+            let slot_size = PAGE_SIZE / 4;
+            let data: Vec<u8> = (0..3 * slot_size).map(|i| i as u8).collect();
+
+            // Output longer than a slot is split across chains of one
+            // buffer each, which the device reads.
+            assert_eq!(tx.send(&data[..slot_size + 10]), slot_size + 10);
+            assert_eq!(
+                tx.queue.published(0),
+                [Buffer { pa: tx.buf.1, len: slot_size as u32, device_writes: false }]
+            );
+            assert_eq!(
+                tx.queue.published(1),
+                [Buffer { pa: tx.buf.1.add_bytes(slot_size), len: 10, device_writes: false }]
+            );
+            assert_eq!(tx.slot(0), &data[..slot_size]);
+            assert_eq!(&tx.slot(1)[..10], &data[slot_size..slot_size + 10]);
+
+            // Only what fits in the free slots is taken.
+            assert_eq!(tx.send(&data), 2 * slot_size);
+            assert_eq!(tx.send(&data), 0);
+
+            // Nothing comes back until the device has sent something.
+            tx.reclaim();
+            assert!(tx.free.is_empty());
+
+            tx.queue.complete(1, 0);
+            tx.reclaim();
+            assert_eq!(tx.free, [1]);
+
+            assert_eq!(tx.send(b"hello"), 5);
+            assert_eq!(
+                tx.queue.published(4),
+                [Buffer { pa: tx.buf.1.add_bytes(slot_size), len: 5, device_writes: false }]
+            );
+            assert_eq!(&tx.slot(1)[..5], b"hello");
+        }
+    }
+}
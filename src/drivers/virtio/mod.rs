@@ -0,0 +1,318 @@
+//! The virtio-mmio transport.
+//!
+//! Only the modern (version 2) register layout is supported, where each
+//! virtqueue's descriptor table and rings are given to the device separately.
+//! QEMU's `virt` machine offers legacy devices unless it's run with
+//! `-global virtio-mmio.force-legacy=false`; those are skipped with a warning.
+//!
+//! Every transport has the same compatible string whatever device is behind
+//! it, so one probe function reads the device ID and hands the transport to
+//! the driver for that type of device.
+
+use self::queue::Virtqueue;
+use crate::{
+    arch::ArchImpl,
+    drivers::{
+        Driver, DriverManager,
+        init::PlatformBus,
+        probe::{DeviceDescriptor, DeviceMatchType, interrupt_map::resolve_interrupt},
+    },
+    kernel_driver,
+};
+use alloc::{boxed::Box, sync::Arc};
+use libkernel::{
+    KernAddressSpace, VirtualMemory,
+    error::{KernelError, ProbeError, Result},
+    memory::{
+        address::{PA, VA},
+        region::PhysMemoryRegion,
+    },
+};
+use log::{info, warn};
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_structs,
+    registers::{ReadOnly, ReadWrite, WriteOnly},
+};
+
+pub mod blk;
+pub mod console;
+pub mod queue;
+
+register_structs! {
+    #[allow(non_snake_case)]
+    VirtioMmioRegs {
+        (0x000 => MagicValue: ReadOnly<u32>),
+        (0x004 => Version: ReadOnly<u32>),
+        (0x008 => DeviceID: ReadOnly<u32>),
+        (0x00c => VendorID: ReadOnly<u32>),
+        (0x010 => DeviceFeatures: ReadOnly<u32>),
+        (0x014 => DeviceFeaturesSel: WriteOnly<u32>),
+        (0x018 => _reserved_0),
+        (0x020 => DriverFeatures: WriteOnly<u32>),
+        (0x024 => DriverFeaturesSel: WriteOnly<u32>),
+        (0x028 => _reserved_1),
+        (0x030 => QueueSel: WriteOnly<u32>),
+        (0x034 => QueueNumMax: ReadOnly<u32>),
+        (0x038 => QueueNum: WriteOnly<u32>),
+        (0x03c => _reserved_2),
+        (0x044 => QueueReady: ReadWrite<u32>),
+        (0x048 => _reserved_3),
+        (0x050 => QueueNotify: WriteOnly<u32>),
+        (0x054 => _reserved_4),
+        (0x060 => InterruptStatus: ReadOnly<u32>),
+        (0x064 => InterruptACK: WriteOnly<u32>),
+        (0x068 => _reserved_5),
+        (0x070 => Status: ReadWrite<u32>),
+        (0x074 => _reserved_6),
+        (0x080 => QueueDescLow: WriteOnly<u32>),
+        (0x084 => QueueDescHigh: WriteOnly<u32>),
+        (0x088 => _reserved_7),
+        (0x090 => QueueDriverLow: WriteOnly<u32>),
+        (0x094 => QueueDriverHigh: WriteOnly<u32>),
+        (0x098 => _reserved_8),
+        (0x0a0 => QueueDeviceLow: WriteOnly<u32>),
+        (0x0a4 => QueueDeviceHigh: WriteOnly<u32>),
+        (0x0a8 => _reserved_9),
+        (0x0fc => ConfigGeneration: ReadOnly<u32>),
+        (0x100 => @END),
+    }
+}
+
+/// "virt" in little endian.
+const MAGIC: u32 = 0x7472_6976;
+
+/// Where the device-specific configuration starts.
+const CONFIG_OFFSET: usize = 0x100;
+
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+const STATUS_FEATURES_OK: u32 = 8;
+const STATUS_FAILED: u32 = 128;
+
+/// The device supports the modern interface. Always required.
+const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+/// `InterruptStatus`: the device has used buffers from a virtqueue.
+pub const INTERRUPT_USED_BUFFER: u32 = 1 << 0;
+
+pub struct VirtioMmio {
+    base: VA,
+    regs: &'static VirtioMmioRegs,
+}
+
+unsafe impl Sync for VirtioMmio {}
+unsafe impl Send for VirtioMmio {}
+
+impl VirtioMmio {
+    /// # Safety
+    ///
+    /// `base` must point to the mapped registers of a virtio-mmio transport.
+    pub unsafe fn new(base: VA) -> Self {
+        Self {
+            base,
+            regs: unsafe { &*(base.value() as *const VirtioMmioRegs) },
+        }
+    }
+
+    /// Returns the ID of the device behind the transport, failing with
+    /// [`ProbeError::NoDevice`] if there's none.
+    pub fn device_id(&self) -> Result<u32> {
+        if self.regs.MagicValue.get() != MAGIC {
+            return Err(ProbeError::NoDevice.into());
+        }
+
+        match self.regs.DeviceID.get() {
+            0 => Err(ProbeError::NoDevice.into()),
+            _ if self.regs.Version.get() != 2 => {
+                warn!("Skipping legacy virtio-mmio device at {:?}", self.base);
+                Err(ProbeError::NoDevice.into())
+            }
+            id => Ok(id),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.regs.Status.set(0);
+    }
+
+    /// Resets the device and agrees on the features in `wanted` that it
+    /// offers, returning them. `VIRTIO_F_VERSION_1` is always negotiated.
+    pub fn negotiate(&self, wanted: u64) -> Result<u64> {
+        self.reset();
+        self.regs.Status.set(STATUS_ACKNOWLEDGE);
+        self.regs.Status.set(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+        let mut offered = 0;
+
+        for sel in 0..2 {
+            self.regs.DeviceFeaturesSel.set(sel);
+            offered |= (self.regs.DeviceFeatures.get() as u64) << (32 * sel);
+        }
+
+        if offered & VIRTIO_F_VERSION_1 == 0 {
+            self.fail();
+            return Err(KernelError::NotSupported);
+        }
+
+        let features = offered & (wanted | VIRTIO_F_VERSION_1);
+
+        for sel in 0..2 {
+            self.regs.DriverFeaturesSel.set(sel);
+            self.regs
+                .DriverFeatures
+                .set((features >> (32 * sel)) as u32);
+        }
+
+        self.set_status(STATUS_FEATURES_OK);
+
+        // The device clears FEATURES_OK if it can't work with what we chose.
+        if self.regs.Status.get() & STATUS_FEATURES_OK == 0 {
+            self.fail();
+            return Err(KernelError::NotSupported);
+        }
+
+        Ok(features)
+    }
+
+    /// The most entries virtqueue `index` can have, or zero if it doesn't
+    /// exist.
+    pub fn queue_max(&self, index: u32) -> u16 {
+        self.regs.QueueSel.set(index);
+        self.regs.QueueNumMax.get().min(u16::MAX as u32) as u16
+    }
+
+    /// Gives `queue` to the device as virtqueue `index`.
+    pub fn setup_queue(&self, index: u32, queue: &Virtqueue) -> Result<()> {
+        self.regs.QueueSel.set(index);
+
+        if self.regs.QueueReady.get() != 0 || queue.size() > self.queue_max(index) {
+            return Err(KernelError::InUse);
+        }
+
+        self.regs.QueueNum.set(queue.size() as u32);
+
+        let set = |low: &WriteOnly<u32>, high: &WriteOnly<u32>, pa: PA| {
+            low.set(pa.value() as u32);
+            high.set((pa.value() as u64 >> 32) as u32);
+        };
+
+        set(
+            &self.regs.QueueDescLow,
+            &self.regs.QueueDescHigh,
+            queue.desc_pa(),
+        );
+        set(
+            &self.regs.QueueDriverLow,
+            &self.regs.QueueDriverHigh,
+            queue.avail_pa(),
+        );
+        set(
+            &self.regs.QueueDeviceLow,
+            &self.regs.QueueDeviceHigh,
+            queue.used_pa(),
+        );
+
+        self.regs.QueueReady.set(1);
+
+        Ok(())
+    }
+
+    /// Tells the device the driver is ready, once its queues are set up.
+    pub fn driver_ok(&self) {
+        self.set_status(STATUS_DRIVER_OK);
+    }
+
+    /// Tells the device there are new buffers in virtqueue `index`.
+    pub fn notify(&self, index: u32) {
+        self.regs.QueueNotify.set(index);
+    }
+
+    /// Acknowledges the device's pending interrupts, returning which they
+    /// were.
+    pub fn ack_interrupt(&self) -> u32 {
+        let status = self.regs.InterruptStatus.get();
+
+        self.regs.InterruptACK.set(status);
+
+        status
+    }
+
+    fn config_u32(&self, offset: usize) -> u32 {
+        unsafe { ((self.base.value() + CONFIG_OFFSET + offset) as *const u32).read_volatile() }
+    }
+
+    /// Reads a 64-bit field of the device's configuration, retrying if the
+    /// device changed it between the two halves being read.
+    pub fn config_u64(&self, offset: usize) -> u64 {
+        loop {
+            let generation = self.regs.ConfigGeneration.get();
+            let low = self.config_u32(offset) as u64;
+            let high = self.config_u32(offset + 4) as u64;
+
+            if self.regs.ConfigGeneration.get() == generation {
+                return high << 32 | low;
+            }
+        }
+    }
+
+    fn set_status(&self, bit: u32) {
+        self.regs.Status.set(self.regs.Status.get() | bit);
+    }
+
+    fn fail(&self) {
+        self.set_status(STATUS_FAILED);
+    }
+}
+
+fn virtio_mmio_probe(dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<dyn Driver>> {
+    match d {
+        DeviceDescriptor::Fdt(fdt_node, _) => {
+            let region = fdt_node
+                .reg()
+                .ok_or(ProbeError::NoReg)?
+                .next()
+                .ok_or(ProbeError::NoReg)?;
+
+            let size = region.size.ok_or(ProbeError::NoRegSize)?;
+
+            let (interrupt_manager, interrupt_config) = resolve_interrupt(dm, &fdt_node, 0)?;
+
+            let mem =
+                ArchImpl::kern_address_space()
+                    .lock_save_irq()
+                    .map_mmio(PhysMemoryRegion::new(
+                        PA::from_value(region.address as usize),
+                        size,
+                    ))?;
+
+            // SAFETY: The registers were mapped above.
+            let dev = unsafe { VirtioMmio::new(mem) };
+
+            // QEMU puts transports in the device tree whether or not anything
+            // is plugged into them.
+            let probe = match dev.device_id()? {
+                blk::VIRTIO_ID_BLOCK => blk::probe,
+                console::VIRTIO_ID_CONSOLE => console::probe,
+                id => {
+                    info!("No driver for virtio device type {id} at {}", fdt_node.name);
+                    return Err(ProbeError::NoDevice.into());
+                }
+            };
+
+            probe(fdt_node.name, dev, &interrupt_manager, interrupt_config)
+        }
+    }
+}
+
+pub fn virtio_mmio_init(bus: &mut PlatformBus, _dm: &mut DriverManager) -> Result<()> {
+    bus.register_platform_driver(
+        DeviceMatchType::FdtCompatible("virtio,mmio"),
+        Box::new(virtio_mmio_probe),
+    );
+
+    Ok(())
+}
+
+kernel_driver!(virtio_mmio_init);
@@ -0,0 +1,354 @@
+//! Split virtqueues.
+//!
+//! A queue is a descriptor table, an available ring the driver publishes
+//! descriptor chains on, and a used ring the device hands them back on. All
+//! three live in one DMA buffer, laid out back to back with the alignment each
+//! needs. Free descriptors are kept on a list threaded through their `next`
+//! fields.
+
+use crate::memory::dma::{alloc_coherent, free_coherent, sync_for_cpu, sync_for_device};
+use aarch64_cpu::asm::barrier;
+use core::ptr;
+use libkernel::{
+    error::{KernelError, Result},
+    memory::address::{PA, VA},
+};
+
+/// The chain continues with the descriptor in `next`.
+const DESC_F_NEXT: u16 = 1;
+/// The device writes to the buffer, rather than reading it.
+const DESC_F_WRITE: u16 = 2;
+
+const DESC_SIZE: usize = 16;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Desc {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+/// A buffer in a descriptor chain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Buffer {
+    pub pa: PA,
+    pub len: u32,
+    /// The device writes to the buffer, rather than reading it.
+    pub device_writes: bool,
+}
+
+pub struct Virtqueue {
+    size: u16,
+    va: VA,
+    pa: PA,
+    /// The offsets of the available and used rings from the descriptor table.
+    avail: usize,
+    used: usize,
+    bytes: usize,
+    free_head: u16,
+    num_free: u16,
+    /// The number of chains published on the available ring, wrapping.
+    avail_idx: u16,
+    /// The number of chains taken back from the used ring, wrapping.
+    last_used: u16,
+}
+
+impl Virtqueue {
+    pub fn new(size: u16) -> Result<Self> {
+        if size == 0 {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let n = size as usize;
+        let avail = DESC_SIZE * n;
+        // flags, idx, ring and used_event.
+        let used = (avail + 6 + 2 * n).next_multiple_of(4);
+        let bytes = used + 6 + 8 * n;
+        let (va, pa) = alloc_coherent(bytes)?;
+
+        let mut queue = Self {
+            size,
+            va,
+            pa,
+            avail,
+            used,
+            bytes,
+            free_head: 0,
+            num_free: size,
+            avail_idx: 0,
+            last_used: 0,
+        };
+
+        for i in 0..size {
+            queue.write_desc(
+                i,
+                Desc {
+                    next: i.wrapping_add(1),
+                    ..Default::default()
+                },
+            );
+        }
+
+        sync_for_device(va, bytes);
+
+        Ok(queue)
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    pub fn desc_pa(&self) -> PA {
+        self.pa
+    }
+
+    pub fn avail_pa(&self) -> PA {
+        self.pa.add_bytes(self.avail)
+    }
+
+    pub fn used_pa(&self) -> PA {
+        self.pa.add_bytes(self.used)
+    }
+
+    fn ptr<T>(&self, offset: usize) -> *mut T {
+        self.va.add_bytes(offset).as_ptr_mut() as *mut T
+    }
+
+    fn desc(&self, i: u16) -> Desc {
+        unsafe { ptr::read_volatile(self.ptr(DESC_SIZE * i as usize)) }
+    }
+
+    fn write_desc(&mut self, i: u16, desc: Desc) {
+        unsafe { ptr::write_volatile(self.ptr(DESC_SIZE * i as usize), desc) }
+    }
+
+    /// Publishes a chain of `bufs` to the device, returning the chain's head
+    /// descriptor, which identifies it when it's used.
+    pub fn add(&mut self, bufs: &[Buffer]) -> Result<u16> {
+        if bufs.is_empty() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        if bufs.len() > self.num_free as usize {
+            return Err(KernelError::BufferFull);
+        }
+
+        let head = self.free_head;
+
+        for (i, buf) in bufs.iter().enumerate() {
+            let idx = self.free_head;
+            let next = self.desc(idx).next;
+            let mut flags = if buf.device_writes { DESC_F_WRITE } else { 0 };
+
+            if i + 1 < bufs.len() {
+                flags |= DESC_F_NEXT;
+            }
+
+            self.write_desc(
+                idx,
+                Desc {
+                    addr: buf.pa.value() as u64,
+                    len: buf.len,
+                    flags,
+                    next,
+                },
+            );
+
+            self.free_head = next;
+        }
+
+        self.num_free -= bufs.len() as u16;
+
+        let slot = self.avail + 4 + 2 * (self.avail_idx % self.size) as usize;
+        unsafe { ptr::write_volatile(self.ptr(slot), head) };
+
+        // The chain and its ring entry must reach the device before the index
+        // that makes them visible.
+        sync_for_device(self.va, self.used);
+
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        unsafe { ptr::write_volatile(self.ptr(self.avail + 2), self.avail_idx) };
+        sync_for_device(self.va.add_bytes(self.avail), 4);
+
+        Ok(head)
+    }
+
+    /// Takes back the next chain the device has finished with, returning its
+    /// head and the number of bytes the device wrote to it.
+    ///
+    /// An entry naming a descriptor outside the queue is skipped, since there's
+    /// no chain to free.
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        sync_for_cpu(self.va.add_bytes(self.used), self.bytes - self.used);
+
+        let used_idx: u16 = unsafe { ptr::read_volatile(self.ptr(self.used + 2)) };
+
+        // The device writes the ring entries before the index, so they mustn't
+        // be read until after the index.
+        barrier::dmb(barrier::SY);
+
+        let (id, len) = loop {
+            if used_idx == self.last_used {
+                return None;
+            }
+
+            let elem = self.used + 4 + 8 * (self.last_used % self.size) as usize;
+            let (id, len): (u32, u32) = unsafe {
+                (
+                    ptr::read_volatile(self.ptr(elem)),
+                    ptr::read_volatile(self.ptr(elem + 4)),
+                )
+            };
+
+            self.last_used = self.last_used.wrapping_add(1);
+
+            if id < self.size as u32 {
+                break (id, len);
+            }
+        };
+
+        // Put the chain back on the free list.
+        let head = id as u16;
+        let mut tail = head;
+        let mut len_chain = 1;
+
+        while self.desc(tail).flags & DESC_F_NEXT != 0 {
+            tail = self.desc(tail).next;
+            len_chain += 1;
+        }
+
+        let mut desc = self.desc(tail);
+        desc.next = self.free_head;
+        self.write_desc(tail, desc);
+
+        self.free_head = head;
+        self.num_free += len_chain;
+
+        Some((head, len))
+    }
+}
+
+/// Plays the device's side of the queue, for testing drivers.
+#[cfg(test)]
+impl Virtqueue {
+    /// The buffers of the `n`th chain published to the device.
+    pub(super) fn published(&self, n: u16) -> alloc::vec::Vec<Buffer> {
+        let slot = self.avail + 4 + 2 * (n % self.size) as usize;
+        let mut i: u16 = unsafe { ptr::read_volatile(self.ptr(slot)) };
+        let mut chain = alloc::vec::Vec::new();
+
+        loop {
+            let desc = self.desc(i);
+
+            chain.push(Buffer {
+                pa: PA::from_value(desc.addr as usize),
+                len: desc.len,
+                device_writes: desc.flags & DESC_F_WRITE != 0,
+            });
+
+            if desc.flags & DESC_F_NEXT == 0 {
+                return chain;
+            }
+
+            i = desc.next;
+        }
+    }
+
+    /// Hands the chain starting at `head` back, with `len` bytes written.
+    pub(super) fn complete(&mut self, head: u16, len: u32) {
+        let used_idx: u16 = unsafe { ptr::read_volatile(self.ptr(self.used + 2)) };
+        let elem = self.used + 4 + 8 * (used_idx % self.size) as usize;
+
+        unsafe {
+            ptr::write_volatile(self.ptr::<u32>(elem), head as u32);
+            ptr::write_volatile(self.ptr::<u32>(elem + 4), len);
+            ptr::write_volatile(self.ptr::<u16>(self.used + 2), used_idx.wrapping_add(1));
+        }
+    }
+}
+
+impl Drop for Virtqueue {
+    fn drop(&mut self) {
+        unsafe { free_coherent(self.pa, self.bytes) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ktest;
+
+    fn buf(pa: usize, len: u32, device_writes: bool) -> Buffer {
+        Buffer {
+            pa: PA::from_value(pa),
+            len,
+            device_writes,
+        }
+    }
+
+    ktest! {
+        fn virtqueue_descriptor_setup() {
+            let mut q = Virtqueue::new(8).unwrap();
+
+            // 128 bytes of descriptors, then a 22 byte available ring, then the
+            // used ring aligned to 4 bytes.
+            assert_eq!(q.desc_pa().value() % DESC_SIZE, 0);
+            assert_eq!(q.avail_pa(), q.desc_pa().add_bytes(128));
+            assert_eq!(q.used_pa(), q.desc_pa().add_bytes(152));
+
+            // A request: a header the device reads, then data and a status
+            // byte it writes.
+            let head = q
+                .add(&[buf(0x1000, 16, false), buf(0x2000, 512, true), buf(0x1010, 1, true)])
+                .unwrap();
+            assert_eq!(head, 0);
+            assert_eq!(
+                q.desc(0),
+                Desc { addr: 0x1000, len: 16, flags: DESC_F_NEXT, next: 1 }
+            );
+            assert_eq!(
+                q.desc(1),
+                Desc { addr: 0x2000, len: 512, flags: DESC_F_NEXT | DESC_F_WRITE, next: 2 }
+            );
+            assert_eq!(q.desc(2).flags, DESC_F_WRITE);
+
+            let avail = |q: &Virtqueue, off: usize| unsafe {
+                ptr::read_volatile(q.ptr::<u16>(q.avail + off))
+            };
+            assert_eq!(avail(&q, 2), 1);
+            assert_eq!(avail(&q, 4), 0);
+
+            assert_eq!(q.add(&[buf(0x3000, 8, false), buf(0x3008, 1, true)]).unwrap(), 3);
+            assert_eq!(avail(&q, 2), 2);
+            assert_eq!(avail(&q, 6), 3);
+            assert!(matches!(q.add(&[buf(0, 1, false); 4]), Err(KernelError::BufferFull)));
+
+            // Nothing has been used yet.
+            assert_eq!(q.pop_used(), None);
+
+            // Play the device, finishing the first chain.
+            unsafe {
+                ptr::write_volatile(q.ptr::<u32>(q.used + 4), 0);
+                ptr::write_volatile(q.ptr::<u32>(q.used + 8), 513);
+                ptr::write_volatile(q.ptr::<u16>(q.used + 2), 1);
+            }
+
+            assert_eq!(q.pop_used(), Some((0, 513)));
+            assert_eq!(q.pop_used(), None);
+            assert_eq!(q.num_free, 6);
+
+            // Its descriptors are handed out again first.
+            assert_eq!(q.add(&[buf(0x4000, 4, false)]).unwrap(), 0);
+            assert_eq!(q.free_head, 1);
+
+            // A bogus entry from the device is dropped without touching the
+            // free list, and the next one is still found.
+            q.complete(8, 0);
+            q.complete(3, 1);
+            assert_eq!(q.pop_used(), Some((3, 1)));
+            assert_eq!(q.num_free, 7);
+        }
+    }
+}
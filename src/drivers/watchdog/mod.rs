@@ -0,0 +1,421 @@
+//! Hardware watchdogs, which reset the board unless petted in time.
+//!
+//! Once enabled, the watchdog is petted by a kernel thread running at the
+//! highest priority, and by nothing else: in particular not from interrupt
+//! context. Should the kernel lock up, e.g. with every core spinning, the
+//! thread stops being scheduled, the pets stop and the board resets.
+//!
+//! Userspace configures the watchdog through the Linux watchdog `ioctl`s on
+//! `/dev/watchdog`.
+
+use super::{CharDriver, Driver, DriverManager, OpenableDevice, ReservedMajors, fs::dev::devfs};
+use crate::{
+    drivers::{init::PlatformBus, timer::sleep},
+    fs::{
+        fops::FileOps,
+        open_file::{FileCtx, OpenFile},
+    },
+    kernel_driver,
+    memory::uaccess::{copy_from_user, copy_to_user},
+    process::kthread::spawn_kthread_with_priority,
+    sync::{CondVar, OnceLock},
+};
+use alloc::{boxed::Box, string::ToString, sync::Arc};
+use async_trait::async_trait;
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use futures::{future::select, pin_mut};
+use libkernel::{
+    driver::CharDevDescriptor,
+    error::{KernelError, Result},
+    fs::{OpenFlags, attr::FilePermissions},
+    memory::address::{TUA, UA},
+    sync::condvar::WakeupType,
+};
+use log::info;
+
+pub mod sbsa_gwdt;
+
+const WDIOC_SETOPTIONS: usize = 0x8004_5704;
+const WDIOC_KEEPALIVE: usize = 0x8004_5705;
+const WDIOC_SETTIMEOUT: usize = 0xc004_5706;
+const WDIOC_GETTIMEOUT: usize = 0x8004_5707;
+
+const WDIOS_DISABLECARD: i32 = 0x1;
+const WDIOS_ENABLECARD: i32 = 0x2;
+
+/// The timeout a watchdog is given when it's registered.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The pet thread outranks every other task, so that only a genuine lock-up
+/// stops it.
+const PET_THREAD_PRIORITY: i8 = i8::MAX;
+
+pub trait HwWatchdog: Send + Sync + Driver {
+    /// The longest timeout the hardware can be programmed with.
+    fn max_timeout(&self) -> Duration;
+
+    /// Programs the time without a pet after which the board is reset, and
+    /// returns the timeout actually programmed, after rounding.
+    fn set_timeout(&self, timeout: Duration) -> Duration;
+
+    fn enable(&self);
+
+    fn disable(&self);
+
+    /// Restarts the countdown to reset.
+    fn pet(&self);
+}
+
+#[derive(Debug, Clone, Copy)]
+struct WatchdogState {
+    enabled: bool,
+    timeout: Duration,
+    /// Bumped on every change, so that the pet thread can tell the interval it
+    /// is sleeping for is stale.
+    generation: u64,
+}
+
+pub struct SysWatchdog {
+    driver: Arc<dyn HwWatchdog>,
+    state: CondVar<WatchdogState>,
+    thread_started: AtomicBool,
+}
+
+impl Driver for SysWatchdog {
+    fn name(&self) -> &'static str {
+        self.driver.name()
+    }
+}
+
+impl SysWatchdog {
+    fn from_driver(driver: Arc<dyn HwWatchdog>) -> Self {
+        let timeout = driver.set_timeout(DEFAULT_TIMEOUT.min(driver.max_timeout()));
+
+        Self {
+            driver,
+            state: CondVar::new(WatchdogState {
+                enabled: false,
+                timeout,
+                generation: 0,
+            }),
+            thread_started: AtomicBool::new(false),
+        }
+    }
+
+    fn state(&self) -> WatchdogState {
+        let mut state = None;
+
+        self.state.update(|s| {
+            state = Some(*s);
+            WakeupType::None
+        });
+
+        state.unwrap()
+    }
+
+    fn change_state(&self, f: impl FnOnce(&mut WatchdogState)) {
+        self.state.update(|s| {
+            f(s);
+            s.generation += 1;
+            WakeupType::All
+        });
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.state().timeout
+    }
+
+    /// Sets the time without a pet after which the board is reset, clamped to
+    /// what the hardware supports. Returns the timeout actually set.
+    pub fn set_timeout(&self, timeout: Duration) -> Result<Duration> {
+        if timeout.is_zero() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let timeout = self
+            .driver
+            .set_timeout(timeout.min(self.driver.max_timeout()));
+
+        self.change_state(|s| s.timeout = timeout);
+
+        Ok(timeout)
+    }
+
+    /// Starts the countdown, and the thread that keeps petting the watchdog.
+    pub fn enable(self: &Arc<Self>) -> Result<()> {
+        if !self.thread_started.swap(true, Ordering::AcqRel) {
+            let wdt = self.clone();
+
+            if let Err(e) =
+                spawn_kthread_with_priority("watchdog", PET_THREAD_PRIORITY, pet_thread(wdt))
+            {
+                self.thread_started.store(false, Ordering::Release);
+                return Err(e);
+            }
+        }
+
+        self.driver.pet();
+        self.driver.enable();
+        self.change_state(|s| s.enabled = true);
+
+        info!(
+            "Watchdog {} enabled with a {:?} timeout",
+            self.name(),
+            self.timeout()
+        );
+
+        Ok(())
+    }
+
+    pub fn disable(&self) {
+        self.driver.disable();
+        self.change_state(|s| s.enabled = false);
+
+        info!("Watchdog {} disabled", self.name());
+    }
+
+    pub fn pet(&self) {
+        self.driver.pet();
+    }
+}
+
+/// How long the pet thread sleeps between pets.
+fn pet_interval(timeout: Duration) -> Duration {
+    timeout / 4
+}
+
+async fn pet_thread(wdt: Arc<SysWatchdog>) {
+    loop {
+        let (timeout, generation) = wdt
+            .state
+            .wait_until(|s| s.enabled.then_some((s.timeout, s.generation)))
+            .await;
+
+        wdt.pet();
+
+        let sleep = sleep(pet_interval(timeout));
+        let changed = wdt
+            .state
+            .wait_until(|s| (s.generation != generation).then_some(()));
+
+        pin_mut!(sleep, changed);
+
+        select(sleep, changed).await;
+    }
+}
+
+static WATCHDOG: OnceLock<Arc<SysWatchdog>> = OnceLock::new();
+
+/// Makes `driver` the system watchdog, exposed as `/dev/watchdog`. It starts
+/// disabled.
+pub fn register_watchdog(driver: Arc<dyn HwWatchdog>) -> Result<Arc<SysWatchdog>> {
+    let wdt = Arc::new(SysWatchdog::from_driver(driver));
+
+    WATCHDOG.set(wdt.clone()).map_err(|_| KernelError::InUse)?;
+
+    devfs().mknod(
+        "watchdog".to_string(),
+        CharDevDescriptor {
+            major: ReservedMajors::Watchdog as _,
+            minor: 0,
+        },
+        FilePermissions::from_bits_retain(0o600),
+    )?;
+
+    Ok(wdt)
+}
+
+struct WatchdogFileOps {
+    wdt: Arc<SysWatchdog>,
+}
+
+#[async_trait]
+impl FileOps for WatchdogFileOps {
+    async fn readat(&mut self, _buf: UA, _count: usize, _offset: u64) -> Result<usize> {
+        Err(KernelError::InvalidValue)
+    }
+
+    /// Any write pets the watchdog, as on Linux.
+    async fn writeat(&mut self, _buf: UA, count: usize, _offset: u64) -> Result<usize> {
+        self.wdt.pet();
+        Ok(count)
+    }
+
+    async fn ioctl(&mut self, _ctx: &mut FileCtx, request: usize, argp: usize) -> Result<usize> {
+        match request {
+            WDIOC_SETOPTIONS => {
+                let options: i32 = copy_from_user(TUA::from_value(argp)).await?;
+
+                if options & WDIOS_DISABLECARD != 0 {
+                    self.wdt.disable();
+                }
+
+                if options & WDIOS_ENABLECARD != 0 {
+                    self.wdt.enable()?;
+                }
+
+                Ok(0)
+            }
+            WDIOC_KEEPALIVE => {
+                self.wdt.pet();
+                Ok(0)
+            }
+            WDIOC_SETTIMEOUT => {
+                let secs: i32 = copy_from_user(TUA::from_value(argp)).await?;
+                let secs = u64::try_from(secs).map_err(|_| KernelError::InvalidValue)?;
+
+                let timeout = self.wdt.set_timeout(Duration::from_secs(secs))?;
+
+                copy_to_user(TUA::from_value(argp), timeout.as_secs() as i32).await?;
+
+                Ok(0)
+            }
+            WDIOC_GETTIMEOUT => {
+                copy_to_user(TUA::from_value(argp), self.wdt.timeout().as_secs() as i32).await?;
+
+                Ok(0)
+            }
+            _ => Err(KernelError::NotATty),
+        }
+    }
+}
+
+struct WatchdogDev;
+
+impl OpenableDevice for WatchdogDev {
+    fn open(&self, flags: OpenFlags) -> Result<Arc<OpenFile>> {
+        let wdt = WATCHDOG.get().ok_or(KernelError::NoDevice)?.clone();
+
+        Ok(Arc::new(OpenFile::new(
+            Box::new(WatchdogFileOps { wdt }),
+            flags,
+        )))
+    }
+}
+
+struct WatchdogCharDev {
+    dev: Arc<dyn OpenableDevice>,
+}
+
+impl CharDriver for WatchdogCharDev {
+    fn get_device(&self, minor: u64) -> Option<Arc<dyn OpenableDevice>> {
+        (minor == 0).then(|| self.dev.clone())
+    }
+}
+
+pub fn watchdog_chardev_init(_bus: &mut PlatformBus, dm: &mut DriverManager) -> Result<()> {
+    let cdev = WatchdogCharDev {
+        dev: Arc::new(WatchdogDev),
+    };
+
+    dm.register_char_driver(ReservedMajors::Watchdog as _, Arc::new(cdev))
+}
+
+kernel_driver!(watchdog_chardev_init);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ktest;
+    use core::{
+        sync::atomic::AtomicUsize,
+        task::{Context, Waker},
+    };
+
+    #[derive(Default)]
+    struct MockWatchdog {
+        enabled: AtomicBool,
+        pets: AtomicUsize,
+    }
+
+    impl Driver for MockWatchdog {
+        fn name(&self) -> &'static str {
+            "mock-wdt"
+        }
+    }
+
+    impl HwWatchdog for MockWatchdog {
+        fn max_timeout(&self) -> Duration {
+            Duration::from_secs(60)
+        }
+
+        fn set_timeout(&self, timeout: Duration) -> Duration {
+            // Whole seconds only.
+            Duration::from_secs(timeout.as_secs())
+        }
+
+        fn enable(&self) {
+            self.enabled.store(true, Ordering::Relaxed);
+        }
+
+        fn disable(&self) {
+            self.enabled.store(false, Ordering::Relaxed);
+        }
+
+        fn pet(&self) {
+            self.pets.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    ktest! {
+        fn watchdog_timeout_is_clamped_and_rounded() {
+            let wdt = SysWatchdog::from_driver(Arc::new(MockWatchdog::default()));
+
+            assert_eq!(wdt.timeout(), DEFAULT_TIMEOUT);
+            assert_eq!(
+                wdt.set_timeout(Duration::from_millis(2500)),
+                Ok(Duration::from_secs(2))
+            );
+            assert_eq!(
+                wdt.set_timeout(Duration::from_secs(3600)),
+                Ok(Duration::from_secs(60))
+            );
+            assert_eq!(wdt.set_timeout(Duration::ZERO), Err(KernelError::InvalidValue));
+        }
+    }
+
+    ktest! {
+        fn watchdog_pet_thread_follows_state() {
+            let mock = Arc::new(MockWatchdog::default());
+            let wdt = Arc::new(SysWatchdog::from_driver(mock.clone()));
+
+            // Stand in for the scheduler, polling the thread directly.
+            let thread = pet_thread(wdt.clone());
+            pin_mut!(thread);
+            let mut poll_thread = || {
+                assert!(thread
+                    .as_mut()
+                    .poll(&mut Context::from_waker(Waker::noop()))
+                    .is_pending());
+            };
+
+            // Disabled, so no pets.
+            poll_thread();
+            assert_eq!(mock.pets.load(Ordering::Relaxed), 0);
+
+            wdt.driver.enable();
+            wdt.change_state(|s| s.enabled = true);
+            poll_thread();
+            assert_eq!(mock.pets.load(Ordering::Relaxed), 1);
+
+            // Sleeping out the interval; another poll doesn't pet.
+            poll_thread();
+            assert_eq!(mock.pets.load(Ordering::Relaxed), 1);
+
+            // A shorter timeout cuts the sleep short.
+            wdt.set_timeout(Duration::from_secs(1)).unwrap();
+            poll_thread();
+            assert_eq!(mock.pets.load(Ordering::Relaxed), 2);
+
+            // Once disabled the pets stop.
+            wdt.disable();
+            poll_thread();
+            poll_thread();
+            assert_eq!(mock.pets.load(Ordering::Relaxed), 2);
+            assert!(!mock.enabled.load(Ordering::Relaxed));
+        }
+    }
+}
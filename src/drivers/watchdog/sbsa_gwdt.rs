@@ -0,0 +1,277 @@
+//! The SBSA Generic Watchdog.
+//!
+//! The watchdog counts on the system counter. Once `WOR` ticks pass without a
+//! refresh it raises WS0, and if another `WOR` ticks pass it raises WS1, which
+//! resets the board. WS0's interrupt is deliberately left unclaimed: handling
+//! it would mean petting from interrupt context, which still happens when the
+//! scheduler has locked up. So the watchdog is run single-stage, with `WOR`
+//! set to half the timeout.
+
+use super::{HwWatchdog, register_watchdog};
+use crate::{
+    arch::ArchImpl,
+    drivers::{
+        Driver, DriverManager,
+        init::PlatformBus,
+        probe::{DeviceDescriptor, DeviceMatchType},
+    },
+    kernel_driver,
+};
+use aarch64_cpu::registers::{CNTFRQ_EL0, Readable as _};
+use alloc::{boxed::Box, sync::Arc};
+use core::time::Duration;
+use libkernel::{
+    KernAddressSpace, VirtualMemory,
+    error::{ProbeError, Result},
+    memory::{
+        address::{PA, VA},
+        region::PhysMemoryRegion,
+    },
+};
+use log::info;
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_structs,
+    registers::{ReadOnly, ReadWrite},
+};
+
+register_structs! {
+    /// Watchdog control frame.
+    #[allow(non_snake_case)]
+    GwdtControlRegs {
+        /// Watchdog Control and Status Register.
+        (0x000 => WCS: ReadWrite<u32>),
+        (0x004 => _reserved_0),
+        /// Watchdog Offset Register. From architecture version 1 it's 48 bits,
+        /// with the top 16 in `WOR_HI`.
+        (0x008 => WOR: ReadWrite<u32>),
+        (0x00c => WOR_HI: ReadWrite<u32>),
+        /// Watchdog Compare Value Register.
+        (0x010 => WCV: ReadWrite<u64>),
+        (0x018 => _reserved_1),
+        /// Watchdog Interface Identification Register.
+        (0xfcc => W_IIDR: ReadOnly<u32>),
+        (0xfd0 => @END),
+    }
+}
+
+register_structs! {
+    /// Watchdog refresh frame.
+    #[allow(non_snake_case)]
+    GwdtRefreshRegs {
+        /// Watchdog Refresh Register. Any write refreshes the watchdog.
+        (0x000 => WRR: ReadWrite<u32>),
+        (0x004 => _reserved_0),
+        /// Watchdog Interface Identification Register.
+        (0xfcc => W_IIDR: ReadOnly<u32>),
+        (0xfd0 => @END),
+    }
+}
+
+/// `WCS.EN`: the watchdog is counting.
+const WCS_EN: u32 = 1 << 0;
+
+struct SbsaGwdt {
+    fdt_name: &'static str,
+    ctrl: &'static GwdtControlRegs,
+    refresh: &'static GwdtRefreshRegs,
+    /// The system counter frequency.
+    freq: u64,
+}
+
+unsafe impl Sync for SbsaGwdt {}
+unsafe impl Send for SbsaGwdt {}
+
+impl SbsaGwdt {
+    /// # Safety
+    ///
+    /// `ctrl` and `refresh` must point to the mapped control and refresh
+    /// frames of a watchdog.
+    unsafe fn new(fdt_name: &'static str, ctrl: VA, refresh: VA, freq: u64) -> Self {
+        Self {
+            fdt_name,
+            ctrl: unsafe { &*(ctrl.value() as *const GwdtControlRegs) },
+            refresh: unsafe { &*(refresh.value() as *const GwdtRefreshRegs) },
+            freq,
+        }
+    }
+
+    /// The largest value `WOR` can hold.
+    fn max_wor(&self) -> u64 {
+        // W_IIDR.ArchVer
+        match (self.ctrl.W_IIDR.get() >> 16) & 0xf {
+            0 => u32::MAX as u64,
+            _ => (1 << 48) - 1,
+        }
+    }
+
+    fn set_wor(&self, wor: u64) {
+        // The write to the low half refreshes the watchdog, so set the high
+        // half first.
+        if self.max_wor() > u32::MAX as u64 {
+            self.ctrl.WOR_HI.set((wor >> 32) as u32);
+        }
+
+        self.ctrl.WOR.set(wor as u32);
+    }
+
+    /// Converts a timeout into a `WOR` value. The board is reset after two
+    /// lots of `WOR` ticks.
+    fn timeout_to_wor(&self, timeout: Duration) -> u64 {
+        let wor = timeout.as_nanos() * self.freq as u128 / 2 / 1_000_000_000;
+
+        wor.min(self.max_wor() as u128) as u64
+    }
+
+    fn wor_to_timeout(&self, wor: u64) -> Duration {
+        Duration::from_nanos((2 * wor as u128 * 1_000_000_000 / self.freq as u128) as u64)
+    }
+}
+
+impl Driver for SbsaGwdt {
+    fn name(&self) -> &'static str {
+        self.fdt_name
+    }
+}
+
+impl HwWatchdog for SbsaGwdt {
+    fn max_timeout(&self) -> Duration {
+        self.wor_to_timeout(self.max_wor())
+    }
+
+    fn set_timeout(&self, timeout: Duration) -> Duration {
+        let wor = self.timeout_to_wor(timeout);
+
+        self.set_wor(wor);
+
+        self.wor_to_timeout(wor)
+    }
+
+    fn enable(&self) {
+        self.ctrl.WCS.set(WCS_EN);
+    }
+
+    fn disable(&self) {
+        self.ctrl.WCS.set(0);
+    }
+
+    fn pet(&self) {
+        self.refresh.WRR.set(0);
+    }
+}
+
+fn sbsa_gwdt_probe(_dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<dyn Driver>> {
+    match d {
+        DeviceDescriptor::Fdt(fdt_node, _) => {
+            let mut regs = fdt_node.reg().ok_or(ProbeError::NoReg)?;
+            let ctrl_region = regs.next().ok_or(ProbeError::NoReg)?;
+            let refresh_region = regs.next().ok_or(ProbeError::NoReg)?;
+
+            let (ctrl_mem, refresh_mem) = {
+                let mut kern_addr_spc = ArchImpl::kern_address_space().lock_save_irq();
+
+                let ctrl_mem = kern_addr_spc.map_mmio(PhysMemoryRegion::new(
+                    PA::from_value(ctrl_region.address as usize),
+                    ctrl_region.size.ok_or(ProbeError::NoRegSize)?,
+                ))?;
+
+                let refresh_mem = kern_addr_spc.map_mmio(PhysMemoryRegion::new(
+                    PA::from_value(refresh_region.address as usize),
+                    refresh_region.size.ok_or(ProbeError::NoRegSize)?,
+                ))?;
+
+                (ctrl_mem, refresh_mem)
+            };
+
+            // SAFETY: Both frames were mapped above.
+            let gwdt =
+                unsafe { SbsaGwdt::new(fdt_node.name, ctrl_mem, refresh_mem, CNTFRQ_EL0.get()) };
+
+            // Firmware may have left it running; it stays off until userspace
+            // asks for it.
+            gwdt.disable();
+
+            info!(
+                "SBSA watchdog {}: max timeout {:?}",
+                fdt_node.name,
+                gwdt.max_timeout()
+            );
+
+            Ok(register_watchdog(Arc::new(gwdt))?)
+        }
+    }
+}
+
+pub fn sbsa_gwdt_init(bus: &mut PlatformBus, _dm: &mut DriverManager) -> Result<()> {
+    bus.register_platform_driver(
+        DeviceMatchType::FdtCompatible("arm,sbsa-gwdt"),
+        Box::new(sbsa_gwdt_probe),
+    );
+
+    Ok(())
+}
+
+kernel_driver!(sbsa_gwdt_init);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ktest, memory::page::ClaimedPage};
+
+    const FREQ: u64 = 62_500_000;
+
+    /// A watchdog backed by zeroed RAM rather than MMIO.
+    fn fake_gwdt(ctrl: &ClaimedPage, refresh: &ClaimedPage) -> SbsaGwdt {
+        unsafe { SbsaGwdt::new("fake-gwdt", ctrl.va(), refresh.va(), FREQ) }
+    }
+
+    ktest! {
+        fn sbsa_gwdt_programs_timeout_and_enable() {
+            let ctrl = ClaimedPage::alloc_zeroed().unwrap();
+            let refresh = ClaimedPage::alloc_zeroed().unwrap();
+            let gwdt = fake_gwdt(&ctrl, &refresh);
+
+            // WS1, the reset, comes after two lots of WOR ticks.
+            assert_eq!(gwdt.set_timeout(Duration::from_secs(10)), Duration::from_secs(10));
+            assert_eq!(gwdt.ctrl.WOR.get() as u64, 5 * FREQ);
+
+            gwdt.enable();
+            assert_eq!(gwdt.ctrl.WCS.get(), WCS_EN);
+
+            gwdt.refresh.WRR.set(0xdead);
+            gwdt.pet();
+            assert_eq!(gwdt.refresh.WRR.get(), 0);
+
+            gwdt.disable();
+            assert_eq!(gwdt.ctrl.WCS.get(), 0);
+        }
+    }
+
+    ktest! {
+        fn sbsa_gwdt_clamps_to_wor_width() {
+            let ctrl = ClaimedPage::alloc_zeroed().unwrap();
+            let refresh = ClaimedPage::alloc_zeroed().unwrap();
+            let gwdt = fake_gwdt(&ctrl, &refresh);
+
+            // Version 0: a 32-bit WOR, good for about 137s at 62.5MHz.
+            let max = gwdt.max_timeout();
+            assert_eq!(max.as_secs(), 2 * u32::MAX as u64 / FREQ);
+            assert_eq!(gwdt.set_timeout(Duration::from_secs(3600)), max);
+            assert_eq!(gwdt.ctrl.WOR.get(), u32::MAX);
+            assert_eq!(gwdt.ctrl.WOR_HI.get(), 0);
+
+            // Version 1: 48 bits, split across WOR and WOR_HI.
+            unsafe {
+                ctrl.as_ptr_mut()
+                    .add(0xfcc)
+                    .cast::<u32>()
+                    .write_volatile(1 << 16)
+            };
+
+            let timeout = gwdt.set_timeout(Duration::from_secs(3600));
+            assert_eq!(timeout, Duration::from_secs(3600));
+            let wor = (gwdt.ctrl.WOR_HI.get() as u64) << 32 | gwdt.ctrl.WOR.get() as u64;
+            assert_eq!(wor, 1800 * FREQ);
+        }
+    }
+}
@@ -4,7 +4,7 @@ use async_trait::async_trait;
 use core::alloc::Layout;
 use libkernel::{
     error::{FsError, KernelError, Result},
-    fs::{DirStream, Dirent, FileType, Inode},
+    fs::{DirStream, Dirent, FileType, Inode, SeekFrom},
     memory::address::UA,
 };
 use ringbuf::Arc;
@@ -90,6 +90,20 @@ impl FileOps for DirFile {
             next,
         })
     }
+
+    /// Repositions the directory stream. Only offsets previously handed out in
+    /// a dirent's `d_off` (or zero, to rewind) are meaningful, so relative and
+    /// end-relative seeks are only accepted as a no-op query of the current
+    /// position.
+    async fn seek(&mut self, ctx: &mut FileCtx, pos: SeekFrom) -> Result<u64> {
+        match pos {
+            SeekFrom::Start(x) => ctx.pos = x,
+            SeekFrom::Current(0) => {}
+            _ => return Err(KernelError::InvalidValue),
+        }
+
+        Ok(ctx.pos)
+    }
 }
 
 #[repr(u8)]
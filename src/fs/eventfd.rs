@@ -0,0 +1,269 @@
+//! Event counters, created by `eventfd2`, for signalling between tasks.
+//!
+//! Each counter is 8 bytes wide. Reads return the counter and reset it to
+//! zero, or in semaphore mode decrement it by one, and block whilst it is
+//! zero. Writes add to the counter, blocking whilst the addition would take it
+//! past `u64::MAX - 1`.
+
+use crate::{
+    memory::uaccess::{copy_from_user, copy_to_user},
+    process::thread_group::signal::{InterruptResult, Interruptable},
+    sched::current::current_task,
+    sync::CondVar,
+};
+use alloc::{boxed::Box, sync::Arc};
+use async_trait::async_trait;
+use core::{mem::size_of, pin::Pin};
+use libkernel::{
+    error::{KernelError, Result},
+    fs::{OpenFlags, SeekFrom},
+    memory::address::UA,
+    sync::condvar::WakeupType,
+};
+
+use super::{
+    fops::FileOps,
+    open_file::{FileCtx, OpenFile},
+};
+
+/// Reads decrement the counter by one, rather than resetting it.
+const EFD_SEMAPHORE: u32 = 1;
+
+/// The largest value the counter can hold.
+const COUNT_MAX: u64 = u64::MAX - 1;
+
+struct EventFd {
+    count: CondVar<u64>,
+    semaphore: bool,
+}
+
+/// Takes the value a read returns from `count`, or `None` if it would block.
+fn take(count: &mut u64, semaphore: bool) -> Option<u64> {
+    if *count == 0 {
+        return None;
+    }
+
+    let val = if semaphore { 1 } else { *count };
+    *count -= val;
+
+    Some(val)
+}
+
+/// Adds `val` to `count`, or returns `None` if the write would block.
+fn add(count: &mut u64, val: u64) -> Option<()> {
+    (val <= COUNT_MAX - *count).then(|| *count += val)
+}
+
+impl EventFd {
+    fn new(initval: u64, semaphore: bool) -> Self {
+        Self {
+            count: CondVar::new(initval),
+            semaphore,
+        }
+    }
+
+    /// Applies `op` to the counter, waiting until it succeeds unless
+    /// `nonblock` is set. The other side is woken afterwards, as the counter
+    /// will have changed.
+    async fn update<T>(&self, nonblock: bool, op: impl Fn(&mut u64) -> Option<T>) -> Result<T> {
+        let res = if nonblock {
+            let mut res = None;
+
+            self.count.update(|count| {
+                res = op(count);
+                WakeupType::None
+            });
+
+            res.ok_or(KernelError::TryAgain)?
+        } else {
+            match self.count.wait_until(op).interruptable().await {
+                InterruptResult::Interrupted => return Err(KernelError::Interrupted),
+                InterruptResult::Uninterrupted(res) => res,
+            }
+        };
+
+        self.count.update(|_| WakeupType::All);
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl FileOps for EventFd {
+    async fn read(&mut self, ctx: &mut FileCtx, buf: UA, count: usize) -> Result<usize> {
+        if count < size_of::<u64>() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let semaphore = self.semaphore;
+        let nonblock = ctx.flags.contains(OpenFlags::O_NONBLOCK);
+        let val = self
+            .update(nonblock, move |count| take(count, semaphore))
+            .await?;
+
+        copy_to_user(buf.cast(), val).await?;
+
+        Ok(size_of::<u64>())
+    }
+
+    async fn readat(&mut self, _buf: UA, _count: usize, _offset: u64) -> Result<usize> {
+        Err(KernelError::SeekPipe)
+    }
+
+    async fn write(&mut self, ctx: &mut FileCtx, buf: UA, count: usize) -> Result<usize> {
+        if count < size_of::<u64>() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let val: u64 = copy_from_user(buf.cast()).await?;
+
+        if val == u64::MAX {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let nonblock = ctx.flags.contains(OpenFlags::O_NONBLOCK);
+        self.update(nonblock, move |count| add(count, val)).await?;
+
+        Ok(size_of::<u64>())
+    }
+
+    async fn writeat(&mut self, _buf: UA, _count: usize, _offset: u64) -> Result<usize> {
+        Err(KernelError::SeekPipe)
+    }
+
+    async fn seek(&mut self, _ctx: &mut FileCtx, _pos: SeekFrom) -> Result<u64> {
+        Err(KernelError::SeekPipe)
+    }
+
+    fn poll_read_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        let ready = self.count.wait_until(|count| (*count > 0).then_some(()));
+
+        Box::pin(async move {
+            ready.await;
+            Ok(())
+        })
+    }
+
+    fn poll_write_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        let ready = self
+            .count
+            .wait_until(|count| (*count < COUNT_MAX).then_some(()));
+
+        Box::pin(async move {
+            ready.await;
+            Ok(())
+        })
+    }
+}
+
+pub fn sys_eventfd2(initval: u32, flags: u32) -> Result<usize> {
+    let semaphore = flags & EFD_SEMAPHORE != 0;
+    let flags = OpenFlags::from_bits_retain(flags & !EFD_SEMAPHORE);
+
+    if !flags
+        .difference(OpenFlags::O_CLOEXEC | OpenFlags::O_NONBLOCK)
+        .is_empty()
+    {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let file = OpenFile::new(
+        Box::new(EventFd::new(initval as _, semaphore)),
+        OpenFlags::O_RDWR | (flags & OpenFlags::O_NONBLOCK),
+    );
+
+    let fd = current_task()
+        .fd_table
+        .lock_save_irq()
+        .insert_with_flags(Arc::new(file), flags.into())?;
+
+    Ok(fd.as_raw() as _)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ktest, testing::ScratchBuf};
+    use futures::FutureExt;
+    use libkernel::memory::PAGE_SIZE;
+
+    async fn write(efd: &mut EventFd, ctx: &mut FileCtx, buf: UA, val: u64) -> Result<usize> {
+        copy_to_user(buf.cast(), val).await?;
+        efd.write(ctx, buf, 8).await
+    }
+
+    async fn read(efd: &mut EventFd, ctx: &mut FileCtx, buf: UA) -> Result<u64> {
+        efd.read(ctx, buf, 8).await?;
+        copy_from_user(buf.cast()).await
+    }
+
+    ktest! {
+        async fn eventfd_counter_mode() {
+            let scratch = ScratchBuf::new(PAGE_SIZE).await;
+            let buf = scratch.addr();
+            let mut efd = EventFd::new(1, false);
+            let mut ctx = FileCtx::new(OpenFlags::O_RDWR | OpenFlags::O_NONBLOCK);
+
+            write(&mut efd, &mut ctx, buf, 3).await.unwrap();
+            write(&mut efd, &mut ctx, buf, 4).await.unwrap();
+
+            // A read returns the whole count and resets it.
+            assert_eq!(read(&mut efd, &mut ctx, buf).await.unwrap(), 8);
+            assert!(matches!(
+                read(&mut efd, &mut ctx, buf).await,
+                Err(KernelError::TryAgain)
+            ));
+
+            // Writes that would overflow the counter are refused.
+            write(&mut efd, &mut ctx, buf, COUNT_MAX).await.unwrap();
+            assert!(matches!(
+                write(&mut efd, &mut ctx, buf, 1).await,
+                Err(KernelError::TryAgain)
+            ));
+            assert!(matches!(
+                write(&mut efd, &mut ctx, buf, u64::MAX).await,
+                Err(KernelError::InvalidValue)
+            ));
+            assert!(matches!(
+                efd.read(&mut ctx, buf, 4).await,
+                Err(KernelError::InvalidValue)
+            ));
+        }
+    }
+
+    ktest! {
+        async fn eventfd_semaphore_mode() {
+            let scratch = ScratchBuf::new(PAGE_SIZE).await;
+            let buf = scratch.addr();
+            let mut efd = EventFd::new(2, true);
+            let mut ctx = FileCtx::new(OpenFlags::O_RDWR | OpenFlags::O_NONBLOCK);
+
+            assert_eq!(read(&mut efd, &mut ctx, buf).await.unwrap(), 1);
+            assert_eq!(read(&mut efd, &mut ctx, buf).await.unwrap(), 1);
+            assert!(matches!(
+                read(&mut efd, &mut ctx, buf).await,
+                Err(KernelError::TryAgain)
+            ));
+        }
+    }
+
+    ktest! {
+        async fn eventfd_readiness() {
+            let scratch = ScratchBuf::new(PAGE_SIZE).await;
+            let buf = scratch.addr();
+            let mut efd = EventFd::new(0, false);
+            let mut ctx = FileCtx::new(OpenFlags::O_RDWR);
+
+            let mut ready = efd.poll_read_ready();
+            assert!((&mut ready).now_or_never().is_none());
+            assert!(efd.poll_write_ready().now_or_never().is_some());
+
+            // A write makes a waiting reader ready.
+            write(&mut efd, &mut ctx, buf, 5).await.unwrap();
+            assert!(matches!(ready.now_or_never(), Some(Ok(()))));
+
+            assert_eq!(read(&mut efd, &mut ctx, buf).await.unwrap(), 5);
+            assert!(efd.poll_read_ready().now_or_never().is_none());
+        }
+    }
+}
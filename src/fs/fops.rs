@@ -1,6 +1,6 @@
 use core::pin::Pin;
 
-use alloc::boxed::Box;
+use alloc::{boxed::Box, sync::Arc};
 use async_trait::async_trait;
 use libkernel::{
     error::{FsError, KernelError, Result},
@@ -8,9 +8,9 @@ use libkernel::{
     memory::address::UA,
 };
 
-use crate::kernel::kpipe::KPipe;
+use crate::{kernel::kpipe::KPipe, process::fd_table::epoll::Epoll};
 
-use super::{dir::OpenFileDirIter, open_file::FileCtx, syscalls::iov::IoVec};
+use super::{dir::OpenFileDirIter, open_file::FileCtx, syscalls::iov::IoVec, timerfd::Timer};
 
 macro_rules! process_iovec {
     ($iovecs:expr, |$addr:ident, $count:ident| $call:expr) => {
@@ -92,6 +92,16 @@ pub trait FileOps: Send + Sync {
         Box::pin(async { Err(KernelError::NotSupported) })
     }
 
+    /// Returns the epoll instance this file refers to, if it is one.
+    fn as_epoll(&self) -> Option<Arc<Epoll>> {
+        None
+    }
+
+    /// Returns the timer this file refers to, if it is a timerfd.
+    fn as_timer(&self) -> Option<Arc<Timer>> {
+        None
+    }
+
     /// Moves the file's cursor to a new position.
     /// Returns the new position from the start of the file.
     async fn seek(&mut self, _ctx: &mut FileCtx, _pos: SeekFrom) -> Result<u64> {
@@ -0,0 +1,131 @@
+//! Anonymous in-memory files, created by `memfd_create`.
+//!
+//! Each file is an unlinked regular file on an internal tmpfs instance, so its
+//! pages are allocated as it's written and freed when it shrinks or the last
+//! reference to it is dropped.
+
+use crate::{
+    arch::ArchImpl,
+    memory::{PageOffsetTranslator, page::PgAllocGetter, uaccess::cstr::UserCStr},
+    sched::current::current_task_shared,
+    sync::OnceLock,
+};
+use alloc::{boxed::Box, format, sync::Arc};
+use core::{ffi::c_char, sync::atomic::Ordering};
+use libkernel::{
+    error::{KernelError, Result},
+    fs::{Inode, OpenFlags, attr::FilePermissions, filesystems::tmpfs::TmpFs, pathbuf::PathBuf},
+    memory::address::TUA,
+};
+
+use super::{VFS, open_file::OpenFile, reg::RegFile};
+
+bitflags::bitflags! {
+    #[derive(Clone, Copy, Debug)]
+    pub struct MemfdFlags: u32 {
+        const MFD_CLOEXEC       = 0x1;
+        const MFD_ALLOW_SEALING = 0x2;
+        const MFD_HUGETLB       = 0x4;
+    }
+}
+
+/// The longest name a memfd can be given, excluding the `memfd:` prefix.
+const MFD_NAME_MAX: usize = 249;
+
+type MemfdFs = TmpFs<ArchImpl, PgAllocGetter, PageOffsetTranslator>;
+
+static MEMFD_FS: OnceLock<Arc<MemfdFs>> = OnceLock::new();
+
+/// Creates an empty anonymous file.
+pub fn create_inode() -> Result<Arc<dyn Inode>> {
+    MEMFD_FS
+        .get_or_init(|| MemfdFs::new(VFS.next_fs_id.fetch_add(1, Ordering::SeqCst)))
+        .create_unlinked(FilePermissions::from_bits_retain(0o777))
+}
+
+pub async fn sys_memfd_create(name: TUA<c_char>, flags: u32) -> Result<usize> {
+    let flags = MemfdFlags::from_bits(flags).ok_or(KernelError::InvalidValue)?;
+
+    if flags.contains(MemfdFlags::MFD_HUGETLB) {
+        return Err(KernelError::NotSupported);
+    }
+
+    let mut buf = [0; MFD_NAME_MAX + 1];
+    let name = match UserCStr::from_ptr(name).copy_from_user(&mut buf).await {
+        Err(KernelError::BufferFull) => return Err(KernelError::InvalidValue),
+        name => name?,
+    };
+
+    let inode = create_inode()?;
+    let mut file = OpenFile::new(Box::new(RegFile::new(inode.clone())), OpenFlags::O_RDWR);
+
+    file.update(inode, PathBuf::from(format!("/memfd:{name}")));
+
+    let fd = current_task_shared()
+        .fd_table
+        .lock_save_irq()
+        .insert(Arc::new(file))?;
+
+    Ok(fd.as_raw() as _)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fs::syscalls::{
+            close::sys_close,
+            rw::{sys_pread64, sys_pwrite64},
+            trunc::sys_ftruncate,
+        },
+        ktest,
+        memory::{
+            PAGE_ALLOC,
+            uaccess::{copy_from_user_slice, copy_to_user_slice},
+        },
+        process::fd_table::Fd,
+        testing::ScratchBuf,
+    };
+    use alloc::vec;
+    use libkernel::memory::PAGE_SIZE;
+
+    ktest! {
+        async fn memfd_write_read_and_truncate() {
+            let scratch = ScratchBuf::new(4 * PAGE_SIZE).await;
+            let name = scratch.addr();
+            let buf = name.add_bytes(PAGE_SIZE);
+
+            copy_to_user_slice(b"test\0", name).await.unwrap();
+            let fd = Fd(sys_memfd_create(name.cast(), 0).await.unwrap() as _);
+
+            let page_alloc = PAGE_ALLOC.get().unwrap();
+            let baseline = page_alloc.free_pages();
+
+            // Write across a page boundary, leaving a hole at the start.
+            let data = vec![0xa5u8; PAGE_SIZE];
+            copy_to_user_slice(&data, buf).await.unwrap();
+            let offset = PAGE_SIZE as u64 / 2;
+            assert_eq!(sys_pwrite64(fd, buf, PAGE_SIZE, offset).await.unwrap(), PAGE_SIZE);
+            assert!(page_alloc.free_pages() < baseline);
+
+            let mut read = vec![0u8; 2 * PAGE_SIZE];
+            assert_eq!(sys_pread64(fd, buf, read.len(), 0).await.unwrap(), 3 * PAGE_SIZE / 2);
+            copy_from_user_slice(buf, &mut read).await.unwrap();
+            assert!(read[..PAGE_SIZE / 2].iter().all(|b| *b == 0));
+            assert_eq!(read[PAGE_SIZE / 2..3 * PAGE_SIZE / 2], data[..]);
+
+            // Growing the file reads back as zeros; shrinking it to nothing
+            // frees every page.
+            sys_ftruncate(fd, 2 * PAGE_SIZE).await.unwrap();
+            assert_eq!(sys_pread64(fd, buf, read.len(), 0).await.unwrap(), 2 * PAGE_SIZE);
+            copy_from_user_slice(buf, &mut read).await.unwrap();
+            assert!(read[3 * PAGE_SIZE / 2..].iter().all(|b| *b == 0));
+
+            sys_ftruncate(fd, 0).await.unwrap();
+            assert_eq!(page_alloc.free_pages(), baseline);
+            assert_eq!(sys_pread64(fd, buf, read.len(), 0).await.unwrap(), 0);
+
+            sys_close(fd).await.unwrap();
+        }
+    }
+}
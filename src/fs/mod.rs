@@ -19,11 +19,17 @@ use open_file::OpenFile;
 use reg::RegFile;
 
 pub mod dir;
+pub mod eventfd;
 pub mod fops;
+pub mod memfd;
 pub mod open_file;
+pub mod page_cache;
 pub mod pipe;
+pub mod readahead;
 pub mod reg;
 pub mod syscalls;
+pub mod timerfd;
+pub mod writeback;
 
 const MAX_SYMLINK: u32 = 40;
 
@@ -40,16 +46,22 @@ impl Inode for DummyInode {
 struct Mount {
     fs: Arc<dyn Filesystem>,
     root_inode: Arc<dyn Inode>,
+    /// The directory this filesystem is mounted on, or `None` for the root
+    /// filesystem.
+    mount_point: Option<Arc<dyn Inode>>,
 }
 
 /// This trait represents a type of filesystem, like "ext4" or "tmpfs". It acts
 /// as a factory for creating mounted instances.
 #[async_trait]
 pub trait FilesystemDriver: Driver + Send + Sync {
+    /// `options` holds the comma-separated mount options, as passed in
+    /// mount(2)'s `data`. Options a filesystem doesn't know are ignored.
     async fn construct(
         &self,
         fs_id: u64,
         blk_dev: Option<Box<dyn BlockDevice>>,
+        options: &str,
     ) -> Result<Arc<dyn Filesystem>>;
 }
 
@@ -87,6 +99,15 @@ impl VfsState {
             .map(|mount| mount.root_inode.clone())
     }
 
+    /// Checks if an inode is the root of a mounted filesystem and returns the
+    /// directory it is mounted on if it is.
+    fn get_mount_point(&self, inode_id: &InodeId) -> Option<Arc<dyn Inode>> {
+        self.mounts
+            .values()
+            .find(|mount| mount.root_inode.id() == *inode_id)
+            .and_then(|mount| mount.mount_point.clone())
+    }
+
     fn get_fs(&self, inode_id: InodeId) -> Option<Arc<dyn Filesystem>> {
         self.filesystems.get(&inode_id.fs_id()).cloned()
     }
@@ -116,6 +137,7 @@ impl VFS {
         &self,
         driver_name: &str,
         blkdev: Option<Box<dyn BlockDevice>>,
+        options: &str,
     ) -> Result<Arc<dyn Filesystem>> {
         let driver = DM
             .lock_save_irq()
@@ -126,7 +148,7 @@ impl VFS {
 
         let id = self.next_fs_id.fetch_add(1, Ordering::SeqCst);
 
-        driver.construct(id, blkdev).await
+        driver.construct(id, blkdev, options).await
     }
 
     /// Mounts the root filesystem.
@@ -134,13 +156,17 @@ impl VFS {
         &self,
         driver_name: &str,
         blkdev: Option<Box<dyn BlockDevice>>,
+        options: &str,
     ) -> Result<()> {
-        let fs = self.create_fs_instance(driver_name, blkdev).await?;
+        let fs = self
+            .create_fs_instance(driver_name, blkdev, options)
+            .await?;
         let root_inode = fs.root_inode().await?;
 
         let mount = Mount {
             fs,
             root_inode: root_inode.clone(),
+            mount_point: None,
         };
 
         // Lock the state to add the new mount and filesystem.
@@ -158,16 +184,29 @@ impl VFS {
         mount_point: Arc<dyn Inode>,
         driver_name: &str,
         blkdev: Option<Box<dyn BlockDevice>>,
+        options: &str,
     ) -> Result<()> {
         if mount_point.getattr().await?.file_type != FileType::Directory {
             return Err(FsError::NotADirectory.into());
         }
 
-        let fs = self.create_fs_instance(driver_name, blkdev).await?;
+        let fs = self
+            .create_fs_instance(driver_name, blkdev, options)
+            .await?;
+
+        self.attach(mount_point, fs).await
+    }
+
+    /// Attaches an already constructed filesystem instance at `mount_point`.
+    async fn attach(&self, mount_point: Arc<dyn Inode>, fs: Arc<dyn Filesystem>) -> Result<()> {
         let mount_point_id = mount_point.id();
         let root_inode = fs.root_inode().await?;
 
-        let new_mount = Mount { fs, root_inode };
+        let new_mount = Mount {
+            fs,
+            root_inode,
+            mount_point: Some(mount_point),
+        };
 
         // Lock the state and insert the new mount.
         self.state
@@ -192,13 +231,15 @@ impl VFS {
         root: Arc<dyn Inode>,
         task: &Arc<Task>,
     ) -> Result<Arc<dyn Inode>> {
+        // Use the task's root inode, in case a custom chroot was set.
+        let abs_root = task.root.lock_save_irq().0.clone();
         let root = if path.is_absolute() {
-            task.root.lock_save_irq().0.clone() // use the task's root inode, in case a custom chroot was set
+            abs_root.clone()
         } else {
             root
         };
 
-        self.resolve_path_internal(path, root, true).await
+        self.resolve_path_internal(path, root, abs_root, true).await
     }
 
     /// Resolves a path string to an Inode, starting from a given root for
@@ -209,13 +250,14 @@ impl VFS {
         root: Arc<dyn Inode>,
         task: &Arc<Task>,
     ) -> Result<Arc<dyn Inode>> {
+        let abs_root = task.root.lock_save_irq().0.clone();
         let root = if path.is_absolute() {
-            task.root.lock_save_irq().0.clone()
+            abs_root.clone()
         } else {
             root
         };
 
-        self.resolve_path_internal(path, root, false).await
+        self.resolve_path_internal(path, root, abs_root, false).await
     }
 
     /// Resolves a path string to an Inode, starting from a given root for
@@ -225,27 +267,43 @@ impl VFS {
         path: &Path,
         root: Arc<dyn Inode>,
     ) -> Result<Arc<dyn Inode>> {
+        let abs_root = self
+            .root_inode
+            .lock_save_irq()
+            .as_ref()
+            .cloned()
+            .ok_or(FsError::NotFound)?;
         let root = if path.is_absolute() {
-            self.root_inode
-                .lock_save_irq()
-                .as_ref()
-                .cloned()
-                .ok_or(FsError::NotFound)?
+            abs_root.clone()
         } else {
             root
         };
 
-        self.resolve_path_internal(path, root, true).await
+        self.resolve_path_internal(path, root, abs_root, true).await
     }
 
+    /// Walks `path` component by component starting at `root`.
+    ///
+    /// Symbolic links are expanded in place, with relative targets resolved
+    /// against the directory containing the link and absolute targets
+    /// restarting at `abs_root`. At most `MAX_SYMLINK` links are followed
+    /// before failing with `FsError::Loop`. When `follow_last_sym` is false,
+    /// a symlink in the final position is returned as-is.
+    ///
+    /// Mount points are crossed on the way down, and `..` at the root of a
+    /// mounted filesystem leads back to the parent of its mount point.
     async fn resolve_path_internal(
         &self,
         path: &Path,
         root: Arc<dyn Inode>,
+        abs_root: Arc<dyn Inode>,
         follow_last_sym: bool,
     ) -> Result<Arc<dyn Inode>> {
         let mut current_inode = root;
         let mut symlink_count = 0;
+        // The directories walked through so far, so that `..` can step back
+        // out without relying on the filesystem storing parent links.
+        let mut ancestors: Vec<Arc<dyn Inode>> = Vec::new();
 
         let mut components: Vec<_> = path.components().map(|s| s.to_owned()).collect();
         components.reverse();
@@ -261,6 +319,13 @@ impl VFS {
                 current_inode = mount_root;
             }
 
+            if component == ".." {
+                current_inode = self
+                    .walk_up(current_inode, &abs_root, &mut ancestors)
+                    .await?;
+                continue;
+            }
+
             let next_inode = current_inode.lookup(&component).await?;
 
             let attr = next_inode.getattr().await?;
@@ -281,14 +346,15 @@ impl VFS {
 
                 if target.is_absolute() {
                     // if absolute, restart from root
-                    current_inode = self.root_inode.lock_save_irq().as_ref().unwrap().clone();
+                    current_inode = abs_root.clone();
+                    ancestors.clear();
                 }
 
                 continue;
             }
 
             // Delegate the lookup to the underlying filesystem.
-            current_inode = next_inode;
+            ancestors.push(core::mem::replace(&mut current_inode, next_inode));
         }
 
         // After the final lookup, check if the destination is itself a mount point.
@@ -303,6 +369,34 @@ impl VFS {
         Ok(current_inode)
     }
 
+    /// Resolves `..` from `current`.
+    async fn walk_up(
+        &self,
+        mut current: Arc<dyn Inode>,
+        abs_root: &Arc<dyn Inode>,
+        ancestors: &mut Vec<Arc<dyn Inode>>,
+    ) -> Result<Arc<dyn Inode>> {
+        loop {
+            // `..` never escapes the task's root, nor the global root.
+            if current.id() == abs_root.id() || current.id() == self.root_inode().id() {
+                return Ok(current);
+            }
+
+            if let Some(parent) = ancestors.pop() {
+                return Ok(parent);
+            }
+
+            // At the root of a mounted filesystem, step out onto the mount
+            // point and take its parent in the covering filesystem instead.
+            let mount_point = self.state.lock_save_irq().get_mount_point(&current.id());
+
+            match mount_point {
+                Some(mount_point) => current = mount_point,
+                None => return current.lookup("..").await,
+            }
+        }
+    }
+
     /// Returns a clone of the root inode.
     pub fn root_inode(&self) -> Arc<dyn Inode> {
         self.root_inode.lock_save_irq().as_ref().unwrap().clone()
@@ -317,7 +411,11 @@ impl VFS {
         task: &Arc<Task>,
     ) -> Result<Arc<OpenFile>> {
         // Attempt to resolve the full path first.
-        let resolve_result = self.resolve_path(path, root.clone(), task).await;
+        let resolve_result = if flags.contains(OpenFlags::O_NOFOLLOW) {
+            self.resolve_path_nofollow(path, root.clone(), task).await
+        } else {
+            self.resolve_path(path, root.clone(), task).await
+        };
 
         let target_inode = match resolve_result {
             // The file/directory exists.
@@ -380,13 +478,13 @@ impl VFS {
             && (flags.contains(OpenFlags::O_WRONLY) || flags.contains(OpenFlags::O_RDWR))
         {
             // TODO: Check for write permissions on the inode itself.
-            target_inode.truncate(0).await?;
+            page_cache::truncate(&target_inode, 0).await?;
         }
 
         match attr.file_type {
             FileType::File => {
                 let mut open_file =
-                    OpenFile::new(Box::new(RegFile::new(target_inode.clone())), flags);
+                    OpenFile::new(Box::new(RegFile::open(target_inode.clone()).await?), flags);
                 open_file.update(target_inode, path.to_owned());
 
                 Ok(Arc::new(open_file))
@@ -398,7 +496,9 @@ impl VFS {
 
                 Ok(Arc::new(open_file))
             }
-            FileType::Symlink => unimplemented!(), // this is implemented at resolve_path_internal
+            // Symlinks are expanded by `resolve_path_internal`; the only way to
+            // land on one here is O_NOFOLLOW naming a link.
+            FileType::Symlink => Err(FsError::Loop.into()),
             FileType::BlockDevice(_) => todo!(),
             FileType::CharDevice(char_dev_descriptor) => {
                 let char_driver = DM
@@ -520,6 +620,10 @@ impl VFS {
 
         parent_inode.unlink(name).await?;
 
+        if attr.file_type == FileType::File && attr.nlinks <= 1 {
+            page_cache::forget(target_inode.id());
+        }
+
         Ok(())
     }
 
@@ -570,9 +674,21 @@ impl VFS {
         new_name: &str,
         no_replace: bool,
     ) -> Result<()> {
+        // A file that's replaced is deleted, just as if it were unlinked.
+        let replaced = match new_parent_inode.lookup(new_name).await {
+            Ok(inode) if !no_replace => Some(inode.id()),
+            _ => None,
+        };
+
         new_parent_inode
             .rename_from(old_parent_inode, old_name, new_name, no_replace)
-            .await
+            .await?;
+
+        if let Some(id) = replaced {
+            page_cache::forget(id);
+        }
+
+        Ok(())
     }
 
     pub async fn exchange(
@@ -629,12 +745,104 @@ impl VFS {
 
 #[cfg(test)]
 mod tests {
+    use crate::arch::ArchImpl;
     use crate::fs::VFS;
     use crate::ktest;
+    use crate::memory::{PageOffsetTranslator, page::PgAllocGetter};
+    use alloc::sync::Arc;
+    use core::sync::atomic::Ordering;
+    use libkernel::error::{FsError, KernelError};
+    use libkernel::fs::{
+        FileType, Filesystem, Inode, attr::FilePermissions, filesystems::tmpfs::TmpFs, path::Path,
+    };
 
     ktest! {
         async fn test_sync_all() {
             VFS.sync_all().await.unwrap();
         }
     }
+
+    fn new_tmpfs() -> Arc<dyn Filesystem> {
+        let id = VFS.next_fs_id.fetch_add(1, Ordering::SeqCst);
+
+        TmpFs::<ArchImpl, PgAllocGetter, PageOffsetTranslator>::new(id)
+    }
+
+    async fn tmpfs_root() -> Arc<dyn Inode> {
+        new_tmpfs().root_inode().await.unwrap()
+    }
+
+    ktest! {
+        async fn test_resolve_symlink_chain() {
+            let root = tmpfs_root().await;
+            let dir = root
+                .create("dir", FileType::Directory, FilePermissions::from_bits_retain(0o755))
+                .await
+                .unwrap();
+            let file = dir
+                .create("file", FileType::File, FilePermissions::from_bits_retain(0o644))
+                .await
+                .unwrap();
+
+            // hop1 -> hop2 -> dir/file, with an absolute second hop.
+            root.symlink("hop2", Path::new("/dir/file")).await.unwrap();
+            root.symlink("hop1", Path::new("hop2")).await.unwrap();
+            // Links in the middle of a path are always followed.
+            dir.symlink("up", Path::new("/dir")).await.unwrap();
+
+            let resolve = |path: &'static str, follow: bool| {
+                VFS.resolve_path_internal(Path::new(path), root.clone(), root.clone(), follow)
+            };
+
+            assert_eq!(resolve("hop1", true).await.unwrap().id(), file.id());
+            assert_eq!(resolve("dir/up/up/file", true).await.unwrap().id(), file.id());
+            assert_eq!(resolve("dir/up/up/file", false).await.unwrap().id(), file.id());
+
+            let last = resolve("hop1", false).await.unwrap();
+            assert_eq!(last.getattr().await.unwrap().file_type, FileType::Symlink);
+        }
+    }
+
+    ktest! {
+        async fn test_resolve_symlink_loop() {
+            let root = tmpfs_root().await;
+
+            root.symlink("a", Path::new("b")).await.unwrap();
+            root.symlink("b", Path::new("a")).await.unwrap();
+
+            let res = VFS
+                .resolve_path_internal(Path::new("a"), root.clone(), root.clone(), true)
+                .await;
+            assert!(matches!(res, Err(KernelError::Fs(FsError::Loop))));
+        }
+    }
+
+    ktest! {
+        async fn test_resolve_across_mount() {
+            let root = tmpfs_root().await;
+            let perms = FilePermissions::from_bits_retain(0o755);
+            let mnt = root.create("mnt", FileType::Directory, perms).await.unwrap();
+            let other = root.create("other", FileType::Directory, perms).await.unwrap();
+
+            let fs = new_tmpfs();
+            let mounted_root = fs.root_inode().await.unwrap();
+            let file = mounted_root.create("file", FileType::File, perms).await.unwrap();
+            VFS.attach(mnt, fs).await.unwrap();
+
+            let resolve = |path: &'static str| {
+                VFS.resolve_path_internal(Path::new(path), root.clone(), root.clone(), true)
+            };
+
+            // Descending into the mount point lands in the mounted filesystem.
+            assert_eq!(resolve("mnt").await.unwrap().id(), mounted_root.id());
+            assert_eq!(resolve("mnt/file").await.unwrap().id(), file.id());
+
+            // `..` at the mounted root goes back to the covering filesystem.
+            assert_eq!(resolve("mnt/../other").await.unwrap().id(), other.id());
+            assert_eq!(resolve("mnt/../mnt/file").await.unwrap().id(), file.id());
+
+            // `..` never climbs above the root.
+            assert_eq!(resolve("../../mnt/file").await.unwrap().id(), file.id());
+        }
+    }
 }
@@ -0,0 +1,550 @@
+//! A cache of file pages, keyed by inode and page-aligned file offset.
+//!
+//! Reads of files whose inodes are [`Inode::cacheable`] are served from here,
+//! copying straight from the cached page to userspace, and shared mappings of
+//! any file map the cached pages themselves (see [`crate::memory::shared`]).
+//! The cache holds a reference to each page, and every reader or mapping takes
+//! its own, so a page is only ever freed once nothing uses it.
+//!
+//! Writes and truncations go to the file and then update the cached pages in
+//! place. A read that raced with one may have read stale data from the file,
+//! so every write bumps a counter, and a read that sees it change throws its
+//! page away and tries again.
+//!
+//! Once the cache holds more than [`MAX_PAGES`], the least recently used pages
+//! that are clean and held by nothing else are dropped. When free memory runs
+//! low, [`PageCache::reclaim`] gives up more of them (see
+//! [`crate::memory::reclaim`]). Each page keeps a weak reference to its file,
+//! so that dirty pages nothing maps any more can be written back first.
+
+use crate::{
+    memory::{
+        PAGE_ALLOC, PageOffsetTranslator,
+        page::{ClaimedPage, free_user_page},
+    },
+    sync::SpinLock,
+};
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    cmp::{max, min},
+    ops::{Bound, RangeBounds},
+    slice,
+};
+use libkernel::{
+    error::Result,
+    fs::{Inode, InodeId},
+    memory::{PAGE_SIZE, page::PageFrame},
+};
+
+/// The most pages the cache keeps once they're no longer in use.
+pub const MAX_PAGES: usize = 1024;
+
+const PAGE_MASK: u64 = !(PAGE_SIZE as u64 - 1);
+
+type Key = (InodeId, u64);
+
+pub struct CachedPage {
+    pub pfn: PageFrame,
+    /// The file the page belongs to, for writing it back.
+    pub inode: Weak<dyn Inode>,
+    /// Set when the page is written through a mapping, and cleared when it's
+    /// taken to be written back.
+    pub dirty: bool,
+    last_use: u64,
+    /// Set whenever the page is used, and cleared as the reclaim clock hand
+    /// passes it.
+    referenced: bool,
+}
+
+pub struct PageCache {
+    pages: BTreeMap<Key, CachedPage>,
+    /// The key of every cached page by when it was last used, oldest first.
+    lru: BTreeMap<u64, Key>,
+    next_use: u64,
+    /// Bumped by every write, truncation and invalidation.
+    writes: u64,
+    /// The last page the reclaim clock hand visited.
+    hand: Option<Key>,
+}
+
+impl PageCache {
+    const fn new() -> Self {
+        Self {
+            pages: BTreeMap::new(),
+            lru: BTreeMap::new(),
+            next_use: 0,
+            writes: 0,
+            hand: None,
+        }
+    }
+
+    fn touch(&mut self) -> u64 {
+        self.next_use += 1;
+        self.next_use
+    }
+
+    /// Looks up a cached page, counting it as just used.
+    pub fn get_mut(&mut self, key: &Key) -> Option<&mut CachedPage> {
+        let last_use = self.touch();
+        let page = self.pages.get_mut(key)?;
+
+        self.lru.remove(&page.last_use);
+        self.lru.insert(last_use, *key);
+        page.last_use = last_use;
+        page.referenced = true;
+
+        Some(page)
+    }
+
+    pub fn range(&self, range: impl RangeBounds<Key>) -> impl Iterator<Item = (&Key, &CachedPage)> {
+        self.pages.range(range)
+    }
+
+    pub fn range_mut(
+        &mut self,
+        range: impl RangeBounds<Key>,
+    ) -> impl Iterator<Item = (&Key, &mut CachedPage)> {
+        self.pages.range_mut(range)
+    }
+
+    /// Adds the page `pfn` of `inode`, handing over the caller's reference to
+    /// it.
+    fn insert(&mut self, key: Key, inode: Weak<dyn Inode>, pfn: PageFrame) -> &mut CachedPage {
+        // Make room first, so the new page can't be the one dropped.
+        self.shrink(MAX_PAGES - 1);
+
+        let last_use = self.touch();
+        self.lru.insert(last_use, key);
+
+        self.pages.entry(key).or_insert(CachedPage {
+            pfn,
+            inode,
+            dirty: false,
+            last_use,
+            referenced: true,
+        })
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if let Some(page) = self.pages.remove(key) {
+            self.lru.remove(&page.last_use);
+            unsafe { free_user_page(page.pfn) };
+        }
+    }
+
+    /// Whether the page is clean and held by nothing but the cache.
+    fn unused(page: &CachedPage) -> bool {
+        !page.dirty && PAGE_ALLOC.get().unwrap().is_allocated_exclusive(page.pfn)
+    }
+
+    /// Drops the least recently used unused pages until at most `limit` are
+    /// cached, or there are none left to drop.
+    fn shrink(&mut self, limit: usize) {
+        let excess = self.pages.len().saturating_sub(limit);
+
+        if excess == 0 {
+            return;
+        }
+
+        let victims: Vec<_> = self
+            .lru
+            .values()
+            .filter(|key| Self::unused(&self.pages[*key]))
+            .take(excess)
+            .copied()
+            .collect();
+
+        for key in victims {
+            self.remove(&key);
+        }
+    }
+
+    /// Drops up to `target` unused pages by the clock algorithm, returning how
+    /// many were dropped.
+    ///
+    /// The hand sweeps round the pages in key order, picking up where it last
+    /// stopped. A page used since the hand last passed gets a second chance:
+    /// it's marked unreferenced and passed over. Pages that are mapped or
+    /// otherwise in use are skipped, as are dirty ones until they've been
+    /// written back (see [`crate::memory::shared::take_unmapped_dirty`]).
+    pub fn reclaim(&mut self, target: usize) -> usize {
+        let mut freed = 0;
+        // Two turns, as the first may only clear reference bits.
+        let mut steps = 2 * self.pages.len();
+
+        while freed < target && steps > 0 {
+            steps -= 1;
+
+            let next = self
+                .hand
+                .and_then(|hand| {
+                    self.pages
+                        .range((Bound::Excluded(hand), Bound::Unbounded))
+                        .next()
+                })
+                .or_else(|| self.pages.iter().next())
+                .map(|(key, _)| *key);
+
+            let Some(key) = next else {
+                break;
+            };
+
+            self.hand = Some(key);
+
+            let page = self.pages.get_mut(&key).unwrap();
+
+            if page.referenced {
+                page.referenced = false;
+            } else if Self::unused(page) {
+                self.remove(&key);
+                freed += 1;
+            }
+        }
+
+        freed
+    }
+
+    /// Drops the unused pages within `range`. Pages that are still mapped or
+    /// dirty stay.
+    pub fn discard(&mut self, range: impl RangeBounds<Key>) {
+        let unused: Vec<_> = self
+            .pages
+            .range(range)
+            .filter(|(_, page)| Self::unused(page))
+            .map(|(key, _)| *key)
+            .collect();
+
+        for key in unused {
+            self.remove(&key);
+        }
+    }
+}
+
+pub static PAGE_CACHE: SpinLock<PageCache> = SpinLock::new(PageCache::new());
+
+/// Takes another reference to the page at `pfn`.
+///
+/// # Safety
+///
+/// The caller must ensure the page can't be freed whilst the reference is
+/// taken, e.g. by holding the cache lock whilst the page is in the cache.
+pub unsafe fn page_ref(pfn: PageFrame) -> PageFrame {
+    let alloc = unsafe {
+        PAGE_ALLOC
+            .get()
+            .unwrap()
+            .alloc_from_region(pfn.as_phys_range())
+    };
+
+    alloc.clone().leak();
+    alloc.leak().start_address().to_pfn()
+}
+
+/// The contents of the page at `pfn`.
+///
+/// # Safety
+///
+/// The caller must hold a reference to the page for as long as the slice is
+/// used.
+unsafe fn contents<'a>(pfn: PageFrame) -> &'a mut [u8] {
+    unsafe {
+        slice::from_raw_parts_mut(
+            pfn.pa().to_va::<PageOffsetTranslator>().as_ptr_mut() as *mut u8,
+            PAGE_SIZE,
+        )
+    }
+}
+
+/// Returns the page at `offset`, which must be page aligned, of `inode`, with a
+/// reference taken on behalf of the caller. The page is read in if it isn't
+/// cached yet. `dirty` marks it as about to be written through a mapping.
+pub async fn get_page(inode: &Arc<dyn Inode>, offset: u64, dirty: bool) -> Result<PageFrame> {
+    let key = (inode.id(), offset);
+
+    loop {
+        let writes = {
+            let mut cache = PAGE_CACHE.lock_save_irq();
+
+            if let Some(page) = cache.get_mut(&key) {
+                page.dirty |= dirty;
+                return Ok(unsafe { page_ref(page.pfn) });
+            }
+
+            cache.writes
+        };
+
+        // Anything past the end of the file reads as zeros.
+        let mut page = ClaimedPage::alloc_zeroed()?;
+        inode.read_at(offset, page.as_slice_mut()).await?;
+
+        let mut cache = PAGE_CACHE.lock_save_irq();
+
+        // Another reader may have read the page in whilst we slept, in which
+        // case ours is dropped in favour of theirs.
+        if let Some(cached) = cache.get_mut(&key) {
+            cached.dirty |= dirty;
+            return Ok(unsafe { page_ref(cached.pfn) });
+        }
+
+        // The file was written whilst we slept, so what we read may be stale.
+        if cache.writes != writes {
+            continue;
+        }
+
+        let cached = cache.insert(key, Arc::downgrade(inode), page.leak());
+        cached.dirty |= dirty;
+
+        return Ok(unsafe { page_ref(cached.pfn) });
+    }
+}
+
+/// Writes `buf` to `inode` at `offset`, then copies what was written into any
+/// cached pages it covers.
+pub async fn write(inode: &Arc<dyn Inode>, offset: u64, buf: &[u8]) -> Result<usize> {
+    let res = inode.write_at(offset, buf).await;
+    let id = inode.id();
+    let mut cache = PAGE_CACHE.lock_save_irq();
+
+    cache.writes += 1;
+
+    let written = match res {
+        Ok(written) => written,
+        Err(e) => {
+            // We can't tell how much made it to the file.
+            cache.discard((id, offset & PAGE_MASK)..(id, offset + buf.len() as u64));
+            return Err(e);
+        }
+    };
+
+    let end = offset + written as u64;
+
+    for (&(_, page_offset), page) in cache.range_mut((id, offset & PAGE_MASK)..(id, end)) {
+        let from = max(page_offset, offset);
+        let to = min(page_offset + PAGE_SIZE as u64, end);
+
+        let data = unsafe { contents(page.pfn) };
+
+        data[(from - page_offset) as usize..(to - page_offset) as usize]
+            .copy_from_slice(&buf[(from - offset) as usize..(to - offset) as usize]);
+    }
+
+    Ok(written)
+}
+
+/// Truncates `inode` to `size`, then zeroes the cached data past the new end.
+pub async fn truncate(inode: &Arc<dyn Inode>, size: u64) -> Result<()> {
+    let res = inode.truncate(size).await;
+    let id = inode.id();
+    let mut cache = PAGE_CACHE.lock_save_irq();
+
+    cache.writes += 1;
+
+    let tail = (size % PAGE_SIZE as u64) as usize;
+
+    if tail != 0
+        && let Some(page) = cache.pages.get(&(id, size & PAGE_MASK))
+    {
+        let data = unsafe { contents(page.pfn) };
+        data[tail..].fill(0);
+    }
+
+    cache.discard((id, size.next_multiple_of(PAGE_SIZE as u64))..=(id, u64::MAX));
+
+    res
+}
+
+/// Drops the cached pages of the file `id`, which has been deleted, so they
+/// can't be mistaken for those of a new file that's given the same ID.
+///
+/// Dirty and mapped pages go too: there's no file left to write them back to,
+/// and whatever maps them holds its own reference.
+pub fn forget(id: InodeId) {
+    let mut cache = PAGE_CACHE.lock_save_irq();
+
+    cache.writes += 1;
+
+    let keys: Vec<_> = cache
+        .pages
+        .range((id, 0)..=(id, u64::MAX))
+        .map(|(key, _)| *key)
+        .collect();
+
+    for key in keys {
+        cache.remove(&key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ktest;
+    use alloc::{boxed::Box, vec};
+    use async_trait::async_trait;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use libkernel::fs::attr::FileAttr;
+
+    /// A file that's read from memory, counting how often it's read.
+    struct CountingInode {
+        data: SpinLock<Vec<u8>>,
+        reads: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Inode for CountingInode {
+        fn id(&self) -> InodeId {
+            InodeId::from_fsid_and_inodeid(u64::MAX, 2)
+        }
+
+        fn cacheable(&self) -> bool {
+            true
+        }
+
+        async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+
+            let data = self.data.lock_save_irq();
+            let len = min(buf.len(), data.len().saturating_sub(offset as usize));
+
+            buf[..len].copy_from_slice(&data[offset as usize..][..len]);
+
+            Ok(len)
+        }
+
+        async fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize> {
+            self.data.lock_save_irq()[offset as usize..][..buf.len()].copy_from_slice(buf);
+
+            Ok(buf.len())
+        }
+
+        async fn truncate(&self, size: u64) -> Result<()> {
+            self.data.lock_save_irq().resize(size as usize, 0);
+
+            Ok(())
+        }
+
+        async fn getattr(&self) -> Result<FileAttr> {
+            Ok(FileAttr {
+                size: self.data.lock_save_irq().len() as u64,
+                ..Default::default()
+            })
+        }
+    }
+
+    /// Reads the page at `offset` through the cache.
+    async fn read_page(inode: &Arc<dyn Inode>, offset: u64) -> Vec<u8> {
+        let page = unsafe { ClaimedPage::from_pfn(get_page(inode, offset, false).await.unwrap()) };
+        let data = page.as_slice().to_vec();
+
+        unsafe { free_user_page(page.leak()) };
+
+        data
+    }
+
+    ktest! {
+        async fn page_cache_serves_repeated_reads() {
+            let file = Arc::new(CountingInode {
+                data: SpinLock::new(vec![0xaa; 2 * PAGE_SIZE]),
+                reads: AtomicUsize::new(0),
+            });
+            let inode: Arc<dyn Inode> = file.clone();
+
+            // Only the first read goes to the file.
+            assert!(read_page(&inode, 0).await.iter().all(|b| *b == 0xaa));
+            assert!(read_page(&inode, 0).await.iter().all(|b| *b == 0xaa));
+            assert_eq!(file.reads.load(Ordering::Relaxed), 1);
+
+            // Writes update the cached page rather than dropping it.
+            write(&inode, 10, &[0x55; 4]).await.unwrap();
+            let data = read_page(&inode, 0).await;
+            assert_eq!(data[9..15], [0xaa, 0x55, 0x55, 0x55, 0x55, 0xaa]);
+            assert_eq!(file.reads.load(Ordering::Relaxed), 1);
+
+            // Truncating zeroes what's past the new end.
+            truncate(&inode, 12).await.unwrap();
+            let data = read_page(&inode, 0).await;
+            assert!(data[..10].iter().all(|b| *b == 0xaa));
+            assert_eq!(data[10..12], [0x55; 2]);
+            assert!(data[12..].iter().all(|b| *b == 0));
+            assert_eq!(file.reads.load(Ordering::Relaxed), 1);
+
+            // Once forgotten, the page is read from the file again.
+            forget(inode.id());
+            read_page(&inode, 0).await;
+            assert_eq!(file.reads.load(Ordering::Relaxed), 2);
+
+            forget(inode.id());
+        }
+    }
+
+    ktest! {
+        async fn forget_drops_mapped_pages() {
+            let old: Arc<dyn Inode> = Arc::new(CountingInode {
+                data: SpinLock::new(vec![0xaa; PAGE_SIZE]),
+                reads: AtomicUsize::new(0),
+            });
+            let id = old.id();
+
+            // The deleted file is still mapped, and dirtied through the mapping.
+            let mapped = get_page(&old, 0, true).await.unwrap();
+            forget(id);
+            assert!(PAGE_CACHE.lock_save_irq().range((id, 0)..=(id, u64::MAX)).next().is_none());
+
+            // A new file given the same ID reads its own data.
+            let new: Arc<dyn Inode> = Arc::new(CountingInode {
+                data: SpinLock::new(vec![0x55; PAGE_SIZE]),
+                reads: AtomicUsize::new(0),
+            });
+            assert_eq!(new.id(), id);
+            assert!(read_page(&new, 0).await.iter().all(|b| *b == 0x55));
+
+            // The mapping keeps the old page.
+            assert!(unsafe { contents(mapped) }.iter().all(|b| *b == 0xaa));
+
+            unsafe { free_user_page(mapped) };
+            forget(id);
+        }
+    }
+
+    ktest! {
+        async fn reclaim_drops_clean_unused_pages() {
+            let inode: Arc<dyn Inode> = Arc::new(CountingInode {
+                data: SpinLock::new(vec![0xaa; 4 * PAGE_SIZE]),
+                reads: AtomicUsize::new(0),
+            });
+            let id = inode.id();
+            let page_alloc = PAGE_ALLOC.get().unwrap();
+
+            for n in 0..4 {
+                read_page(&inode, (n * PAGE_SIZE) as u64).await;
+            }
+
+            // The first page stays in use and the second is left dirty.
+            let held = get_page(&inode, 0, false).await.unwrap();
+            let dirty = get_page(&inode, PAGE_SIZE as u64, true).await.unwrap();
+            unsafe { free_user_page(dirty) };
+
+            let baseline = page_alloc.free_pages();
+
+            // Within two turns of the hand, every clean unused page goes.
+            assert!(PAGE_CACHE.lock_save_irq().reclaim(usize::MAX) >= 2);
+            assert!(page_alloc.free_pages() >= baseline + 2);
+
+            let mut cache = PAGE_CACHE.lock_save_irq();
+            let left: Vec<_> = cache
+                .range((id, 0)..=(id, u64::MAX))
+                .map(|(&(_, offset), _)| offset)
+                .collect();
+            assert_eq!(left, [0, PAGE_SIZE as u64]);
+
+            cache.get_mut(&(id, PAGE_SIZE as u64)).unwrap().dirty = false;
+            drop(cache);
+
+            unsafe { free_user_page(held) };
+            forget(id);
+        }
+    }
+}
@@ -13,11 +13,12 @@ use alloc::{boxed::Box, sync::Arc};
 use async_trait::async_trait;
 use core::{
     future,
-    pin::pin,
+    pin::{Pin, pin},
     sync::atomic::{AtomicU64, Ordering},
     task::Poll,
     time::Duration,
 };
+use futures::future::select;
 use libkernel::{
     error::{KernelError, Result},
     fs::{
@@ -76,7 +77,24 @@ struct PipeInner {
     other_side_gone: CondVar<bool>,
 }
 
-impl PipeInner {}
+impl PipeInner {
+    /// Resolves once `ready` does, or the other end of the pipe is closed;
+    /// either way, the next read or write won't block.
+    fn poll_ready(
+        &self,
+        ready: impl Future<Output = ()> + Send + 'static,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        let gone_fut = self
+            .other_side_gone
+            .wait_until(|gone| if *gone { Some(()) } else { None });
+
+        Box::pin(async move {
+            select(pin!(ready), pin!(gone_fut)).await;
+
+            Ok(())
+        })
+    }
+}
 
 struct PipeReader {
     inner: PipeInner,
@@ -149,6 +167,10 @@ impl FileOps for PipeReader {
         self.do_read(async { Ok(kbuf.splice_from(&self.inner.buf, count).await) })
             .await
     }
+
+    fn poll_read_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        self.inner.poll_ready(self.inner.buf.read_ready())
+    }
 }
 
 impl Drop for PipeReader {
@@ -228,6 +250,13 @@ impl FileOps for PipeWriter {
         self.do_write(async { Ok(self.inner.buf.splice_from(kbuf, count).await) })
             .await
     }
+
+    fn poll_write_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        let buf = self.inner.buf.clone();
+
+        self.inner
+            .poll_ready(async move { buf.write_ready().await })
+    }
 }
 
 impl Drop for PipeWriter {
@@ -279,8 +308,8 @@ pub async fn sys_pipe2(fds: TUA<[Fd; 2]>, flags: u32) -> Result<usize> {
         read_file.update(inode.clone(), PathBuf::new());
         write_file.update(inode, PathBuf::new());
 
-        let read_fd = fds.insert(Arc::new(read_file))?;
-        let write_fd = fds.insert(Arc::new(write_file))?;
+        let read_fd = fds.insert_with_flags(Arc::new(read_file), flags.into())?;
+        let write_fd = fds.insert_with_flags(Arc::new(write_file), flags.into())?;
 
         (read_fd, write_fd)
     };
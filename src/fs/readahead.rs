@@ -0,0 +1,173 @@
+//! Readahead for sequential reads through the page cache.
+//!
+//! Each open file tracks where its last read ended. A read that carries on from
+//! there, or starts at the beginning of the file, is taken as streaming, and
+//! the pages up to a window past it are read into the cache by a kernel thread
+//! while the reader carries on with what it asked for. Any other read resets
+//! the window.
+//!
+//! The window is topped up once the reader is half way through it, so pages
+//! are fetched in batches rather than one per read, and it's capped at a
+//! fraction of the cache so prefetched pages can't push out everything else.
+
+use super::page_cache::{self, MAX_PAGES};
+use crate::{
+    memory::page::free_user_page,
+    process::kthread::{KThreadHandle, spawn_kthread},
+};
+use alloc::sync::Arc;
+use core::{
+    cmp::{max, min},
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use libkernel::{error::Result, fs::Inode, memory::PAGE_SIZE};
+
+/// How many pages are read ahead of a streaming reader. Set by the
+/// `--readahead` option; zero turns readahead off.
+pub static READAHEAD_PAGES: AtomicUsize = AtomicUsize::new(32);
+
+/// The readahead window in bytes.
+fn window() -> u64 {
+    let pages = min(READAHEAD_PAGES.load(Ordering::Relaxed), MAX_PAGES / 4);
+
+    (pages * PAGE_SIZE) as u64
+}
+
+/// The readahead state of an open file.
+#[derive(Default)]
+pub struct Readahead {
+    /// Where the next read starts if the file is being read sequentially.
+    next: u64,
+    /// The end of what has been prefetched so far.
+    ahead: u64,
+}
+
+impl Readahead {
+    /// Records a read of `offset..end` of a file `size` bytes long, and returns
+    /// the page-aligned range to prefetch, if it's time to.
+    pub fn advance(&mut self, offset: u64, end: u64, size: u64) -> Option<Range<u64>> {
+        let sequential = offset == self.next;
+
+        self.next = end;
+
+        if !sequential {
+            self.ahead = 0;
+
+            // A read from the start of the file starts a stream too.
+            if offset != 0 {
+                return None;
+            }
+        }
+
+        let window = window();
+
+        if window == 0 || self.ahead >= end + window / 2 {
+            return None;
+        }
+
+        // The page holding `end` is read in by the read itself.
+        let start = max(self.ahead, end.next_multiple_of(PAGE_SIZE as u64));
+        let stop = min(end + window, size).next_multiple_of(PAGE_SIZE as u64);
+
+        if start >= stop {
+            return None;
+        }
+
+        self.ahead = stop;
+
+        Some(start..stop)
+    }
+}
+
+/// Reads the pages of `inode` in `range` into the page cache, stopping at the
+/// first that can't be read.
+async fn prefetch(inode: Arc<dyn Inode>, range: Range<u64>) {
+    for offset in range.step_by(PAGE_SIZE) {
+        match page_cache::get_page(&inode, offset, false).await {
+            Ok(pfn) => unsafe { free_user_page(pfn) },
+            Err(_) => break,
+        }
+    }
+}
+
+/// Starts a kernel thread prefetching `range` of `inode`.
+pub fn spawn_readahead(inode: Arc<dyn Inode>, range: Range<u64>) -> Result<KThreadHandle> {
+    spawn_kthread("readahead", prefetch(inode, range))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fs::page_cache::PAGE_CACHE, ktest};
+    use alloc::boxed::Box;
+    use async_trait::async_trait;
+    use libkernel::fs::{InodeId, attr::FileAttr};
+
+    const PG: u64 = PAGE_SIZE as u64;
+
+    /// A file of `size` zero bytes.
+    struct ZeroInode {
+        size: u64,
+    }
+
+    #[async_trait]
+    impl Inode for ZeroInode {
+        fn id(&self) -> InodeId {
+            InodeId::from_fsid_and_inodeid(u64::MAX, 3)
+        }
+
+        fn cacheable(&self) -> bool {
+            true
+        }
+
+        async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+            let len = min(buf.len() as u64, self.size.saturating_sub(offset)) as usize;
+
+            buf[..len].fill(0);
+
+            Ok(len)
+        }
+
+        async fn getattr(&self) -> Result<FileAttr> {
+            Ok(FileAttr {
+                size: self.size,
+                ..Default::default()
+            })
+        }
+    }
+
+    fn cached(id: InodeId, range: Range<u64>) -> usize {
+        PAGE_CACHE
+            .lock_save_irq()
+            .range((id, range.start)..(id, range.end))
+            .count()
+    }
+
+    ktest! {
+        async fn sequential_reads_prefetch_ahead() {
+            let inode: Arc<dyn Inode> = Arc::new(ZeroInode { size: 64 * PG });
+            let id = inode.id();
+            let mut ra = Readahead::default();
+
+            // The first read of the file starts a window past it, which is
+            // read into the cache in the background.
+            let range = ra.advance(0, PG, 64 * PG).unwrap();
+            assert_eq!(range, PG..33 * PG);
+            spawn_readahead(inode.clone(), range).unwrap().join().await;
+            assert_eq!(cached(id, 0..64 * PG), 32);
+            assert_eq!(cached(id, PG..33 * PG), 32);
+
+            // Nothing more is fetched until the reader is half way through.
+            assert_eq!(ra.advance(PG, 2 * PG, 64 * PG), None);
+            assert_eq!(ra.advance(2 * PG, 20 * PG, 64 * PG), Some(33 * PG..52 * PG));
+
+            // A seek resets the window, and it never runs past the end of the
+            // file.
+            assert_eq!(ra.advance(50 * PG, 51 * PG, 64 * PG), None);
+            assert_eq!(ra.advance(51 * PG, 52 * PG, 64 * PG), Some(52 * PG..64 * PG));
+
+            page_cache::forget(id);
+        }
+    }
+}
@@ -1,17 +1,22 @@
-use super::{fops::FileOps, open_file::FileCtx};
+use super::{
+    fops::FileOps,
+    open_file::FileCtx,
+    page_cache,
+    readahead::{Readahead, spawn_readahead},
+};
 use crate::{
     kernel::kpipe::KPipe,
     memory::{
-        page::ClaimedPage,
+        page::{ClaimedPage, free_user_page},
         uaccess::{copy_from_user_slice, copy_to_user_slice},
     },
 };
-use alloc::{boxed::Box, sync::Arc};
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
 use async_trait::async_trait;
 use core::{cmp::min, pin::Pin};
 use libkernel::{
     error::Result,
-    fs::{Inode, SeekFrom},
+    fs::{Inode, InodeId, SeekFrom, attr::FileAttr},
     memory::{PAGE_SIZE, address::UA},
 };
 
@@ -19,11 +24,90 @@ const SPLICE_BUF_SZ: usize = 32;
 
 pub struct RegFile {
     inode: Arc<dyn Inode>,
+    readahead: Readahead,
 }
 
 impl RegFile {
     pub fn new(inode: Arc<dyn Inode>) -> Self {
-        Self { inode }
+        Self {
+            inode,
+            readahead: Readahead::default(),
+        }
+    }
+
+    /// Creates a `RegFile` for a newly opened `inode`. Generated files are
+    /// snapshotted here, so reads see their contents as of open time.
+    pub async fn open(inode: Arc<dyn Inode>) -> Result<Self> {
+        Ok(match inode.snapshot().await? {
+            Some(data) => Self::new(Arc::new(SnapshotInode { inode, data })),
+            None => Self::new(inode),
+        })
+    }
+
+    /// Reads through the page cache, copying straight from the cached pages to
+    /// `user_buf`.
+    async fn read_cached(
+        &mut self,
+        mut user_buf: UA,
+        count: usize,
+        mut offset: u64,
+    ) -> Result<usize> {
+        let size = self.inode.getattr().await?.size;
+        let end = min(offset.saturating_add(count as u64), size).max(offset);
+        let mut total_bytes_read = 0;
+
+        if let Some(range) = self.readahead.advance(offset, end, size) {
+            // Readahead is only ever an optimisation, so the read carries on
+            // without it if the thread can't be started.
+            let _ = spawn_readahead(self.inode.clone(), range);
+        }
+
+        while offset < end {
+            let page_offset = offset & !(PAGE_SIZE as u64 - 1);
+            let start = (offset - page_offset) as usize;
+            let len = min(PAGE_SIZE - start, (end - offset) as usize);
+
+            let pfn = page_cache::get_page(&self.inode, page_offset, false).await?;
+            let page = unsafe { ClaimedPage::from_pfn(pfn) };
+            let res = copy_to_user_slice(&page.as_slice()[start..start + len], user_buf).await;
+
+            unsafe { free_user_page(page.leak()) };
+            res?;
+
+            offset += len as u64;
+            total_bytes_read += len;
+            user_buf = user_buf.add_bytes(len);
+        }
+
+        Ok(total_bytes_read)
+    }
+}
+
+/// The contents of a generated file, captured when it was opened.
+struct SnapshotInode {
+    inode: Arc<dyn Inode>,
+    data: Vec<u8>,
+}
+
+#[async_trait]
+impl Inode for SnapshotInode {
+    fn id(&self) -> InodeId {
+        self.inode.id()
+    }
+
+    async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let data = self.data.get(offset as usize..).unwrap_or_default();
+        let len = min(data.len(), buf.len());
+
+        buf[..len].copy_from_slice(&data[..len]);
+
+        Ok(len)
+    }
+
+    async fn getattr(&self) -> Result<FileAttr> {
+        let mut attr = self.inode.getattr().await?;
+        attr.size = self.data.len() as _;
+        Ok(attr)
     }
 }
 
@@ -37,6 +121,10 @@ impl FileOps for RegFile {
         mut count: usize,
         mut offset: u64,
     ) -> Result<usize> {
+        if self.inode.cacheable() {
+            return self.read_cached(user_buf, count, offset).await;
+        }
+
         let mut pg = ClaimedPage::alloc_zeroed()?;
         let kbuf = pg.as_slice_mut();
         let mut total_bytes_read = 0;
@@ -74,7 +162,7 @@ impl FileOps for RegFile {
 
             copy_from_user_slice(buf, &mut kbuf[..chunk_sz]).await?;
 
-            let bytes_written = self.inode.write_at(offset, &kbuf[..chunk_sz]).await?;
+            let bytes_written = page_cache::write(&self.inode, offset, &kbuf[..chunk_sz]).await?;
 
             // If we wrote 0 bytes, the disk might be full or the file cannot be
             // extended.
@@ -92,7 +180,7 @@ impl FileOps for RegFile {
     }
 
     async fn truncate(&mut self, _ctx: &FileCtx, new_size: usize) -> Result<()> {
-        self.inode.truncate(new_size as _).await
+        page_cache::truncate(&self.inode, new_size as _).await
     }
 
     fn poll_read_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
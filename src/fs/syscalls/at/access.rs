@@ -5,7 +5,7 @@ use crate::{
 };
 use core::ffi::c_char;
 use libkernel::{
-    error::Result,
+    error::{FsError, KernelError, Result},
     fs::{attr::AccessMode, path::Path},
     memory::address::TUA,
 };
@@ -18,9 +18,17 @@ pub async fn sys_faccessat2(dirfd: Fd, path: TUA<c_char>, mode: i32, flags: i32)
     let mut buf = [0; 1024];
 
     let task = current_task_shared();
-    let access_mode = AccessMode::from_bits_retain(mode);
-    let path = Path::new(UserCStr::from_ptr(path).copy_from_user(&mut buf).await?);
+    let access_mode = AccessMode::from_bits(mode).ok_or(KernelError::InvalidValue)?;
     let at_flags = AtFlags::from_bits_retain(flags);
+
+    if !at_flags
+        .difference(AtFlags::AT_EACCESS | AtFlags::AT_SYMLINK_NOFOLLOW | AtFlags::AT_EMPTY_PATH)
+        .is_empty()
+    {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let path = Path::new(UserCStr::from_ptr(path).copy_from_user(&mut buf).await?);
     let start_node = resolve_at_start_node(dirfd, path, at_flags).await?;
     let node = resolve_path_flags(dirfd, path, start_node, &task, at_flags).await?;
 
@@ -41,7 +49,10 @@ pub async fn sys_faccessat2(dirfd: Fd, path: TUA<c_char>, mode: i32, flags: i32)
         (creds.uid(), creds.gid())
     };
 
-    attrs
-        .check_access(uid, gid, creds.caps(), access_mode)
-        .map(|_| 0)
+    // access(2) reports a failed check as EACCES rather than EPERM.
+    match attrs.check_access(uid, gid, creds.caps(), access_mode) {
+        Ok(()) => Ok(0),
+        Err(KernelError::NotPermitted) => Err(FsError::PermissionDenied.into()),
+        Err(e) => Err(e),
+    }
 }
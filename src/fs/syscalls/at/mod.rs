@@ -58,7 +58,9 @@ async fn resolve_at_start_node(dirfd: Fd, path: &Path, flags: AtFlags) -> Result
             .get(dirfd)
             .ok_or(KernelError::BadFd)?;
 
-        let inode = file.inode().ok_or(KernelError::NotSupported)?;
+        // Pipes, sockets and the like have no backing inode and so can never
+        // be a directory.
+        let inode = file.inode().ok_or(FsError::NotADirectory)?;
 
         if inode.getattr().await?.file_type != FileType::Directory {
             return Err(FsError::NotADirectory.into());
@@ -24,7 +24,10 @@ pub async fn sys_openat(dirfd: Fd, path: TUA<c_char>, flags: u32, mode: u16) ->
 
     let file = VFS.open(path, flags, start_node, mode, &task).await?;
 
-    let fd = task.fd_table.lock_save_irq().insert(file)?;
+    let fd = task
+        .fd_table
+        .lock_save_irq()
+        .insert_with_flags(file, flags.into())?;
 
     Ok(fd.as_raw() as _)
 }
@@ -6,7 +6,7 @@ use crate::{
 };
 use core::ffi::c_char;
 use libkernel::{
-    error::{KernelError, Result},
+    error::Result,
     fs::{attr::FileAttr, path::Path},
     memory::address::TUA,
 };
@@ -54,7 +54,7 @@ impl From<FileAttr> for Stat {
             st_size: value.size as _,
             st_blksize: value.block_size as _,
             __pad2: 0,
-            st_blocks: 0,
+            st_blocks: value.blocks as _,
             st_atime: value.atime.as_secs() as _,
             st_atime_nsec: value.atime.subsec_nanos() as _,
             st_mtime: value.mtime.as_secs() as _,
@@ -79,11 +79,7 @@ pub async fn sys_newfstatat(
     let flags = AtFlags::from_bits_truncate(flags);
     let path = Path::new(UserCStr::from_ptr(path).copy_from_user(&mut buf).await?);
 
-    let start_node = match resolve_at_start_node(dirfd, path, flags).await {
-        Ok(node) => node,
-        Err(err) if err != KernelError::NotSupported => panic!("{err}"),
-        Err(err) => return Err(err),
-    };
+    let start_node = resolve_at_start_node(dirfd, path, flags).await?;
     let node = resolve_path_flags(dirfd, path, start_node, &task, flags).await?;
 
     let attr = node.getattr().await?;
@@ -1,44 +1,68 @@
 use crate::{
     fs::VFS,
     memory::uaccess::{copy_to_user_slice, cstr::UserCStr},
-    process::fd_table::Fd,
+    process::{Task, fd_table::Fd},
     sched::current::current_task_shared,
 };
-use alloc::{borrow::ToOwned, ffi::CString, string::ToString};
+use alloc::{borrow::ToOwned, ffi::CString, string::ToString, sync::Arc};
 use core::{ffi::c_char, str::FromStr};
 use libkernel::{
-    error::{KernelError, Result},
-    fs::path::Path,
+    error::{FsError, KernelError, Result},
+    fs::{FileType, Inode, path::Path},
     memory::address::{TUA, UA},
     proc::caps::CapabilitiesFlags,
 };
 
-pub async fn sys_getcwd(buf: UA, len: usize) -> Result<usize> {
-    let task = current_task_shared();
+/// Returns the task's cwd as a C string, provided it fits in `len` bytes.
+fn cwd_cstring(task: &Task, len: usize) -> Result<CString> {
     let path = task.cwd.lock_save_irq().1.as_str().to_string();
     let cstr = CString::from_str(&path).map_err(|_| KernelError::InvalidValue)?;
-    let slice = cstr.as_bytes_with_nul();
 
-    if slice.len() > len {
-        return Err(KernelError::TooLarge);
+    if cstr.as_bytes_with_nul().len() > len {
+        return Err(KernelError::NameTooLong);
     }
 
-    copy_to_user_slice(slice, buf).await?;
+    Ok(cstr)
+}
+
+pub async fn sys_getcwd(buf: UA, len: usize) -> Result<usize> {
+    let task = current_task_shared();
+    let cstr = cwd_cstring(&task, len)?;
+
+    copy_to_user_slice(cstr.as_bytes_with_nul(), buf).await?;
 
     Ok(buf.value())
 }
 
+/// Fails with `NotADirectory` unless `inode` is a directory.
+async fn check_is_dir(inode: &Arc<dyn Inode>) -> Result<()> {
+    if inode.getattr().await?.file_type != FileType::Directory {
+        return Err(FsError::NotADirectory.into());
+    }
+
+    Ok(())
+}
+
+/// Changes `task`'s cwd to `path`, resolved from its current cwd. The path
+/// string is kept normalised so it can be handed back by `getcwd`.
+async fn chdir(task: &Arc<Task>, path: &Path) -> Result<()> {
+    let (current_node, current_path) = task.cwd.lock_save_irq().clone();
+    let new_path = current_path.join(path).normalize();
+
+    let node = VFS.resolve_path(path, current_node, task).await?;
+    check_is_dir(&node).await?;
+
+    *task.cwd.lock_save_irq() = (node, new_path);
+
+    Ok(())
+}
+
 pub async fn sys_chdir(path: TUA<c_char>) -> Result<usize> {
     let mut buf = [0; 1024];
 
     let path = Path::new(UserCStr::from_ptr(path).copy_from_user(&mut buf).await?);
-    let task = current_task_shared();
-    let current_path = task.cwd.lock_save_irq().0.clone();
-    let new_path = task.cwd.lock_save_irq().1.join(path);
-
-    let node = VFS.resolve_path(path, current_path, &task).await?;
 
-    *task.cwd.lock_save_irq() = (node, new_path);
+    chdir(&current_task_shared(), path).await?;
 
     Ok(0)
 }
@@ -54,7 +78,7 @@ pub async fn sys_chroot(path: TUA<c_char>) -> Result<usize> {
 
     let path = Path::new(UserCStr::from_ptr(path).copy_from_user(&mut buf).await?);
     let current_path = task.root.lock_save_irq().0.clone();
-    let new_path = task.root.lock_save_irq().1.join(path);
+    let new_path = task.root.lock_save_irq().1.join(path).normalize();
 
     let node = VFS.resolve_path(path, current_path, &task).await?;
 
@@ -71,10 +95,49 @@ pub async fn sys_fchdir(fd: Fd) -> Result<usize> {
         .get(fd)
         .ok_or(KernelError::BadFd)?;
 
-    *task.cwd.lock_save_irq() = (
-        file.inode().ok_or(KernelError::BadFd)?,
-        file.path().ok_or(KernelError::BadFd)?.to_owned(),
-    );
+    let inode = file.inode().ok_or(KernelError::BadFd)?;
+    check_is_dir(&inode).await?;
+
+    *task.cwd.lock_save_irq() = (inode, file.path().ok_or(KernelError::BadFd)?.to_owned());
 
     Ok(0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ktest;
+    use libkernel::fs::pathbuf::PathBuf;
+
+    ktest! {
+        async fn chdir_tracks_absolute_cwd() {
+            let task = current_task_shared();
+            let saved = task.cwd.lock_save_irq().clone();
+
+            *task.cwd.lock_save_irq() = (VFS.root_inode(), PathBuf::from("/"));
+
+            chdir(&task, Path::new("dev")).await.unwrap();
+            assert_eq!(cwd_cstring(&task, 64).unwrap().as_bytes(), b"/dev");
+
+            // The path given back is normalised.
+            chdir(&task, Path::new("./.././dev/")).await.unwrap();
+            assert_eq!(cwd_cstring(&task, 64).unwrap().as_bytes(), b"/dev");
+
+            // "/dev" and its NUL need five bytes.
+            assert_eq!(cwd_cstring(&task, 5).unwrap().as_bytes(), b"/dev");
+            assert_eq!(cwd_cstring(&task, 4).err(), Some(KernelError::NameTooLong));
+
+            // Only directories can be the cwd.
+            assert_eq!(
+                chdir(&task, Path::new("console")).await.err(),
+                Some(FsError::NotADirectory.into())
+            );
+            assert_eq!(cwd_cstring(&task, 64).unwrap().as_bytes(), b"/dev");
+
+            chdir(&task, Path::new("/")).await.unwrap();
+            assert_eq!(cwd_cstring(&task, 64).unwrap().as_bytes(), b"/");
+
+            *task.cwd.lock_save_irq() = saved;
+        }
+    }
+}
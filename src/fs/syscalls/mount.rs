@@ -0,0 +1,125 @@
+use crate::{
+    drivers::blk::find_block_device,
+    fs::{
+        VFS,
+        writeback::{BlockCache, spawn_writeback},
+    },
+    memory::uaccess::cstr::UserCStr,
+    sched::current::current_task_shared,
+};
+use alloc::{boxed::Box, sync::Arc};
+use core::ffi::c_char;
+use libkernel::{
+    error::{FsError, KernelError, Result},
+    fs::{BlockDevice, FileType, blk::cache::CacheConfig, path::Path},
+    memory::address::TUA,
+    proc::caps::CapabilitiesFlags,
+};
+
+bitflags::bitflags! {
+    #[derive(Clone, Copy, Debug)]
+    pub struct MountFlags: u64 {
+        const MS_RDONLY = 1;
+        const MS_NOSUID = 2;
+        const MS_NODEV = 4;
+        const MS_NOEXEC = 8;
+        const MS_REMOUNT = 32;
+        const MS_BIND = 4096;
+        const MS_MOVE = 8192;
+    }
+}
+
+/// Maps the filesystem type names used by mount(2) onto the registered
+/// filesystem drivers, along with whether the filesystem lives on a block
+/// device named by `source`.
+fn fs_driver(fstype: &str) -> Option<(&'static str, bool)> {
+    match fstype {
+        "tmpfs" => Some(("tmpfs", false)),
+        "proc" => Some(("procfs", false)),
+        "sysfs" => Some(("sysfs", false)),
+        "devtmpfs" => Some(("devfs", false)),
+        "cgroup2" => Some(("cgroupfs", false)),
+        "vfat" => Some(("fat32fs", true)),
+        "ext4" => Some(("ext4fs", true)),
+        _ => None,
+    }
+}
+
+pub async fn sys_mount(
+    source: TUA<c_char>,
+    target: TUA<c_char>,
+    fstype: TUA<c_char>,
+    flags: u64,
+    data: TUA<c_char>,
+) -> Result<usize> {
+    let task = current_task_shared();
+    task.creds
+        .lock_save_irq()
+        .caps()
+        .check_capable(CapabilitiesFlags::CAP_SYS_ADMIN)?;
+
+    let flags = MountFlags::from_bits_truncate(flags);
+
+    if flags.intersects(MountFlags::MS_REMOUNT | MountFlags::MS_BIND | MountFlags::MS_MOVE) {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let mut source_buf = [0; 1024];
+    let mut target_buf = [0; 1024];
+    let mut fstype_buf = [0; 64];
+    let mut data_buf = [0; 256];
+
+    let target = Path::new(UserCStr::from_ptr(target).copy_from_user(&mut target_buf).await?);
+    let fstype = UserCStr::from_ptr(fstype)
+        .copy_from_user(&mut fstype_buf)
+        .await?;
+    let options = if data.is_null() {
+        ""
+    } else {
+        UserCStr::from_ptr(data)
+            .copy_from_user(&mut data_buf)
+            .await?
+    };
+
+    let cwd = task.cwd.lock_save_irq().0.clone();
+    let mount_point = VFS.resolve_path(target, cwd, &task).await?;
+
+    if mount_point.getattr().await?.file_type != FileType::Directory {
+        return Err(FsError::NotADirectory.into());
+    }
+
+    let (driver, needs_device) = fs_driver(fstype).ok_or(FsError::NoDevice)?;
+
+    // There are no block device nodes yet, so `source` names a registered
+    // block device, like `vda` or `/dev/vda`.
+    let cache = if needs_device {
+        if source.is_null() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let source = UserCStr::from_ptr(source)
+            .copy_from_user(&mut source_buf)
+            .await?;
+        let name = source.strip_prefix("/dev/").unwrap_or(source);
+        let dev = find_block_device(name).ok_or(FsError::NotFound)?;
+
+        let cache = BlockCache::new(Box::new(dev), CacheConfig::default());
+
+        Some(Arc::new(cache))
+    } else {
+        None
+    };
+
+    let blkdev = cache
+        .clone()
+        .map(|cache| Box::new(cache) as Box<dyn BlockDevice>);
+
+    VFS.mount(mount_point, driver, blkdev, options).await?;
+
+    // Only start flushing the cache once a filesystem owns it.
+    if let Some(cache) = cache {
+        spawn_writeback(cache)?;
+    }
+
+    Ok(0)
+}
@@ -49,17 +49,18 @@ unsafe impl UserCopyable for StatFs {}
 
 async fn statfs_impl(inode: Arc<dyn Inode>) -> libkernel::error::Result<StatFs> {
     let fs = VFS.get_fs(inode).await?;
+    let stats = fs.stats().await?;
     Ok(StatFs {
         f_type: fs.magic() as _,
-        f_bsize: 0,
-        f_blocks: 0,
-        f_bfree: 0,
-        f_bavail: 0,
+        f_bsize: stats.block_size as _,
+        f_blocks: stats.total_blocks,
+        f_bfree: stats.free_blocks,
+        f_bavail: stats.free_blocks,
         f_files: 0,
         f_ffree: 0,
         f_fsid: fs.id(),
-        f_namelen: 0,
-        f_frsize: 0,
+        f_namelen: stats.max_name_len as _,
+        f_frsize: stats.block_size as _,
         f_flags: 0,
         f_spare: [0; 6],
     })
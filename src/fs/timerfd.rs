@@ -0,0 +1,400 @@
+//! Timers, created by `timerfd_create`, that are read through a file.
+//!
+//! Expirations aren't delivered by the timer interrupt. Instead, the deadline
+//! is compared against the clock whenever the file is read or polled, and the
+//! number of periods that have elapsed since is worked out then. A timer left
+//! unread for several intervals therefore reports all of them at once.
+
+use crate::{
+    clock::{ClockId, realtime::date, timespec::TimeSpec},
+    drivers::timer::{sleep, uptime},
+    memory::uaccess::{UserCopyable, copy_to_user},
+    process::{
+        fd_table::Fd,
+        thread_group::signal::{InterruptResult, Interruptable},
+    },
+    sched::current::current_task_shared,
+    sync::CondVar,
+};
+use alloc::{boxed::Box, sync::Arc};
+use async_trait::async_trait;
+use core::{
+    mem::size_of,
+    pin::{Pin, pin},
+    time::Duration,
+};
+use futures::future::select;
+use libkernel::{
+    error::{KernelError, Result},
+    fs::{OpenFlags, SeekFrom},
+    memory::address::{TUA, UA},
+    sync::condvar::WakeupType,
+};
+
+use super::{
+    fops::FileOps,
+    open_file::{FileCtx, OpenFile},
+};
+
+/// `it_value` is an absolute time on the timer's clock, not a relative one.
+const TFD_TIMER_ABSTIME: u32 = 1;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ITimerSpec {
+    pub it_interval: TimeSpec,
+    pub it_value: TimeSpec,
+}
+
+unsafe impl UserCopyable for ITimerSpec {}
+
+#[derive(Clone, Copy, Default)]
+struct TimerState {
+    /// The next expiration, as an uptime, or `None` if the timer is disarmed.
+    deadline: Option<Duration>,
+    /// The period of the timer, or zero for a one-shot timer.
+    interval: Duration,
+    /// Bumped whenever the timer is re-armed, to wake pollers waiting on the
+    /// old deadline.
+    generation: u64,
+}
+
+impl TimerState {
+    /// Consumes every expiration up to `now`, returning how many there were.
+    fn expire(&mut self, now: Duration) -> u64 {
+        let Some(deadline) = self.deadline.filter(|deadline| *deadline <= now) else {
+            return 0;
+        };
+
+        if self.interval.is_zero() {
+            self.deadline = None;
+            return 1;
+        }
+
+        let elapsed = (now - deadline).as_nanos();
+        let interval = self.interval.as_nanos();
+        let into_period = elapsed % interval;
+
+        // The next deadline is the first period boundary after `now`.
+        self.deadline = Some(
+            now + self.interval
+                - Duration::new(
+                    (into_period / NANOS_PER_SEC) as u64,
+                    (into_period % NANOS_PER_SEC) as u32,
+                ),
+        );
+
+        (elapsed / interval) as u64 + 1
+    }
+}
+
+pub struct Timer {
+    clock: ClockId,
+    state: CondVar<TimerState>,
+}
+
+impl Timer {
+    fn state(&self) -> TimerState {
+        let mut state = TimerState::default();
+
+        self.state.update(|s| {
+            state = *s;
+            WakeupType::None
+        });
+
+        state
+    }
+
+    /// Returns the offset to add to an uptime to get a time on this timer's
+    /// clock.
+    fn clock_offset(&self) -> Duration {
+        match self.clock {
+            ClockId::Realtime => date().saturating_sub(uptime()),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Returns the time left until the next expiration and the interval, as
+    /// reported to userspace.
+    fn get(&self) -> ITimerSpec {
+        let state = self.state();
+
+        ITimerSpec {
+            it_interval: state.interval.into(),
+            it_value: state
+                .deadline
+                .map_or(Duration::ZERO, |deadline| deadline.saturating_sub(uptime()))
+                .into(),
+        }
+    }
+
+    /// Arms the timer to first expire at `value` (or disarms it, if zero) and
+    /// then every `interval`.
+    fn set(&self, value: Duration, interval: Duration, abs: bool) {
+        let deadline = if value.is_zero() {
+            None
+        } else if abs {
+            Some(value.saturating_sub(self.clock_offset()))
+        } else {
+            Some(uptime() + value)
+        };
+
+        self.state.update(|state| {
+            state.deadline = deadline;
+            state.interval = interval;
+            state.generation += 1;
+            WakeupType::All
+        });
+    }
+
+    /// Consumes and returns the expirations since the last call.
+    fn expire(&self) -> u64 {
+        let mut expirations = 0;
+
+        self.state.update(|state| {
+            expirations = state.expire(uptime());
+            WakeupType::None
+        });
+
+        expirations
+    }
+
+    /// Resolves once the timer has expired, or is re-armed.
+    async fn wait(&self) {
+        let state = self.state();
+        let generation = state.generation;
+
+        let rearmed = self
+            .state
+            .wait_until(move |state| (state.generation != generation).then_some(()));
+
+        match state.deadline {
+            Some(deadline) => {
+                select(
+                    pin!(sleep(deadline.saturating_sub(uptime()))),
+                    pin!(rearmed),
+                )
+                .await;
+            }
+            None => rearmed.await,
+        }
+    }
+}
+
+struct TimerFd(Arc<Timer>);
+
+#[async_trait]
+impl FileOps for TimerFd {
+    async fn read(&mut self, ctx: &mut FileCtx, buf: UA, count: usize) -> Result<usize> {
+        if count < size_of::<u64>() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let expirations = loop {
+            let expirations = self.0.expire();
+
+            if expirations != 0 {
+                break expirations;
+            }
+
+            if ctx.flags.contains(OpenFlags::O_NONBLOCK) {
+                return Err(KernelError::TryAgain);
+            }
+
+            if let InterruptResult::Interrupted = self.0.wait().interruptable().await {
+                return Err(KernelError::Interrupted);
+            }
+        };
+
+        copy_to_user(buf.cast(), expirations).await?;
+
+        Ok(size_of::<u64>())
+    }
+
+    async fn readat(&mut self, _buf: UA, _count: usize, _offset: u64) -> Result<usize> {
+        Err(KernelError::SeekPipe)
+    }
+
+    async fn writeat(&mut self, _buf: UA, _count: usize, _offset: u64) -> Result<usize> {
+        Err(KernelError::InvalidValue)
+    }
+
+    async fn seek(&mut self, _ctx: &mut FileCtx, _pos: SeekFrom) -> Result<u64> {
+        Err(KernelError::SeekPipe)
+    }
+
+    fn poll_read_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        let timer = self.0.clone();
+
+        Box::pin(async move {
+            while timer
+                .state()
+                .deadline
+                .is_none_or(|deadline| deadline > uptime())
+            {
+                timer.wait().await;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn as_timer(&self) -> Option<Arc<Timer>> {
+        Some(self.0.clone())
+    }
+}
+
+pub fn sys_timerfd_create(clockid: i32, flags: u32) -> Result<usize> {
+    let clock = match ClockId::try_from(clockid).map_err(|_| KernelError::InvalidValue)? {
+        clock @ (ClockId::Realtime | ClockId::Monotonic) => clock,
+        // There's no suspend, so boot time is monotonic time.
+        ClockId::BootTime => ClockId::Monotonic,
+        _ => return Err(KernelError::InvalidValue),
+    };
+
+    let flags = OpenFlags::from_bits_retain(flags);
+
+    if !flags
+        .difference(OpenFlags::O_CLOEXEC | OpenFlags::O_NONBLOCK)
+        .is_empty()
+    {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let timer = Timer {
+        clock,
+        state: CondVar::new(TimerState::default()),
+    };
+
+    let file = OpenFile::new(
+        Box::new(TimerFd(Arc::new(timer))),
+        OpenFlags::O_RDONLY | (flags & OpenFlags::O_NONBLOCK),
+    );
+
+    let fd = current_task_shared()
+        .fd_table
+        .lock_save_irq()
+        .insert_with_flags(Arc::new(file), flags.into())?;
+
+    Ok(fd.as_raw() as _)
+}
+
+pub async fn sys_timerfd_settime(
+    fd: Fd,
+    flags: u32,
+    new_value: TUA<ITimerSpec>,
+    old_value: TUA<ITimerSpec>,
+) -> Result<usize> {
+    if flags & !TFD_TIMER_ABSTIME != 0 {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let file = current_task_shared()
+        .fd_table
+        .lock_save_irq()
+        .get(fd)
+        .ok_or(KernelError::BadFd)?;
+
+    let timer = file
+        .lock()
+        .await
+        .0
+        .as_timer()
+        .ok_or(KernelError::InvalidValue)?;
+
+    let interval: TUA<TimeSpec> = new_value.to_untyped().cast();
+    let interval = TimeSpec::copy_from_user(interval).await?;
+    let value = TimeSpec::copy_from_user(new_value.to_untyped().cast().add_objs(1)).await?;
+    let old = timer.get();
+
+    timer.set(
+        value.into(),
+        interval.into(),
+        flags & TFD_TIMER_ABSTIME != 0,
+    );
+
+    if !old_value.is_null() {
+        copy_to_user(old_value, old).await?;
+    }
+
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ktest, memory::uaccess::copy_from_user, testing::ScratchBuf};
+    use libkernel::memory::PAGE_SIZE;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    fn timer() -> Arc<Timer> {
+        Arc::new(Timer {
+            clock: ClockId::Monotonic,
+            state: CondVar::new(TimerState::default()),
+        })
+    }
+
+    ktest! {
+        fn expirations_between_reads() {
+            let mut state = TimerState {
+                deadline: Some(10 * MS),
+                interval: 5 * MS,
+                generation: 0,
+            };
+
+            assert_eq!(state.expire(9 * MS), 0);
+            assert_eq!(state.expire(10 * MS), 1);
+            assert_eq!(state.deadline, Some(15 * MS));
+
+            // Periods missed between reads are all counted, and the timer
+            // stays in phase with its first deadline.
+            assert_eq!(state.expire(27 * MS), 3);
+            assert_eq!(state.deadline, Some(30 * MS));
+            assert_eq!(state.expire(29 * MS), 0);
+
+            let mut one_shot = TimerState {
+                deadline: Some(10 * MS),
+                ..Default::default()
+            };
+
+            assert_eq!(one_shot.expire(50 * MS), 1);
+            assert_eq!(one_shot.deadline, None);
+            assert_eq!(one_shot.expire(100 * MS), 0);
+        }
+    }
+
+    ktest! {
+        async fn interval_timer_counts_expirations() {
+            let scratch = ScratchBuf::new(PAGE_SIZE).await;
+            let buf = scratch.addr();
+
+            let timer = timer();
+            let mut tfd = TimerFd(timer.clone());
+            let mut ctx = FileCtx::new(OpenFlags::O_RDONLY | OpenFlags::O_NONBLOCK);
+
+            timer.set(5 * MS, 5 * MS, false);
+            assert!(matches!(tfd.read(&mut ctx, buf, 8).await, Err(KernelError::TryAgain)));
+
+            tfd.poll_read_ready().await.unwrap();
+            let start = uptime();
+            sleep(17 * MS).await;
+            let elapsed = uptime() - start;
+
+            tfd.read(&mut ctx, buf, 8).await.unwrap();
+            let expirations: u64 = copy_from_user(buf.cast()).await.unwrap();
+
+            // The first expiration, then one per interval slept, give or take
+            // one for the time between the wait and the read.
+            let slept = (elapsed.as_millis() / 5) as u64;
+            assert!((slept + 1..=slept + 2).contains(&expirations));
+
+            // Disarming stops further expirations.
+            timer.set(Duration::ZERO, Duration::ZERO, false);
+            sleep(6 * MS).await;
+            assert!(matches!(tfd.read(&mut ctx, buf, 8).await, Err(KernelError::TryAgain)));
+        }
+    }
+}
@@ -0,0 +1,17 @@
+use crate::{
+    arch::ArchImpl,
+    drivers::timer::sleep,
+    process::kthread::{KThreadHandle, spawn_kthread},
+};
+use alloc::sync::Arc;
+use libkernel::{error::Result, fs::blk::cache::CachedBlockDevice};
+
+pub type BlockCache = CachedBlockDevice<ArchImpl>;
+
+/// Starts a kernel thread that periodically flushes `cache` to its backing
+/// device. The interval and watermark are taken from the cache's config.
+pub fn spawn_writeback(cache: Arc<BlockCache>) -> Result<KThreadHandle> {
+    spawn_kthread("writeback", async move {
+        cache.run_writeback(sleep).await;
+    })
+}
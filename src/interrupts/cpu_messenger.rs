@@ -1,6 +1,9 @@
 //! A module for sending messages between CPUs, utilising IPIs.
 
-use core::task::Waker;
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    task::Waker,
+};
 
 use super::{
     ClaimedInterrupt, InterruptConfig, InterruptDescriptor, InterruptHandler, get_interrupt_root,
@@ -8,7 +11,7 @@ use super::{
 use crate::kernel::cpu_id::CpuId;
 use crate::process::owned::OwnedTask;
 use crate::{
-    arch::ArchImpl,
+    arch::{Arch, ArchImpl},
     drivers::Driver,
     kernel::kpipe::KBuf,
     sched,
@@ -25,6 +28,8 @@ use log::warn;
 pub enum Message {
     PutTask(Box<OwnedTask>),
     WakeupTask(Waker),
+    /// Pick again on the way out of the interrupt.
+    Resched,
 }
 
 struct CpuMessenger {
@@ -40,6 +45,10 @@ impl Driver for CpuMessenger {
 
 impl InterruptHandler for CpuMessenger {
     fn handle_irq(&self, _desc: InterruptDescriptor) {
+        if STOP_REQUESTED.load(Ordering::Acquire) {
+            ArchImpl::cpu_off();
+        }
+
         while let Some(message) = CPU_MESSENGER
             .get()
             .unwrap()
@@ -52,6 +61,7 @@ impl InterruptHandler for CpuMessenger {
             match message {
                 Message::PutTask(task) => sched::insert_task(task),
                 Message::WakeupTask(waker) => waker.wake(),
+                Message::Resched => sched::set_need_resched(),
             }
         }
     }
@@ -104,4 +114,30 @@ pub fn message_cpu(cpu_id: CpuId, message: Message) -> Result<()> {
     Ok(())
 }
 
+/// Asks every other CPU to take itself offline. Used when the kernel panics so
+/// that the remaining cores stop touching shared state while the panic is
+/// reported.
+pub fn stop_other_cpus() {
+    STOP_REQUESTED.store(true, Ordering::Release);
+
+    if CPU_MESSENGER.get().is_none() {
+        return;
+    }
+
+    let Some(irq) = get_interrupt_root() else {
+        return;
+    };
+
+    let this_cpu = ArchImpl::id();
+
+    for cpu in (0..ArchImpl::cpu_count()).filter(|&cpu| cpu != this_cpu) {
+        if !irq.try_raise_ipi(cpu) {
+            // The interrupt controller is locked, most likely by the
+            // panicking CPU itself. There's nothing more we can safely do.
+            break;
+        }
+    }
+}
+
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
 static CPU_MESSENGER: OnceLock<Arc<CpuMessenger>> = OnceLock::new();
@@ -0,0 +1,161 @@
+//! A fast path for a single latency-critical interrupt, delivered as an FIQ
+//! rather than an IRQ.
+//!
+//! Critical sections only mask IRQs, so the FIQ handler can run in the middle
+//! of any of them, including the allocator's and those of every spinlock. It
+//! must therefore not allocate, take a lock or block, and should hand its
+//! results to the rest of the kernel through atomics.
+
+use super::{InterruptConfig, InterruptDescriptor, TriggerMode, get_interrupt_root};
+use crate::kernel::cmdline;
+use alloc::boxed::Box;
+use core::{
+    ptr::null_mut,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+use libkernel::error::{KernelError, Result};
+use log::{info, warn};
+
+/// Runs in FIQ context, see the module documentation for what it may do.
+pub type FiqHandler = fn(InterruptDescriptor);
+
+/// How an interrupt controller acknowledges and completes FIQs. Neither may
+/// take a lock.
+#[derive(Clone, Copy)]
+pub struct FiqOps {
+    /// Acknowledges the pending FIQ, returning its raw ID and descriptor, or
+    /// `None` if it was spurious.
+    pub ack: fn() -> Option<(usize, InterruptDescriptor)>,
+    /// Signals end-of-interrupt for a raw ID returned by `ack`.
+    pub eoi: fn(usize),
+}
+
+struct FiqRoute {
+    ops: FiqOps,
+    handler: FiqHandler,
+}
+
+/// Set once and then never freed, so the vector can use it without a lock.
+static FIQ_ROUTE: AtomicPtr<FiqRoute> = AtomicPtr::new(null_mut());
+
+/// Installs the FIQ route. There is only one, so this fails with `InUse` if
+/// an interrupt has already been routed to FIQ.
+pub(super) fn install(ops: FiqOps, handler: FiqHandler) -> Result<()> {
+    let route = Box::into_raw(Box::new(FiqRoute { ops, handler }));
+
+    FIQ_ROUTE
+        .compare_exchange(null_mut(), route, Ordering::AcqRel, Ordering::Acquire)
+        .map(|_| ())
+        .map_err(|_| {
+            // SAFETY: `route` was never published.
+            drop(unsafe { Box::from_raw(route) });
+            KernelError::InUse
+        })
+}
+
+pub(super) fn is_installed() -> bool {
+    !FIQ_ROUTE.load(Ordering::Acquire).is_null()
+}
+
+fn dispatch(route: &FiqRoute) {
+    if let Some((raw_id, desc)) = (route.ops.ack)() {
+        (route.handler)(desc);
+        (route.ops.eoi)(raw_id);
+    }
+}
+
+/// Handles an FIQ, called from the exception vector. Returns `false` if no
+/// route has been installed, in which case no FIQ was expected.
+pub fn handle_fiq() -> bool {
+    // SAFETY: Once published, the route is never freed or changed.
+    match unsafe { FIQ_ROUTE.load(Ordering::Acquire).as_ref() } {
+        Some(route) => {
+            dispatch(route);
+            true
+        }
+        None => false,
+    }
+}
+
+static FIQ_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// A sample FIQ handler, which only counts.
+pub fn count_fiq(_desc: InterruptDescriptor) {
+    FIQ_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The number of FIQs [`count_fiq`] has handled.
+#[expect(dead_code)]
+pub fn fiq_count() -> usize {
+    FIQ_COUNT.load(Ordering::Relaxed)
+}
+
+/// Routes the SPI given by the `fiq=<spi>` boot parameter, if any, to
+/// [`count_fiq`].
+pub fn apply_cmdline_route() {
+    let Some(spi) = cmdline::get("fiq") else {
+        return;
+    };
+
+    let Ok(spi) = spi.parse() else {
+        warn!("Ignoring invalid fiq={spi}");
+        return;
+    };
+
+    let config = InterruptConfig {
+        descriptor: InterruptDescriptor::Spi(spi),
+        trigger: TriggerMode::LevelHigh,
+    };
+
+    let result = get_interrupt_root()
+        .ok_or(KernelError::NoDevice)
+        .and_then(|root| root.claim_fiq(config, count_fiq));
+
+    match result {
+        Ok(()) => info!("Routed SPI {spi} to FIQ"),
+        Err(e) => warn!("Could not route SPI {spi} to FIQ: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ktest;
+
+    static ACKED: AtomicUsize = AtomicUsize::new(0);
+    static HANDLED: AtomicUsize = AtomicUsize::new(0);
+    static EOI: AtomicUsize = AtomicUsize::new(0);
+
+    fn mock_ack() -> Option<(usize, InterruptDescriptor)> {
+        ACKED.fetch_add(1, Ordering::Relaxed);
+        Some((77, InterruptDescriptor::Spi(45)))
+    }
+
+    fn mock_eoi(raw_id: usize) {
+        assert_eq!(HANDLED.load(Ordering::Relaxed), 1);
+        EOI.store(raw_id, Ordering::Relaxed);
+    }
+
+    fn mock_handler(desc: InterruptDescriptor) {
+        assert_eq!(desc, InterruptDescriptor::Spi(45));
+        HANDLED.fetch_add(1, Ordering::Relaxed);
+    }
+
+    ktest! {
+        fn fiq_dispatch_acks_handles_then_eois() {
+            let route = FiqRoute {
+                ops: FiqOps {
+                    ack: mock_ack,
+                    eoi: mock_eoi,
+                },
+                handler: mock_handler,
+            };
+
+            dispatch(&route);
+
+            assert_eq!(ACKED.load(Ordering::Relaxed), 1);
+            assert_eq!(HANDLED.load(Ordering::Relaxed), 1);
+            assert_eq!(EOI.load(Ordering::Relaxed), 77);
+        }
+    }
+}
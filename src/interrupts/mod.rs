@@ -1,17 +1,35 @@
 use alloc::{
     boxed::Box,
     collections::BTreeMap,
+    format,
+    string::String,
     sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use libkernel::{
+    error::{KernelError, Result},
+    sync::condvar::WakeupType,
 };
-use libkernel::error::{KernelError, Result};
 use log::{debug, info, warn};
 
 use crate::{
+    arch::{Arch, ArchImpl},
     drivers::Driver,
-    sync::{OnceLock, SpinLock},
+    kernel::random::add_interrupt_randomness,
+    per_cpu_shared,
+    process::kthread::spawn_kthread,
+    sched::CPU_COUNTERS,
+    sync::{CondVar, OnceLock, SpinLock},
 };
 
 pub mod cpu_messenger;
+pub mod fiq;
+
+use fiq::{FiqHandler, FiqOps};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TriggerMode {
@@ -28,6 +46,43 @@ pub enum InterruptDescriptor {
     Ipi(usize),
 }
 
+impl InterruptDescriptor {
+    /// A dense index for the interrupt, numbered like GIC interrupt IDs: IPIs
+    /// first, then PPIs, then SPIs. `None` if it's beyond `IRQ_LINES`.
+    fn line(self) -> Option<usize> {
+        let line = match self {
+            Self::Ipi(x) if x < 16 => x,
+            Self::Ppi(x) if x < 16 => x + 16,
+            Self::Spi(x) => x + 32,
+            _ => return None,
+        };
+
+        (line < IRQ_LINES).then_some(line)
+    }
+}
+
+/// The number of interrupt lines that have statistics kept.
+const IRQ_LINES: usize = 1024;
+
+// How many times each interrupt line's handler has run on each CPU.
+per_cpu_shared! {
+    static IRQ_COUNTS: Box<[AtomicUsize]> = || (0..IRQ_LINES).map(|_| AtomicUsize::new(0)).collect();
+}
+
+/// The number of times a claimed interrupt has been handled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IrqStat {
+    pub desc: InterruptDescriptor,
+    /// The count for each CPU, indexed by CPU ID.
+    pub per_cpu: Vec<usize>,
+}
+
+impl IrqStat {
+    pub fn total(&self) -> usize {
+        self.per_cpu.iter().sum()
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct InterruptConfig {
     pub descriptor: InterruptDescriptor,
@@ -62,10 +117,80 @@ pub trait InterruptController: Send + Sync {
         &self,
         iter: &mut dyn Iterator<Item = u32>,
     ) -> Result<InterruptConfig>;
+
+    /// Makes `desc` signal an FIQ rather than an IRQ, giving it priority over
+    /// every IRQ, and returns how to acknowledge it from the FIQ vector. The
+    /// interrupt is left disabled.
+    fn route_to_fiq(&mut self, _desc: InterruptDescriptor) -> Result<FiqOps> {
+        Err(KernelError::NotSupported)
+    }
 }
 
 pub trait InterruptHandler: Send + Sync {
     fn handle_irq(&self, desc: InterruptDescriptor);
+
+    /// The bottom half of an interrupt claimed with
+    /// [`InterruptManager::claim_threaded_interrupt`].
+    ///
+    /// For such interrupts `handle_irq` should only quieten the device. This
+    /// then runs in the interrupt's kernel thread with interrupts enabled, and
+    /// unless [`oneshot`](Self::oneshot) says otherwise the interrupt stays
+    /// masked until it returns.
+    fn handle_irq_threaded(&self, _desc: InterruptDescriptor) {}
+
+    /// Whether a threaded interrupt is masked from the hard IRQ until the
+    /// bottom half has run, like Linux's `IRQF_ONESHOT`. A handler whose
+    /// `handle_irq` always leaves the device quiet can return `false`, so that
+    /// it keeps taking interrupts while the thread catches up.
+    fn oneshot(&self) -> bool {
+        true
+    }
+}
+
+/// Shared between the hard IRQ handler of a threaded interrupt and its kernel
+/// thread.
+#[derive(Default)]
+struct IrqThreadState {
+    /// The interrupt fired and the thread hasn't run since.
+    pending: bool,
+    /// The interrupt was released, so the thread should exit.
+    exiting: bool,
+}
+
+type IrqThreadBody = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Interrupt threads claimed before kernel threads can run, or `None` once
+/// they have been started.
+static PENDING_IRQ_THREADS: SpinLock<Option<Vec<(String, IrqThreadBody)>>> =
+    SpinLock::new(Some(Vec::new()));
+
+fn spawn_irq_thread(name: String, body: IrqThreadBody) {
+    let mut pending = PENDING_IRQ_THREADS.lock_save_irq();
+
+    match pending.as_mut() {
+        Some(queue) => queue.push((name, body)),
+        None => {
+            drop(pending);
+
+            if let Err(e) = spawn_kthread(&name, body) {
+                warn!("Failed to start {name}: {e}");
+            }
+        }
+    }
+}
+
+/// Starts the threads of interrupts claimed during early boot. Threads of
+/// interrupts claimed later are started straight away.
+pub fn start_irq_threads() {
+    let Some(queue) = PENDING_IRQ_THREADS.lock_save_irq().take() else {
+        return;
+    };
+
+    for (name, body) in queue {
+        if let Err(e) = spawn_kthread(&name, body) {
+            warn!("Failed to start {name}: {e}");
+        }
+    }
 }
 
 pub struct InterruptManager {
@@ -103,6 +228,79 @@ impl InterruptManager {
         config: InterruptConfig,
         constructor: FConstructor,
     ) -> Result<Arc<T>>
+    where
+        T: 'static + Send + Sync + Driver + InterruptHandler,
+        FConstructor: FnOnce(ClaimedInterrupt) -> T,
+    {
+        self.claim(config, constructor, false)
+            .map(|(driver, _)| driver)
+    }
+
+    /// Claims an interrupt whose handling is split in two, like Linux's
+    /// `request_threaded_irq`.
+    ///
+    /// The hard IRQ handler masks the interrupt and runs the driver's
+    /// `handle_irq`, then wakes a kernel thread dedicated to the interrupt,
+    /// which runs `handle_irq_threaded` and unmasks it again.
+    pub fn claim_threaded_interrupt<T, FConstructor>(
+        self: &Arc<Self>,
+        config: InterruptConfig,
+        constructor: FConstructor,
+    ) -> Result<Arc<T>>
+    where
+        T: 'static + Send + Sync + Driver + InterruptHandler,
+        FConstructor: FnOnce(ClaimedInterrupt) -> T,
+    {
+        let (driver, thread) = self.claim(config, constructor, true)?;
+
+        if let Some(body) = thread {
+            spawn_irq_thread(format!("irq/{:?}", config.descriptor), body);
+        }
+
+        Ok(driver)
+    }
+
+    /// Routes an interrupt to the FIQ fast path, where `handler` runs straight
+    /// from the exception vector. Only one interrupt can be routed to FIQ, and
+    /// the route is permanent.
+    pub fn claim_fiq(&self, config: InterruptConfig, handler: FiqHandler) -> Result<()> {
+        if fiq::is_installed() {
+            return Err(KernelError::InUse);
+        }
+
+        if self
+            .claimed_interrupts
+            .lock_save_irq()
+            .contains_key(&config.descriptor)
+        {
+            return Err(KernelError::InUse);
+        }
+
+        let ops = self
+            .controller
+            .lock_save_irq()
+            .route_to_fiq(config.descriptor)?;
+
+        fiq::install(ops, handler)?;
+
+        self.controller.lock_save_irq().enable_interrupt(config);
+
+        info!(
+            "Interrupt {:?} routed to FIQ on {}",
+            config.descriptor, self.name
+        );
+
+        Ok(())
+    }
+
+    /// Claims an interrupt, also returning the body of its kernel thread if
+    /// it's `threaded`.
+    fn claim<T, FConstructor>(
+        self: &Arc<Self>,
+        config: InterruptConfig,
+        constructor: FConstructor,
+        threaded: bool,
+    ) -> Result<(Arc<T>, Option<IrqThreadBody>)>
     where
         T: 'static + Send + Sync + Driver + InterruptHandler,
         FConstructor: FnOnce(ClaimedInterrupt) -> T,
@@ -113,11 +311,14 @@ impl InterruptManager {
             return Err(KernelError::InUse);
         }
 
+        let thread = threaded.then(|| CondVar::new(IrqThreadState::default()));
+
         let driver: Arc<T> = Arc::new_cyclic(|driver_weak: &Weak<T>| {
             let handle = ClaimedInterrupt {
                 desc: config.descriptor,
                 manager: Arc::clone(self),
                 handler: driver_weak.clone(),
+                thread: thread.clone(),
             };
 
             let driver = constructor(handle.clone());
@@ -127,6 +328,14 @@ impl InterruptManager {
             driver
         });
 
+        drop(claimed_int);
+
+        let body = thread.map(|state| {
+            let handler: Weak<dyn InterruptHandler> = Arc::downgrade(&driver) as _;
+
+            Box::pin(irq_thread(self.clone(), config, handler, state)) as IrqThreadBody
+        });
+
         self.controller.lock_save_irq().enable_interrupt(config);
 
         debug!(
@@ -135,18 +344,39 @@ impl InterruptManager {
             config.descriptor,
         );
 
-        Ok(driver)
+        Ok((driver, body))
     }
 
-    fn remove_interrupt(&self, desc: InterruptDescriptor) {
-        let mut claimed_int = self.claimed_interrupts.lock_save_irq();
+    /// Gives up the claim on `desc`, if `handler` still holds it. Once a claim
+    /// has been released, the interrupt may have been claimed again, and a
+    /// stale handle mustn't take it from the new owner.
+    fn remove_interrupt(&self, desc: InterruptDescriptor, handler: &Weak<dyn InterruptHandler>) {
+        let handle = {
+            let mut claimed_ints = self.claimed_interrupts.lock_save_irq();
+
+            match claimed_ints.get(&desc) {
+                Some(claim) if Weak::ptr_eq(&claim.handler, handler) => claimed_ints.remove(&desc),
+                _ => None,
+            }
+        };
 
-        if claimed_int.remove(&desc).is_some() {
+        if let Some(handle) = &handle {
             self.controller.lock_save_irq().disable_interrupt(desc);
+
+            if let Some(thread) = &handle.thread {
+                thread.update(|state| {
+                    state.exiting = true;
+                    WakeupType::All
+                });
+            }
         }
+
+        // Dropping the manager's own handle re-enters this function, so it
+        // must happen with the lock released. The second call finds nothing.
+        drop(handle);
     }
 
-    fn get_active_handler(&self) -> Option<(Arc<dyn InterruptHandler>, InterruptDescriptor)> {
+    fn get_active_handler(&self) -> Option<ActiveInterrupt> {
         let mut claimed_ints = self.claimed_interrupts.lock_save_irq();
 
         let ctx = self.controller.lock_save_irq().read_active_interrupt()?;
@@ -154,22 +384,91 @@ impl InterruptManager {
         let irq = claimed_ints.get_mut(&desc)?;
         let handler = irq.handler.upgrade()?;
 
-        Some((handler, desc))
+        Some(ActiveInterrupt {
+            handler,
+            desc,
+            thread: irq.thread.clone(),
+        })
     }
 
     pub fn handle_interrupt(&self) {
-        let Some((handler, desc)) = self.get_active_handler() else {
+        CPU_COUNTERS.get().irqs.fetch_add(1, Ordering::Relaxed);
+        add_interrupt_randomness();
+
+        let Some(ActiveInterrupt {
+            handler,
+            desc,
+            thread,
+        }) = self.get_active_handler()
+        else {
             warn!("IRQ fired for stale IRQ handle");
             return;
         };
 
+        if let Some(line) = desc.line() {
+            IRQ_COUNTS.get()[line].fetch_add(1, Ordering::Relaxed);
+        }
+
+        let Some(thread) = thread else {
+            handler.handle_irq(desc);
+            return;
+        };
+
+        // Keep a level-triggered line from firing again until the thread
+        // has dealt with the device.
+        if handler.oneshot() {
+            self.controller.lock_save_irq().disable_interrupt(desc);
+        }
+
         handler.handle_irq(desc);
+
+        thread.update(|state| {
+            state.pending = true;
+            WakeupType::One
+        });
+    }
+
+    /// Returns a snapshot of how often each claimed interrupt has been
+    /// handled, and on which CPUs.
+    pub fn irq_stats(&self) -> Vec<IrqStat> {
+        let descs: Vec<_> = self
+            .claimed_interrupts
+            .lock_save_irq()
+            .keys()
+            .copied()
+            .collect();
+
+        descs
+            .into_iter()
+            .map(|desc| IrqStat {
+                desc,
+                per_cpu: (0..ArchImpl::cpu_count())
+                    .map(|cpu| {
+                        desc.line().map_or(0, |line| {
+                            IRQ_COUNTS.get_by_cpu(cpu)[line].load(Ordering::Relaxed)
+                        })
+                    })
+                    .collect(),
+            })
+            .collect()
     }
 
     pub fn raise_ipi(&self, cpu: usize) {
         self.controller.lock_save_irq().raise_ipi(cpu);
     }
 
+    /// Raises an IPI only if the controller isn't already locked. Used on
+    /// paths, such as a panic, where the current CPU may hold the lock.
+    pub fn try_raise_ipi(&self, cpu: usize) -> bool {
+        match self.controller.try_lock_save_irq() {
+            Some(mut controller) => {
+                controller.raise_ipi(cpu);
+                true
+            }
+            None => false,
+        }
+    }
+
     pub fn enable_core(&self, cpu_id: usize) {
         self.controller.lock_save_irq().enable_core(cpu_id);
     }
@@ -185,16 +484,87 @@ impl Driver for InterruptManager {
     }
 }
 
+struct ActiveInterrupt {
+    handler: Arc<dyn InterruptHandler>,
+    desc: InterruptDescriptor,
+    thread: Option<CondVar<IrqThreadState>>,
+}
+
+/// The kernel thread of a threaded interrupt. Runs the bottom half each time
+/// the hard IRQ handler marks the interrupt pending, until it's released.
+async fn irq_thread(
+    manager: Arc<InterruptManager>,
+    config: InterruptConfig,
+    handler: Weak<dyn InterruptHandler>,
+    state: CondVar<IrqThreadState>,
+) {
+    loop {
+        let exiting = state
+            .wait_until(|state| {
+                if state.exiting {
+                    Some(true)
+                } else if state.pending {
+                    state.pending = false;
+                    Some(false)
+                } else {
+                    None
+                }
+            })
+            .await;
+
+        if exiting {
+            return;
+        }
+
+        let Some(handler) = handler.upgrade() else {
+            return;
+        };
+
+        handler.handle_irq_threaded(config.descriptor);
+
+        let oneshot = handler.oneshot();
+        drop(handler);
+
+        if !oneshot {
+            continue;
+        }
+
+        // Hold the state lock so a concurrent release can't be undone by
+        // unmasking after it.
+        state.update(|state| {
+            if !state.exiting {
+                manager.controller.lock_save_irq().enable_interrupt(config);
+            }
+            WakeupType::None
+        });
+    }
+}
+
 #[derive(Clone)]
 pub struct ClaimedInterrupt {
     desc: InterruptDescriptor,
     manager: Arc<InterruptManager>,
     handler: Weak<dyn InterruptHandler>,
+    thread: Option<CondVar<IrqThreadState>>,
+}
+
+impl ClaimedInterrupt {
+    /// Whether the interrupt was claimed as a threaded one, so the handler's
+    /// `handle_irq_threaded` will run.
+    pub fn is_threaded(&self) -> bool {
+        self.thread.is_some()
+    }
+
+    /// Disables the interrupt and gives up the claim on it. This otherwise
+    /// happens when the handle is dropped.
+    pub fn release(&self) {
+        self.manager.remove_interrupt(self.desc, &self.handler);
+    }
 }
 
 impl Drop for ClaimedInterrupt {
     fn drop(&mut self) {
-        self.manager.remove_interrupt(self.desc);
+        self.manager.remove_interrupt(self.desc, &self.handler);
     }
 }
 
@@ -210,3 +580,254 @@ pub fn set_interrupt_root(root: Arc<InterruptManager>) {
 pub fn get_interrupt_root() -> Option<Arc<InterruptManager>> {
     ROOT_INTERRUPT_CONTROLLER.get().cloned()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ktest;
+
+    struct MockContext(InterruptDescriptor);
+
+    impl InterruptContext for MockContext {
+        fn descriptor(&self) -> InterruptDescriptor {
+            self.0
+        }
+    }
+
+    /// Reports `pending` as active every time it's asked.
+    struct MockController {
+        pending: InterruptDescriptor,
+        /// Whether `pending` is unmasked.
+        enabled: bool,
+    }
+
+    impl MockController {
+        fn new(pending: InterruptDescriptor) -> Self {
+            Self {
+                pending,
+                enabled: false,
+            }
+        }
+    }
+
+    impl InterruptController for MockController {
+        fn enable_interrupt(&mut self, i: InterruptConfig) {
+            if i.descriptor == self.pending {
+                self.enabled = true;
+            }
+        }
+
+        fn disable_interrupt(&mut self, i: InterruptDescriptor) {
+            if i == self.pending {
+                self.enabled = false;
+            }
+        }
+
+        fn read_active_interrupt(&mut self) -> Option<Box<dyn InterruptContext>> {
+            Some(Box::new(MockContext(self.pending)))
+        }
+
+        fn raise_ipi(&mut self, _target_cpu_id: usize) {}
+
+        fn enable_core(&mut self, _cpu_id: usize) {}
+
+        fn parse_fdt_interrupt_regs(
+            &self,
+            _iter: &mut dyn Iterator<Item = u32>,
+        ) -> Result<InterruptConfig> {
+            Err(KernelError::NotSupported)
+        }
+    }
+
+    struct MockDevice {
+        irq: ClaimedInterrupt,
+        fired: AtomicUsize,
+        threaded: AtomicUsize,
+        oneshot: bool,
+    }
+
+    impl Driver for MockDevice {
+        fn name(&self) -> &'static str {
+            "mock-irq-device"
+        }
+    }
+
+    impl InterruptHandler for MockDevice {
+        fn handle_irq(&self, _desc: InterruptDescriptor) {
+            self.fired.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn handle_irq_threaded(&self, _desc: InterruptDescriptor) {
+            self.threaded.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn oneshot(&self) -> bool {
+            self.oneshot
+        }
+    }
+
+    fn mock_device(irq: ClaimedInterrupt) -> MockDevice {
+        MockDevice {
+            irq,
+            fired: AtomicUsize::new(0),
+            threaded: AtomicUsize::new(0),
+            oneshot: true,
+        }
+    }
+
+    ktest! {
+        fn irq_stats_count_handled_interrupts() {
+            const FIRES: usize = 5;
+            let desc = InterruptDescriptor::Spi(900);
+
+            let manager = InterruptManager::new(
+                "mock-intc",
+                Arc::new(SpinLock::new(MockController::new(desc))),
+            );
+            let device = manager
+                .claim_interrupt(
+                    InterruptConfig {
+                        descriptor: desc,
+                        trigger: TriggerMode::LevelHigh,
+                    },
+                    mock_device,
+                )
+                .unwrap();
+
+            // The counters are global, so only look at what this test adds.
+            let before = manager.irq_stats()[0].total();
+
+            for _ in 0..FIRES {
+                manager.handle_interrupt();
+            }
+
+            let stats = manager.irq_stats();
+            assert_eq!(stats.len(), 1);
+            assert_eq!(stats[0].desc, desc);
+            assert_eq!(stats[0].total() - before, FIRES);
+            assert_eq!(device.fired.load(Ordering::Relaxed), FIRES);
+        }
+    }
+
+    ktest! {
+        fn released_irq_handle_leaves_new_claim_alone() {
+            let desc = InterruptDescriptor::Spi(902);
+            let controller = Arc::new(SpinLock::new(MockController::new(desc)));
+            let manager = InterruptManager::new("mock-intc", controller.clone());
+            let config = InterruptConfig {
+                descriptor: desc,
+                trigger: TriggerMode::LevelHigh,
+            };
+
+            let old = manager.claim_interrupt(config, mock_device).unwrap();
+            old.irq.release();
+            assert!(!controller.lock_save_irq().enabled);
+
+            let new = manager.claim_interrupt(config, mock_device).unwrap();
+
+            // Neither releasing the old handle again nor dropping it touches
+            // the new claim.
+            old.irq.release();
+            drop(old);
+            assert!(controller.lock_save_irq().enabled);
+
+            manager.handle_interrupt();
+            assert_eq!(new.fired.load(Ordering::Relaxed), 1);
+        }
+    }
+
+    ktest! {
+        fn threaded_irq_runs_bottom_half_after_top_half() {
+            let desc = InterruptDescriptor::Spi(901);
+            let controller = Arc::new(SpinLock::new(MockController::new(desc)));
+            let manager = InterruptManager::new("mock-intc", controller.clone());
+
+            let (device, thread) = manager
+                .claim(
+                    InterruptConfig {
+                        descriptor: desc,
+                        trigger: TriggerMode::LevelHigh,
+                    },
+                    mock_device,
+                    true,
+                )
+                .unwrap();
+
+            // Stand in for the scheduler, polling the thread's body.
+            let mut thread = thread.unwrap();
+            let mut poll_thread = || {
+                thread
+                    .as_mut()
+                    .poll(&mut core::task::Context::from_waker(core::task::Waker::noop()))
+            };
+
+            assert!(poll_thread().is_pending());
+            assert_eq!(device.threaded.load(Ordering::Relaxed), 0);
+            assert!(controller.lock_save_irq().enabled);
+
+            manager.handle_interrupt();
+
+            // The top half ran and masked the interrupt, the bottom half is
+            // waiting for the thread.
+            assert_eq!(device.fired.load(Ordering::Relaxed), 1);
+            assert_eq!(device.threaded.load(Ordering::Relaxed), 0);
+            assert!(!controller.lock_save_irq().enabled);
+
+            assert!(poll_thread().is_pending());
+            assert_eq!(device.threaded.load(Ordering::Relaxed), 1);
+            assert!(controller.lock_save_irq().enabled);
+
+            // Releasing the interrupt stops the thread.
+            drop(device);
+            assert!(poll_thread().is_ready());
+            assert!(!controller.lock_save_irq().enabled);
+        }
+    }
+
+    ktest! {
+        fn threaded_irq_without_oneshot_stays_unmasked() {
+            let desc = InterruptDescriptor::Spi(902);
+            let controller = Arc::new(SpinLock::new(MockController::new(desc)));
+            let manager = InterruptManager::new("mock-intc", controller.clone());
+
+            let (device, thread) = manager
+                .claim(
+                    InterruptConfig {
+                        descriptor: desc,
+                        trigger: TriggerMode::LevelHigh,
+                    },
+                    |irq| MockDevice {
+                        oneshot: false,
+                        ..mock_device(irq)
+                    },
+                    true,
+                )
+                .unwrap();
+
+            let mut thread = thread.unwrap();
+            let mut poll_thread = || {
+                thread
+                    .as_mut()
+                    .poll(&mut core::task::Context::from_waker(core::task::Waker::noop()))
+            };
+
+            assert!(poll_thread().is_pending());
+
+            // The line stays enabled, so the top half keeps running before the
+            // thread gets a look in.
+            manager.handle_interrupt();
+            assert!(controller.lock_save_irq().enabled);
+            manager.handle_interrupt();
+            assert_eq!(device.fired.load(Ordering::Relaxed), 2);
+            assert_eq!(device.threaded.load(Ordering::Relaxed), 0);
+
+            assert!(poll_thread().is_pending());
+            assert_eq!(device.threaded.load(Ordering::Relaxed), 1);
+            assert!(controller.lock_save_irq().enabled);
+
+            drop(device);
+            assert!(poll_thread().is_ready());
+            assert!(!controller.lock_save_irq().enabled);
+        }
+    }
+}
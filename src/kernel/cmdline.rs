@@ -0,0 +1,153 @@
+//! Kernel boot parameters, taken from the `bootargs` of the FDT's `/chosen`
+//! node.
+//!
+//! Parameters are separated by whitespace and are either `key=value` pairs or
+//! bare flags. Double quotes may be used anywhere in a parameter to include
+//! whitespace, as in `key="a value"`, and are removed. Should a key be given
+//! more than once, the last occurrence wins.
+
+use crate::sync::OnceLock;
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+use log::warn;
+
+/// Maps each key to its value, or `None` for a bare flag.
+type Params = BTreeMap<String, Option<String>>;
+
+static PARAMS: OnceLock<Params> = OnceLock::new();
+
+/// Splits `cmdline` into parameters, honouring quotes.
+fn split(cmdline: &str) -> Vec<String> {
+    let mut params = Vec::new();
+    let mut param = String::new();
+    let mut in_param = false;
+    let mut quoted = false;
+
+    for c in cmdline.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_param = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_param {
+                    params.push(core::mem::take(&mut param));
+                    in_param = false;
+                }
+            }
+            c => {
+                param.push(c);
+                in_param = true;
+            }
+        }
+    }
+
+    // An unterminated quote runs to the end of the line.
+    if in_param {
+        params.push(param);
+    }
+
+    params
+}
+
+fn parse(cmdline: &str) -> Params {
+    split(cmdline)
+        .into_iter()
+        .map(|param| match param.split_once('=') {
+            Some((key, value)) => (key.to_string(), Some(value.to_string())),
+            None => (param, None),
+        })
+        .collect()
+}
+
+/// Parses the kernel command line. Must be called once, early during boot,
+/// before any parameter is looked up.
+pub fn init(cmdline: &str) {
+    if PARAMS.set(parse(cmdline)).is_err() {
+        panic!("Kernel command line parsed twice");
+    }
+}
+
+/// Returns the value given for `key`, or `None` if it wasn't given a value.
+pub fn get(key: &str) -> Option<&'static str> {
+    PARAMS.get()?.get(key)?.as_deref()
+}
+
+/// Returns whether `key` was given, either as a flag or with a value.
+pub fn has(key: &str) -> bool {
+    PARAMS.get().is_some_and(|params| params.contains_key(key))
+}
+
+/// The block device to mount the root filesystem from, named by
+/// `--rootdev=<name>`.
+pub fn root_dev() -> Option<&'static str> {
+    get("--rootdev")
+}
+
+/// Mount options for the root filesystem, set by `--rootflags=<options>`.
+pub fn root_flags() -> &'static str {
+    get("--rootflags").unwrap_or("")
+}
+
+/// The readahead window in pages, set by `--readahead=<pages>`.
+pub fn readahead_pages() -> Option<usize> {
+    let pages = get("--readahead")?;
+
+    pages
+        .parse()
+        .inspect_err(|_| warn!("Invalid readahead window {pages}, ignoring."))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ktest;
+
+    fn param<'a>(params: &'a Params, key: &str) -> Option<Option<&'a str>> {
+        params.get(key).map(|value| value.as_deref())
+    }
+
+    ktest! {
+        fn cmdline_keys_and_flags() {
+            let params = parse("console=ttyS1  loglevel=debug quiet\troot=/dev/ram0 ro");
+
+            assert_eq!(params.len(), 5);
+            assert_eq!(param(&params, "console"), Some(Some("ttyS1")));
+            assert_eq!(param(&params, "loglevel"), Some(Some("debug")));
+            assert_eq!(param(&params, "root"), Some(Some("/dev/ram0")));
+            assert_eq!(param(&params, "quiet"), Some(None));
+            assert_eq!(param(&params, "ro"), Some(None));
+            assert_eq!(param(&params, "rw"), None);
+
+            assert!(parse("").is_empty());
+            assert!(parse("   ").is_empty());
+        }
+    }
+
+    ktest! {
+        fn cmdline_quotes_and_edge_cases() {
+            let params = parse(
+                r#"init.arg="hello world" "dyndbg=file foo.c +p" empty= a=b=c last=1 last=2 x="unterminated"#,
+            );
+
+            assert_eq!(param(&params, "init.arg"), Some(Some("hello world")));
+            assert_eq!(param(&params, "dyndbg"), Some(Some("file foo.c +p")));
+            assert_eq!(param(&params, "empty"), Some(Some("")));
+            // Only the first `=` separates the key from the value.
+            assert_eq!(param(&params, "a"), Some(Some("b=c")));
+            assert_eq!(param(&params, "last"), Some(Some("2")));
+            assert_eq!(param(&params, "x"), Some(Some("unterminated")));
+
+            // The options the kernel already takes parse as well.
+            let params = parse("--init=/bin/sh --rootfs=ext4fs --no-zero-user-pages");
+
+            assert_eq!(param(&params, "--init"), Some(Some("/bin/sh")));
+            assert_eq!(param(&params, "--rootfs"), Some(Some("ext4fs")));
+            assert_eq!(param(&params, "--no-zero-user-pages"), Some(None));
+        }
+    }
+}
@@ -2,6 +2,9 @@ use libkernel::CpuOps;
 
 use crate::arch::ArchImpl;
 
+/// A logical CPU number. The boot CPU is 0 and the rest are numbered densely
+/// from there, whatever the hardware calls them, so an id can index per-CPU
+/// arrays.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub struct CpuId(usize);
 
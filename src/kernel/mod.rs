@@ -1,7 +1,8 @@
+pub mod cmdline;
 pub mod cpu_id;
 pub mod hostname;
 pub mod kpipe;
 pub mod power;
-pub mod rand;
+pub mod random;
 pub mod sysinfo;
 pub mod uname;
@@ -0,0 +1,401 @@
+//! The kernel's random number generator.
+//!
+//! Output is drawn from a ChaCha20 CSPRNG. The generator is seeded at boot from
+//! the CPU's hardware RNG where there is one, otherwise from jitter in the
+//! system timer, and a kernel thread reseeds it every [`RESEED_INTERVAL`].
+//!
+//! Reseeds also draw on the timing of interrupts. Each CPU mixes the counter
+//! value at every IRQ into its own `IrqEntropy` using only relaxed atomics,
+//! so the IRQ path never takes a lock; the kernel thread folds the per-CPU
+//! contributions into the global `EntropyPool` every [`FOLD_INTERVAL`].
+
+use crate::{
+    arch::{Arch, ArchImpl},
+    drivers::timer::{now, sleep},
+    memory::uaccess::copy_to_user_slice,
+    per_cpu_shared,
+    process::kthread::spawn_kthread,
+    sync::{CondVar, OnceLock, SpinLock},
+};
+use core::{
+    hint::black_box,
+    mem,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+use libkernel::{
+    error::{KernelError, Result},
+    memory::address::TUA,
+    sync::condvar::WakeupType,
+};
+use log::{debug, info};
+use rand_chacha::{
+    ChaCha20Rng,
+    rand_core::{RngCore, SeedableRng},
+};
+
+const RESEED_INTERVAL: Duration = Duration::from_secs(60);
+
+const FOLD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Words of interrupt timing kept per CPU and in the global pool.
+const POOL_WORDS: usize = 4;
+
+/// Interrupt samples needed to credit one bit of entropy. Only the low bits of
+/// each timestamp are unpredictable, so this is deliberately conservative.
+const SAMPLES_PER_BIT: usize = 64;
+
+/// Bytes generated per pool lock, so large requests don't hold it for long.
+const CHUNK_SZ: usize = 256;
+
+/// Timer samples folded into each word of a jitter-derived seed.
+const JITTER_SAMPLES: usize = 64;
+
+type Seed = <ChaCha20Rng as SeedableRng>::Seed;
+
+bitflags::bitflags! {
+    #[derive(Clone, Copy, Debug)]
+    pub struct GetRandomFlags: u32 {
+        const GRND_NONBLOCK = 0x1;
+        const GRND_RANDOM   = 0x2;
+        const GRND_INSECURE = 0x4;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SeedSource {
+    Hardware,
+    TimerJitter,
+}
+
+/// Interrupt timings gathered by a single CPU since the last fold.
+#[derive(Default)]
+struct IrqEntropy {
+    words: [AtomicU64; POOL_WORDS],
+    samples: AtomicUsize,
+}
+
+impl IrqEntropy {
+    fn mix(&self, ticks: u64) {
+        let n = self.samples.fetch_add(1, Ordering::Relaxed);
+
+        // Spread successive samples across the words and bit positions, since
+        // their low bits are where the entropy lies.
+        self.words[n % POOL_WORDS].fetch_xor(
+            ticks.rotate_left((n as u32).wrapping_mul(7)),
+            Ordering::Relaxed,
+        );
+    }
+}
+
+per_cpu_shared! {
+    static IRQ_ENTROPY: IrqEntropy = IrqEntropy::default;
+}
+
+/// Interrupt timings folded in from every CPU, awaiting the next reseed.
+struct EntropyPool {
+    words: [u64; POOL_WORDS],
+    samples: usize,
+}
+
+impl EntropyPool {
+    const fn new() -> Self {
+        Self {
+            words: [0; POOL_WORDS],
+            samples: 0,
+        }
+    }
+
+    /// Moves `cpu`'s contribution into the pool, leaving it empty.
+    fn fold(&mut self, cpu: &IrqEntropy) {
+        for (word, contrib) in self.words.iter_mut().zip(&cpu.words) {
+            *word = word.rotate_left(17) ^ contrib.swap(0, Ordering::Relaxed);
+        }
+
+        self.samples += cpu.samples.swap(0, Ordering::Relaxed);
+    }
+
+    fn entropy_bits(&self) -> usize {
+        (self.samples / SAMPLES_PER_BIT).min(POOL_WORDS * u64::BITS as usize)
+    }
+
+    fn take(&mut self) -> [u64; POOL_WORDS] {
+        self.samples = 0;
+        mem::take(&mut self.words)
+    }
+}
+
+static ENTROPY: SpinLock<EntropyPool> = SpinLock::new(EntropyPool::new());
+
+/// Mixes the time of the current interrupt into this CPU's entropy. Called on
+/// every IRQ, so must stay cheap.
+pub fn add_interrupt_randomness() {
+    IRQ_ENTROPY.get().mix(ticks());
+}
+
+/// Returns an estimate of the bits of interrupt entropy gathered since the
+/// last reseed.
+pub fn entropy_bits() -> usize {
+    ENTROPY.lock_save_irq().entropy_bits()
+}
+
+fn fold_irq_entropy() {
+    let mut pool = ENTROPY.lock_save_irq();
+
+    for cpu in 0..ArchImpl::cpu_count() {
+        pool.fold(IRQ_ENTROPY.get_by_cpu(cpu));
+    }
+}
+
+static POOL: OnceLock<CondVar<Option<ChaCha20Rng>>> = OnceLock::new();
+
+fn pool() -> &'static CondVar<Option<ChaCha20Rng>> {
+    POOL.get_or_init(|| CondVar::new(None))
+}
+
+fn ticks() -> u64 {
+    now().map(|now| now.ticks()).unwrap_or(0)
+}
+
+/// Derives a word of entropy from the time taken by short, data-dependent
+/// spins, which varies with cache, pipeline and interrupt state.
+fn jitter_word() -> u64 {
+    let mut acc = ticks();
+
+    for _ in 0..JITTER_SAMPLES {
+        let start = ticks();
+
+        for i in 0..(acc & 0xff) {
+            black_box(i);
+        }
+
+        acc = acc.rotate_left(7) ^ ticks().wrapping_sub(start);
+    }
+
+    acc
+}
+
+/// Builds a seed from `hw_random`, falling back to timer jitter for any word
+/// the hardware can't provide.
+fn gather_seed(hw_random: impl Fn() -> Option<u64>) -> (Seed, SeedSource) {
+    let mut seed = Seed::default();
+    let mut source = SeedSource::Hardware;
+
+    for word in seed.chunks_exact_mut(8) {
+        let val = hw_random().unwrap_or_else(|| {
+            source = SeedSource::TimerJitter;
+            jitter_word()
+        });
+
+        word.copy_from_slice(&val.to_le_bytes());
+    }
+
+    (seed, source)
+}
+
+fn reseed() -> SeedSource {
+    let (mut seed, source) = gather_seed(ArchImpl::hw_random);
+
+    fold_irq_entropy();
+    debug!(
+        "Reseeding with ~{} bits of interrupt entropy",
+        entropy_bits()
+    );
+
+    let irq_words = ENTROPY.lock_save_irq().take();
+
+    for (word, irq) in seed.chunks_exact_mut(8).zip(irq_words) {
+        for (byte, irq) in word.iter_mut().zip(irq.to_le_bytes()) {
+            *byte ^= irq;
+        }
+    }
+
+    pool().update(|rng| {
+        // Fold in output from the old generator, so that a reseed from a
+        // weaker source can't lose the entropy already gathered.
+        if let Some(old_rng) = rng.as_mut() {
+            let mut old = Seed::default();
+            old_rng.fill_bytes(&mut old);
+
+            for (new, old) in seed.iter_mut().zip(old) {
+                *new ^= old;
+            }
+        }
+
+        *rng = Some(ChaCha20Rng::from_seed(seed));
+
+        WakeupType::All
+    });
+
+    source
+}
+
+/// Seeds the pool and starts the kernel thread that periodically reseeds it.
+pub fn random_init() -> Result<()> {
+    let source = reseed();
+
+    info!("Random number generator seeded from {source:?}");
+
+    spawn_kthread("random", async {
+        let folds_per_reseed = RESEED_INTERVAL.as_secs() / FOLD_INTERVAL.as_secs();
+
+        loop {
+            for _ in 0..folds_per_reseed {
+                sleep(FOLD_INTERVAL).await;
+                fold_irq_entropy();
+            }
+
+            reseed();
+        }
+    })?;
+
+    Ok(())
+}
+
+fn is_seeded() -> bool {
+    let mut seeded = false;
+
+    pool().update(|rng| {
+        seeded = rng.is_some();
+        WakeupType::None
+    });
+
+    seeded
+}
+
+/// Fills `buf` from the pool. Returns `false` if the pool isn't seeded yet.
+fn try_fill_bytes(buf: &mut [u8]) -> bool {
+    let mut seeded = false;
+
+    pool().update(|rng| {
+        if let Some(rng) = rng {
+            rng.fill_bytes(buf);
+            seeded = true;
+        }
+
+        WakeupType::None
+    });
+
+    seeded
+}
+
+/// Fills `buf` with random bytes for the kernel's own use, such as `AT_RANDOM`.
+pub fn fill_random_bytes(buf: &mut [u8]) {
+    // The pool is seeded early in boot, but make sure of it rather than hand
+    // out zeroes.
+    if !try_fill_bytes(buf) {
+        reseed();
+        try_fill_bytes(buf);
+    }
+}
+
+pub async fn sys_getrandom(ubuf: TUA<u8>, count: usize, flags: u32) -> Result<usize> {
+    let flags = GetRandomFlags::from_bits(flags).ok_or(KernelError::InvalidValue)?;
+
+    if flags.contains(GetRandomFlags::GRND_RANDOM | GetRandomFlags::GRND_INSECURE) {
+        return Err(KernelError::InvalidValue);
+    }
+
+    if !is_seeded() {
+        // There's no unseeded output to hand out, so GRND_INSECURE can't do
+        // better than GRND_NONBLOCK.
+        if flags.intersects(GetRandomFlags::GRND_NONBLOCK | GetRandomFlags::GRND_INSECURE) {
+            return Err(KernelError::TryAgain);
+        }
+
+        pool().wait_until(|rng| rng.is_some().then_some(())).await;
+    }
+
+    let mut chunk = [0u8; CHUNK_SZ];
+    let mut written = 0;
+
+    while written < count {
+        let len = CHUNK_SZ.min(count - written);
+
+        try_fill_bytes(&mut chunk[..len]);
+        copy_to_user_slice(&chunk[..len], ubuf.to_untyped().add_bytes(written)).await?;
+
+        written += len;
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ktest;
+
+    ktest! {
+        fn outputs_are_distinct() {
+            let mut a = [0u8; 64];
+            let mut b = [0u8; 64];
+
+            assert!(try_fill_bytes(&mut a));
+            assert!(try_fill_bytes(&mut b));
+            assert_ne!(a, b);
+
+            // Roughly half of a large sample's bits should be set.
+            let mut sample = [0u8; 4096];
+            assert!(try_fill_bytes(&mut sample));
+            let ones: u32 = sample.iter().map(|b| b.count_ones()).sum();
+            let bits = sample.len() as u32 * 8;
+            assert!((bits * 45 / 100..bits * 55 / 100).contains(&ones));
+        }
+    }
+
+    ktest! {
+        fn fold_combines_cpus_deterministically() {
+            let cpus = [IrqEntropy::default(), IrqEntropy::default()];
+            let fold_all = || {
+                let mut pool = EntropyPool::new();
+                for cpu in &cpus {
+                    pool.fold(cpu);
+                }
+                pool
+            };
+
+            let mix_all = || {
+                for t in 0..SAMPLES_PER_BIT as u64 {
+                    cpus[0].mix(t * 3);
+                    cpus[1].mix(t * 5 + 1);
+                }
+            };
+
+            mix_all();
+            let mut a = fold_all();
+
+            // Folding drains the per-CPU contributions.
+            assert!(cpus.iter().all(|cpu| cpu.samples.load(Ordering::Relaxed) == 0));
+            assert_eq!(fold_all().words, [0; POOL_WORDS]);
+
+            mix_all();
+            let mut b = fold_all();
+
+            assert_eq!(a.entropy_bits(), 2);
+
+            let (words_a, words_b) = (a.take(), b.take());
+            assert_eq!(words_a, words_b);
+            assert_ne!(words_a, [0; POOL_WORDS]);
+            assert_eq!(a.entropy_bits(), 0);
+        }
+    }
+
+    ktest! {
+        fn falls_back_to_jitter_without_hw_rng() {
+            let (seed, source) = gather_seed(|| None);
+
+            assert_eq!(source, SeedSource::TimerJitter);
+            assert_ne!(seed, Seed::default());
+        }
+    }
+
+    ktest! {
+        fn uses_hw_rng_when_present() {
+            let (seed, source) = gather_seed(|| Some(0x0123_4567_89ab_cdef));
+
+            assert_eq!(source, SeedSource::Hardware);
+            assert_eq!(seed[..8], 0x0123_4567_89ab_cdefu64.to_le_bytes());
+        }
+    }
+}
@@ -12,18 +12,30 @@
 use alloc::{
     boxed::Box,
     string::{String, ToString},
+    sync::Arc,
     vec,
     vec::Vec,
 };
 use arch::{Arch, ArchImpl};
-use core::panic::PanicInfo;
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, Ordering},
+};
 use drivers::{fdt_prober::get_fdt, fs::register_fs_drivers};
-use fs::VFS;
+use fs::{
+    VFS,
+    writeback::{BlockCache, spawn_writeback},
+};
 use getargs::{Opt, Options};
+use interrupts::start_irq_threads;
+use kernel::random::random_init;
 use libkernel::{
     CpuOps, VirtualMemory,
     fs::{
-        BlockDevice, OpenFlags, attr::FilePermissions, blk::ramdisk::RamdiskBlkDev, path::Path,
+        BlockDevice, OpenFlags,
+        attr::FilePermissions,
+        blk::{cache::CacheConfig, ramdisk::RamdiskBlkDev},
+        path::Path,
         pathbuf::PathBuf,
     },
     memory::{
@@ -32,6 +44,7 @@ use libkernel::{
     },
 };
 use log::{error, warn};
+use memory::reclaim::reclaim_init;
 use process::ctx::UserCtx;
 use sched::{
     current::current_task_shared, sched_init, spawn_kernel_work, uspc_ret::dispatch_userspace_task,
@@ -71,18 +84,46 @@ fn on_panic(info: &PanicInfo) -> ! {
         error!("Kernel panicked at unknown location: {panic_msg}");
     }
 
-    ArchImpl::power_off();
+    // A fault while dumping state would otherwise recurse forever; just stop.
+    if PANICKING.swap(true, Ordering::AcqRel) {
+        ArchImpl::halt();
+    }
+
+    interrupts::cpu_messenger::stop_other_cpus();
+    ArchImpl::dump_panic_context();
+
+    // Tests rely on the machine exiting so that the runner sees the failure.
+    if cfg!(test) {
+        ArchImpl::power_off();
+    }
+
+    ArchImpl::halt();
 }
 
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
 async fn launch_init(mut opts: KOptions) {
+    start_irq_threads();
+    random_init().expect("Could not start the random number generator");
+    reclaim_init().expect("Could not start the reclaim thread");
+
     let init = opts
         .init
         .unwrap_or_else(|| panic!("No init specified in kernel command line"));
 
     let dt = get_fdt();
+    let root_dev = kernel::cmdline::root_dev();
+
+    let root_block_dev: Option<Box<dyn BlockDevice>> = if let Some(name) = root_dev {
+        let dev = drivers::blk::find_block_device(name)
+            .unwrap_or_else(|| panic!("No block device named {name}"));
+
+        let cache = Arc::new(BlockCache::new(Box::new(dev), CacheConfig::default()));
 
-    let initrd_block_dev: Option<Box<dyn BlockDevice>> = if let Some(chosen) =
-        dt.find_nodes("/chosen").next()
+        spawn_writeback(cache.clone()).expect("Could not start writeback thread");
+
+        Some(Box::new(cache))
+    } else if let Some(chosen) = dt.find_nodes("/chosen").next()
         && let Some(start_addr) = chosen
             .find_property("linux,initrd-start")
             .map(|prop| prop.u64())
@@ -95,14 +136,18 @@ async fn launch_init(mut opts: KOptions) {
             PA::from_value(end_addr as _),
         );
 
-        Some(Box::new(
-            RamdiskBlkDev::new(
-                region,
-                VA::from_value(0xffff_9800_0000_0000),
-                &mut *ArchImpl::kern_address_space().lock_save_irq(),
-            )
-            .unwrap(),
-        ))
+        let ramdisk = RamdiskBlkDev::new(
+            region,
+            VA::from_value(0xffff_9800_0000_0000),
+            &mut *ArchImpl::kern_address_space().lock_save_irq(),
+        )
+        .unwrap();
+
+        let cache = Arc::new(BlockCache::new(Box::new(ramdisk), CacheConfig::default()));
+
+        spawn_writeback(cache.clone()).expect("Could not start writeback thread");
+
+        Some(Box::new(cache))
     } else {
         None
     };
@@ -111,7 +156,7 @@ async fn launch_init(mut opts: KOptions) {
         .root_fs
         .unwrap_or_else(|| panic!("No root FS driver specified in kernel command line"));
 
-    VFS.mount_root(&root_fs, initrd_block_dev)
+    VFS.mount_root(&root_fs, root_block_dev, kernel::cmdline::root_flags())
         .await
         .unwrap_or_else(|e| panic!("Failed to mount root FS: {}", e));
 
@@ -122,7 +167,7 @@ async fn launch_init(mut opts: KOptions) {
             .await
             .unwrap_or_else(|e| panic!("Could not find automount path: {}. {e}", path.as_str()));
 
-        VFS.mount(mount_point, fs, None)
+        VFS.mount(mount_point, fs, None, "")
             .await
             .unwrap_or_else(|e| panic!("Automount failed: {e}"));
     }
@@ -205,6 +250,13 @@ fn parse_args(args: &str) -> KOptions {
                 Opt::Long("init") => kopts.init = Some(PathBuf::from(opts.value().unwrap())),
                 Opt::Long("init-arg") => kopts.init_args.push(opts.value().unwrap().to_string()),
                 Opt::Long("rootfs") => kopts.root_fs = Some(opts.value().unwrap().to_string()),
+                Opt::Long("no-zero-user-pages") => {
+                    memory::page::ZERO_USER_PAGES.store(false, Ordering::Relaxed)
+                }
+                // Looked up through `kernel::cmdline`.
+                Opt::Long("rootdev" | "rootflags" | "readahead") => {
+                    let _ = opts.value();
+                }
                 Opt::Long("automount") => {
                     let string = opts.value().unwrap();
                     let mut split = string.split(",");
@@ -227,6 +279,12 @@ pub fn kmain(args: String, ctx_frame: *mut UserCtx) {
 
     register_fs_drivers();
 
+    interrupts::fiq::apply_cmdline_route();
+
+    if let Some(pages) = kernel::cmdline::readahead_pages() {
+        fs::readahead::READAHEAD_PAGES.store(pages, Ordering::Relaxed);
+    }
+
     let kopts = parse_args(&args);
 
     spawn_kernel_work(launch_init(kopts));
@@ -1,8 +1,12 @@
 use core::convert::Infallible;
 
-use libkernel::memory::address::VA;
+use libkernel::memory::{PAGE_SIZE, address::VA};
 
-use crate::sched::current::current_task;
+use crate::{
+    memory::page::free_user_page,
+    process::{ProcVM, thread_group::rsrc_lim::RlimitId},
+    sched::current::current_task,
+};
 
 /// Handles the `brk` system call.
 ///
@@ -18,28 +22,115 @@ use crate::sched::current::current_task;
 /// error type.
 /// - If `addr` is 0, it returns the current break.
 /// - On a successful resize, it returns the new break.
-/// - On a failed resize, it returns the current, unchanged break.
+/// - On a failed resize, it returns the current, unchanged break. Growing the
+///   address space past `RLIMIT_AS` fails.
 pub async fn sys_brk(addr: VA) -> Result<usize, Infallible> {
     let task = current_task();
+    let as_limit = task.process.rsrc_lim.lock_save_irq().get(RlimitId::AS);
     let mut vm = task.vm.lock_save_irq();
 
+    Ok(do_brk(&mut vm, addr, as_limit.rlim_cur))
+}
+
+fn do_brk(vm: &mut ProcVM, addr: VA, as_limit: u64) -> usize {
     // The query case `brk(0)` is special and is handled separately from modifications.
     if addr.is_null() {
-        let current_brk_val = vm.current_brk().value();
-        return Ok(current_brk_val as usize);
+        return vm.current_brk().value();
+    }
+
+    let growth = addr
+        .align_up(PAGE_SIZE)
+        .value()
+        .saturating_sub(vm.current_brk().value());
+
+    if (vm.mapped_bytes() + growth) as u64 > as_limit {
+        return vm.current_brk().value();
     }
 
     // For non-null addresses, attempt to resize the break.
-    let resize_result = vm.resize_brk(addr);
+    match vm.resize_brk(addr) {
+        // Success: The break was resized. Free any pages released by shrinking
+        // it, then return the new address.
+        Ok((new_brk, pages)) => {
+            for page in pages {
+                unsafe { free_user_page(page) };
+            }
 
-    match resize_result {
-        // Success: The break was resized. The function returns the new address.
-        Ok(new_brk) => Ok(new_brk.value()),
+            new_brk.value()
+        }
         // Failure: The resize was invalid (e.g., collision, shrink below start).
         // The contract is to return the current, unchanged break address.
-        Err(_) => {
-            let current_brk_val = vm.current_brk().value();
-            Ok(current_brk_val as usize)
+        Err(_) => vm.current_brk().value(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ktest,
+        memory::{
+            PAGE_ALLOC,
+            fault::{FaultResolution, handle_demand_fault},
+        },
+        process::thread_group::rsrc_lim::RLIM_INFINITY,
+        sync::SpinLock,
+    };
+    use alloc::sync::Arc;
+    use libkernel::{
+        UserAddressSpace,
+        memory::{
+            proc_vm::vmarea::{AccessKind, VMAPermissions, VMArea, VMAreaKind},
+            region::VirtMemoryRegion,
+        },
+    };
+
+    ktest! {
+        fn brk_grows_and_shrinks_heap() {
+            let text = VirtMemoryRegion::new(VA::from_value(0x40_0000), PAGE_SIZE);
+            let vm = Arc::new(SpinLock::new(
+                ProcVM::from_vma(VMArea::new(text, VMAreaKind::Anon, VMAPermissions::rx()))
+                    .unwrap(),
+            ));
+            let heap_start = text.end_address();
+
+            assert_eq!(do_brk(&mut vm.lock_save_irq(), VA::null(), RLIM_INFINITY), heap_start.value());
+
+            // Grow by two pages; the heap is mapped lazily on first touch.
+            let new_brk = heap_start.add_pages(2);
+            assert_eq!(do_brk(&mut vm.lock_save_irq(), new_brk, RLIM_INFINITY), new_brk.value());
+            assert_eq!(do_brk(&mut vm.lock_save_irq(), VA::null(), RLIM_INFINITY), new_brk.value());
+
+            let top_page = heap_start.add_pages(1);
+            assert!(matches!(
+                handle_demand_fault(vm.clone(), top_page, AccessKind::Write),
+                Ok(FaultResolution::Resolved)
+            ));
+
+            let mut vm = vm.lock_save_irq();
+            let pfn = vm
+                .mm_mut()
+                .address_space_mut()
+                .translate(top_page)
+                .unwrap()
+                .pfn;
+
+            // Shrinking must unmap and free the page.
+            assert_eq!(do_brk(&mut vm, top_page, RLIM_INFINITY), top_page.value());
+            assert!(vm.mm_mut().address_space_mut().translate(top_page).is_none());
+            assert!(vm.mm_mut().find_vma(top_page).is_none());
+            assert!(!PAGE_ALLOC.get().unwrap().is_allocated(pfn));
+
+            // The break can't go below where it started.
+            assert_eq!(do_brk(&mut vm, text.start_address(), RLIM_INFINITY), top_page.value());
+
+            // Nor grow the address space past RLIMIT_AS.
+            let limit = (vm.mapped_bytes() + PAGE_SIZE) as u64;
+            assert_eq!(do_brk(&mut vm, top_page.add_pages(2), limit), top_page.value());
+            assert_eq!(
+                do_brk(&mut vm, top_page.add_pages(1), limit),
+                top_page.add_pages(1).value()
+            );
         }
     }
 }
@@ -0,0 +1,117 @@
+//! Buffers for devices that do DMA.
+//!
+//! A DMA buffer is a block of physically contiguous frames straight from the
+//! buddy allocator, so a block of `2^order` pages is always aligned to its own
+//! size. The CPU reaches it through the kernel's linear map, which is cached,
+//! so drivers must call [`sync_for_device`] before a device reads the buffer
+//! and [`sync_for_cpu`] after a device has written to it.
+
+use super::{PAGE_ALLOC, PageOffsetTranslator};
+use crate::arch::{Arch, ArchImpl};
+use core::slice;
+use libkernel::{
+    error::{KernelError, Result},
+    memory::{
+        PAGE_SIZE,
+        address::{PA, VA},
+        region::PhysMemoryRegion,
+    },
+};
+
+/// The order of the smallest block of pages holding `size` bytes.
+fn order_for(size: usize) -> u8 {
+    size.div_ceil(PAGE_SIZE)
+        .next_power_of_two()
+        .trailing_zeros() as u8
+}
+
+/// Allocates a zeroed buffer of at least `size` bytes for DMA, returning the
+/// kernel address to access it through and the physical address to give the
+/// device. The buffer is aligned to its size, rounded up to a power of two
+/// pages.
+pub fn alloc_coherent(size: usize) -> Result<(VA, PA)> {
+    if size == 0 {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let order = order_for(size);
+    let pa = PAGE_ALLOC
+        .get()
+        .unwrap()
+        .alloc_frames(order)?
+        .leak()
+        .start_address();
+    let va = pa.to_va::<PageOffsetTranslator>();
+    let len = PAGE_SIZE << order;
+
+    unsafe { slice::from_raw_parts_mut(va.as_ptr_mut() as *mut u8, len) }.fill(0);
+
+    // No dirty lines may be left to be written back over what the device
+    // writes later.
+    ArchImpl::dcache_invalidate_range(va, len);
+
+    Ok((va, pa))
+}
+
+/// Frees a buffer from [`alloc_coherent`].
+///
+/// # Safety
+///
+/// `pa` and `size` must be those of a buffer from [`alloc_coherent`], which
+/// neither the CPU nor any device uses any more.
+pub unsafe fn free_coherent(pa: PA, size: usize) {
+    let region = PhysMemoryRegion::new(pa, PAGE_SIZE << order_for(size));
+
+    drop(unsafe { PAGE_ALLOC.get().unwrap().alloc_from_region(region) });
+}
+
+/// Makes what the CPU has written to `va..va + len` visible to devices.
+pub fn sync_for_device(va: VA, len: usize) {
+    ArchImpl::dcache_clean_range(va, len);
+}
+
+/// Makes what a device has written to `va..va + len` visible to the CPU.
+pub fn sync_for_cpu(va: VA, len: usize) {
+    ArchImpl::dcache_invalidate_range(va, len);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ktest;
+
+    ktest! {
+        fn dma_buffers_are_contiguous_and_aligned() {
+            assert!(matches!(alloc_coherent(0), Err(KernelError::InvalidValue)));
+
+            let size = 3 * PAGE_SIZE;
+            let (va, pa) = alloc_coherent(size).unwrap();
+            let block = 4 * PAGE_SIZE;
+            let page_alloc = PAGE_ALLOC.get().unwrap();
+
+            // Three pages come from an order 2 block, aligned to its size.
+            assert_eq!(pa.value() % block, 0);
+            assert_eq!(va, pa.to_va::<PageOffsetTranslator>());
+
+            // Every page of the block belongs to the one allocation.
+            let pfn = pa.to_pfn();
+            for i in 0..4 {
+                assert!(page_alloc.is_allocated(pfn.add_pages(i)));
+                assert_eq!(page_alloc.ref_count(pfn.add_pages(i)), 1);
+            }
+
+            let buf = unsafe { slice::from_raw_parts_mut(va.as_ptr_mut() as *mut u8, block) };
+            assert!(buf.iter().all(|b| *b == 0));
+            buf.fill(0x5a);
+            sync_for_device(va, block);
+            sync_for_cpu(va, block);
+            assert!(buf.iter().all(|b| *b == 0x5a));
+
+            unsafe { free_coherent(pa, size) };
+
+            for i in 0..4 {
+                assert!(!page_alloc.is_allocated(pfn.add_pages(i)));
+            }
+        }
+    }
+}
@@ -4,10 +4,18 @@ use alloc::sync::Arc;
 use libkernel::{
     PageInfo, UserAddressSpace,
     error::{KernelError, MapError, Result},
-    memory::{address::VA, permissions::PtePermissions, proc_vm::vmarea::AccessKind},
+    memory::{
+        address::VA,
+        permissions::PtePermissions,
+        proc_vm::vmarea::{AccessKind, VMArea, VMAreaKind},
+    },
 };
 
-use super::{PAGE_ALLOC, page::ClaimedPage};
+use super::{
+    PAGE_ALLOC,
+    page::{ClaimedPage, free_user_page},
+    shared::{mark_dirty, shared_page},
+};
 
 /// Represents the outcome of a page fault handling attempt.
 ///
@@ -51,9 +59,23 @@ pub fn handle_demand_fault(
     }
     .clone();
 
-    let mut new_page = ClaimedPage::alloc_zeroed()?;
     let page_va = faulting_addr.page_aligned();
 
+    if let VMAreaKind::File(mapping) = vma.kind()
+        && mapping.is_shared()
+    {
+        drop(vm);
+
+        return Ok(FaultResolution::Deferred(Box::new(handle_shared_fault(
+            proc_vm,
+            faulting_addr,
+            access_kind,
+            vma,
+        ))));
+    }
+
+    let mut new_page = ClaimedPage::alloc_zeroed()?;
+
     if let Some(vma_read) = vma.resolve_fault(faulting_addr) {
         drop(vm);
 
@@ -123,6 +145,65 @@ pub fn handle_demand_fault(
     }
 }
 
+/// Maps the page of a shared file mapping, which is the same page that every
+/// other shared mapping of that part of the file has.
+async fn handle_shared_fault(
+    proc_vm: Arc<SpinLock<ProcVM>>,
+    faulting_addr: VA,
+    access_kind: AccessKind,
+    vma: VMArea,
+) -> Result<()> {
+    let VMAreaKind::File(mapping) = vma.kind() else {
+        unreachable!("Shared mappings are always of files");
+    };
+
+    let page_va = faulting_addr.page_aligned();
+    let file_offset =
+        mapping.offset() + (page_va.value() - vma.region().start_address().value()) as u64;
+
+    let (pfn, writable) = shared_page(
+        &mapping.file(),
+        file_offset,
+        access_kind == AccessKind::Write,
+    )
+    .await?;
+
+    // As for private file mappings, the VMA must be revalidated after
+    // sleeping.
+    let mut vm = proc_vm.lock_save_irq();
+
+    let is_vma_still_valid = vm
+        .find_vma_for_fault(faulting_addr, access_kind)
+        .is_some_and(|validated_vma| *validated_vma == vma);
+
+    if !is_vma_still_valid {
+        unsafe { free_user_page(pfn) };
+        return Ok(());
+    }
+
+    let perms = if writable {
+        PtePermissions::from(vma.permissions())
+    } else {
+        vma.pte_permissions()
+    };
+
+    let res = vm
+        .mm_mut()
+        .address_space_mut()
+        .map_page(pfn, page_va, perms);
+
+    // Unless the mapping took it, our reference to the page is unused. That
+    // includes when another CPU has mapped the same page for us.
+    if res.is_err() {
+        unsafe { free_user_page(pfn) };
+    }
+
+    match res {
+        Err(KernelError::MappingError(MapError::AlreadyMapped)) => Ok(()),
+        res => res,
+    }
+}
+
 /// Handle a page fault when a page is present, but the access kind differ from
 /// permissible accesses defined in the PTE, a 'protection' fault.
 pub fn handle_protection_fault(
@@ -176,8 +257,34 @@ pub fn handle_protection_fault(
                 .remap(faulting_addr, new_page.leak(), new_pte_perms)
                 .unwrap();
 
+            // If the other sharers have gone in the meantime, this was the
+            // last reference to the old page.
+            unsafe { free_user_page(src_page.leak()) };
+
             Ok(FaultResolution::Resolved)
         }
+    } else if let Some(vma) = vm
+        .find_vma_for_fault(faulting_addr, access_kind)
+        .filter(|vma| access_kind == AccessKind::Write && vma.is_shared())
+    {
+        // The first write to a page of a shared mapping. Note that the page
+        // is dirty before letting it be written.
+        let VMAreaKind::File(mapping) = vma.kind() else {
+            unreachable!("Shared mappings are always of files");
+        };
+
+        let page_va = faulting_addr.page_aligned();
+        let file_offset =
+            mapping.offset() + (page_va.value() - vma.region().start_address().value()) as u64;
+        let perms = PtePermissions::from(vma.permissions());
+
+        mark_dirty(mapping.file().id(), file_offset);
+
+        vm.mm_mut()
+            .address_space_mut()
+            .protect_range(page_va.page_region(), perms)?;
+
+        Ok(FaultResolution::Resolved)
     } else {
         // Any other protection fault *should* be a segmentation fault. Let's
         // just verify.
@@ -186,3 +293,234 @@ pub fn handle_protection_fault(
         Ok(FaultResolution::Denied)
     }
 }
+
+/// Handle an access flag fault: the first access to a page, on a CPU that
+/// doesn't set the access flag itself.
+pub fn handle_access_flag_fault(vm: &mut ProcVM, faulting_addr: VA) -> Result<FaultResolution> {
+    match vm.mm_mut().address_space_mut().mark_accessed(faulting_addr) {
+        // If the page has been unmapped since, retrying the access takes a
+        // translation fault instead.
+        Ok(()) | Err(KernelError::MappingError(_)) => Ok(FaultResolution::Resolved),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fs::memfd, ktest, memory::PageOffsetTranslator};
+    use libkernel::memory::{
+        PAGE_SIZE,
+        proc_vm::vmarea::{VMAPermissions, VMArea, VMAreaKind},
+        region::VirtMemoryRegion,
+    };
+
+    ktest! {
+        fn write_to_mprotected_page_faults() {
+            let addr = VA::from_value(0x10_0000);
+            let vma = VMArea::new(
+                VirtMemoryRegion::new(addr, PAGE_SIZE),
+                VMAreaKind::Anon,
+                VMAPermissions::rw(),
+            );
+            let vm = Arc::new(SpinLock::new(ProcVM::from_vma(vma).unwrap()));
+
+            assert!(matches!(
+                handle_demand_fault(vm.clone(), addr, AccessKind::Write),
+                Ok(FaultResolution::Resolved)
+            ));
+
+            let mut vm = vm.lock_save_irq();
+            vm.mm_mut()
+                .mprotect(addr.page_region(), VMAPermissions::ro())
+                .unwrap();
+
+            let pg_info = vm.mm_mut().address_space_mut().translate(addr).unwrap();
+            assert!(pg_info.perms.is_read() && !pg_info.perms.is_write());
+
+            // A write to the page is now a protection fault, which must be
+            // refused.
+            assert!(matches!(
+                handle_protection_fault(&mut vm, addr, AccessKind::Write, pg_info),
+                Ok(FaultResolution::Denied)
+            ));
+        }
+    }
+
+    ktest! {
+        fn first_access_sets_access_flag() {
+            let addr = VA::from_value(0x10_0000);
+            let vma = VMArea::new(
+                VirtMemoryRegion::new(addr, PAGE_SIZE),
+                VMAreaKind::Anon,
+                VMAPermissions::rw(),
+            );
+            let vm = Arc::new(SpinLock::new(ProcVM::from_vma(vma).unwrap()));
+
+            assert!(matches!(
+                handle_demand_fault(vm.clone(), addr, AccessKind::Read),
+                Ok(FaultResolution::Resolved)
+            ));
+
+            let mut vm = vm.lock_save_irq();
+            let translate =
+                |vm: &mut ProcVM| vm.mm_mut().address_space_mut().translate(addr).unwrap();
+
+            // Nothing has used the mapping yet. Anonymous memory is writable
+            // from the start, so counts as dirty.
+            let pg_info = translate(&mut vm);
+            assert!(!pg_info.accessed);
+            assert!(pg_info.dirty);
+
+            // Without the hardware's help, the first access takes an access
+            // flag fault, which sets the flag and lets the access be retried.
+            assert!(matches!(
+                handle_access_flag_fault(&mut vm, addr.add_bytes(8)),
+                Ok(FaultResolution::Resolved)
+            ));
+            assert!(translate(&mut vm).accessed);
+
+            // There's nothing to do for a page that's gone.
+            assert!(matches!(
+                handle_access_flag_fault(&mut vm, addr.add_pages(1)),
+                Ok(FaultResolution::Resolved)
+            ));
+        }
+    }
+
+    ktest! {
+        async fn shared_mappings_see_each_others_writes() {
+            let inode = memfd::create_inode().unwrap();
+            inode.truncate(PAGE_SIZE as u64).await.unwrap();
+
+            let map = |addr| {
+                let vma = VMArea::new(
+                    VirtMemoryRegion::new(addr, PAGE_SIZE),
+                    VMAreaKind::new_shared_file(inode.clone(), 0, PAGE_SIZE as u64),
+                    VMAPermissions::rw(),
+                );
+
+                Arc::new(SpinLock::new(ProcVM::from_vma(vma).unwrap()))
+            };
+
+            let addr_a = VA::from_value(0x10_0000);
+            let addr_b = VA::from_value(0x20_0000);
+            let vm_a = map(addr_a);
+            let vm_b = map(addr_b);
+
+            for (vm, addr) in [(&vm_a, addr_a), (&vm_b, addr_b)] {
+                let Ok(FaultResolution::Deferred(fut)) =
+                    handle_demand_fault(vm.clone(), addr, AccessKind::Write)
+                else {
+                    panic!("Faults on shared mappings should be deferred");
+                };
+
+                Box::into_pin(fut).await.unwrap();
+            }
+
+            let translate = |vm: &Arc<SpinLock<ProcVM>>, addr| {
+                vm.lock_save_irq()
+                    .mm_mut()
+                    .address_space_mut()
+                    .translate(addr)
+                    .unwrap()
+            };
+
+            // Both address spaces map the file's own page, writable rather
+            // than CoW.
+            let pg_a = translate(&vm_a, addr_a);
+            let pg_b = translate(&vm_b, addr_b);
+            assert_eq!(pg_a.pfn, pg_b.pfn);
+            assert!(pg_a.perms.is_write() && !pg_a.perms.is_cow());
+
+            // What's written through one mapping is seen through the other,
+            // and by reads of the file.
+            let page = unsafe {
+                core::slice::from_raw_parts_mut(
+                    pg_a.pfn.pa().to_va::<PageOffsetTranslator>().as_ptr_mut() as *mut u8,
+                    PAGE_SIZE,
+                )
+            };
+            page[..5].copy_from_slice(b"hello");
+
+            let mut buf = [0; 5];
+            inode.read_at(0, &mut buf).await.unwrap();
+            assert_eq!(&buf, b"hello");
+
+            // A forked child shares the page too. Its first write is noted,
+            // rather than copying the page.
+            let mut child = vm_a.lock_save_irq().clone_as_cow().unwrap();
+            let pg_child = child.mm_mut().address_space_mut().translate(addr_a).unwrap();
+            assert_eq!(pg_child.pfn, pg_a.pfn);
+            assert!(!pg_child.perms.is_write() && !pg_child.perms.is_cow());
+            assert!(!translate(&vm_a, addr_a).perms.is_cow());
+
+            assert!(matches!(
+                handle_protection_fault(&mut child, addr_a, AccessKind::Write, pg_child),
+                Ok(FaultResolution::Resolved)
+            ));
+            let pg_child = child.mm_mut().address_space_mut().translate(addr_a).unwrap();
+            assert_eq!(pg_child.pfn, pg_a.pfn);
+            assert!(pg_child.perms.is_write());
+
+            // The mappings keep the page alive after the file lets go of it,
+            // until the last of them is gone.
+            inode.truncate(0).await.unwrap();
+            assert!(PAGE_ALLOC.get().unwrap().is_allocated(pg_a.pfn));
+
+            drop((vm_a, vm_b, child));
+
+            assert!(!PAGE_ALLOC.get().unwrap().is_allocated(pg_a.pfn));
+        }
+    }
+
+    ktest! {
+        fn resident_pages_follow_faults() {
+            let addr = VA::from_value(0x10_0000);
+            let vma = VMArea::new(
+                VirtMemoryRegion::new(addr, 4 * PAGE_SIZE),
+                VMAreaKind::Anon,
+                VMAPermissions::rw(),
+            );
+            let vm = Arc::new(SpinLock::new(ProcVM::from_vma(vma).unwrap()));
+
+            for n in 0..3 {
+                handle_demand_fault(vm.clone(), addr.add_pages(n), AccessKind::Write).unwrap();
+            }
+            assert_eq!(vm.lock_save_irq().resident_pages(), 3);
+
+            // Pages shared copy-on-write count in both, and copying one on a
+            // write doesn't change that.
+            let mut parent = vm.lock_save_irq();
+            let mut child = parent.clone_as_cow().unwrap();
+            assert_eq!(child.resident_pages(), 3);
+
+            let pg_info = child.mm_mut().address_space_mut().translate(addr).unwrap();
+            handle_protection_fault(&mut child, addr, AccessKind::Write, pg_info).unwrap();
+            assert_eq!(child.resident_pages(), 3);
+            assert_eq!(parent.resident_pages(), 3);
+
+            let unmapped = parent.mm_mut().munmap(addr.page_region()).unwrap();
+            assert_eq!(parent.resident_pages(), 2);
+
+            for pfn in unmapped {
+                unsafe { free_user_page(pfn) };
+            }
+
+            drop(parent);
+
+            // Nothing is left once everything is unmapped.
+            let unmapped = child
+                .mm_mut()
+                .munmap(VirtMemoryRegion::new(addr, 4 * PAGE_SIZE))
+                .unwrap();
+            assert_eq!(child.resident_pages(), 0);
+
+            for pfn in unmapped {
+                unsafe { free_user_page(pfn) };
+            }
+
+            child.destroy();
+        }
+    }
+}
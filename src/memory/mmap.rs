@@ -1,10 +1,20 @@
 use core::sync::atomic::{AtomicUsize, Ordering};
 
-use crate::{process::fd_table::Fd, sched::current::current_task};
+use crate::{
+    fs::memfd,
+    memory::{
+        page::free_user_page,
+        shared::{release, shared_ranges, take_dirty, write_back},
+    },
+    process::{fd_table::Fd, kthread::spawn_kthread, thread_group::rsrc_lim::RlimitId},
+    sched::current::current_task,
+};
 use alloc::string::{String, ToString};
 use libkernel::{
     error::{KernelError, Result},
+    fs::OpenFlags,
     memory::{
+        PAGE_MASK, PAGE_SIZE,
         address::VA,
         proc_vm::{
             memory_map::AddressRequest,
@@ -13,17 +23,22 @@ use libkernel::{
         region::VirtMemoryRegion,
     },
 };
+use log::warn;
 
-const PROT_READ: u64 = 1;
-const PROT_WRITE: u64 = 2;
+pub(crate) const PROT_READ: u64 = 1;
+pub(crate) const PROT_WRITE: u64 = 2;
 const PROT_EXEC: u64 = 4;
 
 const MAP_SHARED: u64 = 0x0001;
-const MAP_PRIVATE: u64 = 0x0002;
+pub(crate) const MAP_PRIVATE: u64 = 0x0002;
 const MAP_FIXED: u64 = 0x0010;
 const MAP_FIXED_NOREPLACE: u64 = 0x100000;
 const MAP_ANON: u64 = 0x0020;
-const MAP_ANONYMOUS: u64 = 0x0020;
+pub(crate) const MAP_ANONYMOUS: u64 = 0x0020;
+
+const MS_ASYNC: u64 = 1;
+const MS_INVALIDATE: u64 = 2;
+const MS_SYNC: u64 = 4;
 
 /// Determines the minimal address that user-space is allowed to specify for
 /// MAP_FIXED{,_NOREPLACE}.
@@ -62,9 +77,11 @@ pub async fn sys_mmap(
         return Err(KernelError::InvalidValue);
     }
 
-    // TODO: Shared Mappings.
-    if (flags & MAP_SHARED) != 0 {
-        return Err(KernelError::NotSupported);
+    let shared = (flags & MAP_SHARED) != 0;
+
+    // The file offset must be page-aligned.
+    if offset as usize & PAGE_MASK != 0 {
+        return Err(KernelError::InvalidValue);
     }
 
     // `MAP_FIXED` and `MAP_FIXED_NOREPLACE` are mutually exclusive.
@@ -84,10 +101,27 @@ pub async fn sys_mmap(
 
     let permissions = prot_to_perms(prot);
 
+    // Mappings may not be both writable and executable (W^X).
+    if permissions.write && permissions.execute {
+        return Err(KernelError::InvalidValue);
+    }
+
     let requested_len = len as usize;
 
     let (kind, name) = if (flags & (MAP_ANON | MAP_ANONYMOUS)) != 0 {
-        (VMAreaKind::Anon, String::new())
+        if shared {
+            // Shared anonymous memory is an anonymous file that only the
+            // mappings, and those inherited from them over fork, refer to.
+            let inode = memfd::create_inode()?;
+            inode.truncate(len).await?;
+
+            (
+                VMAreaKind::new_shared_file(inode, 0, len),
+                "/dev/zero (deleted)".to_string(),
+            )
+        } else {
+            (VMAreaKind::Anon, String::new())
+        }
     } else {
         // File-backed mapping: require a valid fd and use the provided offset.
         let fd = current_task()
@@ -102,7 +136,17 @@ pub async fn sys_mmap(
             .map(|x| x.as_str().to_string())
             .unwrap_or_default();
 
-        (VMAreaKind::new_file(inode, offset, len), name)
+        if shared {
+            // Writes through the mapping reach the file, so it must have been
+            // opened for writing as well as reading.
+            if permissions.write && !fd.flags().await.contains(OpenFlags::O_RDWR) {
+                return Err(KernelError::PermissionDenied);
+            }
+
+            (VMAreaKind::new_shared_file(inode, offset, len), name)
+        } else {
+            (VMAreaKind::new_file(inode, offset, len), name)
+        }
     };
 
     let address_request = if addr.is_null() {
@@ -124,14 +168,25 @@ pub async fn sys_mmap(
         AddressRequest::Hint(addr)
     };
 
+    let task = current_task();
+    let as_limit = task.process.rsrc_lim.lock_save_irq().get(RlimitId::AS);
+
     // Lock the task and call the core memory manager to perform the mapping.
-    let new_mapping_addr = current_task().vm.lock_save_irq().mm_mut().mmap(
-        address_request,
-        requested_len,
-        permissions,
-        kind,
-        name,
-    )?;
+    let mut vm = task.vm.lock_save_irq();
+
+    // A MAP_FIXED mapping may replace some of what's counted, but is charged
+    // in full.
+    let charged = requested_len
+        .checked_next_multiple_of(PAGE_SIZE)
+        .ok_or(KernelError::NoMemory)?;
+
+    if vm.mapped_bytes().saturating_add(charged) as u64 > as_limit.rlim_cur {
+        return Err(KernelError::NoMemory);
+    }
+
+    let new_mapping_addr =
+        vm.mm_mut()
+            .mmap(address_request, requested_len, permissions, kind, name)?;
 
     Ok(new_mapping_addr.value())
 }
@@ -139,27 +194,98 @@ pub async fn sys_mmap(
 pub async fn sys_munmap(addr: VA, len: usize) -> Result<usize> {
     let region = VirtMemoryRegion::new(addr, len);
 
-    let pages = current_task().vm.lock_save_irq().mm_mut().munmap(region)?;
+    let (pages, shared) = {
+        let mut vm = current_task().vm.lock_save_irq();
+        let shared = shared_ranges(&mut vm, Some(region.align_to_page_boundary()));
+
+        (vm.mm_mut().munmap(region)?, shared)
+    };
+
+    // Free any physical frames that were unmapped. They're no longer mapped,
+    // so this process's references to them can be dropped.
+    for p in pages {
+        unsafe { free_user_page(p) };
+    }
+
+    release(shared).await;
+
+    Ok(0)
+}
+
+pub async fn sys_msync(addr: VA, len: usize, flags: u64) -> Result<usize> {
+    if !addr.is_page_aligned()
+        || (flags & !(MS_ASYNC | MS_INVALIDATE | MS_SYNC)) != 0
+        || (flags & MS_ASYNC != 0 && flags & MS_SYNC != 0)
+    {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let len = len
+        .checked_next_multiple_of(PAGE_SIZE)
+        .filter(|len| addr.value().checked_add(*len).is_some())
+        .ok_or(KernelError::NoMemory)?;
+
+    if len == 0 {
+        return Ok(0);
+    }
+
+    let region = VirtMemoryRegion::new(addr, len);
+
+    let (ranges, pages) = {
+        let mut vm = current_task().vm.lock_save_irq();
 
-    // Free any physical frames that were unmapped.
-    if !pages.is_empty() {
-        // The frames returned by munmap are no longer mapped and belong to this process;
-        // creating temporary allocations from these regions allows the allocator to reclaim them on drop.
-        let allocator = crate::memory::PAGE_ALLOC
-            .get()
-            .ok_or(KernelError::NoMemory)?;
+        // Every page of the region must be mapped.
+        let mut cursor = region.start_address();
 
-        for p in pages {
-            // Create a temporary allocation from the single-page region and drop it immediately to free.
-            let tmp = unsafe { allocator.alloc_from_region(p.as_phys_range()) };
-            drop(tmp);
+        while cursor < region.end_address() {
+            cursor = vm
+                .mm_mut()
+                .find_vma(cursor)
+                .ok_or(KernelError::NoMemory)?
+                .region()
+                .end_address();
         }
+
+        let ranges = shared_ranges(&mut vm, Some(region));
+        let pages = take_dirty(Some(&mut *vm), &ranges);
+
+        (ranges, pages)
+    };
+
+    // Every mapping of a file shares the same pages, so there's never a stale
+    // copy for MS_INVALIDATE to throw away.
+    if flags & MS_SYNC != 0 {
+        write_back(pages).await?;
+
+        for range in ranges {
+            range.inode().datasync().await?;
+        }
+    } else if !pages.is_empty() {
+        spawn_kthread("msync", async move {
+            if let Err(e) = write_back(pages).await {
+                warn!("Could not write back shared mapping: {e:?}");
+            }
+        })?;
     }
 
     Ok(0)
 }
 
 pub fn sys_mprotect(addr: VA, len: usize, prot: u64) -> Result<usize> {
+    if !addr.is_page_aligned() {
+        return Err(KernelError::InvalidValue);
+    }
+
+    // The length is rounded up to a whole number of pages.
+    let len = len
+        .checked_next_multiple_of(PAGE_SIZE)
+        .filter(|len| addr.value().checked_add(*len).is_some())
+        .ok_or(KernelError::NoMemory)?;
+
+    if len == 0 {
+        return Ok(0);
+    }
+
     let perms = prot_to_perms(prot);
     let region = VirtMemoryRegion::new(addr, len);
 
@@ -11,11 +11,15 @@ use libkernel::memory::{
 };
 
 pub mod brk;
+pub mod dma;
 pub mod fault;
 pub mod mincore;
 pub mod mmap;
+pub mod oom;
 pub mod page;
 pub mod process_vm;
+pub mod reclaim;
+pub mod shared;
 pub mod uaccess;
 
 pub type PageOffsetTranslator = libkernel::memory::pg_offset::PageOffsetTranslator<ArchImpl>;
@@ -0,0 +1,209 @@
+//! The OOM killer, the last resort when memory runs out.
+//!
+//! When an allocation fails and reclaim can't free anything, the process with
+//! the most pages mapped is sent `SIGKILL`, and the allocation waits for it to
+//! return its memory (see [`super::reclaim`]). Init and kernel threads are
+//! never picked.
+
+use super::PAGE_ALLOC;
+use crate::{
+    process::{
+        ProcVM, TASK_LIST, Task,
+        thread_group::{
+            ProcessState, ThreadGroup,
+            signal::{SigId, SigSet},
+        },
+    },
+    sync::SpinLock,
+};
+use alloc::sync::{Arc, Weak};
+use log::{error, warn};
+
+/// Whether the killer may pick `process`: init and kernel threads are the only
+/// processes without a parent, and both are spared.
+fn killable(process: &ThreadGroup) -> bool {
+    !process.tgid.is_init() && process.parent.lock_save_irq().is_some()
+}
+
+/// Whether `process` has already been killed or is exiting, and so is on its
+/// way to releasing its memory.
+fn dying(process: &ThreadGroup) -> bool {
+    *process.state.lock_save_irq() == ProcessState::Exiting
+        || process
+            .pending_signals
+            .lock_save_irq()
+            .contains(SigSet::SIGKILL)
+}
+
+/// The process picked to free memory.
+enum Victim {
+    /// The task whose process has the most pages mapped, with that number.
+    Kill(Arc<Task>, usize),
+    /// A task whose process is already dying with pages still mapped, whose
+    /// memory may be enough.
+    Dying(Arc<Task>),
+}
+
+/// Picks the process to free memory: one that's already dying if there is one,
+/// or else the one with the most pages mapped.
+///
+/// Zombies, and dying processes that have already released their memory, are
+/// passed over. So is a process whose memory map is locked, as it may be by
+/// the allocation that ran out, since it can't be measured.
+fn select_victim(tasks: impl Iterator<Item = Arc<Task>>) -> Option<Victim> {
+    let mut victim: Option<(Arc<Task>, usize)> = None;
+
+    for task in tasks.filter(|task| killable(&task.process)) {
+        if *task.process.state.lock_save_irq() == ProcessState::Zombie {
+            continue;
+        }
+
+        let Some(rss) = task.vm.try_lock_save_irq().map(|vm| vm.resident_pages()) else {
+            continue;
+        };
+
+        if dying(&task.process) {
+            if rss != 0 {
+                return Some(Victim::Dying(task));
+            }
+
+            continue;
+        }
+
+        if victim.as_ref().is_none_or(|(_, most)| rss > *most) {
+            victim = Some((task, rss));
+        }
+    }
+
+    victim.map(|(task, rss)| Victim::Kill(task, rss))
+}
+
+/// Kills the process using the most memory, unless one is already dying.
+/// Returns the dying process's address space, to wait on for its memory to be
+/// released, or `None` if there's nothing to kill.
+pub fn oom_kill() -> Option<Arc<SpinLock<ProcVM>>> {
+    let alloc = PAGE_ALLOC.get().unwrap();
+
+    // Only try once the task list is free: the allocation may have been made
+    // with it locked.
+    let tasks = TASK_LIST.try_lock_save_irq()?;
+
+    let victim = select_victim(tasks.values().filter_map(Weak::upgrade))?;
+
+    drop(tasks);
+
+    let (task, rss) = match victim {
+        Victim::Kill(task, rss) => (task, rss),
+        Victim::Dying(task) => return Some(task.vm.clone()),
+    };
+
+    error!(
+        "Out of memory: {} of {} pages free",
+        alloc.free_pages(),
+        alloc.total_pages()
+    );
+    warn!(
+        "Killing process {} ({}) with {rss} pages resident",
+        task.process.tgid,
+        task.comm.lock_save_irq().as_str()
+    );
+
+    task.process.deliver_signal(SigId::SIGKILL);
+
+    Some(task.vm.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fs::DummyInode,
+        kernel::cpu_id::CpuId,
+        ktest,
+        memory::page::ClaimedPage,
+        process::{
+            Comm, TaskState, Tid, creds::Credentials, fd_table::FileDescriptorTable,
+            ptrace::PTrace, thread_group::builder::ThreadGroupBuilder,
+        },
+        sync::SpinLock,
+    };
+    use core::sync::atomic::{AtomicBool, AtomicUsize};
+    use libkernel::{
+        UserAddressSpace,
+        fs::pathbuf::PathBuf,
+        memory::{PAGE_SIZE, address::VA, permissions::PtePermissions, proc_vm::ProcessVM},
+    };
+
+    /// A task in a new child of `parent` with `pages` pages mapped.
+    fn task_with_pages(parent: &Arc<ThreadGroup>, pages: usize) -> Arc<Task> {
+        let (process, tid) = parent.clone().new_child(false);
+        let mut vm = ProcessVM::empty().unwrap();
+
+        for n in 0..pages {
+            let pfn = ClaimedPage::alloc_zeroed().unwrap().leak();
+            vm.mm_mut()
+                .address_space_mut()
+                .map_page(
+                    pfn,
+                    VA::from_value(0x1000_0000 + n * PAGE_SIZE),
+                    PtePermissions::rw(true),
+                )
+                .unwrap();
+        }
+
+        Arc::new(Task {
+            tid,
+            comm: Arc::new(SpinLock::new(Comm::new("oom-test"))),
+            process,
+            vm: Arc::new(SpinLock::new(vm)),
+            cwd: Arc::new(SpinLock::new((Arc::new(DummyInode {}), PathBuf::new()))),
+            root: Arc::new(SpinLock::new((Arc::new(DummyInode {}), PathBuf::new()))),
+            creds: SpinLock::new(Credentials::new_root()),
+            fd_table: Arc::new(SpinLock::new(FileDescriptorTable::new())),
+            state: Arc::new(SpinLock::new(TaskState::Runnable)),
+            last_cpu: SpinLock::new(CpuId::this()),
+            on_cpu: AtomicBool::new(false),
+            ptrace: SpinLock::new(PTrace::new()),
+            utime: AtomicUsize::new(0),
+            stime: AtomicUsize::new(0),
+            last_account: AtomicUsize::new(0),
+        })
+    }
+
+    ktest! {
+        fn oom_killer_picks_largest_process() {
+            let parent = ThreadGroupBuilder::new(ThreadGroup::next_tgid()).build();
+            let small = task_with_pages(&parent, 2);
+            let large = task_with_pages(&parent, 5);
+
+            let tasks = [small.clone(), large.clone()];
+            let Some(Victim::Kill(victim, rss)) = select_victim(tasks.iter().cloned()) else {
+                panic!("the largest process should be killed");
+            };
+            assert!(Arc::ptr_eq(&victim, &large));
+            assert_eq!(rss, 5);
+
+            // The parent has no parent of its own, like init and kernel
+            // threads, so it's never picked.
+            assert!(!killable(&parent));
+
+            // Nothing else is killed whilst the victim dies.
+            victim.process.deliver_signal(SigId::SIGKILL);
+            let Some(Victim::Dying(dying)) = select_victim(tasks.iter().cloned()) else {
+                panic!("the dying process should be waited on");
+            };
+            assert!(Arc::ptr_eq(&dying, &large));
+
+            // Once it has released its memory, the next largest is killed.
+            *large.vm.lock_save_irq() = ProcessVM::empty().unwrap();
+            let Some(Victim::Kill(victim, _)) = select_victim(tasks.iter().cloned()) else {
+                panic!("the next largest process should be killed");
+            };
+            assert!(Arc::ptr_eq(&victim, &small));
+
+            // Zombies are never picked.
+            *small.process.state.lock_save_irq() = ProcessState::Zombie;
+            assert!(select_victim(tasks.iter().cloned()).is_none());
+        }
+    }
+}
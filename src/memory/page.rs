@@ -1,6 +1,7 @@
-use super::{PAGE_ALLOC, PageOffsetTranslator};
+use super::{PAGE_ALLOC, PageOffsetTranslator, reclaim};
 use crate::arch::ArchImpl;
-use libkernel::memory::allocators::phys::PageAllocGetter;
+use core::sync::atomic::{AtomicBool, Ordering};
+use libkernel::memory::{allocators::phys::PageAllocGetter, page::PageFrame};
 
 pub struct PgAllocGetter {}
 
@@ -9,7 +10,76 @@ impl PageAllocGetter<ArchImpl> for PgAllocGetter {
     {
         PAGE_ALLOC.get().unwrap()
     }
+
+    fn reclaim() -> bool {
+        reclaim::reclaim_for_failed_alloc()
+    }
 }
 
 pub type ClaimedPage =
     libkernel::memory::page::ClaimedPage<ArchImpl, PgAllocGetter, PageOffsetTranslator>;
+
+/// Whether pages freed from user address spaces are zeroed before they're
+/// returned to the allocator. Cleared by the `--no-zero-user-pages` option.
+pub static ZERO_USER_PAGES: AtomicBool = AtomicBool::new(true);
+
+/// Drops a user mapping's reference to the page at `pfn`. When the last
+/// reference goes the page is zeroed, so its contents can't leak into another
+/// process.
+///
+/// # Safety
+///
+/// The caller must own a reference to the page which it no longer uses, e.g.
+/// from a mapping that has just been removed.
+pub unsafe fn free_user_page(pfn: PageFrame) {
+    let alloc = unsafe {
+        PAGE_ALLOC
+            .get()
+            .unwrap()
+            .alloc_from_region(pfn.as_phys_range())
+    };
+
+    if ZERO_USER_PAGES.load(Ordering::Relaxed) {
+        alloc.free_zeroed::<PageOffsetTranslator>();
+    } else {
+        drop(alloc);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ktest;
+    use libkernel::{
+        UserAddressSpace, VirtualMemory,
+        memory::{PAGE_SIZE, address::VA, permissions::PtePermissions},
+    };
+
+    ktest! {
+        fn user_pages_are_zeroed_on_free() {
+            let mut addr_space = <ArchImpl as VirtualMemory>::ProcessAddressSpace::new().unwrap();
+            let mut page = ClaimedPage::alloc_zeroed().unwrap();
+
+            page.as_slice_mut().fill(0xaa);
+
+            let pfn = page.leak();
+            addr_space
+                .map_page(pfn, VA::from_value(0x1000), PtePermissions::rw(true))
+                .unwrap();
+
+            // Tearing down the address space frees the page.
+            drop(addr_space);
+            assert!(!PAGE_ALLOC.get().unwrap().is_allocated(pfn));
+
+            // Whoever allocates the page next, with or without zeroing it,
+            // must see zeroes.
+            let contents = unsafe {
+                core::slice::from_raw_parts(
+                    pfn.pa().to_va::<PageOffsetTranslator>().as_ptr() as *const u8,
+                    PAGE_SIZE,
+                )
+            };
+            assert!(contents.iter().all(|&b| b == 0));
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! Reclaiming memory under pressure.
+//!
+//! An allocation that leaves free memory below the low watermark raises
+//! pressure on the frame allocator, and the reclaim thread then frees enough
+//! to get back up to the high one, writing back dirty cached pages that nothing
+//! maps if dropping clean ones isn't enough. A user page allocation that fails
+//! reclaims a batch itself and tries again, or failing that calls in the
+//! [OOM killer](super::oom) and waits for its victim to release its memory.
+//! Only the page cache gives up pages for now; see
+//! [`PageCache::reclaim`](crate::fs::page_cache::PageCache::reclaim) for which
+//! it picks.
+
+use super::{PAGE_ALLOC, oom, shared};
+use crate::{
+    drivers::timer::{now, sleep},
+    fs::page_cache::PAGE_CACHE,
+    process::kthread::spawn_kthread,
+};
+use core::{hint::spin_loop, time::Duration};
+use libkernel::error::Result;
+use log::warn;
+
+/// The least the low watermark is set to, however little memory there is.
+const MIN_LOW_WATERMARK: usize = 32;
+
+/// The most pages reclaimed after an allocation fails.
+const FAILED_ALLOC_BATCH: usize = 32;
+
+/// How long a failed allocation waits for the OOM killer's victim to release
+/// its memory.
+const OOM_WAIT: Duration = Duration::from_secs(1);
+
+/// How often the reclaim thread checks for memory pressure.
+const RECLAIM_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The low and high watermarks, in pages, for a machine with `total` pages.
+fn watermarks(total: usize) -> (usize, usize) {
+    let low = (total / 128).max(MIN_LOW_WATERMARK);
+
+    (low, 2 * low)
+}
+
+/// How many pages to reclaim with `free` of `total` pages free.
+fn wanted(free: usize, total: usize) -> usize {
+    let (low, high) = watermarks(total);
+
+    if free < low { high - free } else { 0 }
+}
+
+/// Reclaims up to `pages` pages, returning how many were freed.
+pub fn reclaim(pages: usize) -> usize {
+    // An allocation made with the cache locked mustn't wait on itself.
+    PAGE_CACHE
+        .try_lock_save_irq()
+        .map_or(0, |mut cache| cache.reclaim(pages))
+}
+
+/// Reclaims memory if free memory is below the low watermark, returning how
+/// many more pages are wanted afterwards.
+fn balance() -> usize {
+    let alloc = PAGE_ALLOC.get().unwrap();
+    let pages = wanted(alloc.free_pages(), alloc.total_pages());
+
+    pages.saturating_sub(reclaim(pages))
+}
+
+/// Brings free memory back above the high watermark as far as possible,
+/// writing back dirty cached pages if there aren't enough clean ones to drop.
+async fn rebalance() {
+    if balance() == 0 {
+        return;
+    }
+
+    if let Err(e) = shared::write_back(shared::take_unmapped_dirty()).await {
+        warn!("Could not write back dirty pages for reclaim: {e:?}");
+    }
+
+    balance();
+}
+
+/// Sets the frame allocator's low watermark and starts the kernel thread that
+/// reclaims memory whenever it's under pressure.
+pub fn reclaim_init() -> Result<()> {
+    let alloc = PAGE_ALLOC.get().unwrap();
+
+    alloc.set_low_watermark(watermarks(alloc.total_pages()).0);
+
+    spawn_kthread("reclaim", async move {
+        loop {
+            sleep(RECLAIM_INTERVAL).await;
+
+            if alloc.take_pressure() {
+                rebalance().await;
+            }
+        }
+    })?;
+
+    Ok(())
+}
+
+/// Reclaims a batch of pages after an allocation fails, returning whether the
+/// allocation is worth retrying.
+///
+/// If nothing could be reclaimed, the OOM killer is called in, and we wait for
+/// its victim to release its memory, or for anything else to free some, for up
+/// to [`OOM_WAIT`]. The caller may not be able to sleep, so this spins; the
+/// victim is left to exit on another CPU.
+pub fn reclaim_for_failed_alloc() -> bool {
+    if reclaim(FAILED_ALLOC_BATCH) != 0 {
+        return true;
+    }
+
+    let Some(victim) = oom::oom_kill() else {
+        return false;
+    };
+
+    let alloc = PAGE_ALLOC.get().unwrap();
+    let free = alloc.free_pages();
+    let Some(deadline) = now().map(|now| now + OOM_WAIT) else {
+        return false;
+    };
+
+    while now().is_some_and(|now| now < deadline) {
+        let released = victim
+            .try_lock_save_irq()
+            .is_some_and(|vm| vm.resident_pages() == 0);
+
+        if released || alloc.free_pages() > free || reclaim(FAILED_ALLOC_BATCH) != 0 {
+            return true;
+        }
+
+        spin_loop();
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ktest;
+
+    ktest! {
+        fn reclaim_watermarks() {
+            let total = 64 * 1024;
+            let (low, high) = watermarks(total);
+
+            assert_eq!(low, 512);
+            assert_eq!(wanted(low, total), 0);
+            assert_eq!(wanted(low - 1, total), high - low + 1);
+            assert_eq!(wanted(0, total), high);
+
+            // Small machines keep a floor.
+            assert_eq!(watermarks(1024), (MIN_LOW_WATERMARK, 2 * MIN_LOW_WATERMARK));
+        }
+    }
+}
@@ -0,0 +1,420 @@
+//! Pages of shared (`MAP_SHARED`) file mappings.
+//!
+//! Filesystems that keep file contents in memory, like the one behind memfds,
+//! hand out the file's own pages (see [`Inode::shared_page`]), so the mappings
+//! and reads and writes of the file all see the same data. Pages of any other
+//! file are mapped from the page cache (see [`crate::fs::page_cache`]).
+//!
+//! Cached pages are mapped read-only until they're written to, so the write
+//! faults and the page can be marked dirty. Only dirty pages are written back
+//! to the file, by `msync` and when they're unmapped, or by reclaim if that
+//! failed. Once nothing maps a page it's dropped from the cache, unless it's
+//! still being used by reads.
+
+use super::{
+    PAGE_ALLOC,
+    page::{ClaimedPage, free_user_page},
+};
+use crate::{
+    fs::page_cache::{self, PAGE_CACHE, page_ref},
+    process::ProcVM,
+};
+use alloc::{sync::Arc, vec::Vec};
+use core::cmp::min;
+use libkernel::{
+    UserAddressSpace,
+    error::{KernelError, Result},
+    fs::{Inode, InodeId},
+    memory::{
+        PAGE_SIZE, address::VA, page::PageFrame, permissions::PtePermissions,
+        proc_vm::vmarea::VMAreaKind, region::VirtMemoryRegion,
+    },
+};
+use log::warn;
+
+/// Returns the page to map at `offset` of `inode` in a shared mapping, with a
+/// reference taken on behalf of the mapping, and whether the page may be
+/// mapped writable.
+///
+/// Cached pages are only writable if they're being mapped for a `write`,
+/// which marks them dirty.
+pub async fn shared_page(
+    inode: &Arc<dyn Inode>,
+    offset: u64,
+    write: bool,
+) -> Result<(PageFrame, bool)> {
+    match inode.shared_page(offset).await {
+        Err(KernelError::NotSupported) => {}
+        res => return res.map(|pfn| (pfn, true)),
+    }
+
+    let pfn = page_cache::get_page(inode, offset, write).await?;
+
+    Ok((pfn, write))
+}
+
+/// Marks the cached page at `offset` of the file `id` dirty, ahead of it being
+/// made writable. Does nothing for pages that aren't cached, which are the
+/// file's own.
+pub fn mark_dirty(id: InodeId, offset: u64) {
+    if let Some(page) = PAGE_CACHE.lock_save_irq().get_mut(&(id, offset)) {
+        page.dirty = true;
+    }
+}
+
+/// A part of a file covered by a shared mapping.
+pub struct SharedRange {
+    inode: Arc<dyn Inode>,
+    start: u64,
+    end: u64,
+    /// Where the start of the range is mapped.
+    va: VA,
+    /// The permissions of the range's pages, before they're written to.
+    perms: PtePermissions,
+}
+
+impl SharedRange {
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        &self.inode
+    }
+}
+
+/// Returns the parts of files mapped shared by `vm` within `region`, or
+/// anywhere in the address space if `region` is `None`.
+pub fn shared_ranges(vm: &mut ProcVM, region: Option<VirtMemoryRegion>) -> Vec<SharedRange> {
+    vm.mm_mut()
+        .iter_vmas()
+        .filter_map(|vma| {
+            let VMAreaKind::File(mapping) = vma.kind() else {
+                return None;
+            };
+
+            if !mapping.is_shared() {
+                return None;
+            }
+
+            let overlap = match region {
+                Some(region) => vma.region().intersection(region)?,
+                None => vma.region(),
+            };
+
+            let start = mapping.offset()
+                + (overlap.start_address().value() - vma.region().start_address().value()) as u64;
+
+            Some(SharedRange {
+                inode: mapping.file(),
+                start,
+                end: start + overlap.size() as u64,
+                va: overlap.start_address(),
+                perms: vma.pte_permissions(),
+            })
+        })
+        .collect()
+}
+
+/// A dirty page taken to be written back to its file.
+pub struct DirtyPage {
+    inode: Arc<dyn Inode>,
+    offset: u64,
+    page: ClaimedPage,
+}
+
+/// Takes the dirty cached pages within `ranges` to be written back.
+///
+/// `vm` is the address space the ranges were found in, if it still maps them.
+/// A page is marked clean, and `vm`'s mapping of it made read-only again so
+/// the next write is noticed, unless some other mapping might still be
+/// writing to it. Those pages stay dirty, to be written back again later.
+pub fn take_dirty(mut vm: Option<&mut ProcVM>, ranges: &[SharedRange]) -> Vec<DirtyPage> {
+    let page_alloc = PAGE_ALLOC.get().unwrap();
+    let mut cache = PAGE_CACHE.lock_save_irq();
+    let mut dirty = Vec::new();
+
+    for range in ranges {
+        let id = range.inode.id();
+
+        for (&(_, offset), page) in cache.range_mut((id, range.start)..(id, range.end)) {
+            if !page.dirty {
+                continue;
+            }
+
+            let va = range.va.add_bytes((offset - range.start) as usize);
+            let mut address_space = vm
+                .as_mut()
+                .map(|vm| vm.mm_mut().address_space_mut())
+                .filter(|address_space| {
+                    address_space
+                        .translate(va)
+                        .is_some_and(|info| info.pfn == page.pfn)
+                });
+
+            // One reference is the cache's, and another ours if we map it.
+            let refs = 1 + address_space.is_some() as u32;
+
+            if page_alloc.ref_count(page.pfn) == refs {
+                page.dirty = false;
+
+                if let Some(address_space) = address_space.as_mut() {
+                    // Failing to write-protect the page would only lose track
+                    // of it being dirtied again, so is fatal for the process.
+                    address_space
+                        .protect_range(va.page_region(), range.perms)
+                        .expect("Could not write-protect mapped page");
+                }
+            }
+
+            dirty.push(DirtyPage {
+                inode: range.inode.clone(),
+                offset,
+                page: unsafe { ClaimedPage::from_pfn(page_ref(page.pfn)) },
+            });
+        }
+    }
+
+    dirty
+}
+
+/// Takes the dirty cached pages that nothing maps any more, for reclaim to
+/// write back so they can be dropped. Pages whose files have since gone can't
+/// be written back, and are given up on.
+pub fn take_unmapped_dirty() -> Vec<DirtyPage> {
+    let page_alloc = PAGE_ALLOC.get().unwrap();
+    let mut cache = PAGE_CACHE.lock_save_irq();
+    let mut dirty = Vec::new();
+
+    for (&(id, offset), page) in cache.range_mut(..) {
+        if !page.dirty || !page_alloc.is_allocated_exclusive(page.pfn) {
+            continue;
+        }
+
+        page.dirty = false;
+
+        let Some(inode) = page.inode.upgrade() else {
+            warn!("Dropping dirty page at {offset:#x} of deleted file {id:?}");
+            continue;
+        };
+
+        dirty.push(DirtyPage {
+            inode,
+            offset,
+            page: unsafe { ClaimedPage::from_pfn(page_ref(page.pfn)) },
+        });
+    }
+
+    dirty
+}
+
+/// Writes `pages` back to their files. Nothing past the end of a file is
+/// written, so the files never grow. Pages that can't be written are marked
+/// dirty again.
+pub async fn write_back(pages: Vec<DirtyPage>) -> Result<()> {
+    let mut res = Ok(());
+
+    for DirtyPage {
+        inode,
+        offset,
+        page,
+    } in pages
+    {
+        let written = match inode.getattr().await {
+            Ok(attr) if offset < attr.size => {
+                let len = min(PAGE_SIZE as u64, attr.size - offset) as usize;
+
+                inode
+                    .write_at(offset, &page.as_slice()[..len])
+                    .await
+                    .map(|_| ())
+            }
+            Ok(_) => Ok(()),
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = written {
+            mark_dirty(inode.id(), offset);
+            res = Err(e);
+        }
+
+        unsafe { free_user_page(page.leak()) };
+    }
+
+    res
+}
+
+/// Writes back the dirty cached pages of `ranges` once they've been unmapped,
+/// and drops any that are no longer mapped anywhere from the cache.
+pub async fn release(ranges: Vec<SharedRange>) {
+    if let Err(e) = write_back(take_dirty(None, &ranges)).await {
+        warn!("Could not write back shared mapping: {e:?}");
+    }
+
+    let mut cache = PAGE_CACHE.lock_save_irq();
+
+    for range in ranges {
+        let id = range.inode.id();
+
+        cache.discard((id, range.start)..(id, range.end));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ktest,
+        memory::{
+            PageOffsetTranslator,
+            fault::{FaultResolution, handle_demand_fault, handle_protection_fault},
+        },
+        sync::SpinLock,
+    };
+    use alloc::{boxed::Box, vec};
+    use async_trait::async_trait;
+    use libkernel::{
+        fs::attr::FileAttr,
+        memory::proc_vm::vmarea::{AccessKind, VMAPermissions, VMArea},
+    };
+
+    /// A file that's read from memory, recording where it's written to.
+    struct RecordingInode {
+        data: SpinLock<Vec<u8>>,
+        writes: SpinLock<Vec<(u64, Vec<u8>)>>,
+    }
+
+    #[async_trait]
+    impl Inode for RecordingInode {
+        fn id(&self) -> InodeId {
+            InodeId::from_fsid_and_inodeid(u64::MAX, 1)
+        }
+
+        async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+            let data = self.data.lock_save_irq();
+            let len = min(buf.len(), data.len().saturating_sub(offset as usize));
+
+            buf[..len].copy_from_slice(&data[offset as usize..][..len]);
+
+            Ok(len)
+        }
+
+        async fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize> {
+            self.writes.lock_save_irq().push((offset, buf.to_vec()));
+            self.data.lock_save_irq()[offset as usize..][..buf.len()].copy_from_slice(buf);
+
+            Ok(buf.len())
+        }
+
+        async fn getattr(&self) -> Result<FileAttr> {
+            Ok(FileAttr {
+                size: self.data.lock_save_irq().len() as u64,
+                ..Default::default()
+            })
+        }
+    }
+
+    ktest! {
+        async fn msync_writes_back_only_dirty_pages() {
+            let len = 3 * PAGE_SIZE;
+            let file = Arc::new(RecordingInode {
+                data: SpinLock::new(vec![0xaa; len]),
+                writes: SpinLock::new(Vec::new()),
+            });
+            let inode: Arc<dyn Inode> = file.clone();
+
+            let addr = VA::from_value(0x10_0000);
+            let vma = VMArea::new(
+                VirtMemoryRegion::new(addr, len),
+                VMAreaKind::new_shared_file(inode, 0, len as u64),
+                VMAPermissions::rw(),
+            );
+            let proc_vm = Arc::new(SpinLock::new(ProcVM::from_vma(vma).unwrap()));
+
+            // Reading the pages in maps them read-only, so they start clean.
+            for i in 0..3 {
+                let Ok(FaultResolution::Deferred(fut)) =
+                    handle_demand_fault(proc_vm.clone(), addr.add_pages(i), AccessKind::Read)
+                else {
+                    panic!("Faults on shared mappings should be deferred");
+                };
+
+                Box::into_pin(fut).await.unwrap();
+            }
+
+            let mut vm = proc_vm.lock_save_irq();
+            let dirty_va = addr.add_pages(1);
+            let pg_info = vm.mm_mut().address_space_mut().translate(dirty_va).unwrap();
+            assert!(!pg_info.perms.is_write());
+
+            // The first write to the middle page dirties it.
+            assert!(matches!(
+                handle_protection_fault(&mut vm, dirty_va, AccessKind::Write, pg_info),
+                Ok(FaultResolution::Resolved)
+            ));
+            let pg_info = vm.mm_mut().address_space_mut().translate(dirty_va).unwrap();
+            assert!(pg_info.perms.is_write());
+
+            unsafe {
+                core::slice::from_raw_parts_mut(
+                    pg_info.pfn.pa().to_va::<PageOffsetTranslator>().as_ptr_mut() as *mut u8,
+                    PAGE_SIZE,
+                )
+                .fill(0x55);
+            }
+
+            let ranges = shared_ranges(&mut vm, None);
+            let pages = take_dirty(Some(&mut *vm), &ranges);
+            write_back(pages).await.unwrap();
+
+            {
+                let writes = file.writes.lock_save_irq();
+                assert_eq!(writes.len(), 1);
+                assert_eq!(writes[0].0, PAGE_SIZE as u64);
+                assert!(writes[0].1.iter().all(|b| *b == 0x55));
+            }
+
+            // The page is clean again, and write-protected to notice the next
+            // write, so a second sync has nothing to do.
+            let pg_info = vm.mm_mut().address_space_mut().translate(dirty_va).unwrap();
+            assert!(!pg_info.perms.is_write());
+
+            let pages = take_dirty(Some(&mut *vm), &ranges);
+            assert!(pages.is_empty());
+
+            // Once unmapped, the clean pages are dropped from the cache
+            // without being written again.
+            drop(vm);
+            drop(proc_vm);
+            release(ranges).await;
+
+            assert_eq!(file.writes.lock_save_irq().len(), 1);
+            let id = file.id();
+            assert!(PAGE_CACHE.lock_save_irq().range((id, 0)..(id, len as u64)).next().is_none());
+        }
+    }
+    ktest! {
+        async fn reclaim_writes_back_unmapped_dirty_pages() {
+            let file = Arc::new(RecordingInode {
+                data: SpinLock::new(vec![0xaa; PAGE_SIZE]),
+                writes: SpinLock::new(Vec::new()),
+            });
+            let inode: Arc<dyn Inode> = file.clone();
+            let id = inode.id();
+
+            // A page dirtied through a mapping that's since gone, as if writing
+            // it back on unmap had failed.
+            let pfn = page_cache::get_page(&inode, 0, true).await.unwrap();
+            unsafe { free_user_page(pfn) };
+
+            let pages: Vec<_> = take_unmapped_dirty()
+                .into_iter()
+                .filter(|page| page.inode.id() == id)
+                .collect();
+            assert_eq!(pages.len(), 1);
+            write_back(pages).await.unwrap();
+            assert_eq!(file.writes.lock_save_irq().len(), 1);
+
+            // Now clean, it's no longer taken, and can be dropped.
+            assert!(take_unmapped_dirty().iter().all(|page| page.inode.id() != id));
+            page_cache::forget(id);
+            assert!(PAGE_CACHE.lock_save_irq().range((id, 0)..=(id, u64::MAX)).next().is_none());
+        }
+    }
+}
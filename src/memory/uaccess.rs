@@ -2,7 +2,8 @@ use core::mem::MaybeUninit;
 
 use crate::arch::{Arch, ArchImpl};
 use alloc::vec::Vec;
-use libkernel::error::Result;
+use libkernel::VirtualMemory;
+use libkernel::error::{KernelError, Result};
 use libkernel::memory::address::{TUA, UA};
 
 pub mod cstr;
@@ -27,7 +28,26 @@ pub mod cstr;
 /// communication.
 pub unsafe trait UserCopyable: Copy {}
 
+/// Checks that `[ua, ua + len)` lies entirely within the user portion of the
+/// address space.
+///
+/// Every uaccess entry point calls this before touching user memory, so that a
+/// user-supplied pointer can never be used to read or write kernel memory.
+///
+/// # Errors
+///
+/// Returns `KernelError::Fault` if the range reaches into kernel space or
+/// wraps around the end of the address space.
+pub fn access_ok(ua: UA, len: usize) -> Result<()> {
+    match ua.value().checked_add(len) {
+        Some(end) if end <= ArchImpl::PAGE_OFFSET => Ok(()),
+        _ => Err(KernelError::Fault),
+    }
+}
+
 pub async fn copy_to_user<T: UserCopyable>(dst: TUA<T>, obj: T) -> Result<()> {
+    access_ok(dst.to_untyped(), core::mem::size_of::<T>())?;
+
     unsafe {
         ArchImpl::copy_to_user(
             (&obj) as *const _ as *const _,
@@ -39,6 +59,8 @@ pub async fn copy_to_user<T: UserCopyable>(dst: TUA<T>, obj: T) -> Result<()> {
 }
 
 pub async fn copy_from_user<T: UserCopyable>(src: TUA<T>) -> Result<T> {
+    access_ok(src.to_untyped(), core::mem::size_of::<T>())?;
+
     let mut uninit: MaybeUninit<T> = MaybeUninit::uninit();
 
     unsafe {
@@ -56,6 +78,8 @@ pub async fn copy_from_user<T: UserCopyable>(src: TUA<T>) -> Result<T> {
 }
 
 pub fn try_copy_from_user<T: UserCopyable>(src: TUA<T>) -> Result<T> {
+    access_ok(src.to_untyped(), core::mem::size_of::<T>())?;
+
     let mut uninit: MaybeUninit<T> = MaybeUninit::uninit();
 
     unsafe {
@@ -75,6 +99,12 @@ pub async fn copy_obj_array_from_user<T: UserCopyable>(
     mut src: TUA<T>,
     len: usize,
 ) -> Result<Vec<T>> {
+    access_ok(
+        src.to_untyped(),
+        len.checked_mul(core::mem::size_of::<T>())
+            .ok_or(KernelError::Fault)?,
+    )?;
+
     let mut ret = Vec::with_capacity(len);
 
     for _ in 0..len {
@@ -86,6 +116,8 @@ pub async fn copy_obj_array_from_user<T: UserCopyable>(
 }
 
 pub async fn copy_objs_to_user<T: UserCopyable>(src: &[T], mut dst: TUA<T>) -> Result<()> {
+    access_ok(dst.to_untyped(), core::mem::size_of_val(src))?;
+
     for obj in src {
         copy_to_user(dst, *obj).await?;
         dst = dst.add_objs(1);
@@ -95,10 +127,14 @@ pub async fn copy_objs_to_user<T: UserCopyable>(src: &[T], mut dst: TUA<T>) -> R
 }
 
 pub async fn copy_from_user_slice(src: UA, dst: &mut [u8]) -> Result<()> {
+    access_ok(src, dst.len())?;
+
     unsafe { ArchImpl::copy_from_user(src, dst.as_mut_ptr() as *mut _ as *mut _, dst.len()).await }
 }
 
 pub async fn copy_to_user_slice(src: &[u8], dst: UA) -> Result<()> {
+    access_ok(dst, src.len())?;
+
     unsafe { ArchImpl::copy_to_user(src.as_ptr().cast(), dst, src.len()).await }
 }
 
@@ -125,3 +161,100 @@ unsafe impl<T: UserCopyable, const N: usize> UserCopyable for [T; N] {}
 // Copying a pointer to another pointer which points to a `UserCopyable` type is
 // safe to copy.
 unsafe impl<T: UserCopyable> UserCopyable for TUA<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::{cstr::UserCStr, *};
+    use crate::{ktest, testing::ScratchBuf};
+    use libkernel::memory::PAGE_SIZE;
+
+    ktest! {
+        fn access_ok_rejects_kernel_and_wrapping_ranges() {
+            let limit = ArchImpl::PAGE_OFFSET;
+
+            assert!(access_ok(UA::from_value(0x1000), PAGE_SIZE).is_ok());
+            assert!(access_ok(UA::from_value(limit - 8), 8).is_ok());
+
+            assert!(matches!(
+                access_ok(UA::from_value(limit - 8), 9),
+                Err(KernelError::Fault)
+            ));
+            assert!(matches!(
+                access_ok(UA::from_value(limit), 0),
+                Err(KernelError::Fault)
+            ));
+            assert!(matches!(
+                access_ok(UA::from_value(0x1000), usize::MAX),
+                Err(KernelError::Fault)
+            ));
+        }
+    }
+
+    ktest! {
+        async fn kernel_pointers_are_not_copied() {
+            static SECRET: u64 = 0x5ec7e7;
+
+            let ptr = TUA::<u64>::from_value((&raw const SECRET).addr());
+
+            assert!(matches!(try_copy_from_user(ptr), Err(KernelError::Fault)));
+            assert!(matches!(copy_from_user(ptr).await, Err(KernelError::Fault)));
+            assert!(matches!(
+                copy_obj_array_from_user(TUA::<u64>::from_value(0x1000), usize::MAX / 4).await,
+                Err(KernelError::Fault)
+            ));
+
+            let mut buf = [0; 16];
+            assert!(matches!(
+                UserCStr::from_ptr(TUA::from_value((&raw const SECRET).addr()))
+                    .copy_from_user(&mut buf)
+                    .await,
+                Err(KernelError::Fault)
+            ));
+        }
+    }
+
+    ktest! {
+        async fn copy_from_unmapped_page_faults() {
+            let scratch = ScratchBuf::new(PAGE_SIZE).await;
+            let ptr = scratch.addr().cast::<u64>();
+
+            copy_to_user(ptr, 0xdead_beef).await.unwrap();
+            assert_eq!(copy_from_user(ptr).await.unwrap(), 0xdead_beef);
+
+            drop(scratch);
+
+            assert!(matches!(copy_from_user(ptr).await, Err(KernelError::Fault)));
+            assert!(matches!(try_copy_from_user(ptr), Err(KernelError::Fault)));
+            assert!(matches!(copy_to_user(ptr, 0).await, Err(KernelError::Fault)));
+        }
+    }
+
+    ktest! {
+        async fn copy_at_every_word_offset() {
+            let scratch = ScratchBuf::new(PAGE_SIZE).await;
+
+            let pattern: Vec<u8> = (0..37).collect();
+
+            for user_off in 0..8 {
+                for kern_off in 0..8 {
+                    let user = scratch.addr().add_bytes(user_off);
+                    let mut src = [0u8; 48];
+                    let mut dst = [0xffu8; 48];
+
+                    src[kern_off..kern_off + pattern.len()].copy_from_slice(&pattern);
+
+                    copy_to_user_slice(&src[kern_off..kern_off + pattern.len()], user)
+                        .await
+                        .unwrap();
+                    copy_from_user_slice(user, &mut dst[kern_off..kern_off + pattern.len()])
+                        .await
+                        .unwrap();
+
+                    assert_eq!(&dst[kern_off..kern_off + pattern.len()], &pattern[..]);
+                    assert!(dst[..kern_off].iter().all(|&b| b == 0xff));
+                    assert!(dst[kern_off + pattern.len()..].iter().all(|&b| b == 0xff));
+                }
+            }
+        }
+    }
+}
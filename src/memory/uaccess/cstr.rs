@@ -2,7 +2,9 @@ use core::ffi::{CStr, c_char};
 use libkernel::error::{KernelError, Result};
 use libkernel::memory::address::TUA;
 
+use super::access_ok;
 use crate::arch::{Arch, ArchImpl};
+use libkernel::VirtualMemory;
 
 pub struct UserCStr(TUA<c_char>);
 
@@ -15,12 +17,20 @@ impl UserCStr {
         // Ensure null-filled buffer.
         buf.fill(0);
 
-        let len = unsafe {
-            ArchImpl::copy_strn_from_user(self.0.to_untyped(), buf.as_mut_ptr(), buf.len())
-        }
-        .await?;
+        // The string can be shorter than `buf`, so only require that it starts
+        // in userspace and never read past the end of it.
+        let src = self.0.to_untyped();
+        access_ok(src, 1)?;
+        let max_len = buf.len().min(ArchImpl::PAGE_OFFSET - src.value());
+
+        let len = unsafe { ArchImpl::copy_strn_from_user(src, buf.as_mut_ptr(), max_len) }.await?;
+
+        if len == max_len {
+            if max_len < buf.len() {
+                // The string runs into kernel space.
+                return Err(KernelError::Fault);
+            }
 
-        if len == buf.len() {
             // We didn't find a NULL byte and filled up the buffer.
             return Err(KernelError::BufferFull);
         }
@@ -4,13 +4,13 @@ use super::{ctx::Context, thread_group::signal::SigSet};
 use crate::kernel::cpu_id::CpuId;
 use crate::memory::uaccess::copy_to_user;
 use crate::{
-    process::{TASK_LIST, Task, TaskState},
+    process::{TASK_LIST, Task, TaskState, Tid, thread_group::ThreadGroup},
     sched::{self, current::current_task},
     sync::SpinLock,
 };
 use alloc::boxed::Box;
 use bitflags::bitflags;
-use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::{AtomicBool, AtomicUsize};
 use libkernel::memory::address::TUA;
 use libkernel::{
     error::{KernelError, Result},
@@ -49,6 +49,40 @@ bitflags! {
     }
 }
 
+/// The thread group a task cloned from one in `process` joins, and its thread
+/// ID. A thread shares its creator's process ID, whereas anything else becomes
+/// the leader of a new process, whose ID it takes as its thread ID.
+fn clone_identity(
+    process: &Arc<ThreadGroup>,
+    flags: &CloneFlags,
+) -> Result<(Arc<ThreadGroup>, Tid)> {
+    if flags.contains(CloneFlags::CLONE_THREAD) {
+        // CLONE_THREAD requires both CLONE_SIGHAND and CLONE_VM to be set.
+        if !flags.contains(CloneFlags::CLONE_SIGHAND | CloneFlags::CLONE_VM) {
+            return Err(KernelError::InvalidValue);
+        }
+
+        // A new task within this thread group.
+        return Ok((process.clone(), process.next_tid()));
+    }
+
+    let tgid_parent = if flags.contains(CloneFlags::CLONE_PARENT) {
+        // Use the parent's parent as the new parent.
+        process
+            .parent
+            .lock_save_irq()
+            .clone()
+            .and_then(|p| p.upgrade())
+            // We cannot call CLONE_PARENT on the init process (which should be
+            // the only process which doesn't have a parent).
+            .ok_or(KernelError::InvalidValue)?
+    } else {
+        process.clone()
+    };
+
+    Ok(tgid_parent.new_child(flags.contains(CloneFlags::CLONE_SIGHAND)))
+}
+
 pub async fn sys_clone(
     flags: u32,
     newsp: UA,
@@ -75,37 +109,11 @@ pub async fn sys_clone(
             user_ctx.tpid_el0 = tls as _;
         }
 
-        let (tg, tid) = if flags.contains(CloneFlags::CLONE_THREAD) {
-            if !flags.contains(CloneFlags::CLONE_SIGHAND & CloneFlags::CLONE_VM) {
-                // CLONE_THREAD requires both CLONE_SIGHAND and CLONE_VM to be
-                // set.
-                return Err(KernelError::InvalidValue);
-            }
-            user_ctx.sp_el0 = newsp.value() as _;
+        let (tg, tid) = clone_identity(&current_task.process, &flags)?;
 
-            (
-                // A new task within this thread group.
-                current_task.process.clone(),
-                current_task.process.next_tid(),
-            )
-        } else {
-            let tgid_parent = if flags.contains(CloneFlags::CLONE_PARENT) {
-                // Use the parent's parent as the new parent.
-                current_task
-                    .process
-                    .parent
-                    .lock_save_irq()
-                    .clone()
-                    .and_then(|p| p.upgrade())
-                    // We cannot call CLONE_PARENT on the init process (which
-                    // should be the only process which doesn't have a parent).
-                    .ok_or(KernelError::InvalidValue)?
-            } else {
-                current_task.process.clone()
-            };
-
-            tgid_parent.new_child(flags.contains(CloneFlags::CLONE_SIGHAND))
-        };
+        if flags.contains(CloneFlags::CLONE_THREAD) {
+            user_ctx.sp_el0 = newsp.value() as _;
+        }
 
         let vm = if flags.contains(CloneFlags::CLONE_VM) {
             current_task.vm.clone()
@@ -161,6 +169,7 @@ pub async fn sys_clone(
             } else {
                 None
             },
+            fault_info: None,
             t_shared: Arc::new(Task {
                 tid,
                 comm: Arc::new(SpinLock::new(*current_task.comm.lock_save_irq())),
@@ -172,6 +181,7 @@ pub async fn sys_clone(
                 creds: SpinLock::new(creds),
                 state: Arc::new(SpinLock::new(TaskState::Runnable)),
                 last_cpu: SpinLock::new(CpuId::this()),
+                on_cpu: AtomicBool::new(false),
                 ptrace: SpinLock::new(ptrace),
                 utime: AtomicUsize::new(0),
                 stime: AtomicUsize::new(0),
@@ -206,3 +216,37 @@ pub async fn sys_clone(
 
     Ok(tid.value() as _)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ktest,
+        process::thread_group::{builder::ThreadGroupBuilder, pid::ppid},
+    };
+
+    ktest! {
+        fn cloned_thread_shares_pid() {
+            let parent = ThreadGroupBuilder::new(ThreadGroup::next_tgid()).build();
+
+            // A fork leads a new process, so its tid is its pid.
+            let (process, leader) = clone_identity(&parent, &CloneFlags::empty()).unwrap();
+            assert_ne!(process.tgid, parent.tgid);
+            assert_eq!(leader, Tid::from_tgid(process.tgid));
+            assert_eq!(ppid(&process), parent.tgid.value());
+
+            let thread_flags =
+                CloneFlags::CLONE_THREAD | CloneFlags::CLONE_SIGHAND | CloneFlags::CLONE_VM;
+            let (group, thread) = clone_identity(&process, &thread_flags).unwrap();
+            assert!(Arc::ptr_eq(&group, &process));
+            assert_ne!(thread, leader);
+            assert_eq!(ppid(&group), parent.tgid.value());
+
+            // Threads must share their creator's memory and signal handlers.
+            assert!(matches!(
+                clone_identity(&process, &CloneFlags::CLONE_THREAD),
+                Err(KernelError::InvalidValue)
+            ));
+        }
+    }
+}
@@ -4,17 +4,26 @@ use crate::process::ptrace::{TracePoint, ptrace_stop};
 use crate::sched::current::current_task_shared;
 use crate::{
     arch::Arch,
+    clock::vvar::{map_vvar, vvar_vma},
+    drivers::timer::USER_HZ,
     fs::VFS,
+    kernel::random::fill_random_bytes,
     memory::{
         page::ClaimedPage,
+        shared::{release, shared_ranges},
         uaccess::{copy_from_user, cstr::UserCStr},
     },
-    process::{ctx::Context, thread_group::signal::SignalActionState},
-    sched::current::current_task,
+    process::{
+        TASK_LIST, ctx::Context, exit::kill_other_threads, thread_group::signal::SignalActionState,
+    },
+    sched::{current::current_task, wait_off_cpu},
 };
 use alloc::{string::String, vec};
 use alloc::{string::ToString, sync::Arc, vec::Vec};
-use auxv::{AT_BASE, AT_ENTRY, AT_NULL, AT_PAGESZ, AT_PHDR, AT_PHENT, AT_PHNUM, AT_RANDOM};
+use auxv::{
+    AT_BASE, AT_CLKTCK, AT_ENTRY, AT_HWCAP, AT_HWCAP2, AT_NULL, AT_PAGESZ, AT_PHDR, AT_PHENT,
+    AT_PHNUM, AT_RANDOM,
+};
 use core::{ffi::c_char, mem, slice};
 use libkernel::{
     UserAddressSpace, VirtualMemory,
@@ -33,10 +42,10 @@ use libkernel::{
     },
 };
 use object::Endian;
-use object::elf::{ET_DYN, ProgramHeader64};
+use object::elf::{EM_AARCH64, ET_DYN, ET_EXEC, PF_W, PF_X, ProgramHeader64};
 use object::{
     LittleEndian,
-    elf::{self, PT_LOAD},
+    elf::{self, PT_LOAD, PT_PHDR},
     read::elf::{FileHeader, ProgramHeader},
 };
 
@@ -49,11 +58,58 @@ const STACK_END: usize = 0x0000_8000_0000_0000;
 const STACK_SZ: usize = 0x2000 * 0x400;
 const STACK_START: usize = STACK_END - STACK_SZ;
 
+/// Parses an ELF header, checking that it's an executable or shared object
+/// for this machine.
+fn parse_elf_header(buf: &[u8]) -> Result<&elf::FileHeader64<LittleEndian>> {
+    let elf =
+        elf::FileHeader64::<LittleEndian>::parse(buf).map_err(|_| ExecError::InvalidElfFormat)?;
+    let endian = elf.endian().map_err(|_| ExecError::InvalidElfFormat)?;
+
+    if elf.e_machine(endian) != EM_AARCH64 {
+        return Err(ExecError::InvalidElfFormat.into());
+    }
+
+    match elf.e_type(endian) {
+        ET_EXEC | ET_DYN => Ok(elf),
+        _ => Err(ExecError::InvalidElfFormat.into()),
+    }
+}
+
+/// Returns the length of the prefix of a `file_size`-byte file that holds the
+/// program header table, checking that the table fits in the file and uses
+/// ELF64 entries.
+fn ph_table_end(elf: &elf::FileHeader64<LittleEndian>, file_size: u64) -> Result<usize> {
+    let endian = elf.endian().map_err(|_| ExecError::InvalidElfFormat)?;
+    let entsize = elf.e_phentsize.get(endian) as u64;
+
+    if entsize != mem::size_of::<ProgramHeader64<LittleEndian>>() as u64 {
+        return Err(ExecError::InvalidPHdrFormat.into());
+    }
+
+    let end = (elf.e_phnum.get(endian) as u64)
+        .checked_mul(entsize)
+        .and_then(|size| size.checked_add(elf.e_phoff.get(endian)))
+        .filter(|&end| end <= file_size)
+        .ok_or(ExecError::InvalidPHdrFormat)?;
+
+    Ok(end as usize)
+}
+
+/// Reads the start of `inode` up to the end of its program header table.
+async fn read_ph_table(
+    inode: &Arc<dyn Inode>,
+    elf: &elf::FileHeader64<LittleEndian>,
+) -> Result<Vec<u8>> {
+    let end = ph_table_end(elf, inode.getattr().await?.size)?;
+    let mut buf = vec![0u8; end];
+
+    inode.read_at(0, &mut buf).await?;
+
+    Ok(buf)
+}
+
 /// Process a set of progream headers from an ELF. Create VMAs for all `PT_LOAD`
 /// segments, optionally applying `bias` to the load address.
-///
-/// If a VMA was found that contains the headers themselves, the address of the
-/// *VMA* is returned.
 fn process_prog_headers<E: Endian>(
     hdrs: &[ProgramHeader64<E>],
     vmas: &mut Vec<VMArea>,
@@ -61,26 +117,118 @@ fn process_prog_headers<E: Endian>(
     elf_file: Arc<dyn Inode>,
     path: &Path,
     endian: E,
-) -> Option<VA> {
-    let mut hdr_addr = None;
-
+) -> Result<()> {
     for hdr in hdrs {
         if hdr.p_type(endian) == PT_LOAD {
-            let mut vma = VMArea::from_pheader(elf_file.clone(), *hdr, endian, bias);
-
-            // Find PHDR: Assumption segment with p_offset == 0 contains
-            // headers.
-            if hdr.p_offset.get(endian) == 0 {
-                hdr_addr = Some(vma.region().start_address());
+            // W^X: refuse segments that would be both writable and executable.
+            if hdr.p_flags(endian) & (PF_W | PF_X) == PF_W | PF_X {
+                return Err(ExecError::InvalidPHdrFormat.into());
             }
 
+            let mut vma = VMArea::from_pheader(elf_file.clone(), *hdr, endian, bias);
+
             vma.set_name(path.as_str());
 
             vmas.push(vma);
         }
     }
 
-    hdr_addr
+    Ok(())
+}
+
+/// Finds where the program header table will be mapped, for `AT_PHDR`. That's
+/// given by `PT_PHDR` if there is one, otherwise it's found from the `PT_LOAD`
+/// segment which covers it in the file.
+fn phdr_addr<E: Endian>(
+    hdrs: &[ProgramHeader64<E>],
+    phoff: u64,
+    bias: Option<usize>,
+    endian: E,
+) -> Option<VA> {
+    let vaddr = hdrs
+        .iter()
+        .find(|hdr| hdr.p_type(endian) == PT_PHDR)
+        .map(|hdr| hdr.p_vaddr(endian))
+        .or_else(|| {
+            hdrs.iter()
+                .filter(|hdr| hdr.p_type(endian) == PT_LOAD)
+                .find(|hdr| {
+                    let offset = hdr.p_offset(endian);
+                    (offset..offset + hdr.p_filesz(endian)).contains(&phoff)
+                })
+                .map(|hdr| hdr.p_vaddr(endian) + (phoff - hdr.p_offset(endian)))
+        })?;
+
+    Some(VA::from_value(vaddr as usize + bias.unwrap_or(0)))
+}
+
+/// A program's `PT_LOAD` segments laid out for its new address space.
+struct ElfImage {
+    vmas: Vec<VMArea>,
+    /// The program's entry point, biased for PIE.
+    entry: VA,
+    /// The start of the heap, just above the highest segment.
+    brk_start: VA,
+    auxv: Vec<u64>,
+}
+
+/// Creates the VMAs for a program's segments, from its header and a buffer
+/// holding (at least) the file up to the end of its program header table.
+fn layout_elf(
+    elf: &elf::FileHeader64<LittleEndian>,
+    ph_buf: &[u8],
+    inode: Arc<dyn Inode>,
+    path: &Path,
+) -> Result<ElfImage> {
+    let endian = elf.endian().map_err(|_| ExecError::InvalidElfFormat)?;
+
+    let hdrs = elf
+        .program_headers(endian, ph_buf)
+        .map_err(|_| ExecError::InvalidPHdrFormat)?;
+
+    // Set up a program bias for PIE.
+    let bias = if elf.e_type.get(endian) == ET_DYN {
+        Some(PROG_BIAS)
+    } else {
+        None
+    };
+
+    let mut auxv = vec![
+        AT_PHNUM,
+        elf.e_phnum.get(endian) as _,
+        AT_PHENT,
+        elf.e_phentsize(endian) as _,
+    ];
+
+    let mut vmas = Vec::new();
+
+    // Process the binary program headers.
+    process_prog_headers(hdrs, &mut vmas, bias, inode, path, endian)?;
+
+    if let Some(hdr_addr) = phdr_addr(hdrs, elf.e_phoff(endian), bias, endian) {
+        auxv.push(AT_PHDR);
+        auxv.push(hdr_addr.value() as _);
+    }
+
+    // The heap begins after the program's highest segment.
+    let brk_start = vmas
+        .iter()
+        .map(|vma| vma.region().end_address())
+        .max()
+        .ok_or(ExecError::InvalidPHdrFormat)?;
+
+    let entry = VA::from_value(elf.e_entry(endian) as usize + bias.unwrap_or(0));
+
+    // AT_ENTRY is the same in the static and interp case.
+    auxv.push(AT_ENTRY);
+    auxv.push(entry.value() as _);
+
+    Ok(ElfImage {
+        vmas,
+        entry,
+        brk_start,
+        auxv,
+    })
 }
 
 async fn exec_elf(
@@ -93,16 +241,11 @@ async fn exec_elf(
     let mut buf = [0u8; core::mem::size_of::<elf::FileHeader64<LittleEndian>>()];
     inode.read_at(0, &mut buf).await?;
 
-    let elf = elf::FileHeader64::<LittleEndian>::parse(buf.as_slice())
-        .map_err(|_| ExecError::InvalidElfFormat)?;
+    let elf = parse_elf_header(buf.as_slice())?;
     let endian = elf.endian().unwrap();
 
     // Read full program header table
-    let ph_table_size = elf.e_phnum.get(endian) as usize * elf.e_phentsize.get(endian) as usize
-        + elf.e_phoff.get(endian) as usize;
-    let mut ph_buf = vec![0u8; ph_table_size];
-
-    inode.read_at(0, &mut ph_buf).await?;
+    let ph_buf = read_ph_table(&inode, elf).await?;
 
     let hdrs = elf
         .program_headers(endian, ph_buf.as_slice())
@@ -128,47 +271,24 @@ async fn exec_elf(
         }
     }
 
-    // Set up a program bias for PIE.
-    let main_bias = if elf.e_type.get(endian) == ET_DYN {
-        Some(PROG_BIAS)
-    } else {
-        None
-    };
-
-    let mut auxv = vec![
-        AT_PHNUM,
-        elf.e_phnum.get(endian) as _,
-        AT_PHENT,
-        elf.e_phentsize(endian) as _,
-    ];
-
-    let mut vmas = Vec::new();
-
-    // Process the binary program headers.
-    if let Some(hdr_addr) =
-        process_prog_headers(hdrs, &mut vmas, main_bias, inode.clone(), path, endian)
-    {
-        auxv.push(AT_PHDR);
-        auxv.push(hdr_addr.add_bytes(elf.e_phoff(endian) as _).value() as _);
-    }
-
-    let main_entry = VA::from_value(elf.e_entry(endian) as usize + main_bias.unwrap_or(0));
-
-    // AT_ENTRY is the same in the static and interp case.
-    auxv.push(AT_ENTRY);
-    auxv.push(main_entry.value() as _);
+    let mut image = layout_elf(elf, &ph_buf, inode.clone(), path)?;
 
     let entry_addr = if let Some(path) = interp_path {
-        auxv.push(AT_BASE);
-        auxv.push(LINKER_BIAS as _);
-
-        // Returns the entry address of the interp program.
-        process_interp(path, &mut vmas).await?
+        // Control goes to the interpreter, which finds the program through
+        // the auxv.
+        process_interp(path, &mut image).await?
     } else {
         // Otherwise, it's just the binary itself.
-        main_entry
+        image.entry
     };
 
+    let ElfImage {
+        mut vmas,
+        brk_start,
+        auxv,
+        ..
+    } = image;
+
     let mut stack_vma = VMArea::new(
         VirtMemoryRegion::new(VA::from_value(STACK_START), STACK_SZ),
         VMAreaKind::Anon,
@@ -178,15 +298,17 @@ async fn exec_elf(
     stack_vma.set_name("[stack]");
 
     vmas.push(stack_vma);
+    vmas.push(vvar_vma());
 
     let mut mem_map = MemoryMap::from_vmas(vmas)?;
+    map_vvar(mem_map.address_space_mut())?;
     let stack_ptr = setup_user_stack(&mut mem_map, &argv, &envp, auxv)?;
 
     // We are now committed to the exec.  Inform ptrace.
     ptrace_stop(TracePoint::Exec).await;
 
     let user_ctx = ArchImpl::new_user_context(entry_addr, stack_ptr);
-    let mut vm = ProcessVM::from_map(mem_map);
+    let mut vm = ProcessVM::from_map(mem_map, brk_start);
 
     // We don't have to worry about actually calling for a full context switch
     // here. Parts of the old process that are replaced will go out of scope and
@@ -196,7 +318,13 @@ async fn exec_elf(
 
     let new_comm = argv.first().map(|s| Comm::new(s.as_str()));
 
-    {
+    // The other threads are running the old image, so they go. The old address
+    // space is swapped out first, so that any of them scheduled from here on
+    // see the new one, and only torn down once none is still running on it.
+    let this = current_task_shared();
+    let others = kill_other_threads(&this);
+
+    let mut old_vm = {
         let mut current_task = current_task();
 
         if let Some(new_comm) = new_comm {
@@ -204,10 +332,22 @@ async fn exec_elf(
         }
 
         current_task.ctx = Context::from_user_ctx(user_ctx);
-        *current_task.vm.lock_save_irq() = vm;
         *current_task.process.signals.lock_save_irq() = SignalActionState::new_default();
+
+        mem::replace(&mut *current_task.vm.lock_save_irq(), vm)
+    };
+
+    for thread in others {
+        wait_off_cpu(&thread);
+
+        this.process.tasks.lock_save_irq().remove(&thread.tid);
+        TASK_LIST.lock_save_irq().remove(&thread.descriptor());
     }
 
+    let shared = shared_ranges(&mut old_vm, None);
+    old_vm.destroy();
+    release(shared).await;
+
     // Close all the CLOEXEC FDs.
     let mut fd_table = current_task().fd_table.lock_save_irq().clone();
     fd_table.close_cloexec_entries().await;
@@ -271,10 +411,15 @@ pub async fn kernel_exec(
     }
 }
 
-// Sets up the user stack according to the System V ABI.
+/// Bytes of random data pointed to by `AT_RANDOM`.
+const AT_RANDOM_SZ: usize = 16;
+
+// Builds the initial user stack according to the System V ABI, returning its
+// contents (which end at `STACK_END`) and the initial stack pointer.
 //
 // The stack layout from high addresses to low addresses is:
 // - Argument and Environment strings
+// - The `AT_RANDOM` bytes
 // - Padding to 16-byte boundary
 // - Auxiliary Vector (auxv)
 // - Environment pointers (envp)
@@ -282,12 +427,12 @@ pub async fn kernel_exec(
 // - Argument count (argc)
 //
 // The final stack pointer will point to `argc`.
-fn setup_user_stack(
-    mm: &mut MemoryMap<<ArchImpl as VirtualMemory>::ProcessAddressSpace>,
+fn build_user_stack(
     argv: &[String],
     envp: &[String],
     mut auxv: Vec<u64>,
-) -> Result<VA> {
+    random: &[u8; AT_RANDOM_SZ],
+) -> Result<(Vec<u8>, VA)> {
     // Calculate the space needed and the virtual addresses for all strings and
     // pointers.
     let mut string_addrs = Vec::new();
@@ -309,6 +454,9 @@ fn setup_user_stack(
 
     let (envp_addrs, argv_addrs) = string_addrs.split_at(envp.len());
 
+    let strings_base_va = STACK_END - total_string_size;
+    let random_va = strings_base_va - AT_RANDOM_SZ;
+
     let mut info_block = Vec::<u64>::new();
     info_block.push(argv.len() as u64); // argc
     info_block.extend(argv_addrs.iter().map(|&addr| addr as u64));
@@ -316,14 +464,23 @@ fn setup_user_stack(
     info_block.extend(envp_addrs.iter().map(|&addr| addr as u64));
     info_block.push(0); // Null terminator for envp
 
+    let (hwcap, hwcap2) = ArchImpl::user_hwcaps();
+
     // Add auxiliary vectors
-    auxv.push(AT_PAGESZ);
-    auxv.push(PAGE_SIZE as u64);
-    auxv.push(AT_RANDOM);
-    // TODO: SECURITY: Actually make this a random value.
-    auxv.push(STACK_END as u64 - 0x10);
-    auxv.push(AT_NULL);
-    auxv.push(0);
+    auxv.extend([
+        AT_PAGESZ,
+        PAGE_SIZE as u64,
+        AT_HWCAP,
+        hwcap,
+        AT_HWCAP2,
+        hwcap2,
+        AT_CLKTCK,
+        USER_HZ,
+        AT_RANDOM,
+        random_va as u64,
+        AT_NULL,
+        0,
+    ]);
 
     info_block.append(&mut auxv);
 
@@ -331,8 +488,7 @@ fn setup_user_stack(
 
     // The top of the info block must be 16-byte aligned. The stack pointer on
     // entry to the new process must also be 16-byte aligned.
-    let strings_base_va = STACK_END - total_string_size;
-    let final_sp_unaligned = strings_base_va - info_block_size;
+    let final_sp_unaligned = random_va - info_block_size;
     let final_sp_val = final_sp_unaligned & !0xF; // Align down to 16 bytes
 
     let total_stack_size = STACK_END - final_sp_val;
@@ -351,6 +507,9 @@ fn setup_user_stack(
         // Null terminator is already there from vec![0;...].
     }
 
+    let random_offset = total_stack_size - (STACK_END - random_va);
+    stack_image[random_offset..random_offset + AT_RANDOM_SZ].copy_from_slice(random);
+
     // Write info block into the image
     let info_block_bytes: &[u8] =
         unsafe { slice::from_raw_parts(info_block.as_ptr().cast(), info_block_size) };
@@ -358,6 +517,23 @@ fn setup_user_stack(
     stack_image[info_block_offset..info_block_offset + info_block_size]
         .copy_from_slice(info_block_bytes);
 
+    Ok((stack_image, VA::from_value(final_sp_val)))
+}
+
+// Builds the initial user stack and maps it into `mm`, returning the initial
+// stack pointer.
+fn setup_user_stack(
+    mm: &mut MemoryMap<<ArchImpl as VirtualMemory>::ProcessAddressSpace>,
+    argv: &[String],
+    envp: &[String],
+    auxv: Vec<u64>,
+) -> Result<VA> {
+    let mut random = [0; AT_RANDOM_SZ];
+    fill_random_bytes(&mut random);
+
+    let (stack_image, stack_ptr) = build_user_stack(argv, envp, auxv, &random)?;
+    let total_stack_size = stack_image.len();
+
     // Allocate pages, copy image, and map into user space
     let num_pages = total_stack_size.div_ceil(PAGE_SIZE);
 
@@ -379,12 +555,53 @@ fn setup_user_stack(
             .map_page(page.leak(), page_va, PtePermissions::rw(true))?;
     }
 
-    Ok(VA::from_value(final_sp_val))
+    Ok(stack_ptr)
+}
+
+/// Lays out the interpreter named by a program's `PT_INTERP` alongside it in
+/// `image`, adding `AT_BASE` to the program's auxv. Returns the interpreter's
+/// entry point.
+fn layout_interp(
+    interp_elf: &elf::FileHeader64<LittleEndian>,
+    ph_buf: &[u8],
+    inode: Arc<dyn Inode>,
+    path: &Path,
+    image: &mut ElfImage,
+) -> Result<VA> {
+    let endian = interp_elf
+        .endian()
+        .map_err(|_| ExecError::InvalidElfFormat)?;
+
+    // The interpreter is always loaded at `LINKER_BIAS`, so it must be
+    // relocatable.
+    if interp_elf.e_type(endian) != ET_DYN {
+        return Err(ExecError::InvalidElfFormat.into());
+    }
+
+    let hdrs = interp_elf
+        .program_headers(endian, ph_buf)
+        .map_err(|_| ExecError::InvalidPHdrFormat)?;
+
+    process_prog_headers(
+        hdrs,
+        &mut image.vmas,
+        Some(LINKER_BIAS),
+        inode,
+        path,
+        endian,
+    )?;
+
+    image.auxv.push(AT_BASE);
+    image.auxv.push(LINKER_BIAS as _);
+
+    Ok(VA::from_value(
+        LINKER_BIAS + interp_elf.e_entry(endian) as usize,
+    ))
 }
 
 // Dynamic linker path: map PT_INTERP interpreter and return start address of
 // the interpreter program.
-async fn process_interp(interp_path: String, vmas: &mut Vec<VMArea>) -> Result<VA> {
+async fn process_interp(interp_path: String, image: &mut ElfImage) -> Result<VA> {
     // Resolve interpreter path from root; this assumes interp_path is absolute.
     let task = current_task_shared();
     let path = Path::new(&interp_path);
@@ -393,33 +610,12 @@ async fn process_interp(interp_path: String, vmas: &mut Vec<VMArea>) -> Result<V
     // Parse interpreter ELF header
     let mut hdr_buf = [0u8; core::mem::size_of::<elf::FileHeader64<LittleEndian>>()];
     interp_inode.read_at(0, &mut hdr_buf).await?;
-    let interp_elf = elf::FileHeader64::<LittleEndian>::parse(&hdr_buf[..])
-        .map_err(|_| ExecError::InvalidElfFormat)?;
-    let iendian = interp_elf.endian().unwrap();
+    let interp_elf = parse_elf_header(&hdr_buf[..])?;
 
     // Read interpreter program headers
-    let interp_ph_table_size = interp_elf.e_phnum.get(iendian) as usize
-        * interp_elf.e_phentsize.get(iendian) as usize
-        + interp_elf.e_phoff.get(iendian) as usize;
-    let mut interp_ph_buf = vec![0u8; interp_ph_table_size];
-    interp_inode.read_at(0, &mut interp_ph_buf).await?;
-    let interp_hdrs = interp_elf
-        .program_headers(iendian, &interp_ph_buf[..])
-        .map_err(|_| ExecError::InvalidPHdrFormat)?;
-
-    // Build VMAs for interpreter
-    process_prog_headers(
-        interp_hdrs,
-        vmas,
-        Some(LINKER_BIAS),
-        interp_inode,
-        path,
-        iendian,
-    );
-
-    let interp_entry = VA::from_value(LINKER_BIAS + interp_elf.e_entry(iendian) as usize);
+    let interp_ph_buf = read_ph_table(&interp_inode, interp_elf).await?;
 
-    Ok(interp_entry)
+    layout_interp(interp_elf, &interp_ph_buf, interp_inode, path, image)
 }
 
 pub async fn sys_execve(
@@ -457,9 +653,306 @@ pub async fn sys_execve(
     }
 
     let path = Path::new(UserCStr::from_ptr(path).copy_from_user(&mut buf).await?);
-    let inode = VFS.resolve_path(path, VFS.root_inode(), &task).await?;
+
+    // Relative paths are resolved from the cwd; absolute ones from the task's
+    // root.
+    let cwd = task.cwd.lock_save_irq().0.clone();
+    let inode = VFS.resolve_path(path, cwd, &task).await?;
 
     kernel_exec(path, inode, argv, envp).await?;
 
     Ok(0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fs::DummyInode, ktest};
+    use object::elf::{EM_X86_64, ET_REL, PF_R, PT_INTERP};
+
+    const EHDR_SIZE: usize = 64;
+    const PHDR_SIZE: usize = 56;
+
+    /// A `PT_LOAD` segment: flags, offset, vaddr, filesz, memsz.
+    type Segment = (u32, u64, u64, u64, u64);
+
+    /// Hand-assembles an ELF64 image with a program header table straight
+    /// after the file header.
+    fn elf_image(e_type: u16, e_machine: u16, entry: u64, segments: &[Segment]) -> Vec<u8> {
+        let phdrs: Vec<_> = segments.iter().map(|&seg| (PT_LOAD, seg)).collect();
+
+        elf_image_with_phdrs(e_type, e_machine, entry, &phdrs)
+    }
+
+    /// Like [`elf_image`], but with program headers of any type.
+    fn elf_image_with_phdrs(
+        e_type: u16,
+        e_machine: u16,
+        entry: u64,
+        phdrs: &[(u32, Segment)],
+    ) -> Vec<u8> {
+        let mut img = Vec::new();
+
+        img.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+        img.extend_from_slice(&[0; 8]);
+        img.extend_from_slice(&e_type.to_le_bytes());
+        img.extend_from_slice(&e_machine.to_le_bytes());
+        img.extend_from_slice(&1u32.to_le_bytes()); // e_version
+        img.extend_from_slice(&entry.to_le_bytes());
+        img.extend_from_slice(&(EHDR_SIZE as u64).to_le_bytes()); // e_phoff
+        img.extend_from_slice(&0u64.to_le_bytes()); // e_shoff
+        img.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+        img.extend_from_slice(&(EHDR_SIZE as u16).to_le_bytes());
+        img.extend_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+        img.extend_from_slice(&(phdrs.len() as u16).to_le_bytes());
+        img.extend_from_slice(&[0; 6]); // No section headers.
+
+        for &(p_type, (flags, offset, vaddr, filesz, memsz)) in phdrs {
+            img.extend_from_slice(&p_type.to_le_bytes());
+            img.extend_from_slice(&flags.to_le_bytes());
+            img.extend_from_slice(&offset.to_le_bytes());
+            img.extend_from_slice(&vaddr.to_le_bytes());
+            img.extend_from_slice(&vaddr.to_le_bytes()); // p_paddr
+            img.extend_from_slice(&filesz.to_le_bytes());
+            img.extend_from_slice(&memsz.to_le_bytes());
+            img.extend_from_slice(&(PAGE_SIZE as u64).to_le_bytes());
+        }
+
+        img
+    }
+
+    fn layout(img: &[u8]) -> Result<ElfImage> {
+        let elf = parse_elf_header(&img[..EHDR_SIZE])?;
+
+        layout_elf(elf, img, Arc::new(DummyInode {}), Path::new("/bin/tiny"))
+    }
+
+    const TEXT: Segment = (PF_R | PF_X, 0, 0x40_0000, 0x200, 0x200);
+    /// 0x10 bytes of data followed by 0x3000 of .bss.
+    const DATA: Segment = (PF_R | PF_W, 0x1010, 0x41_1010, 0x10, 0x3010);
+
+    ktest! {
+        fn elf_loader_maps_static_segments() {
+            let img = elf_image(ET_EXEC, EM_AARCH64, 0x40_0080, &[TEXT, DATA]);
+            let image = layout(&img).unwrap();
+
+            assert_eq!(image.entry, VA::from_value(0x40_0080));
+            assert_eq!(image.vmas.len(), 2);
+
+            let text = &image.vmas[0];
+            assert_eq!(
+                text.region(),
+                VirtMemoryRegion::new(VA::from_value(0x40_0000), PAGE_SIZE)
+            );
+            let perms = text.permissions();
+            assert!(perms.read && perms.execute && !perms.write);
+
+            let data = &image.vmas[1];
+            assert_eq!(
+                data.region(),
+                VirtMemoryRegion::new(VA::from_value(0x41_1000), 4 * PAGE_SIZE)
+            );
+            let perms = data.permissions();
+            assert!(perms.read && perms.write && !perms.execute);
+
+            // Only the first data page reads from the file; past `p_filesz`
+            // the pages are .bss and zero-filled.
+            let read = data.resolve_fault(VA::from_value(0x41_1000)).unwrap();
+            assert_eq!((read.file_offset, read.page_offset, read.read_len), (0x1010, 0x10, 0x10));
+            assert!(data.resolve_fault(VA::from_value(0x41_2000)).is_none());
+
+            assert_eq!(image.brk_start, VA::from_value(0x41_5000));
+
+            // The headers are in the text segment.
+            let at_phdr = image.auxv.iter().position(|&x| x == AT_PHDR).unwrap();
+            assert_eq!(image.auxv[at_phdr + 1], 0x40_0000 + EHDR_SIZE as u64);
+        }
+    }
+
+    ktest! {
+        fn elf_loader_biases_pie() {
+            let img = elf_image(ET_DYN, EM_AARCH64, 0x80, &[(PF_R | PF_X, 0, 0, 0x200, 0x200)]);
+            let image = layout(&img).unwrap();
+
+            assert_eq!(image.entry, VA::from_value(PROG_BIAS + 0x80));
+            assert_eq!(image.vmas[0].region().start_address(), VA::from_value(PROG_BIAS));
+        }
+    }
+
+    ktest! {
+        fn elf_loader_sets_auxv_for_interp() {
+            const LD_SO: &[u8] = b"/lib/ld-linux-aarch64.so.1\0";
+
+            let phdrs_size = 3 * PHDR_SIZE as u64;
+            let interp_off = EHDR_SIZE as u64 + phdrs_size;
+            let img = elf_image_with_phdrs(
+                ET_DYN,
+                EM_AARCH64,
+                0x680,
+                &[
+                    (PT_PHDR, (PF_R, EHDR_SIZE as u64, EHDR_SIZE as u64, phdrs_size, phdrs_size)),
+                    (PT_INTERP, (PF_R, interp_off, interp_off, LD_SO.len() as u64, LD_SO.len() as u64)),
+                    (PT_LOAD, (PF_R | PF_X, 0, 0, 0x800, 0x800)),
+                ],
+            );
+            let mut image = layout(&img).unwrap();
+
+            let interp = elf_image(ET_DYN, EM_AARCH64, 0x1c40, &[(PF_R | PF_X, 0, 0, 0x2000, 0x2000)]);
+            let interp_elf = parse_elf_header(&interp[..EHDR_SIZE]).unwrap();
+            let entry = layout_interp(
+                interp_elf,
+                &interp,
+                Arc::new(DummyInode {}),
+                Path::new("/lib/ld-linux-aarch64.so.1"),
+                &mut image,
+            )
+            .unwrap();
+
+            // Control goes to the interpreter...
+            assert_eq!(entry, VA::from_value(LINKER_BIAS + 0x1c40));
+
+            // ...which is told where it is, and where the program and its
+            // headers are.
+            assert_eq!(
+                image.auxv,
+                [
+                    AT_PHNUM, 3,
+                    AT_PHENT, PHDR_SIZE as u64,
+                    AT_PHDR, (PROG_BIAS + EHDR_SIZE) as u64,
+                    AT_ENTRY, (PROG_BIAS + 0x680) as u64,
+                    AT_BASE, LINKER_BIAS as u64,
+                ]
+            );
+
+            // Both images are mapped.
+            assert_eq!(image.vmas.len(), 2);
+            assert_eq!(image.vmas[1].region().start_address(), VA::from_value(LINKER_BIAS));
+
+            // The interpreter has to be relocatable.
+            let interp = elf_image(ET_EXEC, EM_AARCH64, 0x40_0080, &[TEXT]);
+            let interp_elf = parse_elf_header(&interp[..EHDR_SIZE]).unwrap();
+            assert_eq!(
+                layout_interp(interp_elf, &interp, Arc::new(DummyInode {}), Path::new("/lib/ld.so"), &mut image).err(),
+                Some(ExecError::InvalidElfFormat.into())
+            );
+        }
+    }
+
+    ktest! {
+        fn elf_loader_bounds_phdr_table() {
+            let img = elf_image(ET_EXEC, EM_AARCH64, 0x40_0080, &[TEXT, DATA]);
+            let elf = parse_elf_header(&img[..EHDR_SIZE]).unwrap();
+            let end = EHDR_SIZE + 2 * PHDR_SIZE;
+
+            assert_eq!(ph_table_end(elf, img.len() as u64), Ok(end));
+
+            // The table has to fit in the file.
+            assert_eq!(
+                ph_table_end(elf, end as u64 - 1),
+                Err(ExecError::InvalidPHdrFormat.into())
+            );
+
+            // An offset near the top of the address space mustn't wrap.
+            let mut bad = img.clone();
+            bad[32..40].copy_from_slice(&u64::MAX.to_le_bytes());
+            let elf = parse_elf_header(&bad[..EHDR_SIZE]).unwrap();
+            assert_eq!(
+                ph_table_end(elf, u64::MAX),
+                Err(ExecError::InvalidPHdrFormat.into())
+            );
+
+            // Entries must be ELF64 program headers.
+            let mut bad = img.clone();
+            bad[54..56].copy_from_slice(&(PHDR_SIZE as u16 + 8).to_le_bytes());
+            let elf = parse_elf_header(&bad[..EHDR_SIZE]).unwrap();
+            assert_eq!(
+                ph_table_end(elf, u64::MAX),
+                Err(ExecError::InvalidPHdrFormat.into())
+            );
+        }
+    }
+
+    /// Reads the native-endian word at `va` in a stack image ending at
+    /// `STACK_END`.
+    fn stack_word(stack: &[u8], va: usize) -> u64 {
+        let offset = stack.len() - (STACK_END - va);
+
+        u64::from_ne_bytes(stack[offset..offset + 8].try_into().unwrap())
+    }
+
+    /// Reads the NUL-terminated string at `va` in a stack image.
+    fn stack_str(stack: &[u8], va: usize) -> &str {
+        let bytes = &stack[stack.len() - (STACK_END - va)..];
+        let len = bytes.iter().position(|&b| b == 0).unwrap();
+
+        core::str::from_utf8(&bytes[..len]).unwrap()
+    }
+
+    ktest! {
+        fn user_stack_carries_auxv() {
+            let img = elf_image(ET_EXEC, EM_AARCH64, 0x40_0080, &[TEXT, DATA]);
+            let image = layout(&img).unwrap();
+
+            let argv = vec!["/bin/tiny".to_string(), "-v".to_string()];
+            let envp = vec!["HOME=/".to_string()];
+            let random = *b"0123456789abcdef";
+
+            let (stack, sp) = build_user_stack(&argv, &envp, image.auxv, &random).unwrap();
+            let sp = sp.value();
+            assert_eq!(sp % 16, 0);
+
+            // argc, then argv and envp, each NULL-terminated.
+            let word = |i: usize| stack_word(&stack, sp + i * 8);
+            assert_eq!(word(0), 2);
+            assert_eq!(stack_str(&stack, word(1) as _), "/bin/tiny");
+            assert_eq!(stack_str(&stack, word(2) as _), "-v");
+            assert_eq!(word(3), 0);
+            assert_eq!(stack_str(&stack, word(4) as _), "HOME=/");
+            assert_eq!(word(5), 0);
+
+            // Then the auxv, up to AT_NULL.
+            let mut auxv = alloc::collections::BTreeMap::new();
+            let mut i = 6;
+            while word(i) != AT_NULL {
+                assert!(auxv.insert(word(i), word(i + 1)).is_none());
+                i += 2;
+            }
+
+            let (hwcap, hwcap2) = ArchImpl::user_hwcaps();
+            assert_eq!(auxv[&AT_PAGESZ], PAGE_SIZE as u64);
+            assert_eq!(auxv[&AT_HWCAP], hwcap);
+            assert_eq!(auxv[&AT_HWCAP2], hwcap2);
+            assert_eq!(auxv[&AT_CLKTCK], 100);
+            assert_eq!(auxv[&AT_PHDR], 0x40_0000 + EHDR_SIZE as u64);
+            assert_eq!(auxv[&AT_PHENT], PHDR_SIZE as u64);
+            assert_eq!(auxv[&AT_PHNUM], 2);
+            assert_eq!(auxv[&AT_ENTRY], 0x40_0080);
+            assert_eq!(auxv.len(), 9);
+
+            // AT_RANDOM points at the random bytes, clear of the strings.
+            let at_random = auxv[&AT_RANDOM] as usize;
+            let offset = stack.len() - (STACK_END - at_random);
+            assert_eq!(stack[offset..offset + AT_RANDOM_SZ], random);
+            assert!(at_random + AT_RANDOM_SZ <= word(4) as usize);
+        }
+    }
+
+    ktest! {
+        fn elf_loader_rejects_bad_images() {
+            let invalid_elf: KernelError = ExecError::InvalidElfFormat.into();
+
+            let img = elf_image(ET_EXEC, EM_X86_64, 0x40_0080, &[TEXT]);
+            assert_eq!(layout(&img).err(), Some(invalid_elf.clone()));
+
+            let img = elf_image(ET_REL, EM_AARCH64, 0, &[TEXT]);
+            assert_eq!(layout(&img).err(), Some(invalid_elf));
+
+            // W^X
+            let img = elf_image(ET_EXEC, EM_AARCH64, 0x40_0080, &[(PF_R | PF_W | PF_X, 0, 0x40_0000, 0x200, 0x200)]);
+            assert_eq!(
+                layout(&img).err(),
+                Some(ExecError::InvalidPHdrFormat.into())
+            );
+        }
+    }
+}
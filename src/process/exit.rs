@@ -1,18 +1,31 @@
 use super::{
-    TASK_LIST, TaskState,
+    ProcVM, TASK_LIST, Task, TaskState,
+    fd_table::FileDescriptorTable,
     ptrace::{TracePoint, ptrace_stop},
     thread_group::{ProcessState, Tgid, ThreadGroup, signal::SigId, wait::ChildState},
     threading::futex::{self, key::FutexKey},
 };
-use crate::sched::current::current_task;
-use crate::{memory::uaccess::copy_to_user, sched::current::current_task_shared};
-use alloc::vec::Vec;
+use crate::sched::{current::current_task, wait_off_cpu};
+use crate::sync::SpinLock;
+use crate::{
+    memory::{
+        shared::{release, shared_ranges},
+        uaccess::copy_to_user,
+    },
+    sched::current::current_task_shared,
+};
+use alloc::{boxed::Box, sync::Weak, vec::Vec};
 use libkernel::error::Result;
 use log::warn;
 use ringbuf::Arc;
 
-pub fn do_exit_group(exit_code: ChildState) {
-    let task = current_task();
+/// Terminates every thread of the current process, then tears the process
+/// down and leaves it a zombie for its parent to reap.
+///
+/// Resources are released in a fixed order: open files (giving each its final
+/// `release`), the `CLONE_CHILD_CLEARTID` word, and then the address space.
+pub async fn do_exit_group(exit_code: ChildState) {
+    let task = current_task_shared();
     let process = Arc::clone(&task.process);
 
     if process.tgid.is_init() {
@@ -42,42 +55,52 @@ pub fn do_exit_group(exit_code: ChildState) {
         *process_state = ProcessState::Exiting;
     }
 
-    // Signal all other threads in the group to terminate. We iterate over Weak
-    // pointers and upgrade them.
-    for thread_weak in process.tasks.lock_save_irq().values() {
-        if let Some(other_thread) = thread_weak.upgrade() {
-            // Don't signal ourselves
-            if other_thread.tid != task.tid {
-                // TODO: Send an IPI/Signal to halt execution now. For now, just
-                // wait for the scheduler to never schedule any of it's tasks
-                // again.
-                *other_thread.state.lock_save_irq() = TaskState::Finished;
-            }
-        }
-    }
+    let others = kill_other_threads(&task);
 
-    // TODO: For a UMP system, the above is sufficient, however on SMP, we need
-    // to wait for all the processes to have stopped execution before tearing
-    // down the address-space, etc.
+    release_files(&task.fd_table, &process).await;
 
-    // Reparent children to `init`
-    {
-        let mut our_children = process.children.lock_save_irq();
+    clear_child_tid().await;
 
-        let init = ThreadGroup::get(Tgid::init()).expect("Could not find init process");
+    release_vm(&task.vm, &process, &others).await;
 
-        let mut init_children = init.children.lock_save_irq();
+    exit_to_parent(&process, &parent, exit_code);
 
-        let mut our_children: Vec<_> = core::mem::take(&mut *our_children).into_iter().collect();
+    // This thread is now finished.
+    *task.state.lock_save_irq() = TaskState::Finished;
 
-        for (tgid, our_child) in our_children.drain(..) {
-            *our_child.parent.lock_save_irq() = Some(Arc::downgrade(&init));
+    // NOTE: that the scheduler will never execute the task again since it's
+    // state is set to Finished.
+}
 
-            init_children.insert(tgid, our_child);
-        }
+/// Kills every other thread of `task`'s process. Each is marked `Finished`, so
+/// it's switched out at its next pass through the scheduler and never runs
+/// again. They're returned so that the caller can wait, with
+/// [`wait_off_cpu`], for them to leave their CPUs before tearing down anything
+/// they use.
+pub fn kill_other_threads(task: &Task) -> Vec<Arc<Task>> {
+    let others: Vec<_> = task
+        .process
+        .tasks
+        .lock_save_irq()
+        .values()
+        .filter_map(Weak::upgrade)
+        .filter(|other| other.tid != task.tid)
+        .collect();
+
+    for other in &others {
+        *other.state.lock_save_irq() = TaskState::Finished;
     }
 
-    parent.children.lock_save_irq().remove(&process.tgid);
+    others
+}
+
+/// Hands `process`'s children over to `init` and leaves `process` a zombie: it
+/// stays in `parent`'s list of children, holding only its exit status, until
+/// `parent` reaps it with `wait4()` or `waitid()`.
+pub fn exit_to_parent(process: &ThreadGroup, parent: &ThreadGroup, exit_code: ChildState) {
+    reparent_children(process);
+
+    *process.state.lock_save_irq() = ProcessState::Zombie;
 
     parent.child_notifiers.child_update(process.tgid, exit_code);
 
@@ -85,16 +108,128 @@ pub fn do_exit_group(exit_code: ChildState) {
         .pending_signals
         .lock_save_irq()
         .set_signal(SigId::SIGCHLD);
+}
 
-    // 5. This thread is now finished.
-    *task.state.lock_save_irq() = TaskState::Finished;
+/// Reparents `process`'s children, along with any state changes of theirs
+/// that it hasn't waited for, to `init`.
+fn reparent_children(process: &ThreadGroup) {
+    let our_children = core::mem::take(&mut *process.children.lock_save_irq());
 
-    // NOTE: that the scheduler will never execute the task again since it's
-    // state is set to Finished.
+    if our_children.is_empty() {
+        return;
+    }
+
+    let init = ThreadGroup::get(Tgid::init()).expect("Could not find init process");
+
+    {
+        let mut init_children = init.children.lock_save_irq();
+
+        for (tgid, our_child) in our_children {
+            *our_child.parent.lock_save_irq() = Some(Arc::downgrade(&init));
+
+            init_children.insert(tgid, our_child);
+        }
+    }
+
+    // Children that have already exited are zombies, which `init` must now
+    // reap.
+    for (tgid, state) in process.child_notifiers.take_all() {
+        init.child_notifiers.child_update(tgid, state);
+    }
+}
+
+/// Returns whether only tasks of `process` hold references to `res`, as
+/// selected from each task by `get`.
+fn private_to_group<T>(
+    res: &Arc<T>,
+    process: &ThreadGroup,
+    get: impl Fn(&Task) -> &Arc<T>,
+) -> bool {
+    let group_users = process
+        .tasks
+        .lock_save_irq()
+        .values()
+        .filter_map(Weak::upgrade)
+        .filter(|task| Arc::ptr_eq(get(task), res))
+        .count();
+
+    Arc::strong_count(res) <= group_users
+}
+
+/// Closes the exiting process's files, unless a task outside the group shares
+/// its file descriptor table (`CLONE_FILES`).
+async fn release_files(files: &Arc<SpinLock<FileDescriptorTable>>, process: &ThreadGroup) {
+    if !private_to_group(files, process, |task| &task.fd_table) {
+        return;
+    }
+
+    let mut table = core::mem::take(&mut *files.lock_save_irq());
+
+    table.close_all().await;
+}
+
+/// Honours `CLONE_CHILD_CLEARTID`: clears the exiting thread's TID word and
+/// futex-wakes a waiter on it.
+async fn clear_child_tid() {
+    let Some(ptr) = current_task().child_tid_ptr.take() else {
+        return;
+    };
+
+    if copy_to_user(ptr, 0u32).await.is_err() {
+        warn!("Failed to clear child TID on exit");
+        return;
+    }
+
+    if let Ok(key) = FutexKey::new_shared(ptr) {
+        futex::wake_key(1, key, u32::MAX);
+    } else {
+        warn!("Failed to get futex wake key on exit");
+    }
 }
 
+/// Tears down the exiting process's address space, unless a task outside the
+/// group shares it (e.g. the parent of a `vfork()`ed child). In that case, the
+/// last task to drop its reference frees it.
+///
+/// The `killed` threads may still be running on other CPUs, so the tables are
+/// only freed once they've all been switched out.
+async fn release_vm(vm: &Arc<SpinLock<ProcVM>>, process: &ThreadGroup, killed: &[Arc<Task>]) {
+    if !private_to_group(vm, process, |task| &task.vm) {
+        return;
+    }
+
+    // Leave the process with an empty VM, so anything inspecting it before
+    // it's reaped (e.g. `/proc/<pid>/maps`) sees no mappings.
+    let Ok(empty) = ProcVM::empty() else {
+        warn!("Could not allocate an empty VM; deferring address space tear down");
+        return;
+    };
+
+    let mut old = core::mem::replace(&mut *vm.lock_save_irq(), empty);
+
+    for thread in killed {
+        wait_off_cpu(thread);
+    }
+
+    let shared = shared_ranges(&mut old, None);
+
+    old.destroy();
+
+    release(shared).await;
+}
+
+/// Terminates the current process with `signal`. The tear down is queued as
+/// the task's kernel work, abandoning any already in flight, so the caller
+/// must process kernel work next.
 pub fn kernel_exit_with_signal(signal: SigId, core: bool) {
-    do_exit_group(ChildState::SignalExit { signal, core });
+    let mut task = current_task();
+
+    drop(task.ctx.take_kernel_work());
+    task.ctx
+        .put_kernel_work(Box::pin(do_exit_group(ChildState::SignalExit {
+            signal,
+            core,
+        })));
 }
 
 pub async fn sys_exit_group(exit_code: usize) -> Result<usize> {
@@ -102,27 +237,15 @@ pub async fn sys_exit_group(exit_code: usize) -> Result<usize> {
 
     do_exit_group(ChildState::NormalExit {
         code: exit_code as _,
-    });
+    })
+    .await;
 
     Ok(0)
 }
 
 pub async fn sys_exit(exit_code: usize) -> Result<usize> {
-    // Honour CLONE_CHILD_CLEARTID: clear the user TID word and futex-wake any waiters.
-    let ptr = current_task().child_tid_ptr.take();
-
     ptrace_stop(TracePoint::Exit).await;
 
-    if let Some(ptr) = ptr {
-        copy_to_user(ptr, 0u32).await?;
-
-        if let Ok(key) = FutexKey::new_shared(ptr) {
-            futex::wake_key(1, key, u32::MAX);
-        } else {
-            warn!("Failed to get futex wake key on sys_exit");
-        }
-    }
-
     let task = current_task_shared();
     let process = Arc::clone(&task.process);
     let mut tasks_lock = process.tasks.lock_save_irq();
@@ -146,19 +269,92 @@ pub async fn sys_exit(exit_code: usize) -> Result<usize> {
         // spawned on this process while the thread_lock is released.
         do_exit_group(ChildState::NormalExit {
             code: exit_code as _,
-        });
-
-        Ok(0)
+        })
+        .await;
     } else {
-        // Mark our own state as finished.
+        // Remove ourself from the process's thread list. The remaining threads
+        // keep the address space and files alive; only this thread's TID word
+        // is ours to clear.
+        tasks_lock.remove(&task.tid);
+        drop(tasks_lock);
+
+        clear_child_tid().await;
+
+        // Mark our own state as finished. This thread stops executing
+        // forever; the task struct will be deallocated when the last
+        // Arc<Task> is dropped (e.g., by the scheduler).
         *task.state.lock_save_irq() = TaskState::Finished;
+    }
 
-        // Remove ourself from the process's thread list.
-        tasks_lock.remove(&task.tid);
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fs::{
+            fops::FileOps,
+            open_file::{FileCtx, OpenFile},
+        },
+        ktest,
+        process::thread_group::builder::ThreadGroupBuilder,
+    };
+    use async_trait::async_trait;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use libkernel::{fs::OpenFlags, memory::address::UA};
+
+    struct CountingFile(Arc<AtomicUsize>);
+
+    #[async_trait]
+    impl FileOps for CountingFile {
+        async fn readat(&mut self, _buf: UA, _count: usize, _offset: u64) -> Result<usize> {
+            Ok(0)
+        }
+
+        async fn writeat(&mut self, _buf: UA, count: usize, _offset: u64) -> Result<usize> {
+            Ok(count)
+        }
 
-        // 3. This thread stops executing forever. The task struct will be
-        // deallocated when the last Arc<Task> is dropped (e.g., by the
-        // scheduler).
-        Ok(0)
+        async fn release(&mut self, _ctx: &FileCtx) -> Result<()> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    ktest! {
+        async fn exit_releases_files_once() {
+            let releases = Arc::new(AtomicUsize::new(0));
+            let open = || {
+                Arc::new(OpenFile::new(
+                    Box::new(CountingFile(releases.clone())),
+                    OpenFlags::O_RDONLY,
+                ))
+            };
+
+            let mut table = FileDescriptorTable::new();
+            let file = open();
+            table.insert(file.clone()).unwrap();
+            table.insert(file).unwrap();
+            table.insert(open()).unwrap();
+
+            let process = ThreadGroupBuilder::new(ThreadGroup::next_tgid()).build();
+            let files = Arc::new(SpinLock::new(table));
+
+            // A table still shared outside the group is left alone.
+            let other = files.clone();
+            release_files(&files, &process).await;
+            assert_eq!(releases.load(Ordering::Relaxed), 0);
+            drop(other);
+
+            // Each file is released once, however many descriptors refer to it.
+            release_files(&files, &process).await;
+            assert_eq!(releases.load(Ordering::Relaxed), 2);
+            assert_eq!(files.lock_save_irq().len(), 0);
+
+            // Nothing is left for a second exit to release.
+            release_files(&files, &process).await;
+            assert_eq!(releases.load(Ordering::Relaxed), 2);
+        }
     }
 }
@@ -1,8 +1,13 @@
+use super::thread_group::rsrc_lim::NOFILE_DEFAULT;
 use crate::{fs::open_file::OpenFile, memory::uaccess::UserCopyable};
 use alloc::{sync::Arc, vec::Vec};
-use libkernel::error::{FsError, Result};
+use libkernel::{
+    error::{FsError, KernelError, Result},
+    fs::OpenFlags,
+};
 
 pub mod dup;
+pub mod epoll;
 pub mod fcntl;
 pub mod select;
 
@@ -39,6 +44,17 @@ bitflags::bitflags! {
     }
 }
 
+impl From<OpenFlags> for FdFlags {
+    /// The descriptor flags requested by `O_CLOEXEC` in an open's flags.
+    fn from(flags: OpenFlags) -> Self {
+        if flags.contains(OpenFlags::O_CLOEXEC) {
+            Self::CLOEXEC
+        } else {
+            Self::empty()
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct FileDescriptorEntry {
     file: Arc<OpenFile>,
@@ -49,9 +65,12 @@ pub struct FileDescriptorEntry {
 pub struct FileDescriptorTable {
     entries: Vec<Option<FileDescriptorEntry>>,
     next_fd_hint: usize,
+    /// New descriptors must be below this: the `RLIMIT_NOFILE` soft limit.
+    limit: usize,
 }
 
-const MAX_FDS: usize = 8192;
+/// The most descriptors a table can hold, whatever `RLIMIT_NOFILE` says.
+pub const MAX_FDS: usize = 8192;
 
 impl Default for FileDescriptorTable {
     fn default() -> Self {
@@ -59,11 +78,36 @@ impl Default for FileDescriptorTable {
     }
 }
 
+/// Drops a reference to `file`, releasing the file if it was the last one.
+async fn release(file: Arc<OpenFile>) {
+    if let Some(file) = Arc::into_inner(file) {
+        let (ops, ctx) = &mut *file.lock().await;
+        let _ = ops.release(ctx).await;
+    }
+}
+
 impl FileDescriptorTable {
     pub fn new() -> Self {
         Self {
             entries: Vec::new(),
             next_fd_hint: 0,
+            limit: NOFILE_DEFAULT.rlim_cur as usize,
+        }
+    }
+
+    /// Sets the `RLIMIT_NOFILE` soft limit. Descriptors already at or above it
+    /// stay open, but no new ones are handed out there.
+    pub fn set_limit(&mut self, limit: u64) {
+        self.limit = limit.min(MAX_FDS as u64) as usize;
+    }
+
+    /// Checks that `fd` could be opened: dup2/dup3 may only target
+    /// descriptors below the limit.
+    pub fn check_fd(&self, fd: Fd) -> Result<()> {
+        if (0..self.limit as i64).contains(&(fd.0 as i64)) {
+            Ok(())
+        } else {
+            Err(KernelError::BadFd)
         }
     }
 
@@ -77,12 +121,15 @@ impl FileDescriptorTable {
 
     /// Inserts a new file into the table, returning the new file descriptor.
     pub fn insert(&mut self, file: Arc<OpenFile>) -> Result<Fd> {
+        self.insert_with_flags(file, FdFlags::default())
+    }
+
+    /// Inserts a new file into the table with the given descriptor flags,
+    /// returning the new file descriptor.
+    pub fn insert_with_flags(&mut self, file: Arc<OpenFile>, flags: FdFlags) -> Result<Fd> {
         let fd = self.find_free_fd()?;
 
-        let entry = FileDescriptorEntry {
-            file,
-            flags: FdFlags::default(),
-        };
+        let entry = FileDescriptorEntry { file, flags };
 
         self.insert_at(fd, entry);
 
@@ -105,13 +152,17 @@ impl FileDescriptorTable {
     /// Insert the given entry at or above the specified index, returning the
     /// file descriptor used.
     fn insert_above(&mut self, min_fd: Fd, file: Arc<OpenFile>) -> Result<Fd> {
+        if min_fd.0 < 0 || min_fd.0 as usize >= self.limit {
+            return Err(KernelError::InvalidValue);
+        }
+
         let start_idx = min_fd.0 as usize;
         let entry = FileDescriptorEntry {
             file,
             flags: FdFlags::default(),
         };
 
-        for i in start_idx..self.entries.len() {
+        for i in start_idx..self.entries.len().min(self.limit) {
             if self.entries[i].is_none() {
                 let fd = Fd(i as i32);
                 self.insert_at(fd, entry);
@@ -120,8 +171,14 @@ impl FileDescriptorTable {
         }
 
         // No free slot found, so we need to expand the table.
-        let fd = Fd(self.entries.len() as i32);
-        self.entries.push(Some(entry));
+        let next = self.entries.len().max(start_idx);
+
+        if next >= self.limit {
+            return Err(FsError::TooManyFiles.into());
+        }
+
+        let fd = Fd(next as i32);
+        self.insert_at(fd, entry);
         Ok(fd)
     }
 
@@ -159,19 +216,28 @@ impl FileDescriptorTable {
             .collect::<Vec<_>>();
 
         for fd in fds_to_close {
-            if let Some(fd) = self.remove(Fd(fd as _))
-                && let Some(file) = Arc::into_inner(fd)
-            {
-                let (ops, ctx) = &mut *file.lock().await;
-                let _ = ops.release(ctx).await;
+            if let Some(file) = self.remove(Fd(fd as _)) {
+                release(file).await;
             }
         }
     }
 
+    /// Closes every file descriptor. Called when the last task using the table
+    /// exits.
+    pub async fn close_all(&mut self) {
+        let entries = core::mem::take(&mut self.entries);
+
+        self.next_fd_hint = 0;
+
+        for entry in entries.into_iter().flatten() {
+            release(entry.file).await;
+        }
+    }
+
     /// Finds the lowest-numbered available file descriptor.
     fn find_free_fd(&mut self) -> Result<Fd> {
         // Start searching from our hint.
-        for i in self.next_fd_hint..self.entries.len() {
+        for i in self.next_fd_hint..self.entries.len().min(self.limit) {
             if self.entries[i].is_none() {
                 self.next_fd_hint = i + 1;
                 return Ok(Fd(i as i32));
@@ -181,7 +247,7 @@ impl FileDescriptorTable {
         // We didn't find a free slot in the existing capacity
         let next = self.entries.len();
 
-        if next >= MAX_FDS {
+        if next >= self.limit {
             Err(FsError::TooManyFiles.into())
         } else {
             self.next_fd_hint = next + 1;
@@ -194,3 +260,105 @@ impl FileDescriptorTable {
         self.entries.iter().filter(|e| e.is_some()).count()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fs::{
+            fops::FileOps,
+            open_file::{FileCtx, OpenFile},
+        },
+        ktest,
+    };
+    use alloc::boxed::Box;
+    use async_trait::async_trait;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use libkernel::memory::address::UA;
+
+    struct CountingFile(Arc<AtomicUsize>);
+
+    #[async_trait]
+    impl FileOps for CountingFile {
+        async fn readat(&mut self, _buf: UA, _count: usize, _offset: u64) -> Result<usize> {
+            Ok(0)
+        }
+
+        async fn writeat(&mut self, _buf: UA, count: usize, _offset: u64) -> Result<usize> {
+            Ok(count)
+        }
+
+        async fn release(&mut self, _ctx: &FileCtx) -> Result<()> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    ktest! {
+        async fn exec_closes_only_cloexec_fds() {
+            let releases = Arc::new(AtomicUsize::new(0));
+            let open = || {
+                Arc::new(OpenFile::new(
+                    Box::new(CountingFile(releases.clone())),
+                    OpenFlags::O_RDONLY,
+                ))
+            };
+
+            let mut table = FileDescriptorTable::new();
+            let kept = table.insert(open()).unwrap();
+            let closed = table
+                .insert_with_flags(open(), OpenFlags::O_CLOEXEC.into())
+                .unwrap();
+            let also_kept = table.insert_with_flags(open(), OpenFlags::O_RDWR.into()).unwrap();
+
+            table.close_cloexec_entries().await;
+
+            assert_eq!(releases.load(Ordering::Relaxed), 1);
+            assert!(table.get(kept).is_some());
+            assert!(table.get(closed).is_none());
+            assert!(table.get(also_kept).is_some());
+
+            // The closed descriptor is the next to be reused.
+            assert_eq!(table.insert(open()).unwrap(), closed);
+        }
+    }
+    ktest! {
+        fn nofile_limit_bounds_new_fds() {
+            let open = || {
+                Arc::new(OpenFile::new(
+                    Box::new(CountingFile(Arc::new(AtomicUsize::new(0)))),
+                    OpenFlags::O_RDONLY,
+                ))
+            };
+
+            let mut table = FileDescriptorTable::new();
+            table.set_limit(3);
+
+            for fd in 0..3 {
+                assert_eq!(table.insert(open()).unwrap(), Fd(fd));
+            }
+            assert!(matches!(
+                table.insert(open()),
+                Err(KernelError::Fs(FsError::TooManyFiles))
+            ));
+            assert!(matches!(
+                table.insert_above(Fd(3), open()),
+                Err(KernelError::InvalidValue)
+            ));
+            assert_eq!(table.check_fd(Fd(3)), Err(KernelError::BadFd));
+
+            // Lowering the limit leaves open descriptors alone, but freed ones
+            // below it are still handed out.
+            table.set_limit(2);
+            assert!(table.get(Fd(2)).is_some());
+            table.remove(Fd(1));
+            assert_eq!(table.insert(open()).unwrap(), Fd(1));
+            assert!(table.insert(open()).is_err());
+
+            // F_DUPFD leaves a gap if asked to.
+            table.set_limit(8);
+            assert_eq!(table.insert_above(Fd(5), open()).unwrap(), Fd(5));
+            assert_eq!(table.insert(open()).unwrap(), Fd(3));
+        }
+    }
+}
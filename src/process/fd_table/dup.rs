@@ -4,7 +4,7 @@ use libkernel::{
     fs::OpenFlags,
 };
 
-use super::{Fd, FdFlags, FileDescriptorEntry};
+use super::{Fd, FileDescriptorEntry};
 
 pub fn dup_fd(fd: Fd, min_fd: Option<Fd>) -> Result<Fd> {
     let task = current_task();
@@ -43,15 +43,13 @@ pub fn sys_dup3(oldfd: Fd, newfd: Fd, flags: u32) -> Result<usize> {
 
     let old_file = files.get(oldfd).ok_or(KernelError::BadFd)?;
 
+    files.check_fd(newfd)?;
+
     files.insert_at(
         newfd,
         FileDescriptorEntry {
             file: old_file.clone(),
-            flags: if flags.contains(OpenFlags::O_CLOEXEC) {
-                FdFlags::CLOEXEC
-            } else {
-                FdFlags::empty()
-            },
+            flags: flags.into(),
         },
     );
 
@@ -0,0 +1,395 @@
+//! Persistent readiness notification through `epoll` instances.
+//!
+//! An epoll instance is itself an open file, holding an interest list of file
+//! descriptors and the events wanted from each. Only level-triggered
+//! notification is supported: a file is reported by every wait for as long as
+//! it remains ready.
+
+use alloc::{
+    boxed::Box,
+    collections::btree_map::{BTreeMap, Entry},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use async_trait::async_trait;
+use core::{future::poll_fn, task::Poll, time::Duration};
+use libkernel::{
+    error::{FsError, KernelError, Result},
+    fs::OpenFlags,
+    memory::address::{TUA, UA},
+};
+
+use crate::{
+    drivers::timer::sleep,
+    fs::{fops::FileOps, open_file::OpenFile},
+    memory::uaccess::{UserCopyable, copy_from_user, copy_objs_to_user},
+    process::thread_group::signal::{InterruptResult, Interruptable, SigSet},
+    sched::current::current_task_shared,
+    sync::SpinLock,
+};
+
+use super::{Fd, select::PollFlags};
+
+const EPOLL_CTL_ADD: i32 = 1;
+const EPOLL_CTL_DEL: i32 = 2;
+const EPOLL_CTL_MOD: i32 = 3;
+
+bitflags::bitflags! {
+    #[derive(Clone, Copy, Debug)]
+    pub struct EpollEvents: u32 {
+        const EPOLLIN        = 0x001;
+        const EPOLLPRI       = 0x002;
+        const EPOLLOUT       = 0x004;
+        const EPOLLERR       = 0x008;
+        const EPOLLHUP       = 0x010;
+        const EPOLLRDNORM    = 0x040;
+        const EPOLLRDBAND    = 0x080;
+        const EPOLLWRNORM    = 0x100;
+        const EPOLLWRBAND    = 0x200;
+        const EPOLLMSG       = 0x400;
+        const EPOLLRDHUP     = 0x2000;
+        const EPOLLEXCLUSIVE = 1 << 28;
+        const EPOLLWAKEUP    = 1 << 29;
+        const EPOLLONESHOT   = 1 << 30;
+        const EPOLLET        = 1 << 31;
+    }
+}
+
+impl EpollEvents {
+    /// Events that change how notifications are delivered, none of which are
+    /// implemented yet.
+    const UNSUPPORTED: Self = Self::EPOLLEXCLUSIVE
+        .union(Self::EPOLLWAKEUP)
+        .union(Self::EPOLLONESHOT)
+        .union(Self::EPOLLET);
+
+    fn poll_flags(self) -> PollFlags {
+        let mut flags = PollFlags::empty();
+
+        if self.contains(Self::EPOLLIN) {
+            flags |= PollFlags::POLLIN;
+        }
+
+        if self.contains(Self::EPOLLOUT) {
+            flags |= PollFlags::POLLOUT;
+        }
+
+        flags
+    }
+
+    fn from_poll_flags(flags: PollFlags) -> Self {
+        let mut events = Self::empty();
+
+        if flags.contains(PollFlags::POLLIN) {
+            events |= Self::EPOLLIN;
+        }
+
+        if flags.contains(PollFlags::POLLOUT) {
+            events |= Self::EPOLLOUT;
+        }
+
+        events
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct EpollEvent {
+    events: EpollEvents,
+    data: u64,
+}
+
+unsafe impl UserCopyable for EpollEvent {}
+
+struct Interest {
+    // Held weakly, so that closing the file drops it from the interest list
+    // rather than keeping it open.
+    file: Weak<OpenFile>,
+    events: EpollEvents,
+    data: u64,
+}
+
+pub struct Epoll {
+    interests: SpinLock<BTreeMap<Fd, Interest>>,
+}
+
+impl Epoll {
+    fn new() -> Self {
+        Self {
+            interests: SpinLock::new(BTreeMap::new()),
+        }
+    }
+
+    fn ctl(&self, op: i32, fd: Fd, file: &Arc<OpenFile>, event: Option<EpollEvent>) -> Result<()> {
+        let mut interests = self.interests.lock_save_irq();
+
+        // An entry left behind by a closed file doesn't count as interest in
+        // whatever now occupies its descriptor.
+        interests.retain(|_, interest| interest.file.strong_count() > 0);
+
+        let interest = event.map(|event| Interest {
+            file: Arc::downgrade(file),
+            events: event.events,
+            data: event.data,
+        });
+
+        match (op, interests.entry(fd), interest) {
+            (EPOLL_CTL_ADD, Entry::Vacant(entry), Some(interest)) => {
+                entry.insert(interest);
+            }
+            (EPOLL_CTL_ADD, Entry::Occupied(_), _) => return Err(FsError::AlreadyExists.into()),
+            (EPOLL_CTL_MOD, Entry::Occupied(mut entry), Some(interest)) => {
+                entry.insert(interest);
+            }
+            (EPOLL_CTL_MOD | EPOLL_CTL_DEL, Entry::Vacant(_), _) => {
+                return Err(FsError::NotFound.into());
+            }
+            (EPOLL_CTL_DEL, Entry::Occupied(entry), _) => {
+                entry.remove();
+            }
+            _ => return Err(KernelError::InvalidValue),
+        }
+
+        Ok(())
+    }
+
+    /// Waits for at least one file in the interest list to become ready,
+    /// returning up to `max` events. Gives up with no events after `timeout`,
+    /// if one is given.
+    async fn wait(&self, max: usize, timeout: Option<Duration>) -> Result<Vec<EpollEvent>> {
+        let interests = {
+            let interests = self.interests.lock_save_irq();
+
+            interests
+                .values()
+                .filter_map(|interest| {
+                    Some((interest.file.upgrade()?, interest.events, interest.data))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut futs = Vec::new();
+
+        for (file, events, data) in interests {
+            futs.push((Box::pin(file.poll(events.poll_flags()).await), data));
+        }
+
+        let mut timeout_fut = timeout.map(|timeout| Box::pin(sleep(timeout)));
+
+        let wait_fut = poll_fn(|cx| {
+            let mut ready = Vec::new();
+
+            for (fut, data) in futs.iter_mut() {
+                if ready.len() == max {
+                    break;
+                }
+
+                match fut.as_mut().poll(cx) {
+                    Poll::Ready(Ok(flags)) => ready.push(EpollEvent {
+                        events: EpollEvents::from_poll_flags(flags),
+                        data: *data,
+                    }),
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    Poll::Pending => continue,
+                }
+            }
+
+            if !ready.is_empty() {
+                return Poll::Ready(Ok(ready));
+            }
+
+            match timeout_fut {
+                Some(ref mut timeout) => timeout.as_mut().poll(cx).map(|_| Ok(ready)),
+                None => Poll::Pending,
+            }
+        });
+
+        match wait_fut.interruptable().await {
+            InterruptResult::Interrupted => Err(KernelError::Interrupted),
+            InterruptResult::Uninterrupted(r) => r,
+        }
+    }
+}
+
+/// The file operations of an epoll instance, which can't be read or written.
+struct EpollFile(Arc<Epoll>);
+
+#[async_trait]
+impl FileOps for EpollFile {
+    async fn readat(&mut self, _buf: UA, _count: usize, _offset: u64) -> Result<usize> {
+        Err(KernelError::InvalidValue)
+    }
+
+    async fn writeat(&mut self, _buf: UA, _count: usize, _offset: u64) -> Result<usize> {
+        Err(KernelError::InvalidValue)
+    }
+
+    fn as_epoll(&self) -> Option<Arc<Epoll>> {
+        Some(self.0.clone())
+    }
+}
+
+/// Looks up the epoll instance open at `epfd`.
+async fn get_epoll(epfd: Fd) -> Result<Arc<Epoll>> {
+    let file = current_task_shared()
+        .fd_table
+        .lock_save_irq()
+        .get(epfd)
+        .ok_or(KernelError::BadFd)?;
+
+    let epoll = file.lock().await.0.as_epoll();
+
+    epoll.ok_or(KernelError::InvalidValue)
+}
+
+pub fn sys_epoll_create1(flags: u32) -> Result<usize> {
+    let flags = OpenFlags::from_bits_retain(flags);
+
+    if !flags.difference(OpenFlags::O_CLOEXEC).is_empty() {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let file = OpenFile::new(
+        Box::new(EpollFile(Arc::new(Epoll::new()))),
+        OpenFlags::O_RDWR,
+    );
+
+    let task = current_task_shared();
+    let fd = task
+        .fd_table
+        .lock_save_irq()
+        .insert_with_flags(Arc::new(file), flags.into())?;
+
+    Ok(fd.as_raw() as _)
+}
+
+pub async fn sys_epoll_ctl(epfd: Fd, op: i32, fd: Fd, event: TUA<EpollEvent>) -> Result<usize> {
+    let epoll = get_epoll(epfd).await?;
+
+    let file = current_task_shared()
+        .fd_table
+        .lock_save_irq()
+        .get(fd)
+        .ok_or(KernelError::BadFd)?;
+
+    if fd == epfd {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let event = if op == EPOLL_CTL_DEL {
+        None
+    } else {
+        let event = copy_from_user(event).await?;
+
+        if event.events.intersects(EpollEvents::UNSUPPORTED) {
+            return Err(KernelError::NotSupported);
+        }
+
+        Some(event)
+    };
+
+    epoll.ctl(op, fd, &file, event)?;
+
+    Ok(0)
+}
+
+pub async fn sys_epoll_pwait(
+    epfd: Fd,
+    events: TUA<EpollEvent>,
+    max_events: i32,
+    timeout_ms: i32,
+    _sigmask: TUA<SigSet>,
+    _sigset_len: usize,
+) -> Result<usize> {
+    if max_events <= 0 {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let epoll = get_epoll(epfd).await?;
+
+    // A negative timeout waits indefinitely.
+    let timeout = u64::try_from(timeout_ms).ok().map(Duration::from_millis);
+
+    let ready = epoll.wait(max_events as _, timeout).await?;
+
+    copy_objs_to_user(&ready, events).await?;
+
+    Ok(ready.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fs::{
+            pipe::sys_pipe2,
+            syscalls::{close::sys_close, rw::sys_write},
+        },
+        ktest,
+        memory::uaccess::{copy_from_user, copy_to_user},
+        testing::ScratchBuf,
+    };
+    use futures::future::join;
+    use libkernel::memory::PAGE_SIZE;
+
+    ktest! {
+        async fn epoll_reports_readable_pipe() {
+            let scratch = ScratchBuf::new(PAGE_SIZE).await;
+
+            let fds_ptr = scratch.addr().cast::<[Fd; 2]>();
+            let event_ptr = scratch.addr().add_bytes(0x100).cast::<EpollEvent>();
+            let buf = scratch.addr().add_bytes(0x200);
+
+            sys_pipe2(fds_ptr, 0).await.unwrap();
+            let [rfd, wfd] = copy_from_user(fds_ptr).await.unwrap();
+            let epfd = Fd(sys_epoll_create1(0).unwrap() as _);
+
+            let wait = |timeout_ms| sys_epoll_pwait(epfd, event_ptr, 1, timeout_ms, TUA::null(), 0);
+
+            copy_to_user(
+                event_ptr,
+                EpollEvent {
+                    events: EpollEvents::EPOLLIN,
+                    data: 42,
+                },
+            )
+            .await
+            .unwrap();
+            sys_epoll_ctl(epfd, EPOLL_CTL_ADD, rfd, event_ptr).await.unwrap();
+            assert!(matches!(
+                sys_epoll_ctl(epfd, EPOLL_CTL_ADD, rfd, event_ptr).await,
+                Err(KernelError::Fs(FsError::AlreadyExists))
+            ));
+
+            // Nothing has been written yet.
+            assert_eq!(wait(0).await.unwrap(), 0);
+
+            // A blocked wait returns once a writer makes the pipe readable.
+            let (n, written) = join(wait(-1), async {
+                sleep(Duration::from_millis(10)).await;
+                sys_write(wfd, buf, 1).await
+            })
+            .await;
+            assert_eq!(written.unwrap(), 1);
+            assert_eq!(n.unwrap(), 1);
+
+            let event = copy_from_user(event_ptr).await.unwrap();
+            assert!(event.events.contains(EpollEvents::EPOLLIN));
+            assert_eq!(event.data, 42);
+
+            // Level-triggered: the unread data is reported again.
+            assert_eq!(wait(0).await.unwrap(), 1);
+
+            sys_epoll_ctl(epfd, EPOLL_CTL_DEL, rfd, TUA::null()).await.unwrap();
+            assert_eq!(wait(0).await.unwrap(), 0);
+            assert!(matches!(
+                sys_epoll_ctl(epfd, EPOLL_CTL_DEL, rfd, TUA::null()).await,
+                Err(KernelError::Fs(FsError::NotFound))
+            ));
+
+            for fd in [epfd, rfd, wfd] {
+                sys_close(fd).await.unwrap();
+            }
+        }
+    }
+}
@@ -0,0 +1,220 @@
+//! Kernel threads.
+//!
+//! A kernel thread is a task that never enters userspace. Its body is an
+//! `async` block installed as the task's kernel work, which the scheduler polls
+//! like any other in-kernel future. Since futures keep their state on the heap
+//! and are always polled on the per-CPU kernel stack (allocated, with guard
+//! pages, by `allocate_kstack_region`), a kernel thread doesn't need a stack of
+//! its own.
+
+use super::{
+    Comm, TASK_LIST, Task, TaskDescriptor, TaskState, Tid,
+    creds::Credentials,
+    ctx::Context,
+    fd_table::FileDescriptorTable,
+    owned::OwnedTask,
+    ptrace::PTrace,
+    thread_group::{
+        ThreadGroup,
+        builder::ThreadGroupBuilder,
+        signal::{SigSet, SignalActionState},
+    },
+};
+use crate::{
+    arch::{Arch, ArchImpl},
+    fs::DummyInode,
+    kernel::cpu_id::CpuId,
+    sched,
+    sync::{CondVar, SpinLock},
+};
+use alloc::{boxed::Box, sync::Arc};
+use core::sync::atomic::{AtomicBool, AtomicUsize};
+use libkernel::{
+    error::Result,
+    fs::pathbuf::PathBuf,
+    memory::{address::VA, proc_vm::ProcessVM},
+    sync::condvar::WakeupType,
+};
+
+/// A handle to a spawned kernel thread, used to wait for it to finish.
+#[derive(Clone)]
+pub struct KThreadHandle {
+    desc: TaskDescriptor,
+    comm: Arc<SpinLock<Comm>>,
+    finished: CondVar<bool>,
+}
+
+impl KThreadHandle {
+    pub fn descriptor(&self) -> TaskDescriptor {
+        self.desc
+    }
+
+    pub fn name(&self) -> Comm {
+        *self.comm.lock_save_irq()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        let mut finished = false;
+
+        self.finished.update(|state| {
+            finished = *state;
+            WakeupType::None
+        });
+
+        finished
+    }
+
+    /// Waits for the thread's body to run to completion.
+    pub async fn join(&self) {
+        self.finished
+            .wait_until(|finished| finished.then_some(()))
+            .await
+    }
+}
+
+/// Builds a kernel thread task running `body`, without placing it on a
+/// runqueue.
+fn create_kthread(
+    name: &str,
+    body: impl Future<Output = ()> + 'static + Send,
+) -> Result<(Box<OwnedTask>, KThreadHandle)> {
+    let process = ThreadGroupBuilder::new(ThreadGroup::next_tgid())
+        .with_sigstate(Arc::new(SpinLock::new(SignalActionState::new_ignore())))
+        .build();
+    let tid = Tid::from_tgid(process.tgid);
+
+    let task = Task {
+        tid,
+        comm: Arc::new(SpinLock::new(Comm::new(name))),
+        process,
+        state: Arc::new(SpinLock::new(TaskState::Runnable)),
+        cwd: Arc::new(SpinLock::new((Arc::new(DummyInode {}), PathBuf::new()))),
+        root: Arc::new(SpinLock::new((Arc::new(DummyInode {}), PathBuf::new()))),
+        creds: SpinLock::new(Credentials::new_root()),
+        vm: Arc::new(SpinLock::new(ProcessVM::empty()?)),
+        fd_table: Arc::new(SpinLock::new(FileDescriptorTable::new())),
+        last_cpu: SpinLock::new(CpuId::this()),
+        on_cpu: AtomicBool::new(false),
+        ptrace: SpinLock::new(PTrace::new()),
+        utime: AtomicUsize::new(0),
+        stime: AtomicUsize::new(0),
+        last_account: AtomicUsize::new(0),
+    };
+
+    let handle = KThreadHandle {
+        desc: task.descriptor(),
+        comm: task.comm.clone(),
+        finished: CondVar::new(false),
+    };
+
+    let state = task.state.clone();
+    let finished = handle.finished.clone();
+    let desc = handle.desc;
+
+    let mut owned = Box::new(OwnedTask {
+        ctx: Context::from_user_ctx(<ArchImpl as Arch>::new_user_context(VA::null(), VA::null())),
+        sig_mask: SigSet::empty(),
+        pending_signals: SigSet::empty(),
+        priority: None,
+        robust_list: None,
+        child_tid_ptr: None,
+        fault_info: None,
+        t_shared: Arc::new(task),
+        in_syscall: false,
+    });
+
+    owned.ctx.put_kernel_work(Box::pin(async move {
+        body.await;
+
+        TASK_LIST.lock_save_irq().remove(&desc);
+
+        // There is no userspace to return to; once the body has finished the
+        // scheduler must drop the task.
+        *state.lock_save_irq() = TaskState::Finished;
+
+        finished.update(|finished| {
+            *finished = true;
+            WakeupType::All
+        });
+    }));
+
+    TASK_LIST
+        .lock_save_irq()
+        .insert(owned.descriptor(), Arc::downgrade(&owned.t_shared));
+
+    owned
+        .process
+        .tasks
+        .lock_save_irq()
+        .insert(tid, Arc::downgrade(&owned.t_shared));
+
+    Ok((owned, handle))
+}
+
+/// Spawns a kernel thread called `name` which runs `body` to completion and
+/// then exits. The thread is scheduled like any other task.
+pub fn spawn_kthread(
+    name: &str,
+    body: impl Future<Output = ()> + 'static + Send,
+) -> Result<KThreadHandle> {
+    let (task, handle) = create_kthread(name, body)?;
+
+    sched::insert_task_cross_cpu(task);
+
+    Ok(handle)
+}
+
+/// Like [`spawn_kthread`], but the thread is scheduled at `priority` rather
+/// than the default.
+pub fn spawn_kthread_with_priority(
+    name: &str,
+    priority: i8,
+    body: impl Future<Output = ()> + 'static + Send,
+) -> Result<KThreadHandle> {
+    let (mut task, handle) = create_kthread(name, body)?;
+
+    task.set_priority(priority);
+
+    sched::insert_task_cross_cpu(task);
+
+    Ok(handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ktest, process::find_task_by_descriptor, sched::waker::create_waker};
+    use core::{sync::atomic::Ordering, task::Poll};
+
+    ktest! {
+        async fn kthread_runs_and_finishes() {
+            let counter = Arc::new(AtomicUsize::new(0));
+            let c = counter.clone();
+
+            let (mut task, handle) = create_kthread("ktest", async move {
+                c.fetch_add(1, Ordering::Relaxed);
+            })
+            .unwrap();
+
+            assert_eq!(handle.name().as_str(), "ktest");
+            assert!(!handle.is_finished());
+            assert!(find_task_by_descriptor(&handle.descriptor()).is_some());
+
+            // Stand in for the scheduler, which would poll the kernel work once
+            // the task is picked.
+            let mut work = task.ctx.take_kernel_work().unwrap();
+            let waker = create_waker(handle.descriptor());
+            assert!(matches!(
+                work.as_mut().poll(&mut core::task::Context::from_waker(&waker)),
+                Poll::Ready(())
+            ));
+
+            assert_eq!(counter.load(Ordering::Relaxed), 1);
+            assert!(task.state.lock_save_irq().is_finished());
+            assert!(handle.is_finished());
+            assert!(find_task_by_descriptor(&handle.descriptor()).is_none());
+
+            handle.join().await;
+        }
+    }
+}
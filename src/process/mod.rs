@@ -15,7 +15,7 @@ use alloc::{
     sync::{Arc, Weak},
 };
 use core::fmt::Display;
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use creds::Credentials;
 use fd_table::FileDescriptorTable;
 use libkernel::{
@@ -38,6 +38,7 @@ pub mod ctx;
 pub mod exec;
 pub mod exit;
 pub mod fd_table;
+pub mod kthread;
 pub mod owned;
 pub mod prctl;
 pub mod ptrace;
@@ -186,6 +187,9 @@ pub struct Task {
     pub fd_table: Arc<SpinLock<FileDescriptorTable>>,
     pub state: Arc<SpinLock<TaskState>>,
     pub last_cpu: SpinLock<CpuId>,
+    /// Whether a CPU is executing this task, i.e. may have its address space
+    /// active.
+    pub on_cpu: AtomicBool,
     pub ptrace: SpinLock<PTrace>,
     pub utime: AtomicUsize,
     pub stime: AtomicUsize,
@@ -9,7 +9,7 @@ use super::{
     thread_group::{
         Tgid,
         builder::ThreadGroupBuilder,
-        signal::{SigId, SigSet, SignalActionState},
+        signal::{FaultInfo, SigId, SigSet, SignalActionState},
     },
     threading::RobustListHead,
 };
@@ -21,7 +21,7 @@ use crate::{
     sync::SpinLock,
 };
 use alloc::sync::Arc;
-use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::{AtomicBool, AtomicUsize};
 use libkernel::{
     VirtualMemory,
     fs::pathbuf::PathBuf,
@@ -40,6 +40,8 @@ pub struct OwnedTask {
     pub priority: Option<i8>,
     pub robust_list: Option<TUA<RobustListHead>>,
     pub child_tid_ptr: Option<TUA<u32>>,
+    /// Details of the pending signal raised by a fault, if any.
+    pub fault_info: Option<FaultInfo>,
     pub t_shared: Arc<Task>,
     pub in_syscall: bool,
 }
@@ -79,6 +81,7 @@ impl OwnedTask {
             vm: Arc::new(SpinLock::new(vm)),
             fd_table: Arc::new(SpinLock::new(FileDescriptorTable::new())),
             last_cpu: SpinLock::new(CpuId::this()),
+            on_cpu: AtomicBool::new(false),
             ptrace: SpinLock::new(PTrace::new()),
             utime: AtomicUsize::new(0),
             stime: AtomicUsize::new(0),
@@ -92,6 +95,7 @@ impl OwnedTask {
             pending_signals: SigSet::empty(),
             robust_list: None,
             child_tid_ptr: None,
+            fault_info: None,
             t_shared: Arc::new(task),
             in_syscall: false,
         }
@@ -111,6 +115,7 @@ impl OwnedTask {
             )),
             fd_table: Arc::new(SpinLock::new(FileDescriptorTable::new())),
             last_cpu: SpinLock::new(CpuId::this()),
+            on_cpu: AtomicBool::new(false),
             ptrace: SpinLock::new(PTrace::new()),
             last_account: AtomicUsize::new(0),
             utime: AtomicUsize::new(0),
@@ -127,6 +132,7 @@ impl OwnedTask {
             )),
             robust_list: None,
             child_tid_ptr: None,
+            fault_info: None,
             t_shared: Arc::new(task),
             in_syscall: false,
         }
@@ -145,6 +151,16 @@ impl OwnedTask {
         self.pending_signals.insert(signal.into());
     }
 
+    /// Raises the signal for a fault caused by this task. Since the faulting
+    /// instruction would only fault again, the signal is unblocked and, if it
+    /// was being ignored, reset to its default action.
+    pub fn raise_fault_signal(&mut self, info: FaultInfo) {
+        self.sig_mask.remove(info.signal.into());
+        self.process.signals.lock_save_irq().unignore(info.signal);
+        self.fault_info = Some(info);
+        self.raise_task_signal(info.signal);
+    }
+
     /// Take a pending signal from this task's pending signal queue, or the
     /// process's pending signal queue, while repsecting the signal mask.
     pub fn take_signal(&mut self) -> Option<SigId> {
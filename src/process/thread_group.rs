@@ -85,6 +85,7 @@ impl Sid {
 pub enum ProcessState {
     Running, // Actively running
     Exiting, // In the middle of being torn down
+    Zombie,  // Exited, waiting to be reaped by its parent
 }
 
 pub struct ThreadGroup {
@@ -12,15 +12,19 @@ pub fn sys_getpid() -> core::result::Result<usize, Infallible> {
     Ok(current_task().process.tgid.value() as _)
 }
 
-pub fn sys_getppid() -> core::result::Result<usize, Infallible> {
-    Ok(current_task()
-        .process
+/// The process ID of `process`'s parent, or 0 if it has none.
+pub fn ppid(process: &ThreadGroup) -> u32 {
+    process
         .parent
         .lock_save_irq()
         .as_ref()
         .and_then(|x| x.upgrade())
         .map(|x| x.tgid.value())
-        .unwrap_or(0) as _)
+        .unwrap_or(0)
+}
+
+pub fn sys_getppid() -> core::result::Result<usize, Infallible> {
+    Ok(ppid(&current_task().process) as _)
 }
 
 pub fn sys_getpgid(pid: PidT) -> Result<usize> {
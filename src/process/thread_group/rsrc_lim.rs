@@ -1,18 +1,22 @@
 use libkernel::{
     error::{KernelError, Result},
     memory::address::TUA,
+    proc::caps::CapabilitiesFlags,
 };
 
 use crate::{
     memory::uaccess::{UserCopyable, copy_from_user, copy_to_user},
-    process::thread_group::{TG_LIST, Tgid},
-    sched::current::current_task,
+    process::{
+        fd_table::MAX_FDS,
+        thread_group::{TG_LIST, Tgid, ThreadGroup},
+    },
+    sched::current::{current_task, current_task_shared},
 };
 
 use super::pid::PidT;
 
 #[repr(u32)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[allow(clippy::upper_case_acronyms)]
 pub enum RlimitId {
     CPU = 0,
@@ -57,7 +61,7 @@ impl TryFrom<u32> for RlimitId {
 pub const RLIM_INFINITY: u64 = u64::MAX;
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct RLimit {
     pub rlim_cur: u64, // The current (soft) limit
     pub rlim_max: u64, // The hard limit
@@ -65,6 +69,12 @@ pub struct RLimit {
 
 unsafe impl UserCopyable for RLimit {}
 
+/// The default `RLIMIT_NOFILE`, which a new file descriptor table starts with.
+pub const NOFILE_DEFAULT: RLimit = RLimit {
+    rlim_cur: 1024,
+    rlim_max: 4096,
+};
+
 impl Default for RLimit {
     /// A sensible default for a limit: 0 for both soft and hard.
     fn default() -> Self {
@@ -115,10 +125,7 @@ impl Default for ResourceLimits {
             rlim_cur: 4096,
             rlim_max: 8192,
         };
-        limits[RlimitId::NOFILE.as_usize()] = RLimit {
-            rlim_cur: 1024,
-            rlim_max: 4096,
-        };
+        limits[RlimitId::NOFILE.as_usize()] = NOFILE_DEFAULT;
         limits[RlimitId::MEMLOCK.as_usize()] = RLimit {
             rlim_cur: 65536,
             rlim_max: 65536,
@@ -176,6 +183,11 @@ impl ResourceLimits {
             return Err(KernelError::NotPermitted);
         }
 
+        // Nobody can have more file descriptors than a table can hold.
+        if id == RlimitId::NOFILE && new_limit.rlim_max > MAX_FDS as u64 {
+            return Err(KernelError::NotPermitted);
+        }
+
         // The new values are valid. Commit them.
         self.limits[id.as_usize()] = new_limit;
 
@@ -183,6 +195,60 @@ impl ResourceLimits {
     }
 }
 
+/// Returns `process`'s limit for `resource`, after replacing it with
+/// `new_limit` if one is given.
+fn update_limit(
+    process: &ThreadGroup,
+    resource: RlimitId,
+    new_limit: Option<RLimit>,
+) -> Result<RLimit> {
+    let Some(new_limit) = new_limit else {
+        return Ok(process.rsrc_lim.lock_save_irq().get(resource));
+    };
+
+    // Raising a hard limit needs CAP_SYS_RESOURCE.
+    let is_privileged = current_task_shared()
+        .creds
+        .lock_save_irq()
+        .caps()
+        .check_capable(CapabilitiesFlags::CAP_SYS_RESOURCE)
+        .is_ok();
+
+    let old_limit = process
+        .rsrc_lim
+        .lock_save_irq()
+        .set(resource, new_limit, is_privileged)?;
+
+    // The file descriptor table enforces its own limit.
+    if resource == RlimitId::NOFILE {
+        for task in process.tasks.lock_save_irq().values() {
+            if let Some(task) = task.upgrade() {
+                task.fd_table.lock_save_irq().set_limit(new_limit.rlim_cur);
+            }
+        }
+    }
+
+    Ok(old_limit)
+}
+
+pub async fn sys_getrlimit(resource: u32, rlim: TUA<RLimit>) -> Result<usize> {
+    let resource: RlimitId = resource.try_into()?;
+    let limit = update_limit(&current_task_shared().process, resource, None)?;
+
+    copy_to_user(rlim, limit).await?;
+
+    Ok(0)
+}
+
+pub async fn sys_setrlimit(resource: u32, rlim: TUA<RLimit>) -> Result<usize> {
+    let resource: RlimitId = resource.try_into()?;
+    let new_limit = copy_from_user(rlim).await?;
+
+    update_limit(&current_task_shared().process, resource, Some(new_limit))?;
+
+    Ok(0)
+}
+
 pub async fn sys_prlimit64(
     pid: PidT,
     resource: u32,
@@ -207,13 +273,7 @@ pub async fn sys_prlimit64(
         None
     };
 
-    let old_lim = if let Some(new_limit) = new_limit {
-        task.rsrc_lim
-            .lock_save_irq()
-            .set(resource, new_limit, true)?
-    } else {
-        task.rsrc_lim.lock_save_irq().get(resource)
-    };
+    let old_lim = update_limit(&task, resource, new_limit)?;
 
     if !old_rlim.is_null() {
         copy_to_user(old_rlim, old_lim).await?;
@@ -221,3 +281,45 @@ pub async fn sys_prlimit64(
 
     Ok(0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ktest;
+
+    ktest! {
+        fn soft_limit_lowers_and_raises() {
+            let mut limits = ResourceLimits::default();
+            let limit = |cur, max| RLimit {
+                rlim_cur: cur,
+                rlim_max: max,
+            };
+
+            // The soft limit can go down and back up to the hard limit freely.
+            assert_eq!(
+                limits.set(RlimitId::NOFILE, limit(16, 4096), false),
+                Ok(NOFILE_DEFAULT)
+            );
+            assert!(limits.set(RlimitId::NOFILE, limit(4096, 4096), false).is_ok());
+            assert_eq!(
+                limits.set(RlimitId::NOFILE, limit(4097, 4096), false),
+                Err(KernelError::InvalidValue)
+            );
+
+            // Lowering the hard limit is permanent without privilege.
+            assert!(limits.set(RlimitId::NOFILE, limit(16, 64), false).is_ok());
+            assert_eq!(
+                limits.set(RlimitId::NOFILE, limit(16, 128), false),
+                Err(KernelError::NotPermitted)
+            );
+            assert!(limits.set(RlimitId::NOFILE, limit(16, 128), true).is_ok());
+            assert_eq!(limits.get(RlimitId::NOFILE), limit(16, 128));
+
+            // Not even privilege gets past what the fd table can hold.
+            assert_eq!(
+                limits.set(RlimitId::NOFILE, limit(16, RLIM_INFINITY), true),
+                Err(KernelError::NotPermitted)
+            );
+        }
+    }
+}
@@ -8,7 +8,10 @@ use core::{
     task::Poll,
 };
 use ksigaction::{KSignalAction, UserspaceSigAction};
-use libkernel::memory::{address::UA, region::UserMemoryRegion};
+use libkernel::memory::{
+    address::{UA, VA},
+    region::UserMemoryRegion,
+};
 
 pub mod kill;
 pub mod ksigaction;
@@ -163,6 +166,19 @@ impl Display for SigId {
 // SIGKILL and SIGSTOP
 const UNMASKABLE_SIGNALS: SigSet = SigSet::SIGKILL.union(SigSet::SIGSTOP);
 
+// si_code values for SIGSEGV
+pub const SEGV_MAPERR: i32 = 1;
+pub const SEGV_ACCERR: i32 = 2;
+
+/// A signal raised by a fault, along with the details reported to its handler
+/// in `siginfo_t`.
+#[derive(Clone, Copy, Debug)]
+pub struct FaultInfo {
+    pub signal: SigId,
+    pub code: i32,
+    pub addr: VA,
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum SigActionState {
     Ignore,
@@ -245,6 +261,13 @@ impl SignalActionState {
         }
     }
 
+    /// Resets `id` to its default action if it's being ignored.
+    pub fn unignore(&mut self, id: SigId) {
+        if matches!(self.action[id], SigActionState::Ignore) {
+            self.action[id] = SigActionState::Default;
+        }
+    }
+
     pub fn action_signal(&self, id: SigId) -> Option<KSignalAction> {
         match self.action[id] {
             SigActionState::Ignore => None, // look for another signal,
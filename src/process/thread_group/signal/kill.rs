@@ -8,59 +8,65 @@ use crate::{
 
 use super::{SigId, uaccess::UserSigId};
 use crate::process::thread_group::TG_LIST;
+use alloc::{sync::Weak, vec::Vec};
 use libkernel::error::{KernelError, Result};
 
 pub fn sys_kill(pid: PidT, signal: UserSigId) -> Result<usize> {
-    let signal: SigId = signal.try_into()?;
+    // Signal 0 delivers nothing, but still checks that the target exists.
+    let signal = if signal.is_null() {
+        None
+    } else {
+        Some(signal.try_into()?)
+    };
 
-    let current_task = current_task();
-    // Kill ourselves
-    if pid == current_task.process.tgid.value() as PidT {
-        current_task.process.deliver_signal(signal);
+    let sender = current_task().process.clone();
 
-        return Ok(0);
-    }
+    kill(&sender, pid, signal)?;
 
-    match pid {
-        p if p > 0 => {
-            let target_tg = ThreadGroup::get(Tgid(p as _)).ok_or(KernelError::NoProcess)?;
-            target_tg.deliver_signal(signal);
-        }
+    Ok(0)
+}
 
-        0 => {
-            let our_pgid = *current_task.process.pgid.lock_save_irq();
-            // Iterate over all thread groups and signal the ones that are in
-            // the same PGID.
-            for tg_weak in crate::process::thread_group::TG_LIST
-                .lock_save_irq()
-                .values()
-            {
-                if let Some(tg) = tg_weak.upgrade()
-                    && *tg.pgid.lock_save_irq() == our_pgid
-                {
-                    tg.deliver_signal(signal);
-                }
-            }
+/// Sends `signal` from `sender` to the process(es) selected by `pid`, as for
+/// `kill(2)`. Fails with `NoProcess` if nothing matches.
+fn kill(sender: &ThreadGroup, pid: PidT, signal: Option<SigId>) -> Result<()> {
+    let deliver = |tg: &ThreadGroup| {
+        if let Some(signal) = signal {
+            tg.deliver_signal(signal);
         }
+    };
 
-        p if p < 0 && p != -1 => {
-            let target_pgid = Pgid((-p) as _);
-            for tg_weak in crate::process::thread_group::TG_LIST
-                .lock_save_irq()
-                .values()
-            {
-                if let Some(tg) = tg_weak.upgrade()
-                    && *tg.pgid.lock_save_irq() == target_pgid
-                {
-                    tg.deliver_signal(signal);
-                }
-            }
-        }
+    if pid > 0 {
+        let target_tg = ThreadGroup::get(Tgid(pid as _)).ok_or(KernelError::NoProcess)?;
+        deliver(&target_tg);
 
-        _ => return Err(KernelError::NotSupported),
+        return Ok(());
     }
 
-    Ok(0)
+    let our_pgid = *sender.pgid.lock_save_irq();
+    let targets: Vec<_> = TG_LIST
+        .lock_save_irq()
+        .values()
+        .filter_map(Weak::upgrade)
+        .filter(|tg| match pid {
+            // Every process in the sender's process group.
+            0 => *tg.pgid.lock_save_irq() == our_pgid,
+            // Every process we could signal, bar init and the sender. Only
+            // init and kernel threads have no parent.
+            -1 => tg.tgid != sender.tgid && tg.parent.lock_save_irq().is_some(),
+            // Every process in the process group `-pid`.
+            p => *tg.pgid.lock_save_irq() == Pgid(p.unsigned_abs()),
+        })
+        .collect();
+
+    if targets.is_empty() {
+        return Err(KernelError::NoProcess);
+    }
+
+    for tg in targets {
+        deliver(&tg);
+    }
+
+    Ok(())
 }
 
 pub fn sys_tkill(tid: PidT, signal: UserSigId) -> Result<usize> {
@@ -103,3 +109,48 @@ pub fn send_signal_to_pg(pgid: Pgid, signal: SigId) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ktest,
+        process::thread_group::{
+            builder::ThreadGroupBuilder,
+            signal::{SigSet, ksigaction::KSignalAction},
+        },
+    };
+
+    ktest! {
+        fn kill_delivers_fatal_default_signal() {
+            let parent = ThreadGroupBuilder::new(ThreadGroup::next_tgid()).build();
+            let (child, _) = parent.clone().new_child(false);
+            let pid = child.tgid.value() as PidT;
+
+            // Signal 0 only checks that the target exists.
+            kill(&parent, pid, None).unwrap();
+            assert!(child.pending_signals.lock_save_irq().is_empty());
+            assert_eq!(
+                kill(&parent, ThreadGroup::next_tgid().value() as PidT, None),
+                Err(KernelError::NoProcess)
+            );
+
+            kill(&parent, pid, Some(SigId::SIGKILL)).unwrap();
+
+            let signal = child
+                .pending_signals
+                .lock_save_irq()
+                .take_signal(SigSet::empty());
+            assert_eq!(signal, Some(SigId::SIGKILL));
+
+            // The default action for SIGKILL terminates the process, whereas
+            // SIGCHLD is ignored.
+            let actions = child.signals.lock_save_irq();
+            assert!(matches!(
+                actions.action_signal(SigId::SIGKILL),
+                Some(KSignalAction::Term)
+            ));
+            assert!(actions.action_signal(SigId::SIGCHLD).is_none());
+        }
+    }
+}
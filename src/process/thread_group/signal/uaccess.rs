@@ -8,6 +8,14 @@ use super::SigId;
 #[derive(Clone, Copy, Debug)]
 pub struct UserSigId(u32);
 
+impl UserSigId {
+    /// Whether this is signal 0, which checks a target exists without
+    /// signalling it.
+    pub fn is_null(self) -> bool {
+        self.0 == 0
+    }
+}
+
 impl TryFrom<UserSigId> for SigId {
     type Error = KernelError;
 
@@ -80,6 +80,13 @@ pub enum ChildState {
 }
 
 impl ChildState {
+    fn is_exit(&self) -> bool {
+        matches!(
+            self,
+            ChildState::NormalExit { .. } | ChildState::SignalExit { .. }
+        )
+    }
+
     fn matches_wait_flags(&self, flags: WaitFlags) -> bool {
         match self {
             ChildState::NormalExit { .. } | ChildState::SignalExit { .. } => {
@@ -119,6 +126,26 @@ impl ChildNotifiers {
             WakeupType::All
         });
     }
+
+    /// Removes and returns every state change that hasn't been waited for.
+    pub fn take_all(&self) -> BTreeMap<Tgid, ChildState> {
+        let mut pending = BTreeMap::new();
+
+        self.inner.update(|state| {
+            pending = core::mem::take(state);
+            WakeupType::None
+        });
+
+        pending
+    }
+}
+
+/// Releases a child that has been waited for, if it has exited. The parent
+/// holds the last reference to a zombie, so dropping it frees what remains.
+fn reap(parent: &ThreadGroup, tgid: Tgid, state: ChildState) {
+    if state.is_exit() {
+        parent.children.lock_save_irq().remove(&tgid);
+    }
 }
 
 fn do_wait(
@@ -211,6 +238,49 @@ fn find_waitable(
     state.get(&key).map(|v| (key, *v))
 }
 
+/// Waits for a child of `parent` selected by `pid` to change state, and
+/// consumes that change, reaping the child if it has exited. Returns `None`
+/// under `WNOHANG` if no child has changed state yet.
+async fn wait_child(
+    parent: &ThreadGroup,
+    pid: PidT,
+    flags: WaitFlags,
+) -> Result<Option<(Tgid, ChildState)>> {
+    let child_proc_count = parent.children.lock_save_irq().iter().count();
+
+    let (tgid, child_state) = if child_proc_count == 0 || flags.contains(WaitFlags::WNOHANG) {
+        // Special case for no children. See if there are any pending child
+        // notification events without sleeping. If there are no children and no
+        // pending events, return ECHILD.
+        let mut ret = None;
+        parent.child_notifiers.inner.update(|s| {
+            ret = do_wait(s, pid, flags);
+            WakeupType::None
+        });
+
+        match ret {
+            Some(ret) => ret,
+            None if child_proc_count == 0 => return Err(KernelError::NoChildProcess),
+            None => return Ok(None),
+        }
+    } else {
+        match parent
+            .child_notifiers
+            .inner
+            .wait_until(|state| do_wait(state, pid, flags))
+            .interruptable()
+            .await
+        {
+            InterruptResult::Interrupted => return Err(KernelError::Interrupted),
+            InterruptResult::Uninterrupted(r) => r,
+        }
+    };
+
+    reap(parent, tgid, child_state);
+
+    Ok(Some((tgid, child_state)))
+}
+
 pub async fn sys_wait4(
     pid: PidT,
     stat_addr: TUA<i32>,
@@ -248,35 +318,8 @@ pub async fn sys_wait4(
 
     let task = current_task_shared();
 
-    let child_proc_count = task.process.children.lock_save_irq().iter().count();
-
-    let (tgid, child_state) = if child_proc_count == 0 || flags.contains(WaitFlags::WNOHANG) {
-        // Special case for no children. See if there are any pending child
-        // notification events without sleeping. If there are no children and no
-        // pending events, return ECHILD.
-        let mut ret = None;
-        task.process.child_notifiers.inner.update(|s| {
-            ret = do_wait(s, pid, flags);
-            WakeupType::None
-        });
-
-        match ret {
-            Some(ret) => ret,
-            None if child_proc_count == 0 => return Err(KernelError::NoChildProcess),
-            None => return Ok(0),
-        }
-    } else {
-        match task
-            .process
-            .child_notifiers
-            .inner
-            .wait_until(|state| do_wait(state, pid, flags))
-            .interruptable()
-            .await
-        {
-            InterruptResult::Interrupted => return Err(KernelError::Interrupted),
-            InterruptResult::Uninterrupted(r) => r,
-        }
+    let Some((tgid, child_state)) = wait_child(&task.process, pid, flags).await? else {
+        return Ok(0);
     };
 
     if !stat_addr.is_null() {
@@ -369,14 +412,14 @@ pub async fn sys_waitid(
     let child_proc_count = task.process.children.lock_save_irq().iter().count();
 
     // Try immediate check if no children or WNOHANG
-    let child_state = if child_proc_count == 0 || flags.contains(WaitFlags::WNOHANG) {
-        let mut ret: Option<ChildState> = None;
+    let (tgid, child_state) = if child_proc_count == 0 || flags.contains(WaitFlags::WNOHANG) {
+        let mut ret = None;
         task.process.child_notifiers.inner.update(|s| {
             // Use non-consuming finder for WNOWAIT, else consume
             ret = if flags.contains(WaitFlags::WNOWAIT) {
-                find_waitable(s, sel_pid, flags).map(|(_, state)| state)
+                find_waitable(s, sel_pid, flags)
             } else {
-                do_wait(s, sel_pid, flags).map(|(_, state)| state)
+                do_wait(s, sel_pid, flags)
             };
             WakeupType::None
         });
@@ -388,8 +431,7 @@ pub async fn sys_waitid(
         }
     } else {
         // Wait until a child matches; first find key, then remove conditionally
-        let (_, state) = task
-            .process
+        task.process
             .child_notifiers
             .inner
             .wait_until(|s| {
@@ -399,10 +441,13 @@ pub async fn sys_waitid(
                     do_wait(s, sel_pid, flags)
                 }
             })
-            .await;
-        state
+            .await
     };
 
+    if !flags.contains(WaitFlags::WNOWAIT) {
+        reap(&task.process, tgid, child_state);
+    }
+
     // Populate siginfo
     if !infop.is_null() {
         let mut siginfo = SigInfo {
@@ -438,3 +483,50 @@ pub async fn sys_waitid(
     // Return 0 on success
     Ok(0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ktest,
+        process::{
+            exit::exit_to_parent,
+            kthread::spawn_kthread,
+            thread_group::{ProcessState, builder::ThreadGroupBuilder},
+        },
+    };
+
+    ktest! {
+        async fn exited_child_is_reaped() {
+            let parent = ThreadGroupBuilder::new(ThreadGroup::next_tgid()).build();
+            let (child, _) = parent.clone().new_child(false);
+            let tgid = child.tgid;
+            let flags = WaitFlags::WEXITED | WaitFlags::WNOHANG;
+
+            // Nothing to reap while the child is running.
+            assert!(matches!(wait_child(&parent, -1, flags).await, Ok(None)));
+
+            let p = parent.clone();
+            let handle = spawn_kthread("wait-child", async move {
+                exit_to_parent(&child, &p, ChildState::NormalExit { code: 42 });
+            })
+            .unwrap();
+            handle.join().await;
+
+            // The child lingers as a zombie until it's waited for.
+            let zombie = ThreadGroup::get(tgid).unwrap();
+            assert_eq!(*zombie.state.lock_save_irq(), ProcessState::Zombie);
+            drop(zombie);
+
+            assert!(matches!(
+                wait_child(&parent, -1, flags).await,
+                Ok(Some((t, ChildState::NormalExit { code: 42 }))) if t == tgid
+            ));
+            assert!(ThreadGroup::get(tgid).is_none());
+            assert!(matches!(
+                wait_child(&parent, -1, flags).await,
+                Err(KernelError::NoChildProcess)
+            ));
+        }
+    }
+}
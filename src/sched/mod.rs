@@ -6,14 +6,14 @@ use crate::process::owned::OwnedTask;
 use crate::{
     arch::Arch,
     per_cpu_private, per_cpu_shared,
-    process::{TASK_LIST, TaskDescriptor, TaskState},
+    process::{TASK_LIST, Task, TaskDescriptor, TaskState},
 };
 use alloc::{boxed::Box, collections::btree_map::BTreeMap, sync::Arc};
 use core::fmt::Debug;
-use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use core::time::Duration;
 use current::{CUR_TASK_PTR, current_task};
-use libkernel::{UserAddressSpace, error::Result};
+use libkernel::{UserAddressSpace, error::Result, sync::lockdep};
 use log::warn;
 use runqueue::{RunQueue, SwitchResult};
 use sched_task::SchedulableTask;
@@ -68,10 +68,62 @@ pub fn get_cpu_stat(cpu_id: CpuId) -> CpuStat<usize> {
     CPU_STAT.get_by_cpu(cpu_id.value()).to_usize()
 }
 
+/// Event counters for a single CPU.
+///
+/// A CPU only ever writes its own counters, so relaxed atomics suffice; they
+/// exist only so that other CPUs can read them without taking a lock.
+#[derive(Debug, Default)]
+pub struct CpuCounters<T>
+where
+    T: Debug + Default,
+{
+    /// Scheduler ticks taken while the CPU was running its idle task.
+    pub idle_ticks: T,
+    pub context_switches: T,
+    pub irqs: T,
+}
+
+impl CpuCounters<AtomicUsize> {
+    pub fn to_usize(&self) -> CpuCounters<usize> {
+        CpuCounters {
+            idle_ticks: self.idle_ticks.load(Ordering::Relaxed),
+            context_switches: self.context_switches.load(Ordering::Relaxed),
+            irqs: self.irqs.load(Ordering::Relaxed),
+        }
+    }
+}
+
+per_cpu_shared! {
+    pub static CPU_COUNTERS: CpuCounters<AtomicUsize> = CpuCounters::default;
+}
+
+/// Returns a snapshot of the event counters for `cpu_id`.
+pub fn cpu_stats(cpu_id: CpuId) -> CpuCounters<usize> {
+    CPU_COUNTERS.get_by_cpu(cpu_id.value()).to_usize()
+}
+
 per_cpu_private! {
     static SCHED_STATE: SchedState = SchedState::new;
 }
 
+// Set when the running task's time slice runs out, or another CPU asks for a
+// reschedule, and consumed by the next call to `schedule()`. Ticks are only
+// taken with IRQs unmasked, so this can never be raised while the CPU holds a
+// `SpinLock`; the reschedule itself happens on the return-to-user path.
+per_cpu_shared! {
+    static NEED_RESCHED: AtomicBool = AtomicBool::default;
+}
+
+// The instant, in timer ticks, at which the running task's time slice ends.
+per_cpu_shared! {
+    static SLICE_END: AtomicU64 = AtomicU64::default;
+}
+
+// Whether a scheduler tick has been taken since `do_schedule` last ran.
+per_cpu_shared! {
+    static TICKED: AtomicBool = AtomicBool::default;
+}
+
 /// Default time-slice assigned to runnable tasks.
 const DEFAULT_TIME_SLICE: Duration = Duration::from_millis(4);
 
@@ -121,6 +173,50 @@ fn schedule() {
     SCHED_STATE.borrow_mut().do_schedule();
 }
 
+/// Called from the timer interrupt on every scheduler tick. Counts the tick
+/// down against the running task's time slice, and asks for a reschedule on
+/// the way back to userspace once the slice has run out.
+///
+/// A woken task that should run before the current one asks for a reschedule
+/// itself, when it's queued.
+pub fn timer_tick(now: Instant) {
+    TICKED.get().store(true, Ordering::Relaxed);
+
+    if now.ticks() >= SLICE_END.get().load(Ordering::Relaxed) {
+        set_need_resched();
+    }
+}
+
+/// Asks this CPU to pick again on its next return to userspace.
+pub fn set_need_resched() {
+    NEED_RESCHED.get().store(true, Ordering::Relaxed);
+}
+
+/// Returns once no CPU is executing `task`, i.e. none can still be using its
+/// address space.
+///
+/// The caller must already have marked `task` `Finished`: once it's switched
+/// out, it's never run again. A task running on another CPU is interrupted so
+/// that it reaches the scheduler without waiting for its slice to end.
+pub fn wait_off_cpu(task: &Task) {
+    if !task.on_cpu.load(Ordering::Acquire) {
+        return;
+    }
+
+    let cpu = *task.last_cpu.lock_save_irq();
+
+    if cpu != CpuId::this() && message_cpu(cpu, Message::Resched).is_err() {
+        warn!(
+            "Could not interrupt CPU {}; waiting for its tick",
+            cpu.value()
+        );
+    }
+
+    while task.on_cpu.load(Ordering::Acquire) {
+        core::hint::spin_loop();
+    }
+}
+
 pub fn spawn_kernel_work(fut: impl Future<Output = ()> + 'static + Send) {
     current_task().ctx.put_kernel_work(Box::pin(fut));
 }
@@ -299,6 +395,22 @@ impl SchedState {
         }
     }
 
+    /// Charges a scheduler tick to this CPU's idle counter if it was idling.
+    fn account_tick(&self) {
+        if self.run_q.current().is_none_or(|task| task.is_idle_task()) {
+            CPU_COUNTERS
+                .get()
+                .idle_ticks
+                .fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Give up the remainder of the current task's slice to its peers.
+    fn yield_current(&mut self) {
+        self.run_q.yield_current();
+        self.force_resched = true;
+    }
+
     pub fn do_schedule(&mut self) {
         self.update_global_least_tasked_cpu_info();
         // Update Clocks
@@ -306,7 +418,12 @@ impl SchedState {
 
         self.advance_vclock(now_inst);
 
-        let mut needs_resched = self.force_resched;
+        let mut needs_resched =
+            self.force_resched | NEED_RESCHED.get().swap(false, Ordering::Relaxed);
+
+        if TICKED.get().swap(false, Ordering::Relaxed) {
+            self.account_tick();
+        }
 
         if let Some(current) = self.run_q.current_mut() {
             current.update_accounting(Some(now_inst));
@@ -340,9 +457,18 @@ impl SchedState {
         // Select Next Task.
         let next_task_desc = self.run_q.find_next_runnable_desc(self.vclock);
 
+        // Kept alive until its address space has been switched away from, so
+        // that anything waiting in `wait_off_cpu` can then tear it down.
+        let prev_task = self.run_q.current().map(|task| task.t_shared.clone());
+
         match self.run_q.switch_tasks(next_task_desc, now_inst) {
             SwitchResult::AlreadyRunning => {
-                // Nothing to do.
+                // Nothing else is due to run, so the task carries on with a
+                // fresh slice.
+                if let Some(current) = self.run_q.current_mut() {
+                    current.start_slice(now_inst);
+                }
+
                 return;
             }
             SwitchResult::Blocked { old_task } => {
@@ -359,11 +485,20 @@ impl SchedState {
         // Update all context since the task has switched.
         if let Some(new_current) = self.run_q.current_mut() {
             NUM_CONTEXT_SWITCHES.fetch_add(1, Ordering::Relaxed);
+            CPU_COUNTERS
+                .get()
+                .context_switches
+                .fetch_add(1, Ordering::Relaxed);
             ArchImpl::context_switch(new_current.t_shared.clone());
+            lockdep::switch_task::<ArchImpl>(Arc::as_ptr(&new_current.t_shared) as usize);
             let now = now().unwrap();
             new_current.reset_last_account(now);
             CUR_TASK_PTR.borrow_mut().set_current(&mut new_current.task);
         }
+
+        if let Some(prev_task) = prev_task {
+            prev_task.on_cpu.store(false, Ordering::Release);
+        }
     }
 }
 
@@ -402,6 +537,52 @@ pub fn sched_init_secondary() {
 }
 
 pub fn sys_sched_yield() -> Result<usize> {
+    SCHED_STATE.borrow_mut().yield_current();
     schedule();
     Ok(0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ktest;
+
+    ktest! {
+        fn idle_ticks_advance_when_nothing_runnable() {
+            let mut state = SchedState::new();
+            let idle_ticks = || cpu_stats(CpuId::this()).idle_ticks;
+            let before = idle_ticks();
+
+            // Nothing at all to run counts as idle, as does the idle task.
+            state.account_tick();
+            state.run_q.running_task =
+                Some(SchedulableTask::new(Box::new(ArchImpl::create_idle_task())));
+            state.account_tick();
+            assert_eq!(idle_ticks(), before + 2);
+
+            state.run_q.running_task =
+                Some(SchedulableTask::new(Box::new(OwnedTask::create_init_task())));
+            state.account_tick();
+            assert_eq!(idle_ticks(), before + 2);
+        }
+    }
+
+    ktest! {
+        fn tick_reschedules_once_slice_runs_out() {
+            let start = now().unwrap();
+            let need_resched = || NEED_RESCHED.get().swap(false, Ordering::Relaxed);
+
+            need_resched();
+            SLICE_END
+                .get()
+                .store((start + DEFAULT_TIME_SLICE).ticks(), Ordering::Relaxed);
+
+            // A tick part way through the slice leaves the task running.
+            timer_tick(start);
+            assert!(!need_resched());
+
+            timer_tick(start + DEFAULT_TIME_SLICE);
+            assert!(need_resched());
+        }
+    }
+}
@@ -23,6 +23,9 @@ pub enum SwitchResult {
 
 /// A simple weight-tracking runqueue.
 ///
+/// Tasks are picked by [`SchedClass`](super::sched_task::SchedClass) first and then by virtual deadline, so
+/// the idle task only runs when nothing else is runnable.
+///
 /// Invariants:
 /// 1. `total_weight` = Sum(queue tasks) + Weight(running_task) (excluding the idle task).
 /// 2. `running_task` is NOT in `queue`.
@@ -153,6 +156,27 @@ impl RunQueue {
         best_queued_desc
     }
 
+    /// Moves the running task to the back of its scheduling class by pushing its
+    /// deadline past that of every queued peer. The task is still preferred
+    /// over any lower class, so yielding never hands the CPU to idle while the
+    /// yielding task is runnable.
+    pub fn yield_current(&mut self) {
+        let Some(current) = self.running_task.as_mut() else {
+            return;
+        };
+
+        let last_deadline = self
+            .queue
+            .values()
+            .filter(|task| task.class() == current.class())
+            .map(|task| task.v_deadline)
+            .max();
+
+        if let Some(last) = last_deadline {
+            current.v_deadline = current.v_deadline.max(last + 1);
+        }
+    }
+
     /// Inserts `task` into this CPU's run-queue.
     pub fn enqueue_task(&mut self, new_task: Box<SchedulableTask>) {
         if !new_task.is_idle_task() {
@@ -167,3 +191,72 @@ impl RunQueue {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        arch::{Arch, ArchImpl},
+        ktest,
+        process::{Tid, owned::OwnedTask},
+    };
+    use alloc::sync::Arc;
+
+    fn runnable(mut task: OwnedTask, tid: u32, priority: i8) -> Box<SchedulableTask> {
+        Arc::get_mut(&mut task.t_shared).unwrap().tid = Tid(tid);
+        task.set_priority(priority);
+        *task.state.lock_save_irq() = TaskState::Runnable;
+
+        let mut task = SchedulableTask::new(Box::new(task));
+        task.inserting_into_runqueue(0);
+        task
+    }
+
+    fn normal_task(tid: u32, priority: i8) -> Box<SchedulableTask> {
+        runnable(OwnedTask::create_init_task(), tid, priority)
+    }
+
+    ktest! {
+        fn higher_priority_runs_first() {
+            let mut rq = RunQueue::new();
+            let idle = runnable(ArchImpl::create_idle_task(), 0, i8::MIN);
+            let idle_desc = idle.descriptor();
+
+            rq.enqueue_task(idle);
+            assert_eq!(rq.find_next_runnable_desc(0), idle_desc);
+
+            let low = normal_task(100, -10);
+            let high = normal_task(101, 10);
+            let high_desc = high.descriptor();
+
+            rq.enqueue_task(low);
+            rq.enqueue_task(high);
+
+            assert_eq!(rq.find_next_runnable_desc(0), high_desc);
+        }
+    }
+
+    ktest! {
+        fn yield_moves_to_back_of_class() {
+            let mut rq = RunQueue::new();
+            rq.enqueue_task(runnable(ArchImpl::create_idle_task(), 0, i8::MIN));
+
+            let mut current = normal_task(100, 0);
+            let current_desc = current.descriptor();
+            *current.state.lock_save_irq() = TaskState::Running;
+            rq.running_task = Some(current);
+
+            // Alone in its class, a yielding task is still preferred over idle.
+            rq.yield_current();
+            assert_eq!(rq.find_next_runnable_desc(0), current_desc);
+
+            let peer = normal_task(101, 0);
+            let peer_desc = peer.descriptor();
+            rq.enqueue_task(peer);
+
+            assert_eq!(rq.find_next_runnable_desc(0), current_desc);
+            rq.yield_current();
+            assert_eq!(rq.find_next_runnable_desc(0), peer_desc);
+        }
+    }
+}
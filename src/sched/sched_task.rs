@@ -1,6 +1,7 @@
 use core::{
     cmp::Ordering,
     ops::{Deref, DerefMut},
+    sync::atomic,
 };
 
 use alloc::boxed::Box;
@@ -11,7 +12,18 @@ use crate::{
     process::{TaskState, owned::OwnedTask},
 };
 
-use super::{DEFAULT_TIME_SLICE, SCHED_WEIGHT_BASE, VT_FIXED_SHIFT};
+use super::{DEFAULT_TIME_SLICE, SCHED_WEIGHT_BASE, SLICE_END, VT_FIXED_SHIFT};
+
+/// The priority level a task is scheduled at.
+///
+/// A runnable task in a lower level always runs in preference to any task in a
+/// higher one. Tasks within a level share the CPU by virtual deadline, which
+/// is round-robin between tasks of equal weight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SchedClass {
+    Normal,
+    Idle,
+}
 
 pub struct SchedulableTask {
     pub task: Box<OwnedTask>,
@@ -99,17 +111,18 @@ impl SchedulableTask {
         if w <= 0 { 1 } else { w as u32 }
     }
 
-    pub fn compare_with(&self, other: &Self) -> core::cmp::Ordering {
+    pub fn class(&self) -> SchedClass {
         if self.is_idle_task() {
-            return Ordering::Greater;
-        }
-
-        if other.is_idle_task() {
-            return Ordering::Less;
+            SchedClass::Idle
+        } else {
+            SchedClass::Normal
         }
+    }
 
-        self.v_deadline
-            .cmp(&other.v_deadline)
+    pub fn compare_with(&self, other: &Self) -> core::cmp::Ordering {
+        self.class()
+            .cmp(&other.class())
+            .then_with(|| self.v_deadline.cmp(&other.v_deadline))
             .then_with(|| self.v_runtime.cmp(&other.v_runtime))
             // If completely equal, prefer the one that hasn't run in a while?
             // Or prefer the one already running to avoid cache thrashing?
@@ -142,14 +155,28 @@ impl SchedulableTask {
     pub fn about_to_execute(&mut self, now: Instant) {
         self.exec_start = Some(now);
         *self.last_cpu.lock_save_irq() = CpuId::this();
-        *self.state.lock_save_irq() = TaskState::Running;
+        self.on_cpu.store(true, atomic::Ordering::Release);
+
+        // A task killed while it was queued stays dead; it's switched straight
+        // back out.
+        let mut state = self.state.lock_save_irq();
+        if !state.is_finished() {
+            *state = TaskState::Running;
+        }
+        drop(state);
+
+        self.start_slice(now);
+    }
 
-        // Deadline logic
+    /// Gives the task a fresh time slice on this CPU if its current one is
+    /// nearly used up, and arms the timer for the slice's end.
+    pub fn start_slice(&mut self, now: Instant) {
         if self.deadline.is_none_or(|d| d <= now + DEFAULT_TIME_SLICE) {
             self.deadline = Some(now + DEFAULT_TIME_SLICE);
         }
 
         if let Some(d) = self.deadline {
+            SLICE_END.get().store(d.ticks(), atomic::Ordering::Relaxed);
             schedule_preempt(d);
         }
     }
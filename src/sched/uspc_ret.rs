@@ -82,6 +82,13 @@ pub fn dispatch_userspace_task(ctx: *mut UserCtx) {
                 state = State::ProcessKernelWork;
             }
             State::ProcessKernelWork => {
+                // A thread killed by another (e.g. by `exit_group()` or
+                // `execve()`) must never run again.
+                if current_task().state.lock_save_irq().is_finished() {
+                    state = State::PickNewTask;
+                    continue;
+                }
+
                 // First, let's handle signals. If there is any scheduled signal
                 // work (this has to be async to handle faults, etc).
                 let (signal_work, desc, is_idle) = {
@@ -113,8 +120,9 @@ pub fn dispatch_userspace_task(ctx: *mut UserCtx) {
                             // terminate.
                             kernel_exit_with_signal(SigId::SIGSEGV, true);
 
-                            // Look for another task, this one is now dead.
-                            state = State::PickNewTask;
+                            // Run the tear down that's been queued as kernel
+                            // work.
+                            state = State::ProcessKernelWork;
                             continue;
                         }
                         Poll::Pending => {
@@ -257,11 +265,12 @@ pub fn dispatch_userspace_task(ctx: *mut UserCtx) {
                             // Signal ignored, look for another.
                             None => continue,
                             Some(KSignalAction::Term | KSignalAction::Core) => {
-                                // Terminate the process, and find a new task.
+                                // Terminate the process. The tear down is
+                                // queued as kernel work.
                                 drop(task);
                                 kernel_exit_with_signal(signal, false);
 
-                                state = State::PickNewTask;
+                                state = State::ProcessKernelWork;
                                 continue 'dispatch;
                             }
                             Some(KSignalAction::Stop) => {
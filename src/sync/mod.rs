@@ -1,4 +1,5 @@
-use crate::arch::ArchImpl;
+use crate::{arch::ArchImpl, drivers::timer::sleep};
+use core::time::Duration;
 
 pub mod per_cpu;
 
@@ -15,9 +16,54 @@ pub type AsyncRwlockWriteGuard<'a, T> =
     libkernel::sync::rwlock::AsyncRwlockWriteGuard<'a, T, ArchImpl>;
 pub type OnceLock<T> = libkernel::sync::once_lock::OnceLock<T, ArchImpl>;
 pub type CondVar<T> = libkernel::sync::condvar::CondVar<T, ArchImpl>;
+
+/// Timed locking for [`Mutex`], driven by the system timer.
+#[cfg_attr(not(test), expect(dead_code))]
+pub trait MutexExt<T: ?Sized> {
+    /// Acquires the lock, giving up and returning `None` once `timeout` has
+    /// passed.
+    async fn lock_timeout(&self, timeout: Duration) -> Option<AsyncMutexGuard<'_, T>>;
+}
+
+impl<T: ?Sized> MutexExt<T> for Mutex<T> {
+    async fn lock_timeout(&self, timeout: Duration) -> Option<AsyncMutexGuard<'_, T>> {
+        self.lock_or(sleep(timeout)).await
+    }
+}
 // pub type Reciever<T> = libkernel::sync::mpsc::Reciever<T, ArchImpl>;
 // pub type Sender<T> = libkernel::sync::mpsc::Sender<T, ArchImpl>;
 
 // pub fn channel<T: Send>() -> (Sender<T>, Reciever<T>) {
 //     libkernel::sync::mpsc::channel()
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{drivers::timer::uptime, ktest};
+    use core::{
+        hint::spin_loop,
+        pin::pin,
+        task::{Context, Poll, Waker},
+    };
+
+    ktest! {
+        fn lock_timeout_gives_up_on_a_held_mutex() {
+            let mutex = Mutex::new(0);
+            let _held = mutex.try_lock().unwrap();
+
+            let timeout = Duration::from_millis(10);
+            let deadline = uptime() + timeout;
+            let mut cx = Context::from_waker(Waker::noop());
+            let mut attempt = pin!(mutex.lock_timeout(timeout));
+
+            assert!(attempt.as_mut().poll(&mut cx).is_pending());
+
+            while uptime() < deadline {
+                spin_loop();
+            }
+
+            assert!(matches!(attempt.as_mut().poll(&mut cx), Poll::Ready(None)));
+        }
+    }
+}
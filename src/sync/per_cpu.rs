@@ -1,7 +1,11 @@
 /// A declarative macro to define a static `PerCpu` variable and register it
 /// for automatic initialization.
+///
+/// The current CPU's instance is reached through
+/// [`libkernel::sync::per_cpu::this_cpu`], or, for a `RefCell`,
+/// [`libkernel::sync::per_cpu::this_cpu_mut`].
 #[macro_export]
-macro_rules! per_cpu_shared {
+macro_rules! per_cpu {
     ($vis:vis static $name:ident: $type:ty = $initializer:expr;) => {
         $vis static $name: libkernel::sync::per_cpu::PerCpu<$type, $crate::arch::ArchImpl> =
             libkernel::sync::per_cpu::PerCpu::new($initializer);
@@ -17,22 +21,23 @@ macro_rules! per_cpu_shared {
     };
 }
 
+/// Defines a `PerCpu` variable shared with interrupt handlers on the same CPU.
+#[macro_export]
+macro_rules! per_cpu_shared {
+    ($vis:vis static $name:ident: $type:ty = $initializer:expr;) => {
+        $crate::per_cpu! {
+            $vis static $name: $type = $initializer;
+        }
+    };
+}
+
 /// Wraps with a [`RefCell`] for convenience
 #[macro_export]
 macro_rules! per_cpu_private {
     ($vis:vis static $name:ident: $type:ty = $initializer:expr;) => {
-        $vis static $name: libkernel::sync::per_cpu::PerCpu<
-            core::cell::RefCell<$type>,
-            $crate::arch::ArchImpl,
-        > = libkernel::sync::per_cpu::PerCpu::new(|| {core::cell::RefCell::new($initializer())});
-
-        paste::paste! {
-        #[unsafe(no_mangle)]
-        #[unsafe(link_section = ".percpu")]
-        #[used(linker)]
-        static [<$name _PERCPU_INITIALIZER>]: &'static (
-                     dyn libkernel::sync::per_cpu::PerCpuInitializer + Sync
-                 ) = &$name;
+        $crate::per_cpu! {
+            $vis static $name: core::cell::RefCell<$type> =
+                || core::cell::RefCell::new($initializer());
         }
     };
 }
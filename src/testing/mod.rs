@@ -1,8 +1,16 @@
 use crate::arch::{Arch, ArchImpl};
 use crate::console::write_fmt;
 use crate::drivers::timer::uptime;
+use crate::memory::mmap::{MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE, sys_mmap};
+use crate::memory::page::free_user_page;
+use crate::process::fd_table::Fd;
+use crate::sched::current::current_task;
 use alloc::format;
 use core::fmt::Display;
+use libkernel::memory::{
+    address::{UA, VA},
+    region::VirtMemoryRegion,
+};
 
 const TEXT_GREEN: &str = "\x1b[32m";
 const TEXT_RED: &str = "\x1b[31m";
@@ -62,6 +70,51 @@ pub fn test_runner(tests: &[&Test]) {
 
 pub fn panic_noop(_: *mut u8, _: *mut u8) {}
 
+/// Anonymous memory in the current task's address space, for tests that need
+/// a user buffer to hand to syscalls. It's unmapped when dropped, so a failing
+/// test doesn't leave it behind.
+pub struct ScratchBuf {
+    addr: UA,
+    len: usize,
+}
+
+impl ScratchBuf {
+    pub async fn new(len: usize) -> Self {
+        let addr = sys_mmap(
+            0,
+            len as _,
+            PROT_READ | PROT_WRITE,
+            MAP_PRIVATE | MAP_ANONYMOUS,
+            Fd(-1),
+            0,
+        )
+        .await
+        .expect("Could not map scratch buffer");
+
+        Self {
+            addr: UA::from_value(addr),
+            len,
+        }
+    }
+
+    pub fn addr(&self) -> UA {
+        self.addr
+    }
+}
+
+impl Drop for ScratchBuf {
+    fn drop(&mut self) {
+        // Private anonymous memory has nothing to write back, so there's no
+        // need for the async half of `sys_munmap`.
+        let region = VirtMemoryRegion::new(VA::from_value(self.addr.value()), self.len);
+        let pages = current_task().vm.lock_save_irq().mm_mut().munmap(region);
+
+        for pfn in pages.into_iter().flatten() {
+            unsafe { free_user_page(pfn) };
+        }
+    }
+}
+
 #[macro_export]
 macro_rules! ktest {
     ($name:ident, fn $fn_name:ident() $body:block) => {
@@ -40,6 +40,78 @@ fn test_readdir() {
 
 register_test!(test_readdir);
 
+fn test_getdents64_resume() {
+    use std::collections::HashSet;
+
+    const NR_FILES: usize = 32;
+
+    let dir = "/tmp/getdents_test";
+    fs::create_dir(dir).expect("Failed to create directory");
+    for i in 0..NR_FILES {
+        fs::File::create_new(format!("{dir}/entry_{i}")).expect("Failed to create file");
+    }
+
+    fn read_all(fd: i32) -> (Vec<String>, usize) {
+        // Small enough that the directory can't be returned in one call.
+        let mut buf = [0u8; 128];
+        let mut names = Vec::new();
+        let mut calls = 0;
+
+        loop {
+            let ret = unsafe {
+                libc::syscall(libc::SYS_getdents64, fd, buf.as_mut_ptr(), buf.len())
+            };
+            if ret < 0 {
+                panic!("getdents64 failed");
+            }
+            if ret == 0 {
+                break;
+            }
+            calls += 1;
+
+            let mut off = 0;
+            while off < ret as usize {
+                let reclen = u16::from_ne_bytes([buf[off + 16], buf[off + 17]]) as usize;
+                let name = CStr::from_bytes_until_nul(&buf[off + 19..off + reclen]).unwrap();
+                names.push(name.to_str().unwrap().to_string());
+                off += reclen;
+            }
+        }
+
+        (names, calls)
+    }
+
+    let c_dir = CString::new(dir).unwrap();
+    unsafe {
+        let fd = libc::open(c_dir.as_ptr(), libc::O_RDONLY | libc::O_DIRECTORY);
+        if fd < 0 {
+            panic!("open failed");
+        }
+
+        let (names, calls) = read_all(fd);
+        assert!(calls > 1, "expected the listing to span multiple calls");
+
+        let unique: HashSet<_> = names.iter().collect();
+        assert_eq!(unique.len(), names.len(), "duplicate entries returned");
+        for i in 0..NR_FILES {
+            assert!(unique.contains(&format!("entry_{i}")));
+        }
+
+        // Rewinding the stream must replay the same listing.
+        if libc::lseek(fd, 0, libc::SEEK_SET) != 0 {
+            panic!("lseek failed");
+        }
+        let (rewound, _) = read_all(fd);
+        assert_eq!(rewound, names);
+
+        libc::close(fd);
+    }
+
+    fs::remove_dir_all(dir).expect("Failed to delete directory");
+}
+
+register_test!(test_getdents64_resume);
+
 fn test_chdir() {
     let path = CString::new("/dev").unwrap();
     let mut buffer = [1u8; 16];
@@ -91,6 +163,66 @@ fn test_fchdir() {
 
 register_test!(test_fchdir);
 
+fn test_openat_dirfd_matrix() {
+    let dir = "/tmp/openat_matrix";
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir(dir).expect("Failed to create directory");
+    fs::write(format!("{dir}/file"), b"x").expect("Failed to create file");
+
+    let c_dir = CString::new(dir).unwrap();
+    let c_abs = CString::new(format!("{dir}/file")).unwrap();
+    let c_rel = CString::new("file").unwrap();
+    let c_dev = CString::new("/dev").unwrap();
+
+    unsafe {
+        let dirfd = libc::open(c_dir.as_ptr(), libc::O_RDONLY | libc::O_DIRECTORY);
+        let otherfd = libc::open(c_dev.as_ptr(), libc::O_RDONLY | libc::O_DIRECTORY);
+        let filefd = libc::open(c_abs.as_ptr(), libc::O_RDONLY);
+        let mut pipefd = [0; 2];
+        assert!(dirfd >= 0 && otherfd >= 0 && filefd >= 0);
+        assert_eq!(libc::pipe(pipefd.as_mut_ptr()), 0);
+
+        let openat = |dirfd, path: &CString| {
+            let fd = libc::openat(dirfd, path.as_ptr(), libc::O_RDONLY);
+            if fd >= 0 {
+                libc::close(fd);
+                Ok(())
+            } else {
+                Err(*libc::__errno_location())
+            }
+        };
+
+        // Absolute paths ignore dirfd entirely, even an invalid one.
+        assert_eq!(openat(libc::AT_FDCWD, &c_abs), Ok(()));
+        assert_eq!(openat(otherfd, &c_abs), Ok(()));
+        assert_eq!(openat(filefd, &c_abs), Ok(()));
+        assert_eq!(openat(9999, &c_abs), Ok(()));
+
+        // Relative paths resolve against the cwd or dirfd.
+        assert_eq!(libc::chdir(c_dir.as_ptr()), 0);
+        assert_eq!(openat(libc::AT_FDCWD, &c_rel), Ok(()));
+        assert_eq!(libc::chdir(c_dev.as_ptr()), 0);
+        assert_eq!(openat(libc::AT_FDCWD, &c_rel), Err(libc::ENOENT));
+        assert_eq!(openat(dirfd, &c_rel), Ok(()));
+        assert_eq!(openat(otherfd, &c_rel), Err(libc::ENOENT));
+
+        // Relative paths with an unusable dirfd.
+        assert_eq!(openat(9999, &c_rel), Err(libc::EBADF));
+        assert_eq!(openat(filefd, &c_rel), Err(libc::ENOTDIR));
+        assert_eq!(openat(pipefd[0], &c_rel), Err(libc::ENOTDIR));
+
+        libc::close(pipefd[0]);
+        libc::close(pipefd[1]);
+        libc::close(filefd);
+        libc::close(otherfd);
+        libc::close(dirfd);
+    }
+
+    fs::remove_dir_all(dir).expect("Failed to delete directory");
+}
+
+register_test!(test_openat_dirfd_matrix);
+
 fn test_chroot() {
     let file = "/bin/busybox";
     let c_file = CString::new(file).unwrap();
@@ -109,6 +241,56 @@ fn test_chroot() {
 
 register_test!(test_chroot);
 
+fn test_mount_tmpfs() {
+    let dir = "/tmp/mount_test";
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir(dir).expect("Failed to create directory");
+
+    let c_dir = CString::new(dir).unwrap();
+    let c_none = CString::new("none").unwrap();
+    let c_tmpfs = CString::new("tmpfs").unwrap();
+    let c_bogus = CString::new("bogusfs").unwrap();
+
+    unsafe {
+        let ret = libc::mount(
+            c_none.as_ptr(),
+            c_dir.as_ptr(),
+            c_bogus.as_ptr(),
+            0,
+            std::ptr::null(),
+        );
+        assert_eq!(ret, -1);
+        assert_eq!(*libc::__errno_location(), libc::ENODEV);
+
+        fs::write(format!("{dir}/not_a_dir"), b"").expect("Failed to create file");
+        let c_file = CString::new(format!("{dir}/not_a_dir")).unwrap();
+        let ret = libc::mount(
+            c_none.as_ptr(),
+            c_file.as_ptr(),
+            c_tmpfs.as_ptr(),
+            0,
+            std::ptr::null(),
+        );
+        assert_eq!(ret, -1);
+        assert_eq!(*libc::__errno_location(), libc::ENOTDIR);
+
+        let ret = libc::mount(
+            c_none.as_ptr(),
+            c_dir.as_ptr(),
+            c_tmpfs.as_ptr(),
+            0,
+            std::ptr::null(),
+        );
+        assert_eq!(ret, 0, "mount failed");
+    }
+
+    fs::write(format!("{dir}/file"), b"mounted").expect("Failed to write file");
+    let data = fs::read(format!("{dir}/../mount_test/file")).expect("Failed to read file");
+    assert_eq!(data, b"mounted");
+}
+
+register_test!(test_mount_tmpfs);
+
 fn test_chmod() {
     let dir_path = "/tmp/chmod_test";
     let c_dir_path = CString::new(dir_path).unwrap();
@@ -332,6 +514,40 @@ fn test_symlink() {
 
 register_test!(test_symlink);
 
+fn test_symlink_nofollow_and_loop() {
+    let dir = "/tmp/symlink_loop";
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir(dir).expect("Failed to create directory");
+    fs::write(format!("{dir}/file"), b"x").expect("Failed to create file");
+
+    let c_file = CString::new("file").unwrap();
+    let c_link = CString::new(format!("{dir}/link")).unwrap();
+    let c_loop_a = CString::new(format!("{dir}/a")).unwrap();
+    let c_loop_b = CString::new(format!("{dir}/b")).unwrap();
+
+    unsafe {
+        assert_eq!(libc::symlink(c_file.as_ptr(), c_link.as_ptr()), 0);
+        assert_eq!(libc::symlink(c_loop_b.as_ptr(), c_loop_a.as_ptr()), 0);
+        assert_eq!(libc::symlink(c_loop_a.as_ptr(), c_loop_b.as_ptr()), 0);
+
+        let fd = libc::open(c_link.as_ptr(), libc::O_RDONLY);
+        assert!(fd >= 0, "open through symlink failed");
+        libc::close(fd);
+
+        let fd = libc::open(c_link.as_ptr(), libc::O_RDONLY | libc::O_NOFOLLOW);
+        assert_eq!(fd, -1);
+        assert_eq!(*libc::__errno_location(), libc::ELOOP);
+
+        let fd = libc::open(c_loop_a.as_ptr(), libc::O_RDONLY);
+        assert_eq!(fd, -1);
+        assert_eq!(*libc::__errno_location(), libc::ELOOP);
+    }
+
+    fs::remove_dir_all(dir).expect("Failed to delete directory");
+}
+
+register_test!(test_symlink_nofollow_and_loop);
+
 fn test_rename() {
     use std::fs::{self, File};
     use std::io::{Read, Write};
@@ -612,3 +828,47 @@ fn test_rust_dir() {
 }
 
 register_test!(test_rust_dir);
+
+fn read_in_chunks(path: &str, chunk: usize) -> String {
+    use std::io::Read;
+
+    let mut file = fs::File::open(path).expect("Failed to open file");
+    let mut out = Vec::new();
+    let mut buf = vec![0; chunk];
+
+    loop {
+        let n = file.read(&mut buf).expect("Failed to read file");
+        if n == 0 {
+            break;
+        }
+        out.extend_from_slice(&buf[..n]);
+    }
+
+    String::from_utf8(out).expect("File is not UTF-8")
+}
+
+fn test_proc_meminfo() {
+    // Small reads must stitch back together into well-formed lines.
+    let meminfo = read_in_chunks("/proc/meminfo", 7);
+
+    for label in ["MemTotal:", "MemFree:", "Slab:"] {
+        let line = meminfo
+            .lines()
+            .find(|l| l.starts_with(label))
+            .unwrap_or_else(|| panic!("{label} missing from /proc/meminfo"));
+        assert!(line.ends_with(" kB"), "malformed line: {line}");
+    }
+}
+
+register_test!(test_proc_meminfo);
+
+fn test_proc_self_maps() {
+    let maps = read_in_chunks("/proc/self/maps", 16);
+
+    assert!(
+        maps.lines().any(|l| l.split(' ').nth(1) == Some("r-xp")),
+        "no executable mapping in /proc/self/maps"
+    );
+}
+
+register_test!(test_proc_self_maps);
@@ -1,7 +1,12 @@
 use colored::Colorize;
 use std::{
+    arch::asm,
     io::{Write, stdout},
-    sync::{Arc, Barrier, Mutex},
+    ptr,
+    sync::{
+        Arc, Barrier, Mutex,
+        atomic::{AtomicU32, Ordering, fence},
+    },
     thread,
 };
 
@@ -59,6 +64,134 @@ fn test_clock_sleep() {
 
 register_test!(test_clock_sleep);
 
+fn test_clock_monotonic_advances() {
+    fn read_monotonic() -> libc::timespec {
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+
+        assert_eq!(
+            unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) },
+            0
+        );
+
+        ts
+    }
+
+    let first = read_monotonic();
+    let mut second = read_monotonic();
+
+    assert!((second.tv_sec, second.tv_nsec) >= (first.tv_sec, first.tv_nsec));
+
+    // The counter ticks at tens of MHz, so it must move within a short spin.
+    for _ in 0..1_000_000 {
+        if (second.tv_sec, second.tv_nsec) != (first.tv_sec, first.tv_nsec) {
+            break;
+        }
+        second = read_monotonic();
+    }
+
+    assert!((second.tv_sec, second.tv_nsec) > (first.tv_sec, first.tv_nsec));
+    assert!((0..1_000_000_000).contains(&second.tv_nsec));
+}
+
+register_test!(test_clock_monotonic_advances);
+
+fn test_vvar_monotonic() {
+    // Layout of the kernel's timekeeping page; see `src/clock/vvar.rs`.
+    #[repr(C)]
+    struct Vvar {
+        seq: AtomicU32,
+        _pad: u32,
+        freq: u64,
+        boot_ticks: u64,
+    }
+
+    const VVAR_BASE: usize = 0x0000_7fff_0000_0000;
+
+    fn timespec_ns(ts: &libc::timespec) -> u128 {
+        ts.tv_sec as u128 * 1_000_000_000 + ts.tv_nsec as u128
+    }
+
+    fn read_monotonic() -> u128 {
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+
+        assert_eq!(
+            unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) },
+            0
+        );
+
+        timespec_ns(&ts)
+    }
+
+    let vvar = unsafe { &*(VVAR_BASE as *const Vvar) };
+
+    let before = read_monotonic();
+
+    let vdso_ns = loop {
+        let seq = vvar.seq.load(Ordering::Acquire);
+        let ticks: u64;
+        unsafe { asm!("isb", "mrs {}, cntpct_el0", out(reg) ticks) };
+        let (freq, boot_ticks) = unsafe {
+            (
+                ptr::read_volatile(&vvar.freq),
+                ptr::read_volatile(&vvar.boot_ticks),
+            )
+        };
+
+        fence(Ordering::Acquire);
+        if seq & 1 == 0 && vvar.seq.load(Ordering::Relaxed) == seq {
+            assert_ne!(freq, 0);
+            break (ticks - boot_ticks) as u128 * 1_000_000_000 / freq as u128;
+        }
+    };
+
+    let after = read_monotonic();
+
+    assert!(before <= vdso_ns && vdso_ns <= after);
+
+    // The page must stay read-only.
+    let ret = unsafe {
+        libc::mprotect(
+            VVAR_BASE as *mut libc::c_void,
+            4096,
+            libc::PROT_READ | libc::PROT_WRITE,
+        )
+    };
+    assert_eq!(ret, -1);
+    assert_eq!(
+        std::io::Error::last_os_error().raw_os_error(),
+        Some(libc::EPERM)
+    );
+}
+
+register_test!(test_vvar_monotonic);
+
+fn test_getrandom() {
+    let mut a = [0u8; 64];
+    let mut b = [0u8; 64];
+
+    for buf in [&mut a, &mut b] {
+        let ret = unsafe { libc::getrandom(buf.as_mut_ptr().cast(), buf.len(), 0) };
+        assert_eq!(ret, buf.len() as isize);
+    }
+
+    assert_ne!(a, b);
+
+    let ret = unsafe { libc::getrandom(a.as_mut_ptr().cast(), a.len(), 0x80) };
+    assert_eq!(ret, -1);
+    assert_eq!(
+        std::io::Error::last_os_error().raw_os_error(),
+        Some(libc::EINVAL)
+    );
+}
+
+register_test!(test_getrandom);
+
 fn test_fork() {
     unsafe {
         let pid = libc::fork();
@@ -119,6 +252,41 @@ fn test_rust_mutex() {
 
 register_test!(test_rust_mutex);
 
+fn test_spinning_threads_are_preempted() {
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::time::Duration;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let counters = Arc::new([AtomicU64::new(0), AtomicU64::new(0)]);
+
+    // Neither thread ever blocks, so the other one (and this thread, to set
+    // `stop`) only gets to run if the timer tick preempts them.
+    let handles: Vec<_> = (0..2)
+        .map(|i| {
+            let stop = Arc::clone(&stop);
+            let counters = Arc::clone(&counters);
+
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    counters[i].fetch_add(1, Ordering::Relaxed);
+                }
+            })
+        })
+        .collect();
+
+    thread::sleep(Duration::from_millis(200));
+    stop.store(true, Ordering::Relaxed);
+
+    for h in handles {
+        h.join().unwrap();
+    }
+
+    assert!(counters[0].load(Ordering::Relaxed) > 0);
+    assert!(counters[1].load(Ordering::Relaxed) > 0);
+}
+
+register_test!(test_spinning_threads_are_preempted);
+
 fn test_parking_lot_mutex_timeout() {
     use parking_lot::Mutex;
     use std::time::Duration;
@@ -194,6 +362,73 @@ fn test_mincore() {
 
 register_test!(test_mincore);
 
+fn test_mprotect() {
+    use std::ptr;
+
+    unsafe {
+        let page_size = libc::sysconf(libc::_SC_PAGESIZE) as usize;
+
+        let addr = libc::mmap(
+            ptr::null_mut(),
+            2 * page_size,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        );
+        if addr == libc::MAP_FAILED {
+            panic!("mmap failed: {}", std::io::Error::last_os_error());
+        }
+
+        ptr::write_volatile(addr as *mut u8, 42);
+
+        // The length is rounded up, so this covers the first page only.
+        let rc = libc::mprotect(addr, 1, libc::PROT_READ);
+        assert_eq!(
+            rc,
+            0,
+            "mprotect failed: {}",
+            std::io::Error::last_os_error()
+        );
+        assert_eq!(ptr::read_volatile(addr as *const u8), 42);
+
+        // The second page must still be writable.
+        ptr::write_volatile((addr as *mut u8).add(page_size), 1);
+
+        let expect_err = |rc: libc::c_int, errno: libc::c_int| {
+            assert_eq!(rc, -1);
+            assert_eq!(std::io::Error::last_os_error().raw_os_error(), Some(errno));
+        };
+
+        // W^X violations and unaligned addresses are rejected.
+        expect_err(
+            libc::mprotect(
+                addr,
+                page_size,
+                libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC,
+            ),
+            libc::EINVAL,
+        );
+        expect_err(
+            libc::mprotect((addr as *mut u8).add(1).cast(), page_size, libc::PROT_READ),
+            libc::EINVAL,
+        );
+
+        // As are ranges which aren't wholly mapped.
+        let rc = libc::munmap((addr as *mut u8).add(page_size).cast(), page_size);
+        assert_eq!(rc, 0, "munmap failed: {}", std::io::Error::last_os_error());
+        expect_err(
+            libc::mprotect(addr, 2 * page_size, libc::PROT_READ),
+            libc::ENOMEM,
+        );
+
+        let rc = libc::munmap(addr, page_size);
+        assert_eq!(rc, 0, "munmap failed: {}", std::io::Error::last_os_error());
+    }
+}
+
+register_test!(test_mprotect);
+
 fn run_test(test_fn: fn()) -> Result<(), i32> {
     // Fork a new process to run the test
     unsafe {